@@ -0,0 +1,102 @@
+//! Compares the ways a download's body can be built from a `GetResult`:
+//!
+//! - `collect` reads the whole object into memory first, as handlers
+//!   once did;
+//! - `reader` streams it through an `AsyncRead` and back, copying every
+//!   chunk into a reader's buffer;
+//! - `forward` is `files::object_body`, which hands the store's chunks on
+//!   as they are.
+//!
+//! Each is run against the in-memory store and a local directory; run with
+//! `cargo bench --bench download_bench`, optionally followed by the object
+//! size in MiB and the number of rounds.
+
+use axum::body::Body;
+use futures_util::{StreamExt, TryStreamExt};
+use object_store::{GetResult, ObjectStore, memory::InMemory, path::Path as ObjectPath};
+use server::{controllers::files::object_body, local_store::LocalStore};
+use std::time::{Duration, Instant};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+const APPROACHES: [(&str, Approach); 3] = [
+    ("collect", Approach::Collect),
+    ("reader", Approach::Reader),
+    ("forward", Approach::Forward),
+];
+
+#[derive(Clone, Copy)]
+enum Approach {
+    Collect,
+    Reader,
+    Forward,
+}
+
+async fn body(approach: Approach, result: GetResult) -> Body {
+    match approach {
+        Approach::Collect => Body::from(result.bytes().await.unwrap()),
+        Approach::Reader => {
+            Body::from_stream(ReaderStream::new(StreamReader::new(result.into_stream())))
+        }
+        Approach::Forward => object_body(result),
+    }
+}
+
+/// Drains `body` the way hyper would, returning how many bytes it held.
+async fn drain(body: Body) -> usize {
+    body.into_data_stream()
+        .map_ok(|chunk| chunk.len())
+        .try_fold(0, |sent, len| async move { Ok(sent + len) })
+        .await
+        .unwrap()
+}
+
+async fn round(store: &dyn ObjectStore, location: &ObjectPath, approach: Approach) -> Duration {
+    let started = Instant::now();
+    let result = store.get(location).await.unwrap();
+    let size = result.meta.size;
+    assert_eq!(drain(body(approach, result).await).await, size);
+    started.elapsed()
+}
+
+async fn bench(name: &str, store: &dyn ObjectStore, size_mib: usize, rounds: u32) {
+    let location = ObjectPath::from("bench.bin");
+    let content: Vec<u8> = (0..size_mib * 1024 * 1024).map(|i| i as u8).collect();
+    store.put(&location, content.into()).await.unwrap();
+
+    for (label, approach) in APPROACHES {
+        // One round to warm caches before the timed ones.
+        round(store, &location, approach).await;
+        let mut times = futures_util::stream::iter(0..rounds)
+            .then(|_| round(store, &location, approach))
+            .collect::<Vec<_>>()
+            .await;
+        times.sort();
+        let median = times[times.len() / 2];
+        let throughput = size_mib as f64 / median.as_secs_f64();
+        println!("{name:>7} {label:>8}: median {median:>10.2?}, {throughput:>9.1} MiB/s");
+    }
+    store.delete(&location).await.unwrap();
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`; anything else is ours.
+    let args: Vec<usize> = std::env::args()
+        .skip(1)
+        .filter_map(|arg| arg.parse().ok())
+        .collect();
+    let size_mib = args.first().copied().unwrap_or(64);
+    let rounds = args.get(1).copied().unwrap_or(10) as u32;
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        bench("memory", &InMemory::new(), size_mib, rounds).await;
+
+        let dir = std::env::temp_dir().join(format!("download-bench-{}", uuid::Uuid::new_v4()));
+        let local = LocalStore::open(dir.to_str().unwrap()).unwrap();
+        bench("local", &local, size_mib, rounds).await;
+        let _ = std::fs::remove_dir_all(dir);
+    });
+}
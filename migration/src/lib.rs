@@ -9,6 +9,29 @@ mod m20250101_000004_seed_default_data;
 mod m20250101_000005_add_updated_at_to_files;
 mod m20250101_000006_add_version_to_files;
 mod m20250101_000007_create_file_versions;
+mod m20250101_000008_add_storage_sync_to_files;
+mod m20250101_000009_create_upload_idempotency_keys;
+mod m20250101_000010_add_metadata_to_files;
+mod m20250101_000011_create_blobs;
+mod m20250101_000012_add_content_hash_to_files;
+mod m20250101_000013_add_compressed_to_files;
+mod m20250101_000014_create_file_tags;
+mod m20250101_000015_add_scan_status_to_files;
+mod m20250101_000016_create_tus_uploads;
+mod m20250101_000017_create_chunked_uploads;
+mod m20250101_000018_create_file_downloads;
+mod m20250101_000019_create_file_audit_log;
+mod m20250101_000020_add_storage_quota_to_users;
+mod m20250101_000021_add_tenant_to_files;
+mod m20250101_000022_create_file_locks;
+mod m20250101_000023_create_file_edit_locks;
+mod m20250101_000024_add_object_shard_to_files;
+mod m20250101_000025_add_checksums_to_files;
+mod m20250101_000026_add_replication_to_files;
+mod m20250101_000027_add_access_fields_to_file_audit_log;
+mod m20250101_000028_make_file_names_unique_per_tenant;
+mod m20250101_000029_add_claims_to_upload_idempotency_keys;
+mod m20250101_000030_grant_files_write_to_seeded_roles;
 
 pub struct Migrator;
 
@@ -23,6 +46,29 @@ impl MigratorTrait for Migrator {
             Box::new(m20250101_000005_add_updated_at_to_files::Migration),
             Box::new(m20250101_000006_add_version_to_files::Migration),
             Box::new(m20250101_000007_create_file_versions::Migration),
+            Box::new(m20250101_000008_add_storage_sync_to_files::Migration),
+            Box::new(m20250101_000009_create_upload_idempotency_keys::Migration),
+            Box::new(m20250101_000010_add_metadata_to_files::Migration),
+            Box::new(m20250101_000011_create_blobs::Migration),
+            Box::new(m20250101_000012_add_content_hash_to_files::Migration),
+            Box::new(m20250101_000013_add_compressed_to_files::Migration),
+            Box::new(m20250101_000014_create_file_tags::Migration),
+            Box::new(m20250101_000015_add_scan_status_to_files::Migration),
+            Box::new(m20250101_000016_create_tus_uploads::Migration),
+            Box::new(m20250101_000017_create_chunked_uploads::Migration),
+            Box::new(m20250101_000018_create_file_downloads::Migration),
+            Box::new(m20250101_000019_create_file_audit_log::Migration),
+            Box::new(m20250101_000020_add_storage_quota_to_users::Migration),
+            Box::new(m20250101_000021_add_tenant_to_files::Migration),
+            Box::new(m20250101_000022_create_file_locks::Migration),
+            Box::new(m20250101_000023_create_file_edit_locks::Migration),
+            Box::new(m20250101_000024_add_object_shard_to_files::Migration),
+            Box::new(m20250101_000025_add_checksums_to_files::Migration),
+            Box::new(m20250101_000026_add_replication_to_files::Migration),
+            Box::new(m20250101_000027_add_access_fields_to_file_audit_log::Migration),
+            Box::new(m20250101_000028_make_file_names_unique_per_tenant::Migration),
+            Box::new(m20250101_000029_add_claims_to_upload_idempotency_keys::Migration),
+            Box::new(m20250101_000030_grant_files_write_to_seeded_roles::Migration),
             // inject-above (do not remove this comment)
         ]
     }
@@ -0,0 +1,82 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(UploadIdempotencyKeys::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UploadIdempotencyKeys::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(UploadIdempotencyKeys::UserId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(UploadIdempotencyKeys::Key)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(UploadIdempotencyKeys::RequestHash).string())
+                    .col(ColumnDef::new(UploadIdempotencyKeys::ResponseStatus).integer())
+                    .col(ColumnDef::new(UploadIdempotencyKeys::ResponseBody).json_binary())
+                    .col(
+                        ColumnDef::new(UploadIdempotencyKeys::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .index(
+                        Index::create()
+                            .unique()
+                            .col(UploadIdempotencyKeys::UserId)
+                            .col(UploadIdempotencyKeys::Key),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-upload_idempotency_keys-user_id")
+                            .from(UploadIdempotencyKeys::Table, UploadIdempotencyKeys::UserId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UploadIdempotencyKeys::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UploadIdempotencyKeys {
+    Table,
+    Id,
+    UserId,
+    Key,
+    RequestHash,
+    ResponseStatus,
+    ResponseBody,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+}
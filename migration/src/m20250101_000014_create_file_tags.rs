@@ -0,0 +1,78 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(FileTags::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(FileTags::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(FileTags::FileId).integer().not_null())
+                    .col(ColumnDef::new(FileTags::Tag).string_len(64).not_null())
+                    .col(
+                        ColumnDef::new(FileTags::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .index(
+                        Index::create()
+                            .unique()
+                            .col(FileTags::FileId)
+                            .col(FileTags::Tag),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-file_tags-file_id")
+                            .from(FileTags::Table, FileTags::FileId)
+                            .to(Files::Table, Files::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-file_tags-tag")
+                    .table(FileTags::Table)
+                    .col(FileTags::Tag)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(FileTags::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum FileTags {
+    Table,
+    Id,
+    FileId,
+    Tag,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Files {
+    Table,
+    Id,
+}
@@ -0,0 +1,94 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(TusUploads::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(TusUploads::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(TusUploads::FileName).string().not_null())
+                    .col(
+                        ColumnDef::new(TusUploads::UploadLength)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(TusUploads::UploadOffset)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(TusUploads::AuthorId).integer().not_null())
+                    .col(ColumnDef::new(TusUploads::Metadata).json_binary())
+                    .col(ColumnDef::new(TusUploads::MultipartId).string().not_null())
+                    .col(ColumnDef::new(TusUploads::Parts).json_binary().not_null())
+                    .col(
+                        ColumnDef::new(TusUploads::TailSize)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(TusUploads::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(TusUploads::UpdatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-tus_uploads-author_id")
+                            .from(TusUploads::Table, TusUploads::AuthorId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(TusUploads::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TusUploads {
+    Table,
+    Id,
+    FileName,
+    UploadLength,
+    UploadOffset,
+    AuthorId,
+    Metadata,
+    MultipartId,
+    Parts,
+    TailSize,
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+}
@@ -0,0 +1,141 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ChunkedUploads::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ChunkedUploads::Id)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ChunkedUploads::FileName).string().not_null())
+                    .col(
+                        ColumnDef::new(ChunkedUploads::AuthorId)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ChunkedUploads::Metadata).json_binary())
+                    .col(
+                        ColumnDef::new(ChunkedUploads::MultipartId)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ChunkedUploads::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(ChunkedUploads::UpdatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-chunked_uploads-author_id")
+                            .from(ChunkedUploads::Table, ChunkedUploads::AuthorId)
+                            .to(Users::Table, Users::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(ChunkedUploadParts::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ChunkedUploadParts::UploadId)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ChunkedUploadParts::PartNumber)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ChunkedUploadParts::ContentId)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ChunkedUploadParts::Size)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ChunkedUploadParts::UpdatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .col(ChunkedUploadParts::UploadId)
+                            .col(ChunkedUploadParts::PartNumber),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-chunked_upload_parts-upload_id")
+                            .from(ChunkedUploadParts::Table, ChunkedUploadParts::UploadId)
+                            .to(ChunkedUploads::Table, ChunkedUploads::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ChunkedUploadParts::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(ChunkedUploads::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ChunkedUploads {
+    Table,
+    Id,
+    FileName,
+    AuthorId,
+    Metadata,
+    MultipartId,
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum ChunkedUploadParts {
+    Table,
+    UploadId,
+    PartNumber,
+    ContentId,
+    Size,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    Id,
+}
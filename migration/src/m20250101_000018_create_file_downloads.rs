@@ -0,0 +1,58 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(FileDownloads::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(FileDownloads::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(FileDownloads::FileKey).text().not_null())
+                    .col(
+                        ColumnDef::new(FileDownloads::DownloadedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(ColumnDef::new(FileDownloads::ClientIp).text())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-file_downloads-file_key")
+                    .table(FileDownloads::Table)
+                    .col(FileDownloads::FileKey)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(FileDownloads::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum FileDownloads {
+    Table,
+    Id,
+    FileKey,
+    DownloadedAt,
+    ClientIp,
+}
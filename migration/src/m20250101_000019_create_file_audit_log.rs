@@ -0,0 +1,75 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(FileAuditLog::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(FileAuditLog::Id)
+                            .big_integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(FileAuditLog::Operation)
+                            .string_len(16)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(FileAuditLog::FileKey).text())
+                    // No foreign key: entries outlive the users they name.
+                    .col(ColumnDef::new(FileAuditLog::UserId).integer())
+                    .col(ColumnDef::new(FileAuditLog::IpAddress).text())
+                    .col(
+                        ColumnDef::new(FileAuditLog::Outcome)
+                            .string_len(16)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(FileAuditLog::ErrorMessage).text())
+                    .col(
+                        ColumnDef::new(FileAuditLog::CreatedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-file_audit_log-file_key")
+                    .table(FileAuditLog::Table)
+                    .col(FileAuditLog::FileKey)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(FileAuditLog::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum FileAuditLog {
+    Table,
+    Id,
+    Operation,
+    FileKey,
+    UserId,
+    IpAddress,
+    Outcome,
+    ErrorMessage,
+    CreatedAt,
+}
@@ -0,0 +1,85 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Keeps `users.storage_used_bytes` at the total size of the user's files in
+/// the same transaction as every insert, resize, reassignment and delete.
+const TRACK_USAGE: &str = r#"
+CREATE OR REPLACE FUNCTION track_storage_usage() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP IN ('UPDATE', 'DELETE') AND OLD.author_id IS NOT NULL THEN
+        UPDATE users SET storage_used_bytes = storage_used_bytes - OLD.size
+        WHERE id = OLD.author_id;
+    END IF;
+    IF TG_OP IN ('INSERT', 'UPDATE') AND NEW.author_id IS NOT NULL THEN
+        UPDATE users SET storage_used_bytes = storage_used_bytes + NEW.size
+        WHERE id = NEW.author_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER files_storage_usage
+AFTER INSERT OR DELETE OR UPDATE OF size, author_id ON files
+FOR EACH ROW EXECUTE FUNCTION track_storage_usage();
+
+UPDATE users SET storage_used_bytes = COALESCE(
+    (SELECT SUM(size) FROM files WHERE files.author_id = users.id), 0
+);
+"#;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .add_column(
+                        ColumnDef::new(Users::StorageUsedBytes)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .add_column(
+                        ColumnDef::new(Users::StorageQuotaBytes)
+                            .big_integer()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .get_connection()
+            .execute_unprepared(TRACK_USAGE)
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "DROP TRIGGER IF EXISTS files_storage_usage ON files;
+                 DROP FUNCTION IF EXISTS track_storage_usage();",
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Users::Table)
+                    .drop_column(Users::StorageUsedBytes)
+                    .drop_column(Users::StorageQuotaBytes)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Users {
+    Table,
+    StorageUsedBytes,
+    StorageQuotaBytes,
+}
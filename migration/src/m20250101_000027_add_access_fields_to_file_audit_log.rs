@@ -0,0 +1,79 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(FileAuditLog::Table)
+                    .add_column(ColumnDef::new(FileAuditLog::TokenId).text().null())
+                    .add_column(ColumnDef::new(FileAuditLog::UserAgent).text().null())
+                    .add_column(ColumnDef::new(FileAuditLog::Bytes).big_integer().null())
+                    .add_column(ColumnDef::new(FileAuditLog::StatusCode).integer().null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // For the per-user query and the retention purge.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-file_audit_log-user_id")
+                    .table(FileAuditLog::Table)
+                    .col(FileAuditLog::UserId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-file_audit_log-created_at")
+                    .table(FileAuditLog::Table)
+                    .col(FileAuditLog::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for name in [
+            "idx-file_audit_log-user_id",
+            "idx-file_audit_log-created_at",
+        ] {
+            manager
+                .drop_index(
+                    Index::drop()
+                        .name(name)
+                        .table(FileAuditLog::Table)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(FileAuditLog::Table)
+                    .drop_column(FileAuditLog::TokenId)
+                    .drop_column(FileAuditLog::UserAgent)
+                    .drop_column(FileAuditLog::Bytes)
+                    .drop_column(FileAuditLog::StatusCode)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum FileAuditLog {
+    Table,
+    UserId,
+    TokenId,
+    UserAgent,
+    Bytes,
+    StatusCode,
+    CreatedAt,
+}
@@ -0,0 +1,70 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// A name belongs to one file per bucket, whoever uploaded it. Files of the
+/// default bucket have no tenant, so those NULLs must not count as distinct.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared("ALTER TABLE files DROP CONSTRAINT files_name_author_id_key")
+            .await?;
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_files_tenant_id")
+                    .table(Files::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_files_tenant_id_name")
+                    .table(Files::Table)
+                    .col(Files::TenantId)
+                    .col(Files::Name)
+                    .unique()
+                    .nulls_not_distinct()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_files_tenant_id_name")
+                    .table(Files::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_files_tenant_id")
+                    .table(Files::Table)
+                    .col(Files::TenantId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "ALTER TABLE files ADD CONSTRAINT files_name_author_id_key UNIQUE (name, author_id)",
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Files {
+    Table,
+    TenantId,
+    Name,
+}
@@ -0,0 +1,93 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// A key is held by the one claim whose token it carries, for as long as
+/// that claim keeps renewing its lease, and belongs to a bucket as well as
+/// a user.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UploadIdempotencyKeys::Table)
+                    .add_column(
+                        ColumnDef::new(UploadIdempotencyKeys::TenantId)
+                            .string()
+                            .null(),
+                    )
+                    .add_column(
+                        ColumnDef::new(UploadIdempotencyKeys::ClaimToken)
+                            .string()
+                            .null(),
+                    )
+                    .add_column(
+                        ColumnDef::new(UploadIdempotencyKeys::LeasedAt)
+                            .timestamp()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "ALTER TABLE upload_idempotency_keys DROP CONSTRAINT upload_idempotency_keys_user_id_key_key",
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_upload_idempotency_keys_tenant_id_user_id_key")
+                    .table(UploadIdempotencyKeys::Table)
+                    .col(UploadIdempotencyKeys::TenantId)
+                    .col(UploadIdempotencyKeys::UserId)
+                    .col(UploadIdempotencyKeys::Key)
+                    .unique()
+                    .nulls_not_distinct()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_upload_idempotency_keys_tenant_id_user_id_key")
+                    .table(UploadIdempotencyKeys::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UploadIdempotencyKeys::Table)
+                    .drop_column(UploadIdempotencyKeys::TenantId)
+                    .drop_column(UploadIdempotencyKeys::ClaimToken)
+                    .drop_column(UploadIdempotencyKeys::LeasedAt)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "ALTER TABLE upload_idempotency_keys ADD CONSTRAINT upload_idempotency_keys_user_id_key_key UNIQUE (user_id, key)",
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum UploadIdempotencyKeys {
+    Table,
+    TenantId,
+    UserId,
+    Key,
+    ClaimToken,
+    LeasedAt,
+}
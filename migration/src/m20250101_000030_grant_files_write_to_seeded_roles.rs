@@ -0,0 +1,31 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Lets the seeded roles edit what they have stored, such as a file's
+/// title and description.
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "UPDATE roles SET attributes = attributes || '[\"files:write\"]'
+                 WHERE name IN ('admin', 'user') AND NOT attributes @> '[\"files:write\"]'",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "UPDATE roles SET attributes = attributes - 'files:write'
+                 WHERE name IN ('admin', 'user')",
+            )
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+//! Uploads and downloads appended to a local JSONL file, which keeps a
+//! record while the database, and so the audit log, is unreachable.
+//!
+//! The file is renamed aside once it would grow past its rotation size;
+//! `archive` then moves the rotated files to the bucket, under
+//! `ARCHIVE_PREFIX`. The logger in use is an `Arc<AccessLogger>` in the
+//! context's shared store, put there with `install`.
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use loco_rs::prelude::AppContext;
+use object_store::{Error, ObjectStore, Result, path::Path as ObjectPath};
+use serde::Serialize;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt as _,
+    sync::Mutex,
+};
+
+use crate::models::file_audit_log::Entry;
+
+/// Where `archive` puts rotated files, as `<prefix><rotated at>.jsonl`.
+pub const ARCHIVE_PREFIX: &str = "__access-logs/";
+
+/// What a rotated file's name adds, after a dot, to the log's own.
+const ROTATED_FORMAT: &str = "%Y-%m-%dT%H-%M-%S-%6f";
+
+/// One line of the log.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessRecord {
+    pub ts: String,
+    /// `upload`, `download` or `link_access`.
+    pub op: String,
+    pub key: Option<String>,
+    /// The user's id, when the request carried a valid token.
+    pub user: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl From<&Entry> for AccessRecord {
+    fn from(entry: &Entry) -> Self {
+        Self {
+            ts: entry.at.and_utc().to_rfc3339(),
+            op: entry.operation.as_str().to_lowercase(),
+            key: entry.file_key.clone(),
+            user: entry.actor.user_id.map(|id| id.to_string()),
+            ip: entry.actor.ip_address.clone(),
+        }
+    }
+}
+
+struct Current {
+    file: File,
+    size: u64,
+}
+
+pub struct AccessLogger {
+    path: PathBuf,
+    rotate_bytes: u64,
+    current: Mutex<Current>,
+}
+
+async fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+}
+
+impl AccessLogger {
+    /// Appends to `path`, creating it and its folder if need be.
+    pub async fn open(path: impl Into<PathBuf>, rotate_bytes: u64) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).await?;
+        }
+        let file = open_append(&path).await?;
+        let size = file.metadata().await?.len();
+        Ok(Self {
+            path,
+            rotate_bytes,
+            current: Mutex::new(Current { file, size }),
+        })
+    }
+
+    /// Appends `record`, first rotating the file if the line would take it
+    /// past `rotate_bytes`.
+    pub async fn write(&self, record: &AccessRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut current = self.current.lock().await;
+        if current.size > 0 && current.size + line.len() as u64 > self.rotate_bytes {
+            current.file.flush().await?;
+            fs::rename(&self.path, rotated_path(&self.path, Utc::now())).await?;
+            *current = Current {
+                file: open_append(&self.path).await?,
+                size: 0,
+            };
+        }
+        current.file.write_all(&line).await?;
+        current.file.flush().await?;
+        current.size += line.len() as u64;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+fn rotated_path(path: &Path, at: DateTime<Utc>) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".{}", at.format(ROTATED_FORMAT)));
+    path.with_file_name(name)
+}
+
+/// The files `path` was rotated into, oldest first, each with the time it
+/// was rotated at as it is formatted in its name.
+pub async fn rotated_files(path: &Path) -> io::Result<Vec<(PathBuf, String)>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let prefix = format!(
+        "{}.",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let mut found = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(stamp) = name.strip_prefix(&prefix)
+            && NaiveDateTime::parse_from_str(stamp, ROTATED_FORMAT).is_ok()
+        {
+            found.push((entry.path(), stamp.to_string()));
+        }
+    }
+    // The stamps sort as the times they name.
+    found.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(found)
+}
+
+fn io_error(e: io::Error) -> Error {
+    Error::Generic {
+        store: "access_log",
+        source: Box::new(e),
+    }
+}
+
+/// Moves every file `path` was rotated into to `store`, returning how many
+/// went. A file is only removed once it is stored, so a failure leaves it
+/// for the next run.
+pub async fn archive(store: &dyn ObjectStore, path: &Path) -> Result<usize> {
+    let mut archived = 0;
+    for (file, stamp) in rotated_files(path).await.map_err(io_error)? {
+        let bytes = fs::read(&file).await.map_err(io_error)?;
+        let key = ObjectPath::from(format!("{ARCHIVE_PREFIX}{stamp}.jsonl"));
+        store.put(&key, bytes.into()).await?;
+        fs::remove_file(&file).await.map_err(io_error)?;
+        archived += 1;
+    }
+    Ok(archived)
+}
+
+/// Makes `logger` the one `record` writes to for `ctx`.
+pub fn install(ctx: &AppContext, logger: Arc<AccessLogger>) {
+    ctx.shared_store.insert(logger);
+}
+
+/// Appends `entry` to the access log of `ctx`, if it keeps one, without
+/// waiting for the write.
+pub fn record(ctx: &AppContext, entry: &Entry) {
+    let Some(logger) = ctx.shared_store.get::<Arc<AccessLogger>>() else {
+        return;
+    };
+    let record = AccessRecord::from(entry);
+    tokio::spawn(async move {
+        if let Err(e) = logger.write(&record).await {
+            tracing::warn!(path = %logger.path().display(), error = %e, "failed to write access log");
+        }
+    });
+}
@@ -1,8 +1,9 @@
 use async_trait::async_trait;
+use axum::{Extension, Router as AxumRouter};
 use loco_rs::{
     Result,
     app::{AppContext, Hooks, Initializer},
-    bgworker::Queue,
+    bgworker::{BackgroundWorker, Queue},
     boot::{BootResult, StartMode, create_app},
     config::Config,
     controller::AppRoutes,
@@ -10,10 +11,21 @@ use loco_rs::{
     task::Tasks,
 };
 use migration::Migrator;
-use std::path::Path;
+use std::{path::Path, sync::Arc};
 
 #[allow(unused_imports)]
-use crate::controllers;
+use crate::{
+    audit_writer, controllers,
+    storage_hooks::{self, NoopHooks},
+    tasks,
+    workers::{
+        abort_orphaned_uploads::AbortOrphanedUploadsWorker, deliver_webhook::DeliverWebhookWorker,
+        expire_tus_uploads::ExpireTusUploadsWorker, generate_preview::GeneratePreviewWorker,
+        generate_thumbnail::GenerateThumbnailWorker, index_content::IndexContentWorker,
+        prune_versions::PruneVersionsWorker, replicate_file::ReplicateFileWorker,
+        scan_file::ScanFileWorker,
+    },
+};
 
 pub struct App;
 #[async_trait]
@@ -40,23 +52,62 @@ impl Hooks for App {
         create_app::<Self, Migrator>(mode, environment, config).await
     }
 
+    async fn after_context(ctx: AppContext) -> Result<AppContext> {
+        controllers::files::validate_storage_config(&ctx)?;
+        controllers::files::warn_if_ephemeral_storage(&ctx);
+        controllers::files::log_storage_timeouts(&ctx);
+        controllers::files::connect_store(&ctx)?;
+        // Install your own `StorageHooks` here to extend uploads and deletes.
+        storage_hooks::install(&ctx, Arc::new(NoopHooks));
+        audit_writer::start(&ctx);
+        controllers::files::open_access_log(&ctx).await?;
+        Ok(ctx)
+    }
+
     async fn initializers(_ctx: &AppContext) -> Result<Vec<Box<dyn Initializer>>> {
         Ok(vec![])
     }
 
-    fn routes(_ctx: &AppContext) -> AppRoutes {
+    fn routes(ctx: &AppContext) -> AppRoutes {
         AppRoutes::with_default_routes()
+            .add_route(controllers::admin::routes())
             .add_route(controllers::auth::routes())
-            .add_route(controllers::files::routes())
+            .add_route(controllers::files::routes(ctx))
+            .add_route(controllers::openapi::routes(ctx))
             .add_route(controllers::roles::routes())
             .add_route(controllers::users::routes())
     }
-    async fn connect_workers(_ctx: &AppContext, _queue: &Queue) -> Result<()> {
+    async fn after_routes(router: AxumRouter, ctx: &AppContext) -> Result<AxumRouter> {
+        Ok(router
+            .layer(Extension(controllers::files::connect_store(ctx)?))
+            .layer(Extension(controllers::files::connect_part_store(ctx)?))
+            .layer(Extension(controllers::files::connect_circuit(ctx)?)))
+    }
+
+    async fn connect_workers(ctx: &AppContext, queue: &Queue) -> Result<()> {
+        queue.register(PruneVersionsWorker::build(ctx)).await?;
+        queue.register(IndexContentWorker::build(ctx)).await?;
+        queue.register(GenerateThumbnailWorker::build(ctx)).await?;
+        queue.register(GeneratePreviewWorker::build(ctx)).await?;
+        queue.register(ScanFileWorker::build(ctx)).await?;
+        queue.register(ReplicateFileWorker::build(ctx)).await?;
+        queue.register(DeliverWebhookWorker::build(ctx)).await?;
+        queue.register(ExpireTusUploadsWorker::build(ctx)).await?;
+        queue
+            .register(AbortOrphanedUploadsWorker::build(ctx))
+            .await?;
         Ok(())
     }
 
     #[allow(unused_variables)]
     fn register_tasks(tasks: &mut Tasks) {
+        tasks.register(tasks::reindex_files::ReindexFiles);
+        tasks.register(tasks::reconcile_storage_usage::ReconcileStorageUsage);
+        tasks.register(tasks::abort_orphaned_uploads::AbortOrphanedUploads);
+        tasks.register(tasks::reconcile_replica::ReconcileReplica);
+        tasks.register(tasks::purge_audit_log::PurgeAuditLog);
+        tasks.register(tasks::archive_access_logs::ArchiveAccessLogs);
+        tasks.register(tasks::verify_files::VerifyFiles);
         // tasks-inject (do not remove)
     }
     async fn truncate(_ctx: &AppContext) -> Result<()> {
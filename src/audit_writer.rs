@@ -0,0 +1,111 @@
+//! Writes the audit log off the request path. Handlers queue entries with
+//! `log`; one task per context stores them in batches, in the order they
+//! were queued, so a burst of downloads costs one insert instead of many.
+//!
+//! The writer lives in the context's shared store, started by
+//! `App::after_context` with `start`.
+
+use loco_rs::prelude::*;
+use sea_orm::DatabaseConnection;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::models::file_audit_log::{self, Entry};
+
+/// Entries waiting to be written before new ones are dropped.
+const QUEUE_CAPACITY: usize = 10_000;
+/// Entries written by one insert, at most.
+const BATCH_SIZE: usize = 100;
+/// How long an entry waits for its batch to fill.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+enum Message {
+    Entry(Box<Entry>),
+    /// Answered once everything queued before it is written.
+    Flush(oneshot::Sender<()>),
+}
+
+#[derive(Clone)]
+struct AuditWriter {
+    sender: mpsc::Sender<Message>,
+}
+
+/// Starts the writer of `ctx`'s audit log.
+pub fn start(ctx: &AppContext) {
+    let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+    tokio::spawn(run(ctx.db.clone(), receiver));
+    ctx.shared_store.insert(AuditWriter { sender });
+}
+
+/// Queues `entry`. Without a writer, as in a CLI task, it is written by a
+/// task of its own; with a full queue it is dropped, and logged instead.
+pub fn log(ctx: &AppContext, entry: Entry) {
+    let Some(writer) = ctx.shared_store.get::<AuditWriter>() else {
+        let db = ctx.db.clone();
+        tokio::spawn(async move { write(&db, &mut vec![entry]).await });
+        return;
+    };
+    let (operation, file_key) = (entry.operation, entry.file_key.clone());
+    if writer
+        .sender
+        .try_send(Message::Entry(Box::new(entry)))
+        .is_err()
+    {
+        tracing::warn!(
+            operation = operation.as_str(),
+            file_key = file_key.as_deref().unwrap_or_default(),
+            "audit log queue is full; entry dropped"
+        );
+    }
+}
+
+/// Waits until everything queued so far is written, so that reading the
+/// log sees it.
+pub async fn flush(ctx: &AppContext) {
+    let Some(writer) = ctx.shared_store.get::<AuditWriter>() else {
+        return;
+    };
+    let (done, written) = oneshot::channel();
+    if writer.sender.send(Message::Flush(done)).await.is_ok() {
+        let _ = written.await;
+    }
+}
+
+async fn run(db: DatabaseConnection, mut receiver: mpsc::Receiver<Message>) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut tick = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        tokio::select! {
+            message = receiver.recv() => match message {
+                Some(Message::Entry(entry)) => {
+                    batch.push(*entry);
+                    if batch.len() >= BATCH_SIZE {
+                        write(&db, &mut batch).await;
+                    }
+                }
+                Some(Message::Flush(done)) => {
+                    write(&db, &mut batch).await;
+                    let _ = done.send(());
+                }
+                None => {
+                    write(&db, &mut batch).await;
+                    return;
+                }
+            },
+            _ = tick.tick() => write(&db, &mut batch).await,
+        }
+    }
+}
+
+/// Writes and empties `batch`. A batch that fails is logged and lost
+/// rather than retried, so one bad entry can't stall the log.
+async fn write(db: &DatabaseConnection, batch: &mut Vec<Entry>) {
+    if batch.is_empty() {
+        return;
+    }
+    let entries = std::mem::take(batch);
+    let count = entries.len();
+    if let Err(e) = file_audit_log::insert_all(db, entries).await {
+        tracing::error!(error = %e, count, "failed to write audit log entries");
+    }
+}
@@ -0,0 +1,755 @@
+//! A read-through cache of downloads on local disk, for files fetched over
+//! and over. An entry is only served after the backend confirms, with a
+//! conditional request, that the object still has the ETag it was cached
+//! under, so a change made elsewhere is never served stale; that check
+//! costs a round trip but none of the transfer.
+//!
+//! A miss is streamed to the client while a copy is written beside it. The
+//! copy becomes an entry once the whole object arrived, by renaming it into
+//! place, so readers never see part of a file. Once the entries outgrow
+//! `max_bytes` the least recently used go first.
+//!
+//! Small objects can be kept in memory as well, where the round trip is
+//! most of what a download costs. Those are served without asking the
+//! backend; see `MemoryCache`.
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures_util::{StreamExt, TryStreamExt, stream::BoxStream};
+use object_store::{
+    Attributes, Error, GetOptions, GetResult, GetResultPayload, ListResult, MultipartId,
+    MultipartUpload, ObjectMeta, ObjectStore, PutMultipartOpts, PutOptions, PutPayload, PutResult,
+    Result, UploadPart,
+    multipart::{MultipartStore, PartId},
+    path::Path,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    ops::Range,
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+
+const READ_BUFFER: usize = 64 * 1024;
+
+/// The entries on disk, shared by every store that caches into `dir`.
+#[derive(Debug)]
+pub struct DownloadCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    index: Mutex<Index>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    entries: HashMap<String, Entry>,
+    /// Keys by when they were last served, oldest first.
+    recency: BTreeMap<u64, String>,
+    total_bytes: u64,
+    clock: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    file: PathBuf,
+    meta: ObjectMeta,
+    attributes: Attributes,
+    last_used: u64,
+}
+
+impl Index {
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.recency.remove(&entry.last_used);
+        self.total_bytes -= entry.meta.size as u64;
+        Some(entry)
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+impl DownloadCache {
+    /// Creates `dir` if needed. The index only lives in memory, so files
+    /// an earlier run left there are removed.
+    pub fn open(dir: impl Into<PathBuf>, max_bytes: u64) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        for file in std::fs::read_dir(&dir)? {
+            let file = file?;
+            if file.file_name().to_str().is_some_and(is_cache_file) {
+                std::fs::remove_file(file.path())?;
+            }
+        }
+        Ok(Self {
+            dir,
+            max_bytes,
+            index: Mutex::new(Index::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    /// Downloads served from disk.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Cacheable downloads that had to be fetched from the backend.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Size of the entries held.
+    pub fn size_bytes(&self) -> u64 {
+        self.index.lock().unwrap().total_bytes
+    }
+
+    fn lookup(&self, key: &str) -> Option<Entry> {
+        self.index.lock().unwrap().entries.get(key).cloned()
+    }
+
+    fn touch(&self, key: &str) {
+        let mut index = self.index.lock().unwrap();
+        let tick = index.tick();
+        let Some(entry) = index.entries.get_mut(key) else {
+            return;
+        };
+        let previous = std::mem::replace(&mut entry.last_used, tick);
+        index.recency.remove(&previous);
+        index.recency.insert(tick, key.to_string());
+    }
+
+    fn insert(&self, key: String, file: PathBuf, meta: ObjectMeta, attributes: Attributes) {
+        let mut stale = Vec::new();
+        {
+            let mut index = self.index.lock().unwrap();
+            // The same key and ETag name the same file, which was just
+            // replaced by an identical copy.
+            if let Some(old) = index.remove(&key).filter(|old| old.file != file) {
+                stale.push(old.file);
+            }
+            let last_used = index.tick();
+            index.total_bytes += meta.size as u64;
+            index.recency.insert(last_used, key.clone());
+            index.entries.insert(
+                key,
+                Entry {
+                    file,
+                    meta,
+                    attributes,
+                    last_used,
+                },
+            );
+            while index.total_bytes > self.max_bytes {
+                let Some((_, oldest)) = index.recency.pop_first() else {
+                    break;
+                };
+                if let Some(evicted) = index.remove(&oldest) {
+                    stale.push(evicted.file);
+                }
+            }
+        }
+        for file in stale {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+
+    fn invalidate(&self, key: &str) {
+        let removed = self.index.lock().unwrap().remove(key);
+        if let Some(entry) = removed {
+            let _ = std::fs::remove_file(entry.file);
+        }
+    }
+
+    fn file_for(&self, key: &str, e_tag: &str) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(key.as_bytes());
+        hasher.update([0]);
+        hasher.update(e_tag.as_bytes());
+        self.dir.join(hex::encode(hasher.finalize()))
+    }
+
+    /// Hands `result` back with a copy of its body written to disk as it
+    /// is read. Objects without an ETag, or bigger than the whole cache,
+    /// are passed through.
+    fn fill(self: &Arc<Self>, key: String, mut result: GetResult) -> GetResult {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let Some(e_tag) = result.meta.e_tag.as_deref() else {
+            return result;
+        };
+        if result.meta.size as u64 > self.max_bytes {
+            return result;
+        }
+        let GetResultPayload::Stream(stream) = result.payload else {
+            // Already a local file.
+            return result;
+        };
+        let file = self.file_for(&key, e_tag);
+        let copy = PendingEntry {
+            temp: file.with_extension(format!("{}.tmp", uuid::Uuid::new_v4().simple())),
+            file,
+            out: None,
+            written: 0,
+            cache: self.clone(),
+            key,
+            meta: result.meta.clone(),
+            attributes: result.attributes.clone(),
+        };
+        result.payload = GetResultPayload::Stream(tee(stream, copy));
+        result
+    }
+}
+
+/// Entry files are named by a SHA-256, copies in progress after it.
+fn is_cache_file(name: &str) -> bool {
+    name.len() >= 64 && name.as_bytes()[..64].iter().all(u8::is_ascii_hexdigit)
+}
+
+/// A copy of a download being written. Dropped before it is complete, as
+/// when the client goes away, it removes what was written.
+struct PendingEntry {
+    temp: PathBuf,
+    file: PathBuf,
+    out: Option<tokio::fs::File>,
+    written: u64,
+    cache: Arc<DownloadCache>,
+    key: String,
+    meta: ObjectMeta,
+    attributes: Attributes,
+}
+
+impl PendingEntry {
+    async fn write(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        if self.out.is_none() {
+            self.out = Some(tokio::fs::File::create(&self.temp).await?);
+        }
+        if let Some(out) = &mut self.out {
+            out.write_all(chunk).await?;
+        }
+        self.written += chunk.len() as u64;
+        Ok(())
+    }
+
+    async fn commit(mut self) -> std::io::Result<()> {
+        if self.written != self.meta.size as u64 {
+            return Ok(());
+        }
+        match self.out.take() {
+            Some(mut out) => out.flush().await?,
+            None => drop(tokio::fs::File::create(&self.temp).await?),
+        }
+        tokio::fs::rename(&self.temp, &self.file).await?;
+        self.cache.insert(
+            std::mem::take(&mut self.key),
+            self.file.clone(),
+            self.meta.clone(),
+            std::mem::take(&mut self.attributes),
+        );
+        Ok(())
+    }
+}
+
+impl Drop for PendingEntry {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.temp);
+    }
+}
+
+/// `stream` as it is, a chunk at a time written to `copy` first. A copy
+/// that fails to write is given up; the download itself goes on.
+fn tee(
+    stream: BoxStream<'static, Result<Bytes>>,
+    copy: PendingEntry,
+) -> BoxStream<'static, Result<Bytes>> {
+    futures_util::stream::unfold(Some((stream, Some(copy))), |state| async move {
+        let (mut stream, mut copy) = state?;
+        match stream.next().await {
+            Some(Ok(chunk)) => {
+                if let Some(c) = &mut copy
+                    && let Err(e) = c.write(&chunk).await
+                {
+                    tracing::warn!(error = %e, "could not write to the download cache");
+                    copy = None;
+                }
+                Some((Ok(chunk), Some((stream, copy))))
+            }
+            Some(Err(e)) => Some((Err(e), None)),
+            None => {
+                if let Some(c) = copy
+                    && let Err(e) = c.commit().await
+                {
+                    tracing::warn!(error = %e, "could not add to the download cache");
+                }
+                None
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Objects of up to `max_object_bytes`, held in memory under `max_bytes`
+/// in all. Unlike `DownloadCache` an entry is served without asking the
+/// backend. Writes through `CacheStore` drop it, and it expires after
+/// `ttl`, which bounds how long a change made elsewhere goes unseen.
+pub struct MemoryCache {
+    entries: moka::sync::Cache<String, MemoryEntry>,
+    max_object_bytes: u64,
+    /// Bumped by every write: a read that overlapped one keeps what it
+    /// read out of the cache, as it may predate the write.
+    writes: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Clone)]
+struct MemoryEntry {
+    bytes: Bytes,
+    meta: ObjectMeta,
+    attributes: Attributes,
+}
+
+impl fmt::Debug for MemoryCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryCache")
+            .field("max_object_bytes", &self.max_object_bytes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MemoryCache {
+    pub fn new(max_bytes: u64, max_object_bytes: u64, ttl: Duration) -> Self {
+        let entries = moka::sync::Cache::builder()
+            .max_capacity(max_bytes)
+            .weigher(|key: &String, entry: &MemoryEntry| {
+                u32::try_from(key.len() + entry.bytes.len()).unwrap_or(u32::MAX)
+            })
+            .time_to_live(ttl)
+            .build();
+        Self {
+            entries,
+            max_object_bytes,
+            writes: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Plain downloads not served from memory, small or not.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn entry_count(&self) -> u64 {
+        self.entries.run_pending_tasks();
+        self.entries.entry_count()
+    }
+
+    /// Content and keys held.
+    pub fn size_bytes(&self) -> u64 {
+        self.entries.run_pending_tasks();
+        self.entries.weighted_size()
+    }
+
+    fn get(&self, key: &str) -> Option<GetResult> {
+        let entry = self.entries.get(key)?;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(GetResult {
+            range: 0..entry.meta.size,
+            payload: bytes_payload(entry.bytes),
+            meta: entry.meta,
+            attributes: entry.attributes,
+        })
+    }
+
+    fn invalidate(&self, key: &str) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.entries.invalidate(key);
+    }
+
+    /// Reads a small `result` whole and keeps it, unless a write happened
+    /// since `writes` was taken.
+    async fn fill(&self, key: String, result: GetResult, writes: u64) -> Result<GetResult> {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        if result.meta.size as u64 > self.max_object_bytes {
+            return Ok(result);
+        }
+        let (meta, range, attributes) = (
+            result.meta.clone(),
+            result.range.clone(),
+            result.attributes.clone(),
+        );
+        let bytes = result.bytes().await?;
+        if self.writes.load(Ordering::Relaxed) == writes {
+            self.entries.insert(
+                key,
+                MemoryEntry {
+                    bytes: bytes.clone(),
+                    meta: meta.clone(),
+                    attributes: attributes.clone(),
+                },
+            );
+        }
+        Ok(GetResult {
+            payload: bytes_payload(bytes),
+            meta,
+            range,
+            attributes,
+        })
+    }
+}
+
+fn bytes_payload(bytes: Bytes) -> GetResultPayload {
+    GetResultPayload::Stream(futures_util::stream::once(async move { Ok(bytes) }).boxed())
+}
+
+/// Requests for anything but a whole object as it is now go straight to
+/// the backend.
+fn is_plain(options: &GetOptions) -> bool {
+    options.if_match.is_none()
+        && options.if_none_match.is_none()
+        && options.if_modified_since.is_none()
+        && options.if_unmodified_since.is_none()
+        && options.range.is_none()
+        && options.version.is_none()
+        && !options.head
+}
+
+enum Revalidated {
+    Hit(GetResult),
+    /// The object as it is now, to be cached afresh.
+    Changed(GetResult),
+    /// Nothing to serve from disk.
+    Gone,
+}
+
+fn read_error(e: std::io::Error) -> Error {
+    Error::Generic {
+        store: "DownloadCache",
+        source: Box::new(e),
+    }
+}
+
+/// `inner`, its plain downloads cached in `cache` and small ones in
+/// `memory`, where those are set up. Writes through it drop the entries
+/// they replace.
+#[derive(Debug)]
+pub struct CacheStore<T> {
+    inner: T,
+    cache: Option<Arc<DownloadCache>>,
+    memory: Option<Arc<MemoryCache>>,
+    /// Keeps apart the keys of stores sharing one cache.
+    namespace: String,
+}
+
+impl<T> CacheStore<T> {
+    pub fn new(inner: T, cache: Option<Arc<DownloadCache>>, namespace: impl Into<String>) -> Self {
+        Self {
+            inner,
+            cache,
+            memory: None,
+            namespace: namespace.into(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_memory(mut self, memory: Option<Arc<MemoryCache>>) -> Self {
+        self.memory = memory;
+        self
+    }
+
+    fn key(&self, location: &Path) -> String {
+        format!("{}\0{location}", self.namespace)
+    }
+
+    fn invalidation(&self, location: &Path) -> Invalidation {
+        Invalidation {
+            key: self.key(location),
+            cache: self.cache.clone(),
+            memory: self.memory.clone(),
+        }
+    }
+
+    fn invalidate(&self, location: &Path) {
+        self.invalidation(location).run();
+    }
+}
+
+/// Drops the entries of one key from both caches.
+#[derive(Debug)]
+struct Invalidation {
+    key: String,
+    cache: Option<Arc<DownloadCache>>,
+    memory: Option<Arc<MemoryCache>>,
+}
+
+impl Invalidation {
+    fn run(&self) {
+        if let Some(cache) = &self.cache {
+            cache.invalidate(&self.key);
+        }
+        if let Some(memory) = &self.memory {
+            memory.invalidate(&self.key);
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for CacheStore<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<T: ObjectStore> CacheStore<T> {
+    async fn read_through(
+        &self,
+        cache: &Arc<DownloadCache>,
+        key: String,
+        location: &Path,
+        options: GetOptions,
+    ) -> Result<GetResult> {
+        let revalidated = match cache.lookup(&key) {
+            Some(entry) => self.revalidate(cache, &key, location, entry).await?,
+            None => Revalidated::Gone,
+        };
+        let result = match revalidated {
+            Revalidated::Hit(hit) => return Ok(hit),
+            Revalidated::Changed(result) => result,
+            Revalidated::Gone => self.inner.get_opts(location, options).await?,
+        };
+        Ok(cache.fill(key, result))
+    }
+
+    /// Asks the backend whether `entry` is still current, and serves it if so.
+    async fn revalidate(
+        &self,
+        cache: &DownloadCache,
+        key: &str,
+        location: &Path,
+        entry: Entry,
+    ) -> Result<Revalidated> {
+        let options = GetOptions {
+            if_none_match: entry.meta.e_tag.clone(),
+            ..GetOptions::default()
+        };
+        match self.inner.get_opts(location, options).await {
+            Err(Error::NotModified { .. }) => {}
+            Ok(changed) => return Ok(Revalidated::Changed(changed)),
+            Err(e) => {
+                if matches!(e, Error::NotFound { .. }) {
+                    cache.invalidate(key);
+                }
+                return Err(e);
+            }
+        }
+        // Evicted since the lookup.
+        let Ok(file) = tokio::fs::File::open(&entry.file).await else {
+            return Ok(Revalidated::Gone);
+        };
+        cache.hits.fetch_add(1, Ordering::Relaxed);
+        cache.touch(key);
+        let stream = ReaderStream::with_capacity(file, READ_BUFFER).map_err(read_error);
+        Ok(Revalidated::Hit(GetResult {
+            payload: GetResultPayload::Stream(stream.boxed()),
+            range: 0..entry.meta.size,
+            meta: entry.meta,
+            attributes: entry.attributes,
+        }))
+    }
+}
+
+#[async_trait]
+impl<T: ObjectStore> ObjectStore for CacheStore<T> {
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult> {
+        let result = self.inner.put_opts(location, payload, opts).await;
+        self.invalidate(location);
+        result
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOpts,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        let invalidation = self.invalidation(location);
+        invalidation.run();
+        let inner = self.inner.put_multipart_opts(location, opts).await?;
+        Ok(Box::new(InvalidatingUpload {
+            inner,
+            invalidation,
+        }))
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        if !is_plain(&options) || (self.cache.is_none() && self.memory.is_none()) {
+            return self.inner.get_opts(location, options).await;
+        }
+        let key = self.key(location);
+        if let Some(hit) = self.memory.as_ref().and_then(|memory| memory.get(&key)) {
+            return Ok(hit);
+        }
+        let writes = self
+            .memory
+            .as_ref()
+            .map(|memory| memory.writes.load(Ordering::Relaxed));
+        let result = match &self.cache {
+            Some(cache) => {
+                self.read_through(cache, key.clone(), location, options)
+                    .await?
+            }
+            None => self.inner.get_opts(location, options).await?,
+        };
+        match (&self.memory, writes) {
+            (Some(memory), Some(writes)) => memory.fill(key, result, writes).await,
+            _ => Ok(result),
+        }
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        self.inner.get_range(location, range).await
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        self.inner.get_ranges(location, ranges).await
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        let result = self.inner.delete(location).await;
+        self.invalidate(location);
+        result
+    }
+
+    fn delete_stream<'a>(
+        &'a self,
+        locations: BoxStream<'a, Result<Path>>,
+    ) -> BoxStream<'a, Result<Path>> {
+        self.inner
+            .delete_stream(locations)
+            .inspect_ok(|location| self.invalidate(location))
+            .boxed()
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.inner.list(prefix)
+    }
+
+    fn list_with_offset(
+        &self,
+        prefix: Option<&Path>,
+        offset: &Path,
+    ) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.inner.list_with_offset(prefix, offset)
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        let result = self.inner.copy(from, to).await;
+        self.invalidate(to);
+        result
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let result = self.inner.rename(from, to).await;
+        self.invalidate(from);
+        self.invalidate(to);
+        result
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        let result = self.inner.copy_if_not_exists(from, to).await;
+        self.invalidate(to);
+        result
+    }
+
+    async fn rename_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        let result = self.inner.rename_if_not_exists(from, to).await;
+        self.invalidate(from);
+        self.invalidate(to);
+        result
+    }
+}
+
+#[async_trait]
+impl<T: MultipartStore> MultipartStore for CacheStore<T> {
+    async fn create_multipart(&self, path: &Path) -> Result<MultipartId> {
+        self.inner.create_multipart(path).await
+    }
+
+    async fn put_part(
+        &self,
+        path: &Path,
+        id: &MultipartId,
+        part_idx: usize,
+        data: PutPayload,
+    ) -> Result<PartId> {
+        self.inner.put_part(path, id, part_idx, data).await
+    }
+
+    async fn complete_multipart(
+        &self,
+        path: &Path,
+        id: &MultipartId,
+        parts: Vec<PartId>,
+    ) -> Result<PutResult> {
+        let result = self.inner.complete_multipart(path, id, parts).await;
+        self.invalidate(path);
+        result
+    }
+
+    async fn abort_multipart(&self, path: &Path, id: &MultipartId) -> Result<()> {
+        self.inner.abort_multipart(path, id).await
+    }
+}
+
+/// A multipart upload through `CacheStore`, which replaces the object only
+/// once it completes.
+#[derive(Debug)]
+struct InvalidatingUpload {
+    inner: Box<dyn MultipartUpload>,
+    invalidation: Invalidation,
+}
+
+#[async_trait]
+impl MultipartUpload for InvalidatingUpload {
+    fn put_part(&mut self, data: PutPayload) -> UploadPart {
+        self.inner.put_part(data)
+    }
+
+    async fn complete(&mut self) -> Result<PutResult> {
+        let result = self.inner.complete().await;
+        self.invalidation.run();
+        result
+    }
+
+    async fn abort(&mut self) -> Result<()> {
+        self.inner.abort().await
+    }
+}
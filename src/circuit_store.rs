@@ -0,0 +1,502 @@
+//! Stops calling a storage backend that keeps failing. After
+//! `failure_threshold` failures in a row, all within `window`, calls are
+//! refused at once for `cool_down`; then a single call is let through to
+//! find out whether the backend is back.
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures_util::{StreamExt, stream::BoxStream};
+use object_store::{
+    Error, GetOptions, GetResult, GetResultPayload, ListResult, MultipartId, MultipartUpload,
+    ObjectMeta, ObjectStore, PutMultipartOpts, PutOptions, PutPayload, PutResult, Result,
+    UploadPart,
+    multipart::{MultipartStore, PartId},
+    path::Path,
+};
+
+use crate::errors::StorageFailure;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    ops::Range,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+static TRIPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Circuits currently open or probing, over every backend.
+pub fn tripped_circuits() -> u64 {
+    TRIPPED.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitPolicy {
+    /// Zero never opens the circuit.
+    pub failure_threshold: u32,
+    /// Failures further apart than this don't add up.
+    pub window: Duration,
+    pub cool_down: Duration,
+}
+
+/// What a refused call fails with, as the source of an `object_store::Error::Generic`.
+#[derive(Debug, thiserror::Error)]
+#[error("storage circuit is open; retry in {retry_after:?}")]
+pub struct CircuitOpen {
+    pub retry_after: Duration,
+}
+
+/// How long to wait before retrying, if `error` is a call the circuit refused.
+pub fn retry_after(error: &Error) -> Option<Duration> {
+    match error {
+        Error::Generic { source, .. } => source
+            .downcast_ref::<CircuitOpen>()
+            .map(|open| open.retry_after),
+        _ => None,
+    }
+}
+
+/// Errors that say the backend is unwell, as opposed to a request it
+/// answered: a missing object or a refused write still counts as a success.
+fn is_outage(error: &Error) -> bool {
+    match error {
+        Error::Generic { store, .. } => *store != "Encryption",
+        Error::JoinError { .. } => true,
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitStatus {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl CircuitStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half_open",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Closed {
+        failures: u32,
+        since: Option<Instant>,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A probe is out. Should it never answer, another is let through
+    /// after a further cool-down.
+    HalfOpen {
+        probe_started: Instant,
+    },
+}
+
+/// The faults a backend's calls failed with, by `StorageFailure` code.
+#[derive(Debug, Default)]
+struct Faults {
+    counts: BTreeMap<&'static str, u64>,
+    last: Option<(StorageFailure, Instant)>,
+}
+
+/// One backend's circuit, shared by every handler that calls it.
+#[derive(Debug)]
+pub struct CircuitState {
+    policy: CircuitPolicy,
+    state: Mutex<State>,
+    faults: Mutex<Faults>,
+}
+
+impl CircuitState {
+    pub fn new(policy: CircuitPolicy) -> Self {
+        Self {
+            policy,
+            state: Mutex::new(State::Closed {
+                failures: 0,
+                since: None,
+            }),
+            faults: Mutex::default(),
+        }
+    }
+
+    fn faults(&self) -> std::sync::MutexGuard<'_, Faults> {
+        self.faults.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Calls that failed since startup, by the code of their
+    /// `StorageFailure`, faults only.
+    pub fn fault_counts(&self) -> BTreeMap<&'static str, u64> {
+        self.faults().counts.clone()
+    }
+
+    /// The most recent fault and how long ago it was.
+    pub fn last_fault(&self) -> Option<(StorageFailure, Duration)> {
+        self.faults()
+            .last
+            .map(|(failure, at)| (failure, at.elapsed()))
+    }
+
+    fn note(&self, error: &Error) {
+        let failure = StorageFailure::of(error);
+        if failure.is_fault() {
+            let mut faults = self.faults();
+            *faults.counts.entry(failure.code()).or_default() += 1;
+            faults.last = Some((failure, Instant::now()));
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// An open circuit whose cool-down has run out reports `HalfOpen`: the
+    /// next call will probe.
+    pub fn status(&self) -> CircuitStatus {
+        match *self.state() {
+            State::Closed { .. } => CircuitStatus::Closed,
+            State::Open { until } if Instant::now() < until => CircuitStatus::Open,
+            State::Open { .. } | State::HalfOpen { .. } => CircuitStatus::HalfOpen,
+        }
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        match *self.state() {
+            State::Closed { failures, .. } => failures,
+            State::Open { .. } | State::HalfOpen { .. } => self.policy.failure_threshold,
+        }
+    }
+
+    /// How long calls will still be refused; `None` while they are let through.
+    pub fn retry_after(&self) -> Option<Duration> {
+        let now = Instant::now();
+        match *self.state() {
+            State::Closed { .. } => None,
+            State::Open { until } => until.checked_duration_since(now),
+            State::HalfOpen { probe_started } => (probe_started + self.policy.cool_down)
+                .checked_duration_since(now)
+                .filter(|wait| !wait.is_zero()),
+        }
+    }
+
+    fn admit(&self) -> Result<()> {
+        let now = Instant::now();
+        let mut state = self.state();
+        let refused_until = match *state {
+            State::Closed { .. } => return Ok(()),
+            State::Open { until } => until,
+            State::HalfOpen { probe_started } => probe_started + self.policy.cool_down,
+        };
+        if now < refused_until {
+            return Err(Error::Generic {
+                store: "Circuit",
+                source: Box::new(CircuitOpen {
+                    retry_after: refused_until - now,
+                }),
+            });
+        }
+        tracing::info!("probing storage backend after cool-down");
+        *state = State::HalfOpen { probe_started: now };
+        Ok(())
+    }
+
+    fn record<T>(&self, result: &Result<T>) {
+        if let Err(e) = result {
+            self.note(e);
+        }
+        match result {
+            Err(e) if is_outage(e) => self.failed(),
+            _ => self.succeeded(),
+        }
+    }
+
+    fn succeeded(&self) {
+        let mut state = self.state();
+        if !matches!(*state, State::Closed { .. }) {
+            TRIPPED.fetch_sub(1, Ordering::Relaxed);
+            tracing::info!("storage backend answered again; closing circuit");
+        }
+        *state = State::Closed {
+            failures: 0,
+            since: None,
+        };
+    }
+
+    fn failed(&self) {
+        if self.policy.failure_threshold == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut state = self.state();
+        *state = match *state {
+            State::Closed { failures, since } => {
+                let (failures, since) = match since {
+                    Some(since) if now.duration_since(since) <= self.policy.window => {
+                        (failures + 1, since)
+                    }
+                    _ => (1, now),
+                };
+                if failures < self.policy.failure_threshold {
+                    State::Closed {
+                        failures,
+                        since: Some(since),
+                    }
+                } else {
+                    TRIPPED.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!(
+                        failures,
+                        cool_down = ?self.policy.cool_down,
+                        "storage backend keeps failing; opening circuit"
+                    );
+                    State::Open {
+                        until: now + self.policy.cool_down,
+                    }
+                }
+            }
+            State::HalfOpen { .. } => {
+                tracing::warn!("storage probe failed; keeping circuit open");
+                State::Open {
+                    until: now + self.policy.cool_down,
+                }
+            }
+            open @ State::Open { .. } => open,
+        };
+    }
+
+    async fn call<T>(&self, call: impl Future<Output = Result<T>>) -> Result<T> {
+        self.admit()?;
+        let result = call.await;
+        self.record(&result);
+        result
+    }
+
+    /// Records the first error of `stream`, or its end, as the call's outcome.
+    fn watch<'a, T: Send + 'a>(
+        self: &Arc<Self>,
+        stream: BoxStream<'a, Result<T>>,
+    ) -> BoxStream<'a, Result<T>> {
+        if let Err(e) = self.admit() {
+            return futures_util::stream::once(async move { Err(e) }).boxed();
+        }
+        let circuit = self.clone();
+        futures_util::stream::unfold(Some(stream), move |state| {
+            let circuit = circuit.clone();
+            async move {
+                let mut stream = state?;
+                match stream.next().await {
+                    Some(Ok(item)) => Some((Ok(item), Some(stream))),
+                    Some(Err(e)) => {
+                        circuit.note(&e);
+                        if is_outage(&e) {
+                            circuit.failed();
+                        } else {
+                            circuit.succeeded();
+                        }
+                        Some((Err(e), None))
+                    }
+                    None => {
+                        circuit.succeeded();
+                        None
+                    }
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+#[derive(Debug)]
+pub struct CircuitStore<T> {
+    inner: T,
+    circuit: Arc<CircuitState>,
+}
+
+impl<T> CircuitStore<T> {
+    pub fn new(inner: T, circuit: Arc<CircuitState>) -> Self {
+        Self { inner, circuit }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for CircuitStore<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+#[async_trait]
+impl<T: ObjectStore> ObjectStore for CircuitStore<T> {
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult> {
+        self.circuit
+            .call(self.inner.put_opts(location, payload, opts))
+            .await
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOpts,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        let upload = self
+            .circuit
+            .call(self.inner.put_multipart_opts(location, opts))
+            .await?;
+        Ok(Box::new(CircuitUpload {
+            inner: upload,
+            circuit: self.circuit.clone(),
+        }))
+    }
+
+    /// A download that fails halfway counts against the backend too.
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        let result = self
+            .circuit
+            .call(self.inner.get_opts(location, options))
+            .await?;
+        let payload = match result.payload {
+            GetResultPayload::Stream(stream) => {
+                GetResultPayload::Stream(self.circuit.watch(stream))
+            }
+            file => file,
+        };
+        Ok(GetResult { payload, ..result })
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        self.circuit
+            .call(self.inner.get_range(location, range))
+            .await
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        self.circuit
+            .call(self.inner.get_ranges(location, ranges))
+            .await
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.circuit.call(self.inner.head(location)).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.circuit.call(self.inner.delete(location)).await
+    }
+
+    fn delete_stream<'a>(
+        &'a self,
+        locations: BoxStream<'a, Result<Path>>,
+    ) -> BoxStream<'a, Result<Path>> {
+        self.circuit.watch(self.inner.delete_stream(locations))
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.circuit.watch(self.inner.list(prefix))
+    }
+
+    fn list_with_offset(
+        &self,
+        prefix: Option<&Path>,
+        offset: &Path,
+    ) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.circuit
+            .watch(self.inner.list_with_offset(prefix, offset))
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.circuit
+            .call(self.inner.list_with_delimiter(prefix))
+            .await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.circuit.call(self.inner.copy(from, to)).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.circuit.call(self.inner.rename(from, to)).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.circuit
+            .call(self.inner.copy_if_not_exists(from, to))
+            .await
+    }
+
+    async fn rename_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.circuit
+            .call(self.inner.rename_if_not_exists(from, to))
+            .await
+    }
+}
+
+#[async_trait]
+impl<T: MultipartStore> MultipartStore for CircuitStore<T> {
+    async fn create_multipart(&self, path: &Path) -> Result<MultipartId> {
+        self.circuit.call(self.inner.create_multipart(path)).await
+    }
+
+    async fn put_part(
+        &self,
+        path: &Path,
+        id: &MultipartId,
+        part_idx: usize,
+        data: PutPayload,
+    ) -> Result<PartId> {
+        self.circuit
+            .call(self.inner.put_part(path, id, part_idx, data))
+            .await
+    }
+
+    async fn complete_multipart(
+        &self,
+        path: &Path,
+        id: &MultipartId,
+        parts: Vec<PartId>,
+    ) -> Result<PutResult> {
+        self.circuit
+            .call(self.inner.complete_multipart(path, id, parts))
+            .await
+    }
+
+    async fn abort_multipart(&self, path: &Path, id: &MultipartId) -> Result<()> {
+        self.circuit
+            .call(self.inner.abort_multipart(path, id))
+            .await
+    }
+}
+
+#[derive(Debug)]
+struct CircuitUpload {
+    inner: Box<dyn MultipartUpload>,
+    circuit: Arc<CircuitState>,
+}
+
+#[async_trait]
+impl MultipartUpload for CircuitUpload {
+    fn put_part(&mut self, data: PutPayload) -> UploadPart {
+        let circuit = self.circuit.clone();
+        let part = self.inner.put_part(data);
+        Box::pin(async move { circuit.call(part).await })
+    }
+
+    async fn complete(&mut self) -> Result<PutResult> {
+        self.circuit.call(self.inner.complete()).await
+    }
+
+    async fn abort(&mut self) -> Result<()> {
+        self.circuit.call(self.inner.abort()).await
+    }
+}
@@ -0,0 +1,221 @@
+//! Full-text index over document contents, kept in a tantivy index on local disk.
+
+use async_zip::base::read::mem::ZipFileReader;
+use futures_util::AsyncReadExt;
+use loco_rs::{Error, Result};
+use quick_xml::{Reader, events::Event};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tantivy::{
+    Index, IndexReader, IndexWriter, TantivyDocument, Term,
+    collector::TopDocs,
+    directory::MmapDirectory,
+    doc,
+    query::QueryParser,
+    schema::{Field, STORED, STRING, Schema, TEXT, Value},
+    snippet::SnippetGenerator,
+};
+use utoipa::ToSchema;
+
+const WRITER_HEAP_BYTES: usize = 50_000_000;
+const SNIPPET_MAX_CHARS: usize = 200;
+/// Upper bound on the XML read out of a DOCX, so a zip bomb can't exhaust memory.
+const MAX_DOCX_XML_BYTES: u64 = 64 * 1024 * 1024;
+
+const PLAIN_TEXT_EXTENSIONS: [&str; 11] = [
+    "txt", "md", "csv", "tsv", "json", "xml", "html", "htm", "log", "yaml", "yml",
+];
+
+static CONTENT_INDEX: Mutex<Option<Arc<ContentIndex>>> = Mutex::new(None);
+
+pub struct ContentIndex {
+    index: Index,
+    reader: IndexReader,
+    writer: Mutex<IndexWriter>,
+    name: Field,
+    body: Field,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ContentHit {
+    pub name: String,
+    pub score: f32,
+    /// Best matching fragment, with the query terms wrapped in `<b>`.
+    pub snippet: String,
+}
+
+fn index_error(e: impl std::fmt::Display) -> Error {
+    Error::Message(format!("Content index error: {e}"))
+}
+
+/// Opens the index under `dir`, creating it on first use. Only one writer
+/// may hold the directory, so the index is opened once per process.
+pub fn open(dir: &str) -> Result<Arc<ContentIndex>> {
+    let mut slot = CONTENT_INDEX.lock().map_err(index_error)?;
+    if let Some(index) = slot.as_ref() {
+        return Ok(index.clone());
+    }
+
+    let mut schema = Schema::builder();
+    let name = schema.add_text_field("name", STRING | STORED);
+    let body = schema.add_text_field("body", TEXT | STORED);
+
+    std::fs::create_dir_all(dir)?;
+    let directory = MmapDirectory::open(dir).map_err(index_error)?;
+    let index = Index::open_or_create(directory, schema.build()).map_err(index_error)?;
+    let reader = index.reader().map_err(index_error)?;
+    let writer = index.writer(WRITER_HEAP_BYTES).map_err(index_error)?;
+
+    let opened = Arc::new(ContentIndex {
+        index,
+        reader,
+        writer: Mutex::new(writer),
+        name,
+        body,
+    });
+    *slot = Some(opened.clone());
+    Ok(opened)
+}
+
+impl ContentIndex {
+    /// Makes `text` the indexed content of `name`, or drops `name` when `None`.
+    /// Blocks on disk I/O.
+    pub fn replace(&self, name: &str, text: Option<&str>) -> Result<()> {
+        let mut writer = self.writer.lock().map_err(index_error)?;
+        writer.delete_term(Term::from_field_text(self.name, name));
+        if let Some(text) = text {
+            writer
+                .add_document(doc!(self.name => name, self.body => text))
+                .map_err(index_error)?;
+        }
+        writer.commit().map_err(index_error)?;
+        Ok(())
+    }
+
+    /// Drops every document, for a rebuild. Blocks on disk I/O.
+    pub fn clear(&self) -> Result<()> {
+        let mut writer = self.writer.lock().map_err(index_error)?;
+        writer.delete_all_documents().map_err(index_error)?;
+        writer.commit().map_err(index_error)?;
+        Ok(())
+    }
+
+    /// Best `limit` matches for `query`, most relevant first.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<ContentHit>> {
+        let parser = QueryParser::for_index(&self.index, vec![self.body]);
+        let query = parser
+            .parse_query(query)
+            .map_err(|e| Error::BadRequest(format!("Invalid search query: {e}")))?;
+
+        self.reader.reload().map_err(index_error)?;
+        let searcher = self.reader.searcher();
+        let top_docs = searcher
+            .search(&query, &TopDocs::with_limit(limit).order_by_score())
+            .map_err(index_error)?;
+        let mut snippets =
+            SnippetGenerator::create(&searcher, &*query, self.body).map_err(index_error)?;
+        snippets.set_max_num_chars(SNIPPET_MAX_CHARS);
+
+        top_docs
+            .into_iter()
+            .map(|(score, address)| {
+                let doc: TantivyDocument = searcher.doc(address).map_err(index_error)?;
+                let name = doc
+                    .get_first(self.name)
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                Ok(ContentHit {
+                    name,
+                    score,
+                    snippet: snippets.snippet_from_doc(&doc).to_html(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Text content of a supported document, `Ok(None)` for formats that aren't
+/// indexed and `Err` for files that claim a supported format but can't be read.
+pub async fn extract_text(
+    file_name: &str,
+    bytes: Vec<u8>,
+) -> std::result::Result<Option<String>, String> {
+    let extension = file_name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        ext if PLAIN_TEXT_EXTENSIONS.contains(&ext) => {
+            Ok(Some(String::from_utf8_lossy(&bytes).into_owned()))
+        }
+        // pdf-extract can panic on malformed input; the blocking task contains it.
+        "pdf" => tokio::task::spawn_blocking(move || pdf_extract::extract_text_from_mem(&bytes))
+            .await
+            .map_err(|e| format!("PDF extraction panicked: {e}"))?
+            .map(Some)
+            .map_err(|e| format!("Invalid PDF: {e}")),
+        "docx" => docx_text(bytes).await.map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Joins the text runs of `word/document.xml`, one line per paragraph.
+async fn docx_text(bytes: Vec<u8>) -> std::result::Result<String, String> {
+    let reader = ZipFileReader::new(bytes)
+        .await
+        .map_err(|e| format!("Invalid DOCX: {e}"))?;
+    let index = reader
+        .file()
+        .entries()
+        .iter()
+        .position(|e| {
+            e.filename()
+                .as_str()
+                .is_ok_and(|n| n == "word/document.xml")
+        })
+        .ok_or("DOCX has no word/document.xml")?;
+
+    let mut xml = Vec::new();
+    reader
+        .reader_with_entry(index)
+        .await
+        .map_err(|e| format!("Invalid DOCX: {e}"))?
+        .take(MAX_DOCX_XML_BYTES)
+        .read_to_end(&mut xml)
+        .await
+        .map_err(|e| format!("Invalid DOCX: {e}"))?;
+
+    let mut reader = Reader::from_reader(&xml[..]);
+    let mut text = String::new();
+    let mut in_run_text = false;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if e.name().as_ref() == b"w:t" => in_run_text = true,
+            Ok(Event::End(e)) if e.name().as_ref() == b"w:t" => in_run_text = false,
+            Ok(Event::End(e)) if e.name().as_ref() == b"w:p" => text.push('\n'),
+            Ok(Event::Text(t)) if in_run_text => {
+                text.push_str(&t.decode().map_err(|e| format!("Invalid DOCX: {e}"))?);
+            }
+            Ok(Event::GeneralRef(r)) if in_run_text => {
+                let resolved = match r.resolve_char_ref() {
+                    Ok(Some(c)) => Some(c),
+                    _ => match &*r {
+                        b"amp" => Some('&'),
+                        b"lt" => Some('<'),
+                        b"gt" => Some('>'),
+                        b"quot" => Some('"'),
+                        b"apos" => Some('\''),
+                        _ => None,
+                    },
+                };
+                text.extend(resolved);
+            }
+            Ok(Event::Eof) => break,
+            Ok(_) => {}
+            Err(e) => return Err(format!("Invalid DOCX: {e}")),
+        }
+    }
+    Ok(text)
+}
@@ -0,0 +1,70 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::HeaderMap,
+    routing::get,
+};
+use loco_rs::{controller::Routes, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{audit_writer, controllers::auth, models::file_audit_log};
+
+const DEFAULT_AUDIT_LIMIT: u64 = 50;
+const MAX_AUDIT_LIMIT: u64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct AuditLogParams {
+    pub limit: Option<u64>,
+    pub page_token: Option<String>,
+    pub file_key: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditLogResponse {
+    pub entries: Vec<file_audit_log::Model>,
+    pub next_page_token: Option<String>,
+}
+
+/// Newest entries first; `page_token` continues where the last page ended.
+pub async fn list_audit_log(
+    State(ctx): State<AppContext>,
+    headers: HeaderMap,
+    Query(params): Query<AuditLogParams>,
+) -> Result<Json<AuditLogResponse>> {
+    auth::require_scope(&headers, "admin")?;
+
+    let before_id = params
+        .page_token
+        .as_deref()
+        .map(|t| t.parse::<i64>())
+        .transpose()
+        .map_err(|_| Error::BadRequest("Invalid page_token".into()))?;
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_AUDIT_LIMIT)
+        .clamp(1, MAX_AUDIT_LIMIT);
+
+    let filter = file_audit_log::Filter {
+        file_key: params.file_key,
+        ..Default::default()
+    };
+    audit_writer::flush(&ctx).await;
+    let mut entries = file_audit_log::page(&ctx.db, before_id, &filter, limit + 1).await?;
+    let next_page_token = if entries.len() as u64 > limit {
+        entries.truncate(limit as usize);
+        entries.last().map(|e| e.id.to_string())
+    } else {
+        None
+    };
+
+    Ok(Json(AuditLogResponse {
+        entries,
+        next_page_token,
+    }))
+}
+
+pub fn routes() -> Routes {
+    Routes::new()
+        .prefix("/admin")
+        .add("/audit-log", get(list_audit_log))
+}
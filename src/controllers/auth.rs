@@ -1,13 +1,14 @@
 use axum::{
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     routing::{get, post},
 };
 use chrono::{Duration, Utc};
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
-use loco_rs::controller::Routes;
+use loco_rs::controller::{ErrorDetail, Routes};
 use loco_rs::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sha2::{Digest, Sha256};
 
 use crate::models::{role, user};
 
@@ -19,6 +20,18 @@ pub struct Claims {
     pub pid: String,
     pub login: String,
     pub exp: usize,
+    /// Role name plus the role's attributes, e.g. `["admin", "read"]`.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// The tenant whose storage the user works in, when there is more than one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+impl Claims {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -69,7 +82,14 @@ pub async fn register(
     let role_name = payload.role_name.unwrap_or_else(|| "dummy".to_string());
     let role = match role::find_by_name(&ctx.db, &role_name).await? {
         Some(r) => r,
-        None => role::create(&ctx.db, &role_name, serde_json::json!(["read"])).await?,
+        None => {
+            role::create(
+                &ctx.db,
+                &role_name,
+                serde_json::json!(["read", "files:write"]),
+            )
+            .await?
+        }
     };
 
     let password_hash = hash_password(&payload.password)?;
@@ -115,7 +135,13 @@ pub async fn login(
         .one(&ctx.db)
         .await?;
 
-    let token = generate_token(&found_user.id.to_string(), &found_user.login)?;
+    let scopes = user_role.as_ref().map(role_scopes).unwrap_or_default();
+    let token = generate_tenant_token(
+        &found_user.id.to_string(),
+        &found_user.login,
+        scopes,
+        found_user.tenant_id.as_deref(),
+    )?;
 
     let response = AuthResponse {
         token,
@@ -165,7 +191,30 @@ fn verify_password(password: &str, hash: &str) -> Result<bool> {
     bcrypt::verify(password, hash).map_err(|e| Error::Message(e.to_string()))
 }
 
-fn generate_token(user_id: &str, login: &str) -> Result<String> {
+fn role_scopes(role: &role::Model) -> Vec<String> {
+    let mut scopes = vec![role.name.clone()];
+    if let Some(attributes) = role.attributes.as_array() {
+        scopes.extend(
+            attributes
+                .iter()
+                .filter_map(|a| a.as_str())
+                .map(String::from),
+        );
+    }
+    scopes
+}
+
+pub fn generate_token(user_id: &str, login: &str, scopes: Vec<String>) -> Result<String> {
+    generate_tenant_token(user_id, login, scopes, None)
+}
+
+/// A token that also names the user's tenant.
+pub fn generate_tenant_token(
+    user_id: &str,
+    login: &str,
+    scopes: Vec<String>,
+    tenant: Option<&str>,
+) -> Result<String> {
     let expiration = Utc::now()
         .checked_add_signed(Duration::hours(TOKEN_LIFETIME_HOURS))
         .expect("valid timestamp")
@@ -175,6 +224,8 @@ fn generate_token(user_id: &str, login: &str) -> Result<String> {
         pid: user_id.to_string(),
         login: login.to_string(),
         exp: expiration,
+        scopes,
+        tenant: tenant.map(str::to_string),
     };
 
     encode(
@@ -195,6 +246,32 @@ pub fn decode_token(token: &str) -> Result<Claims> {
     .map_err(|e| Error::Message(e.to_string()))
 }
 
+/// Names `token` in logs without giving it away: the start of its SHA-256.
+pub fn token_id(token: &str) -> String {
+    hex::encode(&Sha256::digest(token.as_bytes())[..8])
+}
+
+pub fn claims_from_headers(headers: &HeaderMap) -> Result<Claims> {
+    let auth_header = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| Error::Unauthorized("Missing Authorization header".into()))?;
+
+    let token = auth_header.strip_prefix("Bearer ").unwrap_or(auth_header);
+    decode_token(token).map_err(|e| Error::Unauthorized(e.to_string()))
+}
+
+pub fn require_scope(headers: &HeaderMap, scope: &str) -> Result<Claims> {
+    let claims = claims_from_headers(headers)?;
+    if !claims.has_scope(scope) {
+        return Err(Error::CustomError(
+            StatusCode::FORBIDDEN,
+            ErrorDetail::new("missing_scope", &format!("Missing '{scope}' scope")),
+        ));
+    }
+    Ok(claims)
+}
+
 pub fn routes() -> Routes {
     Routes::new()
         .prefix("/auth")
@@ -0,0 +1,452 @@
+//! Gzipped copies of a file, and zip and tar downloads of many.
+
+use super::*;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ArchiveParams {
+    /// Replace an existing `<name>.gz`.
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ArchiveResponse {
+    pub archived_key: String,
+    pub original_size: u64,
+    pub archived_size: u64,
+    /// `original_size / archived_size`.
+    pub compression_ratio: f64,
+}
+
+/// Gzips a file into a new `<name>.gz` object next to it. The content is
+/// streamed through the encoder into a multipart upload, so it is never held
+/// in memory whole; the original stays as it is.
+#[utoipa::path(
+    post,
+    path = "/files/{file_name}/archive",
+    operation_id = "archiveFile",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included"), ArchiveParams),
+    responses(
+        (status = 200, description = "The gzipped copy was stored", body = ArchiveResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 409, description = "`<name>.gz` exists, or the file is stored gzipped already", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn archive_file(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+    Query(params): Query<ArchiveParams>,
+) -> FileResult<Json<ArchiveResponse>> {
+    auth::claims_from_headers(&headers)?;
+
+    let record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id()).await?;
+    if let Some(record) = &record {
+        ensure_servable(record)?;
+    }
+    let source = record
+        .as_ref()
+        .map_or_else(|| ObjectPath::from(file_name.as_str()), latest_path);
+    let archived_key = format!("{file_name}{GZIP_SUFFIX}");
+    let target = ObjectPath::from(archived_key.as_str());
+
+    let result = store.get(&source).await.map_err(FileError::StorageError)?;
+    // A gzipped upload already lives at `<name>.gz`; archiving it would
+    // replace the file's own content.
+    if is_gzip_encoded(&result.attributes) || source == target {
+        return Err(FileError::Rejected {
+            status: StatusCode::CONFLICT,
+            code: "already_compressed".into(),
+            message: format!("'{file_name}' is stored gzipped already"),
+            details: None,
+        });
+    }
+    if !params.overwrite {
+        match store.head(&target).await {
+            Ok(_) => {
+                return Err(FileError::Rejected {
+                    status: StatusCode::CONFLICT,
+                    code: "archive_exists".into(),
+                    message: format!("'{archived_key}' already exists"),
+                    details: None,
+                });
+            }
+            Err(ObjectStoreError::NotFound { .. }) => {}
+            Err(e) => return Err(FileError::StorageError(e)),
+        }
+    }
+
+    let config = get_s3_config(&ctx);
+    let part_size = config.multipart_threshold_bytes.max(MIN_PART_BYTES as u64) as usize;
+    let mut attributes = Attributes::new();
+    attributes.insert(Attribute::ContentType, "application/gzip".into());
+    let writer = BufWriter::with_capacity(store.clone(), target.clone(), part_size)
+        .with_attributes(attributes);
+    let mut encoder = GzipEncoder::new(writer);
+
+    let mut original_size = 0u64;
+    let mut chunks = result.into_stream();
+    let written: std::io::Result<()> = async {
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk?;
+            original_size += chunk.len() as u64;
+            encoder.write_all(&chunk).await?;
+        }
+        encoder.shutdown().await
+    }
+    .await;
+    if let Err(e) = written {
+        // Nothing is left behind: the upload only completes on shutdown.
+        let _ = encoder.get_mut().abort().await;
+        return Err(FileError::Internal(format!("Archive '{file_name}': {e}")));
+    }
+
+    let archived_size = store
+        .head(&target)
+        .await
+        .map_err(FileError::StorageError)?
+        .size as u64;
+    Ok(Json(ArchiveResponse {
+        archived_key,
+        original_size,
+        archived_size,
+        compression_ratio: original_size as f64 / archived_size as f64,
+    }))
+}
+
+/// Size of the in-memory pipe between the zip writer task and the response body.
+pub(super) const ZIP_PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Name of the archive entry listing files skipped with `ignore_missing`.
+pub(super) const ZIP_MISSING_MANIFEST: &str = "MISSING.txt";
+
+#[utoipa::path(
+    post,
+    path = "/files/batch/download",
+    operation_id = "downloadFileBatch",
+    tag = "files",
+    request_body(content = BatchDownloadRequest),
+    responses(
+        (status = 200, description = "A zip archive of the files", content_type = "application/zip", body = [u8]),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn batch_download(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    headers: HeaderMap,
+    Json(req): Json<BatchDownloadRequest>,
+) -> FileResult<Response> {
+    auth::claims_from_headers(&headers)?;
+
+    let mut seen = HashSet::new();
+    let names: Vec<String> = req
+        .files
+        .iter()
+        .map(|name| normalize_name(name.trim_matches('/')))
+        .filter(|name| !name.is_empty() && seen.insert(name.clone()))
+        .collect();
+
+    if names.is_empty() {
+        return Err(FileError::BadRequest("No files requested".into()));
+    }
+
+    let mut entries = Vec::with_capacity(names.len());
+    for name in names {
+        let record = file::find_by_name_for_tenant(&ctx.db, &name, tenant.id()).await?;
+        if let Some(record) = &record {
+            ensure_servable(record)?;
+        }
+        let path = record.map_or_else(|| ObjectPath::from(name.as_str()), |f| latest_path(&f));
+        entries.push((name, path));
+    }
+
+    // The status line goes out before the first entry is written, so without
+    // `ignore_missing` every file has to be checked up front.
+    if !req.ignore_missing {
+        for (name, path) in &entries {
+            store.head(path).await.map_err(|e| match e {
+                ObjectStoreError::NotFound { .. } => file_not_found(name),
+                e => FileError::StorageError(e),
+            })?;
+        }
+    }
+
+    let archive_name = req
+        .archive_name
+        .unwrap_or_else(|| format!("files-{}.zip", Utc::now().format("%Y%m%d-%H%M%S")));
+
+    let (writer, reader) = tokio::io::duplex(ZIP_PIPE_CAPACITY);
+    let ignore_missing = req.ignore_missing;
+    tokio::spawn(async move {
+        if let Err(e) = write_zip_archive(store, entries, ignore_missing, writer).await {
+            tracing::error!(error = %e, "zip archive stream aborted");
+        }
+    });
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(header::CONTENT_DISPOSITION, attachment(&archive_name))
+        .body(Body::from_stream(ReaderStream::new(reader)))
+        .map_err(|e| FileError::Internal(format!("Build response: {e}")))?;
+
+    Ok(response)
+}
+
+/// Streams each object into its own zip entry, keyed by the full file name so
+/// files with the same basename in different folders stay apart.
+pub(super) async fn write_zip_archive(
+    store: Arc<dyn ObjectStore>,
+    entries: Vec<(String, ObjectPath)>,
+    ignore_missing: bool,
+    writer: DuplexStream,
+) -> Result<()> {
+    let mut zip = ZipFileWriter::with_tokio(writer);
+    let mut missing = Vec::new();
+
+    for (name, path) in entries {
+        let result = match store.get(&path).await {
+            Ok(r) => r,
+            Err(ObjectStoreError::NotFound { .. }) if ignore_missing => {
+                missing.push(name);
+                continue;
+            }
+            Err(e) => return Err(Error::wrap(e)),
+        };
+
+        let entry = ZipEntryBuilder::new(name.into(), Compression::Deflate);
+        let mut entry_writer = zip
+            .write_entry_stream(entry)
+            .await
+            .map_err(|e| Error::Message(format!("Zip error: {e}")))?;
+
+        // Archive entries hold the original bytes, so gzipped uploads are
+        // decoded on the way through.
+        let gzip_encoded = is_gzip_encoded(&result.attributes);
+        let raw = StreamReader::new(result.into_stream());
+        let mut reader: Pin<Box<dyn tokio::io::AsyncRead + Send>> = if gzip_encoded {
+            Box::pin(GzipDecoder::new(raw))
+        } else {
+            Box::pin(raw)
+        };
+
+        let mut buf = vec![0; ZIP_PIPE_CAPACITY];
+        loop {
+            let n = reader.read(&mut buf).await.map_err(read_error)?;
+            if n == 0 {
+                break;
+            }
+            entry_writer.write_all(&buf[..n]).await?;
+        }
+
+        entry_writer
+            .close()
+            .await
+            .map_err(|e| Error::Message(format!("Zip error: {e}")))?;
+    }
+
+    if !missing.is_empty() {
+        let manifest = format!(
+            "The following files were not found and have been omitted:\n{}\n",
+            missing.join("\n")
+        );
+        let entry = ZipEntryBuilder::new(ZIP_MISSING_MANIFEST.into(), Compression::Deflate);
+        zip.write_entry_whole(entry, manifest.as_bytes())
+            .await
+            .map_err(|e| Error::Message(format!("Zip error: {e}")))?;
+    }
+
+    zip.close()
+        .await
+        .map_err(|e| Error::Message(format!("Zip error: {e}")))?;
+
+    Ok(())
+}
+
+/// A failed read of an object's content, as the storage error under it
+/// when there is one.
+pub(super) fn read_error(e: std::io::Error) -> Error {
+    let kind = e.kind();
+    match e
+        .into_inner()
+        .map(|inner| inner.downcast::<ObjectStoreError>())
+    {
+        Some(Ok(e)) => Error::wrap(*e),
+        Some(Err(inner)) => Error::Message(format!("Read error: {inner}")),
+        None => Error::Message(format!("Read error: {kind}")),
+    }
+}
+
+/// Exports the files under `prefix` as a tar. Quarantined files and files
+/// whose object is gone are left out.
+#[utoipa::path(
+    get,
+    path = "/files/export.tar",
+    operation_id = "exportFiles",
+    tag = "files",
+    params(ExportParams),
+    responses(
+        (status = 200, description = "A tar archive of the files, or with `gzip` a gzipped one", content_type = "application/x-tar", body = [u8]),
+        (status = 400, description = "Invalid prefix", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn export_files(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    Query(params): Query<ExportParams>,
+) -> FileResult<Response> {
+    auth::claims_from_headers(&headers)?;
+    let prefix = folder_prefix(params.prefix.as_deref().unwrap_or_default())?;
+    let records =
+        file::find_by_name_prefix(&ctx.db, prefix.as_deref().unwrap_or_default(), tenant.id())
+            .await?;
+
+    let mut entries = Vec::with_capacity(records.len());
+    for record in records {
+        if ensure_servable(&record).is_err() {
+            continue;
+        }
+        match export_entry(store.as_ref(), &record.name, Some(&record)).await {
+            Ok(entry) => entries.push(entry),
+            Err(FileError::NotFound(_)) => {
+                tracing::warn!(file_name = %record.name, "indexed file has no object, left out of export");
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    tar_response(store, entries, params.gzip.unwrap_or(false))
+}
+
+#[utoipa::path(
+    post,
+    path = "/files/export.tar",
+    operation_id = "exportFileList",
+    tag = "files",
+    request_body(content = ExportRequest),
+    responses(
+        (status = 200, description = "A tar archive of the files, or with `gzip` a gzipped one", content_type = "application/x-tar", body = [u8]),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "A file is quarantined", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn export_file_list(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    Json(req): Json<ExportRequest>,
+) -> FileResult<Response> {
+    auth::claims_from_headers(&headers)?;
+
+    let mut seen = HashSet::new();
+    let names: Vec<String> = req
+        .files
+        .iter()
+        .map(|name| normalize_name(name.trim_matches('/')))
+        .filter(|name| !name.is_empty() && seen.insert(name.clone()))
+        .collect();
+    if names.is_empty() {
+        return Err(FileError::BadRequest("No files requested".into()));
+    }
+
+    let mut entries = Vec::with_capacity(names.len());
+    for name in names {
+        let record = file::find_by_name_for_tenant(&ctx.db, &name, tenant.id()).await?;
+        if let Some(record) = &record {
+            ensure_servable(record)?;
+        }
+        entries.push(export_entry(store.as_ref(), &name, record.as_ref()).await?);
+    }
+    tar_response(store, entries, req.gzip)
+}
+
+/// Where `name` is stored and the size its tar header gives. Gzipped
+/// objects are archived decoded, at the size the index has for them.
+pub(super) async fn export_entry(
+    store: &dyn ObjectStore,
+    name: &str,
+    record: Option<&file::Model>,
+) -> FileResult<TarEntry> {
+    let path = record.map_or_else(|| ObjectPath::from(name), latest_path);
+    let meta = store.head(&path).await.map_err(|e| match e {
+        ObjectStoreError::NotFound { .. } => file_not_found(name),
+        e => FileError::StorageError(e),
+    })?;
+    let size = match record {
+        Some(record) if record.compressed && record.version == 1 => record.size as u64,
+        _ => meta.size as u64,
+    };
+    Ok(TarEntry {
+        name: name.to_string(),
+        path,
+        size,
+        modified: meta.last_modified,
+    })
+}
+
+/// Streams the archive as it is written. An archive that had to stop ends
+/// the body with an error, so the client sees the transfer fail too.
+pub(super) fn tar_response(
+    store: Arc<dyn ObjectStore>,
+    entries: Vec<TarEntry>,
+    gzip: bool,
+) -> FileResult<Response> {
+    let (writer, reader) = tokio::io::duplex(ZIP_PIPE_CAPACITY);
+    let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let written = if gzip {
+            tar_stream::write_archive(store.as_ref(), &entries, GzipEncoder::new(writer)).await
+        } else {
+            tar_stream::write_archive(store.as_ref(), &entries, writer).await
+        };
+        if let Err(e) = &written {
+            tracing::error!(error = %e, "tar export stream aborted");
+        }
+        let _ = done_tx.send(written.map_err(|e| e.to_string()));
+    });
+
+    let finished = futures_util::stream::once(async move {
+        match done_rx.await {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(Err(std::io::Error::other(e))),
+            Err(_) => Some(Err(std::io::Error::other("tar export task ended early"))),
+        }
+    })
+    .filter_map(futures_util::future::ready);
+
+    let (content_type, extension) = if gzip {
+        ("application/gzip", ".tar.gz")
+    } else {
+        ("application/x-tar", ".tar")
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"files-{}{extension}\"",
+                Utc::now().format("%Y%m%d-%H%M%S")
+            ),
+        )
+        .body(Body::from_stream(ReaderStream::new(reader).chain(finished)))
+        .map_err(|e| FileError::Internal(format!("Build response: {e}")))
+}
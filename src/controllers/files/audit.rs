@@ -0,0 +1,234 @@
+//! The audit trail of file operations and the access log.
+
+use super::*;
+
+/// Opens the access log `settings.access_log` names, if any, for uploads
+/// and downloads to be written to.
+pub async fn open_access_log(ctx: &AppContext) -> Result<()> {
+    let config = get_s3_config(ctx);
+    let Some(path) = &config.access_log.path else {
+        return Ok(());
+    };
+    let logger = AccessLogger::open(path, config.access_log.rotate_bytes)
+        .await
+        .map_err(|e| Error::Message(format!("Access log '{path}' is unusable: {e}")))?;
+    access_log::install(ctx, Arc::new(logger));
+    Ok(())
+}
+
+/// Moves the rotated access logs to the default bucket, returning how many
+/// went.
+pub async fn archive_access_logs(ctx: &AppContext) -> Result<usize> {
+    let config = get_s3_config(ctx);
+    let Some(path) = &config.access_log.path else {
+        return Ok(0);
+    };
+    let store = shared_store(&config)?;
+    access_log::archive(store.as_ref(), std::path::Path::new(path))
+        .await
+        .map_err(|e| Error::Message(format!("Failed to archive access logs: {e}")))
+}
+
+/// Removes the audit log entries older than `audit_retention_days`,
+/// returning how many there were; none while that is unset.
+pub async fn purge_audit_log(ctx: &AppContext) -> Result<u64> {
+    let Some(days) = get_s3_config(ctx).audit_retention_days else {
+        return Ok(0);
+    };
+    let cutoff = Utc::now() - chrono::Duration::days(days.into());
+    Ok(file_audit_log::delete_older_than(&ctx.db, cutoff.naive_utc()).await?)
+}
+
+pub(super) const DEFAULT_AUDIT_LIMIT: u64 = 50;
+pub(super) const MAX_AUDIT_LIMIT: u64 = 200;
+
+/// One audit log entry, as the `/files` audit endpoints return it.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AuditEntry {
+    pub id: i64,
+    /// `UPLOAD`, `DOWNLOAD`, `LINK_ACCESS`, `DELETE`, `COPY` or `MOVE`.
+    pub operation: String,
+    pub file_key: Option<String>,
+    pub user_id: Option<i32>,
+    /// The start of the SHA-256 of the bearer token or signed link used.
+    pub token_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub bytes: Option<i64>,
+    pub status_code: Option<i32>,
+    /// `SUCCESS` or `FAILURE`.
+    pub outcome: String,
+    pub error_message: Option<String>,
+    pub created_at: String,
+}
+
+impl From<file_audit_log::Model> for AuditEntry {
+    fn from(entry: file_audit_log::Model) -> Self {
+        Self {
+            id: entry.id,
+            operation: entry.operation,
+            file_key: entry.file_key,
+            user_id: entry.user_id,
+            token_id: entry.token_id,
+            ip_address: entry.ip_address,
+            user_agent: entry.user_agent,
+            bytes: entry.bytes,
+            status_code: entry.status_code,
+            outcome: entry.outcome,
+            error_message: entry.error_message,
+            created_at: entry.created_at.and_utc().to_rfc3339(),
+        }
+    }
+}
+
+/// Newest first.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AuditTrail {
+    pub entries: Vec<AuditEntry>,
+    /// Pass as `page_token` for the next page; unset on the last one.
+    pub next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditPageParams {
+    /// Entries per page, 50 by default and at most 200.
+    pub limit: Option<u64>,
+    pub page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditSearchParams {
+    /// Only entries of the user with this id.
+    pub user: Option<i32>,
+    /// An RFC 3339 timestamp; only entries from then on.
+    pub from: Option<String>,
+    /// An RFC 3339 timestamp; only entries from before then.
+    pub to: Option<String>,
+    /// Entries per page, 50 by default and at most 200.
+    pub limit: Option<u64>,
+    pub page_token: Option<String>,
+}
+
+pub(super) fn audit_time(
+    name: &str,
+    value: Option<&str>,
+) -> FileResult<Option<chrono::NaiveDateTime>> {
+    value
+        .map(|v| {
+            chrono::DateTime::parse_from_rfc3339(v)
+                .map(|at| at.naive_utc())
+                .map_err(|_| FileError::BadRequest(format!("{name} must be an RFC 3339 timestamp")))
+        })
+        .transpose()
+}
+
+/// A page of the entries `filter` matches, once those still queued are
+/// written.
+pub(super) async fn audit_page(
+    ctx: &AppContext,
+    filter: &file_audit_log::Filter,
+    limit: Option<u64>,
+    page_token: Option<&str>,
+) -> FileResult<AuditTrail> {
+    let before_id = page_token
+        .map(str::parse::<i64>)
+        .transpose()
+        .map_err(|_| FileError::BadRequest("Invalid page_token".into()))?;
+    let limit = limit
+        .unwrap_or(DEFAULT_AUDIT_LIMIT)
+        .clamp(1, MAX_AUDIT_LIMIT);
+
+    audit_writer::flush(ctx).await;
+    let mut entries = file_audit_log::page(&ctx.db, before_id, filter, limit + 1).await?;
+    let next_page_token = if entries.len() as u64 > limit {
+        entries.truncate(limit as usize);
+        entries.last().map(|e| e.id.to_string())
+    } else {
+        None
+    };
+    Ok(AuditTrail {
+        entries: entries.into_iter().map(AuditEntry::from).collect(),
+        next_page_token,
+    })
+}
+
+/// The audit log of one file. Open to admins, and to the file's owner while
+/// the file exists.
+#[utoipa::path(
+    get,
+    path = "/files/{file_name}/audit",
+    operation_id = "getFileAudit",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included"), AuditPageParams),
+    responses(
+        (status = 200, description = "What was done to the file, newest first", body = AuditTrail),
+        (status = 400, description = "Invalid page_token", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "The caller neither owns the file nor is an admin", body = ErrorBody),
+        (status = 404, description = "No such file, for a caller who isn't an admin", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn get_file_audit(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+    Query(params): Query<AuditPageParams>,
+) -> FileResult<Json<AuditTrail>> {
+    let claims = auth::claims_from_headers(&headers)?;
+    if !claims.has_scope("admin") {
+        let record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+            .await?
+            .ok_or_else(|| file_not_found(&file_name))?;
+        if record.author_id.is_none() || record.author_id != claims.pid.parse().ok() {
+            return Err(FileError::Rejected {
+                status: StatusCode::FORBIDDEN,
+                code: "not_file_owner".into(),
+                message: format!(
+                    "Only admins and the owner of '{file_name}' can read its audit log"
+                ),
+                details: None,
+            });
+        }
+    }
+    let filter = file_audit_log::Filter {
+        file_key: Some(file_name),
+        ..Default::default()
+    };
+    let trail = audit_page(&ctx, &filter, params.limit, params.page_token.as_deref()).await?;
+    Ok(Json(trail))
+}
+
+/// The audit log of every file, narrowed down by user and time. Admins only.
+#[utoipa::path(
+    get,
+    path = "/files/audit",
+    operation_id = "searchAuditLog",
+    tag = "files",
+    params(AuditSearchParams),
+    responses(
+        (status = 200, description = "The matching entries, newest first", body = AuditTrail),
+        (status = 400, description = "Invalid from, to or page_token", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "Not an admin", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn search_audit_log(
+    State(ctx): State<AppContext>,
+    headers: HeaderMap,
+    Query(params): Query<AuditSearchParams>,
+) -> FileResult<Json<AuditTrail>> {
+    auth::require_scope(&headers, "admin")?;
+    let filter = file_audit_log::Filter {
+        user_id: params.user,
+        from: audit_time("from", params.from.as_deref())?,
+        to: audit_time("to", params.to.as_deref())?,
+        ..Default::default()
+    };
+    let trail = audit_page(&ctx, &filter, params.limit, params.page_token.as_deref()).await?;
+    Ok(Json(trail))
+}
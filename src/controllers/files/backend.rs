@@ -0,0 +1,243 @@
+//! Building the stores files are kept in, with the layers every request
+//! goes through.
+
+use super::*;
+
+/// The multipart API of the store, which resumable uploads are assembled
+/// with. The local filesystem backend has none.
+#[derive(Clone)]
+pub struct PartStore(pub Option<Arc<dyn MultipartStore>>);
+
+/// A bucket's store, its multipart side and the circuit breaker both go through.
+pub(super) type StorageBackend = (Arc<dyn ObjectStore>, PartStore, Arc<CircuitState>);
+
+pub(super) static STORE: OnceLock<StorageBackend> = OnceLock::new();
+
+/// Shared by the stores of every tenant.
+pub(super) static DOWNLOAD_CACHE: OnceLock<Arc<DownloadCache>> = OnceLock::new();
+
+pub(super) fn download_cache(config: &S3Config) -> Result<Option<Arc<DownloadCache>>> {
+    let Some(dir) = &config.download_cache.dir else {
+        return Ok(None);
+    };
+    if let Some(cache) = DOWNLOAD_CACHE.get() {
+        return Ok(Some(cache.clone()));
+    }
+    let cache = DownloadCache::open(dir, config.download_cache.max_bytes)
+        .map_err(|e| Error::Message(format!("Download cache '{dir}' is unusable: {e}")))?;
+    Ok(Some(DOWNLOAD_CACHE.get_or_init(|| Arc::new(cache)).clone()))
+}
+
+pub(super) static MEMORY_CACHE: OnceLock<Arc<MemoryCache>> = OnceLock::new();
+
+pub(super) fn memory_cache(config: &S3Config) -> Option<Arc<MemoryCache>> {
+    let settings = &config.memory_cache;
+    if settings.max_bytes == 0 {
+        return None;
+    }
+    let cache = MEMORY_CACHE.get_or_init(|| {
+        Arc::new(MemoryCache::new(
+            settings.max_bytes,
+            settings.max_object_bytes,
+            Duration::from_secs(settings.ttl_seconds),
+        ))
+    });
+    Some(cache.clone())
+}
+
+/// Refuses to start with storage settings that can't work.
+pub fn validate_storage_config(ctx: &AppContext) -> Result<()> {
+    get_s3_config(ctx).validate().map_err(|e| {
+        tracing::error!(error = %e, "invalid storage settings");
+        Error::Message(e.to_string())
+    })
+}
+
+/// Logs a loud warning at startup when uploads are only kept in memory.
+pub fn warn_if_ephemeral_storage(ctx: &AppContext) {
+    let config = get_s3_config(ctx);
+    if config.local_storage_path.is_none() && config.backend == "memory" {
+        tracing::warn!(
+            "!!! storage backend is 'memory': uploaded files are NOT persisted and will be lost on restart !!!"
+        );
+    }
+}
+
+/// Logs the storage timeouts in effect, so a deployment's values can be
+/// checked without reading its configuration.
+pub fn log_storage_timeouts(ctx: &AppContext) {
+    let config = get_s3_config(ctx);
+    tracing::info!(
+        connect_timeout_seconds = config.connect_timeout_seconds,
+        operation_timeout_seconds = config.operation_timeout_seconds,
+        download_idle_timeout_seconds = config.download_idle_timeout_seconds,
+        "storage timeouts"
+    );
+}
+
+/// Builds the store once at startup and hands it to every handler as an
+/// `Extension`, so the S3 client and its connections are reused.
+pub fn connect_store(ctx: &AppContext) -> Result<Arc<dyn ObjectStore>> {
+    shared_store(&get_s3_config(ctx))
+}
+
+/// The multipart side of `connect_store`, backed by the same store.
+pub fn connect_part_store(ctx: &AppContext) -> Result<PartStore> {
+    Ok(shared_backend(&get_s3_config(ctx))?.1)
+}
+
+/// The process-wide store, for workers and tasks that have no request to extract it from.
+pub(super) fn shared_store(config: &S3Config) -> Result<Arc<dyn ObjectStore>> {
+    Ok(shared_backend(config)?.0)
+}
+
+/// The circuit breaker of `connect_store`, for `GET /files/health`.
+pub fn connect_circuit(ctx: &AppContext) -> Result<Arc<CircuitState>> {
+    Ok(shared_backend(&get_s3_config(ctx))?.2)
+}
+
+pub(super) fn shared_backend(config: &S3Config) -> Result<StorageBackend> {
+    if let Some(backend) = STORE.get() {
+        return Ok(backend.clone());
+    }
+    let backend = create_store(config)?;
+    Ok(STORE.get_or_init(|| backend).clone())
+}
+
+pub(super) fn create_store(config: &S3Config) -> Result<StorageBackend> {
+    let circuit = Arc::new(CircuitState::new(config.circuit.policy()));
+    let guards = Guards {
+        operation: Duration::from_secs(config.operation_timeout_seconds),
+        idle: Duration::from_secs(config.download_idle_timeout_seconds),
+        circuit: circuit.clone(),
+        cache: download_cache(config)?,
+        memory: memory_cache(config),
+        namespace: config
+            .local_storage_path
+            .clone()
+            .unwrap_or_else(|| config.bucket.clone()),
+    };
+    let keyring = config
+        .encryption
+        .keyring()
+        .map_err(|e| Error::Message(e.to_string()))?;
+    if let Some(path) = &config.local_storage_path {
+        let store = LocalStore::open(path).map_err(Error::Message)?;
+        let store: Arc<dyn ObjectStore> = match keyring {
+            Some(keyring) => Arc::new(guards.apply(EncryptedStore::new(store, keyring))),
+            None => Arc::new(guards.apply(store)),
+        };
+        return Ok((store, PartStore(None), circuit));
+    }
+    let retry = config.retry.policy();
+    let (store, parts) = match config.backend.as_str() {
+        "s3" => {
+            let store = create_s3_store(config)?;
+            match config
+                .object_lock()
+                .map_err(|e| Error::Message(e.to_string()))?
+            {
+                Some(lock) => layered(locked(config, store, lock), &guards, keyring, retry),
+                None => layered(store, &guards, keyring, retry),
+            }
+        }
+        "memory" => layered(InMemory::new(), &guards, keyring, retry),
+        other => return Err(Error::Message(format!("Unknown storage backend '{other}'"))),
+    };
+    Ok((store, parts, circuit))
+}
+
+/// What every backend is wrapped in last: the timeouts, and around them the
+/// circuit breaker, so a call that timed out counts as a failure. The
+/// caches go outside both: a disk hit still asks the backend whether it is
+/// current, and a memory hit doesn't call it at all.
+#[derive(Debug, Clone)]
+pub(super) struct Guards {
+    pub(super) operation: Duration,
+    pub(super) idle: Duration,
+    pub(super) circuit: Arc<CircuitState>,
+    pub(super) cache: Option<Arc<DownloadCache>>,
+    pub(super) memory: Option<Arc<MemoryCache>>,
+    /// Sets this bucket's keys apart in the caches.
+    pub(super) namespace: String,
+}
+
+impl Guards {
+    pub(super) fn apply<T>(&self, store: T) -> CacheStore<CircuitStore<TimeoutStore<T>>> {
+        CacheStore::new(
+            CircuitStore::new(
+                TimeoutStore::new(store, self.operation).with_idle_timeout(self.idle),
+                self.circuit.clone(),
+            ),
+            self.cache.clone(),
+            self.namespace.clone(),
+        )
+        .with_memory(self.memory.clone())
+    }
+}
+
+/// Wraps a backend in retries, encryption, when a key is configured, and
+/// the guards. Retries go innermost: a completed upload must not be sealed
+/// a second time.
+pub(super) fn layered<T: ObjectStore + MultipartStore>(
+    store: T,
+    guards: &Guards,
+    keyring: Option<Keyring>,
+    retry: RetryPolicy,
+) -> (Arc<dyn ObjectStore>, PartStore) {
+    let store = RetryStore::new(store, retry);
+    match keyring {
+        Some(keyring) => {
+            let store = Arc::new(guards.apply(EncryptedStore::new(store, keyring)));
+            (store.clone(), PartStore(Some(store)))
+        }
+        None => {
+            let store = Arc::new(guards.apply(store));
+            (store.clone(), PartStore(Some(store)))
+        }
+    }
+}
+
+/// The client's own request timeout is off: it would cap a download's
+/// total duration. `TimeoutStore` bounds each call instead.
+pub(super) fn create_s3_store(config: &S3Config) -> Result<AmazonS3> {
+    let client = ClientOptions::new()
+        .with_connect_timeout(Duration::from_secs(config.connect_timeout_seconds))
+        .with_timeout_disabled();
+    let mut builder = AmazonS3Builder::new()
+        .with_client_options(client)
+        .with_bucket_name(&config.bucket)
+        .with_region(&config.region)
+        .with_endpoint(&config.endpoint)
+        .with_access_key_id(&config.access_key)
+        .with_secret_access_key(&config.secret_key)
+        .with_allow_http(true)
+        .with_virtual_hosted_style_request(false)
+        .with_retry(config.retry.s3());
+    if config.s3_checksums {
+        builder = builder.with_checksum_algorithm(Checksum::SHA256);
+    }
+    let store = with_encryption(builder, config.sse, config.kms_key_id.as_deref())
+        .build()
+        .map_err(Error::wrap)?;
+
+    Ok(store)
+}
+
+/// `store`, with what it writes locked.
+pub(super) fn locked(config: &S3Config, store: AmazonS3, lock: ObjectLock) -> LockedS3 {
+    let mut upload_headers = Vec::new();
+    if config.s3_checksums {
+        upload_headers.push(("x-amz-checksum-algorithm".into(), "SHA256".into()));
+    }
+    let bucket = LockedBucket {
+        endpoint: config.endpoint.clone(),
+        name: config.bucket.clone(),
+        region: config.region.clone(),
+        access_key: config.access_key.clone(),
+        secret_key: config.secret_key.clone(),
+        write_headers: sse_headers(config.sse, config.kms_key_id.as_deref()),
+        upload_headers,
+    };
+    LockedS3::new(store, bucket, lock)
+}
@@ -0,0 +1,369 @@
+//! Uploads sent in numbered parts and completed in one call.
+
+use super::*;
+
+/// Staging objects of chunked uploads, never listed or synced.
+pub(super) const CHUNKED_PREFIX: &str = "uploads/";
+/// S3 numbers parts from 1 to 10 000.
+pub(super) const MAX_PART_NUMBER: i32 = 10_000;
+
+/// The multipart upload a chunked upload is assembled in, completed into this key.
+pub(super) fn chunked_staging_path(id: &str) -> ObjectPath {
+    ObjectPath::from(format!("{CHUNKED_PREFIX}{id}"))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateChunkedUploadRequest {
+    pub file_name: String,
+    pub metadata: Option<FileMetadata>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChunkedUploadInfo {
+    pub id: String,
+    pub file_name: String,
+    /// Every part but the last has to be at least this large.
+    pub min_part_bytes: usize,
+    pub max_part_number: i32,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChunkedPartInfo {
+    pub part_number: i32,
+    pub size: i64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CompleteChunkedUploadRequest {
+    /// Part numbers in file order.
+    pub parts: Vec<i32>,
+}
+
+/// An upload of `author`, for any of the chunked upload endpoints.
+pub(super) async fn find_chunked_upload(
+    ctx: &AppContext,
+    author: &user::Model,
+    id: &str,
+) -> FileResult<chunked_upload::Model> {
+    chunked_upload::find(&ctx.db, id)
+        .await?
+        .filter(|u| u.author_id == author.id)
+        .ok_or_else(|| FileError::NotFound(format!("Upload '{id}' not found")))
+}
+
+/// Starts a chunked upload; its parts are then sent one request each.
+#[utoipa::path(
+    post,
+    path = "/files/uploads",
+    operation_id = "createChunkedUpload",
+    tag = "files",
+    request_body(content = CreateChunkedUploadRequest),
+    responses(
+        (status = 201, description = "Upload created", body = ChunkedUploadInfo),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 501, description = "The storage backend has no multipart uploads", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn create_chunked_upload(
+    State(ctx): State<AppContext>,
+    Extension(parts): Extension<PartStore>,
+    headers: HeaderMap,
+    Json(mut req): Json<CreateChunkedUploadRequest>,
+) -> FileResult<(StatusCode, Json<ChunkedUploadInfo>)> {
+    let author = token_author(&ctx, &headers).await?;
+    let parts = multipart_backend(&parts)?;
+
+    req.file_name = normalize_name(&req.file_name);
+    if safe_path_segments(&req.file_name).is_none() {
+        return Err(FileError::BadRequest(format!(
+            "Invalid file name '{}'",
+            req.file_name
+        )));
+    }
+    let metadata = match req.metadata {
+        Some(mut metadata) => {
+            metadata.thumbnail = None;
+            metadata.preview = None;
+            metadata.preview_error = None;
+            metadata.validate()?;
+            Some(serde_json::to_value(&metadata)?)
+        }
+        None => None,
+    };
+
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    let multipart_id = parts
+        .create_multipart(&chunked_staging_path(&id))
+        .await
+        .map_err(FileError::StorageError)?;
+    chunked_upload::create(
+        &ctx.db,
+        chunked_upload::NewUpload {
+            id: &id,
+            file_name: &req.file_name,
+            author_id: author.id,
+            metadata,
+            multipart_id: &multipart_id,
+        },
+    )
+    .await?;
+    events::bus().publish(
+        ProgressKind::UploadStarted,
+        &req.file_name,
+        Some(author.id),
+        None,
+    );
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ChunkedUploadInfo {
+            id,
+            file_name: req.file_name,
+            min_part_bytes: MIN_PART_BYTES,
+            max_part_number: MAX_PART_NUMBER,
+        }),
+    ))
+}
+
+/// Stores the body as part `part_number`. Sending a part again replaces it,
+/// so a failed part can simply be retried.
+#[utoipa::path(
+    put,
+    path = "/files/uploads/{id}/parts/{part_number}",
+    operation_id = "putChunkedPart",
+    tag = "files",
+    params(("id" = String, Path, description = "Upload id"), ("part_number" = i32, Path, description = "1 to 10000")),
+    request_body(content = [u8], content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "Part stored", body = ChunkedPartInfo),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 413, description = "The part exceeds `max_file_size_bytes`", body = ErrorBody),
+        (status = 501, description = "The storage backend has no multipart uploads", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn put_chunked_part(
+    State(ctx): State<AppContext>,
+    Extension(parts): Extension<PartStore>,
+    headers: HeaderMap,
+    Path((id, part_number)): Path<(String, i32)>,
+    body: Bytes,
+) -> FileResult<Json<ChunkedPartInfo>> {
+    let author = token_author(&ctx, &headers).await?;
+    let parts = multipart_backend(&parts)?;
+    let config = get_s3_config(&ctx);
+    let upload = find_chunked_upload(&ctx, &author, &id).await?;
+
+    if !(1..=MAX_PART_NUMBER).contains(&part_number) {
+        return Err(FileError::BadRequest(format!(
+            "Part numbers go from 1 to {MAX_PART_NUMBER}"
+        )));
+    }
+    if body.is_empty() {
+        return Err(FileError::BadRequest("Part is empty".into()));
+    }
+    for digest in client_digests(&headers)? {
+        digest.verify(
+            &format!("part {part_number}"),
+            &digest.algorithm.digest(&body),
+        )?;
+    }
+    if body.len() as u64 > config.max_file_size_bytes {
+        return Err(FileError::Rejected {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            code: "file_too_large".into(),
+            message: format!("File exceeds the {} byte limit", config.max_file_size_bytes),
+            details: None,
+        });
+    }
+
+    let size = body.len() as i64;
+    let part_id = parts
+        .put_part(
+            &chunked_staging_path(&id),
+            &upload.multipart_id,
+            (part_number - 1) as usize,
+            body.into(),
+        )
+        .await
+        .map_err(FileError::StorageError)?;
+    chunked_upload_part::upsert(&ctx.db, &id, part_number, &part_id.content_id, size).await?;
+    chunked_upload::touch(&ctx.db, &id).await?;
+
+    Ok(Json(ChunkedPartInfo { part_number, size }))
+}
+
+/// Assembles the listed parts into the file and stores it like any other
+/// upload. Listing a part that never arrived is a 409 naming the absent ones.
+#[utoipa::path(
+    post,
+    path = "/files/uploads/{id}/complete",
+    operation_id = "completeChunkedUpload",
+    tag = "files",
+    params(("id" = String, Path, description = "Upload id")),
+    request_body(content = CompleteChunkedUploadRequest),
+    responses(
+        (status = 200, description = "The stored file", body = FileInfo),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 409, description = "Some listed parts never arrived", body = ErrorBody),
+        (status = 413, description = "The file exceeds the size limit", body = ErrorBody),
+        (status = 422, description = "A part other than the last is too small", body = ErrorBody),
+        (status = 501, description = "The storage backend has no multipart uploads", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn complete_chunked_upload(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(parts): Extension<PartStore>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Json(req): Json<CompleteChunkedUploadRequest>,
+) -> FileResult<Json<FileInfo>> {
+    let author = token_author(&ctx, &headers).await?;
+    let parts = multipart_backend(&parts)?;
+    let config = get_s3_config(&ctx);
+    let upload = find_chunked_upload(&ctx, &author, &id).await?;
+
+    if req.parts.is_empty() || !req.parts.windows(2).all(|w| w[0] < w[1]) {
+        return Err(FileError::BadRequest(
+            "parts must list part numbers in ascending order".into(),
+        ));
+    }
+    let received: BTreeMap<i32, chunked_upload_part::Model> =
+        chunked_upload_part::find_by_upload(&ctx.db, &id)
+            .await?
+            .into_iter()
+            .map(|p| (p.part_number, p))
+            .collect();
+    let missing: Vec<i32> = req
+        .parts
+        .iter()
+        .copied()
+        .filter(|n| !received.contains_key(n))
+        .collect();
+    if !missing.is_empty() {
+        return Err(FileError::Rejected {
+            status: StatusCode::CONFLICT,
+            code: "parts_missing".into(),
+            message: format!("Upload '{id}' is missing {} part(s)", missing.len()),
+            details: Some(serde_json::json!({ "missing": missing })),
+        });
+    }
+    let listed: Vec<&chunked_upload_part::Model> = req.parts.iter().map(|n| &received[n]).collect();
+    let too_small: Vec<i32> = listed[..listed.len() - 1]
+        .iter()
+        .filter(|p| p.size < MIN_PART_BYTES as i64)
+        .map(|p| p.part_number)
+        .collect();
+    if !too_small.is_empty() {
+        return Err(FileError::Rejected {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            code: "part_too_small".into(),
+            message: format!("Every part but the last needs at least {MIN_PART_BYTES} bytes"),
+            details: Some(serde_json::json!({ "parts": too_small })),
+        });
+    }
+    if listed.iter().map(|p| p.size as u64).sum::<u64>() > config.max_file_size_bytes {
+        return Err(FileError::Rejected {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            code: "file_too_large".into(),
+            message: format!("File exceeds the {} byte limit", config.max_file_size_bytes),
+            details: None,
+        });
+    }
+
+    // Up to here the client can still fix the upload and complete it again.
+    let staging = chunked_staging_path(&id);
+    let part_ids = listed
+        .iter()
+        .map(|p| PartId {
+            content_id: p.content_id.clone(),
+        })
+        .collect();
+    parts
+        .complete_multipart(&staging, &upload.multipart_id, part_ids)
+        .await
+        .map_err(FileError::StorageError)?;
+
+    let stored = match read_object(store.as_ref(), &staging).await {
+        Ok(bytes) => {
+            let metadata: Option<FileMetadata> = upload
+                .metadata
+                .clone()
+                .and_then(|m| serde_json::from_value(m).ok());
+            store_new_file(
+                &ctx,
+                &config,
+                store.as_ref(),
+                &upload.file_name,
+                bytes,
+                &author,
+                metadata.as_ref(),
+                tenant.id(),
+            )
+            .await
+            .map_err(FileError::from)
+        }
+        Err(e) => Err(e),
+    };
+    discard_chunked_upload(&ctx, store.as_ref(), &upload).await?;
+
+    let mut stored = stored?;
+    after_upload_stored(&ctx, &config, store.as_ref(), &author, &mut stored).await;
+    Ok(Json(stored.info))
+}
+
+/// Aborts the upload and drops the parts received so far.
+#[utoipa::path(
+    delete,
+    path = "/files/uploads/{id}",
+    operation_id = "abortChunkedUpload",
+    tag = "files",
+    params(("id" = String, Path, description = "Upload id")),
+    responses(
+        (status = 204, description = "Upload discarded"),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 501, description = "The storage backend has no multipart uploads", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn abort_chunked_upload(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(parts): Extension<PartStore>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> FileResult<StatusCode> {
+    let author = token_author(&ctx, &headers).await?;
+    let parts = multipart_backend(&parts)?;
+    let upload = find_chunked_upload(&ctx, &author, &id).await?;
+
+    // Kept on failure, so the abort can be retried; gone already is fine.
+    match parts
+        .abort_multipart(&chunked_staging_path(&id), &upload.multipart_id)
+        .await
+    {
+        Ok(()) | Err(object_store::Error::NotFound { .. }) => {}
+        Err(e) => return Err(FileError::StorageError(e)),
+    }
+    discard_chunked_upload(&ctx, store.as_ref(), &upload).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn discard_chunked_upload(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    upload: &chunked_upload::Model,
+) -> Result<()> {
+    let _ = store.delete(&chunked_staging_path(&upload.id)).await;
+    chunked_upload::delete(&ctx.db, &upload.id).await?;
+    Ok(())
+}
@@ -0,0 +1,946 @@
+//! Storage settings, read once from `settings` in the app config.
+
+use super::*;
+
+/// `x-amz-server-side-encryption` to request on writes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerSideEncryption {
+    #[default]
+    None,
+    /// SSE-S3: keys managed by S3 (`AES256`).
+    S3,
+    /// SSE-KMS with a customer managed key.
+    Kms,
+}
+
+/// Makes every object `builder`'s store writes, copies included, request `sse`.
+pub fn with_encryption(
+    builder: AmazonS3Builder,
+    sse: ServerSideEncryption,
+    kms_key_id: Option<&str>,
+) -> AmazonS3Builder {
+    // object_store only exports the typed key for KMS; SSE-S3 goes by name.
+    let algorithm = || {
+        "aws_server_side_encryption"
+            .parse()
+            .expect("object_store knows its own config key")
+    };
+    match (sse, kms_key_id) {
+        (ServerSideEncryption::None, _) => builder,
+        (ServerSideEncryption::S3, _) => builder.with_config(algorithm(), "AES256"),
+        (ServerSideEncryption::Kms, Some(key_id)) => builder.with_sse_kms_encryption(key_id),
+        // Without a key S3 falls back to the account's default KMS key.
+        (ServerSideEncryption::Kms, None) => builder.with_config(algorithm(), "aws:kms"),
+    }
+}
+
+/// The headers `with_encryption` has object_store send, for requests made
+/// without it.
+pub fn sse_headers(sse: ServerSideEncryption, kms_key_id: Option<&str>) -> Vec<(String, String)> {
+    let algorithm = |value: &str| {
+        (
+            "x-amz-server-side-encryption".to_string(),
+            value.to_string(),
+        )
+    };
+    match (sse, kms_key_id) {
+        (ServerSideEncryption::None, _) => Vec::new(),
+        (ServerSideEncryption::S3, _) => vec![algorithm("AES256")],
+        (ServerSideEncryption::Kms, Some(key_id)) => vec![
+            algorithm("aws:kms"),
+            (
+                "x-amz-server-side-encryption-aws-kms-key-id".into(),
+                key_id.into(),
+            ),
+        ],
+        (ServerSideEncryption::Kms, None) => vec![algorithm("aws:kms")],
+    }
+}
+
+/// How `GET /files/{file_name}` hands out content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownloadMode {
+    /// Streamed through this server.
+    #[default]
+    Proxy,
+    /// A 302 to a pre-signed S3 URL, for buckets clients can reach.
+    Redirect,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(super) struct S3Config {
+    /// `s3` (default) or `memory` for a non-persistent in-process store.
+    pub(super) backend: String,
+    /// Keep files in this local directory instead, whatever `backend` says.
+    pub(super) local_storage_path: Option<String>,
+    /// Store uploads once per distinct content under `blobs/{sha256}`.
+    pub(super) dedup: bool,
+    /// Re-uploading an existing name adds a version instead of a second file.
+    pub(super) versioning: bool,
+    /// Versions kept per file; older ones are pruned in the background. Unset keeps all.
+    pub(super) keep_versions: Option<u64>,
+    /// Without `versioning`, copy an object about to be overwritten to
+    /// `<key>__v<unix millis>` and update the existing row in place.
+    pub(super) enable_versioning: bool,
+    /// Where new files' objects go: `flat` under their name, `date` under
+    /// `YYYY/MM/DD/` or `hash` under `ab/cd/` from their SHA-256.
+    pub(super) path_strategy: String,
+    /// Gzip new text uploads (CSV, JSON, XML, ...) and store them under `.gz` keys.
+    #[serde(alias = "compress")]
+    pub(super) compress_text_uploads: bool,
+    /// Compress downloads and JSON listings for clients that accept it. Turn
+    /// off when a CDN in front already does.
+    pub(super) compress_responses: bool,
+    /// Local directory of the full-text index behind `/files/search/content`.
+    pub(super) content_index_dir: String,
+    /// Longest side of generated image thumbnails, in pixels.
+    pub(super) thumbnail_max_dimension: u32,
+    /// Resolution PDF previews are rendered at.
+    pub(super) preview_dpi: u32,
+    /// The `pdftoppm` executable that renders PDF previews.
+    pub(super) pdftoppm_path: String,
+    /// Resumable uploads without progress for this long are aborted.
+    pub(super) tus_upload_ttl_hours: i64,
+    /// Multipart uploads S3 has held open for this long, and no upload in
+    /// progress is assembling, are aborted by `AbortOrphanedUploadsWorker`.
+    pub(super) multipart_max_age_hours: i64,
+    /// Audit log entries older than this are removed by the
+    /// `purge_audit_log` task. Unset, they are kept forever.
+    pub(super) audit_retention_days: Option<u32>,
+    pub(super) access_log: AccessLogConfig,
+    pub(super) endpoint: String,
+    pub(super) bucket: String,
+    pub(super) region: String,
+    pub(super) access_key: String,
+    pub(super) secret_key: String,
+    /// Server-side encryption requested for every object written to S3.
+    pub(super) sse: ServerSideEncryption,
+    /// The KMS key objects are encrypted with when `sse` is `kms`.
+    pub(super) kms_key_id: Option<String>,
+    /// Send a SHA-256 with every object written to S3, for S3 to check the
+    /// bytes it received against. Not every S3-compatible store supports it.
+    pub(super) s3_checksums: bool,
+    /// `GOVERNANCE` or `COMPLIANCE`: lock every object written to S3 against
+    /// changes and deletes. The bucket must have Object Lock enabled.
+    pub(super) object_lock_mode: Option<String>,
+    /// How long, from its upload, an object stays locked.
+    pub(super) object_lock_retain_until_days: Option<u32>,
+    /// A CDN serving the bucket, such as `https://cdn.example.com`. File
+    /// responses then carry `<cdn_base_url>/<key>` as `cdn_url`.
+    pub(super) cdn_base_url: Option<String>,
+    /// Key of the HMAC in links from `GET /files/{file_name}/signed-url`.
+    /// Unset, no links are handed out.
+    pub(super) signing_secret: Option<String>,
+    /// How long a signed link stays valid.
+    pub(super) signed_url_ttl_seconds: u64,
+    /// Refuse downloads that carry neither a valid signed link nor a bearer token.
+    pub(super) require_signed_downloads: bool,
+    /// Downloads can still be switched per request with `?redirect=`.
+    pub(super) download_mode: DownloadMode,
+    /// Largest single file accepted by `upload_file`.
+    pub(super) max_file_size_bytes: u64,
+    /// Largest body of `POST /files`, all its files together. A request
+    /// that declares more is refused before any of it is read.
+    pub(super) max_request_body_bytes: u64,
+    /// Largest `data` accepted by `POST /files/base64`, checked before it
+    /// is decoded.
+    pub(super) base64_max_size_bytes: u64,
+    /// Bytes each user may store, unless their `storage_quota_bytes` says
+    /// otherwise. Unset is unlimited.
+    pub(super) default_quota_bytes: Option<u64>,
+    /// A lock an upload holds on a file name is taken over after this long,
+    /// in case the upload died without releasing it.
+    pub(super) upload_lock_ttl_seconds: i32,
+    /// How long an edit lock from `POST /files/{file_name}/lock` lasts when
+    /// the request names no `ttl_seconds`, and how long past its last
+    /// refresh it goes stale.
+    pub(super) edit_lock_ttl_seconds: i32,
+    /// Longest `ttl_seconds` an edit lock may ask for.
+    pub(super) edit_lock_max_ttl_seconds: i32,
+    /// Uploads above this size are sent as multipart uploads of parts this
+    /// big instead of one PUT.
+    pub(super) multipart_threshold_bytes: u64,
+    /// Longest a single storage call may take before the request fails with a 503.
+    pub(super) operation_timeout_seconds: u64,
+    /// Longest the S3 client may take to open a connection.
+    pub(super) connect_timeout_seconds: u64,
+    /// Longest a download may go without receiving a chunk. Its total
+    /// duration is not limited.
+    pub(super) download_idle_timeout_seconds: u64,
+    /// Upper bound on entries in a zip uploaded with `?extract=true`.
+    pub(super) max_extract_entries: usize,
+    /// Upper bound on the total uncompressed size of an extracted zip.
+    pub(super) max_extract_bytes: u64,
+    /// Origins allowed to call `/files` from a browser. `*` allows any; empty disables CORS.
+    pub(super) cors_allowed_origins: Vec<String>,
+    pub(super) cors_allowed_methods: Vec<String>,
+    /// Request headers a browser may send; `*` allows any.
+    pub(super) cors_allowed_headers: Vec<String>,
+    /// Response headers scripts on another origin may read.
+    pub(super) cors_expose_headers: Vec<String>,
+    /// Lets browsers send cookies and `Authorization`. Needs explicit origins.
+    pub(super) cors_allow_credentials: bool,
+    pub(super) cors_max_age_seconds: u32,
+    pub(super) scan: ScanConfig,
+    pub(super) webhooks: WebhookConfig,
+    pub(super) remote_fetch: RemoteFetchConfig,
+    pub(super) encryption: EncryptionConfig,
+    pub(super) retry: StorageRetryConfig,
+    pub(super) circuit: CircuitConfig,
+    pub(super) concurrency: ConcurrencyConfig,
+    pub(super) download_cache: DownloadCacheConfig,
+    pub(super) memory_cache: MemoryCacheConfig,
+    pub(super) replication: ReplicationConfig,
+    /// Tenants with a bucket of their own, keyed by the id requests name
+    /// them with in `X-Tenant-Id` or their token's `tenant` claim.
+    pub(super) tenants: BTreeMap<String, TenantStorage>,
+}
+
+/// One tenant's bucket, under `settings.tenants.<id>`. Connection settings
+/// left out are those of the default bucket.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub(super) struct TenantStorage {
+    pub(super) bucket: String,
+    pub(super) endpoint: Option<String>,
+    pub(super) region: Option<String>,
+    pub(super) access_key: Option<String>,
+    pub(super) secret_key: Option<String>,
+}
+
+/// Virus scanning of uploads, under `settings.scan`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(super) struct ScanConfig {
+    pub(super) enabled: bool,
+    /// clamd address for the INSTREAM protocol.
+    pub(super) host: String,
+    pub(super) port: u16,
+    /// Uploads up to this size are scanned before the request returns; larger
+    /// ones stay `pending` until the scan worker gets to them.
+    pub(super) inline_max_bytes: i64,
+    /// Serve files as `unscanned` when clamd can't be reached, instead of
+    /// keeping them `pending`.
+    pub(super) fail_open: bool,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            enabled: std::env::var("SCAN_ENABLED").is_ok_and(|v| v == "true"),
+            host: std::env::var("CLAMD_HOST").unwrap_or_else(|_| "clamav".into()),
+            port: std::env::var("CLAMD_PORT")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(3310),
+            inline_max_bytes: 10 * 1024 * 1024,
+            fail_open: std::env::var("SCAN_FAIL_OPEN").is_ok_and(|v| v == "true"),
+        }
+    }
+}
+
+/// Receivers of file events, under `settings.webhooks`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(super) struct WebhookConfig {
+    pub(super) targets: Vec<WebhookTarget>,
+    /// Deliveries still failing after this many tries are logged for manual replay.
+    pub(super) max_attempts: u32,
+    /// Wait before the first retry; it doubles with every further one.
+    pub(super) backoff_ms: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            targets: Vec::new(),
+            max_attempts: 5,
+            backoff_ms: 1000,
+        }
+    }
+}
+
+/// Sources `POST /files/from-url` may download from, under `settings.remote_fetch`.
+/// Nothing is allowed until `allowed_hosts` or `allow_any_host` is set.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(super) struct RemoteFetchConfig {
+    /// Exact host names, or `*.example.com` for a domain and its subdomains.
+    pub(super) allowed_hosts: Vec<String>,
+    pub(super) allow_any_host: bool,
+    /// Lets URLs reach loopback, private and link-local addresses.
+    pub(super) allow_private_addresses: bool,
+    pub(super) timeout_seconds: u64,
+    pub(super) max_redirects: usize,
+}
+
+impl Default for RemoteFetchConfig {
+    fn default() -> Self {
+        Self {
+            allowed_hosts: Vec::new(),
+            allow_any_host: false,
+            allow_private_addresses: false,
+            timeout_seconds: 60,
+            max_redirects: 5,
+        }
+    }
+}
+
+/// Retries of failed storage calls, under `settings.retry`. Set
+/// `max_retries` to 0 to turn them off.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(super) struct StorageRetryConfig {
+    pub(super) max_retries: usize,
+    /// Wait before the first retry; it doubles, with jitter, for every further one.
+    pub(super) init_backoff_ms: u64,
+    pub(super) max_backoff_ms: u64,
+    /// S3 requests are not retried once this long has passed since the first try.
+    pub(super) retry_timeout_seconds: u64,
+}
+
+impl Default for StorageRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            init_backoff_ms: 100,
+            max_backoff_ms: 5000,
+            retry_timeout_seconds: 60,
+        }
+    }
+}
+
+impl StorageRetryConfig {
+    pub(super) fn policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.max_retries,
+            init_backoff: Duration::from_millis(self.init_backoff_ms),
+            max_backoff: Duration::from_millis(self.max_backoff_ms),
+        }
+    }
+
+    pub(super) fn s3(&self) -> RetryConfig {
+        RetryConfig {
+            backoff: BackoffConfig {
+                init_backoff: Duration::from_millis(self.init_backoff_ms),
+                max_backoff: Duration::from_millis(self.max_backoff_ms),
+                base: 2.0,
+            },
+            max_retries: self.max_retries,
+            retry_timeout: Duration::from_secs(self.retry_timeout_seconds),
+        }
+    }
+}
+
+/// The circuit breaker in front of each bucket, under `settings.circuit`.
+/// Set `failure_threshold` to 0 to turn it off.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(super) struct CircuitConfig {
+    /// Failures in a row, each within `window_seconds` of the first, that
+    /// open the circuit.
+    pub(super) failure_threshold: u32,
+    pub(super) window_seconds: u64,
+    /// How long calls are refused with a 503 before one is let through to
+    /// probe the backend.
+    pub(super) cool_down_seconds: u64,
+}
+
+impl Default for CircuitConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            window_seconds: 30,
+            cool_down_seconds: 30,
+        }
+    }
+}
+
+impl CircuitConfig {
+    pub(super) fn policy(&self) -> CircuitPolicy {
+        CircuitPolicy {
+            failure_threshold: self.failure_threshold,
+            window: Duration::from_secs(self.window_seconds),
+            cool_down: Duration::from_secs(self.cool_down_seconds),
+        }
+    }
+}
+
+/// Transfers handled at once, under `settings.concurrency`. A limit of 0
+/// lifts it.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(super) struct ConcurrencyConfig {
+    pub(super) max_uploads: usize,
+    /// Downloads cost less than uploads, so more of them may run.
+    pub(super) max_downloads: usize,
+    /// Whether a transfer over the limit waits for a slot or gets a 503 at once.
+    pub(super) when_busy: WhenBusy,
+    /// How long a waiting transfer is held before it gets the 503.
+    pub(super) max_wait_seconds: u64,
+    /// Sent as `Retry-After` with the 503.
+    pub(super) retry_after_seconds: u64,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            max_uploads: 16,
+            max_downloads: 256,
+            when_busy: WhenBusy::Queue,
+            max_wait_seconds: 30,
+            retry_after_seconds: 5,
+        }
+    }
+}
+
+/// A local JSONL log of uploads and downloads, under `settings.access_log`.
+/// Off until a `path` is set.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(super) struct AccessLogConfig {
+    pub(super) path: Option<String>,
+    /// The file is rotated, to be archived to the bucket, before it grows
+    /// past this.
+    pub(super) rotate_bytes: u64,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            path: std::env::var("ACCESS_LOG_PATH").ok(),
+            rotate_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// Copies of downloaded objects kept on local disk, under
+/// `settings.download_cache`. Off until a `dir` is set.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(super) struct DownloadCacheConfig {
+    pub(super) dir: Option<String>,
+    /// Least recently served copies are removed past this.
+    pub(super) max_bytes: u64,
+}
+
+impl Default for DownloadCacheConfig {
+    fn default() -> Self {
+        Self {
+            dir: std::env::var("DOWNLOAD_CACHE_DIR").ok(),
+            max_bytes: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// Small objects kept in memory, under `settings.memory_cache`. Set
+/// `max_bytes` to 0 to turn it off.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(super) struct MemoryCacheConfig {
+    pub(super) max_bytes: u64,
+    /// Larger objects are never held.
+    pub(super) max_object_bytes: u64,
+    /// How long an entry is served before it is read again. Changes made
+    /// other than through this server go unseen for up to this long.
+    pub(super) ttl_seconds: u64,
+}
+
+impl Default for MemoryCacheConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: 64 * 1024 * 1024,
+            max_object_bytes: 256 * 1024,
+            ttl_seconds: 30,
+        }
+    }
+}
+
+/// A second bucket every object of the default one is copied to, for
+/// disaster recovery, under `settings.replication`. Off until a `bucket`
+/// is set; connection settings left out are those of the default bucket.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(super) struct ReplicationConfig {
+    pub(super) bucket: Option<String>,
+    pub(super) endpoint: Option<String>,
+    pub(super) region: Option<String>,
+    pub(super) access_key: Option<String>,
+    pub(super) secret_key: Option<String>,
+    /// Deleting a file deletes its copy too. Off, the replica keeps it.
+    pub(super) replicate_deletes: bool,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            bucket: std::env::var("REPLICATION_BUCKET").ok(),
+            endpoint: None,
+            region: None,
+            access_key: None,
+            secret_key: None,
+            replicate_deletes: std::env::var("REPLICATE_DELETES").is_ok_and(|v| v == "true"),
+        }
+    }
+}
+
+/// Encryption of object contents before they reach the store, under
+/// `settings.encryption`. Without a key objects are stored as uploaded.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub(super) struct EncryptionConfig {
+    /// Base64 of the 32-byte AES-256 key new objects are sealed with.
+    pub(super) key: Option<String>,
+    /// A file holding the key, as base64 or as the raw 32 bytes.
+    pub(super) key_file: Option<String>,
+    /// Retired keys, still used to open objects sealed before a rotation.
+    pub(super) previous_keys: Vec<String>,
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        Self {
+            key: std::env::var("FILES_ENCRYPTION_KEY").ok(),
+            key_file: std::env::var("FILES_ENCRYPTION_KEY_FILE").ok(),
+            previous_keys: std::env::var("FILES_ENCRYPTION_PREVIOUS_KEYS")
+                .map(|v| v.split(',').map(|k| k.trim().to_string()).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl EncryptionConfig {
+    pub(super) fn keyring(&self) -> std::result::Result<Option<Keyring>, ConfigError> {
+        let current = match (&self.key, &self.key_file) {
+            (Some(_), Some(_)) => {
+                return Err(ConfigError(
+                    "set either encryption.key or encryption.key_file, not both".into(),
+                ));
+            }
+            (Some(key), None) => Keyring::decode_key(key).map_err(ConfigError)?,
+            (None, Some(path)) => {
+                let bytes = std::fs::read(path).map_err(|e| {
+                    ConfigError(format!("encryption key file '{path}' is unreadable: {e}"))
+                })?;
+                match <[u8; 32]>::try_from(bytes.as_slice()) {
+                    Ok(key) => key,
+                    Err(_) => Keyring::decode_key(&String::from_utf8_lossy(&bytes))
+                        .map_err(|e| ConfigError(format!("{e} (in '{path}')")))?,
+                }
+            }
+            (None, None) if self.previous_keys.is_empty() => return Ok(None),
+            (None, None) => {
+                return Err(ConfigError(
+                    "encryption.previous_keys needs a current encryption.key".into(),
+                ));
+            }
+        };
+        let previous = self
+            .previous_keys
+            .iter()
+            .filter(|k| !k.is_empty())
+            .map(|k| Keyring::decode_key(k).map_err(ConfigError))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(Some(Keyring::new(&current, &previous)))
+    }
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            backend: std::env::var("STORAGE_BACKEND").unwrap_or_else(|_| "s3".into()),
+            local_storage_path: std::env::var("LOCAL_STORAGE_PATH").ok(),
+            dedup: std::env::var("STORAGE_DEDUP").is_ok_and(|v| v == "true"),
+            versioning: std::env::var("STORAGE_VERSIONING").is_ok_and(|v| v == "true"),
+            keep_versions: std::env::var("STORAGE_KEEP_VERSIONS")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            enable_versioning: std::env::var("STORAGE_ENABLE_VERSIONING")
+                .is_ok_and(|v| v == "true"),
+            path_strategy: std::env::var("PATH_STRATEGY").unwrap_or_else(|_| "flat".into()),
+            compress_text_uploads: std::env::var("COMPRESS_TEXT_UPLOADS")
+                .is_ok_and(|v| v == "true"),
+            compress_responses: std::env::var("COMPRESS_RESPONSES").map_or(true, |v| v != "false"),
+            content_index_dir: std::env::var("CONTENT_INDEX_DIR")
+                .unwrap_or_else(|_| "storage/content-index".into()),
+            thumbnail_max_dimension: std::env::var("THUMBNAIL_MAX_DIMENSION")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(200),
+            preview_dpi: std::env::var("PREVIEW_DPI")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(72),
+            pdftoppm_path: std::env::var("PDFTOPPM_PATH").unwrap_or_else(|_| "pdftoppm".into()),
+            tus_upload_ttl_hours: std::env::var("TUS_UPLOAD_TTL_HOURS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(24),
+            multipart_max_age_hours: std::env::var("MULTIPART_MAX_AGE_HOURS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(24),
+            endpoint: std::env::var("S3_ENDPOINT").unwrap_or_else(|_| "http://minio:9000".into()),
+            bucket: std::env::var("S3_BUCKET").unwrap_or_else(|_| "files".into()),
+            region: std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".into()),
+            access_key: std::env::var("S3_ACCESS_KEY").unwrap_or_else(|_| "admin".into()),
+            secret_key: std::env::var("S3_SECRET_KEY").unwrap_or_else(|_| "admin1234".into()),
+            sse: match std::env::var("S3_SSE").as_deref() {
+                Ok("s3") => ServerSideEncryption::S3,
+                Ok("kms") => ServerSideEncryption::Kms,
+                _ => ServerSideEncryption::None,
+            },
+            kms_key_id: std::env::var("S3_KMS_KEY_ID").ok(),
+            s3_checksums: std::env::var("S3_CHECKSUMS").is_ok_and(|v| v == "true"),
+            object_lock_mode: std::env::var("S3_OBJECT_LOCK_MODE").ok(),
+            object_lock_retain_until_days: std::env::var("S3_OBJECT_LOCK_RETAIN_UNTIL_DAYS")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            audit_retention_days: std::env::var("AUDIT_RETENTION_DAYS")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            cdn_base_url: std::env::var("CDN_BASE_URL").ok(),
+            signing_secret: std::env::var("SIGNING_SECRET").ok(),
+            signed_url_ttl_seconds: std::env::var("SIGNED_URL_TTL_SECONDS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(300),
+            require_signed_downloads: std::env::var("REQUIRE_SIGNED_DOWNLOADS")
+                .is_ok_and(|v| v == "true"),
+            download_mode: match std::env::var("DOWNLOAD_MODE").as_deref() {
+                Ok("redirect") => DownloadMode::Redirect,
+                _ => DownloadMode::Proxy,
+            },
+            max_file_size_bytes: 100 * 1024 * 1024,
+            max_request_body_bytes: 500 * 1024 * 1024,
+            // The base64 of a `max_file_size_bytes` file.
+            base64_max_size_bytes: (100 * 1024 * 1024u64).div_ceil(3) * 4,
+            upload_lock_ttl_seconds: 300,
+            edit_lock_ttl_seconds: std::env::var("EDIT_LOCK_TTL_SECONDS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(900),
+            edit_lock_max_ttl_seconds: 24 * 60 * 60,
+            default_quota_bytes: std::env::var("DEFAULT_QUOTA_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            multipart_threshold_bytes: std::env::var("MULTIPART_THRESHOLD_BYTES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(8 * 1024 * 1024),
+            operation_timeout_seconds: std::env::var("STORAGE_OPERATION_TIMEOUT_SECONDS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+            connect_timeout_seconds: std::env::var("STORAGE_CONNECT_TIMEOUT_SECONDS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(5),
+            download_idle_timeout_seconds: std::env::var("STORAGE_DOWNLOAD_IDLE_TIMEOUT_SECONDS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(30),
+            max_extract_entries: 1000,
+            max_extract_bytes: 1024 * 1024 * 1024,
+            cors_allowed_origins: Vec::new(),
+            cors_allowed_methods: ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"]
+                .map(String::from)
+                .to_vec(),
+            cors_allowed_headers: vec!["*".into()],
+            cors_expose_headers: [
+                "ETag",
+                "Content-Disposition",
+                "Location",
+                "X-Next-Cursor",
+                "Upload-Offset",
+                "Tus-Resumable",
+            ]
+            .map(String::from)
+            .to_vec(),
+            cors_allow_credentials: false,
+            cors_max_age_seconds: 3600,
+            scan: ScanConfig::default(),
+            webhooks: WebhookConfig::default(),
+            remote_fetch: RemoteFetchConfig::default(),
+            encryption: EncryptionConfig::default(),
+            retry: StorageRetryConfig::default(),
+            circuit: CircuitConfig::default(),
+            concurrency: ConcurrencyConfig::default(),
+            access_log: AccessLogConfig::default(),
+            download_cache: DownloadCacheConfig::default(),
+            memory_cache: MemoryCacheConfig::default(),
+            replication: ReplicationConfig::default(),
+            tenants: BTreeMap::new(),
+        }
+    }
+}
+
+impl S3Config {
+    /// The settings of the replica's store, if replication is on.
+    pub(super) fn for_replica(&self) -> Option<S3Config> {
+        let replication = &self.replication;
+        let storage = TenantStorage {
+            bucket: replication.bucket.clone()?,
+            endpoint: replication.endpoint.clone(),
+            region: replication.region.clone(),
+            access_key: replication.access_key.clone(),
+            secret_key: replication.secret_key.clone(),
+        };
+        let mut config = self.for_tenant(&storage);
+        config.replication.bucket = None;
+        Some(config)
+    }
+
+    /// The settings of `storage`'s bucket. A local store keeps each tenant
+    /// in a subdirectory named after its bucket.
+    pub(super) fn for_tenant(&self, storage: &TenantStorage) -> S3Config {
+        let mut config = self.clone();
+        config.tenants.clear();
+        config.bucket = storage.bucket.clone();
+        let overrides = [
+            (&mut config.endpoint, &storage.endpoint),
+            (&mut config.region, &storage.region),
+            (&mut config.access_key, &storage.access_key),
+            (&mut config.secret_key, &storage.secret_key),
+        ];
+        for (setting, value) in overrides {
+            if let Some(value) = value {
+                *setting = value.clone();
+            }
+        }
+        if let Some(path) = &self.local_storage_path {
+            let path = std::path::Path::new(path).join(&storage.bucket);
+            config.local_storage_path = Some(path.to_string_lossy().into_owned());
+        }
+        config
+    }
+
+    /// The lock every object written to S3 gets, if one is configured.
+    pub(super) fn object_lock(&self) -> std::result::Result<Option<ObjectLock>, ConfigError> {
+        match (&self.object_lock_mode, self.object_lock_retain_until_days) {
+            (None, None) => Ok(None),
+            (Some(mode), Some(days)) => {
+                let mode = LockMode::parse(mode).ok_or_else(|| {
+                    ConfigError(format!(
+                        "object_lock_mode '{mode}' must be GOVERNANCE or COMPLIANCE"
+                    ))
+                })?;
+                if days == 0 {
+                    return Err(ConfigError(
+                        "object_lock_retain_until_days must be at least 1".into(),
+                    ));
+                }
+                // S3 refuses multipart parts written under a lock without one.
+                if !self.s3_checksums {
+                    return Err(ConfigError("object_lock_mode needs s3_checksums".into()));
+                }
+                Ok(Some(ObjectLock {
+                    mode,
+                    retain_days: days,
+                }))
+            }
+            _ => Err(ConfigError(
+                "object_lock_mode and object_lock_retain_until_days go together".into(),
+            )),
+        }
+    }
+
+    /// Catches settings that would otherwise only fail at the first S3
+    /// request, with an opaque error. Other backends don't use them.
+    pub(super) fn validate(&self) -> std::result::Result<(), ConfigError> {
+        if self.multipart_threshold_bytes < MIN_PART_BYTES as u64 {
+            return Err(ConfigError(format!(
+                "multipart_threshold_bytes must be at least {MIN_PART_BYTES} (the S3 minimum part size)"
+            )));
+        }
+        let timeouts = [
+            ("operation_timeout_seconds", self.operation_timeout_seconds),
+            ("connect_timeout_seconds", self.connect_timeout_seconds),
+            (
+                "download_idle_timeout_seconds",
+                self.download_idle_timeout_seconds,
+            ),
+        ];
+        if let Some((name, _)) = timeouts.iter().find(|(_, seconds)| *seconds == 0) {
+            return Err(ConfigError(format!("{name} must be at least 1")));
+        }
+        if PathStrategy::parse(&self.path_strategy).is_none() {
+            return Err(ConfigError(format!(
+                "path_strategy '{}' must be flat, date or hash",
+                self.path_strategy
+            )));
+        }
+        if self.sse == ServerSideEncryption::Kms
+            && self
+                .kms_key_id
+                .as_deref()
+                .is_none_or(|k| k.trim().is_empty())
+        {
+            return Err(ConfigError("sse 'kms' needs a kms_key_id".into()));
+        }
+        self.object_lock()?;
+        if self.access_log.path.is_some() && self.access_log.rotate_bytes == 0 {
+            return Err(ConfigError(
+                "access_log.rotate_bytes must be at least 1".into(),
+            ));
+        }
+        if self.audit_retention_days == Some(0) {
+            return Err(ConfigError(
+                "audit_retention_days must be at least 1".into(),
+            ));
+        }
+        if self.cors_allow_credentials {
+            // The Fetch standard forbids `*` alongside credentials.
+            if self.cors_allowed_origins.iter().any(|o| o == "*") {
+                return Err(ConfigError(
+                    "cors_allow_credentials needs explicit cors_allowed_origins, not '*'".into(),
+                ));
+            }
+            if self.cors_expose_headers.iter().any(|h| h == "*") {
+                return Err(ConfigError(
+                    "cors_allow_credentials needs explicit cors_expose_headers, not '*'".into(),
+                ));
+            }
+        }
+        let keyring = self.encryption.keyring()?;
+        if self.download_cache.dir.is_some() {
+            // Copies on disk would hold what the key keeps from the bucket.
+            if keyring.is_some() {
+                return Err(ConfigError(
+                    "download_cache can't be used with encryption".into(),
+                ));
+            }
+            if self.download_cache.max_bytes == 0 {
+                return Err(ConfigError(
+                    "download_cache.max_bytes must be at least 1".into(),
+                ));
+            }
+        }
+        if self.memory_cache.max_bytes > 0 && self.memory_cache.ttl_seconds == 0 {
+            return Err(ConfigError(
+                "memory_cache.ttl_seconds must be at least 1".into(),
+            ));
+        }
+        if let Some(replica) = self.for_replica() {
+            if self.local_storage_path.is_some() {
+                return Err(ConfigError(
+                    "replication needs an object store backend, not local storage".into(),
+                ));
+            }
+            if replica.bucket == self.bucket && replica.endpoint == self.endpoint {
+                return Err(ConfigError(
+                    "replication.bucket must not be the default bucket".into(),
+                ));
+            }
+            replica
+                .validate()
+                .map_err(|e| ConfigError(format!("replication: {}", e.0)))?;
+        }
+        for (id, storage) in &self.tenants {
+            if storage.bucket.trim().is_empty() {
+                return Err(ConfigError(format!("tenant '{id}' needs a bucket")));
+            }
+            self.for_tenant(storage)
+                .validate()
+                .map_err(|e| ConfigError(format!("tenant '{id}': {}", e.0)))?;
+        }
+        if self.signing_secret.as_deref().is_some_and(|s| s.is_empty()) {
+            return Err(ConfigError("signing_secret must not be empty".into()));
+        }
+        if self.require_signed_downloads && self.signing_secret.is_none() {
+            return Err(ConfigError(
+                "require_signed_downloads needs a signing_secret".into(),
+            ));
+        }
+        if self.max_request_body_bytes == 0 {
+            return Err(ConfigError(
+                "max_request_body_bytes must be at least 1".into(),
+            ));
+        }
+        if self.signed_url_ttl_seconds == 0 {
+            return Err(ConfigError(
+                "signed_url_ttl_seconds must be at least 1".into(),
+            ));
+        }
+        if self.edit_lock_ttl_seconds < 1 || self.edit_lock_max_ttl_seconds < 1 {
+            return Err(ConfigError(
+                "edit_lock_ttl_seconds and edit_lock_max_ttl_seconds must be at least 1".into(),
+            ));
+        }
+        if self.edit_lock_ttl_seconds > self.edit_lock_max_ttl_seconds {
+            return Err(ConfigError(
+                "edit_lock_ttl_seconds must not exceed edit_lock_max_ttl_seconds".into(),
+            ));
+        }
+        if let Some(base) = &self.cdn_base_url {
+            let valid = url::Url::parse(base)
+                .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host());
+            if !valid {
+                return Err(ConfigError(format!(
+                    "cdn_base_url '{base}' must be an absolute http or https URL"
+                )));
+            }
+        }
+        if self.local_storage_path.is_some() || self.backend != "s3" {
+            return Ok(());
+        }
+
+        let endpoint = url::Url::parse(&self.endpoint).map_err(|e| {
+            ConfigError(format!(
+                "endpoint '{}' is not a valid URL: {e}",
+                self.endpoint
+            ))
+        })?;
+        if !matches!(endpoint.scheme(), "http" | "https") {
+            return Err(ConfigError(format!(
+                "endpoint '{}' must be an http or https URL",
+                self.endpoint
+            )));
+        }
+        if self.bucket.trim().is_empty() {
+            return Err(ConfigError("bucket name must not be empty".into()));
+        }
+        if self.access_key.trim().is_empty() {
+            return Err(ConfigError("access key must not be empty".into()));
+        }
+        if self.secret_key.trim().is_empty() {
+            return Err(ConfigError("secret key must not be empty".into()));
+        }
+        if !is_region_name(&self.region) {
+            return Err(ConfigError(format!(
+                "region '{}' is not a region name like 'us-east-1'",
+                self.region
+            )));
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn is_region_name(region: &str) -> bool {
+    !region.is_empty()
+        && !region.starts_with('-')
+        && !region.ends_with('-')
+        && region
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+pub(super) static S3_CONFIG: OnceLock<S3Config> = OnceLock::new();
+
+/// S3 wants every part of a multipart upload but the last to be at least 5 MiB.
+pub(super) const MIN_PART_BYTES: usize = 5 * 1024 * 1024;
+
+pub(super) fn get_s3_config(ctx: &AppContext) -> S3Config {
+    S3_CONFIG
+        .get_or_init(|| {
+            ctx.config
+                .settings
+                .as_ref()
+                .and_then(|s| serde_json::from_value(s.clone()).ok())
+                .unwrap_or_default()
+        })
+        .clone()
+}
@@ -0,0 +1,599 @@
+//! What is derived from a file's content: the content index, thumbnails,
+//! previews and resized images.
+
+use super::*;
+
+pub(super) async fn schedule_content_indexing(ctx: &AppContext, name: &str) {
+    let args = IndexContentArgs {
+        name: name.to_string(),
+    };
+    if let Err(e) = IndexContentWorker::perform_later(ctx, args).await {
+        tracing::warn!(name, error = %e, "failed to schedule content indexing");
+    }
+}
+
+/// Re-reads `name` from storage and updates its content index entry. Files
+/// that are gone, unsupported or unreadable drop out of the index.
+pub async fn index_content(ctx: &AppContext, name: &str) -> Result<()> {
+    let config = get_s3_config(ctx);
+    let store = shared_store(&config)?;
+    let index = content_index::open(&config.content_index_dir)?;
+
+    let text = match read_current_content(ctx, store.as_ref(), name, None).await? {
+        Some(bytes) => indexable_text(name, bytes).await,
+        None => None,
+    };
+    update_content_index(index, name.to_string(), text).await
+}
+
+/// Empties the content index and indexes every file found in the bucket
+/// again, returning how many were indexed.
+pub async fn rebuild_content_index(ctx: &AppContext) -> Result<usize> {
+    let config = get_s3_config(ctx);
+    let store = shared_store(&config)?;
+    let index = content_index::open(&config.content_index_dir)?;
+    let cleared = index.clone();
+    tokio::task::spawn_blocking(move || cleared.clear())
+        .await
+        .map_err(|e| Error::Message(format!("Content index task failed: {e}")))??;
+
+    let mut keys: BTreeSet<String> = BTreeSet::new();
+    let mut listing = store.list(None);
+    while let Some(meta) = listing.try_next().await.map_err(Error::wrap)? {
+        let key = meta.location.to_string();
+        if !(key.starts_with("blobs/") || is_derived_key(&key)) {
+            keys.insert(key);
+        }
+    }
+    // Rows name the files whose latest content lives in a blob or `.gz` key.
+    for row in file::find_all_for_tenant(&ctx.db, None).await? {
+        if keys.remove(latest_path(&row).as_ref()) || row.blob_hash.is_some() {
+            keys.insert(row.name);
+        }
+    }
+
+    let mut indexed = 0;
+    for name in keys {
+        let Some(bytes) = read_current_content(ctx, store.as_ref(), &name, None).await? else {
+            continue;
+        };
+        let Some(text) = indexable_text(&name, bytes).await else {
+            continue;
+        };
+        update_content_index(index.clone(), name, Some(text)).await?;
+        indexed += 1;
+    }
+    Ok(indexed)
+}
+
+pub(super) async fn read_current_content(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    name: &str,
+    tenant: Option<&str>,
+) -> Result<Option<Bytes>> {
+    let path = file::find_by_name_for_tenant(&ctx.db, name, tenant)
+        .await?
+        .map_or_else(|| ObjectPath::from(name), |f| latest_path(&f));
+    match store.get(&path).await {
+        Ok(result) => read_decoded(result).await.map(Some),
+        Err(ObjectStoreError::NotFound { .. }) => Ok(None),
+        Err(e) => Err(Error::wrap(e)),
+    }
+}
+
+pub(super) async fn schedule_thumbnail(ctx: &AppContext, name: &str) {
+    if !thumbnails::is_image(name) {
+        return;
+    }
+    let args = GenerateThumbnailArgs {
+        name: name.to_string(),
+    };
+    if let Err(e) = GenerateThumbnailWorker::perform_later(ctx, args).await {
+        tracing::warn!(name, error = %e, "failed to schedule thumbnail generation");
+    }
+}
+
+/// Renders the thumbnail of image `name` under `thumbnails/` and records its
+/// key in the file's metadata. Images that can't be decoded are logged and skipped.
+pub async fn generate_thumbnail(ctx: &AppContext, name: &str) -> Result<()> {
+    let config = get_s3_config(ctx);
+    let store = shared_store(&config)?;
+    let Some(bytes) = read_current_content(ctx, store.as_ref(), name, None).await? else {
+        return Ok(());
+    };
+
+    let max_dimension = config.thumbnail_max_dimension.max(1);
+    let rendered = tokio::task::spawn_blocking(move || thumbnails::render(&bytes, max_dimension))
+        .await
+        .unwrap_or_else(|e| Err(format!("Thumbnail rendering panicked: {e}")));
+    let thumbnail = match rendered {
+        Ok(thumbnail) => thumbnail,
+        Err(e) => {
+            tracing::warn!(name, error = %e, "skipping thumbnail");
+            return Ok(());
+        }
+    };
+
+    let key = thumbnails::thumbnail_key(name);
+    let mut attributes = Attributes::new();
+    attributes.insert(Attribute::ContentType, "image/webp".into());
+    store
+        .put_opts(
+            &ObjectPath::from(key.as_str()),
+            Bytes::from(thumbnail).into(),
+            PutOptions {
+                attributes,
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(Error::wrap)?;
+
+    // Re-read the row so metadata edits made while rendering are kept.
+    let Some(record) = file::find_by_name_for_tenant(&ctx.db, name, None).await? else {
+        return Ok(());
+    };
+    let mut metadata: FileMetadata = record
+        .metadata
+        .and_then(|m| serde_json::from_value(m).ok())
+        .unwrap_or_default();
+    metadata.thumbnail = Some(key.clone());
+    file::update_metadata(&ctx.db, record.id, serde_json::to_value(&metadata)?).await?;
+    events::bus().publish(
+        ProgressKind::ThumbnailReady,
+        name,
+        record.author_id,
+        Some(serde_json::json!({ "thumbnail": key })),
+    );
+    Ok(())
+}
+
+/// A render that runs longer than this is treated as a failed preview.
+pub(super) const PREVIEW_RENDER_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub(super) fn pdf_renderer(config: &S3Config) -> Arc<dyn PdfRenderer> {
+    Arc::new(Pdftoppm {
+        binary: config.pdftoppm_path.clone(),
+        timeout: PREVIEW_RENDER_TIMEOUT,
+    })
+}
+
+pub(super) async fn schedule_preview(ctx: &AppContext, name: &str) {
+    if !previews::is_pdf(name) {
+        return;
+    }
+    let args = GeneratePreviewArgs {
+        name: name.to_string(),
+    };
+    if let Err(e) = GeneratePreviewWorker::perform_later(ctx, args).await {
+        tracing::warn!(name, error = %e, "failed to schedule preview rendering");
+    }
+}
+
+/// Renders the first page of PDF `name` next to its thumbnails. A PDF that
+/// can't be rendered, such as an encrypted one, gets `preview_error` instead.
+pub async fn generate_preview(ctx: &AppContext, name: &str) -> Result<()> {
+    let config = get_s3_config(ctx);
+    let store = shared_store(&config)?;
+    let Some(bytes) = read_current_content(ctx, store.as_ref(), name, None).await? else {
+        return Ok(());
+    };
+
+    let renderer = pdf_renderer(&config);
+    let dpi = config.preview_dpi.max(1);
+    let rendered = tokio::task::spawn_blocking(move || renderer.render_first_page(&bytes, dpi))
+        .await
+        .unwrap_or_else(|e| Err(format!("Preview rendering panicked: {e}")));
+
+    let outcome = match rendered {
+        Ok(png) => {
+            let key = previews::preview_key(name);
+            let mut attributes = Attributes::new();
+            attributes.insert(
+                Attribute::ContentType,
+                previews::PREVIEW_CONTENT_TYPE.into(),
+            );
+            store
+                .put_opts(
+                    &ObjectPath::from(key.as_str()),
+                    Bytes::from(png).into(),
+                    PutOptions {
+                        attributes,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .map_err(Error::wrap)?;
+            Ok(key)
+        }
+        Err(e) => {
+            tracing::warn!(name, error = %e, "no preview for PDF");
+            Err(e)
+        }
+    };
+
+    // Re-read the row so metadata edits made while rendering are kept.
+    let Some(record) = file::find_by_name_for_tenant(&ctx.db, name, None).await? else {
+        return Ok(());
+    };
+    let mut metadata: FileMetadata = record
+        .metadata
+        .and_then(|m| serde_json::from_value(m).ok())
+        .unwrap_or_default();
+    (metadata.preview, metadata.preview_error) = match outcome {
+        Ok(key) => (Some(key), None),
+        Err(e) => (None, Some(e)),
+    };
+    file::update_metadata(&ctx.db, record.id, serde_json::to_value(&metadata)?).await?;
+    Ok(())
+}
+
+/// A corrupt file is logged and left out rather than failing the caller.
+pub(super) async fn indexable_text(name: &str, bytes: Bytes) -> Option<String> {
+    match content_index::extract_text(name, bytes.to_vec()).await {
+        Ok(text) => text,
+        Err(e) => {
+            tracing::warn!(name, error = %e, "skipping unreadable file in content index");
+            None
+        }
+    }
+}
+
+pub(super) async fn update_content_index(
+    index: Arc<ContentIndex>,
+    name: String,
+    text: Option<String>,
+) -> Result<()> {
+    tokio::task::spawn_blocking(move || index.replace(&name, text.as_deref()))
+        .await
+        .map_err(|e| Error::Message(format!("Content index task failed: {e}")))?
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ResizeParams {
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    #[serde(default)]
+    pub fit: thumbnails::Fit,
+    /// Defaults to the format of the original.
+    pub format: Option<thumbnails::OutputFormat>,
+}
+
+/// Serves an image resized into a `w` x `h` box. Results are cached under
+/// `resized/`, keyed by the original's content so an overwrite is never
+/// answered with a stale copy.
+#[utoipa::path(
+    get,
+    path = "/files/{file_name}/resized",
+    operation_id = "getResizedFile",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included"), ResizeParams),
+    responses(
+        (status = 200, description = "The image resized into the box", content_type = "image/*", body = [u8]),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+    ),
+)]
+pub async fn get_resized_file(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    FileName(file_name): FileName,
+    Query(params): Query<ResizeParams>,
+) -> FileResult<Response> {
+    let Some(source_format) = thumbnails::OutputFormat::from_file_name(&file_name) else {
+        return Err(FileError::Rejected {
+            status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            code: "unsupported_media_type".into(),
+            message: format!("'{file_name}' is not a PNG, JPEG or WebP image"),
+            details: None,
+        });
+    };
+
+    let max = thumbnails::MAX_RESIZE_DIMENSION;
+    let (width, height) = match (params.w, params.h) {
+        (None, None) => return Err(FileError::BadRequest("w or h is required".into())),
+        (Some(w), Some(h)) => (w, h),
+        (w, h) if params.fit == thumbnails::Fit::Contain => (w.unwrap_or(max), h.unwrap_or(max)),
+        _ => {
+            return Err(FileError::BadRequest(format!(
+                "fit={} needs both w and h",
+                params.fit.as_str()
+            )));
+        }
+    };
+    if !(1..=max).contains(&width) || !(1..=max).contains(&height) {
+        return Err(FileError::BadRequest(format!(
+            "w and h must be between 1 and {max}"
+        )));
+    }
+    let format = params.format.unwrap_or(source_format);
+
+    let record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id()).await?;
+    let source = record
+        .as_ref()
+        .map_or_else(|| ObjectPath::from(file_name.as_str()), latest_path);
+    let content_tag = match record.and_then(|f| f.content_hash) {
+        Some(hash) => hash,
+        None => {
+            let meta = store.head(&source).await.map_err(FileError::StorageError)?;
+            let tag = meta
+                .e_tag
+                .unwrap_or_else(|| meta.last_modified.timestamp_millis().to_string());
+            hex::encode(Sha256::digest(format!("{source}:{tag}")))
+        }
+    };
+    let variant = format!(
+        "{width}x{height}-{}.{}",
+        params.fit.as_str(),
+        format.extension()
+    );
+    let key = ObjectPath::from(format!("resized/{content_tag}/{variant}"));
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, format.content_type())
+        .header(header::CACHE_CONTROL, "public, max-age=86400")
+        .header(header::ETAG, format!("\"{content_tag}-{variant}\""));
+
+    match store.get(&key).await {
+        Ok(cached) => {
+            return response
+                .header(header::CONTENT_LENGTH, cached.meta.size)
+                .body(Body::from_stream(cached.into_stream()))
+                .map_err(|e| FileError::Internal(format!("Build response: {e}")));
+        }
+        Err(ObjectStoreError::NotFound { .. }) => {}
+        Err(e) => return Err(FileError::StorageError(e)),
+    }
+
+    let original = store.get(&source).await.map_err(FileError::StorageError)?;
+    let bytes = read_decoded(original).await?;
+    let fit = params.fit;
+    let resized =
+        tokio::task::spawn_blocking(move || thumbnails::resize(&bytes, width, height, fit, format))
+            .await
+            .unwrap_or_else(|e| Err(format!("Resizing panicked: {e}")))
+            .map_err(|e| unprocessable("invalid_image", &e))?;
+    let resized = Bytes::from(resized);
+
+    if let Err(e) = store.put(&key, resized.clone().into()).await {
+        tracing::warn!(key = %key, error = %e, "failed to cache resized image");
+    }
+
+    response
+        .header(header::CONTENT_LENGTH, resized.len())
+        .body(Body::from(resized))
+        .map_err(|e| FileError::Internal(format!("Build response: {e}")))
+}
+
+/// Originals up to this size stand in for a thumbnail that isn't ready yet.
+pub(super) const THUMBNAIL_FALLBACK_MAX_BYTES: i64 = 256 * 1024;
+
+/// Serves the WebP thumbnail of an image. Until one exists, small images
+/// redirect to the original and larger ones are 404 with `processing` set.
+#[utoipa::path(
+    get,
+    path = "/files/{file_name}/thumbnail",
+    operation_id = "getFileThumbnail",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included")),
+    responses(
+        (status = 200, description = "The thumbnail image", content_type = "image/webp", body = [u8]),
+        (status = 302, description = "No thumbnail yet; the image is small enough to serve itself"),
+        (status = 404, description = "No such file, or its thumbnail is still being rendered", body = ErrorBody),
+    ),
+)]
+pub async fn get_file_thumbnail(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    FileName(file_name): FileName,
+) -> FileResult<Response> {
+    let record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_name))?;
+    let thumbnail = record
+        .metadata
+        .and_then(|m| serde_json::from_value::<FileMetadata>(m).ok())
+        .and_then(|m| m.thumbnail);
+
+    let Some(key) = thumbnail else {
+        if thumbnails::is_image(&file_name) && record.size <= THUMBNAIL_FALLBACK_MAX_BYTES {
+            let encoded: Vec<String> = file_name.split('/').map(glacier::encode_segment).collect();
+            return Response::builder()
+                .status(StatusCode::FOUND)
+                .header(header::LOCATION, format!("/files/{}", encoded.join("/")))
+                .body(Body::empty())
+                .map_err(|e| FileError::Internal(format!("Build response: {e}")));
+        }
+        if thumbnails::is_image(&file_name) {
+            return Err(FileError::Rejected {
+                status: StatusCode::NOT_FOUND,
+                code: "thumbnail_processing".into(),
+                message: format!("The thumbnail of '{file_name}' is not ready yet"),
+                details: Some(serde_json::json!({ "processing": true })),
+            });
+        }
+        return Err(file_not_found(&file_name));
+    };
+
+    let bytes = store
+        .get(&ObjectPath::from(key))
+        .await
+        .map_err(FileError::StorageError)?
+        .bytes()
+        .await
+        .map_err(FileError::StorageError)?;
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "image/webp")
+        .header(header::CONTENT_LENGTH, bytes.len())
+        .body(Body::from(bytes))
+        .map_err(|e| FileError::Internal(format!("Build response: {e}")))
+}
+
+pub(super) const PREVIEW_DEFAULT_BYTES: usize = 1024;
+pub(super) const PREVIEW_MAX_BYTES: usize = 64 * 1024;
+pub(super) const PDF_MAGIC: &[u8] = b"%PDF";
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PreviewParams {
+    /// Serve this many of the file's first bytes, at most 65536, instead of
+    /// a rendered preview. Files without one get 1024 of them.
+    pub bytes: Option<usize>,
+    /// Refuse with a 415 a file that doesn't start like a PDF does. Implies
+    /// a preview in bytes.
+    #[serde(default)]
+    pub require_pdf: bool,
+}
+
+/// Serves the first-page preview of a PDF. Images redirect to their
+/// thumbnail; PDFs uploaded before previews existed are rendered on first
+/// request. Other files, and any file asked for `bytes`, are previewed by
+/// their first bytes.
+#[utoipa::path(
+    get,
+    path = "/files/{file_name}/preview",
+    operation_id = "getFilePreview",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included"), PreviewParams),
+    responses(
+        (status = 200, description = "First page of the PDF as an image", content_type = "image/png", body = [u8]),
+        (status = 200, description = "First bytes of the file, as text/plain for text", content_type = "application/octet-stream", body = [u8]),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 415, description = "Not a PDF, with require_pdf", body = ErrorBody),
+    ),
+)]
+pub async fn get_file_preview(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    FileName(file_name): FileName,
+    Query(params): Query<PreviewParams>,
+) -> FileResult<Response> {
+    let record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_name))?;
+
+    let in_bytes = params.bytes.is_some() || params.require_pdf;
+    if in_bytes || !(thumbnails::is_image(&file_name) || previews::is_pdf(&file_name)) {
+        return preview_file(store.as_ref(), &record, &params).await;
+    }
+    if thumbnails::is_image(&file_name) {
+        let encoded: Vec<String> = file_name.split('/').map(glacier::encode_segment).collect();
+        return Response::builder()
+            .status(StatusCode::FOUND)
+            .header(
+                header::LOCATION,
+                format!("/files/{}/thumbnail", encoded.join("/")),
+            )
+            .body(Body::empty())
+            .map_err(|e| FileError::Internal(format!("Build response: {e}")));
+    }
+
+    let metadata: FileMetadata = record
+        .metadata
+        .and_then(|m| serde_json::from_value(m).ok())
+        .unwrap_or_default();
+    let key = match (metadata.preview, metadata.preview_error) {
+        (Some(key), _) => key,
+        (None, Some(reason)) => {
+            return Err(FileError::Rejected {
+                status: StatusCode::NOT_FOUND,
+                code: "preview_unavailable".into(),
+                message: format!("No preview can be rendered for '{file_name}'"),
+                details: Some(serde_json::json!({ "reason": reason })),
+            });
+        }
+        (None, None) => {
+            schedule_preview(&ctx, &file_name).await;
+            return Err(FileError::Rejected {
+                status: StatusCode::NOT_FOUND,
+                code: "preview_pending".into(),
+                message: format!("The preview of '{file_name}' is not ready yet"),
+                details: None,
+            });
+        }
+    };
+
+    let preview = store
+        .get(&ObjectPath::from(key))
+        .await
+        .map_err(FileError::StorageError)?;
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, previews::PREVIEW_CONTENT_TYPE)
+        .header(header::CONTENT_LENGTH, preview.meta.size)
+        .header(header::CACHE_CONTROL, "public, max-age=86400");
+    if let Some(e_tag) = &preview.meta.e_tag {
+        response = response.header(header::ETAG, e_tag);
+    }
+    response
+        .body(Body::from_stream(preview.into_stream()))
+        .map_err(|e| FileError::Internal(format!("Build response: {e}")))
+}
+
+/// The first bytes of `record`, as text if it is text.
+pub(super) async fn preview_file(
+    store: &dyn ObjectStore,
+    record: &file::Model,
+    params: &PreviewParams,
+) -> FileResult<Response> {
+    if params.require_pdf && read_head(store, record, PDF_MAGIC.len()).await? != PDF_MAGIC {
+        return Err(FileError::Rejected {
+            status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            code: "not_a_pdf".into(),
+            message: format!("'{}' is not a PDF", record.name),
+            details: None,
+        });
+    }
+    let len = params
+        .bytes
+        .unwrap_or(PREVIEW_DEFAULT_BYTES)
+        .clamp(1, PREVIEW_MAX_BYTES);
+    let head = read_head(store, record, len).await?;
+    let content_type = if is_text_like(&record.name) {
+        "text/plain; charset=utf-8"
+    } else {
+        "application/octet-stream"
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, head.len())
+        .body(Body::from(head))
+        .map_err(|e| FileError::Internal(format!("Build response: {e}")))
+}
+
+/// Up to `len` of the first bytes of `record`'s content. A gzipped object
+/// is decoded only as far as they go; any other is read by range.
+pub(super) async fn read_head(
+    store: &dyn ObjectStore,
+    record: &file::Model,
+    len: usize,
+) -> FileResult<Bytes> {
+    let path = latest_path(record);
+    if record.compressed && path.as_ref().ends_with(GZIP_SUFFIX) {
+        let result = store.get(&path).await.map_err(FileError::StorageError)?;
+        let mut head = Vec::with_capacity(len);
+        GzipDecoder::new(StreamReader::new(result.into_stream()))
+            .take(len as u64)
+            .read_to_end(&mut head)
+            .await
+            .map_err(|e| FileError::Internal(format!("Read '{}': {e}", record.name)))?;
+        return Ok(head.into());
+    }
+    let end = len.min(record.size.max(0) as usize);
+    if end == 0 {
+        return Ok(Bytes::new());
+    }
+    store
+        .get_range(&path, 0..end)
+        .await
+        .map_err(FileError::StorageError)
+}
@@ -0,0 +1,219 @@
+//! Serving a file's content.
+
+use super::*;
+
+#[utoipa::path(
+    get,
+    path = "/files/{file_name}",
+    operation_id = "downloadFile",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included"), DownloadParams, SignedDownloadParams),
+    responses(
+        (status = 200, description = "The file content", content_type = "application/octet-stream", body = [u8]),
+        (status = 302, description = "To a pre-signed S3 URL that expires after `signed_url_ttl_seconds`, in redirect mode"),
+        (status = 401, description = "Neither a signed link nor a token, with `require_signed_downloads` on", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 403, description = "The file is quarantined or not yet scanned, or the signed link is invalid or expired", body = ErrorBody),
+    ),
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn get_file(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    remote_ip: RemoteIP,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+    Query(params): Query<DownloadParams>,
+) -> FileResult<Response> {
+    // Only indexed files are served: the keys of quarantined content, blobs
+    // and the trash have no row of their own, and are never looked up raw.
+    let record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_name))?;
+    ensure_servable(&record)?;
+
+    let path = match params.version.as_deref() {
+        Some(version) => snapshot_path(&file_name, snapshot_timestamp(version)?),
+        None => latest_path(&record),
+    };
+    let config = get_s3_config(&ctx);
+    let client_ip = client_ip(remote_ip, connect_info);
+
+    // Redirects skip gzipped objects for clients that can't decode them,
+    // and backends `presigner` can't sign for; those are proxied.
+    let gzipped = record.compressed && record.version == 1;
+    if params
+        .redirect
+        .unwrap_or(config.download_mode == DownloadMode::Redirect)
+        && (!gzipped || accepts_gzip(&headers))
+        && let Some(signer) = presigner(&config, tenant.id())?
+    {
+        // Checked here, so a missing file is a 404 rather than S3's error.
+        match store.head(&path).await {
+            Ok(_) => {}
+            Err(object_store::Error::NotFound { .. }) => return Err(file_not_found(&file_name)),
+            Err(e) => return Err(FileError::StorageError(e)),
+        }
+        let url = signer
+            .signed_url(
+                Method::GET,
+                &path,
+                Duration::from_secs(config.signed_url_ttl_seconds),
+            )
+            .await
+            .map_err(FileError::StorageError)?;
+        record_download(&ctx, upload_lock_key(tenant.id(), &file_name), client_ip);
+        return Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, url.as_str())
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(Body::empty())
+            .map_err(|e| FileError::Internal(format!("Build response: {e}")));
+    }
+
+    let result = store.get(&path).await.map_err(FileError::StorageError)?;
+    let cdn_url = cdn_url(&config, &path);
+    let e_tag = result.meta.e_tag.clone();
+
+    let content_type = mime_guess::from_path(&file_name)
+        .first_or_octet_stream()
+        .to_string();
+
+    // Gzipped objects go out as they are to clients that take gzip, and
+    // decoded for everyone else. Either way the body is streamed.
+    let gzip_encoded = is_gzip_encoded(&result.attributes);
+    let pass_through = gzip_encoded && accepts_gzip(&headers);
+    let (content_length, body) = if gzip_encoded && !pass_through {
+        let size = result
+            .attributes
+            .get(&Attribute::Metadata(UNCOMPRESSED_SIZE_METADATA.into()))
+            .and_then(|v| v.parse::<u64>().ok());
+        let decoder = GzipDecoder::new(StreamReader::new(result.into_stream()));
+        (size, Body::from_stream(ReaderStream::new(decoder)))
+    } else {
+        (Some(result.meta.size as u64), object_body(result))
+    };
+
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_DISPOSITION, attachment(&file_name));
+    if let Some(len) = content_length {
+        response = response.header(header::CONTENT_LENGTH, len);
+    }
+    if let Some(e_tag) = e_tag {
+        response = response.header(header::ETAG, e_tag);
+    }
+    let mut response = response
+        .body(body)
+        .map_err(|e| FileError::Internal(format!("Build response: {e}")))?;
+
+    if gzip_encoded {
+        response
+            .headers_mut()
+            .insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    if pass_through {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    }
+    if let Some(value) = cdn_url.and_then(|url| HeaderValue::from_str(&url).ok()) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static("x-cdn-url"), value);
+    }
+
+    record_download(&ctx, upload_lock_key(tenant.id(), &file_name), client_ip);
+    Ok(response)
+}
+
+/// Counted off the request path; a lost row only skews the statistics.
+pub(super) fn record_download(ctx: &AppContext, file_name: String, client_ip: Option<IpAddr>) {
+    let db = ctx.db.clone();
+    tokio::spawn(async move {
+        let client_ip = client_ip.map(|ip| ip.to_string());
+        if let Err(e) = file_download::record(&db, &file_name, client_ip).await {
+            tracing::warn!(file_name, error = %e, "failed to record download");
+        }
+    });
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FileExists {
+    pub exists: bool,
+    /// Only when the file exists.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Whether `file_name` is stored, answered in the body: the status is 200
+/// either way, so it can't be mistaken for a refused request.
+#[utoipa::path(
+    get,
+    path = "/files/{file_name}/exists",
+    operation_id = "fileExists",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included")),
+    responses(
+        (status = 200, description = "Whether the file exists", body = FileExists),
+    ),
+)]
+pub async fn file_exists(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    FileName(file_name): FileName,
+) -> FileResult<Json<FileExists>> {
+    let Some(record) = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id()).await?
+    else {
+        return Ok(Json(FileExists {
+            exists: false,
+            name: None,
+        }));
+    };
+    let exists = match store.head(&latest_path(&record)).await {
+        Ok(_) => true,
+        Err(ObjectStoreError::NotFound { .. }) => false,
+        Err(e) => return Err(FileError::StorageError(e)),
+    };
+    Ok(Json(FileExists {
+        exists,
+        name: exists.then_some(file_name),
+    }))
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DownloadCount {
+    pub count: i64,
+    pub last_downloaded_at: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/files/{file_name}/download-count",
+    operation_id = "getDownloadCount",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included")),
+    responses(
+        (status = 200, description = "How often the file was downloaded", body = DownloadCount),
+        (status = 404, description = "No such file", body = ErrorBody),
+    ),
+)]
+pub async fn get_download_count(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    FileName(file_name): FileName,
+) -> FileResult<Json<DownloadCount>> {
+    file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_name))?;
+    let key = upload_lock_key(tenant.id(), &file_name);
+    let (count, last) = file_download::stats(&ctx.db, &key).await?;
+    Ok(Json(DownloadCount {
+        count,
+        last_downloaded_at: last.map(|at| at.and_utc().to_rfc3339()),
+    }))
+}
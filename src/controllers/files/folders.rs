@@ -0,0 +1,833 @@
+//! Deleting files, and deleting and moving folders.
+
+use super::*;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeletedFile {
+    pub deleted: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FolderDeleteParams {
+    /// A folder such as `projects/old/`; the trailing slash is implied.
+    #[serde(default)]
+    pub prefix: String,
+    /// Must be `ALL` for an empty prefix, which deletes the whole bucket.
+    pub confirm: Option<String>,
+    /// Reports what would go without deleting anything.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FolderFailure {
+    /// A file name, or a storage key no file owns.
+    pub key: String,
+    pub error: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct FolderMoveRequest {
+    /// A folder such as `projects/alpha/`; the trailing slash is implied.
+    pub from: String,
+    pub to: String,
+    /// Replace what `to` already holds under the same names.
+    #[serde(default)]
+    pub overwrite: bool,
+    /// Keep what `to` already holds and leave those sources under `from`.
+    #[serde(default)]
+    pub merge: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FolderMoved {
+    pub from: String,
+    pub to: String,
+    /// Files, and objects no file owns, now under `to`.
+    pub moved: usize,
+    /// Left under `from` because `merge` kept what `to` held.
+    pub skipped: usize,
+    pub failures: Vec<FolderFailure>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FolderDeleted {
+    pub prefix: String,
+    pub dry_run: bool,
+    /// Indexed files removed with their rows, or that would be.
+    pub files: Vec<String>,
+    /// Objects under the prefix removed, derived copies included.
+    pub deleted_objects: usize,
+    pub bytes_freed: u64,
+    /// What was left in place; the rest of the folder went regardless.
+    pub failures: Vec<FolderFailure>,
+}
+
+#[utoipa::path(
+    delete,
+    path = "/files/{file_name}",
+    operation_id = "deleteFile",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included")),
+    responses(
+        (status = 200, description = "The file and everything derived from it is gone", body = DeletedFile),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn delete_file(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    remote_ip: RemoteIP,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+) -> FileResult<Json<DeletedFile>> {
+    let auth_header = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(FileError::Unauthorized)?;
+
+    let token = auth_header.strip_prefix("Bearer ").unwrap_or(auth_header);
+    let claims =
+        crate::controllers::auth::decode_token(token).map_err(|_| FileError::Unauthorized)?;
+    let actor = request_actor(
+        &headers,
+        claims.pid.parse().ok(),
+        client_ip(remote_ip, connect_info),
+    );
+
+    remove_file_audited(&ctx, store.as_ref(), tenant.id(), &file_name, &actor).await?;
+
+    Ok(Json(DeletedFile { deleted: file_name }))
+}
+
+/// `remove_file`, with a failure recorded in the audit log too.
+pub(super) async fn remove_file_audited(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    tenant: Option<&str>,
+    file_name: &str,
+    actor: &Actor,
+) -> FileResult<()> {
+    if let Err(e) = remove_file(ctx, store, tenant, file_name, actor).await {
+        let mut entry = Entry::new(Operation::Delete, Some(file_name), actor).failed(e.to_string());
+        entry.status_code = Some(e.status().as_u16());
+        audit_writer::log(ctx, entry);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Files handled at once by a folder delete or move.
+pub(super) const FOLDER_CONCURRENCY: usize = 8;
+
+/// Deletes everything under a folder: each indexed file like `DELETE
+/// /files/{file_name}` does, then whatever objects under the prefix no
+/// file owns. A failure is reported and the rest carries on; the objects
+/// of a file that could not be removed are left alone with its row.
+#[utoipa::path(
+    delete,
+    path = "/files/folder",
+    operation_id = "deleteFolder",
+    tag = "files",
+    params(FolderDeleteParams),
+    responses(
+        (status = 200, description = "What was deleted, or would be on a dry run, and what failed", body = FolderDeleted),
+        (status = 400, description = "Invalid prefix, or an empty one without `confirm=ALL`", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn delete_folder(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    remote_ip: RemoteIP,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
+    Query(params): Query<FolderDeleteParams>,
+) -> FileResult<Json<FolderDeleted>> {
+    let auth_header = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(FileError::Unauthorized)?;
+
+    let token = auth_header.strip_prefix("Bearer ").unwrap_or(auth_header);
+    let claims =
+        crate::controllers::auth::decode_token(token).map_err(|_| FileError::Unauthorized)?;
+    let actor = request_actor(
+        &headers,
+        claims.pid.parse().ok(),
+        client_ip(remote_ip, connect_info),
+    );
+
+    let prefix = match folder_prefix(&params.prefix)? {
+        Some(prefix) => prefix,
+        None if params.confirm.as_deref() == Some("ALL") => String::new(),
+        None => {
+            return Err(FileError::BadRequest(
+                "An empty prefix deletes every file; pass confirm=ALL to do that".into(),
+            ));
+        }
+    };
+
+    let rows = file::find_by_name_prefix(&ctx.db, &prefix, tenant.id()).await?;
+    let files: Vec<String> = rows.into_iter().map(|f| f.name).collect();
+    let list_prefix = (!prefix.is_empty()).then(|| ObjectPath::from(prefix.as_str()));
+    let objects: Vec<ObjectMeta> = store
+        .list(list_prefix.as_ref())
+        .try_collect()
+        .await
+        .map_err(FileError::StorageError)?;
+
+    if params.dry_run {
+        return Ok(Json(FolderDeleted {
+            prefix,
+            dry_run: true,
+            files,
+            deleted_objects: objects.len(),
+            bytes_freed: objects.iter().map(|o| o.size as u64).sum(),
+            failures: Vec::new(),
+        }));
+    }
+
+    let mut removed = Vec::new();
+    let mut failures = Vec::new();
+    let mut kept = HashSet::new();
+    let mut results = futures_util::stream::iter(files)
+        .map(|name| {
+            let (ctx, store, actor, tenant) = (&ctx, store.as_ref(), &actor, tenant.id());
+            async move {
+                let result = remove_file_audited(ctx, store, tenant, &name, actor).await;
+                (name, result)
+            }
+        })
+        .buffer_unordered(FOLDER_CONCURRENCY);
+    while let Some((name, result)) = results.next().await {
+        match result {
+            Ok(()) => removed.push(name),
+            Err(e) => {
+                tracing::warn!(file_name = %name, error = %e, "folder delete left a file in place");
+                failures.push(FolderFailure {
+                    key: name.clone(),
+                    error: e.to_string(),
+                });
+                kept.insert(name);
+            }
+        }
+    }
+    drop(results);
+    removed.sort();
+
+    // Most of these went with their file above; deleting them again finds
+    // them gone, which counts the same.
+    let targets: Vec<ObjectMeta> = objects
+        .into_iter()
+        .filter(|o| !owned_by_any(o.location.as_ref(), &kept))
+        .collect();
+    let mut deletes = futures_util::stream::iter(targets)
+        .map(|o| {
+            let store = store.as_ref();
+            async move {
+                let result = match store.delete(&o.location).await {
+                    Err(object_store::Error::NotFound { .. }) => Ok(()),
+                    other => other,
+                };
+                (o, result)
+            }
+        })
+        .buffer_unordered(STORAGE_META_CONCURRENCY);
+    let (mut deleted_objects, mut bytes_freed) = (0, 0);
+    while let Some((o, result)) = deletes.next().await {
+        match result {
+            Ok(()) => {
+                deleted_objects += 1;
+                bytes_freed += o.size as u64;
+            }
+            Err(e) => failures.push(FolderFailure {
+                key: o.location.to_string(),
+                error: e.to_string(),
+            }),
+        }
+    }
+    drop(deletes);
+    failures.sort_by(|a, b| a.key.cmp(&b.key));
+
+    tracing::info!(
+        prefix = %prefix,
+        files = removed.len(),
+        deleted_objects,
+        bytes_freed,
+        failed = failures.len(),
+        "deleted folder"
+    );
+    Ok(Json(FolderDeleted {
+        prefix,
+        dry_run: false,
+        files: removed,
+        deleted_objects,
+        bytes_freed,
+        failures,
+    }))
+}
+
+/// `value` as a folder ending in `/`, or `None` for the root.
+pub(super) fn folder_prefix(value: &str) -> FileResult<Option<String>> {
+    let trimmed = value.trim_matches('/');
+    if trimmed.is_empty() {
+        return Ok(None);
+    }
+    let segments = safe_path_segments(trimmed)
+        .ok_or_else(|| FileError::BadRequest(format!("Invalid prefix '{value}'")))?;
+    Ok(Some(format!("{}/", segments.join("/"))))
+}
+
+/// What a folder move does about names the destination already holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Clash {
+    Refuse,
+    Overwrite,
+    Merge,
+}
+
+pub(super) enum MoveOutcome {
+    Moved,
+    /// `merge` kept the destination's file, so the source stays.
+    Skipped,
+}
+
+/// Moves every object under `from` to the same place under `to`: each
+/// indexed file with its row, versions, snapshots and thumbnails, then the
+/// objects no file owns.
+///
+/// Safe to re-run after a crash. Objects are copied without replacing
+/// what is there, a copy found in place from an earlier run is compared
+/// with its source, every copy is checked before any source is deleted,
+/// and the row is renamed last, so a file is under `from` until all of it
+/// is under `to`.
+#[utoipa::path(
+    post,
+    path = "/files/folder/move",
+    operation_id = "moveFolder",
+    tag = "files",
+    request_body = FolderMoveRequest,
+    responses(
+        (status = 200, description = "What was moved, kept back by `merge`, or failed", body = FolderMoved),
+        (status = 400, description = "Invalid or nested folders", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 409, description = "The destination is not empty and neither `overwrite` nor `merge` was passed", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn move_folder(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    remote_ip: RemoteIP,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
+    Json(request): Json<FolderMoveRequest>,
+) -> FileResult<Json<FolderMoved>> {
+    let claims = auth::claims_from_headers(&headers)?;
+    let actor = request_actor(
+        &headers,
+        claims.pid.parse().ok(),
+        client_ip(remote_ip, connect_info),
+    );
+
+    let (Some(from), Some(to)) = (folder_prefix(&request.from)?, folder_prefix(&request.to)?)
+    else {
+        return Err(FileError::BadRequest(
+            "Both from and to must name a folder".into(),
+        ));
+    };
+    if from.starts_with(&to) || to.starts_with(&from) {
+        return Err(FileError::BadRequest(format!(
+            "'{from}' and '{to}' must not contain one another"
+        )));
+    }
+    let clash = match (request.overwrite, request.merge) {
+        (true, true) => {
+            return Err(FileError::BadRequest(
+                "Pass overwrite or merge, not both".into(),
+            ));
+        }
+        (true, false) => Clash::Overwrite,
+        (false, true) => Clash::Merge,
+        (false, false) => Clash::Refuse,
+    };
+    let to_path = ObjectPath::from(to.as_str());
+    if clash == Clash::Refuse {
+        let indexed = !file::find_by_name_prefix(&ctx.db, &to, tenant.id())
+            .await?
+            .is_empty();
+        let stored = store
+            .list(Some(&to_path))
+            .next()
+            .await
+            .transpose()
+            .map_err(FileError::StorageError)?
+            .is_some();
+        if indexed || stored {
+            return Err(FileError::Rejected {
+                status: StatusCode::CONFLICT,
+                code: "destination_not_empty".into(),
+                message: format!("'{to}' already holds files; pass overwrite or merge"),
+                details: None,
+            });
+        }
+    }
+
+    let rows = file::find_by_name_prefix(&ctx.db, &from, tenant.id()).await?;
+    let (mut moved, mut skipped) = (0, 0);
+    let mut failures = Vec::new();
+    let mut kept = HashSet::new();
+    let mut results = futures_util::stream::iter(rows)
+        .map(|f| {
+            let (ctx, store, actor, tenant) = (&ctx, store.as_ref(), &actor, &tenant);
+            let target = format!("{to}{}", &f.name[from.len()..]);
+            async move {
+                let result = move_indexed_file(ctx, store, tenant, &f, &target, clash, actor).await;
+                (f.name, result)
+            }
+        })
+        .buffer_unordered(FOLDER_CONCURRENCY);
+    while let Some((name, result)) = results.next().await {
+        match result {
+            Ok(MoveOutcome::Moved) => moved += 1,
+            Ok(MoveOutcome::Skipped) => {
+                skipped += 1;
+                kept.insert(name);
+            }
+            Err(e) => {
+                tracing::warn!(file_name = %name, error = %e, "folder move left a file in place");
+                failures.push(FolderFailure {
+                    key: name.clone(),
+                    error: e.to_string(),
+                });
+                kept.insert(name);
+            }
+        }
+    }
+    drop(results);
+
+    // Whatever is left and not kept back above belongs to no file.
+    let from_path = ObjectPath::from(from.as_str());
+    let strays: Vec<ObjectPath> = store
+        .list(Some(&from_path))
+        .map_ok(|meta| meta.location)
+        .try_filter(|key| futures_util::future::ready(!owned_by_any(key.as_ref(), &kept)))
+        .try_collect()
+        .await
+        .map_err(FileError::StorageError)?;
+    let mut results = futures_util::stream::iter(strays)
+        .map(|source| {
+            let store = store.as_ref();
+            let target = ObjectPath::from(format!("{to}{}", &source.as_ref()[from.len()..]));
+            async move {
+                let result = move_object(store, &source, &target, clash).await;
+                (source, result)
+            }
+        })
+        .buffer_unordered(STORAGE_META_CONCURRENCY);
+    while let Some((source, result)) = results.next().await {
+        match result {
+            Ok(true) => moved += 1,
+            Ok(false) => skipped += 1,
+            Err(e) => failures.push(FolderFailure {
+                key: source.to_string(),
+                error: e.to_string(),
+            }),
+        }
+    }
+    drop(results);
+    failures.sort_by(|a, b| a.key.cmp(&b.key));
+
+    tracing::info!(from = %from, to = %to, moved, skipped, failed = failures.len(), "moved folder");
+    Ok(Json(FolderMoved {
+        from,
+        to,
+        moved,
+        skipped,
+        failures,
+    }))
+}
+
+/// Moves one file and everything derived from it to `target`, holding the
+/// upload locks of both names so no upload writes either meanwhile.
+pub(super) async fn move_indexed_file(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    tenant: &Tenant,
+    file: &file::Model,
+    target: &str,
+    clash: Clash,
+    actor: &Actor,
+) -> FileResult<MoveOutcome> {
+    let ttl = get_s3_config(ctx).upload_lock_ttl_seconds;
+    let mut locks = Vec::new();
+    for name in [file.name.as_str(), target] {
+        let key = upload_lock_key(tenant.id(), name);
+        match file_lock::acquire(&ctx.db, &key, ttl).await? {
+            Some(locked_at) => locks.push((key, locked_at)),
+            None => {
+                release_locks(ctx, locks).await;
+                return Err(file_locked(name).into());
+            }
+        }
+    }
+    let result = move_locked_file(ctx, store, file, target, clash, actor).await;
+    release_locks(ctx, locks).await;
+    if result.is_ok() {
+        schedule_content_indexing(ctx, &file.name).await;
+        schedule_content_indexing(ctx, target).await;
+    }
+    result
+}
+
+pub(super) async fn release_locks(
+    ctx: &AppContext,
+    locks: Vec<(String, sea_orm::prelude::DateTime)>,
+) {
+    for (key, locked_at) in locks {
+        if let Err(e) = file_lock::release(&ctx.db, &key, locked_at).await {
+            tracing::warn!(key = %key, error = %e, "failed to release upload lock");
+        }
+    }
+}
+
+pub(super) async fn move_locked_file(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    file: &file::Model,
+    target: &str,
+    clash: Clash,
+    actor: &Actor,
+) -> FileResult<MoveOutcome> {
+    let tenant = file.tenant_id.as_deref();
+    ensure_edit_unlocked(ctx, tenant, &file.name, actor.user_id).await?;
+    if file::find_by_name_for_tenant(&ctx.db, target, tenant)
+        .await?
+        .is_some()
+    {
+        match clash {
+            Clash::Merge => return Ok(MoveOutcome::Skipped),
+            Clash::Overwrite | Clash::Refuse => {
+                remove_file(ctx, store, tenant, target, actor).await?
+            }
+        }
+    }
+
+    let moved = file::Model {
+        name: target.to_string(),
+        ..file.clone()
+    };
+    // The object the row points at must end up under `target`; the rest
+    // only if it exists.
+    let mut objects = vec![(latest_path(file), latest_path(&moved), !file.orphaned)];
+    if file.compressed {
+        objects.push((
+            ObjectPath::from(format!("{}{GZIP_SUFFIX}", file.name)),
+            ObjectPath::from(format!("{target}{GZIP_SUFFIX}")),
+            false,
+        ));
+    }
+    for v in 1..=file.version {
+        objects.push((version_path(file, v), version_path(&moved, v), false));
+    }
+    for snapshot in list_snapshots(store, &file.name).await? {
+        let timestamp = &snapshot.key[file.name.len() + SNAPSHOT_SEPARATOR.len()..];
+        objects.push((
+            ObjectPath::from(snapshot.key.as_str()),
+            snapshot_path(target, timestamp),
+            false,
+        ));
+    }
+    for key in [
+        thumbnails::thumbnail_key,
+        previews::preview_key,
+        |name: &str| format!("{QUARANTINE_PREFIX}{name}"),
+    ] {
+        objects.push((
+            ObjectPath::from(key(&file.name)),
+            ObjectPath::from(key(target)),
+            false,
+        ));
+    }
+    // A shared blob stays where it is.
+    objects.retain(|(source, target, _)| source != target);
+    objects.dedup_by(|a, b| a.0 == b.0);
+
+    if clash == Clash::Merge {
+        for (source, target, _) in &objects {
+            if store.head(target).await.is_ok()
+                && store.head(source).await.is_ok()
+                && !same_object(store, source, target)
+                    .await
+                    .map_err(FileError::StorageError)?
+            {
+                return Ok(MoveOutcome::Skipped);
+            }
+        }
+    }
+
+    let mut copied = Vec::new();
+    for (source, target, required) in &objects {
+        match copy_object(store, source, target, clash).await {
+            Ok(CopyOutcome::Copied) => copied.push((source, target)),
+            Ok(CopyOutcome::Present) => {}
+            Ok(CopyOutcome::Kept) => return Ok(MoveOutcome::Skipped),
+            Err(ObjectStoreError::NotFound { .. }) => {
+                if *required && store.head(target).await.is_err() {
+                    return Err(file_not_found(&file.name));
+                }
+            }
+            Err(e) => return Err(FileError::StorageError(e)),
+        }
+    }
+    for (source, target) in copied {
+        verify_copy(store, source, target).await?;
+    }
+    for (source, _, _) in &objects {
+        match store.delete(source).await {
+            Ok(()) | Err(ObjectStoreError::NotFound { .. }) => {}
+            Err(e) => return Err(FileError::StorageError(e)),
+        }
+    }
+
+    file::rename(&ctx.db, file.id, target).await?;
+    audit_writer::log(ctx, Entry::new(Operation::Move, Some(target), actor));
+    Ok(MoveOutcome::Moved)
+}
+
+pub(super) enum CopyOutcome {
+    Copied,
+    /// An identical object is already there, from an earlier run.
+    Present,
+    /// A different object is there and `merge` keeps it.
+    Kept,
+}
+
+/// Copies without replacing, unless `clash` says to. Errs with `NotFound`
+/// when the source is gone.
+pub(super) async fn copy_object(
+    store: &dyn ObjectStore,
+    source: &ObjectPath,
+    target: &ObjectPath,
+    clash: Clash,
+) -> object_store::Result<CopyOutcome> {
+    let exists = match store.copy_if_not_exists(source, target).await {
+        Ok(()) => return Ok(CopyOutcome::Copied),
+        Err(ObjectStoreError::AlreadyExists { .. }) => true,
+        // S3 needs a conditional copy set up for this; the upload locks
+        // held while moving keep the check and the copy together.
+        Err(ObjectStoreError::NotImplemented) => store.head(target).await.is_ok(),
+        Err(e) => return Err(e),
+    };
+    if !exists {
+        store.copy(source, target).await?;
+        return Ok(CopyOutcome::Copied);
+    }
+    if same_object(store, source, target).await? {
+        return Ok(CopyOutcome::Present);
+    }
+    match clash {
+        Clash::Merge => Ok(CopyOutcome::Kept),
+        Clash::Overwrite | Clash::Refuse => {
+            store.copy(source, target).await?;
+            Ok(CopyOutcome::Copied)
+        }
+    }
+}
+
+/// Moves an object no file owns. `false` when `merge` kept the target.
+pub(super) async fn move_object(
+    store: &dyn ObjectStore,
+    source: &ObjectPath,
+    target: &ObjectPath,
+    clash: Clash,
+) -> FileResult<bool> {
+    match copy_object(store, source, target, clash).await {
+        Ok(CopyOutcome::Copied) => verify_copy(store, source, target).await?,
+        Ok(CopyOutcome::Present) => {}
+        Ok(CopyOutcome::Kept) => return Ok(false),
+        // Moved by the file it belongs to meanwhile.
+        Err(ObjectStoreError::NotFound { .. }) => return Ok(true),
+        Err(e) => return Err(FileError::StorageError(e)),
+    }
+    match store.delete(source).await {
+        Ok(()) | Err(ObjectStoreError::NotFound { .. }) => Ok(true),
+        Err(e) => Err(FileError::StorageError(e)),
+    }
+}
+
+pub(super) async fn verify_copy(
+    store: &dyn ObjectStore,
+    source: &ObjectPath,
+    target: &ObjectPath,
+) -> FileResult<()> {
+    let (source_meta, target_meta) = (store.head(source).await, store.head(target).await);
+    match (source_meta, target_meta) {
+        (Ok(s), Ok(t)) if s.size == t.size => Ok(()),
+        (Err(e), _) | (_, Err(e)) => Err(FileError::StorageError(e)),
+        (Ok(s), Ok(t)) => Err(FileError::Internal(format!(
+            "Copy of '{source}' has {} bytes instead of {}",
+            t.size, s.size
+        ))),
+    }
+}
+
+/// Same size, and the same ETag or SHA-256, or failing both the same bytes.
+pub(super) async fn same_object(
+    store: &dyn ObjectStore,
+    a: &ObjectPath,
+    b: &ObjectPath,
+) -> object_store::Result<bool> {
+    let head = GetOptions {
+        head: true,
+        ..Default::default()
+    };
+    let (a_head, b_head) = (
+        store.get_opts(a, head.clone()).await?,
+        store.get_opts(b, head).await?,
+    );
+    if a_head.meta.size != b_head.meta.size {
+        return Ok(false);
+    }
+    if a_head.meta.e_tag.is_some() && a_head.meta.e_tag == b_head.meta.e_tag {
+        return Ok(true);
+    }
+    let sha256 = Attribute::Metadata("sha256".into());
+    if let (Some(x), Some(y)) = (
+        a_head.attributes.get(&sha256),
+        b_head.attributes.get(&sha256),
+    ) {
+        return Ok(x == y);
+    }
+    let a_bytes = store.get(a).await?.bytes().await?;
+    let b_bytes = store.get(b).await?.bytes().await?;
+    Ok(a_bytes == b_bytes)
+}
+
+/// Whether `key` is one of the files' own objects: the file, its gzipped
+/// copy or one of its snapshots.
+pub(super) fn owned_by_any(key: &str, files: &HashSet<String>) -> bool {
+    if files.contains(key) {
+        return true;
+    }
+    if let Some(name) = key.strip_suffix(GZIP_SUFFIX)
+        && files.contains(name)
+    {
+        return true;
+    }
+    key.rsplit_once(SNAPSHOT_SEPARATOR)
+        .is_some_and(|(name, ts)| valid_snapshot_timestamp(ts) && files.contains(name))
+}
+
+/// Deletes a file with every object derived from it. The row goes in one
+/// transaction with its audit entry.
+pub(super) async fn remove_file(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    tenant: Option<&str>,
+    file_name: &str,
+    actor: &Actor,
+) -> FileResult<()> {
+    let file_record = file::find_by_name_for_tenant(&ctx.db, file_name, tenant).await?;
+    if file_record.is_some() {
+        ensure_edit_unlocked(ctx, tenant, file_name, actor.user_id).await?;
+    }
+    storage_hooks::configured(ctx).pre_delete(file_name).await?;
+
+    // A refusal here, such as an object still under retention, keeps the
+    // file; past this point deletes are best-effort cleanup.
+    delete_object(store, &ObjectPath::from(file_name)).await?;
+    if let Some(f) = &file_record
+        && f.object_shard.is_some()
+    {
+        delete_object(store, &latest_path(f)).await?;
+    }
+
+    if let Some(f) = &file_record {
+        if f.compressed {
+            let _ = store
+                .delete(&ObjectPath::from(format!("{file_name}{GZIP_SUFFIX}")))
+                .await;
+        }
+        for v in 1..=f.version {
+            // A shared blob is only removed through `release_blob` below.
+            if v == 1 && f.blob_hash.is_some() {
+                continue;
+            }
+            let _ = store.delete(&version_path(f, v)).await;
+        }
+    }
+
+    for snapshot in list_snapshots(store, file_name).await? {
+        let _ = store.delete(&ObjectPath::from(snapshot.key)).await;
+    }
+    let _ = store
+        .delete(&ObjectPath::from(thumbnails::thumbnail_key(file_name)))
+        .await;
+    let _ = store
+        .delete(&ObjectPath::from(previews::preview_key(file_name)))
+        .await;
+    let _ = store
+        .delete(&ObjectPath::from(format!("{QUARANTINE_PREFIX}{file_name}")))
+        .await;
+
+    file::delete_by_name(&ctx.db, file_name, tenant).await?;
+    audit_writer::log(ctx, Entry::new(Operation::Delete, Some(file_name), actor));
+    if let Some(f) = &file_record {
+        let config = get_s3_config(ctx);
+        // A shared blob may still back other files; reconciliation removes
+        // it from the replica once it is gone from the bucket.
+        if config.replication.replicate_deletes
+            && f.replication_status.is_some()
+            && f.blob_hash.is_none()
+        {
+            let args = ReplicateFileArgs {
+                name: file_name.to_string(),
+                deleted_key: Some(latest_path(f).to_string()),
+            };
+            if let Err(e) = ReplicateFileWorker::perform_later(ctx, args).await {
+                tracing::warn!(file = %file_name, error = %e, "failed to schedule replica deletion");
+            }
+        }
+        let checksum = f.content_hash.clone();
+        notify_webhooks(
+            ctx,
+            &config,
+            FileEvent::Deleted,
+            f.size,
+            checksum,
+            file_name,
+        )
+        .await;
+    }
+
+    // The row is already gone, so a failure here can't be retried by the
+    // client; the blob keeps its count and is only leaked, never lost.
+    if let Some(hash) = file_record.and_then(|f| f.blob_hash)
+        && let Err(e) = release_blob(ctx, store, &hash).await
+    {
+        tracing::warn!(hash = %hash, error = %e, "failed to release blob");
+    }
+    schedule_content_indexing(ctx, file_name).await;
+
+    Ok(())
+}
+
+/// Deletes `location`, which may already be gone.
+pub(super) async fn delete_object(
+    store: &dyn ObjectStore,
+    location: &ObjectPath,
+) -> FileResult<()> {
+    match store.delete(location).await {
+        Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+        Err(e) => Err(FileError::StorageError(e)),
+    }
+}
@@ -0,0 +1,334 @@
+//! Middleware of the file routes: CORS, compression, transfer limits,
+//! signed downloads and download auditing.
+
+use super::*;
+
+pub(super) fn cors_layer(config: &S3Config) -> Option<CorsLayer> {
+    if config.cors_allowed_origins.is_empty() {
+        return None;
+    }
+
+    let origins = if config.cors_allowed_origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(config.cors_allowed_origins.iter().filter_map(|origin| {
+            HeaderValue::from_str(origin)
+                .inspect_err(|_| tracing::warn!(origin, "ignoring invalid CORS origin"))
+                .ok()
+        }))
+    };
+
+    let methods: Vec<Method> = config
+        .cors_allowed_methods
+        .iter()
+        .filter_map(|method| {
+            Method::from_bytes(method.to_uppercase().as_bytes())
+                .inspect_err(|_| tracing::warn!(method, "ignoring invalid CORS method"))
+                .ok()
+        })
+        .collect();
+
+    let headers = |names: &[String], kind: &'static str| -> Vec<HeaderName> {
+        names
+            .iter()
+            .filter_map(|name| {
+                HeaderName::from_bytes(name.as_bytes())
+                    .inspect_err(|_| tracing::warn!(name, kind, "ignoring invalid CORS header"))
+                    .ok()
+            })
+            .collect()
+    };
+    // With credentials a literal `*` would be taken as a header name, so the
+    // headers the preflight asks for are echoed back instead.
+    let allowed_headers = if !config.cors_allowed_headers.iter().any(|h| h == "*") {
+        AllowHeaders::list(headers(&config.cors_allowed_headers, "allowed"))
+    } else if config.cors_allow_credentials {
+        AllowHeaders::mirror_request()
+    } else {
+        AllowHeaders::any()
+    };
+    let exposed_headers = if config.cors_expose_headers.iter().any(|h| h == "*") {
+        ExposeHeaders::any()
+    } else {
+        ExposeHeaders::list(headers(&config.cors_expose_headers, "exposed"))
+    };
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(methods)
+            .allow_headers(allowed_headers)
+            .expose_headers(exposed_headers)
+            .allow_credentials(config.cors_allow_credentials)
+            .max_age(Duration::from_secs(u64::from(config.cors_max_age_seconds))),
+    )
+}
+
+/// Listings below this size aren't worth compressing.
+pub(super) const COMPRESSION_MIN_BYTES: u16 = 1024;
+
+/// Brotli, gzip or deflate for JSON responses only. Nothing is compressed
+/// unless `enabled`.
+pub(super) fn json_compression(enabled: bool) -> CompressionLayer<impl Predicate> {
+    let is_json = move |_: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions| {
+        enabled
+            && headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.starts_with("application/json"))
+    };
+
+    CompressionLayer::new()
+        .no_zstd()
+        .compress_when(SizeAbove::new(COMPRESSION_MIN_BYTES).and(is_json))
+}
+
+/// Media types that are compressed already, so compressing them again only
+/// costs CPU.
+pub(super) fn is_precompressed(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match essence.split_once('/') {
+        Some(("image", subtype)) => subtype != "svg+xml" && subtype != "bmp",
+        Some(("video" | "audio", _)) => true,
+        Some(("font", subtype)) => subtype.starts_with("woff"),
+        Some(("application", subtype)) => matches!(
+            subtype,
+            "zip"
+                | "gzip"
+                | "x-gzip"
+                | "x-bzip2"
+                | "x-xz"
+                | "zstd"
+                | "x-7z-compressed"
+                | "vnd.rar"
+                | "x-rar-compressed"
+                | "java-archive"
+                | "epub+zip"
+                | "pdf"
+        ),
+        _ => false,
+    }
+}
+
+/// Compression for file downloads, encoding the body as it streams.
+/// Objects served gzipped as stored and partial (`Content-Range`) responses
+/// are left alone by the layer itself; this also skips media that is
+/// compressed already.
+pub(super) fn download_compression(enabled: bool) -> CompressionLayer<impl Predicate> {
+    let worth_it = move |status: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions| {
+        enabled
+            && status != StatusCode::PARTIAL_CONTENT
+            && !headers
+                .get(header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(is_precompressed)
+    };
+
+    CompressionLayer::new()
+        .no_zstd()
+        .compress_when(SizeAbove::new(COMPRESSION_MIN_BYTES).and(worth_it))
+}
+
+/// One kind of transfer's `TransferLimit`, as route middleware sees it.
+#[derive(Clone)]
+pub(super) struct TransferGate {
+    pub(super) limit: Arc<TransferLimit>,
+    /// Names the transfers in the 503.
+    pub(super) transfers: &'static str,
+    pub(super) retry_after: Duration,
+}
+
+impl TransferGate {
+    pub(super) async fn enter(&self) -> std::result::Result<OwnedSemaphorePermit, FileError> {
+        self.limit.acquire().await.map_err(|_| FileError::Busy {
+            transfers: self.transfers,
+            retry_after: self.retry_after,
+        })
+    }
+}
+
+pub(super) struct TransferGates {
+    pub(super) uploads: TransferGate,
+    pub(super) downloads: TransferGate,
+}
+
+pub(super) static TRANSFER_GATES: OnceLock<TransferGates> = OnceLock::new();
+
+pub(super) fn transfer_gates(ctx: &AppContext) -> &'static TransferGates {
+    TRANSFER_GATES.get_or_init(|| {
+        let config = get_s3_config(ctx).concurrency;
+        let gate = |size, transfers| TransferGate {
+            limit: Arc::new(TransferLimit::new(
+                size,
+                config.when_busy,
+                Duration::from_secs(config.max_wait_seconds),
+            )),
+            transfers,
+            retry_after: Duration::from_secs(config.retry_after_seconds),
+        };
+        TransferGates {
+            uploads: gate(config.max_uploads, "uploads"),
+            downloads: gate(config.max_downloads, "downloads"),
+        }
+    })
+}
+
+/// Holds an upload slot while the handler reads the body and stores it.
+pub(super) async fn limit_uploads(
+    State(gate): State<TransferGate>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match gate.enter().await {
+        Ok(_slot) => next.run(request).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct DownloadSigning {
+    pub(super) secret: Option<String>,
+    pub(super) required: bool,
+}
+
+/// Checks the signature of a download that carries one. Without one, the
+/// download goes ahead unless `require_signed_downloads` asks for a token.
+pub(super) async fn verify_signed_token(
+    State(signing): State<DownloadSigning>,
+    FileName(file_name): FileName,
+    Query(params): Query<SignedDownloadParams>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> FileResult<Response> {
+    let invalid = |e: signed_urls::SignatureError| FileError::Rejected {
+        status: StatusCode::FORBIDDEN,
+        code: match e {
+            signed_urls::SignatureError::Expired => "signature_expired",
+            signed_urls::SignatureError::Invalid => "invalid_signature",
+        }
+        .into(),
+        message: format!("Refused '{file_name}': {e}"),
+        details: None,
+    };
+    match (params.token, params.expires) {
+        (None, None) => {
+            if signing.required {
+                auth::claims_from_headers(&headers)?;
+            }
+        }
+        (Some(token), Some(expires)) => {
+            let Some(secret) = signing.secret.as_deref() else {
+                return Err(invalid(signed_urls::SignatureError::Invalid));
+            };
+            signed_urls::verify(secret, &file_name, expires, &token, Utc::now().timestamp())
+                .map_err(invalid)?;
+        }
+        _ => {
+            return Err(FileError::BadRequest(
+                "A signed link needs both token and expires".into(),
+            ));
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+/// Audits a download when dropped, which is once its body has been sent or
+/// the client went away.
+pub(super) struct DownloadAudit {
+    pub(super) ctx: AppContext,
+    pub(super) entry: Option<Entry>,
+    pub(super) bytes: i64,
+}
+
+impl DownloadAudit {
+    pub(super) fn sent(&mut self, bytes: usize) {
+        self.bytes += bytes as i64;
+    }
+}
+
+impl Drop for DownloadAudit {
+    fn drop(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            entry.bytes = Some(self.bytes);
+            access_log::record(&self.ctx, &entry);
+            audit_writer::log(&self.ctx, entry);
+        }
+    }
+}
+
+/// Audits every download, refused ones included, with the bytes that went
+/// out. A signed link is named by its token, a download with a bearer
+/// token by that.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn audit_download(
+    State(ctx): State<AppContext>,
+    file_name: Result<Path<String>, PathRejection>,
+    Query(query): Query<HashMap<String, String>>,
+    remote_ip: RemoteIP,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
+    request: Request,
+    next: Next,
+) -> Response {
+    let user_id = auth::claims_from_headers(&headers)
+        .ok()
+        .and_then(|claims| claims.pid.parse().ok());
+    let mut actor = request_actor(&headers, user_id, client_ip(remote_ip, connect_info));
+    let operation = match query.get("token") {
+        Some(token) => {
+            actor.token_id = Some(auth::token_id(token));
+            Operation::LinkAccess
+        }
+        None => Operation::Download,
+    };
+    // A name that isn't UTF-8 is left to the handler's 400 unaudited.
+    let Ok(Path(file_name)) = file_name else {
+        return next.run(request).await;
+    };
+    let mut entry = Entry::new(operation, Some(&normalize_name(&file_name)), &actor);
+
+    let response = next.run(request).await;
+    let status = response.status();
+    entry.status_code = Some(status.as_u16());
+    if status.is_client_error() || status.is_server_error() {
+        entry = entry.failed(status.canonical_reason().unwrap_or("Download failed"));
+    }
+    let mut audit = DownloadAudit {
+        ctx,
+        entry: Some(entry),
+        bytes: 0,
+    };
+    let (parts, body) = response.into_parts();
+    let body = Body::from_stream(body.into_data_stream().map(move |chunk| {
+        if let Ok(chunk) = &chunk {
+            audit.sent(chunk.len());
+        }
+        chunk
+    }));
+    Response::from_parts(parts, body)
+}
+
+/// Holds a download slot until the response body has been sent, which is
+/// long after the handler returns.
+pub(super) async fn limit_downloads(
+    State(gate): State<TransferGate>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let slot = match gate.enter().await {
+        Ok(slot) => slot,
+        Err(e) => return e.into_response(),
+    };
+    let (parts, body) = next.run(request).await.into_parts();
+    let body = Body::from_stream(body.into_data_stream().map(move |chunk| {
+        let _slot = &slot;
+        chunk
+    }));
+    Response::from_parts(parts, body)
+}
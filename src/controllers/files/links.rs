@@ -0,0 +1,179 @@
+//! Signed and pre-signed links to a file.
+
+use super::*;
+
+#[utoipa::path(
+    get,
+    path = "/files/{file_name}/signed-url",
+    operation_id = "createSignedDownloadUrl",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included")),
+    responses(
+        (status = 200, description = "A download link that needs no token until it expires", body = SignedDownloadUrl),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "The file is quarantined or not yet scanned", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 501, description = "No `signing_secret` is configured", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn get_signed_url(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+) -> FileResult<Json<SignedDownloadUrl>> {
+    auth::claims_from_headers(&headers)?;
+    let config = get_s3_config(&ctx);
+    let Some(secret) = config.signing_secret.as_deref() else {
+        return Err(FileError::Rejected {
+            status: StatusCode::NOT_IMPLEMENTED,
+            code: "signing_unconfigured".into(),
+            message: "Signed download links need a signing_secret".into(),
+            details: None,
+        });
+    };
+    let record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_name))?;
+    ensure_servable(&record)?;
+
+    let expires_at = Utc::now() + Duration::from_secs(config.signed_url_ttl_seconds);
+    let expires = expires_at.timestamp();
+    let token = signed_urls::sign(secret, &file_name, expires);
+    Ok(Json(SignedDownloadUrl {
+        url: format!(
+            "/files/{}?token={token}&expires={expires}",
+            glacier::encode_segment(&file_name)
+        ),
+        expires_at: expires_at.to_rfc3339(),
+    }))
+}
+
+/// Signers for the buckets links were asked for so far, by tenant; the
+/// default bucket is under "".
+pub(super) static PRESIGNERS: Mutex<BTreeMap<String, Arc<AmazonS3>>> = Mutex::new(BTreeMap::new());
+
+/// What can pre-sign URLs to the tenant's bucket. `None` where storage
+/// doesn't hold objects the way clients are served them: other backends,
+/// and encrypted buckets.
+pub(super) fn presigner(config: &S3Config, tenant: Option<&str>) -> Result<Option<Arc<AmazonS3>>> {
+    let encrypted = config.encryption.keyring().map_err(Error::wrap)?.is_some();
+    if config.backend != "s3" || config.local_storage_path.is_some() || encrypted {
+        return Ok(None);
+    }
+    let key = tenant.unwrap_or_default();
+    if let Some(signer) = PRESIGNERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(key)
+    {
+        return Ok(Some(signer.clone()));
+    }
+    let signer = match tenant {
+        Some(tenant) => match config.tenants.get(tenant) {
+            Some(storage) => create_s3_store(&config.for_tenant(storage))?,
+            None => return Ok(None),
+        },
+        None => create_s3_store(config)?,
+    };
+    let mut signers = PRESIGNERS.lock().unwrap_or_else(|e| e.into_inner());
+    Ok(Some(
+        signers
+            .entry(key.to_string())
+            .or_insert_with(|| Arc::new(signer))
+            .clone(),
+    ))
+}
+
+/// Sends the client where it can fetch the file without this server in
+/// the way: a 302 to the CDN when one is configured, else a 307 to a
+/// pre-signed S3 URL, or on other backends to a signed `GET
+/// /files/{file_name}` link.
+#[utoipa::path(
+    get,
+    path = "/files/{file_name}/link",
+    operation_id = "getFileLink",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included"), LinkParams),
+    responses(
+        (status = 200, description = "The URL, for `redirect=false`", body = FileLink),
+        (status = 302, description = "To the file on the CDN"),
+        (status = 307, description = "To a URL that expires after `signed_url_ttl_seconds`"),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "The file is quarantined or not yet scanned", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 501, description = "No CDN, S3 or `signing_secret` to link to", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn link_file(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+    Query(params): Query<LinkParams>,
+) -> FileResult<Response> {
+    auth::claims_from_headers(&headers)?;
+    let config = get_s3_config(&ctx);
+    let record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id()).await?;
+    if let Some(record) = &record {
+        ensure_servable(record)?;
+    }
+    let path = record
+        .as_ref()
+        .map_or_else(|| ObjectPath::from(file_name.as_str()), latest_path);
+    match store.head(&path).await {
+        Ok(_) => {}
+        Err(object_store::Error::NotFound { .. }) => return Err(file_not_found(&file_name)),
+        Err(e) => return Err(FileError::StorageError(e)),
+    }
+
+    let ttl = Duration::from_secs(config.signed_url_ttl_seconds);
+    let (status, link) = if let Some(url) = cdn_url(&config, &path) {
+        let link = FileLink {
+            url,
+            expires_at: None,
+        };
+        (StatusCode::FOUND, link)
+    } else if let Some(signer) = presigner(&config, tenant.id())? {
+        let url = signer
+            .signed_url(Method::GET, &path, ttl)
+            .await
+            .map_err(FileError::StorageError)?;
+        let link = FileLink {
+            url: url.to_string(),
+            expires_at: Some((Utc::now() + ttl).to_rfc3339()),
+        };
+        (StatusCode::TEMPORARY_REDIRECT, link)
+    } else if let Some(secret) = config.signing_secret.as_deref() {
+        let expires_at = Utc::now() + ttl;
+        let expires = expires_at.timestamp();
+        let token = signed_urls::sign(secret, &file_name, expires);
+        let link = FileLink {
+            url: format!(
+                "/files/{}?token={token}&expires={expires}",
+                glacier::encode_segment(&file_name)
+            ),
+            expires_at: Some(expires_at.to_rfc3339()),
+        };
+        (StatusCode::TEMPORARY_REDIRECT, link)
+    } else {
+        return Err(FileError::Rejected {
+            status: StatusCode::NOT_IMPLEMENTED,
+            code: "link_unavailable".into(),
+            message: "No CDN, S3 bucket or signing_secret to link to".into(),
+            details: None,
+        });
+    };
+
+    if params.redirect == Some(false) {
+        return Ok(Json(link).into_response());
+    }
+    Response::builder()
+        .status(status)
+        .header(header::LOCATION, &link.url)
+        .body(Body::empty())
+        .map_err(|e| FileError::Internal(format!("Build response: {e}")))
+}
@@ -0,0 +1,936 @@
+//! Listing, searching and browsing files, and their tags.
+
+use super::*;
+
+/// What `GET /files` lists by. Ties keep upload order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortBy {
+    #[default]
+    Uploaded,
+    Name,
+    Size,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListFormat {
+    /// One array, sent once every file is ready.
+    #[default]
+    Json,
+    /// One file per line, each sent as soon as it is ready.
+    Ndjson,
+}
+
+/// Where a file falls in a listing sorted by `SortBy`. Cursors carry the
+/// key of the last file of a page.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SortKey {
+    Uploaded(i32),
+    Name(String, i32),
+    Size(i64, i32),
+}
+
+impl SortKey {
+    pub fn of(file: &FileInfo, sort_by: SortBy) -> Self {
+        match sort_by {
+            SortBy::Uploaded => Self::Uploaded(file.id),
+            SortBy::Name => Self::Name(file.name.clone(), file.id),
+            SortBy::Size => Self::Size(file.size, file.id),
+        }
+    }
+
+    pub(super) fn sort_by(&self) -> SortBy {
+        match self {
+            Self::Uploaded(_) => SortBy::Uploaded,
+            Self::Name(..) => SortBy::Name,
+            Self::Size(..) => SortBy::Size,
+        }
+    }
+}
+
+/// The query of `GET /files`. `tag` may repeat and `meta.<key>` may be
+/// given for any key; every filter given must match.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(try_from = "Vec<(String, String)>")]
+pub struct ListOptions {
+    /// Only names starting with this, folders included.
+    pub prefix: Option<String>,
+    pub sort_by: SortBy,
+    pub order: SortOrder,
+    /// Files per page. Set whenever a cursor is.
+    pub limit: Option<u64>,
+    /// The last file of the previous page.
+    pub cursor: Option<SortKey>,
+    pub min_size: Option<i64>,
+    pub max_size: Option<i64>,
+    pub uploaded_after: Option<chrono::DateTime<Utc>>,
+    pub uploaded_before: Option<chrono::DateTime<Utc>>,
+    /// Normalized, like the tags they are matched against.
+    pub tag_filter: BTreeSet<String>,
+    /// Custom metadata values, by key.
+    pub metadata: BTreeMap<String, String>,
+    /// `Ndjson` is also chosen by `Accept: application/x-ndjson`.
+    pub format: ListFormat,
+    /// Also list infected files. Admins only.
+    pub include_quarantined: bool,
+}
+
+impl TryFrom<Vec<(String, String)>> for ListOptions {
+    type Error = String;
+
+    fn try_from(params: Vec<(String, String)>) -> std::result::Result<Self, String> {
+        let mut options = Self::default();
+        let mut cursor = None;
+        for (key, value) in params {
+            let invalid = || format!("Invalid {key}");
+            match key.as_str() {
+                "prefix" => options.prefix = Some(value),
+                "sort_by" => {
+                    options.sort_by = match value.as_str() {
+                        "uploaded" => SortBy::Uploaded,
+                        "name" => SortBy::Name,
+                        "size" => SortBy::Size,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "order" => {
+                    options.order = match value.as_str() {
+                        "asc" => SortOrder::Asc,
+                        "desc" => SortOrder::Desc,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "format" => {
+                    options.format = match value.as_str() {
+                        "json" => ListFormat::Json,
+                        "ndjson" => ListFormat::Ndjson,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "include_quarantined" => {
+                    options.include_quarantined = value.parse().map_err(|_| invalid())?
+                }
+                "limit" => options.limit = Some(value.parse().map_err(|_| invalid())?),
+                "cursor" => cursor = Some(decode_list_cursor(&value).ok_or_else(invalid)?),
+                "min_size" => options.min_size = Some(value.parse().map_err(|_| invalid())?),
+                "max_size" => options.max_size = Some(value.parse().map_err(|_| invalid())?),
+                "uploaded_after" | "uploaded_before" => {
+                    let at = chrono::DateTime::parse_from_rfc3339(&value)
+                        .map_err(|_| format!("{key} must be an RFC 3339 timestamp"))?
+                        .to_utc();
+                    if key == "uploaded_after" {
+                        options.uploaded_after = Some(at);
+                    } else {
+                        options.uploaded_before = Some(at);
+                    }
+                }
+                "tag" => {
+                    let tag = file_tag::normalize(&value).ok_or_else(|| {
+                        format!("Tags must be 1 to {} characters", file_tag::MAX_TAG_LEN)
+                    })?;
+                    options.tag_filter.insert(tag);
+                }
+                _ => {
+                    if let Some(field) = key.strip_prefix("meta.") {
+                        options.metadata.insert(field.to_string(), value);
+                    }
+                }
+            }
+        }
+        if let Some(cursor) = &cursor
+            && cursor.sort_by() != options.sort_by
+        {
+            return Err("Cursor is from a listing sorted differently".into());
+        }
+        options.limit = options
+            .limit
+            .or(cursor.as_ref().map(|_| DEFAULT_LIST_LIMIT))
+            .map(|l| l.clamp(1, MAX_LIST_LIMIT));
+        options.cursor = cursor;
+        Ok(options)
+    }
+}
+
+impl ListOptions {
+    pub(super) fn matches(&self, file: &FileInfo) -> bool {
+        let uploaded = || {
+            chrono::DateTime::parse_from_rfc3339(&file.created_at)
+                .ok()
+                .map(|at| at.to_utc())
+        };
+        let custom = file.metadata.as_ref().map(|m| &m.custom);
+        self.prefix
+            .as_ref()
+            .is_none_or(|p| file.name.starts_with(p))
+            && self.min_size.is_none_or(|min| file.size >= min)
+            && self.max_size.is_none_or(|max| file.size <= max)
+            && self
+                .uploaded_after
+                .is_none_or(|after| uploaded().is_some_and(|at| at > after))
+            && self
+                .uploaded_before
+                .is_none_or(|before| uploaded().is_some_and(|at| at < before))
+            && self.tag_filter.iter().all(|tag| file.tags.contains(tag))
+            && self
+                .metadata
+                .iter()
+                .all(|(k, v)| custom.and_then(|c| c.get(k)) == Some(v))
+    }
+}
+
+/// The files `options` selects, in its order and past its cursor. Tags must
+/// already be attached. The page limit is left to the caller.
+pub fn apply_list_options(
+    files: impl IntoIterator<Item = FileInfo>,
+    options: &ListOptions,
+) -> impl Iterator<Item = FileInfo> {
+    let mut keyed: Vec<(SortKey, FileInfo)> = files
+        .into_iter()
+        .filter(|f| options.matches(f))
+        .map(|f| (SortKey::of(&f, options.sort_by), f))
+        .filter(|(key, _)| match (&options.cursor, options.order) {
+            (None, _) => true,
+            (Some(cursor), SortOrder::Asc) => key > cursor,
+            (Some(cursor), SortOrder::Desc) => key < cursor,
+        })
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| match options.order {
+        SortOrder::Asc => a.cmp(b),
+        SortOrder::Desc => b.cmp(a),
+    });
+    keyed.into_iter().map(|(_, f)| f)
+}
+
+/// Lists visible files, filtered and sorted by `ListOptions`.
+#[utoipa::path(
+    get,
+    path = "/files",
+    operation_id = "listFiles",
+    tag = "files",
+    params(
+        ("prefix" = Option<String>, Query, description = "Only names starting with this, folders included"),
+        ("sort_by" = Option<String>, Query, description = "`uploaded` (the default), `name` or `size`"),
+        ("order" = Option<String>, Query, description = "`asc` (the default) or `desc`"),
+        ("min_size" = Option<i64>, Query, description = "Only files of at least this many bytes"),
+        ("max_size" = Option<i64>, Query, description = "Only files of at most this many bytes"),
+        ("uploaded_after" = Option<String>, Query, description = "Only files uploaded after this RFC 3339 time"),
+        ("uploaded_before" = Option<String>, Query, description = "Only files uploaded before this RFC 3339 time"),
+        ("tag" = Option<String>, Query, description = "Only files carrying this tag; may repeat"),
+        ("meta.{key}" = Option<String>, Query, description = "Only files whose custom metadata `key` has this value"),
+        ("limit" = Option<u64>, Query, description = "Files per page, at most 1000. Without it or `cursor`, every file is listed"),
+        ("cursor" = Option<String>, Query, description = "The `X-Next-Cursor` of the previous page, listed with the same `sort_by`"),
+        ("format" = Option<String>, Query, description = "`json` (the default) or `ndjson`, which `Accept: application/x-ndjson` also asks for"),
+        ("include_quarantined" = Option<bool>, Query, description = "Also list files the virus scan found infected. Needs the `admin` scope"),
+    ),
+    responses(
+        (status = 200, description = "Visible files. As NDJSON, one file per line; a line of `{\"error\": ErrorBody}` ends a listing that failed part way",
+            content(([FileInfo] = "application/json"), (FileInfo = "application/x-ndjson")),
+            headers(("X-Next-Cursor" = String, description = "Set while more pages remain"))),
+        (status = 400, description = "Invalid filter or cursor"),
+        (status = 401, description = "`include_quarantined` without a token", body = ErrorBody),
+        (status = 403, description = "`include_quarantined` without the `admin` scope", body = ErrorBody),
+    ),
+)]
+pub async fn get_all_files(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    Query(options): Query<ListOptions>,
+) -> FileResult<Response> {
+    if options.include_quarantined {
+        auth::require_scope(&headers, "admin")?;
+    }
+    // The index narrows the rows by what it can match cheaply;
+    // `apply_list_options` does the rest.
+    let filter = file::ListFilter {
+        metadata: (!options.metadata.is_empty())
+            .then(|| serde_json::json!({ "custom": options.metadata })),
+        tags: options.tag_filter.iter().cloned().collect(),
+        tenant_id: tenant.0,
+        name_prefix: options.prefix.clone(),
+        include_quarantined: options.include_quarantined,
+        after_id: match options.cursor {
+            Some(SortKey::Uploaded(id)) if options.order == SortOrder::Asc => Some(id),
+            _ => None,
+        },
+        limit: None,
+    };
+    let db_files = file::find_all_with_authors(&ctx.db, filter).await?;
+
+    let config = get_s3_config(&ctx);
+    let paths: HashMap<i32, ObjectPath> = db_files
+        .iter()
+        .map(|(f, _)| (f.id, latest_path(f)))
+        .collect();
+    let mut files: Vec<FileInfo> = db_files
+        .into_iter()
+        .map(|(f, author)| file_info(&config, f, author.as_ref()))
+        .collect();
+    attach_tags(&ctx, &mut files).await?;
+
+    let listed = apply_list_options(files, &options);
+    // One extra file tells whether there is another page.
+    let mut files: Vec<FileInfo> = match options.limit {
+        Some(limit) => listed.take(limit as usize + 1).collect(),
+        None => listed.collect(),
+    };
+    let next_cursor = match options.limit {
+        Some(limit) if files.len() as u64 > limit => {
+            files.truncate(limit as usize);
+            files
+                .last()
+                .map(|f| encode_list_cursor(&SortKey::of(f, options.sort_by)))
+        }
+        _ => None,
+    };
+
+    let paths: Vec<ObjectPath> = files.iter().map(|f| paths[&f.id].clone()).collect();
+    let mut response = if options.format == ListFormat::Ndjson || accepts_ndjson(&headers) {
+        ndjson_listing(store, files, paths)
+    } else {
+        attach_storage_meta(store.as_ref(), &mut files, &paths).await;
+        Json(files).into_response()
+    };
+    if let Some(cursor) = next_cursor
+        && let Ok(value) = HeaderValue::from_str(&cursor)
+    {
+        response.headers_mut().insert(NEXT_CURSOR, value);
+    }
+    Ok(response)
+}
+
+pub(super) const NDJSON: &str = "application/x-ndjson";
+
+pub(super) fn accepts_ndjson(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|range| {
+            range
+                .split(';')
+                .next()
+                .is_some_and(|essence| essence.trim().eq_ignore_ascii_case(NDJSON))
+        })
+}
+
+/// Sends each file as soon as storage has answered for it, in listing
+/// order. Missing objects are listed without their storage metadata, like
+/// in the JSON array; any other storage failure ends the listing with an
+/// `{"error": ...}` line, since the status went out with the first.
+pub(super) fn ndjson_listing(
+    store: Arc<dyn ObjectStore>,
+    files: Vec<FileInfo>,
+    paths: Vec<ObjectPath>,
+) -> Response {
+    let lines = futures_util::stream::iter(files.into_iter().zip(paths))
+        .map(move |(mut info, path)| {
+            let store = store.clone();
+            async move {
+                match store.head(&path).await {
+                    Ok(meta) => info.set_storage_meta(meta),
+                    Err(ObjectStoreError::NotFound { .. }) => {}
+                    Err(e) => return Err(FileError::StorageError(e)),
+                }
+                Ok(info)
+            }
+        })
+        .buffered(STORAGE_META_CONCURRENCY)
+        .scan(false, |ended, result| {
+            if *ended {
+                return std::future::ready(None);
+            }
+            let line = match result {
+                Ok(info) => serde_json::to_vec(&info),
+                Err(e) => {
+                    *ended = true;
+                    tracing::warn!(error = %e, "listing ended early");
+                    serde_json::to_vec(&serde_json::json!({ "error": e.body() }))
+                }
+            };
+            let mut line = line.unwrap_or_default();
+            line.push(b'\n');
+            std::future::ready(Some(Ok::<_, std::convert::Infallible>(Bytes::from(line))))
+        });
+
+    ([(header::CONTENT_TYPE, NDJSON)], Body::from_stream(lines)).into_response()
+}
+
+/// Files read from the index at a time by `GET /files/export`.
+pub(super) const EXPORT_PAGE_SIZE: u64 = 500;
+
+/// The whole index as NDJSON, one `FileInfo` per line in upload order. It is
+/// read a page at a time while the body is sent, so the catalog is never
+/// held in memory; a failure part way ends it with an `{"error": ...}` line.
+#[utoipa::path(
+    get,
+    path = "/files/export",
+    operation_id = "exportCatalog",
+    tag = "files",
+    responses(
+        (status = 200, description = "Every visible file, one per line; a line of `{\"error\": ErrorBody}` ends a catalog that failed part way",
+            content((FileInfo = "application/x-ndjson")),
+            headers(("Content-Disposition" = String, description = "`attachment; filename=\"catalog.ndjson\"`"))),
+    ),
+)]
+pub async fn export_catalog(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+) -> Response {
+    let config = get_s3_config(&ctx);
+    // The id of the last file sent, or `None` once the catalog has ended.
+    let pages = futures_util::stream::unfold(Some(0), move |after_id| {
+        let (ctx, config, tenant_id) = (ctx.clone(), config.clone(), tenant.0.clone());
+        async move {
+            let filter = file::ListFilter {
+                tenant_id,
+                after_id: Some(after_id?),
+                limit: Some(EXPORT_PAGE_SIZE),
+                ..Default::default()
+            };
+            let page = match catalog_page(&ctx, &config, filter).await {
+                Ok(page) => page,
+                Err(e) => {
+                    tracing::warn!(error = %e, "catalog export ended early");
+                    let mut line = serde_json::to_vec(&serde_json::json!({ "error": e.body() }))
+                        .unwrap_or_default();
+                    line.push(b'\n');
+                    return Some((Bytes::from(line), None));
+                }
+            };
+            let last = page.last().map(|f| f.id)?;
+            let mut lines = Vec::new();
+            for info in &page {
+                if serde_json::to_writer(&mut lines, info).is_ok() {
+                    lines.push(b'\n');
+                }
+            }
+            let next = (page.len() as u64 == EXPORT_PAGE_SIZE).then_some(last);
+            Some((Bytes::from(lines), next))
+        }
+    })
+    .map(Ok::<_, std::convert::Infallible>);
+
+    (
+        [
+            (header::CONTENT_TYPE, NDJSON),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"catalog.ndjson\"",
+            ),
+        ],
+        Body::from_stream(pages),
+    )
+        .into_response()
+}
+
+pub(super) async fn catalog_page(
+    ctx: &AppContext,
+    config: &S3Config,
+    filter: file::ListFilter,
+) -> FileResult<Vec<FileInfo>> {
+    let mut files: Vec<FileInfo> = file::find_all_with_authors(&ctx.db, filter)
+        .await?
+        .into_iter()
+        .map(|(f, author)| file_info(config, f, author.as_ref()))
+        .collect();
+    attach_tags(ctx, &mut files).await?;
+    Ok(files)
+}
+
+pub(super) const DEFAULT_LIST_LIMIT: u64 = 100;
+pub(super) const MAX_LIST_LIMIT: u64 = 1000;
+pub(super) const NEXT_CURSOR: HeaderName = HeaderName::from_static("x-next-cursor");
+
+/// Cursors name the last file of a page. Clients should treat them as opaque.
+pub(super) fn encode_list_cursor(key: &SortKey) -> String {
+    let cursor = match key {
+        SortKey::Uploaded(id) => format!("after:{id}"),
+        SortKey::Name(name, id) => format!("name:{id}:{name}"),
+        SortKey::Size(size, id) => format!("size:{id}:{size}"),
+    };
+    BASE64_URL_SAFE_NO_PAD.encode(cursor)
+}
+
+pub(super) fn decode_list_cursor(cursor: &str) -> Option<SortKey> {
+    let cursor = String::from_utf8(BASE64_URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+    if let Some(id) = cursor.strip_prefix("after:") {
+        return Some(SortKey::Uploaded(id.parse().ok()?));
+    }
+    let (kind, rest) = cursor.split_once(':')?;
+    let (id, value) = rest.split_once(':')?;
+    let id = id.parse().ok()?;
+    match kind {
+        "name" => Some(SortKey::Name(value.to_string(), id)),
+        "size" => Some(SortKey::Size(value.parse().ok()?, id)),
+        _ => None,
+    }
+}
+
+pub(super) async fn attach_tags(ctx: &AppContext, files: &mut [FileInfo]) -> Result<()> {
+    let ids: Vec<i32> = files.iter().map(|f| f.id).collect();
+    let mut tags = file_tag::find_by_file_ids(&ctx.db, &ids).await?;
+    for f in files {
+        f.tags = tags.remove(&f.id).unwrap_or_default();
+    }
+    Ok(())
+}
+
+/// HEAD requests in flight at once while filling in a listing.
+pub(super) const STORAGE_META_CONCURRENCY: usize = 16;
+
+/// Sets what storage reports for the object at each of `paths` on the file
+/// listed at the same index. Files storage can't answer for keep those
+/// fields unset; the listing itself comes from the index and doesn't fail.
+pub(super) async fn attach_storage_meta(
+    store: &dyn ObjectStore,
+    files: &mut [FileInfo],
+    paths: &[ObjectPath],
+) {
+    let metas: Vec<_> = futures_util::stream::iter(paths.to_vec())
+        .map(|path| async move { store.head(&path).await })
+        .buffered(STORAGE_META_CONCURRENCY)
+        .collect()
+        .await;
+    let mut failed = 0;
+    let mut last_error = None;
+    for (info, meta) in files.iter_mut().zip(metas) {
+        match meta {
+            Ok(meta) => info.set_storage_meta(meta),
+            Err(ObjectStoreError::NotFound { .. }) => {}
+            Err(e) => {
+                failed += 1;
+                last_error = Some(e);
+            }
+        }
+    }
+    if let Some(error) = last_error {
+        tracing::warn!(failed, error = %error, "listing without storage metadata");
+    }
+}
+
+/// Case-insensitive substring search over full file names, folders included.
+/// Results come in upload order; `next_page_token` is set while more remain.
+#[utoipa::path(
+    get,
+    path = "/files/search",
+    operation_id = "searchFiles",
+    tag = "files",
+    params(SearchParams),
+    responses(
+        (status = 200, description = "Matching files", body = SearchResponse),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+    ),
+)]
+pub async fn search_files(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    Query(params): Query<SearchParams>,
+) -> FileResult<Json<SearchResponse>> {
+    let q = params.q.trim();
+    if q.chars().count() < MIN_SEARCH_QUERY_CHARS {
+        return Err(FileError::BadRequest(format!(
+            "Search query must be at least {MIN_SEARCH_QUERY_CHARS} characters"
+        )));
+    }
+    let after_id = params
+        .page_token
+        .as_deref()
+        .map(|t| t.parse::<i32>())
+        .transpose()
+        .map_err(|_| FileError::BadRequest("Invalid page_token".into()))?;
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .clamp(1, MAX_SEARCH_LIMIT);
+
+    // One extra row tells whether there is another page.
+    let mut rows = file::search_by_name(&ctx.db, q, after_id, limit + 1, tenant.id()).await?;
+    let next_page_token = if rows.len() as u64 > limit {
+        rows.truncate(limit as usize);
+        rows.last().map(|(f, _)| f.id.to_string())
+    } else {
+        None
+    };
+
+    let config = get_s3_config(&ctx);
+    let paths: Vec<ObjectPath> = rows.iter().map(|(f, _)| latest_path(f)).collect();
+    let mut files: Vec<FileInfo> = rows
+        .into_iter()
+        .map(|(f, author)| file_info(&config, f, author.as_ref()))
+        .collect();
+    attach_tags(&ctx, &mut files).await?;
+    attach_storage_meta(store.as_ref(), &mut files, &paths).await;
+
+    Ok(Json(SearchResponse {
+        files,
+        next_page_token,
+    }))
+}
+
+/// One level of the file tree, split on `/` like `list_with_delimiter`.
+///
+/// Folders come from the names in the index rather than from storage keys,
+/// which also hold deduplicated blobs, gzipped copies, snapshots and
+/// staged uploads under names of their own.
+#[utoipa::path(
+    get,
+    path = "/files/browse",
+    operation_id = "browseFiles",
+    tag = "files",
+    params(BrowseParams),
+    responses(
+        (status = 200, description = "The folder's subfolders and files, or the single file `path` names", body = FolderListing),
+        (status = 400, description = "Invalid path", body = ErrorBody),
+    ),
+)]
+pub async fn browse_files(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    Query(params): Query<BrowseParams>,
+) -> FileResult<Json<FolderListing>> {
+    let trimmed = params.path.trim_matches('/');
+    let folder = if trimmed.is_empty() {
+        String::new()
+    } else {
+        let segments = safe_path_segments(trimmed)
+            .ok_or_else(|| FileError::BadRequest(format!("Invalid path '{}'", params.path)))?;
+        format!("{}/", segments.join("/"))
+    };
+    let config = get_s3_config(&ctx);
+
+    if !params.path.ends_with('/')
+        && !trimmed.is_empty()
+        && let Some(found) =
+            file::find_by_name_for_tenant(&ctx.db, folder.trim_end_matches('/'), tenant.id())
+                .await?
+                .filter(|f| !f.orphaned)
+    {
+        let path = latest_path(&found);
+        let author = match found.author_id {
+            Some(id) => user::find_by_id(&ctx.db, id).await?,
+            None => None,
+        };
+        let mut objects = vec![file_info(&config, found, author.as_ref())];
+        attach_tags(&ctx, &mut objects).await?;
+        attach_storage_meta(store.as_ref(), &mut objects, &[path]).await;
+        return Ok(Json(FolderListing {
+            path: objects[0].name.clone(),
+            common_prefixes: Vec::new(),
+            objects,
+            prefix_count: 0,
+            object_count: 1,
+        }));
+    }
+
+    let filter = file::ListFilter {
+        tenant_id: tenant.0,
+        name_prefix: (!folder.is_empty()).then(|| folder.clone()),
+        ..Default::default()
+    };
+    let rows = file::find_all_with_authors(&ctx.db, filter).await?;
+    let mut prefixes: BTreeMap<String, (i64, usize)> = BTreeMap::new();
+    let mut direct = Vec::new();
+    for (f, author) in rows {
+        let Some(rest) = f.name.strip_prefix(&folder) else {
+            continue;
+        };
+        match rest.split_once('/') {
+            Some((sub, _)) => {
+                let totals = prefixes.entry(format!("{folder}{sub}/")).or_default();
+                totals.0 += f.size;
+                totals.1 += 1;
+            }
+            None => direct.push((f, author)),
+        }
+    }
+    direct.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+
+    let paths: Vec<ObjectPath> = direct.iter().map(|(f, _)| latest_path(f)).collect();
+    let mut objects: Vec<FileInfo> = direct
+        .into_iter()
+        .map(|(f, author)| file_info(&config, f, author.as_ref()))
+        .collect();
+    attach_tags(&ctx, &mut objects).await?;
+    attach_storage_meta(store.as_ref(), &mut objects, &paths).await;
+    let common_prefixes: Vec<FolderEntry> = prefixes
+        .into_iter()
+        .map(|(prefix, (size, count))| FolderEntry {
+            prefix,
+            total_size: params.with_sizes.then_some(size),
+            file_count: params.with_sizes.then_some(count),
+        })
+        .collect();
+
+    Ok(Json(FolderListing {
+        path: folder,
+        prefix_count: common_prefixes.len(),
+        object_count: objects.len(),
+        common_prefixes,
+        objects,
+    }))
+}
+
+/// Full-text search over the contents of indexed documents, best match first.
+#[utoipa::path(
+    get,
+    path = "/files/search/content",
+    operation_id = "searchFileContents",
+    tag = "files",
+    params(ContentSearchParams),
+    responses(
+        (status = 200, description = "Files whose content matches", body = ContentSearchResponse),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+    ),
+)]
+pub async fn search_file_contents(
+    State(ctx): State<AppContext>,
+    Query(params): Query<ContentSearchParams>,
+) -> FileResult<Json<ContentSearchResponse>> {
+    let q = params.q.trim().to_string();
+    if q.is_empty() {
+        return Err(FileError::BadRequest(
+            "Search query must not be empty".into(),
+        ));
+    }
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .clamp(1, MAX_SEARCH_LIMIT) as usize;
+
+    let config = get_s3_config(&ctx);
+    let index = content_index::open(&config.content_index_dir)?;
+    let results = tokio::task::spawn_blocking(move || index.search(&q, limit))
+        .await
+        .map_err(|e| FileError::Internal(format!("Content index task failed: {e}")))??;
+
+    Ok(Json(ContentSearchResponse { results }))
+}
+
+pub(super) fn normalize_tag(tag: &str) -> Result<String> {
+    file_tag::normalize(tag).ok_or_else(|| {
+        unprocessable(
+            "invalid_tag",
+            &format!("Tags must be 1 to {} characters", file_tag::MAX_TAG_LEN),
+        )
+    })
+}
+
+/// All tags in use with the number of files carrying each.
+#[utoipa::path(
+    get,
+    path = "/files/tags",
+    operation_id = "listTags",
+    tag = "files",
+    responses(
+        (status = 200, description = "Every tag with the number of files carrying it", body = [TagCount]),
+    ),
+)]
+pub async fn list_tags(State(ctx): State<AppContext>) -> FileResult<Json<Vec<TagCount>>> {
+    let counts = file_tag::counts(&ctx.db).await?;
+    Ok(Json(
+        counts
+            .into_iter()
+            .map(|(tag, count)| TagCount { tag, count })
+            .collect(),
+    ))
+}
+
+/// Adds tags to a file and returns all of its tags.
+#[utoipa::path(
+    post,
+    path = "/files/{file_name}/tags",
+    operation_id = "addFileTags",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included")),
+    request_body(content = AddTagsRequest),
+    responses(
+        (status = 200, description = "All tags of the file", body = [String]),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn add_file_tags(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+    Json(req): Json<AddTagsRequest>,
+) -> FileResult<Json<Vec<String>>> {
+    auth::claims_from_headers(&headers)?;
+
+    let record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_name))?;
+
+    let tags: BTreeSet<String> = req
+        .tags
+        .iter()
+        .map(|t| normalize_tag(t))
+        .collect::<Result<_>>()?;
+    file_tag::add(&ctx.db, record.id, &tags.into_iter().collect::<Vec<_>>()).await?;
+
+    Ok(Json(file_tag::find_by_file_id(&ctx.db, record.id).await?))
+}
+
+/// Removes one tag from a file and returns the tags it has left.
+#[utoipa::path(
+    delete,
+    path = "/files/{file_name}/tags/{tag}",
+    operation_id = "removeFileTag",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included"), ("tag" = String, Path, description = "Tag to remove")),
+    responses(
+        (status = 200, description = "Remaining tags of the file", body = [String]),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn remove_file_tag(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    Path((file_name, tag)): Path<(String, String)>,
+) -> FileResult<Json<Vec<String>>> {
+    let file_name = checked_name(&file_name)?;
+    auth::claims_from_headers(&headers)?;
+
+    let record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_name))?;
+
+    if !file_tag::remove(&ctx.db, record.id, &normalize_tag(&tag)?).await? {
+        return Err(file_not_found(&file_name));
+    }
+
+    Ok(Json(file_tag::find_by_file_id(&ctx.db, record.id).await?))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DuplicatesParams {
+    /// Only groups of at least this many files. At least 2, the default.
+    pub min_count: Option<usize>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DuplicateGroup {
+    pub sha256: String,
+    /// Oldest first.
+    pub files: Vec<FileInfo>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DuplicatesResponse {
+    /// Largest reclaimable space first.
+    pub groups: Vec<DuplicateGroup>,
+    /// Files left out because neither storage nor the index knows their hash.
+    pub skipped_without_hash: usize,
+}
+
+/// Groups visible files by the SHA-256 their object was stored with, and
+/// returns the groups with more than one member. Reads every object's
+/// metadata, so it takes a while on a large bucket.
+#[utoipa::path(
+    get,
+    path = "/files/duplicates",
+    operation_id = "findDuplicates",
+    tag = "files",
+    params(DuplicatesParams),
+    responses(
+        (status = 200, description = "Files with identical content", body = DuplicatesResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "Missing the `admin` scope", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn find_duplicates(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    Query(params): Query<DuplicatesParams>,
+) -> FileResult<Json<DuplicatesResponse>> {
+    auth::require_scope(&headers, "admin")?;
+    let min_count = params.min_count.unwrap_or(2).max(2);
+
+    let filter = file::ListFilter {
+        tenant_id: tenant.0,
+        ..Default::default()
+    };
+    let rows = file::find_all_with_authors(&ctx.db, filter).await?;
+    // Uploads from before the attribute was written only have their first
+    // version's hash in the index.
+    let lookups: Vec<(ObjectPath, Option<String>)> = rows
+        .iter()
+        .map(|(f, _)| {
+            (
+                latest_path(f),
+                f.content_hash.clone().filter(|_| f.version == 1),
+            )
+        })
+        .collect();
+    let hashes: Vec<Option<String>> = futures_util::stream::iter(lookups)
+        .map(|(path, indexed)| {
+            let store = store.clone();
+            async move {
+                let options = GetOptions {
+                    head: true,
+                    ..Default::default()
+                };
+                let stored = match store.get_opts(&path, options).await {
+                    Ok(result) => result
+                        .attributes
+                        .get(&Attribute::Metadata("sha256".into()))
+                        .map(|v| v.to_string()),
+                    Err(ObjectStoreError::NotFound { .. }) => None,
+                    Err(e) => return Err(FileError::StorageError(e)),
+                };
+                Ok(stored.or(indexed))
+            }
+        })
+        .buffered(STORAGE_META_CONCURRENCY)
+        .try_collect()
+        .await?;
+
+    let config = get_s3_config(&ctx);
+    let mut skipped_without_hash = 0;
+    let mut by_hash: HashMap<String, Vec<FileInfo>> = HashMap::new();
+    for ((f, author), hash) in rows.into_iter().zip(hashes) {
+        match hash {
+            Some(hash) => {
+                by_hash
+                    .entry(hash)
+                    .or_default()
+                    .push(file_info(&config, f, author.as_ref()))
+            }
+            None => skipped_without_hash += 1,
+        }
+    }
+    let mut groups: Vec<DuplicateGroup> = by_hash
+        .into_iter()
+        .filter(|(_, files)| files.len() >= min_count)
+        .map(|(sha256, files)| DuplicateGroup { sha256, files })
+        .collect();
+    groups.sort_by_key(|g| {
+        let wasted = g.files.iter().skip(1).map(|f| f.size).sum::<i64>();
+        (Reverse(wasted), g.sha256.clone())
+    });
+
+    Ok(Json(DuplicatesResponse {
+        groups,
+        skipped_without_hash,
+    }))
+}
@@ -0,0 +1,198 @@
+//! Edit locks, which keep a file to one editor while they work on it.
+
+use super::*;
+
+pub(super) fn edit_lock_ttl(config: &S3Config, params: &EditLockParams) -> FileResult<i32> {
+    let ttl = params.ttl_seconds.unwrap_or(config.edit_lock_ttl_seconds);
+    if !(1..=config.edit_lock_max_ttl_seconds).contains(&ttl) {
+        return Err(FileError::BadRequest(format!(
+            "ttl_seconds must be between 1 and {}",
+            config.edit_lock_max_ttl_seconds
+        )));
+    }
+    Ok(ttl)
+}
+
+pub(super) async fn edit_lock_info(
+    ctx: &AppContext,
+    file_name: &str,
+    lock: file_edit_lock::Model,
+) -> FileResult<EditLock> {
+    let holder = user::find_by_id(&ctx.db, lock.holder_id).await?;
+    Ok(EditLock {
+        file_name: file_name.to_string(),
+        holder: LockHolder {
+            id: lock.holder_id,
+            login: holder.map(|u| u.login),
+        },
+        acquired_at: lock.acquired_at.and_utc().to_rfc3339(),
+        expires_at: lock.expires_at().and_utc().to_rfc3339(),
+    })
+}
+
+pub(super) fn not_edit_locked(file_name: &str) -> FileError {
+    FileError::Rejected {
+        status: StatusCode::NOT_FOUND,
+        code: "not_locked".into(),
+        message: format!("You hold no edit lock on '{file_name}'"),
+        details: None,
+    }
+}
+
+/// Takes an edit lock on a file, so that others' uploads and deletes of it
+/// are refused with a 423 until it is released or goes stale. Taking a
+/// lock one already holds extends it.
+#[utoipa::path(
+    post,
+    path = "/files/{file_name}/lock",
+    operation_id = "lockFile",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included"), EditLockParams),
+    responses(
+        (status = 200, description = "The lock, now held by the caller", body = EditLock),
+        (status = 400, description = "`ttl_seconds` out of range", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 423, description = "Someone else holds the lock; `details` names them", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn lock_file(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+    Query(params): Query<EditLockParams>,
+) -> FileResult<Json<EditLock>> {
+    let author = token_author(&ctx, &headers).await?;
+    file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_name))?;
+    let ttl = edit_lock_ttl(&get_s3_config(&ctx), &params)?;
+    let key = upload_lock_key(tenant.id(), &file_name);
+
+    // A lock released or gone stale between the calls is tried once more.
+    for _ in 0..2 {
+        let lock = match file_edit_lock::acquire(&ctx.db, &key, author.id, ttl).await? {
+            Some(lock) => Some(lock),
+            None => file_edit_lock::refresh(&ctx.db, &key, author.id, ttl).await?,
+        };
+        if let Some(lock) = lock {
+            return Ok(Json(edit_lock_info(&ctx, &file_name, lock).await?));
+        }
+        ensure_edit_unlocked(&ctx, tenant.id(), &file_name, Some(author.id)).await?;
+    }
+    Err(file_locked(&file_name).into())
+}
+
+#[utoipa::path(
+    get,
+    path = "/files/{file_name}/lock",
+    operation_id = "getFileLock",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included")),
+    responses(
+        (status = 200, description = "Whether the file is locked for editing, and by whom", body = EditLockStatus),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn get_file_lock(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+) -> FileResult<Json<EditLockStatus>> {
+    auth::claims_from_headers(&headers)?;
+    file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_name))?;
+    let key = upload_lock_key(tenant.id(), &file_name);
+    let lock = match file_edit_lock::find_active(&ctx.db, &key).await? {
+        Some(lock) => Some(edit_lock_info(&ctx, &file_name, lock).await?),
+        None => None,
+    };
+    Ok(Json(EditLockStatus {
+        locked: lock.is_some(),
+        lock,
+    }))
+}
+
+/// Restarts the holder's lock from now, for clients that keep an editor
+/// open longer than one TTL.
+#[utoipa::path(
+    patch,
+    path = "/files/{file_name}/lock",
+    operation_id = "refreshFileLock",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included"), EditLockParams),
+    responses(
+        (status = 200, description = "The refreshed lock", body = EditLock),
+        (status = 400, description = "`ttl_seconds` out of range", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "The caller holds no live lock on the file", body = ErrorBody),
+        (status = 423, description = "Someone else holds the lock", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn refresh_file_lock(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+    Query(params): Query<EditLockParams>,
+) -> FileResult<Json<EditLock>> {
+    let author = token_author(&ctx, &headers).await?;
+    let ttl = edit_lock_ttl(&get_s3_config(&ctx), &params)?;
+    let key = upload_lock_key(tenant.id(), &file_name);
+    match file_edit_lock::refresh(&ctx.db, &key, author.id, ttl).await? {
+        Some(lock) => Ok(Json(edit_lock_info(&ctx, &file_name, lock).await?)),
+        None => {
+            ensure_edit_unlocked(&ctx, tenant.id(), &file_name, Some(author.id)).await?;
+            Err(not_edit_locked(&file_name))
+        }
+    }
+}
+
+/// Releases the caller's lock. An admin may release anyone's, for a holder
+/// who left without doing so.
+#[utoipa::path(
+    delete,
+    path = "/files/{file_name}/lock",
+    operation_id = "unlockFile",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included")),
+    responses(
+        (status = 200, description = "The file is no longer locked", body = EditLockStatus),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "The caller holds no live lock on the file", body = ErrorBody),
+        (status = 423, description = "Someone else holds the lock", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn unlock_file(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+) -> FileResult<Json<EditLockStatus>> {
+    let claims = auth::claims_from_headers(&headers)?;
+    let key = upload_lock_key(tenant.id(), &file_name);
+    let holder = if auth::require_scope(&headers, "admin").is_ok() {
+        None
+    } else {
+        let user_id: i32 = claims.pid.parse().map_err(|_| FileError::Unauthorized)?;
+        ensure_edit_unlocked(&ctx, tenant.id(), &file_name, Some(user_id)).await?;
+        Some(user_id)
+    };
+    if file_edit_lock::find_active(&ctx.db, &key).await?.is_none()
+        || !file_edit_lock::release(&ctx.db, &key, holder).await?
+    {
+        return Err(not_edit_locked(&file_name));
+    }
+    Ok(Json(EditLockStatus {
+        locked: false,
+        lock: None,
+    }))
+}
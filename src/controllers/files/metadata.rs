@@ -0,0 +1,345 @@
+//! Reading and editing a file's attributes.
+
+use super::*;
+
+/// Reads the stored attributes of an object without transferring its body.
+///
+/// object_store can't read S3 object tagging, so `tags` holds the key-value
+/// tags kept in the index (the file's custom metadata) instead.
+#[utoipa::path(
+    get,
+    path = "/files/{file_name}/metadata",
+    operation_id = "getObjectMetadata",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included")),
+    responses(
+        (status = 200, description = "Attributes stored with the object", body = ObjectMetadataResponse),
+        (status = 404, description = "No such file", body = ErrorBody),
+    ),
+)]
+pub async fn get_file_metadata(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    FileName(file_name): FileName,
+) -> FileResult<Json<ObjectMetadataResponse>> {
+    Ok(Json(
+        read_object_metadata(&ctx, store.as_ref(), &tenant, file_name).await?,
+    ))
+}
+
+pub(super) async fn read_object_metadata(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    tenant: &Tenant,
+    file_name: String,
+) -> Result<ObjectMetadataResponse> {
+    let record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id()).await?;
+    let path = record
+        .as_ref()
+        .map_or_else(|| ObjectPath::from(file_name.as_str()), latest_path);
+
+    let options = GetOptions {
+        head: true,
+        ..Default::default()
+    };
+    let result = store.get_opts(&path, options).await.map_err(|e| match e {
+        ObjectStoreError::NotFound { .. } => Error::NotFound,
+        _ => Error::wrap(e),
+    })?;
+
+    let mut attributes = BTreeMap::new();
+    let mut content_type = None;
+    let mut content_encoding = None;
+    for (attribute, value) in &result.attributes {
+        match attribute {
+            Attribute::ContentType => content_type = Some(value.to_string()),
+            Attribute::ContentEncoding => content_encoding = Some(value.to_string()),
+            Attribute::Metadata(key) => {
+                attributes.insert(key.to_string(), value.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    // object_store's head doesn't surface the encryption headers.
+    let config = get_s3_config(ctx);
+    let encryption = match glacier_bucket(&config) {
+        Ok(bucket) => bucket.encryption(path.as_ref()).await?,
+        Err(_) => None,
+    };
+
+    let stored_metadata: Option<FileMetadata> = record
+        .as_ref()
+        .and_then(|f| f.metadata.clone())
+        .and_then(|m| serde_json::from_value(m).ok());
+    let sha256 = attributes.get("sha256").cloned().or_else(|| {
+        record
+            .as_ref()
+            .filter(|f| f.version == 1)
+            .and_then(|f| f.content_hash.clone())
+    });
+
+    Ok(ObjectMetadataResponse {
+        content_type: content_type.unwrap_or_else(|| {
+            mime_guess::from_path(&file_name)
+                .first_or_octet_stream()
+                .to_string()
+        }),
+        name: file_name,
+        key: result.meta.location.to_string(),
+        size: result.meta.size as u64,
+        content_encoding,
+        e_tag: result.meta.e_tag,
+        last_modified: result.meta.last_modified.to_rfc3339(),
+        sha256,
+        encryption,
+        attributes,
+        tags: stored_metadata.map(|m| m.custom).unwrap_or_default(),
+        replication: record.and_then(|f| {
+            Some(ReplicationState {
+                status: f.replication_status?,
+                last_error: f.replication_error,
+            })
+        }),
+    })
+}
+
+/// `FileMetadata` fields that can be corrected after upload.
+pub(super) const MUTABLE_METADATA_FIELDS: [&str; 3] = ["title", "author", "description"];
+/// Describe the content itself, so they only change with a new upload.
+pub(super) const IMMUTABLE_METADATA_FIELDS: [&str; 2] = ["sha256", "size"];
+
+/// Merges a partial `{title, author, description}` body (`null` clears a
+/// field) into the object's attributes and the index.
+///
+/// object_store's copy can't replace S3 metadata, so the object is rewritten
+/// in place instead. Shared dedup blobs carry no per-file attributes, so for
+/// those only the index changes.
+#[utoipa::path(
+    patch,
+    path = "/files/{file_name}/metadata",
+    operation_id = "updateObjectMetadata",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included")),
+    request_body(content = Object, description = "Fields to set; `null` removes one"),
+    responses(
+        (status = 200, description = "The object's attributes after the update", body = ObjectMetadataResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "Missing the `files:write` scope", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn update_file_metadata(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+    Json(patch): Json<serde_json::Map<String, serde_json::Value>>,
+) -> FileResult<Json<ObjectMetadataResponse>> {
+    auth::require_scope(&headers, "files:write")?;
+
+    let mut changes = Vec::with_capacity(patch.len());
+    for (key, value) in patch {
+        if IMMUTABLE_METADATA_FIELDS.contains(&key.as_str()) {
+            return Err(
+                unprocessable("immutable_field", &format!("'{key}' cannot be changed")).into(),
+            );
+        }
+        if !MUTABLE_METADATA_FIELDS.contains(&key.as_str()) {
+            return Err(FileError::BadRequest(format!(
+                "Unknown metadata field '{key}'"
+            )));
+        }
+        let value = match value {
+            serde_json::Value::Null => None,
+            serde_json::Value::String(v) => Some(v),
+            _ => {
+                return Err(FileError::BadRequest(format!(
+                    "'{key}' must be a string or null"
+                )));
+            }
+        };
+        changes.push((key, value));
+    }
+
+    let record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_name))?;
+
+    let mut metadata: FileMetadata = record
+        .metadata
+        .clone()
+        .and_then(|m| serde_json::from_value(m).ok())
+        .unwrap_or_default();
+    for (key, value) in &changes {
+        let field = match key.as_str() {
+            "title" => &mut metadata.title,
+            "author" => &mut metadata.author,
+            _ => &mut metadata.description,
+        };
+        *field = value.clone();
+    }
+
+    if record.blob_hash.is_none() || record.version > 1 {
+        let user_id = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.strip_prefix("Bearer ").unwrap_or(v))
+            .and_then(|token| crate::controllers::auth::decode_token(token).ok())
+            .and_then(|claims| claims.pid.parse().ok());
+        ensure_edit_unlocked(&ctx, tenant.id(), &file_name, user_id).await?;
+        // The object is rewritten whole, so no upload may land meanwhile.
+        let config = get_s3_config(&ctx);
+        holding_upload_lock(&ctx, &config, tenant.id(), &file_name, async {
+            let path = latest_path(&record);
+            let current = store.get(&path).await.map_err(FileError::StorageError)?;
+
+            let mut attributes = current.attributes.clone();
+            for (key, value) in changes {
+                let attribute = Attribute::Metadata(key.into());
+                match value {
+                    Some(value) => attributes.insert(attribute, value.into()),
+                    None => attributes.remove(&attribute),
+                };
+            }
+
+            let bytes = current.bytes().await.map_err(FileError::StorageError)?;
+            store
+                .put_opts(
+                    &path,
+                    bytes.into(),
+                    PutOptions {
+                        attributes,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .map_err(FileError::StorageError)?;
+            Ok::<_, FileError>(())
+        })
+        .await??;
+    }
+
+    file::update_metadata(&ctx.db, record.id, serde_json::to_value(&metadata)?).await?;
+
+    Ok(Json(
+        read_object_metadata(&ctx, store.as_ref(), &tenant, file_name).await?,
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/files/{file_name}/meta",
+    operation_id = "getFileMeta",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included")),
+    responses(
+        (status = 200, description = "Storage details and the index row", body = FileDetails),
+        (status = 404, description = "No such file", body = ErrorBody),
+    ),
+)]
+pub async fn get_file_meta(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    FileName(file_name): FileName,
+) -> FileResult<Json<FileDetails>> {
+    let record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id()).await?;
+    let path = record
+        .as_ref()
+        .map_or_else(|| ObjectPath::from(file_name.as_str()), latest_path);
+
+    let meta = store.head(&path).await.map_err(FileError::StorageError)?;
+
+    let (checksum, version_count, file) = match record {
+        Some(f) => {
+            let version_count = file_version::count_by_file_id(&ctx.db, f.id).await?;
+            let author = match f.author_id {
+                Some(id) => user::find_by_id(&ctx.db, id).await?,
+                None => None,
+            };
+            let checksum = f.content_hash.clone().filter(|_| f.version == 1);
+            let tags = file_tag::find_by_file_id(&ctx.db, f.id).await?;
+            let mut info = FileInfo {
+                tags,
+                ..file_info(&get_s3_config(&ctx), f, author.as_ref())
+            };
+            info.set_storage_meta(meta.clone());
+            (checksum, version_count, Some(info))
+        }
+        None => (None, 0, None),
+    };
+
+    Ok(Json(FileDetails {
+        content_type: mime_guess::from_path(&file_name)
+            .first_or_octet_stream()
+            .to_string(),
+        name: file_name,
+        key: meta.location.to_string(),
+        size: meta.size as u64,
+        e_tag: meta.e_tag,
+        last_modified: meta.last_modified.to_rfc3339(),
+        checksum,
+        version_count,
+        file,
+    }))
+}
+
+/// Sets or, with a `null` value, removes custom metadata keys of a file.
+#[utoipa::path(
+    patch,
+    path = "/files/{file_name}/meta",
+    operation_id = "updateFileMeta",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included")),
+    request_body(content = BTreeMap<String, Option<String>>, description = "Custom metadata to set; `null` removes a key"),
+    responses(
+        (status = 200, description = "The updated file", body = FileInfo),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 422, description = "Invalid metadata", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn update_file_meta(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+    Json(changes): Json<BTreeMap<String, Option<String>>>,
+) -> FileResult<Json<FileInfo>> {
+    auth::claims_from_headers(&headers)?;
+
+    let record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_name))?;
+
+    let mut metadata: FileMetadata = record
+        .metadata
+        .clone()
+        .and_then(|m| serde_json::from_value(m).ok())
+        .unwrap_or_default();
+    for (key, value) in changes {
+        match value {
+            Some(value) => metadata.custom.insert(key, value),
+            None => metadata.custom.remove(&key),
+        };
+    }
+    metadata.validate()?;
+
+    let updated =
+        file::update_metadata(&ctx.db, record.id, serde_json::to_value(&metadata)?).await?;
+    let author = match updated.author_id {
+        Some(id) => user::find_by_id(&ctx.db, id).await?,
+        None => None,
+    };
+
+    Ok(Json(file_info(
+        &get_s3_config(&ctx),
+        updated,
+        author.as_ref(),
+    )))
+}
@@ -0,0 +1,1274 @@
+use async_compression::tokio::{bufread::GzipDecoder, write::GzipEncoder};
+use async_zip::{
+    Compression, ZipEntryBuilder, base::read::mem::ZipFileReader, tokio::write::ZipFileWriter,
+};
+use axum::{
+    Json,
+    body::{Body, Bytes, HttpBody},
+    extract::{
+        ConnectInfo, DefaultBodyLimit, Extension, FromRequestParts, Multipart, Path, Query,
+        Request, State,
+        multipart::{Field, MultipartError},
+        rejection::PathRejection,
+    },
+    http::{
+        Extensions, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Version, header,
+        request::Parts,
+    },
+    middleware::{self, Next},
+    response::{
+        Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::{delete, get, head, patch, post, put},
+};
+use base64::{
+    Engine,
+    prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD},
+};
+use chrono::Utc;
+use futures_util::{AsyncReadExt, AsyncWriteExt, Stream, StreamExt, TryStreamExt};
+use loco_rs::{
+    controller::{ErrorDetail, Routes, middleware::remote_ip::RemoteIP},
+    prelude::*,
+};
+use object_store::{
+    Attribute, Attributes, BackoffConfig, ClientOptions, Error as ObjectStoreError, GetOptions,
+    GetResult, GetResultPayload, ObjectMeta, ObjectStore, PutMultipartOpts, PutOptions, PutResult,
+    RetryConfig,
+    aws::{AmazonS3, AmazonS3Builder, Checksum},
+    buffered::BufWriter,
+    memory::InMemory,
+    multipart::{MultipartStore, PartId},
+    path::Path as ObjectPath,
+    signer::Signer,
+};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _, DuplexStream},
+    sync::{OwnedSemaphorePermit, broadcast::error::RecvError},
+};
+use tokio_util::io::{ReaderStream, StreamReader};
+use tower_http::{
+    compression::{
+        CompressionLayer,
+        predicate::{Predicate, SizeAbove},
+    },
+    cors::{AllowHeaders, AllowOrigin, CorsLayer, ExposeHeaders},
+    limit::RequestBodyLimitLayer,
+    set_header::SetResponseHeaderLayer,
+};
+use unicode_normalization::UnicodeNormalization as _;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    access_log::{self, AccessLogger},
+    audit_writer,
+    cache_store::{CacheStore, DownloadCache, MemoryCache},
+    circuit_store::{self, CircuitPolicy, CircuitState, CircuitStore},
+    content_index::{self, ContentHit, ContentIndex},
+    controllers::auth,
+    encryption::{EncryptedStore, Keyring},
+    errors::{ConfigError, ErrorBody, FileError, FileResult, StorageFailure},
+    events::{self, ProgressEvent, ProgressKind},
+    glacier::{self, RestoreRequested},
+    local_store::LocalStore,
+    models::{
+        blob, chunked_upload, chunked_upload_part, file,
+        file_audit_log::{self, Actor, Entry, Operation},
+        file_download, file_edit_lock, file_lock, file_tag, file_version, tus_upload,
+        upload_idempotency_key, user,
+    },
+    multipart_gc::{self, BucketClient, Sweep},
+    object_lock::{LockMode, LockedBucket, LockedS3, ObjectLock},
+    path_strategy::{self, PathStrategy},
+    previews::{self, PdfRenderer, Pdftoppm},
+    remote_fetch::{self, FetchError, FetchPolicy},
+    replication::{self, Reconciliation, ReplicationStatus},
+    retry_store::{self, RetryPolicy, RetryStore},
+    scanner::{self, Clamd, ScanStatus, Scanner},
+    signed_urls, storage_hooks,
+    tar_stream::{self, TarEntry},
+    thumbnails,
+    timeout_store::TimeoutStore,
+    transfer_limit::{TransferLimit, WhenBusy},
+    upload_pipeline::{
+        ChecksumStep, ClientDigest, CompressStep, DigestAlgorithm, Finished, HookStep,
+        SanitizeStep, StepError, UploadContext, UploadPipeline, UploadStep, is_text_like,
+    },
+    webhooks::{self, FileEvent, WebhookPayload, WebhookTarget},
+    workers::{
+        deliver_webhook::{DeliverWebhookArgs, DeliverWebhookWorker},
+        expire_tus_uploads::{ExpireTusUploadsArgs, ExpireTusUploadsWorker},
+        generate_preview::{GeneratePreviewArgs, GeneratePreviewWorker},
+        generate_thumbnail::{GenerateThumbnailArgs, GenerateThumbnailWorker},
+        index_content::{IndexContentArgs, IndexContentWorker},
+        prune_versions::{PruneVersionsArgs, PruneVersionsWorker},
+        replicate_file::{ReplicateFileArgs, ReplicateFileWorker},
+        scan_file::{ScanFileArgs, ScanFileWorker},
+    },
+};
+
+mod archive;
+mod audit;
+mod backend;
+mod chunked;
+mod config;
+mod derived;
+mod download;
+mod folders;
+mod layers;
+mod links;
+mod listing;
+mod locks;
+mod metadata;
+mod notify;
+mod restore;
+mod scan;
+mod stats;
+mod sync;
+mod tenancy;
+mod tus;
+mod upload;
+mod versions;
+mod write;
+
+pub use self::{
+    archive::*, audit::*, backend::*, chunked::*, config::*, derived::*, download::*, folders::*,
+    links::*, listing::*, locks::*, metadata::*, notify::*, restore::*, scan::*, stats::*, sync::*,
+    tenancy::*, tus::*, upload::*, versions::*,
+};
+use self::{layers::*, write::*};
+
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const IDEMPOTENT_REPLAY_HEADER: &str = "Idempotent-Replayed";
+const DEDUPLICATED_HEADER: &str = "X-Deduplicated";
+const UPLOAD_CHECKSUM_HEADER: &str = "X-Upload-Checksum";
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateWithVersionRequest {
+    pub version: i32,
+    pub size: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FileVersionInfo {
+    pub id: i32,
+    pub version: i32,
+    pub size: i64,
+    pub author: AuthorInfo,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FileInfo {
+    pub id: i32,
+    pub name: String,
+    pub size: i64,
+    pub author: Option<AuthorInfo>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub version: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<FileMetadata>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scan_status: Option<String>,
+    /// Where the CDN serves the current content, when `cdn_base_url` is set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cdn_url: Option<String>,
+    /// Guessed from the name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// What storage reports for the current content; unset where it wasn't asked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e_tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    /// The bucket's own version ID, on buckets with versioning enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_id: Option<String>,
+    /// Digests the client sent with the current content, all of which it
+    /// matched; hex by algorithm.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<String, String>,
+}
+
+impl FileInfo {
+    pub fn new(file: file::Model, author: Option<&user::Model>) -> Self {
+        Self {
+            content_type: Some(
+                mime_guess::from_path(&file.name)
+                    .first_or_octet_stream()
+                    .to_string(),
+            ),
+            id: file.id,
+            name: file.name,
+            size: file.size,
+            author: author.map(|a| AuthorInfo {
+                id: a.id,
+                login: a.login.clone(),
+            }),
+            created_at: file.created_at.and_utc().to_rfc3339(),
+            updated_at: file.updated_at.and_utc().to_rfc3339(),
+            version: file.version,
+            checksums: file
+                .checksums
+                .filter(|_| file.version == 1)
+                .and_then(|c| serde_json::from_value(c).ok())
+                .unwrap_or_default(),
+            metadata: file.metadata.and_then(|m| serde_json::from_value(m).ok()),
+            tags: Vec::new(),
+            scan_status: file.scan_status,
+            cdn_url: None,
+            e_tag: None,
+            last_modified: None,
+            version_id: None,
+        }
+    }
+
+    fn set_storage_meta(&mut self, meta: ObjectMeta) {
+        self.e_tag = meta.e_tag;
+        self.last_modified = Some(meta.last_modified.to_rfc3339());
+        self.version_id = meta.version;
+    }
+}
+
+/// Descriptive fields a client can attach to an upload via the `metadata` part.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct FileMetadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Free-form tags such as `case_id`, set with `meta.<key>` parts or `PATCH /files/{file_name}/meta`.
+    /// Kept in the index only: S3 caps user metadata at 2 KB.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub custom: BTreeMap<String, String>,
+    /// Key of the generated thumbnail. Set by the thumbnail worker, never by clients.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
+    /// Key of the first-page preview of a PDF. Set by the preview worker.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>,
+    /// Why no preview could be rendered, so the PDF isn't retried on every request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview_error: Option<String>,
+}
+
+const MAX_CUSTOM_METADATA_KEYS: usize = 20;
+const MAX_CUSTOM_METADATA_KEY_BYTES: usize = 128;
+const MAX_CUSTOM_METADATA_VALUE_BYTES: usize = 1024;
+
+impl FileMetadata {
+    fn validate(&self) -> Result<()> {
+        if self.custom.len() > MAX_CUSTOM_METADATA_KEYS {
+            return Err(unprocessable(
+                "too_many_metadata_keys",
+                &format!("At most {MAX_CUSTOM_METADATA_KEYS} custom metadata keys are allowed"),
+            ));
+        }
+        for (key, value) in &self.custom {
+            let valid_key = !key.is_empty()
+                && key.len() <= MAX_CUSTOM_METADATA_KEY_BYTES
+                && key
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+            if !valid_key {
+                return Err(unprocessable(
+                    "invalid_metadata_key",
+                    &format!("Invalid metadata key '{key}'"),
+                ));
+            }
+            if value.len() > MAX_CUSTOM_METADATA_VALUE_BYTES {
+                return Err(unprocessable(
+                    "metadata_value_too_large",
+                    &format!("Value of '{key}' exceeds {MAX_CUSTOM_METADATA_VALUE_BYTES} bytes"),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps the fields onto S3 user metadata (`x-amz-meta-<field>`).
+    pub(crate) fn to_attributes(&self) -> Attributes {
+        let mut attributes = Attributes::new();
+        for (key, value) in [
+            ("title", &self.title),
+            ("author", &self.author),
+            ("description", &self.description),
+        ] {
+            if let Some(value) = value {
+                attributes.insert(Attribute::Metadata(key.into()), value.clone().into());
+            }
+        }
+        attributes
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuthorInfo {
+    pub id: i32,
+    pub login: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UploadResponse {
+    pub uploaded: Vec<FileInfo>,
+    pub results: Vec<UploadOutcome>,
+}
+
+/// The body of a 400 from `POST /files`: the results when no file could be
+/// stored, or an error when the request itself is refused. Only documented.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum UploadFailure<'a> {
+    Results(UploadResponse),
+    Error(ErrorBody<'a>),
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadStatus {
+    Stored,
+    /// Same bytes were already stored, so the existing file is returned instead.
+    Deduplicated,
+    Rejected,
+    Failed,
+}
+
+/// What happened to one multipart field of an upload.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UploadOutcome {
+    pub name: String,
+    pub status: UploadStatus,
+    pub size: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e_tag: Option<String>,
+    /// Why a file was not stored, as the `code` of an error response.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<FileInfo>,
+    /// Snapshot key the overwritten object was copied to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_version: Option<String>,
+}
+
+impl UploadOutcome {
+    fn stored(stored: StoredFile) -> Self {
+        Self {
+            name: stored.info.name.clone(),
+            status: UploadStatus::Stored,
+            size: stored.info.size,
+            e_tag: stored.e_tag,
+            code: None,
+            reason: None,
+            file: Some(stored.info),
+            previous_version: stored.previous_version,
+        }
+    }
+
+    fn deduplicated(existing: FileInfo) -> Self {
+        Self {
+            name: existing.name.clone(),
+            status: UploadStatus::Deduplicated,
+            size: existing.size,
+            e_tag: None,
+            code: None,
+            reason: None,
+            file: Some(existing),
+            previous_version: None,
+        }
+    }
+
+    fn rejected(name: &str, size: i64, reason: &str) -> Self {
+        Self::unstored(name, size, UploadStatus::Rejected, "bad_request", reason)
+    }
+
+    fn failed(name: &str, size: i64, reason: &str) -> Self {
+        Self::unstored(name, size, UploadStatus::Failed, "internal_error", reason)
+    }
+
+    /// Client errors become `rejected`, anything else `failed`. The reason
+    /// is what an error response would say; what went wrong inside the
+    /// server is only logged.
+    fn from_error(name: &str, size: i64, err: Error) -> Self {
+        let err = FileError::from(err);
+        let status = if err.status().is_client_error() {
+            UploadStatus::Rejected
+        } else {
+            tracing::error!(file = %name, error = %err, code = err.code(), "upload of a file failed");
+            UploadStatus::Failed
+        };
+        let body = err.body();
+        Self::unstored(name, size, status, body.code, &body.message)
+    }
+
+    fn unstored(name: &str, size: i64, status: UploadStatus, code: &str, reason: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            size,
+            e_tag: None,
+            code: Some(code.to_string()),
+            reason: Some(reason.to_string()),
+            file: None,
+            previous_version: None,
+        }
+    }
+}
+
+pub(crate) struct StoredFile {
+    info: FileInfo,
+    /// The tenant whose bucket the file went to.
+    tenant_id: Option<String>,
+    content_hash: Option<String>,
+    e_tag: Option<String>,
+    previous_version: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UploadParams {
+    #[serde(default)]
+    pub extract: bool,
+    pub target_prefix: Option<String>,
+    /// Return an existing file with the same content instead of storing a copy.
+    #[serde(default)]
+    pub deduplicate: bool,
+}
+
+/// The multipart body of `POST /files`, as described in the OpenAPI spec.
+#[derive(ToSchema)]
+pub struct UploadForm {
+    /// One part per file, named after it.
+    #[schema(value_type = Vec<String>, format = Binary)]
+    pub file: Vec<Vec<u8>>,
+    /// JSON for the files after it.
+    pub metadata: Option<FileMetadata>,
+    /// A `meta.<key>` part sets one custom metadata value for the files after it.
+    #[schema(rename = "meta.{key}")]
+    pub meta: Option<String>,
+    /// Folder the files are stored under, such as `invoices/2024/`. Must
+    /// come before them.
+    pub destination_prefix: Option<String>,
+}
+
+/// The multipart body of `POST /files/sync`.
+#[derive(ToSchema)]
+pub struct SyncForm {
+    pub file_id: i32,
+    /// The version the client last saw; a newer one on the server is a conflict.
+    pub version: i32,
+    #[schema(value_type = String, format = Binary)]
+    pub file: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RevertRequest {
+    pub version: i32,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchDownloadRequest {
+    pub files: Vec<String>,
+    #[serde(default)]
+    pub ignore_missing: bool,
+    pub archive_name: Option<String>,
+}
+
+/// The caller's storage use, as returned by `GET /files/quota`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct QuotaResponse {
+    pub used_bytes: i64,
+    /// Unset when the caller has no quota.
+    pub limit_bytes: Option<i64>,
+    pub remaining_bytes: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StorageStatsParams {
+    /// Scan the bucket now instead of serving the cached usage. Admins only.
+    #[serde(default)]
+    pub refresh: bool,
+    /// How many of the largest objects to list; at most 100.
+    pub top: Option<usize>,
+}
+
+/// `count` through `newest` come from the index; `storage` from a scan of the bucket.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StorageStatsResponse {
+    pub count: i64,
+    pub total_bytes: i64,
+    pub avg_bytes: i64,
+    pub largest: Option<LargestFileInfo>,
+    pub newest: Option<NewestFileInfo>,
+    pub storage: StorageUsage,
+    /// Renames and multipart completes retried since the server started.
+    pub storage_retries: u64,
+    /// Buckets whose circuit breaker is currently open or probing.
+    pub storage_open_circuits: u64,
+    pub uploads: TransferCounts,
+    pub downloads: TransferCounts,
+    /// Absent unless `download_cache` is set up.
+    pub download_cache: Option<DownloadCacheStats>,
+    /// Absent while `memory_cache` is off.
+    pub memory_cache: Option<MemoryCacheStats>,
+}
+
+/// Plain downloads since the server started, and what the cache holds now.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DownloadCacheStats {
+    /// Served from disk.
+    pub hits: u64,
+    /// Fetched from storage.
+    pub misses: u64,
+    pub size_bytes: u64,
+}
+
+/// Like `DownloadCacheStats`, for the small objects held in memory.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MemoryCacheStats {
+    pub entries: u64,
+    pub size_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Hits over all plain downloads; 0 before the first.
+    pub hit_ratio: f64,
+}
+
+/// Transfers of one kind right now, against `settings.concurrency`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TransferCounts {
+    pub in_flight: usize,
+    /// Waiting for a slot.
+    pub queued: usize,
+}
+
+impl From<&TransferLimit> for TransferCounts {
+    fn from(limit: &TransferLimit) -> Self {
+        Self {
+            in_flight: limit.in_flight(),
+            queued: limit.queued(),
+        }
+    }
+}
+
+/// Every object in the bucket, versions, thumbnails and blobs included.
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct StorageUsage {
+    pub object_count: u64,
+    pub total_bytes: u64,
+    /// Keyed by the first path segment with its slash, or `/` for top-level objects.
+    pub by_prefix: BTreeMap<String, UsageTotals>,
+    /// Keyed by the content type guessed from the key.
+    pub by_content_type: BTreeMap<String, UsageTotals>,
+    /// Largest first.
+    pub largest_objects: Vec<ObjectSize>,
+    /// When the scan finished; it is reused for `STATS_CACHE_TTL`.
+    pub generated_at: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct UsageTotals {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ObjectSize {
+    pub key: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LargestFileInfo {
+    pub name: String,
+    pub size: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct NewestFileInfo {
+    pub name: String,
+    pub created_at: String,
+}
+
+/// What storage reports for one object, plus its index row when there is one.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FileDetails {
+    pub name: String,
+    /// Object key actually holding the current content.
+    pub key: String,
+    /// Stored size, which is the compressed size for gzipped uploads.
+    pub size: u64,
+    pub content_type: String,
+    pub e_tag: Option<String>,
+    pub last_modified: String,
+    /// SHA-256 of the content, known while the file is still at version 1.
+    pub checksum: Option<String>,
+    pub version_count: u64,
+    pub file: Option<FileInfo>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct VersionSnapshot {
+    pub timestamp: String,
+    pub key: String,
+    pub size: i64,
+    pub last_modified: String,
+}
+
+/// `GET /files/{file_name}/versions` lists snapshots when `enable_versioning` is set.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum FileVersionListing {
+    Recorded(Vec<FileVersionInfo>),
+    Snapshots(Vec<VersionSnapshot>),
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadParams {
+    pub version: Option<String>,
+    /// Overrides `download_mode`: a redirect to S3 instead of the content.
+    pub redirect: Option<bool>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ScanResult {
+    pub name: String,
+    /// `clean`, `infected`, `pending` or `unscanned`.
+    pub scan_status: Option<String>,
+    /// What clamd found in an infected file.
+    pub signature: Option<String>,
+}
+
+/// The query of a link from `GET /files/{file_name}/signed-url`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SignedDownloadParams {
+    /// Hex HMAC-SHA256 of the file name and `expires`.
+    pub token: Option<String>,
+    /// Unix seconds after which the link is refused.
+    pub expires: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SignedDownloadUrl {
+    /// Relative to the server, so it resolves against whatever proxy the
+    /// client reached it through.
+    pub url: String,
+    /// RFC 3339.
+    pub expires_at: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LinkParams {
+    /// `false` answers with the URL as JSON instead of redirecting to it.
+    pub redirect: Option<bool>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FileLink {
+    pub url: String,
+    /// RFC 3339. Unset for CDN URLs, which don't expire.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportParams {
+    /// Folder to export; every file when unset.
+    pub prefix: Option<String>,
+    /// Gzip the archive.
+    pub gzip: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ExportRequest {
+    pub files: Vec<String>,
+    /// Gzip the archive.
+    #[serde(default)]
+    pub gzip: bool,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EditLockParams {
+    /// How long the lock lasts without a refresh. The server's
+    /// `edit_lock_ttl_seconds` when unset.
+    pub ttl_seconds: Option<i32>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LockHolder {
+    pub id: i32,
+    /// Unset once the user is gone.
+    pub login: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EditLock {
+    pub file_name: String,
+    pub holder: LockHolder,
+    /// RFC 3339.
+    pub acquired_at: String,
+    /// RFC 3339. The lock goes stale then unless the holder refreshes it.
+    pub expires_at: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EditLockStatus {
+    pub locked: bool,
+    pub lock: Option<EditLock>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchParams {
+    pub q: String,
+    pub limit: Option<u64>,
+    pub page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BrowseParams {
+    /// A folder such as `projects/2024/`, with or without the trailing
+    /// slash, or a file name. Empty lists the root.
+    #[serde(default)]
+    pub path: String,
+    /// Adds the total size and file count under each subfolder.
+    #[serde(default)]
+    pub with_sizes: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FolderListing {
+    /// The folder listed, ending in `/` unless it is the root, or the file
+    /// name when `path` named a file.
+    pub path: String,
+    /// Subfolders, each ending in `/`.
+    pub common_prefixes: Vec<FolderEntry>,
+    /// Files directly in the folder.
+    pub objects: Vec<FileInfo>,
+    pub prefix_count: usize,
+    pub object_count: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FolderEntry {
+    pub prefix: String,
+    /// Bytes in the folder and every folder below it. Only with `with_sizes`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_size: Option<i64>,
+    /// Files in the folder and every folder below it. Only with `with_sizes`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_count: Option<usize>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchResponse {
+    pub files: Vec<FileInfo>,
+    pub next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ContentSearchParams {
+    pub q: String,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ContentSearchResponse {
+    pub results: Vec<ContentHit>,
+}
+
+const MIN_SEARCH_QUERY_CHARS: usize = 2;
+const DEFAULT_SEARCH_LIMIT: u64 = 50;
+const MAX_SEARCH_LIMIT: u64 = 200;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RestoreArchiveRequest {
+    /// How long S3 keeps the restored copy before it is archived-only again.
+    pub restore_days: u32,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AddTagsRequest {
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: i64,
+}
+
+/// Attributes stored with one object, as returned by `GET /files/{file_name}/metadata`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ObjectMetadataResponse {
+    pub name: String,
+    pub key: String,
+    pub size: u64,
+    pub content_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<String>,
+    pub e_tag: Option<String>,
+    pub last_modified: String,
+    pub sha256: Option<String>,
+    /// How S3 encrypted the object at rest; unset when it didn't, and on
+    /// backends other than S3.
+    pub encryption: Option<glacier::Encryption>,
+    /// Every `x-amz-meta-*` field stored on the object.
+    pub attributes: BTreeMap<String, String>,
+    pub tags: BTreeMap<String, String>,
+    /// Where the file's copy in the replica stands; unset when it isn't
+    /// replicated.
+    pub replication: Option<ReplicationState>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReplicationState {
+    /// `pending`, `synced` or `failed`.
+    pub status: String,
+    /// Why the last copy failed, while it is `failed`.
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StorageSyncParams {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StorageSyncResponse {
+    pub added: u64,
+    pub removed: u64,
+    pub unchanged: u64,
+    pub dry_run: bool,
+}
+
+fn file_not_found(file_name: &str) -> FileError {
+    FileError::NotFound(format!("File '{file_name}' not found"))
+}
+
+fn blob_path(hash: &str) -> ObjectPath {
+    ObjectPath::from(format!("blobs/{hash}"))
+}
+
+/// Object holding the current content of `file`.
+fn latest_path(file: &file::Model) -> ObjectPath {
+    if let Some(hash) = &file.blob_hash
+        && file.version == 1
+    {
+        return blob_path(hash);
+    }
+    if file.version > 1 {
+        return ObjectPath::from(file.name.as_str());
+    }
+    let key = path_strategy::sharded_key(file.object_shard.as_deref(), &file.name);
+    if file.compressed {
+        ObjectPath::from(format!("{key}{GZIP_SUFFIX}"))
+    } else {
+        ObjectPath::from(key)
+    }
+}
+
+/// A `Content-Disposition` saving as `name`: spelled out in `filename*` for
+/// clients that read RFC 6266, and with anything but printable ASCII
+/// replaced in `filename` for the rest.
+fn attachment(name: &str) -> String {
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    if fallback == name {
+        return format!("attachment; filename=\"{name}\"");
+    }
+    format!(
+        "attachment; filename=\"{fallback}\"; filename*=UTF-8''{}",
+        glacier::encode_segment(name)
+    )
+}
+
+/// The CDN URL of `key`: the key's segments, percent-encoded, under `cdn_base_url`.
+fn cdn_url(config: &S3Config, key: &ObjectPath) -> Option<String> {
+    let base = config.cdn_base_url.as_deref()?.trim_end_matches('/');
+    let encoded: Vec<String> = key
+        .as_ref()
+        .split('/')
+        .map(glacier::encode_segment)
+        .collect();
+    Some(format!("{base}/{}", encoded.join("/")))
+}
+
+/// `FileInfo::new`, plus the CDN URL of the file's current content.
+fn file_info(config: &S3Config, file: file::Model, author: Option<&user::Model>) -> FileInfo {
+    FileInfo {
+        cdn_url: cdn_url(config, &latest_path(&file)),
+        ..FileInfo::new(file, author)
+    }
+}
+
+const GZIP_SUFFIX: &str = ".gz";
+
+/// User metadata of gzipped objects holding the size before compression.
+const UNCOMPRESSED_SIZE_METADATA: &str = "uncompressed-size";
+
+/// True if `Accept-Encoding` admits gzip, by name or by `*`, with a non-zero q.
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            let refused = params.any(|p| {
+                p.strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+        })
+}
+
+pub(crate) fn is_gzip_encoded(attributes: &Attributes) -> bool {
+    attributes
+        .get(&Attribute::ContentEncoding)
+        .is_some_and(|v| v.as_ref() == "gzip")
+}
+
+/// Read size of downloads that come from a local file, matching the parts
+/// S3 objects are usually written, and so streamed back, in.
+pub const DOWNLOAD_CHUNK_BYTES: usize = 8 * 1024 * 1024;
+
+/// `result` as a response body, without copying it. The chunks a remote
+/// store streams go to the socket as the `Bytes` they arrived in; a local
+/// file is read `DOWNLOAD_CHUNK_BYTES` at a time rather than in the 8 KiB
+/// blocking reads of `GetResult::into_stream`.
+pub fn object_body(result: GetResult) -> Body {
+    match result.payload {
+        GetResultPayload::Stream(stream) => Body::from_stream(stream),
+        GetResultPayload::File(mut file, _) => {
+            use std::io::{Seek as _, SeekFrom};
+            let range = result.range;
+            match file.seek(SeekFrom::Start(range.start as u64)) {
+                Ok(_) => {
+                    let file =
+                        tokio::fs::File::from_std(file).take((range.end - range.start) as u64);
+                    Body::from_stream(ReaderStream::with_capacity(file, DOWNLOAD_CHUNK_BYTES))
+                }
+                Err(e) => {
+                    Body::from_stream(futures_util::stream::once(
+                        async move { Err::<Bytes, _>(e) },
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Reads a whole object, undoing the gzip applied by `compress_text_uploads`.
+async fn read_decoded(result: GetResult) -> Result<Bytes> {
+    let encoded = is_gzip_encoded(&result.attributes);
+    let bytes = result.bytes().await.map_err(Error::wrap)?;
+    if !encoded {
+        return Ok(bytes);
+    }
+
+    let mut decoded = Vec::new();
+    GzipDecoder::new(&bytes[..])
+        .read_to_end(&mut decoded)
+        .await?;
+    Ok(decoded.into())
+}
+
+fn unprocessable(code: &str, message: &str) -> Error {
+    Error::CustomError(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        ErrorDetail::new(code, message),
+    )
+}
+
+/// Splits a relative path into its segments, refusing anything that could
+/// escape the target prefix.
+pub(crate) fn safe_path_segments(path: &str) -> Option<Vec<&str>> {
+    let has_drive_letter = path.as_bytes().get(1) == Some(&b':');
+    if path.starts_with('/') || path.contains('\\') || has_drive_letter {
+        return None;
+    }
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.is_empty() || segments.iter().any(|s| *s == "." || *s == "..") {
+        return None;
+    }
+    Some(segments)
+}
+
+/// `name` in Unicode NFC, the form names are stored and looked up in, so
+/// that an accent typed as a combining mark finds the file all the same.
+pub fn normalize_name(name: &str) -> String {
+    name.nfc().collect()
+}
+
+/// `name` normalized, or a 400 when it can't name an object. Keys are still
+/// built with `ObjectPath::from`, which escapes what S3 advises against and
+/// so keeps the keys already stored; `parse` only tells sequences no key
+/// can hold, such as control characters or empty segments.
+pub fn checked_name(name: &str) -> FileResult<String> {
+    let name = normalize_name(name);
+    if safe_path_segments(&name).is_none() || ObjectPath::parse(&name).is_err() {
+        return Err(FileError::BadRequest(format!(
+            "Invalid file name: {name:?}"
+        )));
+    }
+    Ok(name)
+}
+
+/// A `{file_name}` path parameter, percent-decoded and then `checked_name`.
+pub struct FileName(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for FileName {
+    type Rejection = FileError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> FileResult<Self> {
+        let Path(name) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| FileError::BadRequest(e.body_text()))?;
+        checked_name(&name).map(Self)
+    }
+}
+
+/// The caller's address, as forwarded by a trusted proxy when the
+/// `remote_ip` middleware is on, else the socket's peer.
+fn client_ip(
+    remote_ip: RemoteIP,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+) -> Option<IpAddr> {
+    match remote_ip {
+        RemoteIP::Forwarded(ip) | RemoteIP::Socket(ip) => Some(ip),
+        RemoteIP::None => connect_info.map(|Extension(ConnectInfo(addr))| addr.ip()),
+    }
+}
+
+/// Who sent `headers`, as the audit log names them. The bearer token is
+/// named whether or not it decodes, so refused requests can be traced too.
+fn request_actor(headers: &HeaderMap, user_id: Option<i32>, ip: Option<IpAddr>) -> Actor {
+    let header_text = |name: HeaderName| headers.get(name).and_then(|v| v.to_str().ok());
+    Actor {
+        user_id,
+        token_id: header_text(header::AUTHORIZATION)
+            .map(|v| auth::token_id(v.strip_prefix("Bearer ").unwrap_or(v))),
+        ip_address: ip.map(|ip| ip.to_string()),
+        user_agent: header_text(header::USER_AGENT).map(String::from),
+    }
+}
+
+pub fn routes(ctx: &AppContext) -> Routes {
+    let config = get_s3_config(ctx);
+    let compress = config.compress_responses;
+    let gates = transfer_gates(ctx);
+    let upload = || middleware::from_fn_with_state(gates.uploads.clone(), limit_uploads);
+    let download = || middleware::from_fn_with_state(gates.downloads.clone(), limit_downloads);
+    let routes = Routes::new()
+        .prefix("/files")
+        .add(
+            "",
+            post(upload_file)
+                .layer((
+                    DefaultBodyLimit::disable(),
+                    RequestBodyLimitLayer::new(config.max_request_body_bytes as usize),
+                ))
+                .layer(middleware::map_response_with_state(
+                    config.max_request_body_bytes,
+                    json_body_limit,
+                ))
+                .layer(upload()),
+        )
+        .add("", get(get_all_files).layer(json_compression(compress)))
+        .add(
+            "/stats",
+            get(storage_stats)
+                .layer((json_compression(compress), Extension(StatsCache::default()))),
+        )
+        .add("/quota", get(get_quota))
+        .add("/health", get(storage_health))
+        .add("/limits", get(upload_limits))
+        .add("/events", get(stream_events))
+        .add("/audit", get(search_audit_log))
+        .add("/export", get(export_catalog))
+        .add("/tags", get(list_tags).layer(json_compression(compress)))
+        .add(
+            "/browse",
+            get(browse_files).layer(json_compression(compress)),
+        )
+        .add(
+            "/search",
+            get(search_files).layer(json_compression(compress)),
+        )
+        .add(
+            "/search/content",
+            get(search_file_contents).layer(json_compression(compress)),
+        )
+        .add(
+            "/{file_name}",
+            get(get_file)
+                .layer(download_compression(compress))
+                .layer(download())
+                .layer(middleware::from_fn_with_state(
+                    DownloadSigning {
+                        secret: config.signing_secret.clone(),
+                        required: config.require_signed_downloads,
+                    },
+                    verify_signed_token,
+                ))
+                .layer(middleware::from_fn_with_state(ctx.clone(), audit_download)),
+        )
+        .add("/{file_name}/signed-url", get(get_signed_url))
+        .add("/{file_name}/scan", post(rescan_file))
+        .add("/{file_name}/link", get(link_file))
+        .add(
+            "/{file_name}/lock",
+            post(lock_file)
+                .get(get_file_lock)
+                .patch(refresh_file_lock)
+                .delete(unlock_file),
+        )
+        .add("/{file_name}/download-count", get(get_download_count))
+        .add("/{file_name}/audit", get(get_file_audit))
+        .add("/{file_name}/exists", get(file_exists))
+        .add("/{file_name}/archive", post(archive_file))
+        .add("/{file_name}/meta", get(get_file_meta))
+        .add("/{file_name}/meta", patch(update_file_meta))
+        .add("/{file_name}/metadata", get(get_file_metadata))
+        .add("/{file_name}/metadata", patch(update_file_metadata))
+        .add("/{file_name}/tags", post(add_file_tags))
+        .add("/{file_name}/tags/{tag}", delete(remove_file_tag))
+        .add("/{file_name}/thumbnail", get(get_file_thumbnail))
+        .add("/{file_name}/preview", get(get_file_preview))
+        .add("/{file_name}/resized", get(get_resized_file))
+        .add("/{file_name}/restore", post(restore_archived_file))
+        .add("/{file_name}/restore-status", get(get_restore_status))
+        .add("/folder", delete(delete_folder))
+        .add("/folder/move", post(move_folder))
+        .add("/{file_name}", delete(delete_file))
+        .add(
+            "/tus",
+            post(create_tus_upload)
+                .options(tus_options)
+                .layer(tus_resumable_header()),
+        )
+        .add(
+            "/tus/{id}",
+            head(head_tus_upload).layer(tus_resumable_header()),
+        )
+        .add(
+            "/tus/{id}",
+            patch(patch_tus_upload)
+                .layer(tus_resumable_header())
+                .layer(upload()),
+        )
+        .add("/from-url", post(upload_from_url).layer(upload()))
+        .add("/import-from-s3", post(import_from_s3).layer(upload()))
+        .add("/merge", post(merge_files).layer(upload()))
+        .add(
+            "/base64",
+            post(upload_base64)
+                .layer(DefaultBodyLimit::max(
+                    (config.base64_max_size_bytes + BASE64_BODY_OVERHEAD) as usize,
+                ))
+                .layer(middleware::map_response_with_state(
+                    config.base64_max_size_bytes + BASE64_BODY_OVERHEAD,
+                    json_body_limit,
+                ))
+                .layer(upload()),
+        )
+        .add("/uploads", post(create_chunked_upload))
+        .add("/uploads/{id}", delete(abort_chunked_upload))
+        .add(
+            "/uploads/{id}/parts/{part_number}",
+            put(put_chunked_part)
+                .layer(DefaultBodyLimit::max(config.max_file_size_bytes as usize))
+                .layer(middleware::map_response_with_state(
+                    config.max_file_size_bytes,
+                    json_body_limit,
+                ))
+                .layer(upload()),
+        )
+        .add("/uploads/{id}/complete", post(complete_chunked_upload))
+        .add(
+            "/sync",
+            post(sync_files)
+                .layer(DefaultBodyLimit::max(sync_body_limit(&config) as usize))
+                .layer(middleware::map_response_with_state(
+                    sync_body_limit(&config),
+                    json_body_limit,
+                ))
+                .layer(upload()),
+        )
+        .add("/sync/storage", post(sync_storage))
+        .add(
+            "/duplicates",
+            get(find_duplicates).layer(json_compression(compress)),
+        )
+        .add("/webhooks/test", post(test_webhooks))
+        .add("/batch/download", post(batch_download).layer(download()))
+        .add(
+            "/export.tar",
+            get(export_files).post(export_file_list).layer(download()),
+        )
+        .add(
+            "/{id}/versions",
+            get(get_file_versions).layer(json_compression(compress)),
+        )
+        .add(
+            "/{file_name}/versions/{version}",
+            get(get_file_version).layer(download()),
+        )
+        .add(
+            "/{file_name}/snapshots/{timestamp}",
+            delete(delete_file_snapshot),
+        )
+        .add(
+            "/{file_name}/versions/{version}/restore",
+            post(restore_file_version),
+        )
+        .add("/{id}/revert", post(revert_file_version))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(config.clone()),
+            route_tenant,
+        ));
+
+    match cors_layer(&config) {
+        Some(cors) => routes.layer(cors),
+        None => routes,
+    }
+}
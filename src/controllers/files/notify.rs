@@ -0,0 +1,197 @@
+//! Telling others about stored files: webhooks and the event stream.
+
+use super::*;
+
+pub(super) const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub(super) fn webhook_client() -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()
+        .map_err(|e| Error::Message(format!("Webhook client error: {e}")))
+}
+
+/// Queues one delivery per target interested in `event`. Never fails the
+/// request that caused the event.
+pub(super) async fn notify_webhooks(
+    ctx: &AppContext,
+    config: &S3Config,
+    event: FileEvent,
+    size: i64,
+    checksum: Option<String>,
+    file_name: &str,
+) {
+    let payload = WebhookPayload {
+        event,
+        file_name: file_name.to_string(),
+        size,
+        checksum,
+        timestamp: chrono::Utc::now().to_rfc3339(),
+    };
+    for target in config.webhooks.targets.iter().filter(|t| t.wants(event)) {
+        let args = DeliverWebhookArgs {
+            url: target.url.clone(),
+            payload: payload.clone(),
+        };
+        if let Err(e) = DeliverWebhookWorker::perform_later(ctx, args).await {
+            tracing::warn!(url = %target.url, event = event.as_str(), error = %e, "failed to schedule webhook");
+        }
+    }
+}
+
+/// Background half of `notify_webhooks`. Deliveries that exhaust their
+/// attempts are logged with the full payload so they can be replayed.
+pub async fn deliver_webhook(ctx: &AppContext, url: &str, payload: &WebhookPayload) {
+    let config = get_s3_config(ctx);
+    let Some(target) = config.webhooks.targets.iter().find(|t| t.url == url) else {
+        tracing::warn!(url, "webhook target no longer configured, dropping event");
+        return;
+    };
+    let result = match webhook_client() {
+        Ok(client) => {
+            webhooks::deliver_with_retries(
+                &client,
+                target,
+                payload,
+                config.webhooks.max_attempts.max(1),
+                Duration::from_millis(config.webhooks.backoff_ms),
+            )
+            .await
+        }
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = result {
+        tracing::error!(
+            url,
+            attempts = config.webhooks.max_attempts,
+            error = %e,
+            payload = %serde_json::to_string(payload).unwrap_or_default(),
+            "webhook delivery gave up"
+        );
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WebhookTestResult {
+    pub url: String,
+    pub delivered: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Sends a sample `webhook.test` event to every target, once and right away,
+/// so integrators see the outcome in the response.
+#[utoipa::path(
+    post,
+    path = "/files/webhooks/test",
+    operation_id = "testWebhooks",
+    tag = "files",
+    responses(
+        (status = 200, description = "Delivery outcome per target", body = [WebhookTestResult]),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "Missing the `admin` scope", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn test_webhooks(
+    State(ctx): State<AppContext>,
+    headers: HeaderMap,
+) -> FileResult<Json<Vec<WebhookTestResult>>> {
+    auth::require_scope(&headers, "admin")?;
+
+    let config = get_s3_config(&ctx);
+    let client = webhook_client()?;
+    let payload = WebhookPayload {
+        event: FileEvent::Test,
+        file_name: "example.txt".into(),
+        size: 11,
+        checksum: Some(hex::encode(Sha256::digest(b"hello world"))),
+        timestamp: chrono::Utc::now().to_rfc3339(),
+    };
+
+    let mut results = Vec::new();
+    for target in &config.webhooks.targets {
+        let outcome = webhooks::deliver(&client, target, &payload).await;
+        results.push(WebhookTestResult {
+            url: target.url.clone(),
+            delivered: outcome.is_ok(),
+            error: outcome.err(),
+        });
+    }
+    Ok(Json(results))
+}
+
+pub(super) const EVENT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventStreamParams {
+    /// Only events about this file.
+    pub file: Option<String>,
+    /// Only events about the caller's own uploads; needs a token.
+    #[serde(default)]
+    pub mine: bool,
+}
+
+/// Streams upload and processing progress as server-sent events. A client
+/// reconnecting with `Last-Event-ID` first gets the recent events it missed.
+#[utoipa::path(
+    get,
+    path = "/files/events",
+    operation_id = "streamFileEvents",
+    tag = "files",
+    params(EventStreamParams),
+    responses(
+        (status = 200, description = "Server-sent progress events", content_type = "text/event-stream", body = String),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn stream_events(
+    headers: HeaderMap,
+    Query(params): Query<EventStreamParams>,
+) -> FileResult<Sse<impl Stream<Item = std::result::Result<Event, axum::Error>>>> {
+    let user_id = if params.mine {
+        let claims = auth::claims_from_headers(&headers)?;
+        Some(
+            claims
+                .pid
+                .parse::<i32>()
+                .map_err(|_| FileError::Unauthorized)?,
+        )
+    } else {
+        None
+    };
+    let last_id = headers
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok());
+
+    let (receiver, replay) = events::bus().subscribe(last_id);
+    let live = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((event, receiver)),
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::debug!(skipped, "event stream fell behind");
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    let wanted = move |event: &ProgressEvent| {
+        params.file.as_ref().is_none_or(|f| *f == event.file_name)
+            && user_id.is_none_or(|id| event.user_id == Some(id))
+    };
+    let stream = futures_util::stream::iter(replay)
+        .chain(live)
+        .filter(move |event| std::future::ready(wanted(event)))
+        .map(|event| {
+            Event::default()
+                .id(event.id.to_string())
+                .event(event.kind.as_str())
+                .json_data(&event)
+        });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(EVENT_KEEP_ALIVE)))
+}
@@ -0,0 +1,132 @@
+//! Restoring files archived to cold storage.
+
+use super::*;
+
+/// Glacier restores go straight to S3, so they need the `s3` backend.
+pub(super) fn glacier_bucket(config: &S3Config) -> Result<glacier::Bucket<'_>> {
+    if config.local_storage_path.is_some() || config.backend != "s3" {
+        let backend = if config.local_storage_path.is_some() {
+            "local"
+        } else {
+            config.backend.as_str()
+        };
+        return Err(Error::CustomError(
+            StatusCode::NOT_IMPLEMENTED,
+            ErrorDetail::new(
+                "restore_unsupported",
+                &format!("The '{backend}' backend has no archive tier"),
+            ),
+        ));
+    }
+    Ok(glacier::Bucket {
+        endpoint: &config.endpoint,
+        name: &config.bucket,
+        region: &config.region,
+        access_key: &config.access_key,
+        secret_key: &config.secret_key,
+    })
+}
+
+/// Starts bringing an object archived by a lifecycle policy back online.
+/// S3 restores in the background: poll `restore-status` until it completes.
+#[utoipa::path(
+    post,
+    path = "/files/{file_name}/restore",
+    operation_id = "restoreArchivedFile",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included")),
+    request_body(content = RestoreArchiveRequest),
+    responses(
+        (status = 202, description = "Restore started"),
+        (status = 200, description = "A restore is already in progress or done"),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 501, description = "The storage backend has no archive tier", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn restore_archived_file(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+    Json(req): Json<RestoreArchiveRequest>,
+) -> FileResult<Response> {
+    let auth_header = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(FileError::Unauthorized)?;
+
+    let token = auth_header.strip_prefix("Bearer ").unwrap_or(auth_header);
+    let _claims =
+        crate::controllers::auth::decode_token(token).map_err(|_| FileError::Unauthorized)?;
+
+    if req.restore_days == 0 {
+        return Err(FileError::BadRequest(
+            "restore_days must be at least 1".into(),
+        ));
+    }
+
+    let config = get_s3_config(&ctx);
+    let bucket = glacier_bucket(&config)?;
+    let key = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .map_or_else(|| ObjectPath::from(file_name.as_str()), |f| latest_path(&f));
+
+    let (status, restore) = match bucket
+        .restore_object(key.as_ref(), req.restore_days)
+        .await?
+    {
+        RestoreRequested::Started => (StatusCode::ACCEPTED, "in-progress"),
+        RestoreRequested::AlreadyRestored => (StatusCode::OK, "completed"),
+    };
+
+    Ok((
+        status,
+        Json(serde_json::json!({
+            "name": file_name,
+            "key": key.to_string(),
+            "restore_days": req.restore_days,
+            "status": restore,
+        })),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/files/{file_name}/restore-status",
+    operation_id = "getRestoreStatus",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included")),
+    responses(
+        (status = 200, description = "Where the restore stands", body = glacier::RestoreState),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 501, description = "The storage backend has no archive tier", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn get_restore_status(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+) -> FileResult<Json<glacier::RestoreState>> {
+    let auth_header = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(FileError::Unauthorized)?;
+
+    let token = auth_header.strip_prefix("Bearer ").unwrap_or(auth_header);
+    let _claims =
+        crate::controllers::auth::decode_token(token).map_err(|_| FileError::Unauthorized)?;
+
+    let config = get_s3_config(&ctx);
+    let bucket = glacier_bucket(&config)?;
+    let key = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .map_or_else(|| ObjectPath::from(file_name.as_str()), |f| latest_path(&f));
+
+    Ok(Json(bucket.restore_state(key.as_ref()).await?))
+}
@@ -0,0 +1,185 @@
+//! Virus scanning and the quarantine of infected files.
+
+use super::*;
+
+/// Infected files are moved under this prefix and never served again.
+pub(super) const QUARANTINE_PREFIX: &str = "quarantine/";
+pub(super) const SCAN_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub(super) fn virus_scanner(config: &ScanConfig) -> Arc<dyn Scanner> {
+    Arc::new(Clamd {
+        host: config.host.clone(),
+        port: config.port,
+        timeout: SCAN_TIMEOUT,
+    })
+}
+
+/// Marks a stored upload `pending`, then scans it right away when it is
+/// small and in the background otherwise. Returns the status it was left in.
+pub(super) async fn start_scan(
+    ctx: &AppContext,
+    config: &S3Config,
+    store: &dyn ObjectStore,
+    tenant: Option<&str>,
+    info: &FileInfo,
+) -> Option<String> {
+    let pending = ScanStatus::Pending.as_str();
+    if let Err(e) = file::set_scan_status(&ctx.db, info.id, pending, None).await {
+        tracing::error!(name = %info.name, error = %e, "failed to mark upload for scanning");
+        return info.scan_status.clone();
+    }
+
+    if info.size > config.scan.inline_max_bytes {
+        let args = ScanFileArgs {
+            name: info.name.clone(),
+        };
+        if let Err(e) = ScanFileWorker::perform_later(ctx, args).await {
+            tracing::warn!(name = %info.name, error = %e, "failed to schedule virus scan");
+        }
+        return Some(pending.to_string());
+    }
+
+    match scan_stored_file(ctx, store, &info.name, tenant).await {
+        Ok(status) => Some(status.unwrap_or(ScanStatus::Pending).as_str().to_string()),
+        Err(e) => {
+            tracing::warn!(name = %info.name, error = %e, "virus scan failed");
+            Some(pending.to_string())
+        }
+    }
+}
+
+/// Background counterpart of the inline scan in `start_scan`.
+pub async fn scan_file(ctx: &AppContext, name: &str) -> Result<()> {
+    let config = get_s3_config(ctx);
+    let store = shared_store(&config)?;
+    scan_stored_file(ctx, store.as_ref(), name, None).await?;
+    Ok(())
+}
+
+/// Scans the current content of `name` and records the verdict. Infected
+/// content is moved to `quarantine/`; `None` when the file is gone.
+pub(super) async fn scan_stored_file(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    name: &str,
+    tenant: Option<&str>,
+) -> Result<Option<ScanStatus>> {
+    let config = get_s3_config(ctx);
+    let Some(record) = file::find_by_name_for_tenant(&ctx.db, name, tenant).await? else {
+        return Ok(None);
+    };
+    let Some(bytes) = read_current_content(ctx, store, name, tenant).await? else {
+        return Ok(None);
+    };
+
+    let scanner = virus_scanner(&config.scan);
+    let (status, signature) =
+        scanner::scan_status(scanner.as_ref(), &bytes, config.scan.fail_open).await;
+    if status == ScanStatus::Infected {
+        tracing::warn!(name, signature = ?signature, "infected upload quarantined");
+        quarantine(store, &record).await?;
+    }
+    file::set_scan_status(&ctx.db, record.id, status.as_str(), signature.as_deref()).await?;
+    events::bus().publish(
+        ProgressKind::ScanCompleted,
+        name,
+        record.author_id,
+        Some(serde_json::json!({ "status": status, "signature": signature })),
+    );
+    Ok(Some(status))
+}
+
+/// Moves the current content of `file` to `quarantine/` and drops its copy
+/// under `versions/`. A shared blob stays put: every file using it has the
+/// same content and is blocked by its own scan status.
+pub(super) async fn quarantine(store: &dyn ObjectStore, file: &file::Model) -> Result<()> {
+    if file.blob_hash.is_some() && file.version == 1 {
+        return Ok(());
+    }
+    let quarantined = ObjectPath::from(format!("{QUARANTINE_PREFIX}{}", file.name));
+    store
+        .rename(&latest_path(file), &quarantined)
+        .await
+        .map_err(Error::wrap)?;
+    let _ = store.delete(&version_path(file, file.version)).await;
+    Ok(())
+}
+
+/// Refuses downloads of files that are infected or not scanned yet.
+pub(super) fn ensure_servable(file: &file::Model) -> FileResult<()> {
+    let Some(status) = file.scan_status.as_deref().and_then(ScanStatus::parse) else {
+        return Ok(());
+    };
+    match status {
+        ScanStatus::Infected => Err(FileError::Rejected {
+            status: StatusCode::FORBIDDEN,
+            code: "file_infected".into(),
+            message: format!("'{}' failed the virus scan and is quarantined", file.name),
+            details: file
+                .scan_signature
+                .as_ref()
+                .map(|signature| serde_json::json!({ "signature": signature })),
+        }),
+        ScanStatus::Pending => Err(FileError::Rejected {
+            status: StatusCode::FORBIDDEN,
+            code: "scan_pending".into(),
+            message: format!("'{}' has not been virus scanned yet", file.name),
+            details: None,
+        }),
+        ScanStatus::Clean | ScanStatus::Unscanned => Ok(()),
+    }
+}
+
+/// Scans a file again, for antivirus hooks and jobs that want a verdict on
+/// demand. An infected file keeps its verdict: its content is in
+/// quarantine, out of reach of the scan.
+#[utoipa::path(
+    post,
+    path = "/files/{file_name}/scan",
+    operation_id = "scanFile",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included")),
+    responses(
+        (status = 200, description = "The verdict. `pending` when clamd could not be reached", body = ScanResult),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "Missing the `admin` scope", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 501, description = "Virus scanning is turned off", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn rescan_file(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    headers: HeaderMap,
+    FileName(file_name): FileName,
+) -> FileResult<Json<ScanResult>> {
+    auth::require_scope(&headers, "admin")?;
+    let record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_name))?;
+    if !get_s3_config(&ctx).scan.enabled {
+        return Err(FileError::Rejected {
+            status: StatusCode::NOT_IMPLEMENTED,
+            code: "scan_disabled".into(),
+            message: "Virus scanning is turned off".into(),
+            details: None,
+        });
+    }
+
+    let infected = record.scan_status.as_deref() == Some(ScanStatus::Infected.as_str());
+    let record = if infected {
+        record
+    } else {
+        scan_stored_file(&ctx, store.as_ref(), &file_name, tenant.id()).await?;
+        file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+            .await?
+            .ok_or_else(|| file_not_found(&file_name))?
+    };
+    Ok(Json(ScanResult {
+        name: record.name,
+        scan_status: record.scan_status,
+        signature: record.scan_signature,
+    }))
+}
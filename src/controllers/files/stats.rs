@@ -0,0 +1,268 @@
+//! Storage usage, quotas, limits and health.
+
+use super::*;
+
+#[utoipa::path(
+    get,
+    path = "/files/stats",
+    operation_id = "getStorageStats",
+    tag = "files",
+    params(StorageStatsParams),
+    responses(
+        (status = 200, description = "Totals over all files", body = StorageStatsResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "`refresh` without the `admin` scope", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn storage_stats(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(cache): Extension<StatsCache>,
+    headers: HeaderMap,
+    Query(params): Query<StorageStatsParams>,
+) -> FileResult<Json<StorageStatsResponse>> {
+    if params.refresh {
+        auth::require_scope(&headers, "admin")?;
+    } else {
+        auth::claims_from_headers(&headers)?;
+    }
+
+    let mut storage = cache.usage(store.as_ref(), params.refresh).await?;
+    storage
+        .largest_objects
+        .truncate(params.top.unwrap_or(10).min(MAX_TOP_OBJECTS));
+    let (count, total_bytes) = file::count_and_total_size(&ctx.db).await?;
+    let largest = file::find_largest(&ctx.db).await?;
+    let newest = file::find_newest(&ctx.db).await?;
+
+    Ok(Json(StorageStatsResponse {
+        count,
+        total_bytes,
+        avg_bytes: if count > 0 { total_bytes / count } else { 0 },
+        largest: largest.map(|f| LargestFileInfo {
+            name: f.name,
+            size: f.size,
+        }),
+        newest: newest.map(|f| NewestFileInfo {
+            name: f.name,
+            created_at: f.created_at.and_utc().to_rfc3339(),
+        }),
+        storage,
+        storage_retries: retry_store::retried_operations(),
+        storage_open_circuits: circuit_store::tripped_circuits(),
+        uploads: TransferCounts::from(transfer_gates(&ctx).uploads.limit.as_ref()),
+        downloads: TransferCounts::from(transfer_gates(&ctx).downloads.limit.as_ref()),
+        download_cache: DOWNLOAD_CACHE.get().map(|cache| DownloadCacheStats {
+            hits: cache.hits(),
+            misses: cache.misses(),
+            size_bytes: cache.size_bytes(),
+        }),
+        memory_cache: MEMORY_CACHE.get().map(|cache| {
+            let (hits, misses) = (cache.hits(), cache.misses());
+            MemoryCacheStats {
+                entries: cache.entry_count(),
+                size_bytes: cache.size_bytes(),
+                hits,
+                misses,
+                hit_ratio: if hits + misses > 0 {
+                    hits as f64 / (hits + misses) as f64
+                } else {
+                    0.0
+                },
+            }
+        }),
+    }))
+}
+
+/// How long a fault of the backend's itself keeps the health check failing.
+pub(super) const HEALTH_FAULT_WINDOW: Duration = Duration::from_secs(60);
+
+/// The circuit breaker in front of the bucket a request is routed to.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StorageHealthResponse {
+    /// False while storage calls are refused, and for a minute after one
+    /// failed with `storage_auth` or `storage_unreachable`.
+    pub ok: bool,
+    /// `closed` while calls go through, `open` while they are refused, and
+    /// `half_open` while a single call probes the backend.
+    pub circuit: String,
+    pub consecutive_failures: u32,
+    /// Until the circuit lets a call through again, while it is open.
+    pub retry_after_seconds: Option<u64>,
+    /// Failed calls since startup by error code: `storage_auth` and
+    /// `storage_unreachable` for storage that is misconfigured or down,
+    /// `storage_error` for anything else.
+    pub faults: BTreeMap<String, u64>,
+    /// The code of the latest of them.
+    pub last_fault: Option<String>,
+    pub last_fault_seconds_ago: Option<u64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/files/health",
+    operation_id = "getStorageHealth",
+    tag = "files",
+    responses(
+        (status = 200, description = "State of the storage circuit breaker", body = StorageHealthResponse),
+    ),
+)]
+pub async fn storage_health(
+    Extension(circuit): Extension<Arc<CircuitState>>,
+) -> Json<StorageHealthResponse> {
+    let status = circuit.status();
+    let last_fault = circuit.last_fault();
+    let storage_down = last_fault.is_some_and(|(failure, ago)| {
+        matches!(failure, StorageFailure::Auth | StorageFailure::Unreachable)
+            && ago < HEALTH_FAULT_WINDOW
+    });
+    Json(StorageHealthResponse {
+        ok: status == circuit_store::CircuitStatus::Closed && !storage_down,
+        circuit: status.as_str().to_string(),
+        consecutive_failures: circuit.consecutive_failures(),
+        retry_after_seconds: circuit.retry_after().map(|d| d.as_secs_f64().ceil() as u64),
+        faults: circuit
+            .fault_counts()
+            .into_iter()
+            .map(|(code, count)| (code.to_string(), count))
+            .collect(),
+        last_fault: last_fault.map(|(failure, _)| failure.code().to_string()),
+        last_fault_seconds_ago: last_fault.map(|(_, ago)| ago.as_secs()),
+    })
+}
+
+/// How large an upload may be, for clients to check before sending one.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UploadLimits {
+    /// Per file, whichever way it is uploaded.
+    pub max_file_size_bytes: u64,
+    /// Whole body of `POST /files`, all its files and the multipart framing
+    /// together. Larger bodies are refused with a 413 before they are read.
+    pub max_upload_body_bytes: u64,
+    /// The same for `POST /files/sync`, which takes a single file.
+    pub max_sync_body_bytes: u64,
+    /// Length of the `data` of `POST /files/base64`.
+    pub base64_max_size_bytes: u64,
+    /// Parts of a chunked upload are limited like files.
+    pub max_part_size_bytes: u64,
+    pub max_extract_entries: usize,
+    pub max_extract_bytes: u64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/files/limits",
+    operation_id = "getUploadLimits",
+    tag = "files",
+    responses(
+        (status = 200, description = "Size limits uploads are checked against", body = UploadLimits),
+    ),
+)]
+pub async fn upload_limits(State(ctx): State<AppContext>) -> Json<UploadLimits> {
+    let config = get_s3_config(&ctx);
+    Json(UploadLimits {
+        max_file_size_bytes: config.max_file_size_bytes,
+        max_upload_body_bytes: config.max_request_body_bytes,
+        max_sync_body_bytes: sync_body_limit(&config),
+        base64_max_size_bytes: config.base64_max_size_bytes,
+        max_part_size_bytes: config.max_file_size_bytes,
+        max_extract_entries: config.max_extract_entries,
+        max_extract_bytes: config.max_extract_bytes,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/files/quota",
+    operation_id = "getStorageQuota",
+    tag = "files",
+    responses(
+        (status = 200, description = "The caller's usage and quota", body = QuotaResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn get_quota(
+    State(ctx): State<AppContext>,
+    headers: HeaderMap,
+) -> FileResult<Json<QuotaResponse>> {
+    let author = token_author(&ctx, &headers).await?;
+    let limit = quota_usage(&ctx, &get_s3_config(&ctx), author.id)
+        .await?
+        .map(|(_, limit)| limit);
+    Ok(Json(QuotaResponse {
+        used_bytes: author.storage_used_bytes,
+        limit_bytes: limit,
+        remaining_bytes: limit.map(|limit| (limit - author.storage_used_bytes).max(0)),
+    }))
+}
+
+/// Objects kept in `StorageUsage::largest_objects`, the most `?top` can ask for.
+pub(super) const MAX_TOP_OBJECTS: usize = 100;
+/// A full bucket scan is expensive; its result is served this long.
+pub(super) const STATS_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// The last bucket scan of `GET /files/stats`. The lock is held while
+/// scanning, so concurrent requests wait for one scan instead of starting more.
+#[derive(Clone, Default)]
+pub struct StatsCache(Arc<tokio::sync::Mutex<Option<(Instant, StorageUsage)>>>);
+
+impl StatsCache {
+    pub(super) async fn usage(
+        &self,
+        store: &dyn ObjectStore,
+        refresh: bool,
+    ) -> FileResult<StorageUsage> {
+        let mut cached = self.0.lock().await;
+        if let Some((at, usage)) = cached.as_ref()
+            && !refresh
+            && at.elapsed() < STATS_CACHE_TTL
+        {
+            return Ok(usage.clone());
+        }
+        let usage = scan_storage_usage(store).await?;
+        *cached = Some((Instant::now(), usage.clone()));
+        Ok(usage)
+    }
+}
+
+/// Adds up the listing as it streams by; only the largest objects are kept.
+pub(super) async fn scan_storage_usage(store: &dyn ObjectStore) -> FileResult<StorageUsage> {
+    let mut usage = StorageUsage::default();
+    let mut largest = BinaryHeap::new();
+    let mut listing = store.list(None);
+    while let Some(meta) = listing.try_next().await.map_err(FileError::StorageError)? {
+        let key = meta.location.to_string();
+        let size = meta.size as u64;
+        let prefix = key
+            .split_once('/')
+            .map_or_else(|| "/".to_string(), |(first, _)| format!("{first}/"));
+        let content_type = mime_guess::from_path(&key)
+            .first_or_octet_stream()
+            .essence_str()
+            .to_string();
+        for totals in [
+            usage.by_prefix.entry(prefix).or_default(),
+            usage.by_content_type.entry(content_type).or_default(),
+        ] {
+            totals.count += 1;
+            totals.bytes += size;
+        }
+        usage.object_count += 1;
+        usage.total_bytes += size;
+
+        // Ties go to the first key in order.
+        largest.push(Reverse((size, Reverse(key))));
+        if largest.len() > MAX_TOP_OBJECTS {
+            largest.pop();
+        }
+    }
+    usage.largest_objects = largest
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((size, Reverse(key)))| ObjectSize { key, size })
+        .collect();
+    usage.generated_at = Utc::now().to_rfc3339();
+    Ok(usage)
+}
@@ -0,0 +1,623 @@
+//! Keeping the index, the bucket and its replica in line with each other,
+//! and syncing clients that edit offline.
+
+use super::*;
+
+pub(super) static REPLICA_STORE: OnceLock<Option<Arc<dyn ObjectStore>>> = OnceLock::new();
+
+/// The replica's store, built on first use; `None` with replication off.
+pub(super) fn replica_store(config: &S3Config) -> Result<Option<Arc<dyn ObjectStore>>> {
+    if let Some(store) = REPLICA_STORE.get() {
+        return Ok(store.clone());
+    }
+    let store = match config.for_replica() {
+        Some(replica) => Some(create_store(&replica)?.0),
+        None => None,
+    };
+    Ok(REPLICA_STORE.get_or_init(|| store).clone())
+}
+
+/// Sets every user's usage to the size of their files as the buckets hold
+/// them, correcting whatever drifted. Returns how many files were measured.
+pub async fn reconcile_storage_usage(ctx: &AppContext) -> Result<usize> {
+    let config = get_s3_config(ctx);
+    let default_store = shared_store(&config)?;
+    let mut usage: HashMap<i32, i64> = HashMap::new();
+    let mut measured = 0;
+    for row in file::find_all(&ctx.db).await? {
+        let Some(author_id) = row.author_id else {
+            continue;
+        };
+        // Usage spans tenants, but each file is in its own tenant's bucket.
+        let store = match row.tenant_id.as_deref() {
+            None => default_store.clone(),
+            Some(tenant) => match tenant_backend(&config, tenant)? {
+                Some((store, ..)) => store,
+                None => {
+                    tracing::warn!(name = %row.name, tenant, "file of an unknown tenant; not counted");
+                    continue;
+                }
+            },
+        };
+        let options = GetOptions {
+            head: true,
+            ..Default::default()
+        };
+        let size = match store.get_opts(&latest_path(&row), options).await {
+            // Gzipped uploads count with the size they were uploaded at.
+            Ok(result) => result
+                .attributes
+                .get(&Attribute::Metadata(UNCOMPRESSED_SIZE_METADATA.into()))
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(result.meta.size as i64),
+            Err(ObjectStoreError::NotFound { .. }) => {
+                tracing::warn!(name = %row.name, "file has no object; counted as empty");
+                0
+            }
+            Err(e) => return Err(Error::wrap(e)),
+        };
+        *usage.entry(author_id).or_default() += size;
+        measured += 1;
+    }
+    let txn = ctx.db.begin().await?;
+    user::reset_storage_used(&txn, &usage).await?;
+    txn.commit().await?;
+    Ok(measured)
+}
+
+/// Copies file `name` to the replica, or with `deleted_key` removes the
+/// object it was stored under from it. Run by `ReplicateFileWorker`.
+pub async fn replicate_file(ctx: &AppContext, name: &str, deleted_key: Option<&str>) -> Result<()> {
+    let config = get_s3_config(ctx);
+    let Some(replica) = replica_store(&config)? else {
+        return Ok(());
+    };
+    match deleted_key {
+        Some(key) => match replica.delete(&ObjectPath::from(key)).await {
+            Ok(()) | Err(ObjectStoreError::NotFound { .. }) => Ok(()),
+            Err(e) => Err(Error::wrap(e)),
+        },
+        None => {
+            let primary = shared_store(&config)?;
+            copy_to_replica(ctx, primary.as_ref(), &replica, name).await
+        }
+    }
+}
+
+/// Copies the current content of file `name` from `primary` to `replica`
+/// and records how that went on the file.
+pub async fn copy_to_replica(
+    ctx: &AppContext,
+    primary: &dyn ObjectStore,
+    replica: &Arc<dyn ObjectStore>,
+    name: &str,
+) -> Result<()> {
+    // Deleted since it was queued.
+    let Some(record) = file::find_by_name_for_tenant(&ctx.db, name, None).await? else {
+        return Ok(());
+    };
+    match replication::copy_object(primary, replica, &latest_path(&record)).await {
+        Ok(()) => {
+            let synced = ReplicationStatus::Synced.as_str();
+            file::set_replication_status(&ctx.db, record.id, synced, None).await?;
+            Ok(())
+        }
+        Err(e) => {
+            let error = e.to_string();
+            let failed = ReplicationStatus::Failed.as_str();
+            file::set_replication_status(&ctx.db, record.id, failed, Some(&error)).await?;
+            Err(Error::wrap(e))
+        }
+    }
+}
+
+/// Brings the replica in line with the default bucket, then copies again
+/// the files whose last copy failed or never ran.
+pub async fn reconcile_replica(ctx: &AppContext) -> Result<Reconciliation> {
+    let config = get_s3_config(ctx);
+    let Some(replica) = replica_store(&config)? else {
+        return Ok(Reconciliation::default());
+    };
+    let primary = shared_store(&config)?;
+    let mut report = replication::reconcile(
+        primary.as_ref(),
+        &replica,
+        config.replication.replicate_deletes,
+    )
+    .await
+    .map_err(Error::wrap)?;
+
+    for status in [ReplicationStatus::Failed, ReplicationStatus::Pending] {
+        for record in file::find_by_replication_status(&ctx.db, status.as_str()).await? {
+            if let Err(e) = copy_to_replica(ctx, primary.as_ref(), &replica, &record.name).await {
+                tracing::warn!(file = %record.name, error = %e, "replication retry failed");
+                report.failed += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[utoipa::path(
+    post,
+    path = "/files/sync",
+    operation_id = "syncFile",
+    tag = "files",
+    request_body(content = SyncForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "The stored file", body = FileInfo),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 409, description = "The file changed since the given version", body = ErrorBody),
+        (status = 413, description = "The body exceeds `max_sync_body_bytes`", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn sync_files(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    remote_ip: RemoteIP,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> FileResult<Json<FileInfo>> {
+    let auth_header = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(FileError::Unauthorized)?;
+
+    let token = auth_header.strip_prefix("Bearer ").unwrap_or(auth_header);
+
+    let claims =
+        crate::controllers::auth::decode_token(token).map_err(|_| FileError::Unauthorized)?;
+
+    let user_id: i32 = claims.pid.parse().unwrap_or(0);
+    let author = user::find_by_id(&ctx.db, user_id)
+        .await?
+        .ok_or(FileError::Unauthorized)?;
+    let actor = request_actor(
+        &headers,
+        Some(author.id),
+        client_ip(remote_ip, connect_info),
+    );
+
+    let mut file_id: Option<i32> = None;
+    let mut version: Option<i32> = None;
+    let mut file_bytes: Option<Bytes> = None;
+
+    let config = get_s3_config(&ctx);
+    while let Some(field) = multipart.next_field().await.map_err(|e| {
+        multipart_error(sync_body_limit(&config), e, |e| {
+            Error::BadRequest(format!("Multipart error: {e}"))
+        })
+    })? {
+        if let Some(name) = field.name() {
+            match name {
+                "file_id" => {
+                    let text = field
+                        .text()
+                        .await
+                        .map_err(|e| FileError::BadRequest(format!("Read file_id: {e}")))?;
+                    file_id = text.parse().ok();
+                }
+                "version" => {
+                    let text = field
+                        .text()
+                        .await
+                        .map_err(|e| FileError::BadRequest(format!("Read version: {e}")))?;
+                    version = text.parse().ok();
+                }
+                "file" => {
+                    let bytes = field.bytes().await.map_err(|e| {
+                        multipart_error(sync_body_limit(&config), e, |e| {
+                            Error::BadRequest(format!("Read file: {e}"))
+                        })
+                    })?;
+                    file_bytes = Some(bytes);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let file_id = file_id.ok_or_else(|| FileError::BadRequest("Missing file_id".into()))?;
+    let version = version.ok_or_else(|| FileError::BadRequest("Missing version".into()))?;
+    let bytes = file_bytes.ok_or_else(|| FileError::BadRequest("Missing file".into()))?;
+
+    let existing = file::find_by_id_for_tenant(&ctx.db, file_id, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_id.to_string()))?;
+    if existing.version != version {
+        return Err(FileError::Rejected {
+            status: StatusCode::CONFLICT,
+            code: "version_conflict".into(),
+            message: format!(
+                "Version conflict: expected {version}, current {}",
+                existing.version
+            ),
+            details: None,
+        });
+    }
+    let stored = write_next_version(
+        &ctx,
+        &config,
+        store.as_ref(),
+        &existing,
+        bytes,
+        &author,
+        &actor,
+    )
+    .await?;
+
+    Ok(Json(stored.info))
+}
+
+/// Reconciles the `files` index with what is actually in the bucket.
+///
+/// Objects without a row are indexed with no uploader, rows without an object
+/// are marked orphaned (and hidden from the listing) until the object reappears.
+#[utoipa::path(
+    post,
+    path = "/files/sync/storage",
+    operation_id = "syncStorage",
+    tag = "files",
+    params(StorageSyncParams),
+    responses(
+        (status = 200, description = "What the index reconciliation changed", body = StorageSyncResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "Missing the `admin` scope", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn sync_storage(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    headers: HeaderMap,
+    Query(params): Query<StorageSyncParams>,
+) -> FileResult<Json<StorageSyncResponse>> {
+    auth::require_scope(&headers, "admin")?;
+
+    let mut objects: HashMap<String, i64> = HashMap::new();
+    let mut blobs: HashSet<String> = HashSet::new();
+    let mut listing = store.list(None);
+    while let Some(meta) = listing.try_next().await.map_err(FileError::StorageError)? {
+        let key = meta.location.to_string();
+        if let Some(hash) = key.strip_prefix("blobs/") {
+            blobs.insert(hash.to_string());
+            continue;
+        }
+        if is_derived_key(&key) {
+            continue;
+        }
+        objects.insert(key, meta.size as i64);
+    }
+
+    // Only the rows of the bucket listed; another tenant's would all look
+    // orphaned.
+    let rows = file::find_all_for_tenant(&ctx.db, tenant.id()).await?;
+    let indexed: HashSet<String> = rows
+        .iter()
+        .flat_map(|f| [f.name.clone(), latest_path(f).to_string()])
+        .collect();
+
+    let mut summary = StorageSyncResponse {
+        added: 0,
+        removed: 0,
+        unchanged: 0,
+        dry_run: params.dry_run,
+    };
+
+    for row in &rows {
+        let present = objects.contains_key(&row.name)
+            || objects.contains_key(latest_path(row).as_ref())
+            || row
+                .blob_hash
+                .as_ref()
+                .is_some_and(|hash| blobs.contains(hash));
+        if present != row.orphaned {
+            summary.unchanged += 1;
+            continue;
+        }
+
+        if present {
+            summary.added += 1;
+        } else {
+            summary.removed += 1;
+        }
+        if !params.dry_run {
+            file::set_orphaned(&ctx.db, row.id, !present).await?;
+        }
+    }
+
+    for (key, size) in &objects {
+        if indexed.contains(key) {
+            continue;
+        }
+        summary.added += 1;
+        if !params.dry_run {
+            file::create_unattributed(&ctx.db, key, *size, tenant.id()).await?;
+        }
+    }
+
+    Ok(Json(summary))
+}
+
+/// What `verify_storage` does with an object no file owns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OrphanObjects {
+    /// Index it as a file with no uploader, like `POST /files/sync/storage`.
+    #[default]
+    Index,
+    Delete,
+}
+
+#[derive(Debug, Clone)]
+pub struct VerifyOptions {
+    /// Resolve what is found instead of only reporting it.
+    pub fix: bool,
+    pub orphan_objects: OrphanObjects,
+    /// Objects and files changed more recently than this are left out: an
+    /// upload in flight has one without the other for a moment.
+    pub min_age: Duration,
+}
+
+impl Default for VerifyOptions {
+    fn default() -> Self {
+        Self {
+            fix: false,
+            orphan_objects: OrphanObjects::default(),
+            min_age: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Files read from the index at a time.
+pub(super) const VERIFY_PAGE_SIZE: u64 = 500;
+/// Keys `Discrepancies` lists, at most; the count goes on past them.
+pub(super) const VERIFY_EXAMPLES: usize = 100;
+
+/// One kind of drift between the index and the bucket.
+#[derive(Debug, Default, Serialize)]
+pub struct Discrepancies {
+    pub count: u64,
+    /// Resolved, with `fix`. A fix is skipped, and not counted, when the
+    /// file changed since it was read.
+    pub fixed: u64,
+    /// The first keys found.
+    pub keys: Vec<String>,
+}
+
+impl Discrepancies {
+    fn found(&mut self, key: &str) {
+        self.count += 1;
+        if self.keys.len() < VERIFY_EXAMPLES {
+            self.keys.push(key.to_string());
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    pub fix: bool,
+    pub objects: u64,
+    pub files: u64,
+    pub in_sync: u64,
+    pub object_without_row: Discrepancies,
+    /// Marked orphaned, which hides them from listings.
+    pub row_without_object: Discrepancies,
+    /// Orphaned files whose object is back.
+    pub row_restored: Discrepancies,
+    pub size_mismatch: Discrepancies,
+    /// Changed within `min_age`, so not compared.
+    pub skipped_recent: u64,
+    /// Fixes that failed; they are logged.
+    pub failed: u64,
+}
+
+/// Compares the default bucket with the files of its index, a key at a
+/// time: the listing and the rows both come in key order and are walked
+/// side by side, so neither is held in memory. Derived objects such as
+/// versions and thumbnails, and blobs no file uses, are not compared.
+///
+/// `fix` is safe alongside the server: recent changes are left alone and
+/// a file that changes while it is compared keeps the change. Sizes are not
+/// compared for compressed files, or with encryption on, as the bucket
+/// holds other bytes than the file's.
+pub async fn verify_storage(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    options: &VerifyOptions,
+) -> Result<VerifyReport> {
+    let config = get_s3_config(ctx);
+    let compare_sizes = config.encryption.keyring().map_err(Error::wrap)?.is_none();
+    let cutoff =
+        Utc::now() - chrono::Duration::from_std(options.min_age).unwrap_or(chrono::Duration::MAX);
+    let mut report = VerifyReport {
+        fix: options.fix,
+        ..Default::default()
+    };
+
+    let mut objects = store
+        .list(None)
+        .try_filter(|meta| {
+            let key = meta.location.as_ref();
+            std::future::ready(
+                key.starts_with("blobs/")
+                    || !(is_derived_key(key) || key.starts_with(TRASH_PREFIX)),
+            )
+        })
+        .map_err(Error::wrap);
+    let mut rows = IndexRows::new(&ctx.db);
+    let mut object = objects.try_next().await?;
+    // Whether a row has claimed `object`.
+    let mut claimed = false;
+    let mut row = rows.next().await?;
+    if object.is_some() {
+        report.objects += 1;
+    }
+
+    loop {
+        let row_key = row.as_ref().map(latest_path);
+        let ordering = match (&object, &row_key) {
+            (None, None) => break,
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (Some(o), Some(key)) => o.location.cmp(key),
+        };
+        match ordering {
+            std::cmp::Ordering::Less => {
+                let meta = object.take().expect("compared");
+                if !claimed {
+                    verify_unowned(ctx, store, options, &meta, cutoff, &mut report).await;
+                }
+                object = objects.try_next().await?;
+                claimed = false;
+                if object.is_some() {
+                    report.objects += 1;
+                }
+            }
+            std::cmp::Ordering::Greater => {
+                let f = row.take().expect("compared");
+                report.files += 1;
+                if f.orphaned {
+                    report.in_sync += 1;
+                } else if f.updated_at.and_utc() > cutoff {
+                    report.skipped_recent += 1;
+                } else {
+                    report.row_without_object.found(&f.name);
+                    if options.fix {
+                        match file::set_orphaned_if_unchanged(&ctx.db, &f, true).await {
+                            Ok(fixed) => report.row_without_object.fixed += u64::from(fixed),
+                            Err(e) => verify_failed(&mut report, &f.name, &e),
+                        }
+                    }
+                }
+                row = rows.next().await?;
+            }
+            std::cmp::Ordering::Equal => {
+                let (meta, f) = (
+                    object.as_ref().expect("compared"),
+                    row.take().expect("compared"),
+                );
+                claimed = true;
+                report.files += 1;
+                let size = meta.size as i64;
+                let sized = compare_sizes && !f.compressed;
+                if f.updated_at.and_utc() > cutoff {
+                    report.skipped_recent += 1;
+                } else if f.orphaned {
+                    report.row_restored.found(&f.name);
+                    if options.fix {
+                        match file::set_orphaned_if_unchanged(&ctx.db, &f, false).await {
+                            Ok(fixed) => report.row_restored.fixed += u64::from(fixed),
+                            Err(e) => verify_failed(&mut report, &f.name, &e),
+                        }
+                    }
+                } else if sized && f.size != size {
+                    report.size_mismatch.found(&f.name);
+                    if options.fix {
+                        match file::set_size_if_unchanged(&ctx.db, &f, size).await {
+                            Ok(fixed) => report.size_mismatch.fixed += u64::from(fixed),
+                            Err(e) => verify_failed(&mut report, &f.name, &e),
+                        }
+                    }
+                } else {
+                    report.in_sync += 1;
+                }
+                row = rows.next().await?;
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// The rows `verify_storage` walks, read in pages rather than streamed so
+/// that fixing one doesn't wait on the connection a stream would hold.
+pub(super) struct IndexRows<'a> {
+    db: &'a DatabaseConnection,
+    page: std::vec::IntoIter<file::Model>,
+    /// Where the next page starts, or `None` once the last one is read.
+    after: Option<Option<(String, i32)>>,
+}
+
+impl<'a> IndexRows<'a> {
+    fn new(db: &'a DatabaseConnection) -> Self {
+        Self {
+            db,
+            page: Vec::new().into_iter(),
+            after: Some(None),
+        }
+    }
+
+    async fn next(&mut self) -> Result<Option<file::Model>> {
+        loop {
+            if let Some(row) = self.page.next() {
+                return Ok(Some(row));
+            }
+            let Some(after) = self.after.take() else {
+                return Ok(None);
+            };
+            let page = file::page_by_object_key(
+                self.db,
+                after.as_ref().map(|(key, id)| (key.as_str(), *id)),
+                VERIFY_PAGE_SIZE,
+            )
+            .await?;
+            if page.len() as u64 == VERIFY_PAGE_SIZE {
+                let last = page.last().expect("a full page");
+                self.after = Some(Some((latest_path(last).as_ref().to_string(), last.id)));
+            }
+            self.page = page.into_iter();
+        }
+    }
+}
+
+/// An object no file claimed. Unused blobs are left to blob cleanup.
+pub(super) async fn verify_unowned(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    options: &VerifyOptions,
+    meta: &ObjectMeta,
+    cutoff: chrono::DateTime<Utc>,
+    report: &mut VerifyReport,
+) {
+    let key = meta.location.as_ref();
+    if key.starts_with("blobs/") {
+        return;
+    }
+    if meta.last_modified > cutoff {
+        report.skipped_recent += 1;
+        return;
+    }
+    report.object_without_row.found(key);
+    if !options.fix {
+        return;
+    }
+    let fixed = async {
+        // Indexed since it was listed.
+        if file::find_by_name_for_tenant(&ctx.db, key, None)
+            .await?
+            .is_some()
+        {
+            return Ok(false);
+        }
+        match options.orphan_objects {
+            OrphanObjects::Index => {
+                file::create_unattributed(&ctx.db, key, meta.size as i64, None).await?;
+            }
+            OrphanObjects::Delete => delete_object(store, &meta.location).await?,
+        }
+        Ok::<_, FileError>(true)
+    };
+    match fixed.await {
+        Ok(fixed) => report.object_without_row.fixed += u64::from(fixed),
+        Err(e) => verify_failed(report, key, &e),
+    }
+}
+
+pub(super) fn verify_failed(report: &mut VerifyReport, key: &str, error: &dyn std::fmt::Display) {
+    tracing::warn!(key, error = %error, "failed to fix storage drift");
+    report.failed += 1;
+}
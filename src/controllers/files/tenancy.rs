@@ -0,0 +1,100 @@
+//! Routing requests to the bucket of their tenant.
+
+use super::*;
+
+/// The tenant `route_tenant` sent a request to; `None` is the default bucket.
+#[derive(Debug, Clone, Default)]
+pub struct Tenant(pub Option<String>);
+
+impl Tenant {
+    pub(super) fn id(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+}
+
+pub(super) const TENANT_ID: HeaderName = HeaderName::from_static("x-tenant-id");
+
+/// Stores of the tenants requests have been routed to so far.
+pub(super) static TENANT_STORES: Mutex<BTreeMap<String, StorageBackend>> =
+    Mutex::new(BTreeMap::new());
+
+/// The tenant's store, built on first use. `None` for an unknown tenant.
+pub(super) fn tenant_backend(config: &S3Config, tenant: &str) -> Result<Option<StorageBackend>> {
+    let Some(storage) = config.tenants.get(tenant) else {
+        return Ok(None);
+    };
+    if let Some(backend) = TENANT_STORES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(tenant)
+    {
+        return Ok(Some(backend.clone()));
+    }
+    // Built outside the lock; of two racing requests, the first to finish wins.
+    let backend = create_store(&config.for_tenant(storage))?;
+    let mut stores = TENANT_STORES.lock().unwrap_or_else(|e| e.into_inner());
+    Ok(Some(
+        stores.entry(tenant.to_string()).or_insert(backend).clone(),
+    ))
+}
+
+/// The tenant named by `X-Tenant-Id` or the token's `tenant` claim, with its
+/// store. Naming an unknown tenant, or one the token doesn't belong to, is
+/// refused.
+pub(super) fn request_tenant(
+    config: &S3Config,
+    headers: &HeaderMap,
+) -> FileResult<Option<(String, StorageBackend)>> {
+    let header = headers
+        .get(&TENANT_ID)
+        .map(|v| v.to_str().map(str::trim))
+        .transpose()
+        .map_err(|_| FileError::BadRequest("X-Tenant-Id is not valid text".into()))?;
+    let claim = auth::claims_from_headers(headers)
+        .ok()
+        .and_then(|claims| claims.tenant);
+    let tenant = match (header, claim) {
+        (Some(header), Some(claim)) if header != claim => {
+            return Err(FileError::Rejected {
+                status: StatusCode::FORBIDDEN,
+                code: "tenant_mismatch".into(),
+                message: format!("The token belongs to tenant '{claim}', not '{header}'"),
+                details: None,
+            });
+        }
+        (Some(header), _) => header.to_string(),
+        (None, Some(claim)) => claim,
+        (None, None) => return Ok(None),
+    };
+    match tenant_backend(config, &tenant)? {
+        Some(backend) => Ok(Some((tenant, backend))),
+        None => Err(FileError::Rejected {
+            status: StatusCode::FORBIDDEN,
+            code: "unknown_tenant".into(),
+            message: format!("Unknown tenant '{tenant}'"),
+            details: None,
+        }),
+    }
+}
+
+/// Swaps the store extensions for those of the request's tenant, so handlers
+/// work on its bucket without knowing about tenants. Requests naming none
+/// stay on the default bucket.
+pub(super) async fn route_tenant(
+    State(config): State<Arc<S3Config>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let tenant = match request_tenant(&config, request.headers()) {
+        Ok(Some((tenant, (store, parts, circuit)))) => {
+            request.extensions_mut().insert(store);
+            request.extensions_mut().insert(parts);
+            request.extensions_mut().insert(circuit);
+            Some(tenant)
+        }
+        Ok(None) => None,
+        Err(e) => return e.into_response(),
+    };
+    request.extensions_mut().insert(Tenant(tenant));
+    next.run(request).await
+}
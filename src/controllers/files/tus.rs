@@ -0,0 +1,641 @@
+//! Resumable uploads over the tus protocol.
+
+use super::*;
+
+pub(super) const TUS_VERSION: &str = "1.0.0";
+pub(super) const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+pub(super) const TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+pub(super) const TUS_MAX_SIZE: HeaderName = HeaderName::from_static("tus-max-size");
+pub(super) const TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+pub(super) const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+pub(super) const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+pub(super) const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+pub(super) const UPLOAD_EXPIRES: HeaderName = HeaderName::from_static("upload-expires");
+pub(super) const TUS_CHUNK_CONTENT_TYPE: &str = "application/offset+octet-stream";
+/// Staging objects of resumable uploads, never listed or synced.
+pub(super) const TUS_PREFIX: &str = "tus/";
+
+/// Uploads with a `PATCH` running in this process.
+pub(super) static TUS_BUSY: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+/// Held while a `PATCH` appends to an upload; a second one is refused.
+pub(super) struct TusLease(String);
+
+impl TusLease {
+    fn acquire(id: &str) -> Option<Self> {
+        let mut busy = TUS_BUSY.lock().unwrap_or_else(|e| e.into_inner());
+        busy.insert(id.to_string()).then(|| Self(id.to_string()))
+    }
+}
+
+impl Drop for TusLease {
+    fn drop(&mut self) {
+        let mut busy = TUS_BUSY.lock().unwrap_or_else(|e| e.into_inner());
+        busy.remove(&self.0);
+    }
+}
+
+/// The multipart upload a tus upload is assembled in, completed into this key.
+pub(super) fn tus_staging_path(id: &str) -> ObjectPath {
+    ObjectPath::from(format!("{TUS_PREFIX}{id}"))
+}
+
+/// Bytes received but not yet enough to fill a part.
+pub(super) fn tus_tail_path(id: &str) -> ObjectPath {
+    ObjectPath::from(format!("{TUS_PREFIX}{id}.tail"))
+}
+
+pub(super) fn multipart_backend(parts: &PartStore) -> FileResult<&dyn MultipartStore> {
+    parts.0.as_deref().ok_or_else(|| FileError::Rejected {
+        status: StatusCode::NOT_IMPLEMENTED,
+        code: "multipart_unsupported".into(),
+        message: "Resumable and chunked uploads need a backend with multipart uploads".into(),
+        details: None,
+    })
+}
+
+/// The user the bearer token belongs to.
+pub(super) async fn token_author(ctx: &AppContext, headers: &HeaderMap) -> FileResult<user::Model> {
+    let claims = auth::claims_from_headers(headers).map_err(|_| FileError::Unauthorized)?;
+    let user_id: i32 = claims.pid.parse().unwrap_or(0);
+    user::find_by_id(&ctx.db, user_id)
+        .await?
+        .ok_or(FileError::Unauthorized)
+}
+
+/// Checks `Tus-Resumable` and the token, and returns the uploader.
+pub(super) async fn tus_author(ctx: &AppContext, headers: &HeaderMap) -> FileResult<user::Model> {
+    if headers.get(&TUS_RESUMABLE).and_then(|v| v.to_str().ok()) != Some(TUS_VERSION) {
+        return Err(FileError::Rejected {
+            status: StatusCode::PRECONDITION_FAILED,
+            code: "tus_version_unsupported".into(),
+            message: format!("Only tus {TUS_VERSION} is supported"),
+            details: Some(serde_json::json!({ "supported": [TUS_VERSION] })),
+        });
+    }
+    token_author(ctx, headers).await
+}
+
+pub(super) fn header_i64(headers: &HeaderMap, name: &HeaderName) -> Option<i64> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|v: &i64| *v >= 0)
+}
+
+/// `Upload-Metadata` is `key base64value` pairs separated by commas.
+/// `filename` names the file; `title`, `author`, `description` and
+/// `meta.<key>` are stored like the fields of a multipart upload.
+pub(super) fn parse_upload_metadata(
+    value: Option<&str>,
+) -> FileResult<(String, Option<FileMetadata>)> {
+    let mut file_name = None;
+    let mut metadata = FileMetadata::default();
+    let mut described = false;
+    for pair in value.unwrap_or_default().split(',') {
+        let pair = pair.trim();
+        if pair.is_empty() {
+            continue;
+        }
+        let (key, encoded) = pair.split_once(' ').unwrap_or((pair, ""));
+        let value = BASE64_STANDARD
+            .decode(encoded.trim())
+            .ok()
+            .and_then(|v| String::from_utf8(v).ok())
+            .ok_or_else(|| {
+                FileError::BadRequest(format!("Upload-Metadata '{key}' is not base64 UTF-8"))
+            })?;
+        match key {
+            "filename" => file_name = Some(value),
+            "title" => metadata.title = Some(value),
+            "author" => metadata.author = Some(value),
+            "description" => metadata.description = Some(value),
+            _ => match key.strip_prefix("meta.") {
+                Some(key) => {
+                    metadata.custom.insert(key.to_string(), value);
+                }
+                None => continue,
+            },
+        }
+        described |= key != "filename";
+    }
+    metadata.validate()?;
+
+    let file_name = file_name
+        .map(|name| normalize_name(&name))
+        .filter(|name| safe_path_segments(name).is_some())
+        .ok_or_else(|| FileError::BadRequest("Upload-Metadata needs a valid 'filename'".into()))?;
+    Ok((file_name, described.then_some(metadata)))
+}
+
+pub(super) fn tus_expires_at(
+    config: &S3Config,
+    upload: &tus_upload::Model,
+) -> chrono::DateTime<Utc> {
+    upload.updated_at.and_utc() + chrono::Duration::hours(config.tus_upload_ttl_hours)
+}
+
+/// An upload of `author` that hasn't expired yet.
+pub(super) async fn find_tus_upload(
+    ctx: &AppContext,
+    config: &S3Config,
+    author: &user::Model,
+    id: &str,
+) -> FileResult<tus_upload::Model> {
+    let upload = tus_upload::find(&ctx.db, id)
+        .await?
+        .filter(|u| u.author_id == author.id)
+        .ok_or_else(|| FileError::NotFound(format!("Upload '{id}' not found")))?;
+    if tus_expires_at(config, &upload) < Utc::now() {
+        return Err(FileError::Rejected {
+            status: StatusCode::GONE,
+            code: "upload_expired".into(),
+            message: format!("Upload '{id}' expired"),
+            details: None,
+        });
+    }
+    Ok(upload)
+}
+
+pub(super) fn http_date(at: chrono::DateTime<Utc>) -> String {
+    at.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Every tus response, errors included, names the protocol version.
+pub(super) fn tus_resumable_header() -> SetResponseHeaderLayer<HeaderValue> {
+    SetResponseHeaderLayer::overriding(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION))
+}
+
+/// Advertises the tus features served under `/files/tus`.
+#[utoipa::path(
+    options,
+    path = "/files/tus",
+    operation_id = "getTusCapabilities",
+    tag = "files",
+    responses(
+        (status = 204, description = "Supported tus version, extensions and maximum size"),
+    ),
+)]
+pub async fn tus_options(State(ctx): State<AppContext>) -> FileResult<Response> {
+    let config = get_s3_config(&ctx);
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header(&TUS_VERSION_HEADER, TUS_VERSION)
+        .header(&TUS_EXTENSION, "creation,expiration")
+        .header(&TUS_MAX_SIZE, config.max_file_size_bytes)
+        .body(Body::empty())
+        .map_err(|e| FileError::Internal(e.to_string()))
+}
+
+/// tus creation: reserves an upload of `Upload-Length` bytes and answers
+/// with its URL in `Location`.
+#[utoipa::path(
+    post,
+    path = "/files/tus",
+    operation_id = "createTusUpload",
+    tag = "files",
+    responses(
+        (status = 201, description = "Upload created; its URL is in `Location`"),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 413, description = "Upload-Length exceeds the limit", body = ErrorBody),
+        (status = 501, description = "The storage backend has no multipart uploads", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn create_tus_upload(
+    State(ctx): State<AppContext>,
+    Extension(parts): Extension<PartStore>,
+    headers: HeaderMap,
+) -> FileResult<Response> {
+    let author = tus_author(&ctx, &headers).await?;
+    let parts = multipart_backend(&parts)?;
+    let config = get_s3_config(&ctx);
+
+    if headers.contains_key("upload-defer-length") {
+        return Err(FileError::BadRequest(
+            "Upload-Defer-Length is not supported".into(),
+        ));
+    }
+    let length = header_i64(&headers, &UPLOAD_LENGTH)
+        .ok_or_else(|| FileError::BadRequest("Upload-Length is required".into()))?;
+    if length as u64 > config.max_file_size_bytes {
+        return Err(FileError::Rejected {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            code: "file_too_large".into(),
+            message: format!("File exceeds the {} byte limit", config.max_file_size_bytes),
+            details: None,
+        });
+    }
+    ensure_quota(&ctx, &config, author.id, length).await?;
+    let metadata = headers.get(&UPLOAD_METADATA).and_then(|v| v.to_str().ok());
+    let (file_name, metadata) = parse_upload_metadata(metadata)?;
+
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    let multipart_id = parts
+        .create_multipart(&tus_staging_path(&id))
+        .await
+        .map_err(FileError::StorageError)?;
+    let upload = tus_upload::create(
+        &ctx.db,
+        tus_upload::NewUpload {
+            id: &id,
+            file_name: &file_name,
+            upload_length: length,
+            author_id: author.id,
+            metadata: metadata.map(|m| serde_json::to_value(&m)).transpose()?,
+            multipart_id: &multipart_id,
+        },
+    )
+    .await?;
+    events::bus().publish(
+        ProgressKind::UploadStarted,
+        &file_name,
+        Some(author.id),
+        None,
+    );
+
+    if let Err(e) = ExpireTusUploadsWorker::perform_later(&ctx, ExpireTusUploadsArgs {}).await {
+        tracing::warn!(error = %e, "failed to schedule expiry of stale uploads");
+    }
+
+    Response::builder()
+        .status(StatusCode::CREATED)
+        .header(header::LOCATION, format!("/files/tus/{id}"))
+        .header(&UPLOAD_EXPIRES, http_date(tus_expires_at(&config, &upload)))
+        .body(Body::empty())
+        .map_err(|e| FileError::Internal(e.to_string()))
+}
+
+/// tus `HEAD`: how many bytes of the upload have arrived.
+#[utoipa::path(
+    head,
+    path = "/files/tus/{id}",
+    operation_id = "getTusUploadOffset",
+    tag = "files",
+    params(("id" = String, Path, description = "Upload id")),
+    responses(
+        (status = 200, description = "Bytes received so far, in `Upload-Offset`"),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn head_tus_upload(
+    State(ctx): State<AppContext>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> FileResult<Response> {
+    let author = tus_author(&ctx, &headers).await?;
+    let config = get_s3_config(&ctx);
+    let upload = find_tus_upload(&ctx, &config, &author, &id).await?;
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(&UPLOAD_OFFSET, upload.upload_offset)
+        .header(&UPLOAD_LENGTH, upload.upload_length)
+        .header(&UPLOAD_EXPIRES, http_date(tus_expires_at(&config, &upload)))
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::empty())
+        .map_err(|e| FileError::Internal(e.to_string()))
+}
+
+/// tus `PATCH`: appends the body at `Upload-Offset`, which has to match what
+/// was received so far. The last chunk turns the upload into a regular file.
+#[utoipa::path(
+    patch,
+    path = "/files/tus/{id}",
+    operation_id = "appendTusUpload",
+    tag = "files",
+    params(("id" = String, Path, description = "Upload id")),
+    request_body(content = [u8], content_type = "application/offset+octet-stream"),
+    responses(
+        (status = 204, description = "Chunk stored; the new offset is in `Upload-Offset`"),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 409, description = "`Upload-Offset` does not match the upload", body = ErrorBody),
+        (status = 501, description = "The storage backend has no multipart uploads", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn patch_tus_upload(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(parts): Extension<PartStore>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    body: Body,
+) -> FileResult<Response> {
+    let author = tus_author(&ctx, &headers).await?;
+    let parts = multipart_backend(&parts)?;
+    let config = get_s3_config(&ctx);
+
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    if content_type != Some(TUS_CHUNK_CONTENT_TYPE) {
+        return Err(FileError::Rejected {
+            status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            code: "unsupported_media_type".into(),
+            message: format!("Chunks must be sent as {TUS_CHUNK_CONTENT_TYPE}"),
+            details: None,
+        });
+    }
+    let offset = header_i64(&headers, &UPLOAD_OFFSET)
+        .ok_or_else(|| FileError::BadRequest("Upload-Offset is required".into()))?;
+
+    let upload = find_tus_upload(&ctx, &config, &author, &id).await?;
+    let offset_mismatch = |expected: i64| FileError::Rejected {
+        status: StatusCode::CONFLICT,
+        code: "offset_mismatch".into(),
+        message: format!("Upload '{id}' is at offset {expected}, not {offset}"),
+        details: Some(serde_json::json!({ "expected": expected })),
+    };
+    if offset != upload.upload_offset {
+        return Err(offset_mismatch(upload.upload_offset));
+    }
+    let Some(_lease) = TusLease::acquire(&id) else {
+        return Err(offset_mismatch(upload.upload_offset));
+    };
+
+    let offset = append_tus_chunk(&ctx, store.as_ref(), parts, &upload, body).await?;
+    if offset == upload.upload_length {
+        let upload = tus_upload::find(&ctx.db, &id)
+            .await?
+            .ok_or_else(|| FileError::NotFound(format!("Upload '{id}' not found")))?;
+        finish_tus_upload(
+            &ctx,
+            &config,
+            store.as_ref(),
+            parts,
+            &upload,
+            &author,
+            &tenant,
+        )
+        .await?;
+    }
+
+    let mut response = Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header(&UPLOAD_OFFSET, offset);
+    if offset < upload.upload_length {
+        let expires = Utc::now() + chrono::Duration::hours(config.tus_upload_ttl_hours);
+        response = response.header(&UPLOAD_EXPIRES, http_date(expires));
+    }
+    response
+        .body(Body::empty())
+        .map_err(|e| FileError::Internal(e.to_string()))
+}
+
+/// Streams `body` into parts of the upload and records the new offset.
+/// Whatever arrived before the connection dropped is kept, so the client
+/// can resume from there.
+pub(super) async fn append_tus_chunk(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    parts: &dyn MultipartStore,
+    upload: &tus_upload::Model,
+    body: Body,
+) -> FileResult<i64> {
+    let staging = tus_staging_path(&upload.id);
+    let tail_path = tus_tail_path(&upload.id);
+    let mut part_ids = upload.part_ids();
+    let mut tail = if upload.tail_size > 0 {
+        read_object(store, &tail_path).await?.to_vec()
+    } else {
+        Vec::new()
+    };
+
+    let mut offset = upload.upload_offset;
+    let mut interrupted = None;
+    let mut chunks = body.into_data_stream();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                interrupted = Some(FileError::BadRequest(format!("Upload interrupted: {e}")));
+                break;
+            }
+        };
+        if offset + chunk.len() as i64 > upload.upload_length {
+            interrupted = Some(FileError::BadRequest(format!(
+                "Body runs past the Upload-Length of {} bytes",
+                upload.upload_length
+            )));
+            break;
+        }
+        offset += chunk.len() as i64;
+        tail.extend_from_slice(&chunk);
+
+        while tail.len() >= MIN_PART_BYTES {
+            let rest = tail.split_off(MIN_PART_BYTES);
+            let part = std::mem::replace(&mut tail, rest);
+            let part_id = parts
+                .put_part(
+                    &staging,
+                    &upload.multipart_id,
+                    part_ids.len(),
+                    Bytes::from(part).into(),
+                )
+                .await
+                .map_err(FileError::StorageError)?;
+            part_ids.push(part_id.content_id);
+        }
+    }
+
+    if !tail.is_empty() {
+        store
+            .put(&tail_path, Bytes::from(tail.clone()).into())
+            .await
+            .map_err(FileError::StorageError)?;
+    } else if upload.tail_size > 0 {
+        let _ = store.delete(&tail_path).await;
+    }
+    let recorded = tus_upload::advance(
+        &ctx.db,
+        &upload.id,
+        upload.upload_offset,
+        offset,
+        &part_ids,
+        tail.len() as i64,
+    )
+    .await?;
+    if !recorded {
+        return Err(FileError::Rejected {
+            status: StatusCode::CONFLICT,
+            code: "offset_mismatch".into(),
+            message: format!("Upload '{}' moved on during this request", upload.id),
+            details: None,
+        });
+    }
+
+    match interrupted {
+        Some(e) => Err(e),
+        None => Ok(offset),
+    }
+}
+
+pub(super) async fn read_object(store: &dyn ObjectStore, path: &ObjectPath) -> FileResult<Bytes> {
+    store
+        .get(path)
+        .await
+        .map_err(FileError::StorageError)?
+        .bytes()
+        .await
+        .map_err(FileError::StorageError)
+}
+
+/// Completes the multipart upload and stores the result like any other
+/// upload. The staging objects and the row go away either way, so a file
+/// the pipeline rejects has to be uploaded again.
+pub(super) async fn finish_tus_upload(
+    ctx: &AppContext,
+    config: &S3Config,
+    store: &dyn ObjectStore,
+    parts: &dyn MultipartStore,
+    upload: &tus_upload::Model,
+    author: &user::Model,
+    tenant: &Tenant,
+) -> FileResult<()> {
+    let assembled = assemble_tus_upload(store, parts, upload).await;
+    let stored = match assembled {
+        Ok(bytes) => {
+            let metadata: Option<FileMetadata> = upload
+                .metadata
+                .clone()
+                .and_then(|m| serde_json::from_value(m).ok());
+            store_new_file(
+                ctx,
+                config,
+                store,
+                &upload.file_name,
+                bytes,
+                author,
+                metadata.as_ref(),
+                tenant.id(),
+            )
+            .await
+            .map_err(FileError::from)
+        }
+        Err(e) => Err(e),
+    };
+    discard_tus_upload(ctx, store, Some(parts), upload).await?;
+
+    let mut stored = stored?;
+    after_upload_stored(ctx, config, store, author, &mut stored).await;
+    Ok(())
+}
+
+pub(super) async fn assemble_tus_upload(
+    store: &dyn ObjectStore,
+    parts: &dyn MultipartStore,
+    upload: &tus_upload::Model,
+) -> FileResult<Bytes> {
+    let staging = tus_staging_path(&upload.id);
+    let tail = if upload.tail_size > 0 {
+        Some(read_object(store, &tus_tail_path(&upload.id)).await?)
+    } else {
+        None
+    };
+
+    let mut part_ids: Vec<PartId> = upload
+        .part_ids()
+        .into_iter()
+        .map(|content_id| PartId { content_id })
+        .collect();
+    if part_ids.is_empty() {
+        // Too small for a single part: store the tail as the whole object.
+        store
+            .put(&staging, tail.unwrap_or_default().into())
+            .await
+            .map_err(FileError::StorageError)?;
+    } else {
+        if let Some(tail) = tail {
+            let part_id = parts
+                .put_part(&staging, &upload.multipart_id, part_ids.len(), tail.into())
+                .await
+                .map_err(FileError::StorageError)?;
+            part_ids.push(part_id);
+        }
+        parts
+            .complete_multipart(&staging, &upload.multipart_id, part_ids)
+            .await
+            .map_err(FileError::StorageError)?;
+    }
+    read_object(store, &staging).await
+}
+
+/// Drops the row of an upload along with its staging objects, aborting the
+/// multipart upload if it is still open.
+pub(super) async fn discard_tus_upload(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    parts: Option<&dyn MultipartStore>,
+    upload: &tus_upload::Model,
+) -> Result<()> {
+    let staging = tus_staging_path(&upload.id);
+    if let Some(parts) = parts {
+        // Fails harmlessly once the upload was completed.
+        let _ = parts.abort_multipart(&staging, &upload.multipart_id).await;
+    }
+    let _ = store.delete(&staging).await;
+    let _ = store.delete(&tus_tail_path(&upload.id)).await;
+    tus_upload::delete(&ctx.db, &upload.id).await?;
+    Ok(())
+}
+
+/// Discards uploads without progress for `tus_upload_ttl_hours`. Returns how
+/// many were removed.
+pub async fn expire_tus_uploads(ctx: &AppContext) -> Result<usize> {
+    let config = get_s3_config(ctx);
+    let (store, parts, _) = shared_backend(&config)?;
+    let before = Utc::now().naive_utc() - chrono::Duration::hours(config.tus_upload_ttl_hours);
+    let stale = tus_upload::find_stale(&ctx.db, before).await?;
+    for upload in &stale {
+        tracing::info!(id = %upload.id, file_name = %upload.file_name, "expiring stale upload");
+        discard_tus_upload(ctx, store.as_ref(), parts.0.as_deref(), upload).await?;
+    }
+    Ok(stale.len())
+}
+
+/// Aborts multipart uploads older than `multipart_max_age_hours` that no
+/// resumable or chunked upload is still assembling, in the default bucket
+/// and every tenant's. Only S3 keeps such uploads.
+pub async fn abort_orphaned_uploads(ctx: &AppContext) -> Result<Sweep> {
+    let config = get_s3_config(ctx);
+    if config.local_storage_path.is_some() || config.backend != "s3" {
+        return Ok(Sweep::default());
+    }
+    let keep: HashSet<String> = tus_upload::multipart_ids(&ctx.db)
+        .await?
+        .into_iter()
+        .chain(chunked_upload::multipart_ids(&ctx.db).await?)
+        .collect();
+    let cutoff = Utc::now() - chrono::Duration::hours(config.multipart_max_age_hours);
+
+    let buckets = std::iter::once(config.clone())
+        .chain(config.tenants.values().map(|t| config.for_tenant(t)));
+    let mut total = Sweep::default();
+    for bucket in buckets {
+        let client = BucketClient::new(
+            &bucket.endpoint,
+            &bucket.bucket,
+            &bucket.region,
+            &bucket.access_key,
+            &bucket.secret_key,
+            Duration::from_secs(bucket.operation_timeout_seconds),
+        )
+        .map_err(|e| Error::Message(e.to_string()))?;
+        match multipart_gc::abort_stale(&client, cutoff, &keep).await {
+            Ok(sweep) => {
+                total.aborted += sweep.aborted;
+                total.total_bytes_freed += sweep.total_bytes_freed;
+            }
+            Err(e) => {
+                tracing::warn!(bucket = %bucket.bucket, error = %e, "could not list multipart uploads");
+            }
+        }
+    }
+    Ok(total)
+}
@@ -0,0 +1,1621 @@
+//! `POST /files` and the other ways content arrives in one request.
+
+use super::*;
+
+#[utoipa::path(
+    post,
+    path = "/files",
+    operation_id = "uploadFile",
+    tag = "files",
+    params(
+        UploadParams,
+        ("If-Match" = Option<String>, Header, description = "Only overwrite a file whose current ETag is one of these, or any existing file for `*`"),
+        ("If-None-Match" = Option<String>, Header, description = "`*`: only create files, never overwrite one"),
+        ("Content-MD5" = Option<String>, Header, description = "Base64 MD5 every file must match; file parts may carry their own"),
+        ("x-amz-checksum-sha256" = Option<String>, Header, description = "Base64 SHA-256 every file must match"),
+        ("x-amz-checksum-crc32" = Option<String>, Header, description = "Base64 big-endian CRC32 every file must match"),
+    ),
+    request_body(content = UploadForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "At least one file was stored. A single stored file's ETag is also in the `ETag` header", body = UploadResponse),
+        (status = 400, description = "No file could be stored, or the request is malformed", body = UploadFailure),
+        (status = 422, description = "Every file was rejected", body = UploadResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 409, description = "The idempotency key is in use by another request, or another upload of the file is running", body = ErrorBody),
+        (status = 412, description = "A file does not match `If-Match` or `If-None-Match`; nothing of it was written", body = ErrorBody),
+        (status = 413, description = "The body exceeds `max_upload_body_bytes`, or the quota", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_file(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    remote_ip: RemoteIP,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
+    Query(params): Query<UploadParams>,
+    multipart: Multipart,
+) -> FileResult<Response> {
+    let auth_header = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(FileError::Unauthorized)?;
+
+    let token = auth_header.strip_prefix("Bearer ").unwrap_or(auth_header);
+    let claims =
+        crate::controllers::auth::decode_token(token).map_err(|_| FileError::Unauthorized)?;
+
+    let config = get_s3_config(&ctx);
+
+    let user_id: i32 = claims.pid.parse().unwrap_or(0);
+    let author = user::find_by_id(&ctx.db, user_id)
+        .await?
+        .ok_or(FileError::Unauthorized)?;
+    let actor = request_actor(
+        &headers,
+        Some(author.id),
+        client_ip(remote_ip, connect_info),
+    );
+    // The body is a little larger than its files; close enough to refuse
+    // an upload that can't fit before any of it is read.
+    if let Some(length) = header_i64(&headers, &header::CONTENT_LENGTH) {
+        ensure_quota(&ctx, &config, author.id, length).await?;
+    }
+
+    let checksums = client_digests(&headers)?;
+    let precondition = upload_precondition(&headers)?;
+    if precondition.is_some() && params.extract {
+        return Err(FileError::BadRequest(
+            "If-Match and If-None-Match can't be combined with extract".into(),
+        ));
+    }
+
+    let idempotency_key = headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+        .map(String::from);
+
+    let Some(key) = idempotency_key else {
+        let processed = process_upload(
+            &ctx,
+            &config,
+            store.as_ref(),
+            &params,
+            &author,
+            &tenant,
+            &checksums,
+            precondition.as_ref(),
+            multipart,
+        )
+        .await;
+        audit_upload(&ctx, &actor, &processed);
+        let (status, response, _) = processed?;
+        return Ok(upload_reply(status, serde_json::to_value(&response)?));
+    };
+
+    let Some(claim_token) =
+        upload_idempotency_key::claim(&ctx.db, tenant.id(), author.id, &key).await?
+    else {
+        let replay = replay_upload(&ctx, tenant.id(), author.id, &key, &params, multipart);
+        return Ok(replay.await?);
+    };
+
+    let processed = holding_claim(
+        &ctx,
+        &claim_token,
+        process_upload(
+            &ctx,
+            &config,
+            store.as_ref(),
+            &params,
+            &author,
+            &tenant,
+            &checksums,
+            precondition.as_ref(),
+            multipart,
+        ),
+    )
+    .await;
+    audit_upload(&ctx, &actor, &processed);
+    match processed {
+        Ok((status, response, request_hash)) => {
+            let body = serde_json::to_value(&response)?;
+            upload_idempotency_key::complete(
+                &ctx.db,
+                &claim_token,
+                &request_hash,
+                i32::from(status.as_u16()),
+                body.clone(),
+            )
+            .await?;
+            Ok(upload_reply(status, body))
+        }
+        Err(e) => {
+            upload_idempotency_key::release(&ctx.db, &claim_token).await?;
+            Err(e.into())
+        }
+    }
+}
+
+/// Runs `upload` while renewing the lease of its Idempotency-Key claim, so
+/// the key stays held for as long as the upload runs, however long that is.
+pub(super) async fn holding_claim<T>(
+    ctx: &AppContext,
+    claim_token: &str,
+    upload: impl std::future::Future<Output = T>,
+) -> T {
+    let period = std::time::Duration::from_secs(
+        u64::try_from(upload_idempotency_key::CLAIM_LEASE_MINUTES * 60 / 2).unwrap_or(60),
+    );
+    let renewals = async {
+        let mut every = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            every.tick().await;
+            match upload_idempotency_key::renew(&ctx.db, claim_token).await {
+                Ok(true) => {}
+                Ok(false) => tracing::warn!("idempotency key claim was lost while uploading"),
+                Err(e) => tracing::warn!(error = %e, "failed to renew idempotency key claim"),
+            }
+        }
+    };
+    tokio::select! {
+        out = upload => out,
+        () = renewals => unreachable!("claim renewals never end"),
+    }
+}
+
+/// One audit entry per file of the request, or a single failure when the
+/// request as a whole was refused. Replays of an idempotent upload add none.
+pub(super) fn audit_upload(
+    ctx: &AppContext,
+    actor: &Actor,
+    processed: &Result<(StatusCode, UploadResponse, String)>,
+) {
+    match processed {
+        Ok((status, response, _)) => {
+            for result in &response.results {
+                let mut entry = Entry::new(Operation::Upload, Some(&result.name), actor);
+                entry.bytes = Some(result.size);
+                entry.status_code = Some(status.as_u16());
+                if let UploadStatus::Rejected | UploadStatus::Failed = result.status {
+                    entry = entry.failed(result.reason.as_deref().unwrap_or("Upload failed"));
+                }
+                access_log::record(ctx, &entry);
+                audit_writer::log(ctx, entry);
+            }
+        }
+        Err(e) => {
+            let entry = Entry::new(Operation::Upload, None, actor).failed(e.to_string());
+            access_log::record(ctx, &entry);
+            audit_writer::log(ctx, entry);
+        }
+    }
+}
+
+/// Runs every multipart field through validation and storage, returning the
+/// overall status, the per-file report and a hash of the request payload.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn process_upload(
+    ctx: &AppContext,
+    config: &S3Config,
+    store: &dyn ObjectStore,
+    params: &UploadParams,
+    author: &user::Model,
+    tenant: &Tenant,
+    expected_checksums: &[ClientDigest],
+    precondition: Option<&UploadPrecondition>,
+    mut multipart: Multipart,
+) -> Result<(StatusCode, UploadResponse, String)> {
+    let mut results = Vec::new();
+    let mut hasher = upload_hasher(params);
+    let mut metadata: Option<FileMetadata> = None;
+    let mut destination: Option<String> = None;
+    let mut seen_file = false;
+
+    // A broken multipart stream can't be resumed, but a bad file can be
+    // skipped: every field gets its own outcome and the loop carries on.
+    while let Some(field) = multipart.next_field().await.map_err(|e| {
+        multipart_error(config.max_request_body_bytes, e, |e| {
+            Error::Message(format!("Multipart error: {e}"))
+        })
+    })? {
+        let field_name = field.name().unwrap_or_default().to_string();
+        let file_name = field.file_name().map(normalize_name);
+        let display_name = file_name.clone().unwrap_or_else(|| field_name.clone());
+        if let Some(name) = &file_name {
+            events::bus().publish(ProgressKind::UploadStarted, name, Some(author.id), None);
+        }
+
+        // Files stop being read once they outgrow what is left of the quota.
+        let quota = match &file_name {
+            Some(_) => quota_usage(ctx, config, author.id).await?,
+            None => None,
+        };
+        let limit = quota.map(|(used, limit)| (limit - used).max(0) as u64);
+        // A file part's own digest headers add to the request's.
+        let mut expected = expected_checksums.to_vec();
+        if file_name.is_some() {
+            expected.extend(client_digests(field.headers())?);
+        }
+        let bytes = match read_field(field, limit).await {
+            Ok(FieldBytes::Complete(bytes)) => bytes,
+            Ok(FieldBytes::TooLarge { received }) => {
+                let (used, limit) = quota.unwrap_or_default();
+                return Err(quota_exceeded(used, limit, received as i64));
+            }
+            Err(e) if is_body_limit(&e) => {
+                return Err(request_too_large(config.max_request_body_bytes));
+            }
+            Err(e) => {
+                tracing::warn!(file = %display_name, error = %e, "failed to read an uploaded file");
+                results.push(UploadOutcome::failed(
+                    &display_name,
+                    0,
+                    "The file could not be read",
+                ));
+                continue;
+            }
+        };
+        hash_upload_field(&mut hasher, &field_name, file_name.as_deref(), &bytes);
+        let size = bytes.len() as i64;
+
+        // The `metadata` and `meta.<key>` parts describe the files that come after them.
+        if field_name == "metadata" && file_name.is_none() {
+            let mut parsed: FileMetadata = serde_json::from_slice(&bytes)
+                .map_err(|e| Error::BadRequest(format!("Invalid metadata: {e}")))?;
+            parsed.thumbnail = None;
+            parsed.preview = None;
+            parsed.preview_error = None;
+            if let Some(previous) = metadata.take() {
+                for (key, value) in previous.custom {
+                    parsed.custom.entry(key).or_insert(value);
+                }
+            }
+            parsed.validate()?;
+            metadata = Some(parsed);
+            continue;
+        }
+        if field_name == "destination_prefix" && file_name.is_none() {
+            if seen_file {
+                return Err(Error::BadRequest(
+                    "destination_prefix must come before the files".into(),
+                ));
+            }
+            let value = std::str::from_utf8(&bytes)
+                .map_err(|_| Error::BadRequest("destination_prefix is not UTF-8".into()))?;
+            destination = destination_prefix(value)?;
+            continue;
+        }
+        if let Some(key) = field_name.strip_prefix("meta.")
+            && file_name.is_none()
+        {
+            let value = String::from_utf8(bytes.to_vec())
+                .map_err(|_| Error::BadRequest(format!("Metadata '{key}' is not UTF-8")))?;
+            let current = metadata.get_or_insert_default();
+            current.custom.insert(key.to_string(), value);
+            current.validate()?;
+            continue;
+        }
+
+        seen_file = true;
+        let Some(file_name) = file_name else {
+            results.push(UploadOutcome::rejected(
+                &display_name,
+                size,
+                "No filename in multipart field",
+            ));
+            continue;
+        };
+        let file_name = match &destination {
+            Some(prefix) => format!("{prefix}/{file_name}"),
+            None => file_name,
+        };
+
+        let put_as = if params.extract {
+            let target_prefix = match (&destination, &params.target_prefix) {
+                (Some(prefix), Some(target)) => Some(format!("{prefix}/{target}")),
+                (_, target) => target.clone(),
+            };
+            PutAs::ZipEntries { target_prefix }
+        } else {
+            PutAs::File
+        };
+        let pipeline = upload_pipeline(
+            ctx,
+            config,
+            store,
+            params,
+            author,
+            tenant,
+            expected,
+            precondition,
+            put_as,
+        );
+        let upload = UploadContext::new(file_name.clone(), bytes, metadata.clone());
+        match pipeline.run(upload).await {
+            Ok(upload) => match upload.finished {
+                Some(Finished::Stored(mut files)) => {
+                    for f in &mut files {
+                        after_upload_stored(ctx, config, store, author, f).await;
+                    }
+                    results.extend(files.into_iter().map(UploadOutcome::stored));
+                }
+                Some(Finished::Deduplicated(existing)) => {
+                    results.push(UploadOutcome::deduplicated(*existing));
+                }
+                None => results.push(UploadOutcome::failed(
+                    &file_name,
+                    size,
+                    "Upload was not stored",
+                )),
+            },
+            Err(StepError::File(e)) => {
+                results.push(UploadOutcome::from_error(&file_name, size, e));
+            }
+            Err(StepError::Request(e)) => return Err(e),
+        }
+    }
+
+    if results.is_empty() {
+        return Err(Error::BadRequest("No files in request".into()));
+    }
+
+    let status = if results
+        .iter()
+        .any(|r| matches!(r.status, UploadStatus::Stored | UploadStatus::Deduplicated))
+    {
+        StatusCode::OK
+    } else if results.iter().all(|r| r.status == UploadStatus::Rejected) {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+
+    let uploaded = results.iter().filter_map(|r| r.file.clone()).collect();
+
+    Ok((
+        status,
+        UploadResponse { uploaded, results },
+        hex::encode(hasher.finalize()),
+    ))
+}
+
+/// Where soft-deleted files go; uploads can't be sent there.
+pub(super) const TRASH_PREFIX: &str = "__trash__/";
+
+/// Checks a `destination_prefix` field, returning it without its slashes, or
+/// `None` if it is blank.
+pub(super) fn destination_prefix(value: &str) -> Result<Option<String>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let prefix = safe_path_segments(value)
+        .ok_or_else(|| unprocessable("invalid_prefix", "Invalid destination prefix"))?
+        .join("/");
+    let key = format!("{prefix}/");
+    if key.starts_with(TRASH_PREFIX) || is_derived_key(&key) {
+        return Err(unprocessable(
+            "reserved_prefix",
+            &format!("'{prefix}/' is reserved for the server"),
+        ));
+    }
+    Ok(Some(prefix))
+}
+
+pub(super) enum FieldBytes {
+    Complete(Bytes),
+    TooLarge { received: u64 },
+}
+
+/// Reads a multipart field whole, unless it grows past `limit` bytes first.
+pub(super) async fn read_field(
+    mut field: Field<'_>,
+    limit: Option<u64>,
+) -> std::result::Result<FieldBytes, MultipartError> {
+    let Some(limit) = limit else {
+        return field.bytes().await.map(FieldBytes::Complete);
+    };
+    let mut bytes = Vec::new();
+    while let Some(chunk) = field.chunk().await? {
+        bytes.extend_from_slice(&chunk);
+        if bytes.len() as u64 > limit {
+            return Ok(FieldBytes::TooLarge {
+                received: bytes.len() as u64,
+            });
+        }
+    }
+    Ok(FieldBytes::Complete(bytes.into()))
+}
+
+/// Room a multipart body needs beyond its file: boundaries, part headers
+/// and the `metadata` fields.
+pub(super) const MULTIPART_BODY_OVERHEAD: u64 = 1024 * 1024;
+
+/// Largest body `POST /files/sync` reads.
+pub(super) fn sync_body_limit(config: &S3Config) -> u64 {
+    config.max_file_size_bytes + MULTIPART_BODY_OVERHEAD
+}
+
+/// Refuses a body over a route's `DefaultBodyLimit` like the size checks
+/// refuse a file, so clients see one kind of 413.
+pub(super) fn request_too_large(limit: u64) -> Error {
+    Error::CustomError(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        ErrorDetail::new(
+            "file_too_large",
+            &format!("Request body exceeds the {limit} byte limit"),
+        ),
+    )
+}
+
+/// A multipart body stops being readable once it passes the route's limit.
+pub(super) fn is_body_limit(e: &MultipartError) -> bool {
+    e.status() == StatusCode::PAYLOAD_TOO_LARGE
+}
+
+pub(super) fn multipart_error(
+    limit: u64,
+    e: MultipartError,
+    otherwise: impl FnOnce(MultipartError) -> Error,
+) -> Error {
+    if is_body_limit(&e) {
+        request_too_large(limit)
+    } else {
+        otherwise(e)
+    }
+}
+
+/// Turns the plain-text 413 axum's extractors answer with past a route's
+/// `DefaultBodyLimit`, or `RequestBodyLimitLayer` with, into `request_too_large`.
+pub(super) async fn json_body_limit<B>(State(limit): State<u64>, response: Response<B>) -> Response
+where
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<axum::BoxError>,
+{
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"application/json"));
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE && !is_json {
+        return FileError::from(request_too_large(limit)).into_response();
+    }
+    response.map(Body::new)
+}
+
+/// Digest headers S3 clients send, with the size of the digest they carry.
+pub(super) const CLIENT_DIGEST_HEADERS: [(&str, DigestAlgorithm, usize); 3] = [
+    ("content-md5", DigestAlgorithm::Md5, 16),
+    ("x-amz-checksum-sha256", DigestAlgorithm::Sha256, 32),
+    ("x-amz-checksum-crc32", DigestAlgorithm::Crc32, 4),
+];
+
+/// The digests sent with some content: `X-Upload-Checksum: sha256=<hex>`,
+/// and the base64 `Content-MD5`, `x-amz-checksum-sha256` and
+/// `x-amz-checksum-crc32`. Sent with `POST /files` itself, every file part
+/// has to match them, so they are meant for single-file uploads; a part can
+/// carry its own.
+pub(super) fn client_digests(headers: &HeaderMap) -> Result<Vec<ClientDigest>> {
+    let mut digests = Vec::new();
+    if let Some(sha256) = upload_checksum(headers)? {
+        digests.push(ClientDigest {
+            algorithm: DigestAlgorithm::Sha256,
+            expected: sha256,
+        });
+    }
+    for (name, algorithm, len) in CLIENT_DIGEST_HEADERS {
+        let Some(value) = headers.get(name) else {
+            continue;
+        };
+        let digest = value
+            .to_str()
+            .ok()
+            .and_then(|v| BASE64_STANDARD.decode(v.trim()).ok())
+            .filter(|d| d.len() == len)
+            .ok_or_else(|| {
+                Error::CustomError(
+                    StatusCode::BAD_REQUEST,
+                    ErrorDetail::new(
+                        "invalid_checksum_header",
+                        &format!("{name} must be the base64 of a {len}-byte digest"),
+                    ),
+                )
+            })?;
+        digests.push(ClientDigest {
+            algorithm,
+            expected: hex::encode(digest),
+        });
+    }
+    // Better refused than stored unchecked by a client that expects a check.
+    if let Some(name) = headers.keys().map(HeaderName::as_str).find(|name| {
+        name.starts_with("x-amz-checksum-")
+            && *name != "x-amz-checksum-type"
+            && !CLIENT_DIGEST_HEADERS
+                .iter()
+                .any(|(known, ..)| known == name)
+    }) {
+        return Err(Error::CustomError(
+            StatusCode::BAD_REQUEST,
+            ErrorDetail::new(
+                "unsupported_checksum",
+                &format!(
+                    "{name} is not supported; send Content-MD5, x-amz-checksum-sha256 or x-amz-checksum-crc32"
+                ),
+            ),
+        ));
+    }
+    Ok(digests)
+}
+
+/// Parses `X-Upload-Checksum: sha256=<hex>` into the lowercase digest.
+pub(super) fn upload_checksum(headers: &HeaderMap) -> Result<Option<String>> {
+    let Some(value) = headers.get(UPLOAD_CHECKSUM_HEADER) else {
+        return Ok(None);
+    };
+
+    let digest = value
+        .to_str()
+        .ok()
+        .and_then(|v| v.trim().strip_prefix("sha256="))
+        .filter(|d| d.len() == 64 && d.bytes().all(|b| b.is_ascii_hexdigit()))
+        .ok_or_else(|| {
+            Error::CustomError(
+                StatusCode::BAD_REQUEST,
+                ErrorDetail::new(
+                    "invalid_checksum_header",
+                    "X-Upload-Checksum must be sha256=<64 hex digits>",
+                ),
+            )
+        })?;
+
+    Ok(Some(digest.to_ascii_lowercase()))
+}
+
+/// The steps each file of a `POST /files` goes through.
+#[allow(clippy::too_many_arguments)]
+pub(super) fn upload_pipeline<'a>(
+    ctx: &'a AppContext,
+    config: &'a S3Config,
+    store: &'a dyn ObjectStore,
+    params: &UploadParams,
+    author: &'a user::Model,
+    tenant: &'a Tenant,
+    expected_checksums: Vec<ClientDigest>,
+    precondition: Option<&'a UploadPrecondition>,
+    put_as: PutAs,
+) -> UploadPipeline<'a> {
+    let mut pipeline = UploadPipeline::new()
+        .step(ChecksumStep {
+            expected: expected_checksums,
+        })
+        .step(SanitizeStep {
+            max_size: config.max_file_size_bytes,
+        })
+        .step(HookStep {
+            hooks: storage_hooks::configured(ctx),
+        });
+    if params.deduplicate && !params.extract {
+        pipeline = pipeline.step(DeduplicateStep {
+            ctx,
+            config,
+            tenant,
+        });
+    }
+    if matches!(put_as, PutAs::File) {
+        pipeline = pipeline.step(CompressStep {
+            enabled: compresses_uploads(config),
+        });
+    }
+    pipeline.step(S3PutStep {
+        ctx,
+        config,
+        store,
+        author,
+        tenant,
+        precondition,
+        put_as,
+    })
+}
+
+/// Whether new files are worth gzipping. Local files can't carry the
+/// `Content-Encoding` that marks them as gzip, and blobs are shared.
+pub(super) fn compresses_uploads(config: &S3Config) -> bool {
+    config.compress_text_uploads && config.local_storage_path.is_none() && !config.dedup
+}
+
+/// Finishes an upload whose content some file of the tenant already has
+/// with that file, instead of storing it again.
+pub(super) struct DeduplicateStep<'a> {
+    ctx: &'a AppContext,
+    config: &'a S3Config,
+    tenant: &'a Tenant,
+}
+
+#[async_trait]
+impl UploadStep for DeduplicateStep<'_> {
+    async fn process(&self, mut upload: UploadContext) -> Result<UploadContext, StepError> {
+        let content_hash = upload.hash_content();
+        let found =
+            file::find_by_content_hash_with_author(&self.ctx.db, &content_hash, self.tenant.id())
+                .await
+                .map_err(|e| StepError::Request(e.into()))?;
+        if let Some((existing, owner)) = found {
+            upload.finished = Some(Finished::Deduplicated(Box::new(file_info(
+                self.config,
+                existing,
+                owner.as_ref(),
+            ))));
+        }
+        Ok(upload)
+    }
+}
+
+pub(super) enum PutAs {
+    File,
+    /// The upload is a zip whose entries are stored, under `target_prefix`
+    /// or a folder named after it.
+    ZipEntries {
+        target_prefix: Option<String>,
+    },
+}
+
+/// Writes the upload under its name's upload lock, once any `If-Match` or
+/// `If-None-Match` holds.
+pub(super) struct S3PutStep<'a> {
+    ctx: &'a AppContext,
+    config: &'a S3Config,
+    store: &'a dyn ObjectStore,
+    author: &'a user::Model,
+    tenant: &'a Tenant,
+    precondition: Option<&'a UploadPrecondition>,
+    put_as: PutAs,
+}
+
+impl S3PutStep<'_> {
+    async fn put(&self, upload: &mut UploadContext) -> Result<Vec<StoredFile>> {
+        match &self.put_as {
+            PutAs::File => {
+                let stored = store_upload(
+                    self.ctx,
+                    self.config,
+                    self.store,
+                    upload,
+                    self.author,
+                    self.tenant.id(),
+                )
+                .await?;
+                Ok(vec![stored])
+            }
+            PutAs::ZipEntries { target_prefix } => {
+                extract_zip_upload(
+                    self.ctx,
+                    self.store,
+                    self.config,
+                    &upload.file_name,
+                    upload.bytes.clone(),
+                    target_prefix.as_deref(),
+                    self.author,
+                    upload.metadata.as_ref(),
+                    self.tenant.id(),
+                )
+                .await
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl UploadStep for S3PutStep<'_> {
+    async fn process(&self, mut upload: UploadContext) -> Result<UploadContext, StepError> {
+        let ctx = self.ctx;
+        // Two uploads of one name at once would interleave their writes.
+        let file_name = upload.file_name.clone();
+        let stored = holding_upload_lock(ctx, self.config, self.tenant.id(), &file_name, async {
+            // Under the lock, so no other upload through here can change
+            // the file between the check and the write.
+            if let Some(precondition) = self.precondition {
+                check_upload_precondition(ctx, self.store, self.tenant, &file_name, precondition)
+                    .await
+                    .map_err(StepError::Request)?;
+            }
+            self.put(&mut upload).await.map_err(StepError::File)
+        })
+        .await
+        .map_err(StepError::Request)?;
+
+        upload.finished = Some(Finished::Stored(stored?));
+        Ok(upload)
+    }
+}
+
+/// What `If-Match` or `If-None-Match` asks of the files an upload overwrites.
+#[derive(Debug)]
+pub(super) enum UploadPrecondition {
+    /// `If-Match`: the file exists, and for a list its ETag is in it.
+    Matches(Option<Vec<String>>),
+    /// `If-None-Match: *`: the file doesn't exist yet.
+    Absent,
+}
+
+pub(super) fn upload_precondition(headers: &HeaderMap) -> Result<Option<UploadPrecondition>> {
+    let invalid = |message: &str| {
+        Error::CustomError(
+            StatusCode::BAD_REQUEST,
+            ErrorDetail::new("invalid_precondition", message),
+        )
+    };
+    let if_match = headers.get(header::IF_MATCH);
+    let if_none_match = headers.get(header::IF_NONE_MATCH);
+    match (if_match, if_none_match) {
+        (None, None) => Ok(None),
+        (Some(_), Some(_)) => Err(invalid("If-Match and If-None-Match can't be sent together")),
+        (None, Some(value)) if value.as_bytes().trim_ascii() == b"*" => {
+            Ok(Some(UploadPrecondition::Absent))
+        }
+        (None, Some(_)) => Err(invalid("Uploads only take If-None-Match: *")),
+        (Some(value), None) => {
+            let value = value
+                .to_str()
+                .map_err(|_| invalid("If-Match is not valid text"))?
+                .trim();
+            if value == "*" {
+                return Ok(Some(UploadPrecondition::Matches(None)));
+            }
+            let tags: Vec<String> = value
+                .split(',')
+                .map(normalize_e_tag)
+                .filter(|t| !t.is_empty())
+                .map(String::from)
+                .collect();
+            if tags.is_empty() {
+                return Err(invalid("If-Match names no ETag"));
+            }
+            Ok(Some(UploadPrecondition::Matches(Some(tags))))
+        }
+    }
+}
+
+/// Storage quotes some ETags and not others, and clients may send them weak.
+pub(super) fn normalize_e_tag(tag: &str) -> &str {
+    let tag = tag.trim();
+    let tag = tag.strip_prefix("W/").unwrap_or(tag);
+    tag.trim_matches('"')
+}
+
+/// Refuses with a 412 an upload of `file_name` that `precondition` rules out.
+pub(super) async fn check_upload_precondition(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    tenant: &Tenant,
+    file_name: &str,
+    precondition: &UploadPrecondition,
+) -> Result<()> {
+    let current = match file::find_by_name_for_tenant(&ctx.db, file_name, tenant.id()).await? {
+        Some(existing) => match store.head(&latest_path(&existing)).await {
+            Ok(meta) => Some(meta),
+            Err(object_store::Error::NotFound { .. }) => None,
+            Err(e) => return Err(Error::wrap(e)),
+        },
+        None => None,
+    };
+    let failed = |description: String| {
+        Error::CustomError(
+            StatusCode::PRECONDITION_FAILED,
+            ErrorDetail {
+                error: Some("precondition_failed".into()),
+                description: Some(description),
+                errors: Some(serde_json::json!({
+                    "current_e_tag": current.as_ref().and_then(|m| m.e_tag.clone()),
+                })),
+            },
+        )
+    };
+    match (precondition, &current) {
+        (UploadPrecondition::Absent, Some(_)) => {
+            Err(failed(format!("'{file_name}' already exists")))
+        }
+        (UploadPrecondition::Absent, None) | (UploadPrecondition::Matches(None), Some(_)) => Ok(()),
+        (UploadPrecondition::Matches(_), None) => {
+            Err(failed(format!("'{file_name}' does not exist")))
+        }
+        (UploadPrecondition::Matches(Some(tags)), Some(meta)) => {
+            let current = meta.e_tag.as_deref().map(normalize_e_tag);
+            if current.is_some_and(|c| tags.iter().any(|t| t == c)) {
+                Ok(())
+            } else {
+                Err(failed(format!("'{file_name}' has changed")))
+            }
+        }
+    }
+}
+
+pub(super) fn upload_hasher(params: &UploadParams) -> Sha256 {
+    let mut hasher = Sha256::new();
+    hasher.update([u8::from(params.extract), u8::from(params.deduplicate)]);
+    hasher.update(params.target_prefix.as_deref().unwrap_or_default());
+    hasher.update([0]);
+    hasher
+}
+
+/// Hashes one multipart field. Boundaries are left out on purpose, since
+/// clients usually pick a new one for every retry.
+pub(super) fn hash_upload_field(
+    hasher: &mut Sha256,
+    field_name: &str,
+    file_name: Option<&str>,
+    bytes: &[u8],
+) {
+    hasher.update(field_name);
+    hasher.update([0]);
+    hasher.update(file_name.unwrap_or_default());
+    hasher.update([0]);
+    hasher.update((bytes.len() as u64).to_be_bytes());
+    hasher.update(bytes);
+}
+
+/// Answers a retry of an upload whose Idempotency-Key is already taken.
+pub(super) async fn replay_upload(
+    ctx: &AppContext,
+    tenant_id: Option<&str>,
+    user_id: i32,
+    key: &str,
+    params: &UploadParams,
+    mut multipart: Multipart,
+) -> Result<Response> {
+    let record = upload_idempotency_key::find(&ctx.db, tenant_id, user_id, key).await?;
+    let Some((request_hash, status, body)) =
+        record.and_then(|r| Some((r.request_hash?, r.response_status?, r.response_body?)))
+    else {
+        return Err(Error::CustomError(
+            StatusCode::CONFLICT,
+            ErrorDetail::new(
+                "idempotency_key_in_use",
+                "An upload with this Idempotency-Key is still in progress",
+            ),
+        ));
+    };
+
+    let config = get_s3_config(ctx);
+    let mut hasher = upload_hasher(params);
+    while let Some(field) = multipart.next_field().await.map_err(|e| {
+        multipart_error(config.max_request_body_bytes, e, |e| {
+            Error::Message(format!("Multipart error: {e}"))
+        })
+    })? {
+        let field_name = field.name().unwrap_or_default().to_string();
+        let file_name = field.file_name().map(|s| s.to_string());
+        let bytes = field.bytes().await.map_err(|e| {
+            multipart_error(config.max_request_body_bytes, e, |e| {
+                Error::Message(format!("Read error: {e}"))
+            })
+        })?;
+        hash_upload_field(&mut hasher, &field_name, file_name.as_deref(), &bytes);
+    }
+
+    if hex::encode(hasher.finalize()) != request_hash {
+        return Err(unprocessable(
+            "idempotency_key_reused",
+            "Idempotency-Key was already used for a different payload",
+        ));
+    }
+
+    let status = u16::try_from(status)
+        .ok()
+        .and_then(|s| StatusCode::from_u16(s).ok())
+        .unwrap_or(StatusCode::OK);
+
+    Ok((
+        [(IDEMPOTENT_REPLAY_HEADER, "true")],
+        upload_reply(status, body),
+    )
+        .into_response())
+}
+
+/// Adds `X-Deduplicated: true` when every file in the response already existed,
+/// i.e. the request wrote nothing to storage.
+pub(super) fn upload_reply(status: StatusCode, body: serde_json::Value) -> Response {
+    let deduplicated = body["results"].as_array().is_some_and(|results| {
+        !results.is_empty() && results.iter().all(|r| r["status"] == "deduplicated")
+    });
+
+    // So that a client can chain conditional uploads of one file.
+    let e_tag = match body["results"].as_array().map(Vec::as_slice) {
+        Some([only]) => only["e_tag"]
+            .as_str()
+            .and_then(|t| HeaderValue::from_str(t).ok()),
+        _ => None,
+    };
+
+    let mut response = (status, Json(body)).into_response();
+    if let Some(e_tag) = e_tag {
+        response.headers_mut().insert(header::ETAG, e_tag);
+    }
+    if deduplicated {
+        response
+            .headers_mut()
+            .insert(DEDUPLICATED_HEADER, HeaderValue::from_static("true"));
+    }
+    response
+}
+
+/// Unpacks an uploaded zip, storing every regular file as its own object under
+/// `target_prefix` (or the archive name without `.zip`).
+///
+/// All entries are checked against the limits before anything is stored, and
+/// the actual decompressed sizes are enforced again while reading.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn extract_zip_upload(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    config: &S3Config,
+    zip_name: &str,
+    bytes: Bytes,
+    target_prefix: Option<&str>,
+    author: &user::Model,
+    metadata: Option<&FileMetadata>,
+    tenant: Option<&str>,
+) -> Result<Vec<StoredFile>> {
+    if !bytes.starts_with(b"PK\x03\x04") && !bytes.starts_with(b"PK\x05\x06") {
+        return Err(unprocessable(
+            "not_a_zip",
+            &format!("'{zip_name}' is not a zip archive"),
+        ));
+    }
+
+    let default_prefix = zip_name
+        .strip_suffix(".zip")
+        .or_else(|| zip_name.strip_suffix(".ZIP"))
+        .unwrap_or(zip_name);
+    let prefix = safe_path_segments(target_prefix.unwrap_or(default_prefix))
+        .ok_or_else(|| unprocessable("invalid_prefix", "Invalid target prefix"))?
+        .join("/");
+
+    let reader = ZipFileReader::new(bytes.to_vec())
+        .await
+        .map_err(|e| unprocessable("invalid_zip", &format!("Invalid zip archive: {e}")))?;
+
+    let entries = reader.file().entries();
+    if entries.len() > config.max_extract_entries {
+        return Err(unprocessable(
+            "too_many_entries",
+            &format!(
+                "Archive has {} entries, the limit is {}",
+                entries.len(),
+                config.max_extract_entries
+            ),
+        ));
+    }
+
+    let mut planned = Vec::new();
+    let mut declared_total: u64 = 0;
+    for (index, entry) in entries.iter().enumerate() {
+        let is_dir = entry
+            .dir()
+            .map_err(|e| unprocessable("invalid_zip", &format!("Invalid zip entry: {e}")))?;
+        let is_symlink = entry
+            .unix_permissions()
+            .is_some_and(|mode| mode & 0o170000 == 0o120000);
+        if is_dir || is_symlink {
+            continue;
+        }
+
+        let entry_name = entry
+            .filename()
+            .as_str()
+            .map_err(|e| unprocessable("invalid_zip", &format!("Invalid entry name: {e}")))?;
+        let segments = safe_path_segments(entry_name).ok_or_else(|| {
+            unprocessable(
+                "unsafe_entry_path",
+                &format!("Entry '{entry_name}' has an unsafe path"),
+            )
+        })?;
+
+        declared_total = declared_total.saturating_add(entry.uncompressed_size());
+        planned.push((
+            index,
+            format!("{prefix}/{}", segments.join("/")),
+            entry.uncompressed_size(),
+        ));
+    }
+
+    if declared_total > config.max_extract_bytes {
+        return Err(unprocessable(
+            "archive_too_large",
+            &format!(
+                "Archive expands to {declared_total} bytes, the limit is {}",
+                config.max_extract_bytes
+            ),
+        ));
+    }
+
+    let mut extracted = Vec::new();
+    let mut remaining = config.max_extract_bytes;
+    for (index, object_name, declared_size) in planned {
+        let entry_reader = reader
+            .reader_with_entry(index)
+            .await
+            .map_err(|e| unprocessable("invalid_zip", &format!("Invalid zip entry: {e}")))?;
+
+        // Never trust the declared size: read at most one byte past it.
+        let mut buf = Vec::new();
+        entry_reader
+            .take(declared_size.min(remaining) + 1)
+            .read_to_end(&mut buf)
+            .await
+            .map_err(|e| unprocessable("invalid_zip", &format!("Invalid zip entry: {e}")))?;
+
+        let size = buf.len() as u64;
+        if size > declared_size || size > remaining {
+            return Err(unprocessable(
+                "archive_too_large",
+                &format!("Entry '{object_name}' is larger than declared"),
+            ));
+        }
+        remaining -= size;
+
+        extracted.push(
+            store_new_file(
+                ctx,
+                config,
+                store,
+                &object_name,
+                Bytes::from(buf),
+                author,
+                metadata,
+                tenant,
+            )
+            .await?,
+        );
+    }
+
+    Ok(extracted)
+}
+
+pub(super) fn fetch_policy(config: &S3Config) -> FetchPolicy {
+    FetchPolicy {
+        allowed_hosts: config.remote_fetch.allowed_hosts.clone(),
+        allow_any_host: config.remote_fetch.allow_any_host,
+        allow_private_addresses: config.remote_fetch.allow_private_addresses,
+        timeout: Duration::from_secs(config.remote_fetch.timeout_seconds),
+        max_redirects: config.remote_fetch.max_redirects,
+        max_bytes: config.max_file_size_bytes,
+    }
+}
+
+pub(super) fn fetch_error(e: FetchError) -> FileError {
+    match e {
+        FetchError::InvalidUrl(message) => FileError::BadRequest(message),
+        FetchError::NotAllowed(message) => FileError::Rejected {
+            status: StatusCode::FORBIDDEN,
+            code: "url_not_allowed".into(),
+            message,
+            details: None,
+        },
+        FetchError::TooLarge(_) => FileError::Rejected {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            code: "file_too_large".into(),
+            message: e.to_string(),
+            details: None,
+        },
+        FetchError::Failed(message) => FileError::Rejected {
+            status: StatusCode::BAD_GATEWAY,
+            code: "fetch_failed".into(),
+            message,
+            details: None,
+        },
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct FromUrlRequest {
+    pub url: String,
+    /// Defaults to the last segment of the URL's path.
+    pub name: Option<String>,
+}
+
+/// Downloads `url` on the server and stores it like a multipart upload,
+/// so migrations don't have to pass every file through the client.
+#[utoipa::path(
+    post,
+    path = "/files/from-url",
+    operation_id = "uploadFromUrl",
+    tag = "files",
+    request_body(content = FromUrlRequest),
+    responses(
+        (status = 200, description = "The stored file", body = FileInfo),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "The URL is not allowed", body = ErrorBody),
+        (status = 413, description = "The remote file is too large", body = ErrorBody),
+        (status = 502, description = "The download failed", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn upload_from_url(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    Json(req): Json<FromUrlRequest>,
+) -> FileResult<Json<FileInfo>> {
+    let author = token_author(&ctx, &headers).await?;
+    let config = get_s3_config(&ctx);
+    if let Some(name) = &req.name
+        && safe_path_segments(name).is_none()
+    {
+        return Err(FileError::BadRequest(format!("Invalid file name '{name}'")));
+    }
+
+    let fetched = remote_fetch::fetch(&fetch_policy(&config), &req.url)
+        .await
+        .map_err(fetch_error)?;
+
+    let file_name = match req.name {
+        Some(name) => normalize_name(&name),
+        None => fetched
+            .url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(|segment| normalize_name(&percent_decode_str(segment).decode_utf8_lossy()))
+            .filter(|name| safe_path_segments(name).is_some())
+            .ok_or_else(|| {
+                FileError::BadRequest("The URL names no file; pass 'name' instead".into())
+            })?,
+    };
+    events::bus().publish(
+        ProgressKind::UploadStarted,
+        &file_name,
+        Some(author.id),
+        None,
+    );
+
+    let mut stored = store_new_file(
+        &ctx,
+        &config,
+        store.as_ref(),
+        &file_name,
+        fetched.bytes,
+        &author,
+        None,
+        tenant.id(),
+    )
+    .await?;
+    after_upload_stored(&ctx, &config, store.as_ref(), &author, &mut stored).await;
+    Ok(Json(stored.info))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct S3ImportRequest {
+    pub source_bucket: String,
+    pub source_key: String,
+    pub source_region: String,
+    pub source_access_key: String,
+    #[schema(format = Password)]
+    pub source_secret_key: String,
+    /// For S3-compatible stores; AWS when absent. Checked against
+    /// `settings.remote_fetch` like a URL for `POST /files/from-url`.
+    pub source_endpoint: Option<String>,
+    /// Defaults to the last segment of `source_key`.
+    pub destination_name: Option<String>,
+}
+
+/// Keeps the credentials out of logs.
+impl std::fmt::Debug for S3ImportRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3ImportRequest")
+            .field("source_bucket", &self.source_bucket)
+            .field("source_key", &self.source_key)
+            .field("source_region", &self.source_region)
+            .field("source_access_key", &"[redacted]")
+            .field("source_secret_key", &"[redacted]")
+            .field("source_endpoint", &self.source_endpoint)
+            .field("destination_name", &self.destination_name)
+            .finish()
+    }
+}
+
+impl S3ImportRequest {
+    /// Rejects what would only fail later, at the source. Messages never
+    /// repeat the credentials.
+    fn validate(&self) -> FileResult<()> {
+        if self.source_bucket.trim().is_empty() {
+            return Err(FileError::BadRequest(
+                "source_bucket must not be empty".into(),
+            ));
+        }
+        if self.source_key.is_empty() || self.source_key.ends_with('/') {
+            return Err(FileError::BadRequest(
+                "source_key must name an object, not a prefix".into(),
+            ));
+        }
+        if !is_region_name(&self.source_region) {
+            return Err(FileError::BadRequest(format!(
+                "source_region '{}' is not a region name like 'us-east-1'",
+                self.source_region
+            )));
+        }
+        for (field, value) in [
+            ("source_access_key", &self.source_access_key),
+            ("source_secret_key", &self.source_secret_key),
+        ] {
+            if value.is_empty() || value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+                return Err(FileError::BadRequest(format!(
+                    "{field} must be non-empty and without whitespace"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn location(&self) -> String {
+        format!("s3://{}/{}", self.source_bucket, self.source_key)
+    }
+}
+
+/// A client for the bucket being imported from, built from the request
+/// alone, so none of the server's own credentials can leak into it.
+pub(super) fn source_store(
+    config: &S3Config,
+    req: &S3ImportRequest,
+    endpoint: Option<&str>,
+) -> FileResult<AmazonS3> {
+    let client = ClientOptions::new()
+        .with_connect_timeout(Duration::from_secs(config.connect_timeout_seconds))
+        .with_timeout(Duration::from_secs(config.remote_fetch.timeout_seconds))
+        .with_allow_http(endpoint.is_some_and(|e| e.starts_with("http://")));
+    let mut builder = AmazonS3Builder::new()
+        .with_client_options(client)
+        .with_bucket_name(&req.source_bucket)
+        .with_region(&req.source_region)
+        .with_access_key_id(&req.source_access_key)
+        .with_secret_access_key(&req.source_secret_key)
+        .with_retry(config.retry.s3());
+    if let Some(endpoint) = endpoint {
+        builder = builder.with_endpoint(endpoint);
+    }
+    builder
+        .build()
+        .map_err(|e| FileError::BadRequest(format!("Invalid source bucket: {e}")))
+}
+
+pub(super) fn source_error(req: &S3ImportRequest, e: ObjectStoreError) -> FileError {
+    let location = req.location();
+    match e {
+        ObjectStoreError::NotFound { .. } => FileError::Rejected {
+            status: StatusCode::NOT_FOUND,
+            code: "source_not_found".into(),
+            message: format!("{location} does not exist"),
+            details: None,
+        },
+        // The error body can quote the access key back; it stays out of
+        // the answer and the log.
+        ObjectStoreError::PermissionDenied { .. } | ObjectStoreError::Unauthenticated { .. } => {
+            FileError::Rejected {
+                status: StatusCode::FORBIDDEN,
+                code: "source_access_denied".into(),
+                message: format!("The source refused the credentials for {location}"),
+                details: None,
+            }
+        }
+        e => {
+            tracing::warn!(source = %location, error = %e, "S3 import failed");
+            FileError::Rejected {
+                status: StatusCode::BAD_GATEWAY,
+                code: "source_failed".into(),
+                message: format!("Reading {location} failed"),
+                details: None,
+            }
+        }
+    }
+}
+
+/// Copies an object out of another S3 bucket, with credentials for that
+/// bucket only, and stores it like an upload.
+#[utoipa::path(
+    post,
+    path = "/files/import-from-s3",
+    operation_id = "importFromS3",
+    tag = "files",
+    request_body(content = S3ImportRequest),
+    responses(
+        (status = 200, description = "The stored file", body = FileInfo),
+        (status = 400, description = "The request names no valid source or destination", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "The source refused the credentials, or its endpoint is not allowed", body = ErrorBody),
+        (status = 404, description = "The source object does not exist", body = ErrorBody),
+        (status = 413, description = "The source object is too large", body = ErrorBody),
+        (status = 502, description = "Reading the source failed", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn import_from_s3(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    Json(req): Json<S3ImportRequest>,
+) -> FileResult<Json<FileInfo>> {
+    let author = token_author(&ctx, &headers).await?;
+    let config = get_s3_config(&ctx);
+    req.validate()?;
+    let file_name = normalize_name(match &req.destination_name {
+        Some(name) => name,
+        None => req.source_key.rsplit('/').next().unwrap_or_default(),
+    });
+    if safe_path_segments(&file_name).is_none() {
+        return Err(FileError::BadRequest(format!(
+            "Invalid file name '{file_name}'"
+        )));
+    }
+
+    let endpoint = match &req.source_endpoint {
+        Some(endpoint) => {
+            remote_fetch::check(&fetch_policy(&config), endpoint)
+                .await
+                .map_err(fetch_error)?;
+            Some(endpoint.trim_end_matches('/'))
+        }
+        None => None,
+    };
+    let source = source_store(&config, &req, endpoint)?;
+    let source_path = ObjectPath::parse(&req.source_key)
+        .map_err(|e| FileError::BadRequest(format!("Invalid source_key: {e}")))?;
+    let result = source
+        .get(&source_path)
+        .await
+        .map_err(|e| source_error(&req, e))?;
+    let too_large = || FileError::Rejected {
+        status: StatusCode::PAYLOAD_TOO_LARGE,
+        code: "file_too_large".into(),
+        message: format!(
+            "{} exceeds the {} byte limit",
+            req.location(),
+            config.max_file_size_bytes
+        ),
+        details: None,
+    };
+    if result.meta.size as u64 > config.max_file_size_bytes {
+        return Err(too_large());
+    }
+    events::bus().publish(
+        ProgressKind::UploadStarted,
+        &file_name,
+        Some(author.id),
+        None,
+    );
+
+    // Read whole, like every other upload: hashing, deduplication and
+    // compression need the content before the write. `put_upload` streams
+    // it on as a multipart upload above `multipart_threshold_bytes`.
+    let mut body = Vec::with_capacity(result.meta.size);
+    let mut chunks = result.into_stream();
+    while let Some(chunk) = chunks.try_next().await.map_err(|e| source_error(&req, e))? {
+        if (body.len() + chunk.len()) as u64 > config.max_file_size_bytes {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let mut stored = store_new_file(
+        &ctx,
+        &config,
+        store.as_ref(),
+        &file_name,
+        body.into(),
+        &author,
+        None,
+        tenant.id(),
+    )
+    .await?;
+    after_upload_stored(&ctx, &config, store.as_ref(), &author, &mut stored).await;
+    Ok(Json(stored.info))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct MergeRequest {
+    /// Files to concatenate, in order.
+    pub sources: Vec<String>,
+    pub destination: String,
+    /// Put between consecutive sources; only for a text destination.
+    pub separator: Option<String>,
+}
+
+/// Concatenates files into `destination`, stored like an upload. Every
+/// source is checked before anything is read, so a missing one fails the
+/// merge without writing.
+#[utoipa::path(
+    post,
+    path = "/files/merge",
+    operation_id = "mergeFiles",
+    tag = "files",
+    request_body(content = MergeRequest),
+    responses(
+        (status = 200, description = "The merged file", body = FileInfo),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 403, description = "A source is quarantined", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+        (status = 413, description = "The merged file would exceed the size limit", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn merge_files(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    Json(mut req): Json<MergeRequest>,
+) -> FileResult<Json<FileInfo>> {
+    let author = token_author(&ctx, &headers).await?;
+    let config = get_s3_config(&ctx);
+    if req.sources.is_empty() {
+        return Err(FileError::BadRequest("No sources to merge".into()));
+    }
+    req.destination = normalize_name(&req.destination);
+    if safe_path_segments(&req.destination).is_none() {
+        return Err(FileError::BadRequest(format!(
+            "Invalid file name '{}'",
+            req.destination
+        )));
+    }
+    let separator = req.separator.as_deref().unwrap_or_default();
+    if !separator.is_empty() && !is_text_like(&req.destination) {
+        return Err(FileError::BadRequest(format!(
+            "A separator only goes between text files, and '{}' is not one",
+            req.destination
+        )));
+    }
+
+    let mut paths = Vec::with_capacity(req.sources.len());
+    let mut size = (separator.len() * (req.sources.len() - 1)) as u64;
+    for name in &req.sources {
+        let name = &normalize_name(name);
+        let record = file::find_by_name_for_tenant(&ctx.db, name, tenant.id())
+            .await?
+            .ok_or_else(|| file_not_found(name))?;
+        ensure_servable(&record)?;
+        let path = latest_path(&record);
+        store.head(&path).await.map_err(|e| match e {
+            ObjectStoreError::NotFound { .. } => file_not_found(name),
+            e => FileError::StorageError(e),
+        })?;
+        size += record.size as u64;
+        paths.push(path);
+    }
+    let too_large = || FileError::Rejected {
+        status: StatusCode::PAYLOAD_TOO_LARGE,
+        code: "file_too_large".into(),
+        message: format!("File exceeds the {} byte limit", config.max_file_size_bytes),
+        details: None,
+    };
+    if size > config.max_file_size_bytes {
+        return Err(too_large());
+    }
+    events::bus().publish(
+        ProgressKind::UploadStarted,
+        &req.destination,
+        Some(author.id),
+        None,
+    );
+
+    // Read whole, as in `import_from_s3`; `put_upload` writes the result
+    // as a multipart upload once it is large enough.
+    let mut merged = Vec::with_capacity(size as usize);
+    for (i, path) in paths.iter().enumerate() {
+        if i > 0 {
+            merged.extend_from_slice(separator.as_bytes());
+        }
+        let result = store.get(path).await.map_err(FileError::StorageError)?;
+        merged.extend_from_slice(&read_decoded(result).await?);
+    }
+    if merged.len() as u64 > config.max_file_size_bytes {
+        return Err(too_large());
+    }
+
+    let mut stored = store_new_file(
+        &ctx,
+        &config,
+        store.as_ref(),
+        &req.destination,
+        merged.into(),
+        &author,
+        None,
+        tenant.id(),
+    )
+    .await?;
+    after_upload_stored(&ctx, &config, store.as_ref(), &author, &mut stored).await;
+    Ok(Json(stored.info))
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Base64UploadRequest {
+    pub name: String,
+    /// Checked to be a media type. Like the part headers of a multipart
+    /// upload it isn't stored: downloads are typed by the file name.
+    pub content_type: Option<String>,
+    /// The file's bytes, in standard base64 with padding.
+    pub data: String,
+}
+
+/// Room in a `POST /files/base64` body for everything but `data`.
+pub(super) const BASE64_BODY_OVERHEAD: u64 = 64 * 1024;
+
+/// Stores a file sent as base64 in a JSON body, for clients that can't send
+/// `multipart/form-data`.
+#[utoipa::path(
+    post,
+    path = "/files/base64",
+    operation_id = "uploadBase64",
+    tag = "files",
+    request_body(content = Base64UploadRequest),
+    responses(
+        (status = 200, description = "The stored file", body = FileInfo),
+        (status = 400, description = "Invalid name, content type or base64", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 413, description = "The file is too large", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn upload_base64(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    Json(mut req): Json<Base64UploadRequest>,
+) -> FileResult<Json<FileInfo>> {
+    let author = token_author(&ctx, &headers).await?;
+    let config = get_s3_config(&ctx);
+    req.name = normalize_name(&req.name);
+    if safe_path_segments(&req.name).is_none() {
+        return Err(FileError::BadRequest(format!(
+            "Invalid file name '{}'",
+            req.name
+        )));
+    }
+    if let Some(content_type) = &req.content_type
+        && content_type.parse::<mime_guess::Mime>().is_err()
+    {
+        return Err(FileError::BadRequest(format!(
+            "Invalid content type '{content_type}'"
+        )));
+    }
+
+    let too_large = || FileError::Rejected {
+        status: StatusCode::PAYLOAD_TOO_LARGE,
+        code: "file_too_large".into(),
+        message: format!("File exceeds the {} byte limit", config.max_file_size_bytes),
+        details: None,
+    };
+    if req.data.len() as u64 > config.base64_max_size_bytes {
+        return Err(too_large());
+    }
+    let bytes = BASE64_STANDARD
+        .decode(&req.data)
+        .map_err(|e| FileError::BadRequest(format!("'data' is not valid base64: {e}")))?;
+    if bytes.len() as u64 > config.max_file_size_bytes {
+        return Err(too_large());
+    }
+    events::bus().publish(
+        ProgressKind::UploadStarted,
+        &req.name,
+        Some(author.id),
+        None,
+    );
+
+    let mut stored = store_new_file(
+        &ctx,
+        &config,
+        store.as_ref(),
+        &req.name,
+        bytes.into(),
+        &author,
+        None,
+        tenant.id(),
+    )
+    .await?;
+    after_upload_stored(&ctx, &config, store.as_ref(), &author, &mut stored).await;
+    Ok(Json(stored.info))
+}
@@ -0,0 +1,551 @@
+//! Earlier versions of a file: recorded ones and snapshots.
+
+use super::*;
+
+pub(super) const SNAPSHOT_SEPARATOR: &str = "__v";
+
+pub(super) fn snapshot_path(file_name: &str, timestamp: &str) -> ObjectPath {
+    ObjectPath::from(format!("{file_name}{SNAPSHOT_SEPARATOR}{timestamp}"))
+}
+
+/// Keys the server writes next to a file's own: old versions, snapshots and thumbnails.
+pub(super) fn is_derived_key(key: &str) -> bool {
+    key.starts_with("versions/")
+        || key.starts_with("thumbnails/")
+        || key.starts_with("resized/")
+        || key.starts_with(QUARANTINE_PREFIX)
+        || key.starts_with(TUS_PREFIX)
+        || key.starts_with(CHUNKED_PREFIX)
+        || key.starts_with(access_log::ARCHIVE_PREFIX)
+        || is_snapshot_key(key)
+}
+
+/// True for `<key>__v<digits>` keys written by `snapshot_object`.
+pub(super) fn is_snapshot_key(key: &str) -> bool {
+    key.rsplit_once(SNAPSHOT_SEPARATOR)
+        .is_some_and(|(_, ts)| valid_snapshot_timestamp(ts))
+}
+
+pub(super) fn valid_snapshot_timestamp(timestamp: &str) -> bool {
+    !timestamp.is_empty() && timestamp.bytes().all(|b| b.is_ascii_digit())
+}
+
+pub(super) fn snapshot_timestamp(timestamp: &str) -> Result<&str> {
+    if valid_snapshot_timestamp(timestamp) {
+        Ok(timestamp)
+    } else {
+        Err(Error::BadRequest(format!("Invalid version '{timestamp}'")))
+    }
+}
+
+/// Copies the object at `source` to a new snapshot of `file_name`, returning
+/// its key, or `None` when there is nothing stored yet.
+pub(super) async fn snapshot_object(
+    store: &dyn ObjectStore,
+    file_name: &str,
+    source: &ObjectPath,
+) -> Result<Option<String>> {
+    match store.head(source).await {
+        Ok(_) => {}
+        Err(ObjectStoreError::NotFound { .. }) => return Ok(None),
+        Err(e) => return Err(Error::wrap(e)),
+    }
+
+    let target = snapshot_path(file_name, &Utc::now().timestamp_millis().to_string());
+    store.copy(source, &target).await.map_err(Error::wrap)?;
+    Ok(Some(target.to_string()))
+}
+
+/// Snapshots of `file_name`, newest first.
+pub(super) async fn list_snapshots(
+    store: &dyn ObjectStore,
+    file_name: &str,
+) -> Result<Vec<VersionSnapshot>> {
+    let prefix = ObjectPath::from(format!("{file_name}{SNAPSHOT_SEPARATOR}")).to_string();
+    let parent = file_name
+        .rsplit_once('/')
+        .map(|(dir, _)| ObjectPath::from(dir));
+    let listing = store
+        .list_with_delimiter(parent.as_ref())
+        .await
+        .map_err(Error::wrap)?;
+
+    let mut snapshots: Vec<VersionSnapshot> = listing
+        .objects
+        .into_iter()
+        .filter_map(|meta| {
+            let timestamp = meta.location.as_ref().strip_prefix(&prefix)?;
+            valid_snapshot_timestamp(timestamp).then(|| VersionSnapshot {
+                timestamp: timestamp.to_string(),
+                key: meta.location.to_string(),
+                size: meta.size as i64,
+                last_modified: meta.last_modified.to_rfc3339(),
+            })
+        })
+        .collect();
+    snapshots.sort_by(|a, b| {
+        b.timestamp
+            .len()
+            .cmp(&a.timestamp.len())
+            .then_with(|| b.timestamp.cmp(&a.timestamp))
+    });
+    Ok(snapshots)
+}
+
+/// Deletes the versions of `file_id` beyond the newest `keep_versions`.
+/// The current version is always the newest, so it is never pruned.
+pub async fn prune_versions(ctx: &AppContext, file_id: i32) -> Result<()> {
+    let config = get_s3_config(ctx);
+    let Some(keep) = config.keep_versions else {
+        return Ok(());
+    };
+    let Some(file_record) = file::find_by_id(&ctx.db, file_id).await? else {
+        return Ok(());
+    };
+    let store = shared_store(&config)?;
+
+    for old in file_version::find_beyond_newest(&ctx.db, file_id, keep.max(1)).await? {
+        if old.version == 1
+            && let Some(hash) = &file_record.blob_hash
+        {
+            file_version::delete_by_id(&ctx.db, old.id).await?;
+            file::clear_blob_hash(&ctx.db, file_id).await?;
+            release_blob(ctx, store.as_ref(), hash).await?;
+            continue;
+        }
+
+        match store.delete(&version_path(&file_record, old.version)).await {
+            Ok(()) | Err(ObjectStoreError::NotFound { .. }) => {}
+            Err(e) => return Err(Error::wrap(e)),
+        }
+        file_version::delete_by_id(&ctx.db, old.id).await?;
+    }
+
+    Ok(())
+}
+
+/// Object holding `version` of `file`.
+pub(super) fn version_path(file: &file::Model, version: i32) -> ObjectPath {
+    let suffix = if file.compressed && version == 1 {
+        GZIP_SUFFIX
+    } else {
+        ""
+    };
+    match &file.blob_hash {
+        Some(hash) if version == 1 => blob_path(hash),
+        _ => ObjectPath::from(format!(
+            "versions/{}/v{}/{}{suffix}",
+            file.id, version, file.name
+        )),
+    }
+}
+
+pub async fn update_file_with_version(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    headers: HeaderMap,
+    Path(file_id): Path<i32>,
+    Json(body): Json<UpdateWithVersionRequest>,
+) -> FileResult<Json<FileInfo>> {
+    let auth_header = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(FileError::Unauthorized)?;
+
+    let token = auth_header.strip_prefix("Bearer ").unwrap_or(auth_header);
+
+    let claims =
+        crate::controllers::auth::decode_token(token).map_err(|_| FileError::Unauthorized)?;
+
+    let user_id: i32 = claims.pid.parse().unwrap_or(0);
+    let author = user::find_by_id(&ctx.db, user_id)
+        .await?
+        .ok_or(FileError::Unauthorized)?;
+
+    file::find_by_id_for_tenant(&ctx.db, file_id, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_id.to_string()))?;
+    let updated_file = file::update_with_version_check(&ctx.db, file_id, body.version, body.size)
+        .await
+        .map_err(|e| {
+            if e.to_string().contains("Version conflict") {
+                FileError::BadRequest(e.to_string())
+            } else {
+                FileError::from(e)
+            }
+        })?;
+
+    Ok(Json(file_info(
+        &get_s3_config(&ctx),
+        updated_file,
+        Some(&author),
+    )))
+}
+
+/// Lists the versions of a file, addressed by id or, failing that, by name.
+#[utoipa::path(
+    get,
+    path = "/files/{id}/versions",
+    operation_id = "listFileVersions",
+    tag = "files",
+    params(("id" = String, Path, description = "File id, or file name when snapshots are enabled")),
+    responses(
+        (status = 200, description = "Versions of the file", body = FileVersionListing),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn get_file_versions(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    headers: HeaderMap,
+    FileName(id_or_name): FileName,
+) -> FileResult<Json<FileVersionListing>> {
+    let auth_header = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(FileError::Unauthorized)?;
+
+    let _token = auth_header.strip_prefix("Bearer ").unwrap_or(auth_header);
+    let _claims =
+        crate::controllers::auth::decode_token(_token).map_err(|_| FileError::Unauthorized)?;
+
+    let config = get_s3_config(&ctx);
+    if config.enable_versioning {
+        let snapshots = list_snapshots(store.as_ref(), &id_or_name).await?;
+        return Ok(Json(FileVersionListing::Snapshots(snapshots)));
+    }
+
+    let by_id = match id_or_name.parse() {
+        Ok(id) => file::find_by_id_for_tenant(&ctx.db, id, tenant.id()).await?,
+        Err(_) => None,
+    };
+    let file_id = match by_id {
+        Some(f) => f.id,
+        None => {
+            file::find_by_name_for_tenant(&ctx.db, &id_or_name, tenant.id())
+                .await?
+                .ok_or_else(|| file_not_found(&id_or_name))?
+                .id
+        }
+    };
+
+    let versions = file_version::find_all_by_file_id(&ctx.db, file_id).await?;
+
+    let version_infos: Vec<FileVersionInfo> = versions
+        .into_iter()
+        .filter_map(|(v, author)| {
+            author.map(|a| FileVersionInfo {
+                id: v.id,
+                version: v.version,
+                size: v.size,
+                author: AuthorInfo {
+                    id: a.id,
+                    login: a.login,
+                },
+                created_at: v.created_at.and_utc().to_rfc3339(),
+            })
+        })
+        .collect();
+
+    Ok(Json(FileVersionListing::Recorded(version_infos)))
+}
+
+/// Snapshots get their own segment: `/versions/{version}` takes the version
+/// numbers of the files index.
+#[utoipa::path(
+    delete,
+    path = "/files/{file_name}/snapshots/{timestamp}",
+    operation_id = "deleteFileSnapshot",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included"), ("timestamp" = String, Path, description = "Snapshot timestamp")),
+    responses(
+        (status = 200, description = "Snapshot removed", body = Object),
+        (status = 400, description = "Invalid request", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such snapshot", body = ErrorBody),
+        (status = 423, description = "Another user holds the file's edit lock", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn delete_file_snapshot(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    remote_ip: RemoteIP,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
+    Path((file_name, timestamp)): Path<(String, String)>,
+) -> FileResult<Json<serde_json::Value>> {
+    let file_name = checked_name(&file_name)?;
+    let auth_header = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(FileError::Unauthorized)?;
+
+    let token = auth_header.strip_prefix("Bearer ").unwrap_or(auth_header);
+    let claims =
+        crate::controllers::auth::decode_token(token).map_err(|_| FileError::Unauthorized)?;
+    let actor = request_actor(
+        &headers,
+        claims.pid.parse().ok(),
+        client_ip(remote_ip, connect_info),
+    );
+
+    let path = snapshot_path(&file_name, snapshot_timestamp(&timestamp)?);
+    let key = path.to_string();
+    let removed: FileResult<()> = async {
+        ensure_edit_unlocked(&ctx, tenant.id(), &file_name, actor.user_id).await?;
+        store.head(&path).await.map_err(FileError::StorageError)?;
+        store.delete(&path).await.map_err(FileError::StorageError)?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = removed {
+        let mut entry = Entry::new(Operation::Delete, Some(&key), &actor).failed(e.to_string());
+        entry.status_code = Some(e.status().as_u16());
+        audit_writer::log(&ctx, entry);
+        return Err(e);
+    }
+    audit_writer::log(&ctx, Entry::new(Operation::Delete, Some(&key), &actor));
+
+    Ok(Json(serde_json::json!({ "deleted": key })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/files/{file_name}/versions/{version}",
+    operation_id = "downloadFileVersion",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included"), ("version" = i32, Path, description = "Version number")),
+    responses(
+        (status = 200, description = "Content of that version", content_type = "application/octet-stream", body = [u8]),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+pub async fn get_file_version(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    headers: HeaderMap,
+    Path((file_name, version)): Path<(String, i32)>,
+) -> FileResult<Response> {
+    let file_name = checked_name(&file_name)?;
+    let auth_header = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(FileError::Unauthorized)?;
+
+    let _token = auth_header.strip_prefix("Bearer ").unwrap_or(auth_header);
+    let _claims =
+        crate::controllers::auth::decode_token(_token).map_err(|_| FileError::Unauthorized)?;
+
+    let file_record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_name))?;
+
+    let _version_record =
+        file_version::find_by_file_id_and_version(&ctx.db, file_record.id, version)
+            .await?
+            .ok_or_else(|| file_not_found(&file_name))?;
+
+    let path = version_path(&file_record, version);
+
+    let result = match store.get(&path).await {
+        Ok(r) => Ok(r),
+        Err(_) => {
+            let fallback_path = ObjectPath::from(file_name.clone());
+            store
+                .get(&fallback_path)
+                .await
+                .map_err(FileError::StorageError)
+        }
+    }?;
+
+    let content_type = mime_guess::from_path(&file_name)
+        .first_or_octet_stream()
+        .to_string();
+
+    let gzip_encoded = is_gzip_encoded(&result.attributes);
+    let size = result.meta.size;
+
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(
+            header::CONTENT_DISPOSITION,
+            attachment(&format!(
+                "{}_v{}",
+                file_name.trim_end_matches(|c: char| !c.is_alphanumeric()),
+                version
+            )),
+        )
+        .header(header::CONTENT_LENGTH, size)
+        .body(object_body(result))
+        .map_err(|e| FileError::Internal(format!("Build response: {e}")))?;
+
+    if gzip_encoded {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    }
+
+    Ok(response)
+}
+
+/// Makes an old version current again by copying it forward as a new version.
+/// Unlike `revert`, the versions in between are kept.
+#[utoipa::path(
+    post,
+    path = "/files/{file_name}/versions/{version}/restore",
+    operation_id = "restoreFileVersion",
+    tag = "files",
+    params(("file_name" = String, Path, description = "File name, folders included"), ("version" = i32, Path, description = "Version to make current again")),
+    responses(
+        (status = 200, description = "The file, now at a new version", body = FileInfo),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn restore_file_version(
+    State(ctx): State<AppContext>,
+    Extension(tenant): Extension<Tenant>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    remote_ip: RemoteIP,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
+    Path((file_name, version)): Path<(String, i32)>,
+) -> FileResult<Json<FileInfo>> {
+    let file_name = checked_name(&file_name)?;
+    let claims = auth::claims_from_headers(&headers)?;
+    let user_id: i32 = claims.pid.parse().unwrap_or(0);
+    let author = user::find_by_id(&ctx.db, user_id)
+        .await?
+        .ok_or(FileError::Unauthorized)?;
+    let actor = request_actor(
+        &headers,
+        Some(author.id),
+        client_ip(remote_ip, connect_info),
+    );
+
+    let file_record = file::find_by_name_for_tenant(&ctx.db, &file_name, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_name))?;
+    file_version::find_by_file_id_and_version(&ctx.db, file_record.id, version)
+        .await?
+        .ok_or_else(|| file_not_found(&file_name))?;
+
+    let config = get_s3_config(&ctx);
+
+    let result = store
+        .get(&version_path(&file_record, version))
+        .await
+        .map_err(FileError::StorageError)?;
+    let bytes = read_decoded(result).await?;
+
+    let stored = write_next_version(
+        &ctx,
+        &config,
+        store.as_ref(),
+        &file_record,
+        bytes,
+        &author,
+        &actor,
+    )
+    .await?;
+
+    Ok(Json(stored.info))
+}
+
+/// Makes `version` the current content again, as a new version, and drops
+/// the versions between the two. `restore` keeps those.
+#[utoipa::path(
+    post,
+    path = "/files/{id}/revert",
+    operation_id = "revertFileVersion",
+    tag = "files",
+    params(("id" = i32, Path, description = "File id")),
+    request_body(content = RevertRequest),
+    responses(
+        (status = 200, description = "The file was reverted", body = Object),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "No such file or version", body = ErrorBody),
+        (status = 423, description = "Another user holds the file's edit lock", body = ErrorBody),
+    ),
+    security(("bearer_auth" = [])),
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn revert_file_version(
+    State(ctx): State<AppContext>,
+    Extension(store): Extension<Arc<dyn ObjectStore>>,
+    Extension(tenant): Extension<Tenant>,
+    remote_ip: RemoteIP,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
+    Path(file_id): Path<i32>,
+    Json(req): Json<RevertRequest>,
+) -> FileResult<Json<serde_json::Value>> {
+    let auth_header = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(FileError::Unauthorized)?;
+
+    let token = auth_header.strip_prefix("Bearer ").unwrap_or(auth_header);
+    let claims =
+        crate::controllers::auth::decode_token(token).map_err(|_| FileError::Unauthorized)?;
+
+    let user_id: i32 = claims.pid.parse().unwrap_or(0);
+    let author = user::find_by_id(&ctx.db, user_id)
+        .await?
+        .ok_or(FileError::Unauthorized)?;
+    let actor = request_actor(
+        &headers,
+        Some(author.id),
+        client_ip(remote_ip, connect_info),
+    );
+
+    let existing = file::find_by_id_for_tenant(&ctx.db, file_id, tenant.id())
+        .await?
+        .ok_or_else(|| file_not_found(&file_id.to_string()))?;
+    file_version::find_by_file_id_and_version(&ctx.db, file_id, req.version)
+        .await?
+        .ok_or_else(|| FileError::NotFound(format!("Version {} not found", req.version)))?;
+
+    let target = store
+        .get(&version_path(&existing, req.version))
+        .await
+        .map_err(FileError::StorageError)?;
+    let bytes = read_decoded(target).await?;
+    let config = get_s3_config(&ctx);
+    let stored = write_next_version(
+        &ctx,
+        &config,
+        store.as_ref(),
+        &existing,
+        bytes,
+        &author,
+        &actor,
+    )
+    .await?;
+
+    let reverted = stored.info.version;
+    for v in (req.version + 1)..reverted {
+        let _ = store.delete(&version_path(&existing, v)).await;
+    }
+    file_version::delete_versions_between(&ctx.db, file_id, req.version, reverted).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "file": {
+            "id": existing.id,
+            "name": existing.name,
+            "version": reverted,
+        }
+    })))
+}
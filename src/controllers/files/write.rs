@@ -0,0 +1,639 @@
+//! The one path uploads, syncs and reverts store content through: the
+//! upload and edit locks, quota, shards, dedup, versions and what follows
+//! a stored file.
+
+use super::*;
+
+/// Everything that follows storing an upload, whichever way it arrived:
+/// scanning, indexing, derived images and notifications.
+pub(super) async fn after_upload_stored(
+    ctx: &AppContext,
+    config: &S3Config,
+    store: &dyn ObjectStore,
+    author: &user::Model,
+    f: &mut StoredFile,
+) {
+    if config.scan.enabled {
+        f.info.scan_status = start_scan(ctx, config, store, f.tenant_id.as_deref(), &f.info).await;
+    }
+    schedule_content_indexing(ctx, &f.info.name).await;
+    schedule_replication(ctx, config, &f.info.name).await;
+    schedule_thumbnail(ctx, &f.info.name).await;
+    schedule_preview(ctx, &f.info.name).await;
+    let info = &f.info;
+    if let Err(e) = storage_hooks::configured(ctx)
+        .post_upload(&info.name, info)
+        .await
+    {
+        tracing::warn!(file = %info.name, error = %e, "post-upload hook failed");
+    }
+    events::bus().publish(
+        ProgressKind::UploadCompleted,
+        &info.name,
+        Some(author.id),
+        Some(serde_json::json!({
+            "size": info.size,
+            "version": info.version,
+            "scan_status": info.scan_status,
+        })),
+    );
+    let checksum = f.content_hash.clone();
+    notify_webhooks(
+        ctx,
+        config,
+        FileEvent::Uploaded,
+        info.size,
+        checksum,
+        &info.name,
+    )
+    .await;
+}
+
+/// Seconds a client refused by `file_locked` is told to wait.
+pub(super) const LOCK_RETRY_AFTER_SECONDS: u64 = 30;
+
+pub(super) fn upload_lock_key(tenant: Option<&str>, file_name: &str) -> String {
+    match tenant {
+        Some(tenant) => format!("{tenant}:{file_name}"),
+        None => file_name.to_string(),
+    }
+}
+
+pub(super) fn file_locked(file_name: &str) -> Error {
+    Error::CustomError(
+        StatusCode::CONFLICT,
+        ErrorDetail {
+            error: Some("locked".into()),
+            description: Some(format!(
+                "'{file_name}' is being uploaded by another request"
+            )),
+            errors: Some(serde_json::json!({ "retry_after": LOCK_RETRY_AFTER_SECONDS })),
+        },
+    )
+}
+
+/// Runs `write` holding the upload lock on `file_name`, so no other write
+/// of the name through here interleaves with it; `file_locked` while
+/// another request holds the lock.
+pub(super) async fn holding_upload_lock<F: Future>(
+    ctx: &AppContext,
+    config: &S3Config,
+    tenant: Option<&str>,
+    file_name: &str,
+    write: F,
+) -> Result<F::Output> {
+    let key = upload_lock_key(tenant, file_name);
+    let Some(locked_at) = file_lock::acquire(&ctx.db, &key, config.upload_lock_ttl_seconds).await?
+    else {
+        return Err(file_locked(file_name));
+    };
+    let written = write.await;
+    if let Err(e) = file_lock::release(&ctx.db, &key, locked_at).await {
+        tracing::warn!(key = %key, error = %e, "failed to release upload lock");
+    }
+    Ok(written)
+}
+
+/// Refuses to change `file_name` while someone other than `user_id` holds
+/// an edit lock on it.
+pub(super) async fn ensure_edit_unlocked(
+    ctx: &AppContext,
+    tenant: Option<&str>,
+    file_name: &str,
+    user_id: Option<i32>,
+) -> Result<()> {
+    let key = upload_lock_key(tenant, file_name);
+    let Some(lock) = file_edit_lock::find_active(&ctx.db, &key).await? else {
+        return Ok(());
+    };
+    if Some(lock.holder_id) == user_id {
+        return Ok(());
+    }
+    let holder = user::find_by_id(&ctx.db, lock.holder_id).await?;
+    let login = holder.map(|u| u.login);
+    Err(Error::CustomError(
+        StatusCode::LOCKED,
+        ErrorDetail {
+            error: Some("edit_locked".into()),
+            description: Some(format!(
+                "'{file_name}' is being edited by {}",
+                login.as_deref().unwrap_or("another user")
+            )),
+            errors: Some(serde_json::json!({
+                "holder": { "id": lock.holder_id, "login": login },
+                "expires_at": lock.expires_at().and_utc().to_rfc3339(),
+            })),
+        },
+    ))
+}
+
+/// The user's usage and quota, or `None` without a quota.
+pub(super) async fn quota_usage(
+    ctx: &AppContext,
+    config: &S3Config,
+    user_id: i32,
+) -> Result<Option<(i64, i64)>> {
+    let user = user::find_by_id(&ctx.db, user_id)
+        .await?
+        .ok_or(Error::NotFound)?;
+    let limit = user
+        .storage_quota_bytes
+        .or(config.default_quota_bytes.map(|b| b as i64));
+    Ok(limit.map(|limit| (user.storage_used_bytes, limit)))
+}
+
+/// Refuses `requested` more bytes that would take the user past their quota.
+/// Concurrent uploads are checked against the same usage, so together they
+/// can overshoot by one file each.
+pub(super) async fn ensure_quota(
+    ctx: &AppContext,
+    config: &S3Config,
+    user_id: i32,
+    requested: i64,
+) -> Result<()> {
+    match quota_usage(ctx, config, user_id).await? {
+        Some((used, limit)) if used + requested > limit => {
+            Err(quota_exceeded(used, limit, requested))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub(super) fn quota_exceeded(used: i64, limit: i64, requested: i64) -> Error {
+    Error::CustomError(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        ErrorDetail {
+            error: Some("quota_exceeded".into()),
+            description: Some(format!(
+                "Storing {requested} more bytes would exceed the {limit} byte quota ({used} used)"
+            )),
+            errors: Some(serde_json::json!({
+                "used": used,
+                "limit": limit,
+                "requested": requested,
+            })),
+        },
+    )
+}
+
+/// Stores a freshly uploaded file as version 1, both at its own key and under `versions/`.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn store_new_file(
+    ctx: &AppContext,
+    config: &S3Config,
+    store: &dyn ObjectStore,
+    file_name: &str,
+    bytes: Bytes,
+    author: &user::Model,
+    metadata: Option<&FileMetadata>,
+    tenant: Option<&str>,
+) -> Result<StoredFile> {
+    let upload = UploadContext::new(file_name, bytes, metadata.cloned());
+    let mut upload = UploadPipeline::new()
+        .step(ChecksumStep {
+            expected: Vec::new(),
+        })
+        .step(HookStep {
+            hooks: storage_hooks::configured(ctx),
+        })
+        .step(CompressStep {
+            enabled: compresses_uploads(config),
+        })
+        .run(upload)
+        .await
+        .map_err(StepError::into_inner)?;
+    store_upload(ctx, config, store, &mut upload, author, tenant).await
+}
+
+/// The write a pipeline ends in: a new version of the file where it is
+/// versioned, else its latest content and first version.
+pub(super) async fn store_upload(
+    ctx: &AppContext,
+    config: &S3Config,
+    store: &dyn ObjectStore,
+    upload: &mut UploadContext,
+    author: &user::Model,
+    tenant: Option<&str>,
+) -> Result<StoredFile> {
+    let content_hash = upload.hash_content();
+    let file_name = upload.file_name.as_str();
+    let metadata = upload.metadata.as_ref();
+    let checksums = (!upload.checksums.is_empty()).then(|| serde_json::json!(upload.checksums));
+    ensure_edit_unlocked(ctx, tenant, file_name, Some(author.id)).await?;
+    ensure_quota(ctx, config, author.id, upload.bytes.len() as i64).await?;
+    // Looked up before anything is written: an upload of a name the bucket
+    // already holds replaces that file, whoever uploaded it.
+    let existing = file::find_by_name_for_tenant(&ctx.db, file_name, tenant).await?;
+    if config.versioning
+        && let Some(existing) = &existing
+    {
+        let stored = store_next_version(
+            ctx,
+            store,
+            existing,
+            upload.bytes.clone(),
+            author,
+            upload.attributes.clone(),
+        )
+        .await?;
+        schedule_version_pruning(ctx, config, existing.id).await;
+        return Ok(stored);
+    }
+    // Later versions of a file are never shared, so only a first one is
+    // replaced by a blob.
+    if config.dedup && existing.as_ref().is_none_or(|f| f.version == 1) {
+        return store_deduplicated_file(
+            ctx,
+            store,
+            file_name,
+            upload.bytes.clone(),
+            &content_hash,
+            author,
+            metadata,
+            checksums,
+            tenant,
+            existing.as_ref(),
+        )
+        .await;
+    }
+
+    let old_path = existing.as_ref().map(latest_path);
+    let previous_version = match &old_path {
+        Some(old_path) if config.enable_versioning => {
+            snapshot_object(store, file_name, old_path).await?
+        }
+        _ => None,
+    };
+
+    let size = upload.bytes.len() as i64;
+    // Only a first version is looked up under its `.gz` key.
+    let gzipped = upload
+        .gzipped
+        .clone()
+        .filter(|_| existing.as_ref().is_none_or(|f| f.version == 1));
+    let compressed = gzipped.is_some();
+    // The same goes for a sharded key.
+    let shard = PathStrategy::parse(&config.path_strategy)
+        .unwrap_or_default()
+        .shard(&upload.bytes)
+        .filter(|_| existing.as_ref().is_none_or(|f| f.version == 1));
+    let (bytes, suffix) = match gzipped {
+        Some(gzipped) => (gzipped, GZIP_SUFFIX),
+        None => (upload.bytes.clone(), ""),
+    };
+    let put_options = || {
+        let mut attributes = upload.attributes.clone();
+        if compressed {
+            attributes.insert(Attribute::ContentEncoding, "gzip".into());
+            attributes.insert(
+                Attribute::Metadata(UNCOMPRESSED_SIZE_METADATA.into()),
+                size.to_string().into(),
+            );
+        }
+        PutOptions {
+            attributes,
+            ..Default::default()
+        }
+    };
+
+    let key = path_strategy::sharded_key(shard.as_deref(), file_name);
+    let latest_path = ObjectPath::from(format!("{key}{suffix}"));
+    let put_result = put_upload(config, store, &latest_path, bytes.clone(), put_options())
+        .await
+        .map_err(Error::wrap)?;
+
+    let metadata_json = metadata.map(serde_json::to_value).transpose()?;
+    let new_file = file::NewFile {
+        name: file_name,
+        size,
+        author_id: author.id,
+        metadata: metadata_json,
+        content_hash: &content_hash,
+        deduplicated: false,
+        compressed,
+        tenant_id: tenant,
+        object_shard: shard.as_deref(),
+        checksums,
+    };
+    let stored_file = match &existing {
+        Some(existing) => {
+            let updated = file::overwrite(&ctx.db, existing.id, new_file).await?;
+            if let Some(old_path) = &old_path
+                && existing.blob_hash.is_none()
+                && *old_path != latest_path
+            {
+                let _ = store.delete(old_path).await;
+            }
+            if let Some(hash) = &existing.blob_hash
+                && let Err(e) = release_blob(ctx, store, hash).await
+            {
+                tracing::warn!(hash = %hash, error = %e, "failed to release blob");
+            }
+            updated
+        }
+        None => {
+            let created = file::create(&ctx.db, new_file).await?;
+            file_version::create(&ctx.db, created.id, 1, size, author.id).await?;
+            created
+        }
+    };
+
+    let versioned_path = version_path(&stored_file, stored_file.version);
+    put_upload(config, store, &versioned_path, bytes, put_options())
+        .await
+        .map_err(Error::wrap)?;
+
+    Ok(StoredFile {
+        content_hash: stored_file.content_hash.clone(),
+        tenant_id: stored_file.tenant_id.clone(),
+        info: file_info(config, stored_file, Some(author)),
+        e_tag: put_result.e_tag,
+        previous_version,
+    })
+}
+
+/// Stores uploaded content with a single PUT, or as a multipart upload
+/// above `multipart_threshold_bytes` so large files don't time out.
+pub(super) async fn put_upload(
+    config: &S3Config,
+    store: &dyn ObjectStore,
+    path: &ObjectPath,
+    bytes: Bytes,
+    opts: PutOptions,
+) -> object_store::Result<PutResult> {
+    let part_size = config.multipart_threshold_bytes.max(MIN_PART_BYTES as u64) as usize;
+    if bytes.len() <= part_size {
+        return store.put_opts(path, bytes.into(), opts).await;
+    }
+
+    let mut upload = store
+        .put_multipart_opts(
+            path,
+            PutMultipartOpts {
+                tags: opts.tags,
+                attributes: opts.attributes,
+            },
+        )
+        .await?;
+    let parts: Vec<_> = (0..bytes.len())
+        .step_by(part_size)
+        .map(|start| {
+            let end = (start + part_size).min(bytes.len());
+            upload.put_part(bytes.slice(start..end).into())
+        })
+        .collect();
+    let result = match futures_util::future::try_join_all(parts).await {
+        Ok(_) => upload.complete().await,
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        let _ = upload.abort().await;
+    }
+    result
+}
+
+/// Writes `bytes` as the next version of `existing` and makes it the latest content.
+pub(super) async fn store_next_version(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    existing: &file::Model,
+    bytes: Bytes,
+    author: &user::Model,
+    attributes: Attributes,
+) -> Result<StoredFile> {
+    let config = get_s3_config(ctx);
+    let size = bytes.len() as i64;
+    let updated =
+        file::sync_with_version_check(&ctx.db, existing.id, existing.version, size, author.id)
+            .await
+            .map_err(|e| match e {
+                DbErr::Custom(msg) if msg.starts_with("Version conflict") => Error::CustomError(
+                    StatusCode::CONFLICT,
+                    ErrorDetail::new("version_conflict", &msg),
+                ),
+                e => e.into(),
+            })?;
+
+    let put_options = || PutOptions {
+        attributes: attributes.clone(),
+        ..Default::default()
+    };
+    put_upload(
+        &config,
+        store,
+        &version_path(&updated, updated.version),
+        bytes.clone(),
+        put_options(),
+    )
+    .await
+    .map_err(Error::wrap)?;
+    let latest = latest_path(&updated);
+    let put_result = put_upload(&config, store, &latest, bytes, put_options())
+        .await
+        .map_err(Error::wrap)?;
+    // A first version may have sat under its shard or `.gz` key. A shared
+    // blob stays, as the file's version 1.
+    let previous = latest_path(existing);
+    if existing.blob_hash.is_none()
+        && previous != latest
+        && let Err(e) = store.delete(&previous).await
+    {
+        tracing::warn!(key = %previous, error = %e, "failed to remove replaced object");
+    }
+
+    Ok(StoredFile {
+        content_hash: updated.content_hash.clone(),
+        tenant_id: updated.tenant_id.clone(),
+        info: file_info(&config, updated, Some(author)),
+        e_tag: put_result.e_tag,
+        previous_version: None,
+    })
+}
+
+/// `store_next_version` for requests that change a file in place rather
+/// than upload it, such as a sync or a revert: under the same upload and
+/// edit locks, followed by what follows an upload, and audited as one.
+pub(super) async fn write_next_version(
+    ctx: &AppContext,
+    config: &S3Config,
+    store: &dyn ObjectStore,
+    existing: &file::Model,
+    bytes: Bytes,
+    author: &user::Model,
+    actor: &Actor,
+) -> FileResult<StoredFile> {
+    let size = bytes.len() as i64;
+    let tenant = existing.tenant_id.as_deref();
+    let written: FileResult<StoredFile> = async {
+        ensure_edit_unlocked(ctx, tenant, &existing.name, Some(author.id)).await?;
+        ensure_quota(ctx, config, author.id, size).await?;
+        let stored = holding_upload_lock(
+            ctx,
+            config,
+            tenant,
+            &existing.name,
+            store_next_version(ctx, store, existing, bytes, author, Attributes::new()),
+        )
+        .await??;
+        Ok(stored)
+    }
+    .await;
+
+    let mut entry = Entry::new(Operation::Upload, Some(&existing.name), actor);
+    entry.bytes = Some(size);
+    match written {
+        Ok(mut stored) => {
+            entry.status_code = Some(StatusCode::OK.as_u16());
+            access_log::record(ctx, &entry);
+            audit_writer::log(ctx, entry);
+            schedule_version_pruning(ctx, config, existing.id).await;
+            after_upload_stored(ctx, config, store, author, &mut stored).await;
+            Ok(stored)
+        }
+        Err(e) => {
+            entry.status_code = Some(e.status().as_u16());
+            entry = entry.failed(e.to_string());
+            access_log::record(ctx, &entry);
+            audit_writer::log(ctx, entry);
+            Err(e)
+        }
+    }
+}
+
+pub(super) async fn schedule_version_pruning(ctx: &AppContext, config: &S3Config, file_id: i32) {
+    if config.keep_versions.is_none() {
+        return;
+    }
+    if let Err(e) = PruneVersionsWorker::perform_later(ctx, PruneVersionsArgs { file_id }).await {
+        tracing::warn!(file_id, error = %e, "failed to schedule version pruning");
+    }
+}
+
+/// Queues the copy of file `name` to the replica, and marks it pending.
+/// Tenants' files stay in their own buckets only.
+pub(super) async fn schedule_replication(ctx: &AppContext, config: &S3Config, name: &str) {
+    if config.for_replica().is_none() {
+        return;
+    }
+    let record = match file::find_by_name_for_tenant(&ctx.db, name, None).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return,
+        Err(e) => {
+            tracing::warn!(name, error = %e, "failed to schedule replication");
+            return;
+        }
+    };
+    let pending = ReplicationStatus::Pending.as_str();
+    if let Err(e) = file::set_replication_status(&ctx.db, record.id, pending, None).await {
+        tracing::warn!(name, error = %e, "failed to mark file pending replication");
+    }
+    let args = ReplicateFileArgs {
+        name: name.to_string(),
+        deleted_key: None,
+    };
+    if let Err(e) = ReplicateFileWorker::perform_later(ctx, args).await {
+        tracing::warn!(name, error = %e, "failed to schedule replication");
+    }
+}
+
+/// Dedup-mode counterpart of `store_new_file`: the content goes to
+/// `blobs/{sha256}` once and every later upload of the same bytes only adds a
+/// row pointing at it. Metadata is kept in the index only, since the object is
+/// shared between files.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn store_deduplicated_file(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    file_name: &str,
+    bytes: Bytes,
+    hash: &str,
+    author: &user::Model,
+    metadata: Option<&FileMetadata>,
+    checksums: Option<serde_json::Value>,
+    tenant: Option<&str>,
+    existing: Option<&file::Model>,
+) -> Result<StoredFile> {
+    let config = get_s3_config(ctx);
+    let size = bytes.len() as i64;
+
+    blob::acquire(&ctx.db, hash, size).await?;
+
+    let stored = async {
+        // Check even when the row already existed: the first uploader may
+        // still be writing, or may have failed after taking its reference.
+        let path = blob_path(hash);
+        let e_tag = match store.head(&path).await {
+            Ok(meta) => meta.e_tag,
+            Err(ObjectStoreError::NotFound { .. }) => {
+                put_upload(&config, store, &path, bytes, PutOptions::default())
+                    .await
+                    .map_err(Error::wrap)?
+                    .e_tag
+            }
+            Err(e) => return Err(Error::wrap(e)),
+        };
+
+        let metadata_json = metadata.map(serde_json::to_value).transpose()?;
+        let new_file = file::NewFile {
+            name: file_name,
+            size,
+            author_id: author.id,
+            metadata: metadata_json,
+            content_hash: hash,
+            deduplicated: true,
+            compressed: false,
+            tenant_id: tenant,
+            object_shard: None,
+            checksums,
+        };
+        let stored_file = match existing {
+            Some(existing) => file::overwrite(&ctx.db, existing.id, new_file).await?,
+            None => {
+                let created = file::create(&ctx.db, new_file).await?;
+                file_version::create(&ctx.db, created.id, 1, size, author.id).await?;
+                created
+            }
+        };
+
+        Ok(StoredFile {
+            content_hash: stored_file.content_hash.clone(),
+            tenant_id: stored_file.tenant_id.clone(),
+            info: file_info(&config, stored_file, Some(author)),
+            e_tag,
+            previous_version: None,
+        })
+    }
+    .await;
+
+    if stored.is_err()
+        && let Err(e) = release_blob(ctx, store, hash).await
+    {
+        tracing::warn!(hash = %hash, error = %e, "failed to release blob after upload error");
+    }
+    // The replaced content is no longer referenced by the file.
+    if stored.is_ok()
+        && let Some(existing) = existing
+    {
+        match &existing.blob_hash {
+            Some(old_hash) => {
+                if let Err(e) = release_blob(ctx, store, old_hash).await {
+                    tracing::warn!(hash = %old_hash, error = %e, "failed to release blob");
+                }
+            }
+            None => {
+                let _ = store.delete(&latest_path(existing)).await;
+            }
+        }
+    }
+    stored
+}
+
+pub(super) async fn release_blob(
+    ctx: &AppContext,
+    store: &dyn ObjectStore,
+    hash: &str,
+) -> Result<()> {
+    let path = blob_path(hash);
+    blob::release(&ctx.db, hash, || store.delete(&path)).await?;
+    Ok(())
+}
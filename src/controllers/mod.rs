@@ -1,4 +1,6 @@
+pub mod admin;
 pub mod auth;
 pub mod files;
+pub mod openapi;
 pub mod roles;
 pub mod users;
@@ -0,0 +1,157 @@
+//! The OpenAPI contract of the `/files` API, and a Swagger UI to browse it.
+
+use axum::{Json, response::Html, routing::get};
+use loco_rs::{app::AppContext, controller::Routes};
+use serde::Deserialize;
+use utoipa::{
+    Modify, OpenApi,
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+};
+
+use crate::{controllers::files, thumbnails};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "d-dox file API"),
+    paths(
+        files::upload_file,
+        files::get_all_files,
+        files::storage_stats,
+        files::get_quota,
+        files::storage_health,
+        files::upload_limits,
+        files::stream_events,
+        files::search_audit_log,
+        files::list_tags,
+        files::search_files,
+        files::browse_files,
+        files::find_duplicates,
+        files::search_file_contents,
+        files::get_file,
+        files::get_signed_url,
+        files::rescan_file,
+        files::link_file,
+        files::lock_file,
+        files::get_file_lock,
+        files::refresh_file_lock,
+        files::unlock_file,
+        files::get_download_count,
+        files::get_file_audit,
+        files::file_exists,
+        files::archive_file,
+        files::get_file_meta,
+        files::update_file_meta,
+        files::get_file_metadata,
+        files::update_file_metadata,
+        files::add_file_tags,
+        files::remove_file_tag,
+        files::get_file_thumbnail,
+        files::get_file_preview,
+        files::get_resized_file,
+        files::restore_archived_file,
+        files::get_restore_status,
+        files::delete_file,
+        files::delete_folder,
+        files::move_folder,
+        files::tus_options,
+        files::create_tus_upload,
+        files::head_tus_upload,
+        files::patch_tus_upload,
+        files::upload_from_url,
+        files::import_from_s3,
+        files::merge_files,
+        files::upload_base64,
+        files::create_chunked_upload,
+        files::abort_chunked_upload,
+        files::put_chunked_part,
+        files::complete_chunked_upload,
+        files::sync_files,
+        files::sync_storage,
+        files::test_webhooks,
+        files::batch_download,
+        files::export_catalog,
+        files::export_files,
+        files::export_file_list,
+        files::get_file_versions,
+        files::get_file_version,
+        files::delete_file_snapshot,
+        files::restore_file_version,
+        files::revert_file_version,
+    ),
+    components(schemas(thumbnails::Fit, thumbnails::OutputFormat)),
+    modifiers(&BearerAuth),
+    tags((name = "files", description = "Storing, finding and serving files"))
+)]
+pub struct ApiDoc;
+
+/// Declares the `bearer_auth` scheme the secured operations refer to.
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_auth",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// Loads the UI itself from a CDN, so the server ships no assets for it.
+const SWAGGER_UI_HTML: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>d-dox file API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/api-docs/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+pub async fn swagger_ui() -> Html<&'static str> {
+    Html(SWAGGER_UI_HTML)
+}
+
+/// The part of `settings` this controller reads.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct DocsSettings {
+    /// Serves the Swagger UI at `/swagger-ui`. The spec is served either way.
+    swagger_ui: bool,
+}
+
+fn docs_settings(ctx: &AppContext) -> DocsSettings {
+    ctx.config
+        .settings
+        .as_ref()
+        .and_then(|s| serde_json::from_value(s.clone()).ok())
+        .unwrap_or_default()
+}
+
+pub fn routes(ctx: &AppContext) -> Routes {
+    let routes = Routes::new()
+        .add("/api-docs/openapi.json", get(openapi_json))
+        // Where the spec was first served.
+        .add("/openapi.json", get(openapi_json));
+    if docs_settings(ctx).swagger_ui {
+        routes.add("/swagger-ui", get(swagger_ui))
+    } else {
+        routes
+    }
+}
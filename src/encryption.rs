@@ -0,0 +1,694 @@
+//! Application-side encryption of stored objects (`settings.encryption`), for
+//! deployments that can't trust the storage provider. Objects are sealed with
+//! AES-256-GCM before they reach the store and opened again on the way out,
+//! so handlers only ever see plaintext.
+//!
+//! A sealed object is a header followed by chunks of 64 KiB of plaintext
+//! plus a 16-byte tag. The header is
+//! `DDXE | scheme | key id (8) | nonce prefix (7)`, and for the `SEGMENT`
+//! scheme also the plaintext length (u64). The key id is the start of the
+//! key's SHA-256, so objects sealed before a rotation still find their key.
+//! Chunk nonces are the prefix, the chunk counter and a last-chunk flag; the
+//! header is authenticated with every chunk. The last chunk is always
+//! shorter than a full one, possibly empty, so truncation is detected.
+
+use aes_gcm::{
+    Aes256Gcm, KeyInit, Nonce,
+    aead::{Aead, OsRng, Payload, rand_core::RngCore},
+};
+use async_trait::async_trait;
+use axum::body::Bytes;
+use base64::Engine;
+use futures_util::{StreamExt, TryStreamExt, stream::BoxStream};
+use object_store::{
+    Attribute, Attributes, GetOptions, GetResult, GetResultPayload, ListResult, MultipartId,
+    MultipartUpload, ObjectMeta, ObjectStore, PutMultipartOpts, PutOptions, PutPayload, PutResult,
+    Result, UploadPart,
+    multipart::{MultipartStore, PartId},
+    path::Path,
+};
+use sha2::{Digest, Sha256};
+use std::{fmt, ops::Range, sync::Arc};
+
+const MAGIC: &[u8; 4] = b"DDXE";
+/// One object, chunked until its end.
+const STREAM: u8 = 1;
+/// One multipart part; an object is then a run of segments.
+const SEGMENT: u8 = 2;
+const HEADER_BYTES: usize = 20;
+const LENGTH_BYTES: usize = 8;
+const CHUNK_BYTES: usize = 64 * 1024;
+const TAG_BYTES: usize = 16;
+
+/// User metadata of sealed objects holding the size before encryption.
+pub const PLAINTEXT_SIZE_METADATA: &str = "plaintext-size";
+
+/// What a ranged read of a sealed object fails with, as the source of an
+/// `object_store::Error::NotSupported`.
+#[derive(Debug, thiserror::Error)]
+#[error("Range reads of encrypted objects are not supported yet; read the whole object instead")]
+pub struct RangeNotSupported;
+
+fn failed(message: String) -> object_store::Error {
+    object_store::Error::Generic {
+        store: "Encryption",
+        source: message.into(),
+    }
+}
+
+#[derive(Clone)]
+struct Key {
+    id: [u8; 8],
+    cipher: Aes256Gcm,
+}
+
+impl Key {
+    fn new(bytes: &[u8; 32]) -> Self {
+        let mut id = [0; 8];
+        id.copy_from_slice(&Sha256::digest(bytes)[..8]);
+        Self {
+            id,
+            cipher: Aes256Gcm::new(bytes.into()),
+        }
+    }
+}
+
+/// The key new objects are sealed with, plus retired keys that still open
+/// the objects sealed before a rotation.
+#[derive(Clone)]
+pub struct Keyring {
+    current: Key,
+    previous: Vec<Key>,
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring")
+            .field("current", &hex::encode(self.current.id))
+            .field(
+                "previous",
+                &self
+                    .previous
+                    .iter()
+                    .map(|k| hex::encode(k.id))
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Keyring {
+    pub fn new(current: &[u8; 32], previous: &[[u8; 32]]) -> Self {
+        Self {
+            current: Key::new(current),
+            previous: previous.iter().map(Key::new).collect(),
+        }
+    }
+
+    /// A key given as base64, which must decode to exactly 32 bytes.
+    pub fn decode_key(encoded: &str) -> std::result::Result<[u8; 32], String> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| format!("encryption key is not valid base64: {e}"))?;
+        <[u8; 32]>::try_from(bytes.as_slice()).map_err(|_| {
+            format!(
+                "encryption key must be 32 bytes (AES-256), not {}",
+                bytes.len()
+            )
+        })
+    }
+
+    fn find(&self, id: &[u8]) -> Option<&Key> {
+        std::iter::once(&self.current)
+            .chain(&self.previous)
+            .find(|k| k.id == id)
+    }
+}
+
+fn nonce(prefix: &[u8; 7], counter: u32, last: bool) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[..7].copy_from_slice(prefix);
+    nonce[7..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = u8::from(last);
+    nonce
+}
+
+/// Seals plaintext as it arrives, one full chunk at a time.
+struct Sealer {
+    key: Key,
+    header: Vec<u8>,
+    prefix: [u8; 7],
+    counter: u32,
+    carry: Vec<u8>,
+    started: bool,
+}
+
+impl Sealer {
+    /// `length` is the plaintext size of a `SEGMENT`; `None` seals a `STREAM`.
+    fn new(keyring: &Keyring, length: Option<u64>) -> Self {
+        let key = keyring.current.clone();
+        let mut prefix = [0; 7];
+        OsRng.fill_bytes(&mut prefix);
+        let mut header = Vec::with_capacity(HEADER_BYTES + LENGTH_BYTES);
+        header.extend_from_slice(MAGIC);
+        header.push(if length.is_some() { SEGMENT } else { STREAM });
+        header.extend_from_slice(&key.id);
+        header.extend_from_slice(&prefix);
+        if let Some(length) = length {
+            header.extend_from_slice(&length.to_be_bytes());
+        }
+        Self {
+            key,
+            header,
+            prefix,
+            counter: 0,
+            carry: Vec::new(),
+            started: false,
+        }
+    }
+
+    fn seal_chunk(&mut self, chunk: &[u8], last: bool, out: &mut Vec<u8>) {
+        let nonce = nonce(&self.prefix, self.counter, last);
+        let sealed = self
+            .key
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: chunk,
+                    aad: &self.header,
+                },
+            )
+            .expect("AES-GCM seals any chunk of CHUNK_BYTES");
+        out.extend_from_slice(&sealed);
+        // Wrapping would take 256 TiB, far past the 5 TiB object limit.
+        self.counter = self.counter.wrapping_add(1);
+    }
+
+    fn start(&mut self, out: &mut Vec<u8>) {
+        if !self.started {
+            out.extend_from_slice(&self.header);
+            self.started = true;
+        }
+    }
+
+    fn update(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        self.start(out);
+        self.carry.extend_from_slice(data);
+        let full = self.carry.len() / CHUNK_BYTES * CHUNK_BYTES;
+        let carry = std::mem::take(&mut self.carry);
+        for chunk in carry[..full].chunks(CHUNK_BYTES) {
+            self.seal_chunk(chunk, false, out);
+        }
+        self.carry = carry[full..].to_vec();
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        self.start(out);
+        let carry = std::mem::take(&mut self.carry);
+        self.seal_chunk(&carry, true, out);
+    }
+}
+
+fn seal_all(keyring: &Keyring, data: &PutPayload, length: Option<u64>) -> Vec<u8> {
+    let mut sealer = Sealer::new(keyring, length);
+    let mut out = Vec::with_capacity(sealed_len(data.content_length()));
+    for bytes in data {
+        sealer.update(bytes, &mut out);
+    }
+    sealer.finish(&mut out);
+    out
+}
+
+fn sealed_len(plaintext: usize) -> usize {
+    HEADER_BYTES + plaintext + (plaintext / CHUNK_BYTES + 1) * TAG_BYTES
+}
+
+/// The plaintext size of a `STREAM` object of `sealed` bytes; every full
+/// chunk and the final short one add a tag.
+fn plaintext_len(sealed: usize) -> usize {
+    let body = sealed.saturating_sub(HEADER_BYTES);
+    let chunks = body.div_ceil(CHUNK_BYTES + TAG_BYTES).max(1);
+    body.saturating_sub(chunks * TAG_BYTES)
+}
+
+/// True if `bytes` start like a sealed object. Anything else is stored plaintext,
+/// such as objects written before encryption was turned on.
+fn is_sealed(bytes: &[u8]) -> bool {
+    bytes.len() >= HEADER_BYTES && bytes.starts_with(MAGIC)
+}
+
+enum Next {
+    More,
+    Chunk { len: usize, last: bool },
+    Truncated,
+}
+
+/// Opens the chunks of one `STREAM` or `SEGMENT`.
+struct Opener {
+    key: Key,
+    header: Vec<u8>,
+    prefix: [u8; 7],
+    counter: u32,
+    /// Plaintext still due in a `SEGMENT`.
+    remaining: Option<u64>,
+}
+
+impl Opener {
+    /// The opener for the header `buffer` starts with and the header's length,
+    /// or `None` while `buffer` is too short to tell.
+    fn parse(keyring: &Keyring, buffer: &[u8]) -> Result<Option<(Self, usize)>> {
+        if buffer.len() < HEADER_BYTES {
+            return Ok(None);
+        }
+        if !buffer.starts_with(MAGIC) {
+            return Err(failed("Encrypted object is corrupt".into()));
+        }
+        let header_len = match buffer[4] {
+            STREAM => HEADER_BYTES,
+            SEGMENT => HEADER_BYTES + LENGTH_BYTES,
+            other => return Err(failed(format!("Unknown encryption scheme {other}"))),
+        };
+        if buffer.len() < header_len {
+            return Ok(None);
+        }
+        let id = &buffer[5..13];
+        let key = keyring.find(id).ok_or_else(|| {
+            failed(format!(
+                "Object was encrypted with key {}, which is not configured",
+                hex::encode(id)
+            ))
+        })?;
+        let mut prefix = [0; 7];
+        prefix.copy_from_slice(&buffer[13..20]);
+        let remaining = (header_len > HEADER_BYTES)
+            .then(|| u64::from_be_bytes(buffer[20..28].try_into().expect("eight length bytes")));
+        let opener = Self {
+            key: key.clone(),
+            header: buffer[..header_len].to_vec(),
+            prefix,
+            counter: 0,
+            remaining,
+        };
+        Ok(Some((opener, header_len)))
+    }
+
+    fn next(&self, buffered: usize, ended: bool) -> Next {
+        let (len, last) = match self.remaining {
+            Some(remaining) if remaining >= CHUNK_BYTES as u64 => (CHUNK_BYTES + TAG_BYTES, false),
+            Some(remaining) => (remaining as usize + TAG_BYTES, true),
+            None if buffered >= CHUNK_BYTES + TAG_BYTES => (CHUNK_BYTES + TAG_BYTES, false),
+            None if ended && buffered >= TAG_BYTES => (buffered, true),
+            None if ended => return Next::Truncated,
+            None => return Next::More,
+        };
+        if buffered >= len {
+            Next::Chunk { len, last }
+        } else if ended {
+            Next::Truncated
+        } else {
+            Next::More
+        }
+    }
+
+    fn open(&mut self, sealed: &[u8], last: bool) -> Result<Vec<u8>> {
+        let nonce = nonce(&self.prefix, self.counter, last);
+        let plain = self
+            .key
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: sealed,
+                    aad: &self.header,
+                },
+            )
+            .map_err(|_| failed("Encrypted object failed authentication".into()))?;
+        self.counter = self.counter.wrapping_add(1);
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= plain.len() as u64;
+        }
+        Ok(plain)
+    }
+}
+
+/// Turns a sealed body back into plaintext, segment after segment.
+struct Opening {
+    input: BoxStream<'static, Result<Bytes>>,
+    keyring: Arc<Keyring>,
+    buffer: Vec<u8>,
+    opener: Option<Opener>,
+    ended: bool,
+}
+
+impl Opening {
+    /// Buffers the next piece of input; false once there is none.
+    async fn fill(&mut self) -> Result<bool> {
+        if self.ended {
+            return Ok(false);
+        }
+        match self.input.try_next().await? {
+            Some(bytes) => self.buffer.extend_from_slice(&bytes),
+            None => self.ended = true,
+        }
+        Ok(!self.ended)
+    }
+
+    async fn next_plaintext(&mut self) -> Result<Option<Bytes>> {
+        loop {
+            let Some(opener) = &mut self.opener else {
+                if self.buffer.is_empty() && !self.fill().await? {
+                    return Ok(None);
+                }
+                match Opener::parse(&self.keyring, &self.buffer)? {
+                    Some((opener, len)) => {
+                        self.buffer.drain(..len);
+                        self.opener = Some(opener);
+                    }
+                    None if !self.fill().await? => {
+                        return Err(failed("Encrypted object is truncated".into()));
+                    }
+                    None => {}
+                }
+                continue;
+            };
+            match opener.next(self.buffer.len(), self.ended) {
+                Next::More => {
+                    self.fill().await?;
+                }
+                Next::Truncated => return Err(failed("Encrypted object is truncated".into())),
+                Next::Chunk { len, last } => {
+                    let plain = opener.open(&self.buffer[..len], last)?;
+                    self.buffer.drain(..len);
+                    if last {
+                        self.opener = None;
+                    }
+                    if !plain.is_empty() {
+                        return Ok(Some(plain.into()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn open_stream(
+    keyring: Arc<Keyring>,
+    prefix: Vec<u8>,
+    input: BoxStream<'static, Result<Bytes>>,
+) -> BoxStream<'static, Result<Bytes>> {
+    let opening = Opening {
+        input,
+        keyring,
+        buffer: prefix,
+        opener: None,
+        ended: false,
+    };
+    futures_util::stream::unfold(Some(opening), |state| async move {
+        let mut opening = state?;
+        match opening.next_plaintext().await {
+            Ok(Some(bytes)) => Some((Ok(bytes), Some(opening))),
+            Ok(None) => None,
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+    // `collect_bytes` polls once more after the end of an empty body.
+    .fuse()
+    .boxed()
+}
+
+/// Seals everything written to `inner` and opens everything read from it.
+/// Objects that aren't sealed are read as they are.
+///
+/// Ranged reads fail with [`RangeNotSupported`]. Listings report the sealed
+/// size; `head` and `get` report the plaintext size, except for objects
+/// assembled from [`MultipartStore`] parts, which only the uploads use.
+#[derive(Debug)]
+pub struct EncryptedStore<T> {
+    inner: T,
+    keyring: Arc<Keyring>,
+}
+
+impl<T> EncryptedStore<T> {
+    pub fn new(inner: T, keyring: Keyring) -> Self {
+        Self {
+            inner,
+            keyring: Arc::new(keyring),
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for EncryptedStore<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Encrypted({})", self.inner)
+    }
+}
+
+impl<T: ObjectStore> EncryptedStore<T> {
+    /// The plaintext size of the object at `location`, from its metadata or
+    /// else from its header.
+    async fn plaintext_size(
+        &self,
+        location: &Path,
+        attributes: &Attributes,
+        size: usize,
+    ) -> Result<usize> {
+        if let Some(size) = attributes
+            .get(&Attribute::Metadata(PLAINTEXT_SIZE_METADATA.into()))
+            .and_then(|v| v.parse().ok())
+        {
+            return Ok(size);
+        }
+        if size < HEADER_BYTES {
+            return Ok(size);
+        }
+        let header = self.inner.get_range(location, 0..HEADER_BYTES).await?;
+        Ok(sealed_size_to_plaintext(&header, size))
+    }
+}
+
+fn sealed_size_to_plaintext(header: &[u8], size: usize) -> usize {
+    if is_sealed(header) && header[4] == STREAM {
+        plaintext_len(size)
+    } else {
+        size
+    }
+}
+
+fn range_not_supported() -> object_store::Error {
+    object_store::Error::NotSupported {
+        source: Box::new(RangeNotSupported),
+    }
+}
+
+#[async_trait]
+impl<T: ObjectStore> ObjectStore for EncryptedStore<T> {
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        mut opts: PutOptions,
+    ) -> Result<PutResult> {
+        opts.attributes.insert(
+            Attribute::Metadata(PLAINTEXT_SIZE_METADATA.into()),
+            payload.content_length().to_string().into(),
+        );
+        let sealed = seal_all(&self.keyring, &payload, None);
+        self.inner.put_opts(location, sealed.into(), opts).await
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOpts,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        let upload = self.inner.put_multipart_opts(location, opts).await?;
+        Ok(Box::new(SealingUpload {
+            inner: upload,
+            sealer: Sealer::new(&self.keyring, None),
+            held: None,
+        }))
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        if options.range.is_some() {
+            return Err(range_not_supported());
+        }
+        let head = options.head;
+        let result = self.inner.get_opts(location, options).await?;
+        let mut meta = result.meta.clone();
+        let mut attributes = result.attributes.clone();
+        attributes.remove(&Attribute::Metadata(PLAINTEXT_SIZE_METADATA.into()));
+        if head {
+            meta.size = self
+                .plaintext_size(location, &result.attributes, meta.size)
+                .await?;
+            return Ok(GetResult {
+                range: 0..meta.size,
+                meta,
+                attributes,
+                ..result
+            });
+        }
+
+        let mut input = result.into_stream();
+        let mut prefix = Vec::new();
+        while prefix.len() < HEADER_BYTES {
+            match input.try_next().await? {
+                Some(bytes) => prefix.extend_from_slice(&bytes),
+                None => break,
+            }
+        }
+        let payload = if is_sealed(&prefix) {
+            meta.size = sealed_size_to_plaintext(&prefix, meta.size);
+            open_stream(self.keyring.clone(), prefix, input)
+        } else {
+            futures_util::stream::once(async move { Ok(Bytes::from(prefix)) })
+                .chain(input)
+                .boxed()
+        };
+        Ok(GetResult {
+            payload: GetResultPayload::Stream(payload),
+            range: 0..meta.size,
+            meta,
+            attributes,
+        })
+    }
+
+    async fn get_range(&self, _location: &Path, _range: Range<usize>) -> Result<Bytes> {
+        Err(range_not_supported())
+    }
+
+    async fn get_ranges(&self, _location: &Path, _ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        Err(range_not_supported())
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        let mut meta = self.inner.head(location).await?;
+        meta.size = self
+            .plaintext_size(location, &Attributes::new(), meta.size)
+            .await?;
+        Ok(meta)
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.inner.delete(location).await
+    }
+
+    fn delete_stream<'a>(
+        &'a self,
+        locations: BoxStream<'a, Result<Path>>,
+    ) -> BoxStream<'a, Result<Path>> {
+        self.inner.delete_stream(locations)
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.inner.list(prefix)
+    }
+
+    fn list_with_offset(
+        &self,
+        prefix: Option<&Path>,
+        offset: &Path,
+    ) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.inner.list_with_offset(prefix, offset)
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.rename(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+
+    async fn rename_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.rename_if_not_exists(from, to).await
+    }
+}
+
+/// Parts uploaded on their own are sealed as self-contained segments, since
+/// they may arrive in any order.
+#[async_trait]
+impl<T: MultipartStore> MultipartStore for EncryptedStore<T> {
+    async fn create_multipart(&self, path: &Path) -> Result<MultipartId> {
+        self.inner.create_multipart(path).await
+    }
+
+    async fn put_part(
+        &self,
+        path: &Path,
+        id: &MultipartId,
+        part_idx: usize,
+        data: PutPayload,
+    ) -> Result<PartId> {
+        let length = data.content_length() as u64;
+        let sealed = seal_all(&self.keyring, &data, Some(length));
+        self.inner.put_part(path, id, part_idx, sealed.into()).await
+    }
+
+    async fn complete_multipart(
+        &self,
+        path: &Path,
+        id: &MultipartId,
+        parts: Vec<PartId>,
+    ) -> Result<PutResult> {
+        self.inner.complete_multipart(path, id, parts).await
+    }
+
+    async fn abort_multipart(&self, path: &Path, id: &MultipartId) -> Result<()> {
+        self.inner.abort_multipart(path, id).await
+    }
+}
+
+/// Seals one stream across all parts. Each part's output is held back until
+/// the next arrives, so `complete` can append the final chunk to the real
+/// last part and every part before it keeps the size it was written with.
+#[derive(Debug)]
+struct SealingUpload {
+    inner: Box<dyn MultipartUpload>,
+    sealer: Sealer,
+    held: Option<Vec<u8>>,
+}
+
+impl fmt::Debug for Sealer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sealer")
+            .field("key", &hex::encode(self.key.id))
+            .field("counter", &self.counter)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl MultipartUpload for SealingUpload {
+    fn put_part(&mut self, data: PutPayload) -> UploadPart {
+        let mut sealed = Vec::with_capacity(sealed_len(data.content_length()));
+        for bytes in &data {
+            self.sealer.update(bytes, &mut sealed);
+        }
+        match self.held.replace(sealed) {
+            Some(previous) => self.inner.put_part(previous.into()),
+            None => Box::pin(async { Ok(()) }),
+        }
+    }
+
+    async fn complete(&mut self) -> Result<PutResult> {
+        let mut last = self.held.take().unwrap_or_default();
+        self.sealer.finish(&mut last);
+        self.inner.put_part(last.into()).await?;
+        self.inner.complete().await
+    }
+
+    async fn abort(&mut self) -> Result<()> {
+        self.inner.abort().await
+    }
+}
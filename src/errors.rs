@@ -0,0 +1,361 @@
+//! Errors of the `/files` endpoints, rendered as `{ "code": ..., "message": ... }`
+//! so clients can branch on `code` instead of parsing messages.
+
+use axum::{
+    Json,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use sea_orm::DbErr;
+use serde::Serialize;
+use std::{error::Error as _, io, time::Duration};
+use utoipa::ToSchema;
+
+use crate::{circuit_store, timeout_store::is_timeout};
+
+/// The `<Code>` and `<Message>` of the S3 error document in `error`.
+fn s3_error(error: &object_store::Error) -> Option<(String, String)> {
+    let text = error.to_string();
+    let tag = |name: &str| {
+        let (_, rest) = text.split_once(&format!("<{name}>"))?;
+        let (value, _) = rest.split_once(&format!("</{name}>"))?;
+        Some(value.to_string())
+    };
+    Some((tag("Code")?, tag("Message").unwrap_or_default()))
+}
+
+/// The status the backend answered `error` with, as object_store words it:
+/// `... error with status 403 Forbidden: ...`.
+fn answered_status(error: &object_store::Error) -> Option<u16> {
+    let text = error.to_string();
+    let (_, rest) = text.split_once("with status ")?;
+    rest.get(..3)?.parse().ok()
+}
+
+/// Whether `error` never got an answer: a refused or dropped connection, a
+/// name that didn't resolve, or a call that ran out of time.
+fn is_unreachable(error: &object_store::Error) -> bool {
+    if is_timeout(error) {
+        return true;
+    }
+    let mut cause = error.source();
+    while let Some(e) = cause {
+        if let Some(e) = e.downcast_ref::<reqwest::Error>()
+            && (e.is_connect() || e.is_timeout())
+        {
+            return true;
+        }
+        if let Some(e) = e.downcast_ref::<io::Error>()
+            && matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::HostUnreachable
+                    | io::ErrorKind::NetworkUnreachable
+                    | io::ErrorKind::TimedOut
+            )
+        {
+            return true;
+        }
+        cause = e.source();
+    }
+    false
+}
+
+/// What a failed storage call says about the backend. Responses, the
+/// health check and its failure counts all go by it, so that storage being
+/// down or misconfigured is told apart from a fault of the server's own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageFailure {
+    NotFound,
+    /// The backend refused the server's credentials or permissions.
+    Auth,
+    /// No answer from the backend, or no bucket by the configured name.
+    Unreachable,
+    /// Refused by the circuit breaker, without calling the backend.
+    CircuitOpen,
+    /// Refused for the object's sake, such as by an Object Lock retention,
+    /// rather than for the server's.
+    Protected,
+    AlreadyExists,
+    Precondition,
+    Other,
+}
+
+impl StorageFailure {
+    pub fn of(error: &object_store::Error) -> Self {
+        use object_store::Error;
+        if circuit_store::retry_after(error).is_some() {
+            return Self::CircuitOpen;
+        }
+        let status = match error {
+            Error::NotFound { .. } => Some(404),
+            Error::Unauthenticated { .. } => Some(401),
+            Error::PermissionDenied { .. } => Some(403),
+            Error::AlreadyExists { .. } => Some(409),
+            Error::Precondition { .. } => Some(412),
+            // Listings and the like keep the status only in their message.
+            Error::Generic { .. } => answered_status(error),
+            _ => None,
+        };
+        let s3_error = || s3_error(error);
+        match status {
+            Some(404) if s3_error().is_some_and(|(code, _)| code == "NoSuchBucket") => {
+                Self::Unreachable
+            }
+            Some(404) => Self::NotFound,
+            Some(401 | 403)
+                if s3_error()
+                    .is_some_and(|(_, message)| message.to_lowercase().contains("object lock")) =>
+            {
+                Self::Protected
+            }
+            Some(401 | 403) => Self::Auth,
+            Some(409) => Self::AlreadyExists,
+            Some(412) => Self::Precondition,
+            _ if is_unreachable(error) => Self::Unreachable,
+            _ => Self::Other,
+        }
+    }
+
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Auth | Self::Unreachable | Self::CircuitOpen => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Protected => StatusCode::FORBIDDEN,
+            Self::AlreadyExists => StatusCode::CONFLICT,
+            Self::Precondition => StatusCode::PRECONDITION_FAILED,
+            Self::Other => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::NotFound => "not_found",
+            Self::Auth => "storage_auth",
+            Self::Unreachable => "storage_unreachable",
+            Self::CircuitOpen => "storage_unavailable",
+            Self::Protected => "storage_access_denied",
+            Self::AlreadyExists => "already_exists",
+            Self::Precondition => "precondition_failed",
+            Self::Other => "storage_error",
+        }
+    }
+
+    /// Whether the failure is the backend's or the server's rather than
+    /// an answer about one object: what the health check counts.
+    pub fn is_fault(self) -> bool {
+        matches!(self, Self::Auth | Self::Unreachable | Self::Other)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FileError {
+    #[error("{0}")]
+    NotFound(String),
+    #[error("authentication required")]
+    Unauthorized,
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("storage error: {0}")]
+    StorageError(#[from] object_store::Error),
+    #[error("configuration error: {0}")]
+    ConfigError(String),
+    /// A rejection that already knows its status and code, such as the 422s
+    /// of upload validation or a 409 version conflict.
+    #[error("{message}")]
+    Rejected {
+        status: StatusCode,
+        code: String,
+        message: String,
+        details: Option<serde_json::Value>,
+    },
+    /// Turned away by a `TransferLimit`.
+    #[error("Too many {transfers} are running; try again shortly")]
+    Busy {
+        transfers: &'static str,
+        retry_after: Duration,
+    },
+    /// Anything else; the message is logged but not shown to the client.
+    #[error("{0}")]
+    Internal(String),
+}
+
+pub type FileResult<T> = std::result::Result<T, FileError>;
+
+/// Storage settings that can't work, found before any request is served.
+#[derive(Debug, thiserror::Error)]
+#[error("S3Config: {0}")]
+pub struct ConfigError(pub String);
+
+impl From<ConfigError> for FileError {
+    fn from(e: ConfigError) -> Self {
+        Self::ConfigError(e.0)
+    }
+}
+
+/// What every `FileError` response carries.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody<'a> {
+    /// Stable, machine-readable, such as `not_found` or `storage_timeout`.
+    pub code: &'a str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+impl FileError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::StorageError(e) => StorageFailure::of(e).status(),
+            Self::ConfigError(_) | Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Rejected { status, .. } => *status,
+            Self::Busy { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        match self {
+            Self::NotFound(_) => "not_found",
+            Self::Unauthorized => "unauthorized",
+            Self::BadRequest(_) => "bad_request",
+            Self::StorageError(e) => StorageFailure::of(e).code(),
+            Self::ConfigError(_) => "config_error",
+            Self::Rejected { code, .. } => code,
+            Self::Busy { .. } => "too_many_transfers",
+            Self::Internal(_) => "internal_error",
+        }
+    }
+
+    /// What the client is told, as it is sent.
+    pub fn body(&self) -> ErrorBody<'_> {
+        ErrorBody {
+            code: self.code(),
+            message: self.public_message(),
+            details: match self {
+                Self::Rejected { details, .. } => details.clone(),
+                // S3's code says what to fix; its message can quote the
+                // bucket's endpoint or the access key back, so it stays out.
+                Self::StorageError(e) => match StorageFailure::of(e) {
+                    StorageFailure::Auth
+                    | StorageFailure::Unreachable
+                    | StorageFailure::Protected => {
+                        s3_error(e).map(|(code, _)| serde_json::json!({ "s3_code": code }))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            },
+        }
+    }
+
+    fn public_message(&self) -> String {
+        match self {
+            Self::StorageError(e) => storage_message(e),
+            Self::ConfigError(_) => "The server is misconfigured".to_string(),
+            Self::Internal(_) => "Internal server error".to_string(),
+            other => other.to_string(),
+        }
+    }
+}
+
+fn storage_message(error: &object_store::Error) -> String {
+    let message = match StorageFailure::of(error) {
+        StorageFailure::NotFound => "Object not found in storage",
+        StorageFailure::Auth => {
+            "The storage backend refused the server's credentials; check its access key and the bucket's policy"
+        }
+        StorageFailure::Unreachable if is_timeout(error) => {
+            "The storage backend did not answer in time"
+        }
+        StorageFailure::Unreachable if matches!(error, object_store::Error::NotFound { .. }) => {
+            "The storage bucket does not exist; check its name and region"
+        }
+        StorageFailure::Unreachable => {
+            "The storage backend could not be reached; check its endpoint and the network to it"
+        }
+        StorageFailure::CircuitOpen => {
+            "The storage backend keeps failing and is given a rest; try again later"
+        }
+        // Only an object's protection is explained, S3's words being the
+        // clearest account of what protects it.
+        StorageFailure::Protected => {
+            return match s3_error(error) {
+                Some((code, message)) => format!("The storage backend refused: {code}: {message}"),
+                None => "The storage backend refused the request".to_string(),
+            };
+        }
+        StorageFailure::AlreadyExists => "The object already exists in storage",
+        StorageFailure::Precondition => "The object in storage has changed",
+        StorageFailure::Other => "The storage backend failed",
+    };
+    message.to_string()
+}
+
+impl IntoResponse for FileError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!(error = %self, code = self.code(), "file endpoint failed");
+        }
+
+        let mut response = (status, Json(self.body())).into_response();
+        let retry_after = match &self {
+            Self::StorageError(e) => circuit_store::retry_after(e),
+            Self::Busy { retry_after, .. } => Some(*retry_after),
+            _ => None,
+        };
+        if let Some(wait) = retry_after {
+            let seconds = wait.as_secs_f64().ceil().max(1.0) as u64;
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, seconds.into());
+        }
+        response
+    }
+}
+
+/// Errors from loco helpers keep the status and code they were raised with.
+impl From<loco_rs::Error> for FileError {
+    fn from(err: loco_rs::Error) -> Self {
+        match err {
+            loco_rs::Error::NotFound => Self::NotFound("Resource was not found".into()),
+            loco_rs::Error::Unauthorized(_) => Self::Unauthorized,
+            loco_rs::Error::BadRequest(message) => Self::BadRequest(message),
+            loco_rs::Error::CustomError(status, detail) => Self::Rejected {
+                status,
+                code: detail.error.unwrap_or_else(|| status.as_str().to_string()),
+                message: detail.description.unwrap_or_default(),
+                details: detail.errors,
+            },
+            loco_rs::Error::DB(e) => e.into(),
+            // Helpers pass storage errors on with `Error::wrap`, so they are
+            // classified like the handlers' own.
+            loco_rs::Error::Any(e) => match e.downcast::<object_store::Error>() {
+                Ok(e) => Self::StorageError(*e),
+                Err(e) => Self::Internal(e.to_string()),
+            },
+            other => Self::Internal(other.to_string()),
+        }
+    }
+}
+
+impl From<DbErr> for FileError {
+    fn from(err: DbErr) -> Self {
+        match err {
+            DbErr::RecordNotFound(message) => Self::NotFound(message),
+            other => Self::Internal(other.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for FileError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Internal(err.to_string())
+    }
+}
@@ -0,0 +1,120 @@
+//! Upload and processing progress, published by handlers and workers and
+//! streamed to clients by `GET /files/events`.
+
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, OnceLock},
+};
+use tokio::sync::broadcast;
+
+/// How many past events a reconnecting client can catch up on.
+const REPLAY_CAPACITY: usize = 256;
+/// Subscribers further behind than this skip ahead instead of blocking publishers.
+const CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ProgressKind {
+    #[serde(rename = "upload.started")]
+    UploadStarted,
+    #[serde(rename = "upload.completed")]
+    UploadCompleted,
+    #[serde(rename = "thumbnail.ready")]
+    ThumbnailReady,
+    #[serde(rename = "scan.completed")]
+    ScanCompleted,
+}
+
+impl ProgressKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::UploadStarted => "upload.started",
+            Self::UploadCompleted => "upload.completed",
+            Self::ThumbnailReady => "thumbnail.ready",
+            Self::ScanCompleted => "scan.completed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressEvent {
+    /// Increases by one per event; sent as the SSE id for `Last-Event-ID`.
+    pub id: u64,
+    pub kind: ProgressKind,
+    pub file_name: String,
+    /// The uploader, when known.
+    pub user_id: Option<i32>,
+    /// Kind-specific details, such as the scan status.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<serde_json::Value>,
+    pub timestamp: String,
+}
+
+pub struct EventBus {
+    sender: broadcast::Sender<ProgressEvent>,
+    /// Also guards id assignment, so ids reach the channel in order.
+    recent: Mutex<(u64, VecDeque<ProgressEvent>)>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            recent: Mutex::new((0, VecDeque::with_capacity(REPLAY_CAPACITY))),
+        }
+    }
+}
+
+impl EventBus {
+    pub fn publish(
+        &self,
+        kind: ProgressKind,
+        file_name: &str,
+        user_id: Option<i32>,
+        detail: Option<serde_json::Value>,
+    ) {
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent.0 += 1;
+        let event = ProgressEvent {
+            id: recent.0,
+            kind,
+            file_name: file_name.to_string(),
+            user_id,
+            detail,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+        };
+        if recent.1.len() == REPLAY_CAPACITY {
+            recent.1.pop_front();
+        }
+        recent.1.push_back(event.clone());
+        // Nobody listening is fine; the event is still kept for replay.
+        let _ = self.sender.send(event);
+    }
+
+    /// A live subscription plus the buffered events newer than `last_id`.
+    /// Both are taken under the lock, so they neither overlap nor leave a gap.
+    pub fn subscribe(
+        &self,
+        last_id: Option<u64>,
+    ) -> (broadcast::Receiver<ProgressEvent>, Vec<ProgressEvent>) {
+        let recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        let receiver = self.sender.subscribe();
+        let replay = match last_id {
+            Some(last_id) => recent
+                .1
+                .iter()
+                .filter(|e| e.id > last_id)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+        (receiver, replay)
+    }
+}
+
+static BUS: OnceLock<EventBus> = OnceLock::new();
+
+/// The process-wide bus; workers run in the same process as the handlers.
+pub fn bus() -> &'static EventBus {
+    BUS.get_or_init(EventBus::default)
+}
@@ -0,0 +1,262 @@
+//! Restores of objects archived to S3 Glacier, and object encryption headers,
+//! which object_store has no API for. Requests are signed with object_store's
+//! SigV4 signer and sent path-style.
+
+use axum::body::Bytes;
+use loco_rs::{Error, Result, controller::ErrorDetail};
+use object_store::aws::{AwsAuthorizer, AwsCredential};
+use reqwest::{Client, Method, StatusCode};
+use serde::Serialize;
+use std::sync::OnceLock;
+use utoipa::ToSchema;
+
+/// Shared so restores reuse connections like the object_store client does.
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+/// Where archived objects live and how to sign requests for them.
+pub struct Bucket<'a> {
+    pub endpoint: &'a str,
+    pub name: &'a str,
+    pub region: &'a str,
+    pub access_key: &'a str,
+    pub secret_key: &'a str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreRequested {
+    /// S3 accepted a new restore job.
+    Started,
+    /// A restored copy already exists; S3 only extended its expiry.
+    AlreadyRestored,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestoreStatus {
+    NotRequested,
+    InProgress,
+    Completed,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RestoreState {
+    pub status: RestoreStatus,
+    pub expiry: Option<String>,
+    pub storage_class: Option<String>,
+}
+
+/// The server-side encryption S3 reports for an object.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Encryption {
+    /// `AES256` for SSE-S3, `aws:kms` or `aws:kms:dsse` for SSE-KMS.
+    pub algorithm: String,
+    pub kms_key_id: Option<String>,
+}
+
+fn s3_error(status: StatusCode, code: &str, description: String) -> Error {
+    Error::CustomError(status, ErrorDetail::new(code, &description))
+}
+
+impl Bucket<'_> {
+    /// Starts a `RestoreObject` job keeping the restored copy for `days`.
+    pub async fn restore_object(&self, key: &str, days: u32) -> Result<RestoreRequested> {
+        let body = format!(
+            "<RestoreRequest><Days>{days}</Days>\
+             <GlacierJobParameters><Tier>Standard</Tier></GlacierJobParameters>\
+             </RestoreRequest>"
+        );
+        let (status, text) = self
+            .send(
+                Method::POST,
+                &format!("{}?restore", self.object_url(key)),
+                Some(body),
+            )
+            .await?;
+
+        match status {
+            StatusCode::ACCEPTED => Ok(RestoreRequested::Started),
+            StatusCode::OK => Ok(RestoreRequested::AlreadyRestored),
+            StatusCode::NOT_FOUND => Err(Error::NotFound),
+            _ => {
+                let code = error_code(&text);
+                Err(match code.as_deref() {
+                    Some("RestoreAlreadyInProgress") => s3_error(
+                        StatusCode::CONFLICT,
+                        "restore_in_progress",
+                        format!("A restore of '{key}' is already in progress"),
+                    ),
+                    Some("InvalidObjectState") => s3_error(
+                        StatusCode::CONFLICT,
+                        "not_archived",
+                        format!("'{key}' is not in an archive storage class"),
+                    ),
+                    _ => Error::Message(format!(
+                        "RestoreObject failed with {status}: {}",
+                        code.unwrap_or_default()
+                    )),
+                })
+            }
+        }
+    }
+
+    /// Reads the `x-amz-restore` and `x-amz-storage-class` headers of `key`.
+    pub async fn restore_state(&self, key: &str) -> Result<RestoreState> {
+        let response = self
+            .execute(Method::HEAD, &self.object_url(key), None)
+            .await?;
+        match response.status() {
+            StatusCode::OK => {}
+            StatusCode::NOT_FOUND => return Err(Error::NotFound),
+            status => return Err(Error::Message(format!("HeadObject failed with {status}"))),
+        }
+
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let storage_class = header("x-amz-storage-class");
+        let Some(restore) = header("x-amz-restore") else {
+            return Ok(RestoreState {
+                status: RestoreStatus::NotRequested,
+                expiry: None,
+                storage_class,
+            });
+        };
+
+        let (ongoing, expiry) = parse_restore_header(&restore);
+        Ok(RestoreState {
+            status: if ongoing {
+                RestoreStatus::InProgress
+            } else {
+                RestoreStatus::Completed
+            },
+            expiry,
+            storage_class,
+        })
+    }
+
+    /// Reads the `x-amz-server-side-encryption` headers of `key`.
+    pub async fn encryption(&self, key: &str) -> Result<Option<Encryption>> {
+        let response = self
+            .execute(Method::HEAD, &self.object_url(key), None)
+            .await?;
+        match response.status() {
+            StatusCode::OK => {}
+            StatusCode::NOT_FOUND => return Err(Error::NotFound),
+            status => return Err(Error::Message(format!("HeadObject failed with {status}"))),
+        }
+
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        Ok(
+            header("x-amz-server-side-encryption").map(|algorithm| Encryption {
+                algorithm,
+                kms_key_id: header("x-amz-server-side-encryption-aws-kms-key-id"),
+            }),
+        )
+    }
+
+    pub(crate) fn object_url(&self, key: &str) -> String {
+        let encoded: Vec<String> = key.split('/').map(encode_segment).collect();
+        format!(
+            "{}/{}/{}",
+            self.endpoint.trim_end_matches('/'),
+            encode_segment(self.name),
+            encoded.join("/")
+        )
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<String>,
+    ) -> Result<(StatusCode, String)> {
+        let response = self.execute(method, url, body).await?;
+        let status = response.status();
+        let text = response
+            .text()
+            .await
+            .map_err(|e| Error::Message(format!("S3 response error: {e}")))?;
+        Ok((status, text))
+    }
+
+    async fn execute(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<String>,
+    ) -> Result<reqwest::Response> {
+        self.signed(method, url, &[], body.map(Bytes::from)).await
+    }
+
+    /// Sends a request with `headers`, signed for this bucket.
+    pub(crate) async fn signed(
+        &self,
+        method: Method,
+        url: &str,
+        headers: &[(&str, String)],
+        body: Option<Bytes>,
+    ) -> Result<reqwest::Response> {
+        let client = CLIENT.get_or_init(Client::new);
+        let mut builder = client.request(method, url);
+        for (name, value) in headers {
+            builder = builder.header(*name, value);
+        }
+        if let Some(body) = body {
+            builder = builder.body(body);
+        }
+        let mut request = builder
+            .build()
+            .map_err(|e| Error::Message(format!("S3 request error: {e}")))?;
+
+        let credential = AwsCredential {
+            key_id: self.access_key.to_string(),
+            secret_key: self.secret_key.to_string(),
+            token: None,
+        };
+        AwsAuthorizer::new(&credential, "s3", self.region).authorize(&mut request, None);
+
+        client
+            .execute(request)
+            .await
+            .map_err(|e| Error::Message(format!("S3 request error: {e}")))
+    }
+}
+
+/// Percent-encodes one path segment the way SigV4 canonical URIs expect.
+pub(crate) fn encode_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Splits `ongoing-request="false", expiry-date="Fri, 21 Dec 2012 00:00:00 GMT"`.
+fn parse_restore_header(value: &str) -> (bool, Option<String>) {
+    let ongoing = value.contains("ongoing-request=\"true\"");
+    let expiry = value
+        .split_once("expiry-date=\"")
+        .and_then(|(_, rest)| rest.split_once('"'))
+        .map(|(date, _)| date.to_string());
+    (ongoing, expiry)
+}
+
+fn error_code(body: &str) -> Option<String> {
+    body.split_once("<Code>")
+        .and_then(|(_, rest)| rest.split_once("</Code>"))
+        .map(|(code, _)| code.to_string())
+}
@@ -1,4 +1,32 @@
+pub mod access_log;
 pub mod app;
+pub mod audit_writer;
+pub mod cache_store;
+pub mod circuit_store;
+pub mod content_index;
 pub mod controllers;
+pub mod encryption;
+pub mod errors;
+pub mod events;
+pub mod glacier;
+pub mod local_store;
 pub mod models;
+pub mod multipart_gc;
+pub mod object_lock;
+pub mod path_strategy;
+pub mod previews;
+pub mod remote_fetch;
+pub mod replication;
+pub mod retry_store;
+pub mod scanner;
+pub mod signed_urls;
+pub mod storage_hooks;
+pub mod tar_stream;
+pub mod tasks;
+pub mod thumbnails;
+pub mod timeout_store;
+pub mod transfer_limit;
+pub mod upload_pipeline;
 pub mod views;
+pub mod webhooks;
+pub mod workers;
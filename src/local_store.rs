@@ -0,0 +1,100 @@
+//! The `local_storage_path` backend: objects are plain files under one directory.
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures_util::stream::BoxStream;
+use object_store::{
+    Attributes, GetOptions, GetResult, ListResult, MultipartUpload, ObjectMeta, ObjectStore,
+    PutMultipartOpts, PutOptions, PutPayload, PutResult, Result, local::LocalFileSystem,
+    path::Path,
+};
+use std::{fmt, ops::Range};
+
+/// `LocalFileSystem` refuses any put that carries attributes, and every
+/// upload sets some (`sha256`, custom metadata). They are dropped here;
+/// readers fall back to the index and to the content type of the file name.
+#[derive(Debug)]
+pub struct LocalStore(LocalFileSystem);
+
+impl LocalStore {
+    /// Creates `path` if needed and checks that it can be written to.
+    pub fn open(path: &str) -> std::result::Result<Self, String> {
+        std::fs::create_dir_all(path)
+            .map_err(|e| format!("Local storage path '{path}' could not be created: {e}"))?;
+
+        let probe = std::path::Path::new(path).join(".write-check");
+        std::fs::write(&probe, b"")
+            .and_then(|()| std::fs::remove_file(&probe))
+            .map_err(|e| format!("Local storage path '{path}' is not writable: {e}"))?;
+
+        LocalFileSystem::new_with_prefix(path)
+            .map(|fs| Self(fs.with_automatic_cleanup(true)))
+            .map_err(|e| format!("Local storage path '{path}' is unusable: {e}"))
+    }
+}
+
+impl fmt::Display for LocalStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[async_trait]
+impl ObjectStore for LocalStore {
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult> {
+        let opts = PutOptions {
+            attributes: Attributes::new(),
+            ..opts
+        };
+        self.0.put_opts(location, payload, opts).await
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOpts,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        let opts = PutMultipartOpts {
+            attributes: Attributes::new(),
+            ..opts
+        };
+        self.0.put_multipart_opts(location, opts).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        self.0.get_opts(location, options).await
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        self.0.get_ranges(location, ranges).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.0.delete(location).await
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.0.list(prefix)
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.0.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.0.copy(from, to).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.0.rename(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.0.copy_if_not_exists(from, to).await
+    }
+}
@@ -0,0 +1,88 @@
+use std::{fmt::Display, future::Future};
+
+use chrono::Utc;
+use loco_rs::prelude::*;
+use sea_orm::{QuerySelect, entity::prelude::*, sea_query::OnConflict};
+use serde::{Deserialize, Serialize};
+
+/// Content-addressed object shared by every deduplicated file with the same bytes.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "blobs")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub hash: String,
+    pub size: i64,
+    pub ref_count: i32,
+    #[sea_orm(column_type = "Timestamp")]
+    pub created_at: sea_orm::prelude::DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Takes a reference on `hash`, creating the row on first use.
+///
+/// The upsert waits on the row lock held by [`release`], so it can never bump
+/// a count that is about to be deleted: it either lands before the release
+/// (which then keeps the object) or after it (as a fresh row).
+pub async fn acquire(db: &DatabaseConnection, hash: &str, size: i64) -> Result<(), DbErr> {
+    Entity::insert(ActiveModel {
+        hash: Set(hash.to_string()),
+        size: Set(size),
+        ref_count: Set(1),
+        created_at: Set(Utc::now().naive_utc()),
+    })
+    .on_conflict(
+        OnConflict::column(Column::Hash)
+            .value(
+                Column::RefCount,
+                Expr::col((Entity, Column::RefCount)).add(1),
+            )
+            .to_owned(),
+    )
+    .exec_without_returning(db)
+    .await?;
+    Ok(())
+}
+
+/// Drops a reference on `hash`. When it is the last one, `remove_object` is
+/// awaited while the row is still locked and the row is deleted only if that
+/// succeeds, so a failed delete leaves the count in place rather than a blob
+/// nobody tracks.
+pub async fn release<F, Fut, E>(
+    db: &DatabaseConnection,
+    hash: &str,
+    remove_object: F,
+) -> Result<(), DbErr>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = std::result::Result<(), E>>,
+    E: Display,
+{
+    let txn = db.begin().await?;
+
+    let Some(blob) = Entity::find_by_id(hash.to_string())
+        .lock_exclusive()
+        .one(&txn)
+        .await?
+    else {
+        return txn.commit().await;
+    };
+
+    if blob.ref_count > 1 {
+        Entity::update_many()
+            .col_expr(Column::RefCount, Expr::col(Column::RefCount).sub(1))
+            .filter(Column::Hash.eq(hash))
+            .exec(&txn)
+            .await?;
+    } else {
+        remove_object()
+            .await
+            .map_err(|e| DbErr::Custom(format!("Failed to delete blob {hash}: {e}")))?;
+        Entity::delete_by_id(hash.to_string()).exec(&txn).await?;
+    }
+
+    txn.commit().await
+}
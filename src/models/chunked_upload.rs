@@ -0,0 +1,100 @@
+use chrono::Utc;
+use loco_rs::prelude::*;
+use sea_orm::{QuerySelect, entity::prelude::*};
+use serde::{Deserialize, Serialize};
+
+/// A file sent as separately uploaded parts, assembled on completion from
+/// the multipart upload `multipart_id`.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "chunked_uploads")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: String,
+    pub file_name: String,
+    pub author_id: i32,
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub metadata: Option<serde_json::Value>,
+    pub multipart_id: String,
+    #[sea_orm(column_type = "Timestamp")]
+    pub created_at: sea_orm::prelude::DateTime,
+    #[sea_orm(column_type = "Timestamp")]
+    pub updated_at: sea_orm::prelude::DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::AuthorId",
+        to = "super::user::Column::Id"
+    )]
+    Author,
+    #[sea_orm(has_many = "super::chunked_upload_part::Entity")]
+    Parts,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Author.def()
+    }
+}
+
+impl Related<super::chunked_upload_part::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Parts.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub struct NewUpload<'a> {
+    pub id: &'a str,
+    pub file_name: &'a str,
+    pub author_id: i32,
+    pub metadata: Option<serde_json::Value>,
+    pub multipart_id: &'a str,
+}
+
+pub async fn create(db: &DatabaseConnection, new: NewUpload<'_>) -> Result<Model, DbErr> {
+    let now = Utc::now().naive_utc();
+    ActiveModel {
+        id: Set(new.id.to_string()),
+        file_name: Set(new.file_name.to_string()),
+        author_id: Set(new.author_id),
+        metadata: Set(new.metadata),
+        multipart_id: Set(new.multipart_id.to_string()),
+        created_at: Set(now),
+        updated_at: Set(now),
+    }
+    .insert(db)
+    .await
+}
+
+/// The multipart uploads every row is assembling in.
+pub async fn multipart_ids(db: &DatabaseConnection) -> Result<Vec<String>, DbErr> {
+    Entity::find()
+        .select_only()
+        .column(Column::MultipartId)
+        .into_tuple()
+        .all(db)
+        .await
+}
+
+pub async fn find(db: &DatabaseConnection, id: &str) -> Result<Option<Model>, DbErr> {
+    Entity::find_by_id(id).one(db).await
+}
+
+pub async fn touch(db: &DatabaseConnection, id: &str) -> Result<(), DbErr> {
+    Entity::update_many()
+        .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+        .filter(Column::Id.eq(id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Deletes the upload; its parts go with it.
+pub async fn delete(db: &DatabaseConnection, id: &str) -> Result<(), DbErr> {
+    Entity::delete_by_id(id).exec(db).await?;
+    Ok(())
+}
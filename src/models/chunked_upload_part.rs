@@ -0,0 +1,72 @@
+use chrono::Utc;
+use loco_rs::prelude::*;
+use sea_orm::{QueryOrder, entity::prelude::*, sea_query::OnConflict};
+use serde::{Deserialize, Serialize};
+
+/// One received part of a chunked upload.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "chunked_upload_parts")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub upload_id: String,
+    /// 1-based, as in S3.
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub part_number: i32,
+    /// What the storage backend answered for the part, needed to complete.
+    pub content_id: String,
+    pub size: i64,
+    #[sea_orm(column_type = "Timestamp")]
+    pub updated_at: sea_orm::prelude::DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::chunked_upload::Entity",
+        from = "Column::UploadId",
+        to = "super::chunked_upload::Column::Id"
+    )]
+    Upload,
+}
+
+impl Related<super::chunked_upload::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Upload.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Records a part, replacing an earlier upload of the same number.
+pub async fn upsert(
+    db: &DatabaseConnection,
+    upload_id: &str,
+    part_number: i32,
+    content_id: &str,
+    size: i64,
+) -> Result<(), DbErr> {
+    let part = ActiveModel {
+        upload_id: Set(upload_id.to_string()),
+        part_number: Set(part_number),
+        content_id: Set(content_id.to_string()),
+        size: Set(size),
+        updated_at: Set(Utc::now().naive_utc()),
+    };
+    Entity::insert(part)
+        .on_conflict(
+            OnConflict::columns([Column::UploadId, Column::PartNumber])
+                .update_columns([Column::ContentId, Column::Size, Column::UpdatedAt])
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn find_by_upload(db: &DatabaseConnection, upload_id: &str) -> Result<Vec<Model>, DbErr> {
+    Entity::find()
+        .filter(Column::UploadId.eq(upload_id))
+        .order_by_asc(Column::PartNumber)
+        .all(db)
+        .await
+}
@@ -1,9 +1,15 @@
 use chrono::Utc;
 use loco_rs::prelude::*;
-use sea_orm::{ActiveValue::NotSet, entity::prelude::*};
+use sea_orm::{
+    ActiveValue::NotSet,
+    QueryOrder, QuerySelect, QueryTrait,
+    entity::prelude::*,
+    sea_query::{Alias, Query, extension::postgres::PgExpr},
+};
 use serde::{Deserialize, Serialize};
 
-use super::file_version;
+use super::file_tag;
+use crate::scanner::ScanStatus;
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
 #[sea_orm(table_name = "files")]
@@ -12,13 +18,38 @@ pub struct Model {
     pub id: i32,
     pub name: String,
     pub size: i64,
-    pub author_id: i32,
+    pub author_id: Option<i32>,
     #[sea_orm(column_type = "Timestamp")]
     pub created_at: sea_orm::prelude::DateTime,
     #[sea_orm(column_type = "Timestamp")]
     pub updated_at: sea_orm::prelude::DateTime,
     #[sea_orm(column_type = "Integer", default_value = 1)]
     pub version: i32,
+    pub orphaned: bool,
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub metadata: Option<serde_json::Value>,
+    /// Set when version 1 lives in the shared `blobs/{sha256}` object.
+    pub blob_hash: Option<String>,
+    /// Hex SHA-256 of the content uploaded as version 1.
+    pub content_hash: Option<String>,
+    /// Version 1 is stored gzip-compressed under `.gz` keys.
+    pub compressed: bool,
+    /// `pending`, `clean`, `infected` or `unscanned`; unset when scanning is off.
+    pub scan_status: Option<String>,
+    /// Signature the virus scanner reported for an infected file.
+    pub scan_signature: Option<String>,
+    /// The tenant whose bucket holds the file; unset for the default bucket.
+    pub tenant_id: Option<String>,
+    /// Folders `path_strategy` put version 1 under, ahead of the name.
+    pub object_shard: Option<String>,
+    /// Digests the client sent with version 1 and it was checked against,
+    /// hex by algorithm.
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub checksums: Option<serde_json::Value>,
+    /// `pending`, `synced` or `failed`; unset when replication is off.
+    pub replication_status: Option<String>,
+    /// Why the last copy to the replica failed.
+    pub replication_error: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -39,21 +70,109 @@ impl Related<super::user::Entity> for Entity {
 
 impl ActiveModelBehavior for ActiveModel {}
 
-pub async fn create(
+/// A freshly uploaded file, recorded as version 1.
+pub struct NewFile<'a> {
+    pub name: &'a str,
+    pub size: i64,
+    pub author_id: i32,
+    pub metadata: Option<serde_json::Value>,
+    pub content_hash: &'a str,
+    /// Content lives in the shared `blobs/{content_hash}` object.
+    pub deduplicated: bool,
+    pub compressed: bool,
+    pub tenant_id: Option<&'a str>,
+    pub object_shard: Option<&'a str>,
+    pub checksums: Option<serde_json::Value>,
+}
+
+pub async fn create(db: &DatabaseConnection, new: NewFile<'_>) -> Result<Model, DbErr> {
+    let now = Utc::now().naive_utc();
+    let res = Entity::insert(ActiveModel {
+        id: NotSet,
+        name: Set(new.name.to_string()),
+        size: Set(new.size),
+        author_id: Set(Some(new.author_id)),
+        created_at: Set(now),
+        updated_at: Set(now),
+        version: Set(1),
+        orphaned: Set(false),
+        metadata: Set(new.metadata),
+        blob_hash: Set(new.deduplicated.then(|| new.content_hash.to_string())),
+        content_hash: Set(Some(new.content_hash.to_string())),
+        compressed: Set(new.compressed),
+        scan_status: Set(None),
+        scan_signature: Set(None),
+        tenant_id: Set(new.tenant_id.map(str::to_string)),
+        object_shard: Set(new.object_shard.map(str::to_string)),
+        checksums: Set(new.checksums),
+        replication_status: Set(None),
+        replication_error: Set(None),
+    })
+    .exec(db)
+    .await?;
+
+    Entity::find_by_id(res.last_insert_id)
+        .one(db)
+        .await?
+        .ok_or(DbErr::RecordNotFound("File not found".to_string()))
+}
+
+/// Replaces the content recorded for file `id`, keeping its name and version.
+pub async fn overwrite(db: &DatabaseConnection, id: i32, new: NewFile<'_>) -> Result<Model, DbErr> {
+    Entity::update_many()
+        .col_expr(Column::Size, Expr::value(new.size))
+        .col_expr(Column::AuthorId, Expr::value(new.author_id))
+        .col_expr(Column::Metadata, Expr::value(new.metadata))
+        .col_expr(
+            Column::BlobHash,
+            Expr::value(new.deduplicated.then(|| new.content_hash.to_string())),
+        )
+        .col_expr(Column::ContentHash, Expr::value(new.content_hash))
+        .col_expr(Column::Compressed, Expr::value(new.compressed))
+        .col_expr(Column::ObjectShard, Expr::value(new.object_shard))
+        .col_expr(Column::Checksums, Expr::value(new.checksums))
+        .col_expr(Column::ScanStatus, Expr::value(Option::<String>::None))
+        .col_expr(Column::ScanSignature, Expr::value(Option::<String>::None))
+        .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+        .filter(Column::Id.eq(id))
+        .exec(db)
+        .await?;
+
+    Entity::find_by_id(id)
+        .one(db)
+        .await?
+        .ok_or(DbErr::RecordNotFound(format!("File {} not found", id)))
+}
+
+/// Indexes an object that was put into the bucket of `tenant_id` directly,
+/// so there is no uploader to record.
+pub async fn create_unattributed(
     db: &DatabaseConnection,
     name: &str,
     size: i64,
-    author_id: i32,
+    tenant_id: Option<&str>,
 ) -> Result<Model, DbErr> {
     let now = Utc::now().naive_utc();
     let res = Entity::insert(ActiveModel {
         id: NotSet,
         name: Set(name.to_string()),
         size: Set(size),
-        author_id: Set(author_id),
+        author_id: Set(None),
         created_at: Set(now),
         updated_at: Set(now),
         version: Set(1),
+        orphaned: Set(false),
+        metadata: Set(None),
+        blob_hash: Set(None),
+        content_hash: Set(None),
+        compressed: Set(false),
+        scan_status: Set(None),
+        scan_signature: Set(None),
+        tenant_id: Set(tenant_id.map(str::to_string)),
+        object_shard: Set(None),
+        checksums: Set(None),
+        replication_status: Set(None),
+        replication_error: Set(None),
     })
     .exec(db)
     .await?;
@@ -64,29 +183,396 @@ pub async fn create(
         .ok_or(DbErr::RecordNotFound("File not found".to_string()))
 }
 
+pub async fn find_all(db: &DatabaseConnection) -> Result<Vec<Model>, DbErr> {
+    Entity::find().all(db).await
+}
+
+/// The files in the bucket of `tenant_id`, or in the default one.
+pub async fn find_all_for_tenant(
+    db: &DatabaseConnection,
+    tenant_id: Option<&str>,
+) -> Result<Vec<Model>, DbErr> {
+    Entity::find()
+        .filter(tenant_condition(tenant_id))
+        .all(db)
+        .await
+}
+
+pub async fn set_orphaned(db: &DatabaseConnection, id: i32, orphaned: bool) -> Result<(), DbErr> {
+    Entity::update_many()
+        .col_expr(Column::Orphaned, Expr::value(orphaned))
+        .filter(Column::Id.eq(id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// The key of the object holding a file's current content, as
+/// `controllers::files` derives it, ordered the way S3 lists keys.
+const OBJECT_KEY_SQL: &str = r#"(CASE
+    WHEN blob_hash IS NOT NULL AND version = 1 THEN 'blobs/' || blob_hash
+    WHEN version > 1 THEN name
+    ELSE COALESCE(object_shard || '/', '') || name || CASE WHEN compressed THEN '.gz' ELSE '' END
+END) COLLATE "C""#;
+
+/// A page of the default bucket's files, orphaned ones included, in the
+/// order of the keys of their objects, from just after `after`, the object
+/// key and id of the last file of the page before.
+pub async fn page_by_object_key(
+    db: &DatabaseConnection,
+    after: Option<(&str, i32)>,
+    limit: u64,
+) -> Result<Vec<Model>, DbErr> {
+    Entity::find()
+        .filter(Column::TenantId.is_null())
+        .apply_if(after, |query, (key, id)| {
+            query.filter(Expr::cust_with_values(
+                format!(r#"({OBJECT_KEY_SQL}, id) > ($1 COLLATE "C", $2)"#),
+                [sea_orm::Value::from(key), id.into()],
+            ))
+        })
+        .order_by(Expr::cust(OBJECT_KEY_SQL), sea_orm::Order::Asc)
+        .order_by_asc(Column::Id)
+        .limit(limit)
+        .all(db)
+        .await
+}
+
+/// `set_orphaned`, unless the file was changed since it was read; returns
+/// whether it was set.
+pub async fn set_orphaned_if_unchanged(
+    db: &DatabaseConnection,
+    file: &Model,
+    orphaned: bool,
+) -> Result<bool, DbErr> {
+    let result = Entity::update_many()
+        .col_expr(Column::Orphaned, Expr::value(orphaned))
+        .filter(Column::Id.eq(file.id))
+        .filter(Column::UpdatedAt.eq(file.updated_at))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected == 1)
+}
+
+/// Records the size the bucket holds for `file`, unless the file was
+/// changed since it was read; returns whether it was recorded.
+pub async fn set_size_if_unchanged(
+    db: &DatabaseConnection,
+    file: &Model,
+    size: i64,
+) -> Result<bool, DbErr> {
+    let result = Entity::update_many()
+        .col_expr(Column::Size, Expr::value(size))
+        .filter(Column::Id.eq(file.id))
+        .filter(Column::UpdatedAt.eq(file.updated_at))
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected == 1)
+}
+
+pub async fn set_scan_status(
+    db: &DatabaseConnection,
+    id: i32,
+    status: &str,
+    signature: Option<&str>,
+) -> Result<(), DbErr> {
+    Entity::update_many()
+        .col_expr(Column::ScanStatus, Expr::value(status))
+        .col_expr(Column::ScanSignature, Expr::value(signature))
+        .filter(Column::Id.eq(id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn set_replication_status(
+    db: &DatabaseConnection,
+    id: i32,
+    status: &str,
+    error: Option<&str>,
+) -> Result<(), DbErr> {
+    Entity::update_many()
+        .col_expr(Column::ReplicationStatus, Expr::value(status))
+        .col_expr(Column::ReplicationError, Expr::value(error))
+        .filter(Column::Id.eq(id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn find_by_replication_status(
+    db: &DatabaseConnection,
+    status: &str,
+) -> Result<Vec<Model>, DbErr> {
+    // Only the default bucket is replicated.
+    Entity::find()
+        .filter(Column::ReplicationStatus.eq(status))
+        .filter(Column::TenantId.is_null())
+        .all(db)
+        .await
+}
+
+pub async fn find_by_id(db: &DatabaseConnection, id: i32) -> Result<Option<Model>, DbErr> {
+    Entity::find_by_id(id).one(db).await
+}
+
+/// `find_by_id`, as long as the file is in the bucket of `tenant_id`, or in
+/// the default one.
+pub async fn find_by_id_for_tenant(
+    db: &DatabaseConnection,
+    id: i32,
+    tenant_id: Option<&str>,
+) -> Result<Option<Model>, DbErr> {
+    Entity::find_by_id(id)
+        .filter(tenant_condition(tenant_id))
+        .one(db)
+        .await
+}
+
+/// Detaches a file from its shared blob once version 1 no longer exists.
+pub async fn clear_blob_hash(db: &DatabaseConnection, id: i32) -> Result<(), DbErr> {
+    Entity::update_many()
+        .col_expr(Column::BlobHash, Expr::value(Option::<String>::None))
+        .filter(Column::Id.eq(id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
 pub async fn find_by_name(db: &DatabaseConnection, name: &str) -> Result<Option<Model>, DbErr> {
     Entity::find().filter(Column::Name.eq(name)).one(db).await
 }
 
-pub async fn delete_by_name(db: &DatabaseConnection, name: &str) -> Result<(), DbErr> {
+/// `find_by_name` among the files of one tenant, or of the default bucket.
+/// Names are only unique within a bucket, so anything serving a request
+/// looks files up with this.
+pub async fn find_by_name_for_tenant(
+    db: &DatabaseConnection,
+    name: &str,
+    tenant_id: Option<&str>,
+) -> Result<Option<Model>, DbErr> {
+    Entity::find()
+        .filter(Column::Name.eq(name))
+        .filter(tenant_condition(tenant_id))
+        .one(db)
+        .await
+}
+
+pub async fn rename<C: ConnectionTrait>(db: &C, id: i32, name: &str) -> Result<(), DbErr> {
+    Entity::update_many()
+        .col_expr(Column::Name, Expr::value(name))
+        .filter(Column::Id.eq(id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_by_name<C: ConnectionTrait>(
+    db: &C,
+    name: &str,
+    tenant_id: Option<&str>,
+) -> Result<(), DbErr> {
     use sea_orm::EntityTrait;
 
-    let file = Entity::find().filter(Column::Name.eq(name)).one(db).await?;
+    let file = Entity::find()
+        .filter(Column::Name.eq(name))
+        .filter(tenant_condition(tenant_id))
+        .one(db)
+        .await?;
     if let Some(f) = file {
         Entity::delete_by_id(f.id).exec(db).await?;
     }
     Ok(())
 }
 
+fn tenant_condition(tenant_id: Option<&str>) -> sea_orm::Condition {
+    let column = Expr::col((Entity, Column::TenantId));
+    sea_orm::Condition::all().add(match tenant_id {
+        Some(tenant_id) => column.eq(tenant_id),
+        None => column.is_null(),
+    })
+}
+
+/// Narrows `find_all_with_authors`.
+#[derive(Debug, Default)]
+pub struct ListFilter {
+    /// Only files whose metadata contains this document (JSONB `@>`).
+    pub metadata: Option<serde_json::Value>,
+    /// Only files carrying every one of these tags. Must not repeat a tag.
+    pub tags: Vec<String>,
+    /// Only the tenant's files, or those of the default bucket when unset.
+    pub tenant_id: Option<String>,
+    /// Only files whose name starts with this.
+    pub name_prefix: Option<String>,
+    /// Also files the virus scan found infected.
+    pub include_quarantined: bool,
+    /// Only files after this id, for the next page.
+    pub after_id: Option<i32>,
+    pub limit: Option<u64>,
+}
+
+/// Leaves out infected files unless `include` is set.
+fn quarantine_condition(include: bool) -> sea_orm::Condition {
+    if include {
+        return sea_orm::Condition::all();
+    }
+    sea_orm::Condition::any()
+        .add(Column::ScanStatus.is_null())
+        .add(Column::ScanStatus.ne(ScanStatus::Infected.as_str()))
+}
+
+/// `value` matched literally by `LIKE`. Backslash is Postgres' default escape.
+fn like_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
 pub async fn find_all_with_authors(
     db: &DatabaseConnection,
+    filter: ListFilter,
 ) -> Result<Vec<(Model, Option<super::user::Model>)>, DbErr> {
+    let tags = (!filter.tags.is_empty()).then_some(filter.tags);
+
     Entity::find()
         .find_also_related(super::user::Entity)
+        .filter(Column::Orphaned.eq(false))
+        .filter(tenant_condition(filter.tenant_id.as_deref()))
+        .filter(quarantine_condition(filter.include_quarantined))
+        .apply_if(filter.metadata, |query, metadata| {
+            query.filter(Expr::col((Entity, Column::Metadata)).contains(metadata))
+        })
+        .apply_if(tags, |query, tags| {
+            let wanted = tags.len() as i64;
+            query.filter(
+                Column::Id.in_subquery(
+                    Query::select()
+                        .column(file_tag::Column::FileId)
+                        .from(file_tag::Entity)
+                        .and_where(file_tag::Column::Tag.is_in(tags))
+                        .group_by_col(file_tag::Column::FileId)
+                        .and_having(Expr::col(file_tag::Column::Id).count().eq(wanted))
+                        .to_owned(),
+                ),
+            )
+        })
+        .apply_if(filter.name_prefix, |q, prefix| {
+            q.filter(Expr::col((Entity, Column::Name)).like(format!("{}%", like_escape(&prefix))))
+        })
+        .apply_if(filter.after_id, |q, id| q.filter(Column::Id.gt(id)))
+        .order_by_asc(Column::Id)
+        .apply_if(filter.limit, |q, limit| q.limit(limit))
         .all(db)
         .await
 }
 
+/// Every file named under `prefix`, orphaned ones included, in name order.
+pub async fn find_by_name_prefix(
+    db: &DatabaseConnection,
+    prefix: &str,
+    tenant_id: Option<&str>,
+) -> Result<Vec<Model>, DbErr> {
+    Entity::find()
+        .filter(tenant_condition(tenant_id))
+        .filter(Expr::col((Entity, Column::Name)).like(format!("{}%", like_escape(prefix))))
+        .order_by_asc(Column::Name)
+        .all(db)
+        .await
+}
+
+/// Visible files whose name contains `query`, ignoring case, after `after_id` in id order.
+pub async fn search_by_name(
+    db: &DatabaseConnection,
+    query: &str,
+    after_id: Option<i32>,
+    limit: u64,
+    tenant_id: Option<&str>,
+) -> Result<Vec<(Model, Option<super::user::Model>)>, DbErr> {
+    let escaped = like_escape(query);
+
+    Entity::find()
+        .find_also_related(super::user::Entity)
+        .filter(Column::Orphaned.eq(false))
+        .filter(tenant_condition(tenant_id))
+        .filter(Expr::col((Entity, Column::Name)).ilike(format!("%{escaped}%")))
+        .apply_if(after_id, |q, id| q.filter(Column::Id.gt(id)))
+        .order_by_asc(Column::Id)
+        .limit(limit)
+        .all(db)
+        .await
+}
+
+pub async fn update_metadata(
+    db: &DatabaseConnection,
+    id: i32,
+    metadata: serde_json::Value,
+) -> Result<Model, DbErr> {
+    Entity::update_many()
+        .col_expr(Column::Metadata, Expr::value(metadata))
+        .filter(Column::Id.eq(id))
+        .exec(db)
+        .await?;
+
+    Entity::find_by_id(id)
+        .one(db)
+        .await?
+        .ok_or(DbErr::RecordNotFound(format!("File {} not found", id)))
+}
+
+/// Returns the number of indexed files and their combined size.
+pub async fn count_and_total_size(db: &DatabaseConnection) -> Result<(i64, i64), DbErr> {
+    let totals: Option<(i64, Option<i64>)> = Entity::find()
+        .select_only()
+        .column_as(Column::Id.count(), "count")
+        // SUM(bigint) is NUMERIC in Postgres.
+        .column_as(
+            Expr::expr(Column::Size.sum()).cast_as(Alias::new("BIGINT")),
+            "total_size",
+        )
+        .filter(Column::Orphaned.eq(false))
+        .into_tuple()
+        .one(db)
+        .await?;
+
+    Ok(totals
+        .map(|(count, total)| (count, total.unwrap_or(0)))
+        .unwrap_or((0, 0)))
+}
+
+pub async fn find_largest(db: &DatabaseConnection) -> Result<Option<Model>, DbErr> {
+    Entity::find()
+        .filter(Column::Orphaned.eq(false))
+        .order_by_desc(Column::Size)
+        .one(db)
+        .await
+}
+
+pub async fn find_newest(db: &DatabaseConnection) -> Result<Option<Model>, DbErr> {
+    Entity::find()
+        .filter(Column::Orphaned.eq(false))
+        .order_by_desc(Column::CreatedAt)
+        .one(db)
+        .await
+}
+
+/// Oldest visible file whose current content is the version 1 upload with this hash.
+pub async fn find_by_content_hash_with_author(
+    db: &DatabaseConnection,
+    content_hash: &str,
+    tenant_id: Option<&str>,
+) -> Result<Option<(Model, Option<super::user::Model>)>, DbErr> {
+    Entity::find()
+        .find_also_related(super::user::Entity)
+        .filter(Column::ContentHash.eq(content_hash))
+        .filter(tenant_condition(tenant_id))
+        .filter(Column::Version.eq(1))
+        .filter(Column::Orphaned.eq(false))
+        .order_by_asc(Column::Id)
+        .one(db)
+        .await
+}
+
 pub async fn find_with_author(
     db: &DatabaseConnection,
     id: i32,
@@ -153,6 +639,7 @@ pub async fn sync_with_version_check(
     active_model.size = Set(size);
     active_model.updated_at = Set(now);
     active_model.version = Set(new_version);
+    active_model.orphaned = Set(false);
     let updated = active_model.update(db).await?;
 
     super::file_version::create(db, file_id, new_version, size, author_id).await?;
@@ -187,10 +674,22 @@ pub async fn sync_by_name_and_author(
         id: NotSet,
         name: Set(name.to_string()),
         size: Set(size),
-        author_id: Set(author_id),
+        author_id: Set(Some(author_id)),
         created_at: Set(now),
         updated_at: Set(now),
         version: Set(1),
+        orphaned: Set(false),
+        metadata: Set(None),
+        blob_hash: Set(None),
+        content_hash: Set(None),
+        compressed: Set(false),
+        scan_status: Set(None),
+        scan_signature: Set(None),
+        tenant_id: Set(None),
+        object_shard: Set(None),
+        checksums: Set(None),
+        replication_status: Set(None),
+        replication_error: Set(None),
     })
     .exec(db)
     .await?;
@@ -202,35 +701,3 @@ pub async fn sync_by_name_and_author(
         .await?
         .ok_or(DbErr::RecordNotFound("File not found".to_string()))
 }
-
-pub async fn revert_to_version(
-    db: &DatabaseConnection,
-    file_id: i32,
-    target_version: i32,
-    _author_id: i32,
-) -> Result<Model, DbErr> {
-    let txn = db.begin().await?;
-
-    let target_version_record = file_version::Entity::find()
-        .filter(file_version::Column::FileId.eq(file_id))
-        .filter(file_version::Column::Version.eq(target_version))
-        .one(&txn)
-        .await?
-        .ok_or_else(|| DbErr::Custom(format!("Version {} not found", target_version)))?;
-
-    file_version::delete_versions_newer_than(&txn, file_id, target_version).await?;
-
-    let current_file = Entity::find_by_id(file_id)
-        .one(&txn)
-        .await?
-        .ok_or(DbErr::RecordNotFound(format!("File {} not found", file_id)))?;
-
-    let mut active_file: ActiveModel = current_file.into();
-    active_file.version = Set(target_version);
-    active_file.size = Set(target_version_record.size);
-    active_file.updated_at = Set(Utc::now().naive_utc());
-    let updated_file = active_file.update(&txn).await?;
-
-    txn.commit().await?;
-    Ok(updated_file)
-}
@@ -0,0 +1,181 @@
+use chrono::Utc;
+use loco_rs::prelude::*;
+use sea_orm::{
+    ActiveValue::NotSet, ConnectionTrait, QueryOrder, QuerySelect, QueryTrait, entity::prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+/// Who touched which file, how, and whether it worked.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "file_audit_log")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    /// `UPLOAD`, `DOWNLOAD`, `LINK_ACCESS`, `DELETE`, `COPY` or `MOVE`.
+    pub operation: String,
+    /// Unset when a request failed before naming a file.
+    #[sea_orm(column_type = "Text", nullable)]
+    pub file_key: Option<String>,
+    pub user_id: Option<i32>,
+    /// Names the bearer token or signed link the request carried.
+    #[sea_orm(column_type = "Text", nullable)]
+    pub token_id: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub ip_address: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub user_agent: Option<String>,
+    /// Sent or received; unset where nothing was transferred.
+    pub bytes: Option<i64>,
+    /// The HTTP status the request was answered with, where it is known.
+    pub status_code: Option<i32>,
+    /// `SUCCESS` or `FAILURE`.
+    pub outcome: String,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub error_message: Option<String>,
+    #[sea_orm(column_type = "Timestamp")]
+    pub created_at: sea_orm::prelude::DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Upload,
+    Download,
+    /// A download through a signed link rather than with a token.
+    LinkAccess,
+    Delete,
+    Copy,
+    Move,
+}
+
+impl Operation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Upload => "UPLOAD",
+            Self::Download => "DOWNLOAD",
+            Self::LinkAccess => "LINK_ACCESS",
+            Self::Delete => "DELETE",
+            Self::Copy => "COPY",
+            Self::Move => "MOVE",
+        }
+    }
+}
+
+pub const SUCCESS: &str = "SUCCESS";
+pub const FAILURE: &str = "FAILURE";
+
+/// The caller an entry is attributed to.
+#[derive(Debug, Clone, Default)]
+pub struct Actor {
+    pub user_id: Option<i32>,
+    pub token_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// An entry yet to be written; it is a failure exactly when `error` is set.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub operation: Operation,
+    pub file_key: Option<String>,
+    pub actor: Actor,
+    pub bytes: Option<i64>,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    /// When it happened, which can be well before it is written.
+    pub at: sea_orm::prelude::DateTime,
+}
+
+impl Entry {
+    pub fn new(operation: Operation, file_key: Option<&str>, actor: &Actor) -> Self {
+        Self {
+            operation,
+            file_key: file_key.map(String::from),
+            actor: actor.clone(),
+            bytes: None,
+            status_code: None,
+            error: None,
+            at: Utc::now().naive_utc(),
+        }
+    }
+
+    pub fn failed(mut self, error: impl Into<String>) -> Self {
+        self.error = Some(error.into());
+        self
+    }
+}
+
+/// Writes `entries` in one statement, in order.
+pub async fn insert_all<C: ConnectionTrait>(db: &C, entries: Vec<Entry>) -> Result<(), DbErr> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let rows = entries.into_iter().map(|entry| ActiveModel {
+        id: NotSet,
+        operation: Set(entry.operation.as_str().to_string()),
+        file_key: Set(entry.file_key),
+        user_id: Set(entry.actor.user_id),
+        token_id: Set(entry.actor.token_id),
+        ip_address: Set(entry.actor.ip_address),
+        user_agent: Set(entry.actor.user_agent),
+        bytes: Set(entry.bytes),
+        status_code: Set(entry.status_code.map(i32::from)),
+        outcome: Set(if entry.error.is_some() {
+            FAILURE
+        } else {
+            SUCCESS
+        }
+        .to_string()),
+        error_message: Set(entry.error),
+        created_at: Set(entry.at),
+    });
+    Entity::insert_many(rows).exec(db).await?;
+    Ok(())
+}
+
+/// What `page` narrows entries down to; unset fields don't narrow.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub file_key: Option<String>,
+    pub user_id: Option<i32>,
+    pub from: Option<sea_orm::prelude::DateTime>,
+    /// Exclusive.
+    pub to: Option<sea_orm::prelude::DateTime>,
+}
+
+/// Entries older than `before_id`, newest first.
+pub async fn page(
+    db: &DatabaseConnection,
+    before_id: Option<i64>,
+    filter: &Filter,
+    limit: u64,
+) -> Result<Vec<Model>, DbErr> {
+    Entity::find()
+        .apply_if(before_id, |q, id| q.filter(Column::Id.lt(id)))
+        .apply_if(filter.file_key.as_deref(), |q, key| {
+            q.filter(Column::FileKey.eq(key))
+        })
+        .apply_if(filter.user_id, |q, id| q.filter(Column::UserId.eq(id)))
+        .apply_if(filter.from, |q, from| q.filter(Column::CreatedAt.gte(from)))
+        .apply_if(filter.to, |q, to| q.filter(Column::CreatedAt.lt(to)))
+        .order_by_desc(Column::Id)
+        .limit(limit)
+        .all(db)
+        .await
+}
+
+/// Removes the entries from before `cutoff`, returning how many there were.
+pub async fn delete_older_than(
+    db: &DatabaseConnection,
+    cutoff: sea_orm::prelude::DateTime,
+) -> Result<u64, DbErr> {
+    let deleted = Entity::delete_many()
+        .filter(Column::CreatedAt.lt(cutoff))
+        .exec(db)
+        .await?;
+    Ok(deleted.rows_affected)
+}
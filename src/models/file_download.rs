@@ -0,0 +1,55 @@
+use chrono::Utc;
+use loco_rs::prelude::*;
+use sea_orm::{ActiveValue::NotSet, QuerySelect, entity::prelude::*};
+use serde::{Deserialize, Serialize};
+
+/// One `GET /files/{file_name}`, kept for usage analytics.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "file_downloads")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    #[sea_orm(column_type = "Text")]
+    pub file_key: String,
+    #[sea_orm(column_type = "Timestamp")]
+    pub downloaded_at: sea_orm::prelude::DateTime,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub client_ip: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+pub async fn record(
+    db: &DatabaseConnection,
+    file_key: &str,
+    client_ip: Option<String>,
+) -> Result<(), DbErr> {
+    ActiveModel {
+        id: NotSet,
+        file_key: Set(file_key.to_string()),
+        downloaded_at: Set(Utc::now().naive_utc()),
+        client_ip: Set(client_ip),
+    }
+    .insert(db)
+    .await?;
+    Ok(())
+}
+
+/// How often `file_key` was downloaded, and when last.
+pub async fn stats(
+    db: &DatabaseConnection,
+    file_key: &str,
+) -> Result<(i64, Option<sea_orm::prelude::DateTime>), DbErr> {
+    let row: Option<(i64, Option<sea_orm::prelude::DateTime>)> = Entity::find()
+        .select_only()
+        .column_as(Column::Id.count(), "count")
+        .column_as(Column::DownloadedAt.max(), "last")
+        .filter(Column::FileKey.eq(file_key))
+        .into_tuple()
+        .one(db)
+        .await?;
+    Ok(row.unwrap_or((0, None)))
+}
@@ -0,0 +1,118 @@
+use chrono::{SubsecRound, Utc};
+use loco_rs::prelude::*;
+use sea_orm::{entity::prelude::*, sea_query::OnConflict};
+use serde::{Deserialize, Serialize};
+
+/// A lock a user takes on a file while editing it. Until it is released or
+/// goes stale, uploads and deletes of the file by anyone else are refused.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "file_edit_locks")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false, column_type = "Text")]
+    pub key: String,
+    pub holder_id: i32,
+    #[sea_orm(column_type = "Timestamp")]
+    pub acquired_at: sea_orm::prelude::DateTime,
+    /// When the holder last took or refreshed it; the TTL runs from here.
+    #[sea_orm(column_type = "Timestamp")]
+    pub refreshed_at: sea_orm::prelude::DateTime,
+    pub ttl_seconds: i32,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl Model {
+    pub fn expires_at(&self) -> sea_orm::prelude::DateTime {
+        self.refreshed_at + chrono::Duration::seconds(i64::from(self.ttl_seconds))
+    }
+
+    fn is_stale(&self) -> bool {
+        self.expires_at() < Utc::now().naive_utc()
+    }
+}
+
+/// Takes the lock on `key` for `holder_id`, or a stale one whoever left it.
+/// `None` while a live lock is held, by `holder_id` too.
+pub async fn acquire(
+    db: &DatabaseConnection,
+    key: &str,
+    holder_id: i32,
+    ttl_seconds: i32,
+) -> Result<Option<Model>, DbErr> {
+    // Rounded to what the column keeps, so the row reads back the same.
+    let now = Utc::now().naive_utc().trunc_subsecs(6);
+    let lock = Model {
+        key: key.to_string(),
+        holder_id,
+        acquired_at: now,
+        refreshed_at: now,
+        ttl_seconds,
+    };
+    let inserted = Entity::insert(ActiveModel::from(lock.clone()))
+        .on_conflict(
+            OnConflict::column(Column::Key)
+                .update_columns([
+                    Column::HolderId,
+                    Column::AcquiredAt,
+                    Column::RefreshedAt,
+                    Column::TtlSeconds,
+                ])
+                .action_and_where(Expr::cust(
+                    "file_edit_locks.refreshed_at + make_interval(secs => file_edit_locks.ttl_seconds) < excluded.refreshed_at",
+                ))
+                .to_owned(),
+        )
+        .exec_without_returning(db)
+        .await?;
+    Ok((inserted == 1).then_some(lock))
+}
+
+/// Restarts the TTL of `holder_id`'s live lock on `key`, now `ttl_seconds`.
+/// `None` when they hold no live lock on it.
+pub async fn refresh(
+    db: &DatabaseConnection,
+    key: &str,
+    holder_id: i32,
+    ttl_seconds: i32,
+) -> Result<Option<Model>, DbErr> {
+    let now = Utc::now().naive_utc().trunc_subsecs(6);
+    let updated = Entity::update_many()
+        .col_expr(Column::RefreshedAt, Expr::value(now))
+        .col_expr(Column::TtlSeconds, Expr::value(ttl_seconds))
+        .filter(Column::Key.eq(key))
+        .filter(Column::HolderId.eq(holder_id))
+        .filter(Expr::cust_with_values(
+            "file_edit_locks.refreshed_at + make_interval(secs => file_edit_locks.ttl_seconds) >= $1",
+            [now],
+        ))
+        .exec(db)
+        .await?;
+    if updated.rows_affected == 0 {
+        return Ok(None);
+    }
+    Entity::find_by_id(key).one(db).await
+}
+
+/// The lock on `key`, unless it has gone stale.
+pub async fn find_active(db: &DatabaseConnection, key: &str) -> Result<Option<Model>, DbErr> {
+    let lock = Entity::find_by_id(key).one(db).await?;
+    Ok(lock.filter(|l| !l.is_stale()))
+}
+
+/// Drops the lock on `key` if `holder_id` holds it, or whoever does when
+/// `None`. Whether there was one to drop.
+pub async fn release(
+    db: &DatabaseConnection,
+    key: &str,
+    holder_id: Option<i32>,
+) -> Result<bool, DbErr> {
+    let mut delete = Entity::delete_many().filter(Column::Key.eq(key));
+    if let Some(id) = holder_id {
+        delete = delete.filter(Column::HolderId.eq(id));
+    }
+    let deleted = delete.exec(db).await?;
+    Ok(deleted.rows_affected > 0)
+}
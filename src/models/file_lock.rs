@@ -0,0 +1,67 @@
+use chrono::{SubsecRound, Utc};
+use loco_rs::prelude::*;
+use sea_orm::{entity::prelude::*, sea_query::OnConflict};
+use serde::{Deserialize, Serialize};
+
+/// An advisory lock on a file name, held while an upload writes it.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "file_locks")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false, column_type = "Text")]
+    pub key: String,
+    #[sea_orm(column_type = "Timestamp")]
+    pub locked_at: sea_orm::prelude::DateTime,
+    /// After this long the holder is presumed dead and the lock can be taken over.
+    pub ttl_seconds: i32,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Takes the lock on `key`, or a stale one left by a holder that never
+/// released it. Returns when it was taken, which `release` needs, or `None`
+/// while someone else holds it.
+pub async fn acquire(
+    db: &DatabaseConnection,
+    key: &str,
+    ttl_seconds: i32,
+) -> Result<Option<sea_orm::prelude::DateTime>, DbErr> {
+    // Rounded to what the column keeps, so `release` can match it.
+    let now = Utc::now().naive_utc().trunc_subsecs(6);
+    let inserted = Entity::insert(ActiveModel {
+        key: Set(key.to_string()),
+        locked_at: Set(now),
+        ttl_seconds: Set(ttl_seconds),
+    })
+    .on_conflict(
+        OnConflict::column(Column::Key)
+            .update_columns([Column::LockedAt, Column::TtlSeconds])
+            .action_and_where(Expr::cust(
+                "file_locks.locked_at + make_interval(secs => file_locks.ttl_seconds) < excluded.locked_at",
+            ))
+            .to_owned(),
+    )
+    .exec_without_returning(db)
+    .await?;
+    Ok((inserted == 1).then_some(now))
+}
+
+/// Drops the lock taken at `locked_at`, unless it went stale and was taken over since.
+pub async fn release(
+    db: &DatabaseConnection,
+    key: &str,
+    locked_at: sea_orm::prelude::DateTime,
+) -> Result<(), DbErr> {
+    Entity::delete_many()
+        .filter(Column::Key.eq(key))
+        .filter(Column::LockedAt.eq(locked_at))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+pub async fn find(db: &DatabaseConnection, key: &str) -> Result<Option<Model>, DbErr> {
+    Entity::find_by_id(key).one(db).await
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use loco_rs::prelude::*;
+use sea_orm::{
+    ActiveValue::NotSet, QueryOrder, QuerySelect, entity::prelude::*, sea_query::OnConflict,
+};
+use serde::{Deserialize, Serialize};
+
+/// Longest tag accepted after normalization.
+pub const MAX_TAG_LEN: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "file_tags")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub file_id: i32,
+    pub tag: String,
+    #[sea_orm(column_type = "Timestamp")]
+    pub created_at: sea_orm::prelude::DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::file::Entity",
+        from = "Column::FileId",
+        to = "super::file::Column::Id"
+    )]
+    File,
+}
+
+impl Related<super::file::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::File.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Trims and lowercases `tag`, returning `None` if it is empty or too long.
+pub fn normalize(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+    (!tag.is_empty() && tag.chars().count() <= MAX_TAG_LEN).then_some(tag)
+}
+
+/// Adds already normalized tags to a file, ignoring ones it already has.
+pub async fn add(db: &DatabaseConnection, file_id: i32, tags: &[String]) -> Result<(), DbErr> {
+    if tags.is_empty() {
+        return Ok(());
+    }
+
+    let now = Utc::now().naive_utc();
+    let rows = tags.iter().map(|tag| ActiveModel {
+        id: NotSet,
+        file_id: Set(file_id),
+        tag: Set(tag.clone()),
+        created_at: Set(now),
+    });
+
+    Entity::insert_many(rows)
+        .on_conflict(
+            OnConflict::columns([Column::FileId, Column::Tag])
+                .do_nothing()
+                .to_owned(),
+        )
+        .do_nothing()
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Returns whether the file had the tag.
+pub async fn remove(db: &DatabaseConnection, file_id: i32, tag: &str) -> Result<bool, DbErr> {
+    let res = Entity::delete_many()
+        .filter(Column::FileId.eq(file_id))
+        .filter(Column::Tag.eq(tag))
+        .exec(db)
+        .await?;
+    Ok(res.rows_affected > 0)
+}
+
+pub async fn find_by_file_id(db: &DatabaseConnection, file_id: i32) -> Result<Vec<String>, DbErr> {
+    Entity::find()
+        .select_only()
+        .column(Column::Tag)
+        .filter(Column::FileId.eq(file_id))
+        .order_by_asc(Column::Tag)
+        .into_tuple()
+        .all(db)
+        .await
+}
+
+/// Tags of every given file, sorted per file.
+pub async fn find_by_file_ids(
+    db: &DatabaseConnection,
+    file_ids: &[i32],
+) -> Result<HashMap<i32, Vec<String>>, DbErr> {
+    let rows: Vec<(i32, String)> = Entity::find()
+        .select_only()
+        .column(Column::FileId)
+        .column(Column::Tag)
+        .filter(Column::FileId.is_in(file_ids.iter().copied()))
+        .order_by_asc(Column::Tag)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let mut tags: HashMap<i32, Vec<String>> = HashMap::new();
+    for (file_id, tag) in rows {
+        tags.entry(file_id).or_default().push(tag);
+    }
+    Ok(tags)
+}
+
+/// Every tag in use with the number of files carrying it, most used first.
+pub async fn counts(db: &DatabaseConnection) -> Result<Vec<(String, i64)>, DbErr> {
+    Entity::find()
+        .select_only()
+        .column(Column::Tag)
+        .column_as(Column::Id.count(), "count")
+        .group_by(Column::Tag)
+        .order_by_desc(Expr::col(sea_orm::sea_query::Alias::new("count")))
+        .order_by_asc(Column::Tag)
+        .into_tuple()
+        .all(db)
+        .await
+}
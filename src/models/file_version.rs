@@ -95,6 +95,13 @@ pub async fn find_all_by_file_id(
         .await
 }
 
+pub async fn count_by_file_id(db: &DatabaseConnection, file_id: i32) -> Result<u64, DbErr> {
+    Entity::find()
+        .filter(Column::FileId.eq(file_id))
+        .count(db)
+        .await
+}
+
 pub async fn exists_by_file_id_and_version(
     db: &DatabaseConnection,
     file_id: i32,
@@ -108,19 +115,42 @@ pub async fn exists_by_file_id_and_version(
     Ok(count > 0)
 }
 
-pub async fn delete_versions_newer_than(
-    db: &impl sea_orm::ConnectionTrait,
+/// Drops the versions after `after` and before `before`.
+pub async fn delete_versions_between(
+    db: &DatabaseConnection,
     file_id: i32,
-    version: i32,
+    after: i32,
+    before: i32,
 ) -> Result<u64, DbErr> {
     Entity::delete_many()
         .filter(Column::FileId.eq(file_id))
-        .filter(Column::Version.gt(version))
+        .filter(Column::Version.gt(after))
+        .filter(Column::Version.lt(before))
         .exec(db)
         .await
         .map(|res| res.rows_affected)
 }
 
+/// Versions beyond the newest `keep`, oldest last.
+pub async fn find_beyond_newest(
+    db: &DatabaseConnection,
+    file_id: i32,
+    keep: u64,
+) -> Result<Vec<Model>, DbErr> {
+    use sea_orm::QuerySelect;
+    Entity::find()
+        .filter(Column::FileId.eq(file_id))
+        .order_by_desc(Column::Version)
+        .offset(keep)
+        .all(db)
+        .await
+}
+
+pub async fn delete_by_id(db: &DatabaseConnection, id: i32) -> Result<(), DbErr> {
+    Entity::delete_by_id(id).exec(db).await?;
+    Ok(())
+}
+
 pub async fn get_max_version(db: &DatabaseConnection, file_id: i32) -> Result<Option<i32>, DbErr> {
     use sea_orm::QuerySelect;
     let result = Entity::find()
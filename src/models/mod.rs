@@ -1,4 +1,14 @@
+pub mod blob;
+pub mod chunked_upload;
+pub mod chunked_upload_part;
 pub mod file;
+pub mod file_audit_log;
+pub mod file_download;
+pub mod file_edit_lock;
+pub mod file_lock;
+pub mod file_tag;
 pub mod file_version;
 pub mod role;
+pub mod tus_upload;
+pub mod upload_idempotency_key;
 pub mod user;
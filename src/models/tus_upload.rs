@@ -0,0 +1,133 @@
+use chrono::Utc;
+use loco_rs::prelude::*;
+use sea_orm::{QuerySelect, entity::prelude::*};
+use serde::{Deserialize, Serialize};
+
+/// A resumable upload in progress. Bytes received so far live in a
+/// multipart upload (`multipart_id`), plus a tail shorter than one part.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "tus_uploads")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: String,
+    pub file_name: String,
+    pub upload_length: i64,
+    pub upload_offset: i64,
+    pub author_id: i32,
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub metadata: Option<serde_json::Value>,
+    pub multipart_id: String,
+    /// Content ids of the uploaded parts, in order.
+    #[sea_orm(column_type = "JsonBinary")]
+    pub parts: serde_json::Value,
+    /// Bytes buffered in the tail object, waiting to fill a part.
+    pub tail_size: i64,
+    #[sea_orm(column_type = "Timestamp")]
+    pub created_at: sea_orm::prelude::DateTime,
+    #[sea_orm(column_type = "Timestamp")]
+    pub updated_at: sea_orm::prelude::DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::AuthorId",
+        to = "super::user::Column::Id"
+    )]
+    Author,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Author.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl Model {
+    pub fn part_ids(&self) -> Vec<String> {
+        serde_json::from_value(self.parts.clone()).unwrap_or_default()
+    }
+}
+
+pub struct NewUpload<'a> {
+    pub id: &'a str,
+    pub file_name: &'a str,
+    pub upload_length: i64,
+    pub author_id: i32,
+    pub metadata: Option<serde_json::Value>,
+    pub multipart_id: &'a str,
+}
+
+pub async fn create(db: &DatabaseConnection, new: NewUpload<'_>) -> Result<Model, DbErr> {
+    let now = Utc::now().naive_utc();
+    ActiveModel {
+        id: Set(new.id.to_string()),
+        file_name: Set(new.file_name.to_string()),
+        upload_length: Set(new.upload_length),
+        upload_offset: Set(0),
+        author_id: Set(new.author_id),
+        metadata: Set(new.metadata),
+        multipart_id: Set(new.multipart_id.to_string()),
+        parts: Set(serde_json::json!([])),
+        tail_size: Set(0),
+        created_at: Set(now),
+        updated_at: Set(now),
+    }
+    .insert(db)
+    .await
+}
+
+/// The multipart uploads every row is assembling in.
+pub async fn multipart_ids(db: &DatabaseConnection) -> Result<Vec<String>, DbErr> {
+    Entity::find()
+        .select_only()
+        .column(Column::MultipartId)
+        .into_tuple()
+        .all(db)
+        .await
+}
+
+pub async fn find(db: &DatabaseConnection, id: &str) -> Result<Option<Model>, DbErr> {
+    Entity::find_by_id(id).one(db).await
+}
+
+/// Records progress, but only if nobody else moved the upload past
+/// `expected_offset` in the meantime. Returns whether the row was updated.
+pub async fn advance(
+    db: &DatabaseConnection,
+    id: &str,
+    expected_offset: i64,
+    upload_offset: i64,
+    parts: &[String],
+    tail_size: i64,
+) -> Result<bool, DbErr> {
+    let res = Entity::update_many()
+        .col_expr(Column::UploadOffset, Expr::value(upload_offset))
+        .col_expr(Column::Parts, Expr::value(serde_json::json!(parts)))
+        .col_expr(Column::TailSize, Expr::value(tail_size))
+        .col_expr(Column::UpdatedAt, Expr::value(Utc::now().naive_utc()))
+        .filter(Column::Id.eq(id))
+        .filter(Column::UploadOffset.eq(expected_offset))
+        .exec(db)
+        .await?;
+    Ok(res.rows_affected == 1)
+}
+
+/// Uploads with no progress since `before`.
+pub async fn find_stale(
+    db: &DatabaseConnection,
+    before: sea_orm::prelude::DateTime,
+) -> Result<Vec<Model>, DbErr> {
+    Entity::find()
+        .filter(Column::UpdatedAt.lt(before))
+        .all(db)
+        .await
+}
+
+pub async fn delete(db: &DatabaseConnection, id: &str) -> Result<(), DbErr> {
+    Entity::delete_by_id(id).exec(db).await?;
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use chrono::{Duration, Utc};
+use loco_rs::prelude::*;
+use sea_orm::{ActiveValue::NotSet, Condition, entity::prelude::*, sea_query::OnConflict};
+use serde::{Deserialize, Serialize};
+
+/// How long a completed upload can be replayed with the same key.
+pub const KEY_LIFETIME_HOURS: i64 = 24;
+
+/// How long a claim without a response holds the key unless it is renewed.
+/// The upload renews it while it runs, so one whose lease ran out is presumed
+/// dead (the process crashed or the request was dropped before it could
+/// release the key) and a retry may take the key over.
+pub const CLAIM_LEASE_MINUTES: i64 = 5;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Deserialize, Serialize)]
+#[sea_orm(table_name = "upload_idempotency_keys")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub tenant_id: Option<String>,
+    pub user_id: i32,
+    pub key: String,
+    /// Held by the request that claimed the key; only it may complete,
+    /// renew or release the claim.
+    pub claim_token: Option<String>,
+    pub request_hash: Option<String>,
+    pub response_status: Option<i32>,
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub response_body: Option<serde_json::Value>,
+    #[sea_orm(column_type = "Timestamp")]
+    pub created_at: sea_orm::prelude::DateTime,
+    #[sea_orm(column_type = "Timestamp")]
+    pub leased_at: sea_orm::prelude::DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+fn key_condition(tenant_id: Option<&str>, user_id: i32, key: &str) -> Condition {
+    let tenant = Expr::col((Entity, Column::TenantId));
+    Condition::all()
+        .add(match tenant_id {
+            Some(tenant_id) => tenant.eq(tenant_id),
+            None => tenant.is_null(),
+        })
+        .add(Column::UserId.eq(user_id))
+        .add(Column::Key.eq(key))
+}
+
+/// Atomically reserves `key` for `user_id` in the bucket of `tenant_id`.
+///
+/// Returns the claim's token, or `None` when an unexpired row already holds
+/// the key, whether that upload is still running or has completed. A claim
+/// that never got a response and whose lease ran out is taken over.
+pub async fn claim(
+    db: &DatabaseConnection,
+    tenant_id: Option<&str>,
+    user_id: i32,
+    key: &str,
+) -> Result<Option<String>, DbErr> {
+    let now = Utc::now().naive_utc();
+
+    Entity::delete_many()
+        .filter(key_condition(tenant_id, user_id, key))
+        .filter(
+            Condition::any()
+                .add(Column::CreatedAt.lt(now - Duration::hours(KEY_LIFETIME_HOURS)))
+                .add(
+                    Condition::all()
+                        .add(Column::ResponseStatus.is_null())
+                        .add(Column::LeasedAt.lt(now - Duration::minutes(CLAIM_LEASE_MINUTES))),
+                ),
+        )
+        .exec(db)
+        .await?;
+
+    let token = uuid::Uuid::new_v4().to_string();
+    let res = Entity::insert(ActiveModel {
+        id: NotSet,
+        tenant_id: Set(tenant_id.map(String::from)),
+        user_id: Set(user_id),
+        key: Set(key.to_string()),
+        claim_token: Set(Some(token.clone())),
+        request_hash: Set(None),
+        response_status: Set(None),
+        response_body: Set(None),
+        created_at: Set(now),
+        leased_at: Set(now),
+    })
+    .on_conflict(
+        OnConflict::columns([Column::TenantId, Column::UserId, Column::Key])
+            .do_nothing()
+            .to_owned(),
+    )
+    .exec(db)
+    .await;
+
+    match res {
+        Ok(_) => Ok(Some(token)),
+        Err(DbErr::RecordNotInserted) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub async fn find(
+    db: &DatabaseConnection,
+    tenant_id: Option<&str>,
+    user_id: i32,
+    key: &str,
+) -> Result<Option<Model>, DbErr> {
+    Entity::find()
+        .filter(key_condition(tenant_id, user_id, key))
+        .one(db)
+        .await
+}
+
+/// Extends the lease of the claim holding `claim_token`. Returns `false` once
+/// the claim is gone, completed or taken over.
+pub async fn renew(db: &DatabaseConnection, claim_token: &str) -> Result<bool, DbErr> {
+    let res = Entity::update_many()
+        .col_expr(Column::LeasedAt, Expr::value(Utc::now().naive_utc()))
+        .filter(Column::ClaimToken.eq(claim_token))
+        .filter(Column::ResponseStatus.is_null())
+        .exec(db)
+        .await?;
+    Ok(res.rows_affected == 1)
+}
+
+/// Records the response of the claim holding `claim_token`, unless it was
+/// taken over since.
+pub async fn complete(
+    db: &DatabaseConnection,
+    claim_token: &str,
+    request_hash: &str,
+    response_status: i32,
+    response_body: serde_json::Value,
+) -> Result<(), DbErr> {
+    Entity::update_many()
+        .col_expr(Column::RequestHash, Expr::value(request_hash))
+        .col_expr(Column::ResponseStatus, Expr::value(response_status))
+        .col_expr(Column::ResponseBody, Expr::value(response_body))
+        .filter(Column::ClaimToken.eq(claim_token))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Frees a key whose upload errored out so the client can retry with it,
+/// unless the claim holding `claim_token` was taken over since.
+pub async fn release(db: &DatabaseConnection, claim_token: &str) -> Result<(), DbErr> {
+    Entity::delete_many()
+        .filter(Column::ClaimToken.eq(claim_token))
+        .exec(db)
+        .await?;
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use loco_rs::prelude::*;
 use sea_orm::{ActiveValue::NotSet, entity::prelude::*};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 use crate::models::role;
 
@@ -13,6 +14,12 @@ pub struct Model {
     pub login: String,
     pub password: String,
     pub role_id: i32,
+    /// Total size of the user's files, kept current by a trigger on `files`.
+    pub storage_used_bytes: i64,
+    /// Overrides `default_quota_bytes` for this user.
+    pub storage_quota_bytes: Option<i64>,
+    /// The tenant whose bucket the user's requests go to; put in their tokens.
+    pub tenant_id: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -46,6 +53,9 @@ pub async fn create(
         login: Set(login.to_string()),
         password: Set(password.to_string()),
         role_id: Set(role_id),
+        storage_used_bytes: NotSet,
+        storage_quota_bytes: NotSet,
+        tenant_id: NotSet,
     })
     .exec(db)
     .await?;
@@ -90,3 +100,36 @@ pub async fn find_with_role(
         .one(db)
         .await
 }
+
+pub async fn set_storage_quota(
+    db: &DatabaseConnection,
+    id: i32,
+    quota_bytes: Option<i64>,
+) -> Result<(), DbErr> {
+    Entity::update_many()
+        .col_expr(Column::StorageQuotaBytes, Expr::value(quota_bytes))
+        .filter(Column::Id.eq(id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Replaces every user's recorded usage; users missing from `usage` have none.
+pub async fn reset_storage_used<C: ConnectionTrait>(
+    db: &C,
+    usage: &HashMap<i32, i64>,
+) -> Result<(), DbErr> {
+    Entity::update_many()
+        .col_expr(Column::StorageUsedBytes, Expr::value(0))
+        .filter(Column::Id.is_not_in(usage.keys().copied()))
+        .exec(db)
+        .await?;
+    for (id, bytes) in usage {
+        Entity::update_many()
+            .col_expr(Column::StorageUsedBytes, Expr::value(*bytes))
+            .filter(Column::Id.eq(*id))
+            .exec(db)
+            .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,303 @@
+//! Aborts multipart uploads S3 still keeps parts of long after they were
+//! started: uploads whose client crashed or gave up, and which are billed
+//! until someone aborts them. object_store can abort an upload but not
+//! list them, so the requests are signed and sent here.
+
+use chrono::{DateTime, Utc};
+use object_store::aws::{AwsAuthorizer, AwsCredential};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use quick_xml::{Reader, events::Event};
+use reqwest::{Client, Method, StatusCode};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+use url::Url;
+
+/// What S3 leaves unencoded in a signed path.
+const KEY_ENCODE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct SweepError(pub String);
+
+/// A multipart upload S3 reports as neither completed nor aborted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingUpload {
+    pub key: String,
+    pub upload_id: String,
+    pub initiated: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Sweep {
+    pub aborted: usize,
+    /// What the aborted uploads' parts held.
+    pub total_bytes_freed: u64,
+}
+
+/// One bucket, reached path-style like the store is.
+pub struct BucketClient {
+    client: Client,
+    endpoint: String,
+    bucket: String,
+    region: String,
+    credential: AwsCredential,
+}
+
+impl BucketClient {
+    pub fn new(
+        endpoint: &str,
+        bucket: &str,
+        region: &str,
+        access_key: &str,
+        secret_key: &str,
+        timeout: Duration,
+    ) -> Result<Self, SweepError> {
+        let client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| SweepError(format!("HTTP client error: {e}")))?;
+        Ok(Self {
+            client,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            bucket: bucket.to_string(),
+            region: region.to_string(),
+            credential: AwsCredential {
+                key_id: access_key.to_string(),
+                secret_key: secret_key.to_string(),
+                token: None,
+            },
+        })
+    }
+
+    fn url(&self, key: Option<&str>) -> Result<Url, SweepError> {
+        let mut url = format!("{}/{}", self.endpoint, self.bucket);
+        if let Some(key) = key {
+            for segment in key.split('/') {
+                url.push('/');
+                url.extend(utf8_percent_encode(segment, KEY_ENCODE));
+            }
+        }
+        Url::parse(&url).map_err(|e| SweepError(format!("Invalid endpoint: {e}")))
+    }
+
+    /// Sends a signed request with no body. `None` for a 404.
+    async fn send(&self, method: Method, url: Url) -> Result<Option<String>, SweepError> {
+        let mut request = self
+            .client
+            .request(method, url.clone())
+            .build()
+            .map_err(|e| SweepError(format!("Invalid request to {url}: {e}")))?;
+        AwsAuthorizer::new(&self.credential, "s3", &self.region).authorize(&mut request, None);
+        let response = self
+            .client
+            .execute(request)
+            .await
+            .map_err(|e| SweepError(format!("Request to {url} failed: {e}")))?;
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            return Err(SweepError(format!("{url} answered {status}")));
+        }
+        let body = response
+            .text()
+            .await
+            .map_err(|e| SweepError(format!("Reading {url} failed: {e}")))?;
+        Ok(Some(body))
+    }
+
+    /// Every pending upload in the bucket, page by page.
+    pub async fn list_uploads(&self) -> Result<Vec<PendingUpload>, SweepError> {
+        let mut uploads = Vec::new();
+        let mut markers: Option<(String, String)> = None;
+        loop {
+            let mut url = self.url(None)?;
+            {
+                let mut query = url.query_pairs_mut();
+                query.append_pair("uploads", "");
+                if let Some((key, upload_id)) = &markers {
+                    query.append_pair("key-marker", key);
+                    query.append_pair("upload-id-marker", upload_id);
+                }
+            }
+            let Some(body) = self.send(Method::GET, url).await? else {
+                return Err(SweepError(format!(
+                    "Bucket '{}' does not exist",
+                    self.bucket
+                )));
+            };
+            let page = parse(&body, b"Upload")?;
+            for mut upload in page.records {
+                let (Some(key), Some(upload_id), Some(initiated)) = (
+                    upload.remove("Key"),
+                    upload.remove("UploadId"),
+                    upload.remove("Initiated"),
+                ) else {
+                    return Err(SweepError("Upload listed without its key or ID".into()));
+                };
+                let initiated = DateTime::parse_from_rfc3339(&initiated)
+                    .map_err(|e| SweepError(format!("Invalid Initiated '{initiated}': {e}")))?
+                    .to_utc();
+                uploads.push(PendingUpload {
+                    key,
+                    upload_id,
+                    initiated,
+                });
+            }
+            if page.fields.get("IsTruncated").map(String::as_str) != Some("true") {
+                return Ok(uploads);
+            }
+            match (
+                page.fields.get("NextKeyMarker"),
+                page.fields.get("NextUploadIdMarker"),
+            ) {
+                (Some(key), Some(upload_id)) => markers = Some((key.clone(), upload_id.clone())),
+                _ => return Err(SweepError("Truncated listing without markers".into())),
+            }
+        }
+    }
+
+    /// Bytes held by the parts uploaded so far; zero once the upload is gone.
+    pub async fn part_bytes(&self, upload: &PendingUpload) -> Result<u64, SweepError> {
+        let mut total = 0;
+        let mut marker: Option<String> = None;
+        loop {
+            let mut url = self.url(Some(&upload.key))?;
+            {
+                let mut query = url.query_pairs_mut();
+                query.append_pair("uploadId", &upload.upload_id);
+                if let Some(marker) = &marker {
+                    query.append_pair("part-number-marker", marker);
+                }
+            }
+            let Some(body) = self.send(Method::GET, url).await? else {
+                return Ok(total);
+            };
+            let page = parse(&body, b"Part")?;
+            for part in &page.records {
+                total += part
+                    .get("Size")
+                    .and_then(|size| size.parse::<u64>().ok())
+                    .ok_or_else(|| SweepError("Part listed without its size".into()))?;
+            }
+            if page.fields.get("IsTruncated").map(String::as_str) != Some("true") {
+                return Ok(total);
+            }
+            marker = Some(
+                page.fields
+                    .get("NextPartNumberMarker")
+                    .cloned()
+                    .ok_or_else(|| SweepError("Truncated part listing without a marker".into()))?,
+            );
+        }
+    }
+
+    /// An upload someone else aborted or completed meanwhile counts as aborted.
+    pub async fn abort(&self, upload: &PendingUpload) -> Result<(), SweepError> {
+        let mut url = self.url(Some(&upload.key))?;
+        url.query_pairs_mut()
+            .append_pair("uploadId", &upload.upload_id);
+        self.send(Method::DELETE, url).await?;
+        Ok(())
+    }
+}
+
+/// Aborts every upload started before `cutoff` whose ID `keep` doesn't
+/// hold. An upload that can't be measured or aborted is logged and left
+/// for the next sweep.
+pub async fn abort_stale(
+    client: &BucketClient,
+    cutoff: DateTime<Utc>,
+    keep: &HashSet<String>,
+) -> Result<Sweep, SweepError> {
+    let mut sweep = Sweep::default();
+    for upload in client.list_uploads().await? {
+        if upload.initiated >= cutoff || keep.contains(&upload.upload_id) {
+            continue;
+        }
+        let freed = match client.part_bytes(&upload).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                tracing::warn!(key = %upload.key, error = %e, "could not measure orphaned upload");
+                0
+            }
+        };
+        match client.abort(&upload).await {
+            Ok(()) => {
+                tracing::info!(key = %upload.key, initiated = %upload.initiated, freed, "aborted orphaned upload");
+                sweep.aborted += 1;
+                sweep.total_bytes_freed += freed;
+            }
+            Err(e) => {
+                tracing::warn!(key = %upload.key, error = %e, "could not abort orphaned upload");
+            }
+        }
+    }
+    Ok(sweep)
+}
+
+/// The children of a listing's root, and of each of its `record` elements.
+struct Page {
+    fields: HashMap<String, String>,
+    records: Vec<HashMap<String, String>>,
+}
+
+fn parse(xml: &str, record: &[u8]) -> Result<Page, SweepError> {
+    let invalid = |e: &dyn std::fmt::Display| SweepError(format!("Invalid listing: {e}"));
+    let mut reader = Reader::from_str(xml);
+    let mut page = Page {
+        fields: HashMap::new(),
+        records: Vec::new(),
+    };
+    let mut path: Vec<Vec<u8>> = Vec::new();
+    let mut current = HashMap::new();
+    let mut text = String::new();
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                path.push(e.name().as_ref().to_vec());
+                text.clear();
+            }
+            Ok(Event::Text(t)) => text.push_str(&t.decode().map_err(|e| invalid(&e))?),
+            Ok(Event::GeneralRef(r)) => {
+                let resolved = match r.resolve_char_ref() {
+                    Ok(Some(c)) => Some(c),
+                    _ => match &*r {
+                        b"amp" => Some('&'),
+                        b"lt" => Some('<'),
+                        b"gt" => Some('>'),
+                        b"quot" => Some('"'),
+                        b"apos" => Some('\''),
+                        _ => None,
+                    },
+                };
+                text.extend(resolved);
+            }
+            Ok(Event::End(_)) => {
+                let name = path.pop().unwrap_or_default();
+                let field = String::from_utf8_lossy(&name).into_owned();
+                match path.len() {
+                    1 if name == record => page.records.push(std::mem::take(&mut current)),
+                    1 => {
+                        page.fields.insert(field, std::mem::take(&mut text));
+                    }
+                    2 if path[1] == record => {
+                        current.insert(field, std::mem::take(&mut text));
+                    }
+                    _ => {}
+                }
+            }
+            Ok(Event::Eof) => return Ok(page),
+            Ok(_) => {}
+            Err(e) => return Err(invalid(&e)),
+        }
+    }
+}
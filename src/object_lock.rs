@@ -0,0 +1,439 @@
+//! S3 Object Lock for compliance buckets: every object written is kept
+//! from being changed or deleted until a retention date. object_store has
+//! no way to add headers to a write, so `LockedS3` sends PutObject,
+//! CopyObject and CreateMultipartUpload itself, signed like the `glacier`
+//! requests, with the lock headers on. Reads, deletes and the parts of a
+//! multipart upload go through the object_store client as usual.
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::{DateTime, Utc};
+use futures_util::{FutureExt, stream::BoxStream};
+use md5::{Digest, Md5};
+use object_store::{
+    Attribute, Attributes, Error, GetOptions, GetResult, ListResult, MultipartId, MultipartUpload,
+    ObjectMeta, ObjectStore, PutMode, PutMultipartOpts, PutOptions, PutPayload, PutResult, Result,
+    TagSet, UploadPart,
+    aws::AmazonS3,
+    multipart::{MultipartStore, PartId},
+    path::Path,
+};
+use reqwest::{Method, StatusCode};
+use std::{
+    fmt,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use crate::glacier::{self, encode_segment};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Users with `s3:BypassGovernanceRetention` may still delete.
+    Governance,
+    /// No one can delete before the date, the root account included.
+    Compliance,
+}
+
+impl LockMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "GOVERNANCE" => Some(Self::Governance),
+            "COMPLIANCE" => Some(Self::Compliance),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Governance => "GOVERNANCE",
+            Self::Compliance => "COMPLIANCE",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectLock {
+    pub mode: LockMode,
+    pub retain_days: u32,
+}
+
+impl ObjectLock {
+    /// What locks an object written at `now`.
+    pub fn headers(&self, now: DateTime<Utc>) -> [(String, String); 2] {
+        let until = now + chrono::Duration::days(i64::from(self.retain_days));
+        [
+            ("x-amz-object-lock-mode".into(), self.mode.as_str().into()),
+            (
+                "x-amz-object-lock-retain-until-date".into(),
+                until.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            ),
+        ]
+    }
+}
+
+/// The bucket an `AmazonS3` writes to, with what signing requests needs.
+#[derive(Debug, Clone)]
+pub struct LockedBucket {
+    pub endpoint: String,
+    pub name: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Sent with every write besides the lock, such as the SSE headers.
+    pub write_headers: Vec<(String, String)>,
+    /// Sent when a multipart upload is created, for what its parts carry.
+    pub upload_headers: Vec<(String, String)>,
+}
+
+/// `s3` with every object it writes locked by `lock`.
+#[derive(Debug, Clone)]
+pub struct LockedS3 {
+    s3: Arc<AmazonS3>,
+    bucket: Arc<LockedBucket>,
+    lock: ObjectLock,
+}
+
+impl LockedS3 {
+    pub fn new(s3: AmazonS3, bucket: LockedBucket, lock: ObjectLock) -> Self {
+        Self {
+            s3: Arc::new(s3),
+            bucket: Arc::new(bucket),
+            lock,
+        }
+    }
+
+    fn signer(&self) -> glacier::Bucket<'_> {
+        glacier::Bucket {
+            endpoint: &self.bucket.endpoint,
+            name: &self.bucket.name,
+            region: &self.bucket.region,
+            access_key: &self.bucket.access_key,
+            secret_key: &self.bucket.secret_key,
+        }
+    }
+
+    fn write_headers(&self, attributes: &Attributes, tags: &TagSet) -> Vec<(String, String)> {
+        let mut headers: Vec<(String, String)> = self.lock.headers(Utc::now()).into();
+        headers.extend(self.bucket.write_headers.iter().cloned());
+        headers.extend(attributes.iter().filter_map(|(attribute, value)| {
+            let name = match attribute {
+                Attribute::ContentType => "content-type".to_string(),
+                Attribute::ContentEncoding => "content-encoding".to_string(),
+                Attribute::CacheControl => "cache-control".to_string(),
+                Attribute::ContentDisposition => "content-disposition".to_string(),
+                Attribute::ContentLanguage => "content-language".to_string(),
+                Attribute::Metadata(key) => format!("x-amz-meta-{key}"),
+                _ => return None,
+            };
+            Some((name, value.to_string()))
+        }));
+        if !tags.encoded().is_empty() {
+            headers.push(("x-amz-tagging".into(), tags.encoded().into()));
+        }
+        headers
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        location: &Path,
+        headers: &[(String, String)],
+        body: Option<Bytes>,
+    ) -> Result<reqwest::Response> {
+        let headers: Vec<(&str, String)> = headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.clone()))
+            .collect();
+        let response = self
+            .signer()
+            .signed(method, url, &headers, body)
+            .await
+            .map_err(|e| Error::Generic {
+                store: "S3",
+                source: e.to_string().into(),
+            })?;
+        if response.status().is_success() {
+            return Ok(response);
+        }
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        Err(status_error(status, location, body))
+    }
+
+    async fn copy_locked(&self, from: &Path, to: &Path) -> Result<()> {
+        let source = format!(
+            "/{}/{}",
+            encode_segment(&self.bucket.name),
+            from.as_ref()
+                .split('/')
+                .map(encode_segment)
+                .collect::<Vec<_>>()
+                .join("/")
+        );
+        let mut headers = self.write_headers(&Attributes::new(), &TagSet::default());
+        headers.push(("x-amz-copy-source".into(), source));
+        let url = self.signer().object_url(to.as_ref());
+        let response = self.send(Method::PUT, &url, to, &headers, None).await?;
+        // CopyObject can fail after its 200, with the error in the body.
+        let body = response.text().await.unwrap_or_default();
+        if body.contains("<Error>") {
+            return Err(Error::Generic {
+                store: "S3",
+                source: body.into(),
+            });
+        }
+        Ok(())
+    }
+
+    async fn create_locked_upload(
+        &self,
+        location: &Path,
+        opts: &PutMultipartOpts,
+    ) -> Result<MultipartId> {
+        let mut headers = self.write_headers(&opts.attributes, &opts.tags);
+        headers.extend(self.bucket.upload_headers.iter().cloned());
+        let url = format!("{}?uploads", self.signer().object_url(location.as_ref()));
+        let response = self
+            .send(Method::POST, &url, location, &headers, None)
+            .await?;
+        let body = response.text().await.unwrap_or_default();
+        body.split_once("<UploadId>")
+            .and_then(|(_, rest)| rest.split_once("</UploadId>"))
+            .map(|(id, _)| id.to_string())
+            .ok_or_else(|| Error::Generic {
+                store: "S3",
+                source: format!("CreateMultipartUpload answered without an UploadId: {body}")
+                    .into(),
+            })
+    }
+}
+
+/// The object_store error a failed S3 response stands for.
+fn status_error(status: StatusCode, location: &Path, body: String) -> Error {
+    let path = location.to_string();
+    let source = format!("S3 answered {status}: {body}").into();
+    match status {
+        StatusCode::NOT_FOUND => Error::NotFound { path, source },
+        StatusCode::PRECONDITION_FAILED => Error::Precondition { path, source },
+        StatusCode::CONFLICT => Error::AlreadyExists { path, source },
+        StatusCode::FORBIDDEN => Error::PermissionDenied { path, source },
+        StatusCode::UNAUTHORIZED => Error::Unauthenticated { path, source },
+        _ => Error::Generic {
+            store: "S3",
+            source,
+        },
+    }
+}
+
+impl fmt::Display for LockedS3 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.s3.fmt(f)
+    }
+}
+
+#[async_trait]
+impl ObjectStore for LockedS3 {
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult> {
+        let bytes = Bytes::from(payload);
+        let mut headers = self.write_headers(&opts.attributes, &opts.tags);
+        // S3 wants a digest of whatever is put under a lock.
+        headers.push(("content-md5".into(), STANDARD.encode(Md5::digest(&bytes))));
+        match opts.mode {
+            PutMode::Overwrite => {}
+            PutMode::Create => headers.push(("if-none-match".into(), "*".into())),
+            PutMode::Update(version) => {
+                let Some(e_tag) = version.e_tag else {
+                    return Err(Error::Generic {
+                        store: "S3",
+                        source: "a conditional put needs an ETag".into(),
+                    });
+                };
+                headers.push(("if-match".into(), e_tag));
+            }
+        }
+        let url = self.signer().object_url(location.as_ref());
+        let response = self
+            .send(Method::PUT, &url, location, &headers, Some(bytes))
+            .await?;
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        Ok(PutResult {
+            e_tag: header("etag"),
+            version: header("x-amz-version-id"),
+        })
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOpts,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        let id = self.create_locked_upload(location, &opts).await?;
+        Ok(Box::new(LockedUpload {
+            s3: self.s3.clone(),
+            location: location.clone(),
+            id,
+            parts: Arc::new(Mutex::new(Vec::new())),
+        }))
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        self.s3.get_opts(location, options).await
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        self.s3.get_range(location, range).await
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        self.s3.get_ranges(location, ranges).await
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.s3.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.s3.delete(location).await
+    }
+
+    fn delete_stream<'a>(
+        &'a self,
+        locations: BoxStream<'a, Result<Path>>,
+    ) -> BoxStream<'a, Result<Path>> {
+        self.s3.delete_stream(locations)
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.s3.list(prefix)
+    }
+
+    fn list_with_offset(
+        &self,
+        prefix: Option<&Path>,
+        offset: &Path,
+    ) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.s3.list_with_offset(prefix, offset)
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.s3.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.copy_locked(from, to).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.copy_locked(from, to).await?;
+        self.s3.delete(from).await
+    }
+
+    /// Callers fall back to a check and a plain `copy`, which is locked.
+    async fn copy_if_not_exists(&self, _from: &Path, _to: &Path) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+
+    async fn rename_if_not_exists(&self, _from: &Path, _to: &Path) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+}
+
+#[async_trait]
+impl MultipartStore for LockedS3 {
+    async fn create_multipart(&self, path: &Path) -> Result<MultipartId> {
+        self.create_locked_upload(path, &PutMultipartOpts::default())
+            .await
+    }
+
+    async fn put_part(
+        &self,
+        path: &Path,
+        id: &MultipartId,
+        part_idx: usize,
+        data: PutPayload,
+    ) -> Result<PartId> {
+        self.s3.put_part(path, id, part_idx, data).await
+    }
+
+    async fn complete_multipart(
+        &self,
+        path: &Path,
+        id: &MultipartId,
+        parts: Vec<PartId>,
+    ) -> Result<PutResult> {
+        self.s3.complete_multipart(path, id, parts).await
+    }
+
+    async fn abort_multipart(&self, path: &Path, id: &MultipartId) -> Result<()> {
+        self.s3.abort_multipart(path, id).await
+    }
+}
+
+/// A multipart upload created locked, its parts sent by the object_store
+/// client.
+#[derive(Debug)]
+struct LockedUpload {
+    s3: Arc<AmazonS3>,
+    location: Path,
+    id: MultipartId,
+    /// By part index, as they finish.
+    parts: Arc<Mutex<Vec<Option<PartId>>>>,
+}
+
+#[async_trait]
+impl MultipartUpload for LockedUpload {
+    fn put_part(&mut self, data: PutPayload) -> UploadPart {
+        let index = {
+            let mut parts = self.parts.lock().unwrap();
+            parts.push(None);
+            parts.len() - 1
+        };
+        let (s3, location, id, parts) = (
+            self.s3.clone(),
+            self.location.clone(),
+            self.id.clone(),
+            self.parts.clone(),
+        );
+        async move {
+            let part = s3.put_part(&location, &id, index, data).await?;
+            parts.lock().unwrap()[index] = Some(part);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    async fn complete(&mut self) -> Result<PutResult> {
+        let parts = self
+            .parts
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| Error::Generic {
+                store: "S3",
+                source: "a part of the upload has not finished".into(),
+            })?;
+        self.s3
+            .complete_multipart(&self.location, &self.id, parts)
+            .await
+    }
+
+    async fn abort(&mut self) -> Result<()> {
+        self.s3.abort_multipart(&self.location, &self.id).await
+    }
+}
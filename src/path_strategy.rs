@@ -0,0 +1,52 @@
+//! Where in the bucket a new file's object goes. S3 spreads load by key
+//! prefix, so millions of keys side by side in one namespace get slow;
+//! sharding them by upload date or content hash spreads them out.
+
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathStrategy {
+    /// `<name>`.
+    #[default]
+    Flat,
+    /// `YYYY/MM/DD/<name>`, the day of the upload.
+    Date,
+    /// `ab/cd/<name>`, the first four hex digits of the content's SHA-256.
+    Hash,
+}
+
+impl PathStrategy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "flat" => Some(Self::Flat),
+            "date" => Some(Self::Date),
+            "hash" => Some(Self::Hash),
+            _ => None,
+        }
+    }
+
+    /// The folders `build_key` puts `bytes` under; `None` when flat.
+    pub fn shard(self, bytes: &[u8]) -> Option<String> {
+        match self {
+            Self::Flat => None,
+            Self::Date => Some(Utc::now().format("%Y/%m/%d").to_string()),
+            Self::Hash => {
+                let digest = hex::encode(&Sha256::digest(bytes)[..2]);
+                Some(format!("{}/{}", &digest[..2], &digest[2..]))
+            }
+        }
+    }
+
+    pub fn build_key(self, name: &str, bytes: &[u8]) -> String {
+        sharded_key(self.shard(bytes).as_deref(), name)
+    }
+}
+
+/// The key of `name` under `shard`, as recorded for its file.
+pub fn sharded_key(shard: Option<&str>, name: &str) -> String {
+    match shard {
+        Some(shard) => format!("{shard}/{name}"),
+        None => name.to_string(),
+    }
+}
@@ -0,0 +1,105 @@
+//! First-page preview images of PDFs, rendered by a pluggable `PdfRenderer`.
+
+use std::{
+    io::Read,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+pub const PREVIEW_CONTENT_TYPE: &str = "image/png";
+
+pub fn is_pdf(file_name: &str) -> bool {
+    file_name
+        .rsplit_once('.')
+        .is_some_and(|(_, ext)| ext.eq_ignore_ascii_case("pdf"))
+}
+
+/// Previews live next to the thumbnails, under the same derived prefix.
+pub fn preview_key(file_name: &str) -> String {
+    format!("thumbnails/{file_name}.preview.png")
+}
+
+/// Renders the first page of a PDF to a PNG. Implementations block, so
+/// callers run them on the blocking pool.
+pub trait PdfRenderer: Send + Sync {
+    fn render_first_page(&self, pdf: &[u8], dpi: u32) -> Result<Vec<u8>, String>;
+}
+
+/// Renders with poppler's `pdftoppm`, killed if it runs past `timeout`.
+pub struct Pdftoppm {
+    pub binary: String,
+    pub timeout: Duration,
+}
+
+static SCRATCH_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A scratch path removed again when dropped.
+struct ScratchFile(PathBuf);
+
+impl ScratchFile {
+    fn new(suffix: &str) -> Self {
+        let n = SCRATCH_COUNTER.fetch_add(1, Ordering::Relaxed);
+        Self(std::env::temp_dir().join(format!("preview-{}-{n}{suffix}", std::process::id())))
+    }
+}
+
+impl Drop for ScratchFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+impl PdfRenderer for Pdftoppm {
+    fn render_first_page(&self, pdf: &[u8], dpi: u32) -> Result<Vec<u8>, String> {
+        // pdftoppm needs a seekable input, so the PDF goes through a file.
+        let input = ScratchFile::new(".pdf");
+        std::fs::write(&input.0, pdf).map_err(|e| format!("Scratch write failed: {e}"))?;
+        // `-singlefile` writes `<root>.png` instead of `<root>-1.png`.
+        let output = ScratchFile::new(".png");
+
+        let mut child = Command::new(&self.binary)
+            .args(["-png", "-singlefile", "-f", "1", "-l", "1", "-r"])
+            .arg(dpi.to_string())
+            .arg(&input.0)
+            .arg(output.0.with_extension(""))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Could not start {}: {e}", self.binary))?;
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+                Ok(None) => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(format!("Rendering timed out after {:?}", self.timeout));
+                }
+                Err(e) => return Err(format!("Waiting for {} failed: {e}", self.binary)),
+            }
+        };
+
+        if !status.success() {
+            let mut stderr = String::new();
+            if let Some(mut pipe) = child.stderr.take() {
+                let _ = pipe.read_to_string(&mut stderr);
+            }
+            let reason = stderr
+                .lines()
+                .next()
+                .unwrap_or("no output")
+                .trim()
+                .to_string();
+            return Err(format!("Rendering failed ({status}): {reason}"));
+        }
+
+        std::fs::read(&output.0).map_err(|e| format!("Rendered page missing: {e}"))
+    }
+}
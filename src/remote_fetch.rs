@@ -0,0 +1,223 @@
+//! Downloads behind `POST /files/from-url`, guarded against being turned
+//! into a proxy for the internal network.
+
+use axum::body::Bytes;
+use reqwest::{Client, StatusCode, header, redirect};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use url::{Host, Url};
+
+/// Which URLs may be fetched, and how much of them.
+#[derive(Debug, Clone)]
+pub struct FetchPolicy {
+    /// Host names that may be fetched; `*.example.com` also admits subdomains.
+    pub allowed_hosts: Vec<String>,
+    /// Admits every host, `allowed_hosts` notwithstanding.
+    pub allow_any_host: bool,
+    /// Admits loopback, private (RFC 1918) and link-local targets.
+    pub allow_private_addresses: bool,
+    /// For the whole download, redirects included.
+    pub timeout: Duration,
+    pub max_redirects: usize,
+    pub max_bytes: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FetchError {
+    #[error("{0}")]
+    InvalidUrl(String),
+    #[error("{0}")]
+    NotAllowed(String),
+    #[error("Remote file exceeds the {0} byte limit")]
+    TooLarge(u64),
+    #[error("{0}")]
+    Failed(String),
+}
+
+#[derive(Debug)]
+pub struct Fetched {
+    /// Where the body came from, after redirects.
+    pub url: Url,
+    pub bytes: Bytes,
+}
+
+/// False for addresses a request from the server must not reach by default:
+/// loopback, private, link-local, shared (CGNAT), multicast and the like.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        || (a == 100 && (64..128).contains(&b)))
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80)
+}
+
+impl FetchPolicy {
+    pub fn allows_host(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        self.allow_any_host
+            || self.allowed_hosts.iter().any(|allowed| {
+                let allowed = allowed.to_ascii_lowercase();
+                match allowed.strip_prefix("*.") {
+                    Some(domain) => host == domain || host.ends_with(&format!(".{domain}")),
+                    None => host == allowed,
+                }
+            })
+    }
+
+    /// The addresses `url` may be fetched from, once its scheme, host and
+    /// every address its name resolves to have been checked.
+    async fn resolve(&self, url: &Url) -> Result<Vec<SocketAddr>, FetchError> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(FetchError::InvalidUrl(format!(
+                "Only http and https URLs can be fetched, not '{}'",
+                url.scheme()
+            )));
+        }
+        let host = url
+            .host()
+            .ok_or_else(|| FetchError::InvalidUrl("URL has no host".into()))?;
+        let name = match &host {
+            Host::Domain(name) => name.to_string(),
+            Host::Ipv4(ip) => ip.to_string(),
+            Host::Ipv6(ip) => ip.to_string(),
+        };
+        if !self.allows_host(&name) {
+            return Err(FetchError::NotAllowed(format!(
+                "Host '{name}' is not allowed"
+            )));
+        }
+
+        let port = url.port_or_known_default().unwrap_or(80);
+        let addrs: Vec<SocketAddr> = match host {
+            Host::Domain(name) => tokio::net::lookup_host((name, port))
+                .await
+                .map_err(|e| FetchError::Failed(format!("Could not resolve '{name}': {e}")))?
+                .collect(),
+            Host::Ipv4(ip) => vec![SocketAddr::new(ip.into(), port)],
+            Host::Ipv6(ip) => vec![SocketAddr::new(ip.into(), port)],
+        };
+        if addrs.is_empty() {
+            return Err(FetchError::Failed(format!("'{name}' has no addresses")));
+        }
+        if !self.allow_private_addresses && addrs.iter().any(|a| !is_public(a.ip())) {
+            return Err(FetchError::NotAllowed(format!(
+                "Host '{name}' resolves to an internal address"
+            )));
+        }
+        Ok(addrs)
+    }
+}
+
+/// Checks `url` against the policy without fetching it, for clients that
+/// make their own connections.
+pub async fn check(policy: &FetchPolicy, url: &str) -> Result<Url, FetchError> {
+    let url = Url::parse(url).map_err(|e| FetchError::InvalidUrl(format!("Invalid URL: {e}")))?;
+    policy.resolve(&url).await?;
+    Ok(url)
+}
+
+/// GETs `url`, following up to `max_redirects` redirects; every hop is
+/// checked against the policy again.
+pub async fn fetch(policy: &FetchPolicy, url: &str) -> Result<Fetched, FetchError> {
+    let url = Url::parse(url).map_err(|e| FetchError::InvalidUrl(format!("Invalid URL: {e}")))?;
+    tokio::time::timeout(policy.timeout, follow(policy, url))
+        .await
+        .unwrap_or_else(|_| {
+            Err(FetchError::Failed(format!(
+                "Download did not finish within {:?}",
+                policy.timeout
+            )))
+        })
+}
+
+async fn follow(policy: &FetchPolicy, mut url: Url) -> Result<Fetched, FetchError> {
+    let mut redirects = 0;
+    loop {
+        let addrs = policy.resolve(&url).await?;
+        // Connect to the addresses just checked, not whatever a second lookup says.
+        let mut client = Client::builder().redirect(redirect::Policy::none());
+        if let Some(Host::Domain(name)) = url.host() {
+            client = client.resolve_to_addrs(name, &addrs);
+        }
+        let client = client
+            .build()
+            .map_err(|e| FetchError::Failed(format!("HTTP client error: {e}")))?;
+        let mut response = client
+            .get(url.clone())
+            .send()
+            .await
+            .map_err(|e| FetchError::Failed(format!("Request to {url} failed: {e}")))?;
+
+        let status = response.status();
+        if status.is_redirection() && status != StatusCode::NOT_MODIFIED {
+            let location = response
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| FetchError::Failed(format!("{url} redirected nowhere")))?;
+            let next = url
+                .join(location)
+                .map_err(|e| FetchError::Failed(format!("Bad redirect from {url}: {e}")))?;
+            redirects += 1;
+            if redirects > policy.max_redirects {
+                return Err(FetchError::Failed(format!(
+                    "More than {} redirects",
+                    policy.max_redirects
+                )));
+            }
+            url = next;
+            continue;
+        }
+        if !status.is_success() {
+            return Err(FetchError::Failed(format!("{url} answered {status}")));
+        }
+
+        if response
+            .content_length()
+            .is_some_and(|len| len > policy.max_bytes)
+        {
+            return Err(FetchError::TooLarge(policy.max_bytes));
+        }
+        let mut body = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| FetchError::Failed(format!("Download from {url} failed: {e}")))?
+        {
+            if (body.len() + chunk.len()) as u64 > policy.max_bytes {
+                return Err(FetchError::TooLarge(policy.max_bytes));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        return Ok(Fetched {
+            url,
+            bytes: body.into(),
+        });
+    }
+}
@@ -0,0 +1,163 @@
+//! A warm copy of the bucket in a second one, for disaster recovery. Each
+//! upload queues its object to be copied once it is stored; `reconcile`
+//! backstops the queue by walking both listings side by side, in key
+//! order, so neither bucket is ever held in memory.
+
+use futures_util::StreamExt;
+use object_store::{
+    Error, ObjectMeta, ObjectStore, Result, buffered::BufWriter, path::Path as ObjectPath,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt as _;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplicationStatus {
+    /// Queued, or being copied.
+    Pending,
+    Synced,
+    /// The last copy failed; the next reconciliation tries again.
+    Failed,
+}
+
+impl ReplicationStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Synced => "synced",
+            Self::Failed => "failed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(Self::Pending),
+            "synced" => Some(Self::Synced),
+            "failed" => Some(Self::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// What one `reconcile` pass did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Reconciliation {
+    pub copied: usize,
+    pub deleted: usize,
+    pub in_sync: usize,
+    /// Objects that could not be copied or deleted; they are logged.
+    pub failed: usize,
+}
+
+/// Copies `location` from `source` to `target`, attributes included,
+/// streaming it through in parts.
+pub async fn copy_object(
+    source: &dyn ObjectStore,
+    target: &Arc<dyn ObjectStore>,
+    location: &ObjectPath,
+) -> Result<()> {
+    let result = source.get(location).await?;
+    let mut writer =
+        BufWriter::new(target.clone(), location.clone()).with_attributes(result.attributes.clone());
+    let mut chunks = result.into_stream();
+    while let Some(chunk) = chunks.next().await {
+        let written = match chunk {
+            Ok(chunk) => writer.put(chunk).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            let _ = writer.abort().await;
+            return Err(e);
+        }
+    }
+    // Past this point the writer can no longer be aborted; parts a failed
+    // upload leaves behind are swept by `abort_orphaned_uploads`.
+    writer.shutdown().await.map_err(|e| Error::Generic {
+        store: "replica",
+        source: Box::new(e),
+    })
+}
+
+/// Whether `replica` still holds what `primary` does. Buckets compute
+/// ETags their own way, and a multipart ETag depends on the part size, so
+/// a replica written after its source with the same size counts too.
+fn in_sync(primary: &ObjectMeta, replica: &ObjectMeta) -> bool {
+    primary.size == replica.size
+        && (primary.e_tag.is_some() && primary.e_tag == replica.e_tag
+            || replica.last_modified >= primary.last_modified)
+}
+
+/// Brings `replica` in line with `primary`: copies what is missing or has
+/// changed and, with `replicate_deletes`, deletes what `primary` no longer
+/// has. Both listings must come in key order, as S3's do.
+pub async fn reconcile(
+    primary: &dyn ObjectStore,
+    replica: &Arc<dyn ObjectStore>,
+    replicate_deletes: bool,
+) -> Result<Reconciliation> {
+    let mut report = Reconciliation::default();
+    let mut sources = primary.list(None);
+    let mut replicas = replica.list(None);
+    let mut source = sources.next().await.transpose()?;
+    let mut copy = replicas.next().await.transpose()?;
+
+    loop {
+        let ordering = match (&source, &copy) {
+            (None, None) => break,
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (Some(s), Some(c)) => s.location.cmp(&c.location),
+        };
+        match ordering {
+            std::cmp::Ordering::Less => {
+                let location = &source.as_ref().expect("compared").location;
+                copy_into(primary, replica, location, &mut report).await;
+                source = sources.next().await.transpose()?;
+            }
+            std::cmp::Ordering::Greater => {
+                let location = &copy.as_ref().expect("compared").location;
+                if replicate_deletes {
+                    match replica.delete(location).await {
+                        Ok(()) | Err(Error::NotFound { .. }) => report.deleted += 1,
+                        Err(e) => {
+                            tracing::warn!(key = %location, error = %e, "failed to delete replica");
+                            report.failed += 1;
+                        }
+                    }
+                }
+                copy = replicas.next().await.transpose()?;
+            }
+            std::cmp::Ordering::Equal => {
+                let (s, c) = (
+                    source.as_ref().expect("compared"),
+                    copy.as_ref().expect("compared"),
+                );
+                if in_sync(s, c) {
+                    report.in_sync += 1;
+                } else {
+                    copy_into(primary, replica, &s.location, &mut report).await;
+                }
+                source = sources.next().await.transpose()?;
+                copy = replicas.next().await.transpose()?;
+            }
+        }
+    }
+    Ok(report)
+}
+
+async fn copy_into(
+    primary: &dyn ObjectStore,
+    replica: &Arc<dyn ObjectStore>,
+    location: &ObjectPath,
+    report: &mut Reconciliation,
+) {
+    match copy_object(primary, replica, location).await {
+        Ok(()) => report.copied += 1,
+        // Deleted since it was listed.
+        Err(Error::NotFound { .. }) => {}
+        Err(e) => {
+            tracing::warn!(key = %location, error = %e, "failed to replicate object");
+            report.failed += 1;
+        }
+    }
+}
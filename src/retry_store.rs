@@ -0,0 +1,300 @@
+//! Retries the storage calls a failed request can't simply be resent for:
+//! a rename is a copy and a delete, and a multipart upload can only be
+//! completed once. Before trying again, each retry checks whether the
+//! attempt that seemed to fail took effect after all.
+//!
+//! Everything else is retried by object_store's own `RetryConfig`.
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures_util::stream::BoxStream;
+use object_store::{
+    Error, GetOptions, GetResult, ListResult, MultipartId, MultipartUpload, ObjectMeta,
+    ObjectStore, PutMultipartOpts, PutOptions, PutPayload, PutResult, Result, UploadPart,
+    multipart::{MultipartStore, PartId},
+    path::Path,
+};
+use std::{
+    fmt,
+    ops::Range,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+static RETRIED: AtomicU64 = AtomicU64::new(0);
+
+/// Storage calls retried by any `RetryStore` since the process started.
+pub fn retried_operations() -> u64 {
+    RETRIED.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Zero turns retrying off.
+    pub max_retries: usize,
+    /// Wait before the first retry; it doubles with every further one.
+    pub init_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Between half and all of the doubled backoff, so clients that failed
+    /// together don't all come back at once.
+    fn backoff(&self, attempt: usize) -> Duration {
+        let full = self
+            .init_backoff
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_backoff);
+        let jitter = (uuid::Uuid::new_v4().as_u128() as u16) as f64 / f64::from(u16::MAX);
+        full.mul_f64(0.5 + jitter / 2.0)
+    }
+
+    async fn wait(&self, operation: &'static str, attempt: usize, error: &Error) {
+        let retried = RETRIED.fetch_add(1, Ordering::Relaxed) + 1;
+        let delay = self.backoff(attempt);
+        tracing::warn!(
+            operation,
+            attempt = attempt + 1,
+            ?delay,
+            retried_total = retried,
+            error = %error,
+            "retrying storage call"
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Errors a second attempt might not run into. A missing object or a
+/// refused request will be missing or refused again.
+fn is_transient(error: &Error) -> bool {
+    matches!(error, Error::Generic { .. } | Error::JoinError { .. })
+}
+
+#[derive(Debug)]
+pub struct RetryStore<T> {
+    inner: Arc<T>,
+    policy: RetryPolicy,
+}
+
+impl<T> RetryStore<T> {
+    pub fn new(inner: T, policy: RetryPolicy) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            policy,
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for RetryStore<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+/// What a completed upload of `location` reports, for a retry that finds an
+/// earlier attempt already completed it.
+async fn completed(store: &dyn ObjectStore, location: &Path) -> Option<PutResult> {
+    let meta = store.head(location).await.ok()?;
+    Some(PutResult {
+        e_tag: meta.e_tag,
+        version: meta.version,
+    })
+}
+
+#[async_trait]
+impl<T: ObjectStore> ObjectStore for RetryStore<T> {
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult> {
+        self.inner.put_opts(location, payload, opts).await
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOpts,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        let upload = self.inner.put_multipart_opts(location, opts).await?;
+        Ok(Box::new(RetryUpload {
+            inner: upload,
+            store: self.inner.clone(),
+            location: location.clone(),
+            policy: self.policy,
+        }))
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        self.inner.get_opts(location, options).await
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        self.inner.get_range(location, range).await
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        self.inner.get_ranges(location, ranges).await
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.inner.delete(location).await
+    }
+
+    fn delete_stream<'a>(
+        &'a self,
+        locations: BoxStream<'a, Result<Path>>,
+    ) -> BoxStream<'a, Result<Path>> {
+        self.inner.delete_stream(locations)
+    }
+
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.inner.list(prefix)
+    }
+
+    fn list_with_offset(
+        &self,
+        prefix: Option<&Path>,
+        offset: &Path,
+    ) -> BoxStream<'_, Result<ObjectMeta>> {
+        self.inner.list_with_offset(prefix, offset)
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    /// A retry that finds the source gone and the target in place takes it
+    /// that the previous attempt's delete went through and only its answer
+    /// was lost.
+    async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            match self.inner.rename(from, to).await {
+                Ok(()) => return Ok(()),
+                Err(Error::NotFound { .. }) if attempt > 0 && self.inner.head(to).await.is_ok() => {
+                    return Ok(());
+                }
+                Err(e) if attempt < self.policy.max_retries && is_transient(&e) => {
+                    self.policy.wait("rename", attempt, &e).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+
+    async fn rename_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.rename_if_not_exists(from, to).await
+    }
+}
+
+#[async_trait]
+impl<T: ObjectStore + MultipartStore> MultipartStore for RetryStore<T> {
+    async fn create_multipart(&self, path: &Path) -> Result<MultipartId> {
+        self.inner.create_multipart(path).await
+    }
+
+    async fn put_part(
+        &self,
+        path: &Path,
+        id: &MultipartId,
+        part_idx: usize,
+        data: PutPayload,
+    ) -> Result<PartId> {
+        self.inner.put_part(path, id, part_idx, data).await
+    }
+
+    /// The upload is gone once completed, so a retry that can't find it
+    /// looks for the object it became.
+    async fn complete_multipart(
+        &self,
+        path: &Path,
+        id: &MultipartId,
+        parts: Vec<PartId>,
+    ) -> Result<PutResult> {
+        let mut attempt = 0;
+        loop {
+            let error = match self.inner.complete_multipart(path, id, parts.clone()).await {
+                Ok(result) => return Ok(result),
+                Err(e) => e,
+            };
+            if attempt > 0
+                && matches!(error, Error::NotFound { .. })
+                && let Some(result) = completed(self.inner.as_ref(), path).await
+            {
+                return Ok(result);
+            }
+            if attempt >= self.policy.max_retries || !is_transient(&error) {
+                return Err(error);
+            }
+            self.policy
+                .wait("complete_multipart", attempt, &error)
+                .await;
+            attempt += 1;
+        }
+    }
+
+    async fn abort_multipart(&self, path: &Path, id: &MultipartId) -> Result<()> {
+        self.inner.abort_multipart(path, id).await
+    }
+}
+
+/// Retries `complete` like `RetryStore::complete_multipart`.
+#[derive(Debug)]
+struct RetryUpload {
+    inner: Box<dyn MultipartUpload>,
+    store: Arc<dyn ObjectStore>,
+    location: Path,
+    policy: RetryPolicy,
+}
+
+#[async_trait]
+impl MultipartUpload for RetryUpload {
+    fn put_part(&mut self, data: PutPayload) -> UploadPart {
+        self.inner.put_part(data)
+    }
+
+    async fn complete(&mut self) -> Result<PutResult> {
+        let mut attempt = 0;
+        loop {
+            let error = match self.inner.complete().await {
+                Ok(result) => return Ok(result),
+                Err(e) => e,
+            };
+            if attempt > 0
+                && matches!(error, Error::NotFound { .. })
+                && let Some(result) = completed(self.store.as_ref(), &self.location).await
+            {
+                return Ok(result);
+            }
+            if attempt >= self.policy.max_retries || !is_transient(&error) {
+                return Err(error);
+            }
+            self.policy
+                .wait("complete_multipart", attempt, &error)
+                .await;
+            attempt += 1;
+        }
+    }
+
+    async fn abort(&mut self) -> Result<()> {
+        self.inner.abort().await
+    }
+}
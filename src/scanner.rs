@@ -0,0 +1,149 @@
+//! Virus scanning of uploads, by default through clamd's INSTREAM command.
+
+use async_trait::async_trait;
+use serde::Serialize;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+/// clamd rejects chunks above its `StreamMaxLength`; small chunks stay well below it.
+const CHUNK_BYTES: usize = 64 * 1024;
+/// Replies are one short line, such as `stream: OK`.
+const MAX_REPLY_BYTES: u64 = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Clean,
+    /// Carries the signature name the scanner reported.
+    Infected(String),
+}
+
+/// Where a file is in scanning, as stored in `files.scan_status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanStatus {
+    /// Not scanned yet, so not downloadable.
+    Pending,
+    Clean,
+    /// Moved to `quarantine/` and never served.
+    Infected,
+    /// The scanner was unavailable and scanning is configured to fail open.
+    Unscanned,
+}
+
+impl ScanStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Clean => "clean",
+            Self::Infected => "infected",
+            Self::Unscanned => "unscanned",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(Self::Pending),
+            "clean" => Some(Self::Clean),
+            "infected" => Some(Self::Infected),
+            "unscanned" => Some(Self::Unscanned),
+            _ => None,
+        }
+    }
+
+    /// Whether a file in this state may be downloaded.
+    pub fn is_servable(self) -> bool {
+        matches!(self, Self::Clean | Self::Unscanned)
+    }
+}
+
+#[async_trait]
+pub trait Scanner: Send + Sync {
+    /// `Err` means the scanner couldn't give a verdict, not that the file is bad.
+    async fn scan(&self, bytes: &[u8]) -> Result<Verdict, String>;
+}
+
+/// The status a file ends up with after scanning `bytes`, plus the signature
+/// of an infected file. Without a verdict the file stays `pending` unless
+/// `fail_open` lets it through as `unscanned`.
+pub async fn scan_status(
+    scanner: &dyn Scanner,
+    bytes: &[u8],
+    fail_open: bool,
+) -> (ScanStatus, Option<String>) {
+    match scanner.scan(bytes).await {
+        Ok(Verdict::Clean) => (ScanStatus::Clean, None),
+        Ok(Verdict::Infected(signature)) => (ScanStatus::Infected, Some(signature)),
+        Err(e) => {
+            tracing::warn!(error = %e, fail_open, "virus scanner unavailable");
+            let status = if fail_open {
+                ScanStatus::Unscanned
+            } else {
+                ScanStatus::Pending
+            };
+            (status, None)
+        }
+    }
+}
+
+/// A clamd daemon reached over TCP.
+pub struct Clamd {
+    pub host: String,
+    pub port: u16,
+    pub timeout: Duration,
+}
+
+#[async_trait]
+impl Scanner for Clamd {
+    async fn scan(&self, bytes: &[u8]) -> Result<Verdict, String> {
+        tokio::time::timeout(self.timeout, self.instream(bytes))
+            .await
+            .map_err(|_| format!("clamd did not answer within {:?}", self.timeout))?
+    }
+}
+
+impl Clamd {
+    async fn instream(&self, bytes: &[u8]) -> Result<Verdict, String> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .map_err(|e| format!("Could not reach clamd at {}:{}: {e}", self.host, self.port))?;
+        let io_error = |e: std::io::Error| format!("clamd connection failed: {e}");
+
+        // The `z` prefix asks for NUL-terminated commands and replies.
+        stream.write_all(b"zINSTREAM\0").await.map_err(io_error)?;
+        for chunk in bytes.chunks(CHUNK_BYTES) {
+            stream
+                .write_all(&(chunk.len() as u32).to_be_bytes())
+                .await
+                .map_err(io_error)?;
+            stream.write_all(chunk).await.map_err(io_error)?;
+        }
+        stream
+            .write_all(&0u32.to_be_bytes())
+            .await
+            .map_err(io_error)?;
+
+        let mut reply = Vec::new();
+        BufReader::new(stream.take(MAX_REPLY_BYTES))
+            .read_until(0, &mut reply)
+            .await
+            .map_err(io_error)?;
+        parse_reply(&reply)
+    }
+}
+
+/// `stream: OK`, `stream: <signature> FOUND` or `<message> ERROR`.
+fn parse_reply(reply: &[u8]) -> Result<Verdict, String> {
+    let reply = String::from_utf8_lossy(reply);
+    let reply = reply.trim_end_matches(['\0', '\n']).trim();
+    let result = reply.strip_prefix("stream:").map(str::trim);
+    match result {
+        Some("OK") => Ok(Verdict::Clean),
+        Some(found) if found.ends_with(" FOUND") => Ok(Verdict::Infected(
+            found.trim_end_matches(" FOUND").trim().to_string(),
+        )),
+        _ => Err(format!("Unexpected clamd reply: {reply}")),
+    }
+}
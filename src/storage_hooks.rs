@@ -0,0 +1,58 @@
+//! Callbacks around storage that extend the server without forking it:
+//! watermarking or DRM before an upload is written, indexing after, or
+//! holding on to files that must not be removed.
+//!
+//! The hooks in use are an `Arc<dyn StorageHooks>` in the context's shared
+//! store, put there by `App::after_context`; swap in your own with
+//! `install`.
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use loco_rs::prelude::*;
+use std::sync::Arc;
+
+use crate::controllers::files::FileInfo;
+
+#[async_trait]
+pub trait StorageHooks: Send + Sync {
+    /// The content to store for an upload named `name`, given what the
+    /// client sent. An error refuses the file.
+    async fn pre_upload(&self, name: &str, bytes: &Bytes) -> Result<Bytes>;
+
+    /// Runs once the file is stored. An error is logged; the upload stands.
+    async fn post_upload(&self, name: &str, info: &FileInfo) -> Result<()>;
+
+    /// An error keeps the file, and is what the delete fails with.
+    async fn pre_delete(&self, name: &str) -> Result<()>;
+}
+
+/// Stores uploads as sent and lets every delete through.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopHooks;
+
+#[async_trait]
+impl StorageHooks for NoopHooks {
+    async fn pre_upload(&self, _name: &str, bytes: &Bytes) -> Result<Bytes> {
+        Ok(bytes.clone())
+    }
+
+    async fn post_upload(&self, _name: &str, _info: &FileInfo) -> Result<()> {
+        Ok(())
+    }
+
+    async fn pre_delete(&self, _name: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Makes `hooks` the ones every handler of `ctx` runs.
+pub fn install(ctx: &AppContext, hooks: Arc<dyn StorageHooks>) {
+    ctx.shared_store.insert(hooks);
+}
+
+/// The hooks installed in `ctx`, `NoopHooks` if there are none.
+pub fn configured(ctx: &AppContext) -> Arc<dyn StorageHooks> {
+    ctx.shared_store
+        .get::<Arc<dyn StorageHooks>>()
+        .unwrap_or_else(|| Arc::new(NoopHooks))
+}
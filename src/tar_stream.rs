@@ -0,0 +1,245 @@
+//! Tar archives written as they are streamed: each entry's header goes out
+//! with the size storage reported for it, then its bytes, so nothing is
+//! held beyond a copy buffer. A header can't be taken back once sent, which
+//! is why an object whose size changed meanwhile ends the archive.
+
+use chrono::{DateTime, Utc};
+use object_store::{ObjectStore, path::Path};
+use std::pin::Pin;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::io::StreamReader;
+
+use crate::controllers::files::is_gzip_encoded;
+
+pub const BLOCK_SIZE: usize = 512;
+
+/// Entry added in place of the rest of an archive that had to stop.
+pub const ERROR_TRAILER: &str = "ERROR.txt";
+
+const COPY_BUFFER: usize = 64 * 1024;
+
+/// Largest size the 11 octal digits of a ustar header hold.
+const MAX_USTAR_SIZE: u64 = 0o777_7777_7777;
+
+pub struct TarEntry {
+    /// Name in the archive.
+    pub name: String,
+    pub path: Path,
+    /// Of the content as archived, so after undoing any gzip storage applied.
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error("'{name}' grew past the {expected} bytes its header holds")]
+    Grew { name: String, expected: u64 },
+    #[error("'{name}' ended after {actual} of the {expected} bytes its header holds")]
+    Truncated {
+        name: String,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("reading '{name}' failed: {message}")]
+    Read { name: String, message: String },
+    #[error("writing the archive failed: {0}")]
+    Write(#[from] std::io::Error),
+}
+
+/// Writes `entries` to `out` and shuts it down. When an entry can't be
+/// archived whole, the archive gets an `ERROR_TRAILER` saying why and no
+/// end-of-archive blocks, so readers see it was cut short.
+pub async fn write_archive<W>(
+    store: &dyn ObjectStore,
+    entries: &[TarEntry],
+    mut out: W,
+) -> Result<(), ExportError>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    let mut written = Ok(());
+    for entry in entries {
+        if let Err(e) = write_entry(store, entry, &mut out).await {
+            if !matches!(e, ExportError::Write(_)) {
+                let report = format!("The export stopped here: {e}\n");
+                let header = header(ERROR_TRAILER, report.len() as u64, Utc::now().timestamp());
+                out.write_all(&header).await?;
+                out.write_all(report.as_bytes()).await?;
+                out.write_all(&[0; BLOCK_SIZE][..padding(report.len() as u64)])
+                    .await?;
+            }
+            written = Err(e);
+            break;
+        }
+    }
+    if written.is_ok() {
+        out.write_all(&[0; 2 * BLOCK_SIZE]).await?;
+    }
+    out.shutdown().await?;
+    written
+}
+
+async fn write_entry<W>(
+    store: &dyn ObjectStore,
+    entry: &TarEntry,
+    out: &mut W,
+) -> Result<(), ExportError>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    let read_error = |e: &dyn std::fmt::Display| ExportError::Read {
+        name: entry.name.clone(),
+        message: e.to_string(),
+    };
+    let result = store.get(&entry.path).await.map_err(|e| read_error(&e))?;
+    let gzip_encoded = is_gzip_encoded(&result.attributes);
+    let raw = StreamReader::new(result.into_stream());
+    let mut reader: Pin<Box<dyn AsyncRead + Send>> = if gzip_encoded {
+        Box::pin(async_compression::tokio::bufread::GzipDecoder::new(raw))
+    } else {
+        Box::pin(raw)
+    };
+
+    out.write_all(&header(&entry.name, entry.size, entry.modified.timestamp()))
+        .await?;
+
+    let mut remaining = entry.size;
+    let mut grew = false;
+    let mut buf = vec![0; COPY_BUFFER];
+    loop {
+        let n = reader.read(&mut buf).await.map_err(|e| read_error(&e))?;
+        if n == 0 {
+            break;
+        }
+        let take = (n as u64).min(remaining) as usize;
+        out.write_all(&buf[..take]).await?;
+        remaining -= take as u64;
+        if take < n {
+            grew = true;
+            break;
+        }
+    }
+
+    // A short entry is filled out to the size its header gave, so what was
+    // written so far still reads as a tar.
+    let zeros = [0; COPY_BUFFER];
+    let mut fill = remaining;
+    while fill > 0 {
+        let n = fill.min(COPY_BUFFER as u64) as usize;
+        out.write_all(&zeros[..n]).await?;
+        fill -= n as u64;
+    }
+    out.write_all(&zeros[..padding(entry.size)]).await?;
+
+    if grew {
+        return Err(ExportError::Grew {
+            name: entry.name.clone(),
+            expected: entry.size,
+        });
+    }
+    if remaining > 0 {
+        return Err(ExportError::Truncated {
+            name: entry.name.clone(),
+            expected: entry.size,
+            actual: entry.size - remaining,
+        });
+    }
+    Ok(())
+}
+
+/// Zeros that round `size` up to whole blocks.
+pub fn padding(size: u64) -> usize {
+    (BLOCK_SIZE - (size % BLOCK_SIZE as u64) as usize) % BLOCK_SIZE
+}
+
+/// The header blocks of a regular file. Names that don't fit ustar's
+/// name and prefix fields, and sizes over 8 GiB, go in a pax header first.
+pub fn header(name: &str, size: u64, modified: i64) -> Vec<u8> {
+    let split = split_name(name);
+    let mut records = String::new();
+    if split.is_none() {
+        records.push_str(&pax_record("path", name));
+    }
+    if size > MAX_USTAR_SIZE {
+        records.push_str(&pax_record("size", &size.to_string()));
+    }
+
+    let mut blocks = Vec::with_capacity(3 * BLOCK_SIZE);
+    if !records.is_empty() {
+        let pax_name = format!("PaxHeaders/{}", truncate(name, 89));
+        blocks.extend(ustar_block(
+            "",
+            &pax_name,
+            records.len() as u64,
+            modified,
+            b'x',
+        ));
+        blocks.extend(records.as_bytes());
+        blocks.resize(blocks.len() + padding(records.len() as u64), 0);
+    }
+    let (prefix, base) = split.unwrap_or(("", truncate(name, 100)));
+    blocks.extend(ustar_block(
+        prefix,
+        base,
+        size.min(MAX_USTAR_SIZE),
+        modified,
+        b'0',
+    ));
+    blocks
+}
+
+fn ustar_block(prefix: &str, name: &str, size: u64, modified: i64, kind: u8) -> [u8; BLOCK_SIZE] {
+    let mut block = [0; BLOCK_SIZE];
+    block[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut block[100..108], 0o644);
+    octal(&mut block[108..116], 0);
+    octal(&mut block[116..124], 0);
+    octal(&mut block[124..136], size);
+    octal(&mut block[136..148], modified.max(0) as u64);
+    block[156] = kind;
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    block[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    // The checksum is summed with its own field read as spaces.
+    block[148..156].fill(b' ');
+    let checksum: u32 = block.iter().map(|&b| u32::from(b)).sum();
+    octal(&mut block[148..155], u64::from(checksum));
+    block
+}
+
+/// Zero-padded octal, ending with a NUL.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{value:0width$o}", width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[field.len() - 1] = 0;
+}
+
+/// `name` as ustar's prefix and name fields, split at a slash.
+fn split_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= 100 {
+        return Some(("", name));
+    }
+    name.match_indices('/')
+        .map(|(i, _)| (&name[..i], &name[i + 1..]))
+        .find(|(prefix, base)| prefix.len() <= 155 && !base.is_empty() && base.len() <= 100)
+}
+
+/// The longest start of `name` that fits in `max` bytes.
+fn truncate(name: &str, max: usize) -> &str {
+    let mut end = name.len().min(max);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    &name[..end]
+}
+
+/// `"<length> <key>=<value>\n"`, the length counting its own digits.
+fn pax_record(key: &str, value: &str) -> String {
+    let rest = key.len() + value.len() + 3;
+    let mut length = rest + 1;
+    while length != rest + length.to_string().len() {
+        length = rest + length.to_string().len();
+    }
+    format!("{length} {key}={value}\n")
+}
@@ -0,0 +1,24 @@
+use loco_rs::prelude::*;
+
+use crate::workers::abort_orphaned_uploads::{
+    AbortOrphanedUploadsArgs, AbortOrphanedUploadsWorker,
+};
+
+/// `cargo loco task abort_orphaned_uploads` queues a sweep of orphaned
+/// multipart uploads. `config/*.yaml` schedules it under `scheduler`.
+pub struct AbortOrphanedUploads;
+
+#[async_trait]
+impl Task for AbortOrphanedUploads {
+    fn task(&self) -> TaskInfo {
+        TaskInfo {
+            name: "abort_orphaned_uploads".to_string(),
+            detail: "Abort multipart uploads older than multipart_max_age_hours".to_string(),
+        }
+    }
+
+    async fn run(&self, ctx: &AppContext, _vars: &task::Vars) -> Result<()> {
+        AbortOrphanedUploadsWorker::perform_later(ctx, AbortOrphanedUploadsArgs {}).await?;
+        Ok(())
+    }
+}
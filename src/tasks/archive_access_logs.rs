@@ -0,0 +1,24 @@
+use loco_rs::prelude::*;
+
+use crate::controllers::files;
+
+/// `cargo loco task archive_access_logs` moves rotated access logs to the
+/// bucket. `config/*.yaml` schedules it under `scheduler`.
+pub struct ArchiveAccessLogs;
+
+#[async_trait]
+impl Task for ArchiveAccessLogs {
+    fn task(&self) -> TaskInfo {
+        TaskInfo {
+            name: "archive_access_logs".to_string(),
+            detail: "Upload rotated access log files to the bucket and remove them locally"
+                .to_string(),
+        }
+    }
+
+    async fn run(&self, ctx: &AppContext, _vars: &task::Vars) -> Result<()> {
+        let archived = files::archive_access_logs(ctx).await?;
+        println!("Archived {archived} access log files");
+        Ok(())
+    }
+}
@@ -0,0 +1,7 @@
+pub mod abort_orphaned_uploads;
+pub mod archive_access_logs;
+pub mod purge_audit_log;
+pub mod reconcile_replica;
+pub mod reconcile_storage_usage;
+pub mod reindex_files;
+pub mod verify_files;
@@ -0,0 +1,23 @@
+use loco_rs::prelude::*;
+
+use crate::controllers::files;
+
+/// `cargo loco task purge_audit_log` enforces `audit_retention_days`.
+/// `config/*.yaml` schedules it under `scheduler`.
+pub struct PurgeAuditLog;
+
+#[async_trait]
+impl Task for PurgeAuditLog {
+    fn task(&self) -> TaskInfo {
+        TaskInfo {
+            name: "purge_audit_log".to_string(),
+            detail: "Remove audit log entries older than the retention period".to_string(),
+        }
+    }
+
+    async fn run(&self, ctx: &AppContext, _vars: &task::Vars) -> Result<()> {
+        let deleted = files::purge_audit_log(ctx).await?;
+        println!("Removed {deleted} audit log entries");
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use loco_rs::prelude::*;
+
+use crate::controllers::files;
+
+/// `cargo loco task reconcile_replica` copies to the replica bucket whatever
+/// the upload queue missed. `config/*.yaml` schedules it under `scheduler`.
+pub struct ReconcileReplica;
+
+#[async_trait]
+impl Task for ReconcileReplica {
+    fn task(&self) -> TaskInfo {
+        TaskInfo {
+            name: "reconcile_replica".to_string(),
+            detail: "Diff the replica bucket against the default one and copy what differs"
+                .to_string(),
+        }
+    }
+
+    async fn run(&self, ctx: &AppContext, _vars: &task::Vars) -> Result<()> {
+        let report = files::reconcile_replica(ctx).await?;
+        println!(
+            "Copied {}, deleted {}, {} in sync, {} failed",
+            report.copied, report.deleted, report.in_sync, report.failed
+        );
+        Ok(())
+    }
+}
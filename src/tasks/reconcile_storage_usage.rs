@@ -0,0 +1,23 @@
+use loco_rs::prelude::*;
+
+use crate::controllers::files;
+
+/// `cargo loco task reconcile_storage_usage` recounts every user's storage
+/// use from the objects in the bucket.
+pub struct ReconcileStorageUsage;
+
+#[async_trait]
+impl Task for ReconcileStorageUsage {
+    fn task(&self) -> TaskInfo {
+        TaskInfo {
+            name: "reconcile_storage_usage".to_string(),
+            detail: "Recount per-user storage use from the bucket".to_string(),
+        }
+    }
+
+    async fn run(&self, ctx: &AppContext, _vars: &task::Vars) -> Result<()> {
+        let measured = files::reconcile_storage_usage(ctx).await?;
+        println!("Measured {measured} files");
+        Ok(())
+    }
+}
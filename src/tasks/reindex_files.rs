@@ -0,0 +1,23 @@
+use loco_rs::prelude::*;
+
+use crate::controllers::files;
+
+/// `cargo loco task reindex_files` drops the content index and rebuilds it
+/// from every file in the bucket.
+pub struct ReindexFiles;
+
+#[async_trait]
+impl Task for ReindexFiles {
+    fn task(&self) -> TaskInfo {
+        TaskInfo {
+            name: "reindex_files".to_string(),
+            detail: "Rebuild the full-text content index from the bucket".to_string(),
+        }
+    }
+
+    async fn run(&self, ctx: &AppContext, _vars: &task::Vars) -> Result<()> {
+        let indexed = files::rebuild_content_index(ctx).await?;
+        println!("Indexed {indexed} files");
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+use loco_rs::prelude::*;
+use std::time::Duration;
+
+use crate::controllers::files::{self, OrphanObjects, VerifyOptions};
+
+/// `cargo loco task files:verify` compares the default bucket with the
+/// files index and prints what differs as JSON. `fix:true` resolves it;
+/// `orphan_objects:delete` deletes objects no file owns instead of indexing
+/// them, and `min_age_minutes:<n>` sets how long a change is left alone.
+pub struct VerifyFiles;
+
+#[async_trait]
+impl Task for VerifyFiles {
+    fn task(&self) -> TaskInfo {
+        TaskInfo {
+            name: "files:verify".to_string(),
+            detail:
+                "Report, and with fix:true resolve, drift between the files index and the bucket"
+                    .to_string(),
+        }
+    }
+
+    async fn run(&self, ctx: &AppContext, vars: &task::Vars) -> Result<()> {
+        let mut options = VerifyOptions {
+            fix: vars.cli_arg("fix").is_ok_and(|v| v == "true"),
+            ..Default::default()
+        };
+        if let Ok(action) = vars.cli_arg("orphan_objects") {
+            options.orphan_objects = match action.as_str() {
+                "index" => OrphanObjects::Index,
+                "delete" => OrphanObjects::Delete,
+                _ => return Err(Error::string("orphan_objects must be index or delete")),
+            };
+        }
+        if let Ok(minutes) = vars.cli_arg("min_age_minutes") {
+            let minutes: u64 = minutes
+                .parse()
+                .map_err(|_| Error::string("min_age_minutes must be a number"))?;
+            options.min_age = Duration::from_secs(minutes * 60);
+        }
+
+        let store = files::connect_store(ctx)?;
+        let report = files::verify_storage(ctx, store.as_ref(), &options).await?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+//! WebP thumbnails and resized copies of uploaded images.
+
+use image::{DynamicImage, ImageFormat, ImageReader, Limits, imageops::FilterType};
+use serde::Deserialize;
+use std::io::Cursor;
+use utoipa::ToSchema;
+
+/// Images wider or taller than this are refused before any pixels are decoded.
+const MAX_SOURCE_DIMENSION: u32 = 16_384;
+/// Decoder allocation cap, so a small but hostile file can't exhaust memory.
+const MAX_DECODE_BYTES: u64 = 256 * 1024 * 1024;
+
+/// The `image/*` types the decoder is built for; others get no thumbnail.
+const IMAGE_SUBTYPES: [&str; 3] = ["png", "jpeg", "webp"];
+
+pub fn is_image(file_name: &str) -> bool {
+    mime_guess::from_path(file_name)
+        .first()
+        .is_some_and(|mime| {
+            mime.type_() == mime_guess::mime::IMAGE
+                && IMAGE_SUBTYPES.contains(&mime.subtype().as_str())
+        })
+}
+
+pub fn thumbnail_key(file_name: &str) -> String {
+    format!("thumbnails/{file_name}.webp")
+}
+
+/// Decodes `bytes` and encodes a WebP no larger than `max_dimension` on
+/// either side. Images that already fit are re-encoded at their own size.
+/// Blocks on CPU work.
+pub fn render(bytes: &[u8], max_dimension: u32) -> Result<Vec<u8>, String> {
+    let image = decode(bytes)?;
+
+    let thumbnail = if image.width() > max_dimension || image.height() > max_dimension {
+        image.thumbnail(max_dimension, max_dimension)
+    } else {
+        image
+    };
+
+    encode(&thumbnail, OutputFormat::Webp)
+}
+
+/// Largest width or height `resize` produces.
+pub const MAX_RESIZE_DIMENSION: u32 = 4096;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Scale to fit inside the box, keeping the aspect ratio.
+    #[default]
+    Contain,
+    /// Scale to cover the box, cropping the overflow.
+    Cover,
+    /// Stretch to exactly the box.
+    Fill,
+}
+
+impl Fit {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Contain => "contain",
+            Self::Cover => "cover",
+            Self::Fill => "fill",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Webp,
+    Jpeg,
+    Png,
+}
+
+impl OutputFormat {
+    /// The output format matching an image file's extension.
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        let (_, ext) = file_name.rsplit_once('.')?;
+        match ext.to_ascii_lowercase().as_str() {
+            "webp" => Some(Self::Webp),
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "png" => Some(Self::Png),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Webp => "webp",
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Webp => "image/webp",
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+        }
+    }
+}
+
+/// Resizes `bytes` into a `width` x `height` box, both already capped at
+/// `MAX_RESIZE_DIMENSION`. Blocks on CPU work.
+pub fn resize(
+    bytes: &[u8],
+    width: u32,
+    height: u32,
+    fit: Fit,
+    format: OutputFormat,
+) -> Result<Vec<u8>, String> {
+    let image = decode(bytes)?;
+    let resized = match fit {
+        Fit::Contain => image.resize(width, height, FilterType::Lanczos3),
+        Fit::Cover => image.resize_to_fill(width, height, FilterType::Lanczos3),
+        Fit::Fill => image.resize_exact(width, height, FilterType::Lanczos3),
+    };
+    encode(&resized, format)
+}
+
+fn decode(bytes: &[u8]) -> Result<DynamicImage, String> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+    limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_BYTES);
+
+    let mut reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| format!("Unreadable image: {e}"))?;
+    reader.limits(limits);
+    reader
+        .decode()
+        .map_err(|e| format!("Undecodable image: {e}"))
+}
+
+fn encode(image: &DynamicImage, format: OutputFormat) -> Result<Vec<u8>, String> {
+    // The WebP encoder only takes 8-bit RGB(A) and JPEG has no alpha channel.
+    let (image, format) = match format {
+        OutputFormat::Webp => (
+            DynamicImage::ImageRgba8(image.to_rgba8()),
+            ImageFormat::WebP,
+        ),
+        OutputFormat::Jpeg => (DynamicImage::ImageRgb8(image.to_rgb8()), ImageFormat::Jpeg),
+        OutputFormat::Png => (image.clone(), ImageFormat::Png),
+    };
+    let mut encoded = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut encoded), format)
+        .map_err(|e| format!("Image encoding failed: {e}"))?;
+    Ok(encoded)
+}
@@ -0,0 +1,284 @@
+//! Bounds every storage call by `operation_timeout_seconds`, so a stalled
+//! backend fails the request instead of holding it open. Download bodies
+//! are bounded by `download_idle_timeout_seconds` between chunks instead,
+//! so a large file that keeps arriving is never cut off.
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures_util::{StreamExt, stream::BoxStream};
+use object_store::{
+    GetOptions, GetResult, GetResultPayload, ListResult, MultipartId, MultipartUpload, ObjectMeta,
+    ObjectStore, PutMultipartOpts, PutOptions, PutPayload, PutResult, Result, UploadPart,
+    multipart::{MultipartStore, PartId},
+    path::Path,
+};
+use std::{fmt, future::Future, ops::Range, time::Duration};
+
+/// What a timed out call fails with, as the source of an `object_store::Error::Generic`.
+#[derive(Debug, thiserror::Error)]
+#[error("{operation} did not finish within {timeout:?}")]
+pub struct StorageTimeout {
+    pub operation: &'static str,
+    pub timeout: Duration,
+}
+
+/// True if `error` came from a call that ran out of time.
+pub fn is_timeout(error: &object_store::Error) -> bool {
+    matches!(error, object_store::Error::Generic { source, .. } if source.is::<StorageTimeout>())
+}
+
+fn timed_out(operation: &'static str, timeout: Duration) -> object_store::Error {
+    object_store::Error::Generic {
+        store: "Timeout",
+        source: Box::new(StorageTimeout { operation, timeout }),
+    }
+}
+
+async fn within<T>(
+    timeout: Duration,
+    operation: &'static str,
+    call: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::time::timeout(timeout, call)
+        .await
+        .unwrap_or_else(|_| Err(timed_out(operation, timeout)))
+}
+
+/// Ends `stream` with an error once its next item takes longer than `timeout`.
+/// Slow but steady downloads keep going; only a stall is cut off.
+fn idle_timeout<'a, T: Send + 'a>(
+    stream: BoxStream<'a, Result<T>>,
+    timeout: Duration,
+    operation: &'static str,
+) -> BoxStream<'a, Result<T>> {
+    futures_util::stream::unfold(Some(stream), move |state| async move {
+        let mut stream = state?;
+        match tokio::time::timeout(timeout, stream.next()).await {
+            Ok(Some(item)) => Some((item, Some(stream))),
+            Ok(None) => None,
+            Err(_) => Some((Err(timed_out(operation, timeout)), None)),
+        }
+    })
+    .boxed()
+}
+
+#[derive(Debug)]
+pub struct TimeoutStore<T> {
+    inner: T,
+    timeout: Duration,
+    idle_timeout: Duration,
+}
+
+impl<T> TimeoutStore<T> {
+    /// Download bodies may also go `timeout` without a chunk, unless
+    /// `with_idle_timeout` says otherwise.
+    pub fn new(inner: T, timeout: Duration) -> Self {
+        Self {
+            inner,
+            timeout,
+            idle_timeout: timeout,
+        }
+    }
+
+    pub fn with_idle_timeout(self, idle_timeout: Duration) -> Self {
+        Self {
+            idle_timeout,
+            ..self
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for TimeoutStore<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+#[async_trait]
+impl<T: ObjectStore> ObjectStore for TimeoutStore<T> {
+    async fn put_opts(
+        &self,
+        location: &Path,
+        payload: PutPayload,
+        opts: PutOptions,
+    ) -> Result<PutResult> {
+        within(
+            self.timeout,
+            "put",
+            self.inner.put_opts(location, payload, opts),
+        )
+        .await
+    }
+
+    async fn put_multipart_opts(
+        &self,
+        location: &Path,
+        opts: PutMultipartOpts,
+    ) -> Result<Box<dyn MultipartUpload>> {
+        let upload = within(
+            self.timeout,
+            "put_multipart",
+            self.inner.put_multipart_opts(location, opts),
+        )
+        .await?;
+        Ok(Box::new(TimeoutUpload {
+            inner: upload,
+            timeout: self.timeout,
+        }))
+    }
+
+    /// The timeout covers the response headers; the idle timeout then
+    /// covers each chunk of the body.
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        let result = within(self.timeout, "get", self.inner.get_opts(location, options)).await?;
+        let payload = match result.payload {
+            GetResultPayload::Stream(stream) => {
+                GetResultPayload::Stream(idle_timeout(stream, self.idle_timeout, "get"))
+            }
+            file => file,
+        };
+        Ok(GetResult { payload, ..result })
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        within(self.timeout, "get", self.inner.get_range(location, range)).await
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        within(self.timeout, "get", self.inner.get_ranges(location, ranges)).await
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        within(self.timeout, "head", self.inner.head(location)).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        within(self.timeout, "delete", self.inner.delete(location)).await
+    }
+
+    fn delete_stream<'a>(
+        &'a self,
+        locations: BoxStream<'a, Result<Path>>,
+    ) -> BoxStream<'a, Result<Path>> {
+        idle_timeout(self.inner.delete_stream(locations), self.timeout, "delete")
+    }
+
+    /// Each page of the listing gets the full timeout.
+    fn list(&self, prefix: Option<&Path>) -> BoxStream<'_, Result<ObjectMeta>> {
+        idle_timeout(self.inner.list(prefix), self.timeout, "list")
+    }
+
+    fn list_with_offset(
+        &self,
+        prefix: Option<&Path>,
+        offset: &Path,
+    ) -> BoxStream<'_, Result<ObjectMeta>> {
+        idle_timeout(
+            self.inner.list_with_offset(prefix, offset),
+            self.timeout,
+            "list",
+        )
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        within(self.timeout, "list", self.inner.list_with_delimiter(prefix)).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        within(self.timeout, "copy", self.inner.copy(from, to)).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        within(self.timeout, "rename", self.inner.rename(from, to)).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        within(
+            self.timeout,
+            "copy",
+            self.inner.copy_if_not_exists(from, to),
+        )
+        .await
+    }
+
+    async fn rename_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        within(
+            self.timeout,
+            "rename",
+            self.inner.rename_if_not_exists(from, to),
+        )
+        .await
+    }
+}
+
+#[async_trait]
+impl<T: MultipartStore> MultipartStore for TimeoutStore<T> {
+    async fn create_multipart(&self, path: &Path) -> Result<MultipartId> {
+        within(
+            self.timeout,
+            "create_multipart",
+            self.inner.create_multipart(path),
+        )
+        .await
+    }
+
+    async fn put_part(
+        &self,
+        path: &Path,
+        id: &MultipartId,
+        part_idx: usize,
+        data: PutPayload,
+    ) -> Result<PartId> {
+        within(
+            self.timeout,
+            "put_part",
+            self.inner.put_part(path, id, part_idx, data),
+        )
+        .await
+    }
+
+    async fn complete_multipart(
+        &self,
+        path: &Path,
+        id: &MultipartId,
+        parts: Vec<PartId>,
+    ) -> Result<PutResult> {
+        within(
+            self.timeout,
+            "complete_multipart",
+            self.inner.complete_multipart(path, id, parts),
+        )
+        .await
+    }
+
+    async fn abort_multipart(&self, path: &Path, id: &MultipartId) -> Result<()> {
+        within(
+            self.timeout,
+            "abort_multipart",
+            self.inner.abort_multipart(path, id),
+        )
+        .await
+    }
+}
+
+/// Gives each part, and the completion, the full timeout.
+#[derive(Debug)]
+struct TimeoutUpload {
+    inner: Box<dyn MultipartUpload>,
+    timeout: Duration,
+}
+
+#[async_trait]
+impl MultipartUpload for TimeoutUpload {
+    fn put_part(&mut self, data: PutPayload) -> UploadPart {
+        Box::pin(within(self.timeout, "put_part", self.inner.put_part(data)))
+    }
+
+    async fn complete(&mut self) -> Result<PutResult> {
+        within(self.timeout, "complete_multipart", self.inner.complete()).await
+    }
+
+    async fn abort(&mut self) -> Result<()> {
+        within(self.timeout, "abort_multipart", self.inner.abort()).await
+    }
+}
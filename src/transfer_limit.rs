@@ -0,0 +1,91 @@
+//! Caps how many uploads or downloads run at once, so a burst of large
+//! transfers can't take the whole link to storage, and the rest get a
+//! fair answer instead of a crawl.
+
+use serde::Deserialize;
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// What a transfer over the limit does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WhenBusy {
+    /// Waits for a slot, up to the limit's `max_wait`.
+    #[default]
+    Queue,
+    /// Is refused at once.
+    Reject,
+}
+
+#[derive(Debug)]
+pub struct TransferLimit {
+    slots: Arc<Semaphore>,
+    size: usize,
+    when_busy: WhenBusy,
+    max_wait: Duration,
+    queued: AtomicUsize,
+}
+
+/// No slot came free in time.
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("too many transfers are running")]
+pub struct Busy;
+
+/// Counts a transfer as queued until it is dropped, however it ends.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl TransferLimit {
+    /// Zero `size` lifts the limit; transfers are still counted.
+    pub fn new(size: usize, when_busy: WhenBusy, max_wait: Duration) -> Self {
+        let size = if size == 0 {
+            Semaphore::MAX_PERMITS
+        } else {
+            size
+        };
+        Self {
+            slots: Arc::new(Semaphore::new(size)),
+            size,
+            when_busy,
+            max_wait,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// A slot, held until the permit is dropped.
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit, Busy> {
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+        if self.when_busy == WhenBusy::Reject {
+            return Err(Busy);
+        }
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        let _waiting = Waiting(&self.queued);
+        match tokio::time::timeout(self.max_wait, self.slots.clone().acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(permit),
+            // The semaphore is never closed.
+            Ok(Err(_)) | Err(_) => Err(Busy),
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.size - self.slots.available_permits()
+    }
+
+    /// Transfers waiting for a slot.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,317 @@
+//! An uploaded file's way to storage as a series of steps: checks first,
+//! then whatever reworks its bytes, then the write. Each step takes the
+//! upload a stage further, refuses it, or finishes it early, as finding
+//! the same content already stored does.
+
+use async_compression::tokio::write::GzipEncoder;
+use async_trait::async_trait;
+use axum::{body::Bytes, http::StatusCode};
+use loco_rs::{controller::ErrorDetail, prelude::*};
+use md5::Md5;
+use object_store::{Attribute, Attributes};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    controllers::files::{FileInfo, FileMetadata, StoredFile, safe_path_segments},
+    storage_hooks::StorageHooks,
+};
+
+/// One file on its way through an `UploadPipeline`.
+pub struct UploadContext {
+    pub file_name: String,
+    /// As the client sent them.
+    pub bytes: Bytes,
+    pub metadata: Option<FileMetadata>,
+    /// What the stored object is written with, gathered along the way.
+    pub attributes: Attributes,
+    /// SHA-256 of `bytes`, from `hash_content`.
+    pub content_hash: Option<String>,
+    /// Digests the client sent that `bytes` matched, hex by algorithm.
+    pub checksums: BTreeMap<String, String>,
+    /// `bytes` gzipped, when `CompressStep` found them worth it. Storage
+    /// only writes this where a file can be served from its gzip.
+    pub gzipped: Option<Bytes>,
+    /// Set once the upload needs no further steps.
+    pub(crate) finished: Option<Finished>,
+}
+
+pub(crate) enum Finished {
+    Stored(Vec<StoredFile>),
+    /// The same content was already stored as this file.
+    Deduplicated(Box<FileInfo>),
+}
+
+impl UploadContext {
+    pub fn new(file_name: impl Into<String>, bytes: Bytes, metadata: Option<FileMetadata>) -> Self {
+        let attributes = metadata
+            .as_ref()
+            .map(FileMetadata::to_attributes)
+            .unwrap_or_default();
+        Self {
+            file_name: file_name.into(),
+            bytes,
+            metadata,
+            attributes,
+            content_hash: None,
+            checksums: BTreeMap::new(),
+            gzipped: None,
+            finished: None,
+        }
+    }
+
+    /// SHA-256 of the content, worked out the first time it is asked for and
+    /// stored with the object.
+    pub fn hash_content(&mut self) -> String {
+        if let Some(hash) = &self.content_hash {
+            return hash.clone();
+        }
+        let hash = hex::encode(Sha256::digest(&self.bytes));
+        self.attributes
+            .insert(Attribute::Metadata("sha256".into()), hash.clone().into());
+        self.content_hash = Some(hash.clone());
+        hash
+    }
+
+    /// Stores `bytes` instead of what the client sent. Digests taken of
+    /// the old content are dropped; they no longer describe the object.
+    pub fn replace_bytes(&mut self, bytes: Bytes) {
+        self.bytes = bytes;
+        self.content_hash = None;
+        self.attributes
+            .remove(&Attribute::Metadata("sha256".into()));
+        for name in std::mem::take(&mut self.checksums).into_keys() {
+            self.attributes.remove(&Attribute::Metadata(name.into()));
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.is_some()
+    }
+}
+
+#[derive(Debug)]
+pub enum StepError {
+    /// This file is refused; the rest of the request carries on.
+    File(Error),
+    /// The request as a whole fails.
+    Request(Error),
+}
+
+impl StepError {
+    pub fn into_inner(self) -> Error {
+        match self {
+            Self::File(e) | Self::Request(e) => e,
+        }
+    }
+}
+
+#[async_trait]
+pub trait UploadStep: Send + Sync {
+    async fn process(&self, upload: UploadContext) -> Result<UploadContext, StepError>;
+}
+
+#[derive(Default)]
+pub struct UploadPipeline<'a> {
+    steps: Vec<Box<dyn UploadStep + 'a>>,
+}
+
+impl<'a> UploadPipeline<'a> {
+    pub fn new() -> Self {
+        Self { steps: Vec::new() }
+    }
+
+    #[must_use]
+    pub fn step(mut self, step: impl UploadStep + 'a) -> Self {
+        self.steps.push(Box::new(step));
+        self
+    }
+
+    /// Runs the steps in order, until one fails or finishes the upload.
+    pub async fn run(&self, mut upload: UploadContext) -> Result<UploadContext, StepError> {
+        for step in &self.steps {
+            if upload.is_finished() {
+                break;
+            }
+            upload = step.process(upload).await?;
+        }
+        Ok(upload)
+    }
+}
+
+/// Refuses names that escape their folder and files over `max_size`.
+pub struct SanitizeStep {
+    pub max_size: u64,
+}
+
+#[async_trait]
+impl UploadStep for SanitizeStep {
+    async fn process(&self, upload: UploadContext) -> Result<UploadContext, StepError> {
+        if safe_path_segments(&upload.file_name).is_none() {
+            return Err(StepError::File(Error::BadRequest(
+                "Invalid file name".into(),
+            )));
+        }
+        if upload.bytes.len() as u64 > self.max_size {
+            return Err(StepError::File(Error::BadRequest(format!(
+                "File exceeds the {} byte limit",
+                self.max_size
+            ))));
+        }
+        Ok(upload)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    Md5,
+    Sha256,
+    Crc32,
+}
+
+impl DigestAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Md5 => "md5",
+            Self::Sha256 => "sha256",
+            Self::Crc32 => "crc32",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Md5 => "MD5",
+            Self::Sha256 => "SHA-256",
+            Self::Crc32 => "CRC32",
+        }
+    }
+
+    /// Lowercase hex, CRC32 big-endian as S3 sends it.
+    pub fn digest(self, bytes: &[u8]) -> String {
+        match self {
+            Self::Md5 => hex::encode(Md5::digest(bytes)),
+            Self::Sha256 => hex::encode(Sha256::digest(bytes)),
+            Self::Crc32 => hex::encode(crc32fast::hash(bytes).to_be_bytes()),
+        }
+    }
+}
+
+/// A digest of the content the client sent along with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientDigest {
+    pub algorithm: DigestAlgorithm,
+    /// Lowercase hex.
+    pub expected: String,
+}
+
+impl ClientDigest {
+    /// A 400 `checksum_mismatch` unless `actual` is the digest expected of
+    /// `subject`.
+    pub fn verify(&self, subject: &str, actual: &str) -> Result<()> {
+        if actual == self.expected {
+            return Ok(());
+        }
+        Err(Error::CustomError(
+            StatusCode::BAD_REQUEST,
+            ErrorDetail {
+                error: Some("checksum_mismatch".into()),
+                description: Some(format!(
+                    "{} of {subject} does not match",
+                    self.algorithm.label()
+                )),
+                errors: Some(serde_json::json!({
+                    "algorithm": self.algorithm.name(),
+                    "expected": self.expected,
+                    "actual": actual,
+                })),
+            },
+        ))
+    }
+}
+
+/// Hashes the content, and fails the request when it isn't what the
+/// client said it sent. Runs before anything is written, so a corrupt
+/// upload leaves no object behind. Digests that match are recorded with
+/// the object.
+pub struct ChecksumStep {
+    pub expected: Vec<ClientDigest>,
+}
+
+#[async_trait]
+impl UploadStep for ChecksumStep {
+    async fn process(&self, mut upload: UploadContext) -> Result<UploadContext, StepError> {
+        let content_hash = upload.hash_content();
+        for digest in &self.expected {
+            let actual = match digest.algorithm {
+                DigestAlgorithm::Sha256 => content_hash.clone(),
+                algorithm => algorithm.digest(&upload.bytes),
+            };
+            digest
+                .verify(&format!("'{}'", upload.file_name), &actual)
+                .map_err(StepError::Request)?;
+            let name = digest.algorithm.name();
+            upload
+                .attributes
+                .insert(Attribute::Metadata(name.into()), actual.clone().into());
+            upload.checksums.insert(name.to_string(), actual);
+        }
+        Ok(upload)
+    }
+}
+
+/// Lets `StorageHooks::pre_upload` rework the content, or refuse the file.
+pub struct HookStep {
+    pub hooks: Arc<dyn StorageHooks>,
+}
+
+#[async_trait]
+impl UploadStep for HookStep {
+    async fn process(&self, mut upload: UploadContext) -> Result<UploadContext, StepError> {
+        let bytes = self
+            .hooks
+            .pre_upload(&upload.file_name, &upload.bytes)
+            .await
+            .map_err(StepError::File)?;
+        if bytes != upload.bytes {
+            upload.replace_bytes(bytes);
+        }
+        Ok(upload)
+    }
+}
+
+/// Gzips text-like files, when `enabled`.
+pub struct CompressStep {
+    pub enabled: bool,
+}
+
+#[async_trait]
+impl UploadStep for CompressStep {
+    async fn process(&self, mut upload: UploadContext) -> Result<UploadContext, StepError> {
+        if self.enabled && is_text_like(&upload.file_name) {
+            upload.gzipped = Some(gzip(&upload.bytes).await.map_err(StepError::File)?);
+        }
+        Ok(upload)
+    }
+}
+
+/// Text-like types, which typically shrink several times under gzip.
+pub(crate) fn is_text_like(file_name: &str) -> bool {
+    let mime = mime_guess::from_path(file_name).first_or_octet_stream();
+    mime.type_() == mime_guess::mime::TEXT
+        || matches!(
+            mime.essence_str(),
+            "application/json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/x-yaml"
+                | "image/svg+xml"
+        )
+}
+
+async fn gzip(bytes: &[u8]) -> Result<Bytes> {
+    let mut encoder = GzipEncoder::new(Vec::new());
+    encoder.write_all(bytes).await?;
+    encoder.shutdown().await?;
+    Ok(encoder.into_inner().into())
+}
@@ -0,0 +1,125 @@
+//! Webhooks fired after file events, signed with HMAC-SHA256 so receivers can
+//! tell them from forgeries.
+
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::time::Duration;
+
+/// `sha256=<hex HMAC of the body>`, keyed by the target's secret.
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+/// Longest wait between two delivery attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileEvent {
+    #[serde(rename = "file.uploaded")]
+    Uploaded,
+    #[serde(rename = "file.deleted")]
+    Deleted,
+    /// Sent by `POST /files/webhooks/test`.
+    #[serde(rename = "webhook.test")]
+    Test,
+}
+
+impl FileEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Uploaded => "file.uploaded",
+            Self::Deleted => "file.deleted",
+            Self::Test => "webhook.test",
+        }
+    }
+}
+
+/// One receiver, under `settings.webhooks.targets`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookTarget {
+    pub url: String,
+    pub secret: String,
+    /// Event names to send, such as `file.deleted`. Empty sends every event.
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+impl WebhookTarget {
+    /// Test events go to every target, whatever its filter.
+    pub fn wants(&self, event: FileEvent) -> bool {
+        event == FileEvent::Test
+            || self.events.is_empty()
+            || self.events.iter().any(|e| e == event.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    pub event: FileEvent,
+    pub file_name: String,
+    pub size: i64,
+    /// SHA-256 of the content, when the index knows it.
+    pub checksum: Option<String>,
+    /// RFC 3339, when the event happened.
+    pub timestamp: String,
+}
+
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Wait before retrying after `attempt` failed ones: `base`, doubled each time.
+pub fn backoff(base: Duration, attempt: u32) -> Duration {
+    base.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+/// A single POST of `payload`; any non-2xx answer counts as a failure.
+pub async fn deliver(
+    client: &Client,
+    target: &WebhookTarget,
+    payload: &WebhookPayload,
+) -> Result<(), String> {
+    let body = serde_json::to_vec(payload).map_err(|e| e.to_string())?;
+    let response = client
+        .post(&target.url)
+        .header("Content-Type", "application/json")
+        .header(EVENT_HEADER, payload.event.as_str())
+        .header(SIGNATURE_HEADER, sign(&target.secret, &body))
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| format!("Request to {} failed: {e}", target.url))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("{} answered {status}", target.url));
+    }
+    Ok(())
+}
+
+/// Delivers `payload` with up to `max_attempts` tries, backing off between
+/// them. Returns the last error once every attempt has failed.
+pub async fn deliver_with_retries(
+    client: &Client,
+    target: &WebhookTarget,
+    payload: &WebhookPayload,
+    max_attempts: u32,
+    base_delay: Duration,
+) -> Result<(), String> {
+    let mut attempt = 1;
+    loop {
+        match deliver(client, target, payload).await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= max_attempts => return Err(e),
+            Err(e) => {
+                let delay = backoff(base_delay, attempt);
+                tracing::warn!(url = %target.url, attempt, error = %e, ?delay, "webhook delivery failed, retrying");
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use loco_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::controllers::files;
+
+/// Aborts the multipart uploads S3 holds for uploads that will never finish.
+/// Enqueued by the `abort_orphaned_uploads` task, on the scheduler's clock.
+pub struct AbortOrphanedUploadsWorker {
+    pub ctx: AppContext,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AbortOrphanedUploadsArgs {}
+
+#[async_trait]
+impl BackgroundWorker<AbortOrphanedUploadsArgs> for AbortOrphanedUploadsWorker {
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+
+    async fn perform(&self, _args: AbortOrphanedUploadsArgs) -> Result<()> {
+        let sweep = files::abort_orphaned_uploads(&self.ctx).await?;
+        tracing::info!(
+            aborted = sweep.aborted,
+            total_bytes_freed = sweep.total_bytes_freed,
+            "orphaned multipart uploads swept"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+use loco_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{controllers::files, webhooks::WebhookPayload};
+
+/// Sends one file event to one webhook target, retrying with backoff.
+pub struct DeliverWebhookWorker {
+    pub ctx: AppContext,
+}
+
+/// The secret stays in the settings; the target is looked up by `url`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeliverWebhookArgs {
+    pub url: String,
+    pub payload: WebhookPayload,
+}
+
+#[async_trait]
+impl BackgroundWorker<DeliverWebhookArgs> for DeliverWebhookWorker {
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+
+    async fn perform(&self, args: DeliverWebhookArgs) -> Result<()> {
+        files::deliver_webhook(&self.ctx, &args.url, &args.payload).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,24 @@
+use loco_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::controllers::files;
+
+/// Aborts resumable uploads that stopped making progress.
+pub struct ExpireTusUploadsWorker {
+    pub ctx: AppContext,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExpireTusUploadsArgs {}
+
+#[async_trait]
+impl BackgroundWorker<ExpireTusUploadsArgs> for ExpireTusUploadsWorker {
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+
+    async fn perform(&self, _args: ExpireTusUploadsArgs) -> Result<()> {
+        files::expire_tus_uploads(&self.ctx).await?;
+        Ok(())
+    }
+}
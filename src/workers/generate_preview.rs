@@ -0,0 +1,25 @@
+use loco_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::controllers::files;
+
+/// Renders the first-page preview of one uploaded PDF.
+pub struct GeneratePreviewWorker {
+    pub ctx: AppContext,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GeneratePreviewArgs {
+    pub name: String,
+}
+
+#[async_trait]
+impl BackgroundWorker<GeneratePreviewArgs> for GeneratePreviewWorker {
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+
+    async fn perform(&self, args: GeneratePreviewArgs) -> Result<()> {
+        files::generate_preview(&self.ctx, &args.name).await
+    }
+}
@@ -0,0 +1,25 @@
+use loco_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::controllers::files;
+
+/// Renders the thumbnail of one uploaded image.
+pub struct GenerateThumbnailWorker {
+    pub ctx: AppContext,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GenerateThumbnailArgs {
+    pub name: String,
+}
+
+#[async_trait]
+impl BackgroundWorker<GenerateThumbnailArgs> for GenerateThumbnailWorker {
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+
+    async fn perform(&self, args: GenerateThumbnailArgs) -> Result<()> {
+        files::generate_thumbnail(&self.ctx, &args.name).await
+    }
+}
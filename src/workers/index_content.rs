@@ -0,0 +1,25 @@
+use loco_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::controllers::files;
+
+/// Brings the content index entry of one file in line with what is stored.
+pub struct IndexContentWorker {
+    pub ctx: AppContext,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct IndexContentArgs {
+    pub name: String,
+}
+
+#[async_trait]
+impl BackgroundWorker<IndexContentArgs> for IndexContentWorker {
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+
+    async fn perform(&self, args: IndexContentArgs) -> Result<()> {
+        files::index_content(&self.ctx, &args.name).await
+    }
+}
@@ -0,0 +1,9 @@
+pub mod abort_orphaned_uploads;
+pub mod deliver_webhook;
+pub mod expire_tus_uploads;
+pub mod generate_preview;
+pub mod generate_thumbnail;
+pub mod index_content;
+pub mod prune_versions;
+pub mod replicate_file;
+pub mod scan_file;
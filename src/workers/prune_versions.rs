@@ -0,0 +1,25 @@
+use loco_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::controllers::files;
+
+/// Applies the `keep_versions` retention to one file after it gains a version.
+pub struct PruneVersionsWorker {
+    pub ctx: AppContext,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PruneVersionsArgs {
+    pub file_id: i32,
+}
+
+#[async_trait]
+impl BackgroundWorker<PruneVersionsArgs> for PruneVersionsWorker {
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+
+    async fn perform(&self, args: PruneVersionsArgs) -> Result<()> {
+        files::prune_versions(&self.ctx, args.file_id).await
+    }
+}
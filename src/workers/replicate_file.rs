@@ -0,0 +1,28 @@
+use loco_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::controllers::files;
+
+/// Copies one upload to the replica bucket, or removes a deleted file's copy.
+pub struct ReplicateFileWorker {
+    pub ctx: AppContext,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ReplicateFileArgs {
+    pub name: String,
+    /// Set once the file is deleted, to the key its content was stored under.
+    #[serde(default)]
+    pub deleted_key: Option<String>,
+}
+
+#[async_trait]
+impl BackgroundWorker<ReplicateFileArgs> for ReplicateFileWorker {
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+
+    async fn perform(&self, args: ReplicateFileArgs) -> Result<()> {
+        files::replicate_file(&self.ctx, &args.name, args.deleted_key.as_deref()).await
+    }
+}
@@ -0,0 +1,25 @@
+use loco_rs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::controllers::files;
+
+/// Virus scans one upload too large to scan inline.
+pub struct ScanFileWorker {
+    pub ctx: AppContext,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ScanFileArgs {
+    pub name: String,
+}
+
+#[async_trait]
+impl BackgroundWorker<ScanFileArgs> for ScanFileWorker {
+    fn build(ctx: &AppContext) -> Self {
+        Self { ctx: ctx.clone() }
+    }
+
+    async fn perform(&self, args: ScanFileArgs) -> Result<()> {
+        files::scan_file(&self.ctx, &args.name).await
+    }
+}
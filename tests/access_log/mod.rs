@@ -0,0 +1,82 @@
+use futures_util::TryStreamExt;
+use object_store::{ObjectStore, memory::InMemory, path::Path as ObjectPath};
+use server::access_log::{self, AccessLogger, AccessRecord};
+use std::path::PathBuf;
+
+fn log_path() -> PathBuf {
+    std::env::temp_dir()
+        .join(format!("access-log-{}", uuid::Uuid::new_v4()))
+        .join("access.jsonl")
+}
+
+fn download(key: &str) -> AccessRecord {
+    AccessRecord {
+        ts: "2026-10-14T09:30:00+00:00".into(),
+        op: "download".into(),
+        key: Some(key.into()),
+        user: Some("7".into()),
+        ip: Some("203.0.113.9".into()),
+    }
+}
+
+fn lines(text: &str) -> Vec<serde_json::Value> {
+    text.lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn records_are_appended_across_reopens() {
+    let path = log_path();
+    AccessLogger::open(&path, 1024)
+        .await
+        .unwrap()
+        .write(&download("a.txt"))
+        .await
+        .unwrap();
+    let logger = AccessLogger::open(&path, 1024).await.unwrap();
+    logger.write(&download("b.txt")).await.unwrap();
+
+    let written = lines(&std::fs::read_to_string(&path).unwrap());
+    assert_eq!(written.len(), 2);
+    assert_eq!(written[1]["key"], "b.txt");
+    assert_eq!(written[1]["op"], "download");
+    assert_eq!(written[1]["user"], "7");
+    assert_eq!(written[1]["ip"], "203.0.113.9");
+    assert!(access_log::rotated_files(&path).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn full_files_are_rotated_and_archived_to_the_bucket() {
+    let path = log_path();
+    let line = serde_json::to_vec(&download("a.txt")).unwrap().len() as u64 + 1;
+    // Room for one line per file.
+    let logger = AccessLogger::open(&path, line + line / 2).await.unwrap();
+    for key in ["a.txt", "b.txt", "c.txt"] {
+        logger.write(&download(key)).await.unwrap();
+    }
+    assert_eq!(access_log::rotated_files(&path).await.unwrap().len(), 2);
+
+    let store = InMemory::new();
+    assert_eq!(access_log::archive(&store, &path).await.unwrap(), 2);
+    assert!(access_log::rotated_files(&path).await.unwrap().is_empty());
+    let prefix = ObjectPath::from(access_log::ARCHIVE_PREFIX);
+    let mut archived: Vec<ObjectPath> = store
+        .list(Some(&prefix))
+        .map_ok(|meta| meta.location)
+        .try_collect()
+        .await
+        .unwrap();
+    archived.sort();
+    let mut keys = Vec::new();
+    for location in &archived {
+        assert!(location.as_ref().ends_with(".jsonl"), "{location}");
+        let bytes = store.get(location).await.unwrap().bytes().await.unwrap();
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+        keys.extend(lines(&text).into_iter().map(|l| l["key"].clone()));
+    }
+    assert_eq!(keys, ["a.txt", "b.txt"]);
+
+    let current = lines(&std::fs::read_to_string(&path).unwrap());
+    assert_eq!(current[0]["key"], "c.txt");
+}
@@ -0,0 +1,191 @@
+use object_store::{ObjectStore, PutPayload, memory::InMemory, path::Path as ObjectPath};
+use server::cache_store::{CacheStore, DownloadCache, MemoryCache};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+fn cache_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("download-cache-{}", uuid::Uuid::new_v4()))
+}
+
+fn cached(
+    backend: &Arc<InMemory>,
+    max_bytes: u64,
+) -> (CacheStore<Arc<dyn ObjectStore>>, Arc<DownloadCache>) {
+    let cache = Arc::new(DownloadCache::open(cache_dir(), max_bytes).unwrap());
+    let store = CacheStore::new(
+        backend.clone() as Arc<dyn ObjectStore>,
+        Some(cache.clone()),
+        "files",
+    );
+    (store, cache)
+}
+
+async fn read(store: &dyn ObjectStore, location: &ObjectPath) -> String {
+    let bytes = store.get(location).await.unwrap().bytes().await.unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn a_second_download_is_served_from_disk() {
+    let backend = Arc::new(InMemory::new());
+    let (store, cache) = cached(&backend, 1024);
+    let location = ObjectPath::from("notes.txt");
+    store.put(&location, "notes".into()).await.unwrap();
+
+    assert_eq!(read(&store, &location).await, "notes");
+    assert_eq!((cache.hits(), cache.misses()), (0, 1));
+    assert_eq!(cache.size_bytes(), 5);
+
+    let result = store.get(&location).await.unwrap();
+    assert_eq!(result.meta.size, 5);
+    assert_eq!(result.bytes().await.unwrap(), "notes");
+    assert_eq!((cache.hits(), cache.misses()), (1, 1));
+}
+
+#[tokio::test]
+async fn a_change_made_behind_the_cache_is_not_served_stale() {
+    let backend = Arc::new(InMemory::new());
+    let (store, cache) = cached(&backend, 1024);
+    let location = ObjectPath::from("notes.txt");
+    store.put(&location, "notes".into()).await.unwrap();
+    read(&store, &location).await;
+
+    backend.put(&location, "summary".into()).await.unwrap();
+    assert_eq!(read(&store, &location).await, "summary");
+    assert_eq!((cache.hits(), cache.misses()), (0, 2));
+    assert_eq!(read(&store, &location).await, "summary");
+    assert_eq!(cache.hits(), 1);
+    assert_eq!(cache.size_bytes(), 7);
+}
+
+#[tokio::test]
+async fn writes_and_deletes_drop_the_entry() {
+    let backend = Arc::new(InMemory::new());
+    let (store, cache) = cached(&backend, 1024);
+    let location = ObjectPath::from("notes.txt");
+    store.put(&location, "notes".into()).await.unwrap();
+    read(&store, &location).await;
+
+    store.put(&location, "summary".into()).await.unwrap();
+    assert_eq!(cache.size_bytes(), 0);
+    assert_eq!(read(&store, &location).await, "summary");
+
+    store.delete(&location).await.unwrap();
+    assert_eq!(cache.size_bytes(), 0);
+    assert!(store.get(&location).await.is_err());
+}
+
+#[tokio::test]
+async fn the_least_recently_served_entries_are_evicted() {
+    let backend = Arc::new(InMemory::new());
+    let (store, cache) = cached(&backend, 10);
+    let (a, b, c) = (
+        ObjectPath::from("a.txt"),
+        ObjectPath::from("b.txt"),
+        ObjectPath::from("c.txt"),
+    );
+    for location in [&a, &b, &c] {
+        store.put(location, PutPayload::from("abcd")).await.unwrap();
+    }
+    read(&store, &a).await;
+    read(&store, &b).await;
+    // Served again, so `b` is now the oldest.
+    read(&store, &a).await;
+    read(&store, &c).await;
+    assert_eq!(cache.size_bytes(), 8);
+
+    let hits = cache.hits();
+    read(&store, &a).await;
+    read(&store, &c).await;
+    assert_eq!(cache.hits(), hits + 2);
+    read(&store, &b).await;
+    assert_eq!(cache.hits(), hits + 2);
+}
+
+#[tokio::test]
+async fn objects_bigger_than_the_cache_and_partial_reads_pass_through() {
+    let backend = Arc::new(InMemory::new());
+    let (store, cache) = cached(&backend, 4);
+    let location = ObjectPath::from("notes.txt");
+    store.put(&location, "notes".into()).await.unwrap();
+
+    read(&store, &location).await;
+    read(&store, &location).await;
+    assert_eq!(cache.hits(), 0);
+    assert_eq!(cache.size_bytes(), 0);
+
+    let small = ObjectPath::from("tag.txt");
+    store.put(&small, "tag".into()).await.unwrap();
+    assert_eq!(store.get_range(&small, 0..2).await.unwrap(), "ta");
+    assert_eq!(cache.size_bytes(), 0);
+}
+
+#[tokio::test]
+async fn files_left_by_an_earlier_run_are_cleared() {
+    let dir = cache_dir();
+    std::fs::create_dir_all(&dir).unwrap();
+    let leftover = dir.join("a".repeat(64));
+    std::fs::write(&leftover, "old").unwrap();
+    std::fs::write(dir.join("README"), "kept").unwrap();
+
+    DownloadCache::open(&dir, 1024).unwrap();
+    assert!(!leftover.exists());
+    assert!(dir.join("README").exists());
+}
+
+fn in_memory(
+    backend: &Arc<InMemory>,
+    ttl: Duration,
+) -> (CacheStore<Arc<dyn ObjectStore>>, Arc<MemoryCache>) {
+    let memory = Arc::new(MemoryCache::new(1024, 8, ttl));
+    let store = CacheStore::new(backend.clone() as Arc<dyn ObjectStore>, None, "files")
+        .with_memory(Some(memory.clone()));
+    (store, memory)
+}
+
+#[tokio::test]
+async fn small_objects_are_served_from_memory_until_they_expire() {
+    let backend = Arc::new(InMemory::new());
+    let (store, memory) = in_memory(&backend, Duration::from_millis(200));
+    let (small, large) = (ObjectPath::from("tag.txt"), ObjectPath::from("notes.txt"));
+    store.put(&small, "tag".into()).await.unwrap();
+    store.put(&large, "summary, too big".into()).await.unwrap();
+
+    read(&store, &small).await;
+    read(&store, &large).await;
+    assert_eq!(memory.entry_count(), 1);
+
+    // Served without asking the backend, which no longer has it.
+    backend.delete(&small).await.unwrap();
+    assert_eq!(read(&store, &small).await, "tag");
+    assert_eq!(memory.hits(), 1);
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(store.get(&small).await.is_err());
+}
+
+#[tokio::test]
+async fn writes_through_the_store_drop_memory_entries() {
+    let backend = Arc::new(InMemory::new());
+    let (store, memory) = in_memory(&backend, Duration::from_secs(60));
+    let location = ObjectPath::from("tag.txt");
+    store.put(&location, "tag".into()).await.unwrap();
+    read(&store, &location).await;
+
+    let mut upload = store.put_multipart(&location).await.unwrap();
+    upload.put_part("new".into()).await.unwrap();
+    assert_eq!(read(&store, &location).await, "tag");
+    upload.complete().await.unwrap();
+    assert_eq!(read(&store, &location).await, "new");
+
+    store
+        .copy(&ObjectPath::from("tag.txt"), &ObjectPath::from("copy.txt"))
+        .await
+        .unwrap();
+    read(&store, &ObjectPath::from("copy.txt")).await;
+    store
+        .rename(&location, &ObjectPath::from("copy.txt"))
+        .await
+        .unwrap();
+    assert!(store.get(&location).await.is_err());
+    assert_eq!(memory.entry_count(), 0);
+}
@@ -0,0 +1,152 @@
+use object_store::{
+    ObjectStore, RetryConfig,
+    aws::{AmazonS3, AmazonS3Builder},
+    path::Path as ObjectPath,
+};
+use server::circuit_store::{self, CircuitPolicy, CircuitState, CircuitStore};
+use std::{sync::Arc, time::Duration};
+use wiremock::{Mock, MockServer, ResponseTemplate, matchers::method};
+
+const COOL_DOWN: Duration = Duration::from_millis(200);
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// S3 at `server` behind a circuit opened by `failure_threshold` failures
+/// within `window`.
+fn guarded_s3(
+    server: &MockServer,
+    failure_threshold: u32,
+    window: Duration,
+) -> CircuitStore<AmazonS3> {
+    let s3 = AmazonS3Builder::new()
+        .with_endpoint(server.uri())
+        .with_allow_http(true)
+        .with_bucket_name("files")
+        .with_region("us-east-1")
+        .with_access_key_id("test")
+        .with_secret_access_key("test")
+        .with_retry(RetryConfig {
+            max_retries: 0,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let circuit = CircuitState::new(CircuitPolicy {
+        failure_threshold,
+        window,
+        cool_down: COOL_DOWN,
+    });
+    CircuitStore::new(s3, Arc::new(circuit))
+}
+
+fn meta_response() -> ResponseTemplate {
+    ResponseTemplate::new(200)
+        .insert_header("Content-Length", "5")
+        .insert_header("ETag", "\"1\"")
+        .insert_header("Last-Modified", "Tue, 13 Oct 2026 00:00:00 GMT")
+}
+
+#[tokio::test]
+async fn repeated_failures_open_the_circuit() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(3)
+        .mount(&server)
+        .await;
+    let store = guarded_s3(&server, 3, WINDOW);
+    let location = ObjectPath::from("report.txt");
+
+    for _ in 0..3 {
+        let error = store.head(&location).await.unwrap_err();
+        assert!(circuit_store::retry_after(&error).is_none(), "{error}");
+    }
+    let error = store.head(&location).await.unwrap_err();
+
+    let wait = circuit_store::retry_after(&error).expect("refused by the circuit");
+    assert!(wait <= COOL_DOWN);
+}
+
+#[tokio::test]
+async fn a_successful_probe_closes_the_circuit() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("HEAD"))
+        .respond_with(meta_response())
+        .mount(&server)
+        .await;
+    let store = guarded_s3(&server, 1, WINDOW);
+    let location = ObjectPath::from("report.txt");
+
+    store.head(&location).await.unwrap_err();
+    assert!(store.head(&location).await.is_err());
+
+    tokio::time::sleep(COOL_DOWN).await;
+    assert_eq!(store.head(&location).await.unwrap().size, 5);
+    assert_eq!(store.head(&location).await.unwrap().size, 5);
+}
+
+#[tokio::test]
+async fn a_failed_probe_opens_the_circuit_again() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2)
+        .mount(&server)
+        .await;
+    let store = guarded_s3(&server, 1, WINDOW);
+    let location = ObjectPath::from("report.txt");
+
+    store.head(&location).await.unwrap_err();
+    tokio::time::sleep(COOL_DOWN).await;
+    let probe = store.head(&location).await.unwrap_err();
+    assert!(circuit_store::retry_after(&probe).is_none(), "{probe}");
+
+    let refused = store.head(&location).await.unwrap_err();
+    assert!(circuit_store::retry_after(&refused).is_some(), "{refused}");
+}
+
+#[tokio::test]
+async fn answers_of_a_healthy_backend_do_not_count() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(3)
+        .mount(&server)
+        .await;
+    let store = guarded_s3(&server, 1, WINDOW);
+
+    for _ in 0..3 {
+        let error = store
+            .head(&ObjectPath::from("missing.txt"))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, object_store::Error::NotFound { .. }));
+    }
+}
+
+#[tokio::test]
+async fn failures_outside_the_window_do_not_add_up() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(4)
+        .mount(&server)
+        .await;
+    let store = guarded_s3(&server, 2, Duration::from_millis(100));
+    let location = ObjectPath::from("report.txt");
+
+    store.head(&location).await.unwrap_err();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    store.head(&location).await.unwrap_err();
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    let error = store.head(&location).await.unwrap_err();
+    assert!(circuit_store::retry_after(&error).is_none(), "{error}");
+
+    store.head(&location).await.unwrap_err();
+    let refused = store.head(&location).await.unwrap_err();
+    assert!(circuit_store::retry_after(&refused).is_some(), "{refused}");
+}
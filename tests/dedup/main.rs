@@ -0,0 +1,192 @@
+//! Uploads with `dedup` on. Storage settings are read once per process, so
+//! these run as a test binary of their own.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{Extension, Router, http::StatusCode};
+use axum_test::{
+    TestServer,
+    multipart::{MultipartForm, Part},
+};
+use loco_rs::{
+    app::{AppContext, Hooks},
+    boot::{BootResult, StartMode},
+    controller::AppRoutes,
+    environment::Environment,
+};
+use object_store::{ObjectStore, memory::InMemory, path::Path as ObjectPath};
+use sea_orm::EntityTrait;
+use serial_test::serial;
+use server::{
+    app::App,
+    controllers::{auth, files},
+    models::{blob, file, role, user},
+};
+use sha2::{Digest, Sha256};
+use tokio::sync::oneshot;
+
+const CONTENT: &str = "shared bytes";
+
+async fn boot_dedup() -> BootResult {
+    let mut config = App::load_config(&Environment::Test).await.unwrap();
+    if let Some(settings) = config.settings.as_mut() {
+        settings["dedup"] = true.into();
+    }
+    // Enough connections for an upload to wait on a release's row lock.
+    config.database.max_connections = 4;
+    App::boot(StartMode::ServerOnly, &Environment::Test, config)
+        .await
+        .unwrap()
+}
+
+fn test_server(ctx: &AppContext, store: Arc<dyn ObjectStore>) -> TestServer {
+    let router: Router = AppRoutes::empty()
+        .add_route(files::routes(ctx))
+        .to_router::<App>(ctx.clone(), Router::new())
+        .unwrap()
+        .layer(Extension(store));
+    TestServer::new(router).unwrap()
+}
+
+async fn bearer_token(ctx: &AppContext) -> String {
+    let role = role::create(&ctx.db, "tester", serde_json::json!(["read"]))
+        .await
+        .unwrap();
+    let author = user::create(&ctx.db, "Tester", "tester", "unused", role.id)
+        .await
+        .unwrap();
+    auth::generate_token(&author.id.to_string(), &author.login, vec!["tester".into()]).unwrap()
+}
+
+async fn upload(server: &TestServer, token: &str, name: &str) {
+    server
+        .post("/files")
+        .authorization_bearer(token)
+        .multipart(MultipartForm::new().add_part(
+            "file",
+            Part::bytes(CONTENT.as_bytes().to_vec()).file_name(name),
+        ))
+        .await
+        .assert_status_ok();
+}
+
+fn content_hash() -> String {
+    hex::encode(Sha256::digest(CONTENT))
+}
+
+async fn object_keys(store: &InMemory) -> Vec<String> {
+    use futures_util::TryStreamExt;
+
+    store
+        .list(None)
+        .map_ok(|meta| meta.location.to_string())
+        .try_collect()
+        .await
+        .unwrap()
+}
+
+async fn ref_count(ctx: &AppContext, hash: &str) -> Option<i32> {
+    blob::Entity::find_by_id(hash.to_string())
+        .one(&ctx.db)
+        .await
+        .unwrap()
+        .map(|b| b.ref_count)
+}
+
+#[tokio::test]
+#[serial]
+async fn a_repeat_upload_only_adds_a_row_for_the_blob() {
+    let boot = boot_dedup().await;
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let store = Arc::new(InMemory::new());
+    let server = test_server(ctx, store.clone());
+    let hash = content_hash();
+
+    upload(&server, &token, "first.txt").await;
+    upload(&server, &token, "second.txt").await;
+
+    assert_eq!(object_keys(&store).await, [format!("blobs/{hash}")]);
+    assert_eq!(ref_count(ctx, &hash).await, Some(2));
+    for name in ["first.txt", "second.txt"] {
+        let row = file::find_by_name(&ctx.db, name).await.unwrap().unwrap();
+        assert_eq!(row.blob_hash.as_deref(), Some(hash.as_str()));
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn the_blob_outlives_all_but_its_last_reference() {
+    let boot = boot_dedup().await;
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let store = Arc::new(InMemory::new());
+    let server = test_server(ctx, store.clone());
+    let hash = content_hash();
+    upload(&server, &token, "first.txt").await;
+    upload(&server, &token, "second.txt").await;
+
+    server
+        .delete("/files/first.txt")
+        .authorization_bearer(&token)
+        .await
+        .assert_status_ok();
+
+    assert_eq!(object_keys(&store).await, [format!("blobs/{hash}")]);
+    assert_eq!(ref_count(ctx, &hash).await, Some(1));
+    let kept = server.get("/files/second.txt").await;
+    kept.assert_status_ok();
+    assert_eq!(kept.text(), CONTENT);
+
+    server
+        .delete("/files/second.txt")
+        .authorization_bearer(&token)
+        .await
+        .assert_status_ok();
+
+    assert!(object_keys(&store).await.is_empty());
+    assert_eq!(ref_count(ctx, &hash).await, None);
+    server
+        .get("/files/second.txt")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+#[serial]
+async fn an_upload_racing_the_last_release_keeps_its_blob() {
+    let boot = boot_dedup().await;
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let store = Arc::new(InMemory::new());
+    let server = test_server(ctx, store.clone());
+    let hash = content_hash();
+    upload(&server, &token, "first.txt").await;
+
+    // Hold the last reference's row lock mid-delete while a second upload of
+    // the same bytes comes in.
+    let (locked, is_locked) = oneshot::channel();
+    let release = tokio::spawn({
+        let db = ctx.db.clone();
+        let store = store.clone();
+        let hash = hash.clone();
+        let path = ObjectPath::from(format!("blobs/{hash}"));
+        async move {
+            blob::release(&db, &hash, || async move {
+                locked.send(()).unwrap();
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                store.delete(&path).await
+            })
+            .await
+        }
+    });
+    is_locked.await.unwrap();
+    upload(&server, &token, "second.txt").await;
+    release.await.unwrap().unwrap();
+
+    assert_eq!(object_keys(&store).await, [format!("blobs/{hash}")]);
+    assert_eq!(ref_count(ctx, &hash).await, Some(1));
+    let response = server.get("/files/second.txt").await;
+    response.assert_status_ok();
+    assert_eq!(response.text(), CONTENT);
+}
@@ -0,0 +1,196 @@
+use object_store::{
+    GetOptions, GetRange, ObjectStore, PutPayload, WriteMultipart, buffered::BufWriter,
+    memory::InMemory, multipart::MultipartStore, path::Path as ObjectPath,
+};
+use server::encryption::{EncryptedStore, Keyring};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+const OLD_KEY: [u8; 32] = [7; 32];
+const NEW_KEY: [u8; 32] = [9; 32];
+
+fn content(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[tokio::test]
+async fn objects_are_sealed_at_rest_and_opened_on_read() {
+    let backend: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let store = EncryptedStore::new(backend.clone(), Keyring::new(&NEW_KEY, &[]));
+    let path = ObjectPath::from("report.txt");
+    // More than one chunk, and not a multiple of it.
+    let plain = content(200_000);
+
+    store.put(&path, plain.clone().into()).await.unwrap();
+
+    let at_rest = backend.get(&path).await.unwrap().bytes().await.unwrap();
+    assert!(at_rest.starts_with(b"DDXE"));
+    assert!(at_rest.windows(64).all(|w| !plain.starts_with(w)));
+    assert!(at_rest.len() > plain.len());
+
+    let result = store.get(&path).await.unwrap();
+    assert_eq!(result.meta.size, plain.len());
+    assert_eq!(result.bytes().await.unwrap(), plain);
+    assert_eq!(store.head(&path).await.unwrap().size, plain.len());
+}
+
+#[tokio::test]
+async fn empty_objects_round_trip() {
+    let store = EncryptedStore::new(InMemory::new(), Keyring::new(&NEW_KEY, &[]));
+    let path = ObjectPath::from("empty.txt");
+
+    store.put(&path, PutPayload::new()).await.unwrap();
+
+    assert_eq!(store.head(&path).await.unwrap().size, 0);
+    assert!(
+        store
+            .get(&path)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn multipart_writes_are_sealed_as_one_stream() {
+    let backend: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let store: Arc<dyn ObjectStore> = Arc::new(EncryptedStore::new(
+        backend.clone(),
+        Keyring::new(&NEW_KEY, &[]),
+    ));
+    let path = ObjectPath::from("large.bin");
+    let plain = content(12 * 1024 * 1024 + 123);
+
+    let mut writer = BufWriter::with_capacity(store.clone(), path.clone(), 5 * 1024 * 1024);
+    writer.write_all(&plain).await.unwrap();
+    writer.shutdown().await.unwrap();
+
+    assert_eq!(store.head(&path).await.unwrap().size, plain.len());
+    assert_eq!(
+        store.get(&path).await.unwrap().bytes().await.unwrap(),
+        plain
+    );
+
+    let upload = store
+        .put_multipart(&ObjectPath::from("parts.bin"))
+        .await
+        .unwrap();
+    let mut write = WriteMultipart::new_with_chunk_size(upload, 5 * 1024 * 1024);
+    write.write(&plain);
+    write.finish().await.unwrap();
+    let read = store.get(&ObjectPath::from("parts.bin")).await.unwrap();
+    assert_eq!(read.bytes().await.unwrap(), plain);
+}
+
+#[tokio::test]
+async fn parts_uploaded_on_their_own_are_opened_in_order() {
+    let store = EncryptedStore::new(InMemory::new(), Keyring::new(&NEW_KEY, &[]));
+    let path = ObjectPath::from("staged.bin");
+    let first = content(70_000);
+    let second = content(10);
+
+    let id = store.create_multipart(&path).await.unwrap();
+    let parts = vec![
+        store
+            .put_part(&path, &id, 0, first.clone().into())
+            .await
+            .unwrap(),
+        store
+            .put_part(&path, &id, 1, second.clone().into())
+            .await
+            .unwrap(),
+    ];
+    store.complete_multipart(&path, &id, parts).await.unwrap();
+
+    let read = store.get(&path).await.unwrap().bytes().await.unwrap();
+    assert_eq!(read, [first, second].concat());
+}
+
+#[tokio::test]
+async fn rotated_keys_still_open_old_objects() {
+    let backend: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let path = ObjectPath::from("old.txt");
+    EncryptedStore::new(backend.clone(), Keyring::new(&OLD_KEY, &[]))
+        .put(&path, "written before the rotation".into())
+        .await
+        .unwrap();
+
+    let rotated = EncryptedStore::new(backend.clone(), Keyring::new(&NEW_KEY, &[OLD_KEY]));
+    let read = rotated.get(&path).await.unwrap().bytes().await.unwrap();
+    assert_eq!(read.as_ref(), b"written before the rotation");
+
+    let forgotten = EncryptedStore::new(backend, Keyring::new(&NEW_KEY, &[]));
+    let result = forgotten.get(&path).await.unwrap();
+    let error = result.bytes().await.unwrap_err();
+    assert!(error.to_string().contains("not configured"), "{error}");
+}
+
+#[tokio::test]
+async fn tampered_objects_fail_to_open() {
+    let backend: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let store = EncryptedStore::new(backend.clone(), Keyring::new(&NEW_KEY, &[]));
+    let path = ObjectPath::from("report.txt");
+    store.put(&path, content(1000).into()).await.unwrap();
+
+    let mut sealed = backend
+        .get(&path)
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap()
+        .to_vec();
+    sealed[100] ^= 1;
+    backend.put(&path, sealed.clone().into()).await.unwrap();
+    let error = store.get(&path).await.unwrap().bytes().await.unwrap_err();
+    assert!(error.to_string().contains("authentication"), "{error}");
+
+    sealed.truncate(sealed.len() - 20);
+    backend.put(&path, sealed.into()).await.unwrap();
+    assert!(store.get(&path).await.unwrap().bytes().await.is_err());
+}
+
+#[tokio::test]
+async fn plaintext_objects_are_read_as_they_are() {
+    let backend: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let path = ObjectPath::from("legacy.txt");
+    backend
+        .put(&path, "stored before encryption".into())
+        .await
+        .unwrap();
+
+    let store = EncryptedStore::new(backend, Keyring::new(&NEW_KEY, &[]));
+    assert_eq!(store.head(&path).await.unwrap().size, 24);
+    let read = store.get(&path).await.unwrap().bytes().await.unwrap();
+    assert_eq!(read.as_ref(), b"stored before encryption");
+}
+
+#[tokio::test]
+async fn range_reads_are_declined() {
+    let store = EncryptedStore::new(InMemory::new(), Keyring::new(&NEW_KEY, &[]));
+    let path = ObjectPath::from("report.txt");
+    store.put(&path, content(1000).into()).await.unwrap();
+
+    let options = GetOptions {
+        range: Some(GetRange::Bounded(0..10)),
+        ..Default::default()
+    };
+    let error = store.get_opts(&path, options).await.unwrap_err();
+    assert!(matches!(error, object_store::Error::NotSupported { .. }));
+    assert!(
+        error.to_string().contains("read the whole object"),
+        "{error}"
+    );
+    assert!(store.get_range(&path, 0..10).await.is_err());
+}
+
+#[test]
+fn keys_must_be_32_bytes_of_base64() {
+    assert!(Keyring::decode_key("AAAA").is_err());
+    assert!(Keyring::decode_key("not base64!").is_err());
+    let key = Keyring::decode_key(&("A".repeat(43) + "=")).unwrap();
+    assert_eq!(key, [0; 32]);
+}
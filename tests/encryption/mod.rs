@@ -0,0 +1,152 @@
+use object_store::{ObjectStore, PutPayload, RetryConfig, aws::AmazonS3Builder, path::Path};
+use server::{
+    controllers::files::{ServerSideEncryption, with_encryption},
+    glacier::{Bucket, Encryption},
+};
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path},
+};
+
+const BUCKET: &str = "files";
+
+fn builder(endpoint: &str) -> AmazonS3Builder {
+    AmazonS3Builder::new()
+        .with_endpoint(endpoint)
+        .with_allow_http(true)
+        .with_bucket_name(BUCKET)
+        .with_region("us-east-1")
+        .with_access_key_id("test")
+        .with_secret_access_key("test")
+        .with_retry(RetryConfig {
+            max_retries: 0,
+            ..Default::default()
+        })
+}
+
+fn bucket(endpoint: &str) -> Bucket<'_> {
+    Bucket {
+        endpoint,
+        name: BUCKET,
+        region: "us-east-1",
+        access_key: "test",
+        secret_key: "test",
+    }
+}
+
+async fn sent_encryption_headers(
+    sse: ServerSideEncryption,
+    kms_key_id: Option<&str>,
+) -> (Option<String>, Option<String>) {
+    let server = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .respond_with(ResponseTemplate::new(200).insert_header("ETag", "\"etag\""))
+        .mount(&server)
+        .await;
+    let store = with_encryption(builder(&server.uri()), sse, kms_key_id)
+        .build()
+        .unwrap();
+
+    store
+        .put(&Path::from("report.pdf"), PutPayload::from_static(b"bytes"))
+        .await
+        .unwrap();
+
+    let request = &server.received_requests().await.unwrap()[0];
+    let header = |name: &str| {
+        request
+            .headers
+            .get(name)
+            .map(|v| v.to_str().unwrap().to_string())
+    };
+    (
+        header("x-amz-server-side-encryption"),
+        header("x-amz-server-side-encryption-aws-kms-key-id"),
+    )
+}
+
+#[tokio::test]
+async fn puts_request_the_configured_encryption() {
+    assert_eq!(
+        sent_encryption_headers(ServerSideEncryption::None, None).await,
+        (None, None)
+    );
+    assert_eq!(
+        sent_encryption_headers(ServerSideEncryption::S3, None).await,
+        (Some("AES256".into()), None)
+    );
+    assert_eq!(
+        sent_encryption_headers(ServerSideEncryption::Kms, Some("alias/files")).await,
+        (Some("aws:kms".into()), Some("alias/files".into()))
+    );
+}
+
+#[tokio::test]
+async fn encryption_is_read_from_the_head_response() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .and(path(format!("/{BUCKET}/sealed.pdf")))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("x-amz-server-side-encryption", "aws:kms")
+                .insert_header("x-amz-server-side-encryption-aws-kms-key-id", "alias/files"),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("HEAD"))
+        .and(path(format!("/{BUCKET}/plain.pdf")))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let uri = server.uri();
+    let bucket = bucket(&uri);
+
+    assert_eq!(
+        bucket.encryption("sealed.pdf").await.unwrap(),
+        Some(Encryption {
+            algorithm: "aws:kms".into(),
+            kms_key_id: Some("alias/files".into()),
+        })
+    );
+    assert_eq!(bucket.encryption("plain.pdf").await.unwrap(), None);
+}
+
+/// Against a real MinIO, which emulates SSE-S3 once it has a KMS key:
+/// start it with `MINIO_KMS_SECRET_KEY=files-key:<base64 32 bytes>`, then
+/// `MINIO_ENDPOINT=http://localhost:9000 cargo test -- --ignored`.
+#[tokio::test]
+#[ignore = "needs MinIO with SSE-S3 at MINIO_ENDPOINT"]
+async fn minio_encrypts_objects_with_sse_s3() {
+    let endpoint = std::env::var("MINIO_ENDPOINT").unwrap();
+    let access_key = std::env::var("MINIO_ACCESS_KEY").unwrap_or_else(|_| "admin".into());
+    let secret_key = std::env::var("MINIO_SECRET_KEY").unwrap_or_else(|_| "admin1234".into());
+    let store = with_encryption(
+        builder(&endpoint)
+            .with_access_key_id(&access_key)
+            .with_secret_access_key(&secret_key),
+        ServerSideEncryption::S3,
+        None,
+    )
+    .build()
+    .unwrap();
+    let key = format!("sse-test-{}.txt", uuid::Uuid::new_v4());
+
+    store
+        .put(
+            &Path::from(key.as_str()),
+            PutPayload::from_static(b"secret"),
+        )
+        .await
+        .unwrap();
+    let bucket = Bucket {
+        access_key: &access_key,
+        secret_key: &secret_key,
+        ..bucket(&endpoint)
+    };
+    let encryption = bucket.encryption(&key).await;
+    let content = store.get(&Path::from(key.as_str())).await;
+    store.delete(&Path::from(key.as_str())).await.unwrap();
+
+    assert_eq!(encryption.unwrap().unwrap().algorithm, "AES256");
+    assert_eq!(content.unwrap().bytes().await.unwrap().as_ref(), b"secret");
+}
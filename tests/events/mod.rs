@@ -0,0 +1,44 @@
+use server::events::{EventBus, ProgressKind};
+
+#[tokio::test]
+async fn subscribers_receive_published_events() {
+    let bus = EventBus::default();
+    let (mut receiver, replay) = bus.subscribe(None);
+    assert!(replay.is_empty());
+
+    bus.publish(ProgressKind::UploadStarted, "a.bin", Some(7), None);
+
+    let event = receiver.recv().await.unwrap();
+    assert_eq!(event.id, 1);
+    assert_eq!(event.kind, ProgressKind::UploadStarted);
+    assert_eq!(event.file_name, "a.bin");
+    assert_eq!(event.user_id, Some(7));
+}
+
+#[tokio::test]
+async fn reconnecting_replays_only_missed_events() {
+    let bus = EventBus::default();
+    bus.publish(ProgressKind::UploadStarted, "a.bin", None, None);
+    bus.publish(ProgressKind::UploadCompleted, "a.bin", None, None);
+    bus.publish(ProgressKind::ScanCompleted, "a.bin", None, None);
+
+    let (mut receiver, replay) = bus.subscribe(Some(1));
+    let ids: Vec<u64> = replay.iter().map(|e| e.id).collect();
+    assert_eq!(ids, [2, 3]);
+
+    bus.publish(ProgressKind::ThumbnailReady, "a.bin", None, None);
+    assert_eq!(receiver.recv().await.unwrap().id, 4);
+}
+
+#[test]
+fn replay_keeps_only_recent_events() {
+    let bus = EventBus::default();
+    for _ in 0..300 {
+        bus.publish(ProgressKind::UploadStarted, "a.bin", None, None);
+    }
+
+    let (_, replay) = bus.subscribe(Some(0));
+    assert_eq!(replay.len(), 256);
+    assert_eq!(replay.first().unwrap().id, 45);
+    assert_eq!(replay.last().unwrap().id, 300);
+}
@@ -0,0 +1,126 @@
+use server::controllers::files::{
+    FileInfo, ListOptions, SortBy, SortKey, SortOrder, apply_list_options,
+};
+
+fn file(id: i32, name: &str, size: i64, created_at: &str, tags: &[&str]) -> FileInfo {
+    FileInfo {
+        id,
+        name: name.into(),
+        size,
+        author: None,
+        created_at: created_at.into(),
+        updated_at: created_at.into(),
+        version: 1,
+        metadata: None,
+        tags: tags.iter().map(|t| t.to_string()).collect(),
+        scan_status: None,
+        cdn_url: None,
+        content_type: None,
+        e_tag: None,
+        last_modified: None,
+        version_id: None,
+        checksums: Default::default(),
+    }
+}
+
+fn files() -> Vec<FileInfo> {
+    vec![
+        file(
+            1,
+            "reports/b.pdf",
+            300,
+            "2024-01-01T00:00:00+00:00",
+            &["q1"],
+        ),
+        file(2, "a.txt", 10, "2024-02-01T00:00:00+00:00", &[]),
+        file(
+            3,
+            "reports/a.pdf",
+            200,
+            "2024-03-01T00:00:00+00:00",
+            &["q1", "final"],
+        ),
+        file(4, "c.txt", 200, "2024-04-01T00:00:00+00:00", &["final"]),
+    ]
+}
+
+fn options(query: &[(&str, &str)]) -> ListOptions {
+    let pairs = query
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect::<Vec<_>>();
+    ListOptions::try_from(pairs).unwrap()
+}
+
+fn ids(options: &ListOptions) -> Vec<i32> {
+    apply_list_options(files(), options).map(|f| f.id).collect()
+}
+
+#[test]
+fn files_stay_in_upload_order_by_default() {
+    assert_eq!(ids(&ListOptions::default()), [1, 2, 3, 4]);
+}
+
+#[test]
+fn sorting_breaks_ties_in_upload_order() {
+    assert_eq!(ids(&options(&[("sort_by", "name")])), [2, 4, 3, 1]);
+    assert_eq!(ids(&options(&[("sort_by", "size")])), [2, 3, 4, 1]);
+    assert_eq!(
+        ids(&options(&[("sort_by", "size"), ("order", "desc")])),
+        [1, 4, 3, 2]
+    );
+}
+
+#[test]
+fn every_filter_given_must_match() {
+    assert_eq!(ids(&options(&[("prefix", "reports/")])), [1, 3]);
+    assert_eq!(
+        ids(&options(&[("min_size", "100"), ("max_size", "200")])),
+        [3, 4]
+    );
+    assert_eq!(
+        ids(&options(&[
+            ("uploaded_after", "2024-01-15T00:00:00Z"),
+            ("uploaded_before", "2024-04-01T00:00:00Z"),
+        ])),
+        [2, 3]
+    );
+    assert_eq!(ids(&options(&[("tag", "Q1"), ("tag", "final")])), [3]);
+    assert_eq!(
+        ids(&options(&[("prefix", "reports/"), ("tag", "final")])),
+        [3]
+    );
+}
+
+#[test]
+fn a_cursor_resumes_after_its_file_in_the_same_sort() {
+    let cursor = SortKey::Size(200, 3);
+    let options = ListOptions {
+        sort_by: SortBy::Size,
+        cursor: Some(cursor.clone()),
+        ..ListOptions::default()
+    };
+    assert_eq!(ids(&options), [4, 1]);
+
+    let options = ListOptions {
+        order: SortOrder::Desc,
+        ..options
+    };
+    assert_eq!(ids(&options), [2]);
+}
+
+#[test]
+fn malformed_queries_are_refused() {
+    for query in [
+        ("sort_by", "colour"),
+        ("order", "up"),
+        ("limit", "many"),
+        ("min_size", "-"),
+        ("uploaded_after", "yesterday"),
+        ("cursor", "not-a-cursor"),
+        ("tag", " "),
+    ] {
+        let pairs = vec![(query.0.to_string(), query.1.to_string())];
+        assert!(ListOptions::try_from(pairs).is_err(), "{query:?}");
+    }
+}
@@ -0,0 +1,21 @@
+mod access_log;
+mod cache_store;
+mod circuit_store;
+mod encrypted_store;
+mod encryption;
+mod events;
+mod list_options;
+mod multipart_gc;
+mod object_body;
+mod path_strategy;
+mod remote_fetch;
+mod replication;
+mod requests;
+mod retry_store;
+mod scanner;
+mod signed_urls;
+mod tar_stream;
+mod timeout_store;
+mod transfer_limit;
+mod upload_pipeline;
+mod webhooks;
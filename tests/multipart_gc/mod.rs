@@ -0,0 +1,182 @@
+use chrono::{TimeZone, Utc};
+use server::multipart_gc::{self, BucketClient, Sweep};
+use std::{collections::HashSet, time::Duration};
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path, query_param},
+};
+
+fn client(server: &MockServer) -> BucketClient {
+    BucketClient::new(
+        &server.uri(),
+        "files",
+        "us-east-1",
+        "test",
+        "test",
+        Duration::from_secs(5),
+    )
+    .unwrap()
+}
+
+fn upload(key: &str, id: &str, initiated: &str) -> String {
+    format!(
+        "<Upload><Key>{key}</Key><UploadId>{id}</UploadId>\
+         <Initiator><ID>someone</ID></Initiator><Initiated>{initiated}</Initiated></Upload>"
+    )
+}
+
+fn listing(uploads: &[String], next: Option<(&str, &str)>) -> String {
+    let truncated = match next {
+        Some((key, id)) => format!(
+            "<IsTruncated>true</IsTruncated><NextKeyMarker>{key}</NextKeyMarker>\
+             <NextUploadIdMarker>{id}</NextUploadIdMarker>"
+        ),
+        None => "<IsTruncated>false</IsTruncated>".into(),
+    };
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <ListMultipartUploadsResult><Bucket>files</Bucket>{truncated}{}</ListMultipartUploadsResult>",
+        uploads.concat()
+    )
+}
+
+fn parts(sizes: &[u64]) -> String {
+    let parts: String = sizes
+        .iter()
+        .enumerate()
+        .map(|(i, size)| {
+            format!(
+                "<Part><PartNumber>{}</PartNumber><Size>{size}</Size></Part>",
+                i + 1
+            )
+        })
+        .collect();
+    format!("<ListPartsResult><IsTruncated>false</IsTruncated>{parts}</ListPartsResult>")
+}
+
+#[tokio::test]
+async fn stale_untracked_uploads_are_aborted_and_measured() {
+    let server = MockServer::start().await;
+    let old = "2024-01-01T00:00:00.000Z";
+    let recent = "2024-06-01T00:00:00.000Z";
+    Mock::given(method("GET"))
+        .and(path("/files"))
+        .and(query_param("uploads", ""))
+        .respond_with(ResponseTemplate::new(200).set_body_string(listing(
+            &[
+                upload("crashed.bin", "u-1", old),
+                upload("tus/live", "u-2", old),
+            ],
+            Some(("tus/live", "u-2")),
+        )))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/files"))
+        .and(query_param("key-marker", "tus/live"))
+        .and(query_param("upload-id-marker", "u-2"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(listing(
+            &[
+                upload("reports/q3 &amp; q4.pdf", "u-3", old),
+                upload("fresh.bin", "u-4", recent),
+            ],
+            None,
+        )))
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/files/crashed.bin"))
+        .and(query_param("uploadId", "u-1"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(parts(&[5_242_880, 100])))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/files/reports/q3%20%26%20q4.pdf"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(parts(&[7])))
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .respond_with(ResponseTemplate::new(204))
+        .mount(&server)
+        .await;
+
+    let cutoff = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+    let keep = HashSet::from(["u-2".to_string()]);
+    let sweep = multipart_gc::abort_stale(&client(&server), cutoff, &keep)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        sweep,
+        Sweep {
+            aborted: 2,
+            total_bytes_freed: 5_242_987,
+        }
+    );
+    let requests = server.received_requests().await.unwrap();
+    let aborted: Vec<String> = requests
+        .iter()
+        .filter(|r| r.method.as_str() == "DELETE")
+        .map(|r| format!("{}?{}", r.url.path(), r.url.query().unwrap_or_default()))
+        .collect();
+    assert_eq!(
+        aborted,
+        [
+            "/files/crashed.bin?uploadId=u-1",
+            "/files/reports/q3%20%26%20q4.pdf?uploadId=u-3"
+        ]
+    );
+    let signed = requests[0].headers["authorization"].to_str().unwrap();
+    assert!(
+        signed.starts_with("AWS4-HMAC-SHA256 Credential=test/"),
+        "{signed}"
+    );
+}
+
+#[tokio::test]
+async fn uploads_gone_by_the_time_they_are_aborted_still_count() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/files"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(listing(
+            &[upload("done.bin", "u-1", "2024-01-01T00:00:00Z")],
+            None,
+        )))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/files/done.bin"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+
+    let sweep = multipart_gc::abort_stale(&client(&server), Utc::now(), &HashSet::new())
+        .await
+        .unwrap();
+    assert_eq!(
+        sweep,
+        Sweep {
+            aborted: 1,
+            total_bytes_freed: 0,
+        }
+    );
+}
+
+#[tokio::test]
+async fn a_failing_listing_is_an_error() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&server)
+        .await;
+
+    let error = multipart_gc::abort_stale(&client(&server), Utc::now(), &HashSet::new())
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("403"), "{error}");
+}
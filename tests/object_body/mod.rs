@@ -0,0 +1,38 @@
+use axum::body::to_bytes;
+use object_store::{GetOptions, GetRange, ObjectStore, memory::InMemory, path::Path as ObjectPath};
+use server::{controllers::files::object_body, local_store::LocalStore};
+
+async fn body_of(store: &dyn ObjectStore, options: GetOptions) -> String {
+    let result = store
+        .get_opts(&ObjectPath::from("notes.txt"), options)
+        .await
+        .unwrap();
+    let bytes = to_bytes(object_body(result), usize::MAX).await.unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+async fn reads_whole_and_ranged(store: &dyn ObjectStore) {
+    store
+        .put(&ObjectPath::from("notes.txt"), "meeting notes".into())
+        .await
+        .unwrap();
+    assert_eq!(body_of(store, GetOptions::default()).await, "meeting notes");
+    let ranged = GetOptions {
+        range: Some(GetRange::Bounded(8..11)),
+        ..Default::default()
+    };
+    assert_eq!(body_of(store, ranged).await, "not");
+}
+
+#[tokio::test]
+async fn a_streamed_object_is_forwarded() {
+    reads_whole_and_ranged(&InMemory::new()).await;
+}
+
+#[tokio::test]
+async fn a_local_file_is_read_from_its_range() {
+    let dir = std::env::temp_dir().join(format!("object-body-{}", uuid::Uuid::new_v4()));
+    let store = LocalStore::open(dir.to_str().unwrap()).unwrap();
+    reads_whole_and_ranged(&store).await;
+    std::fs::remove_dir_all(dir).unwrap();
+}
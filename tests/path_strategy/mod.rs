@@ -0,0 +1,39 @@
+use chrono::Utc;
+use server::path_strategy::{PathStrategy, sharded_key};
+
+#[test]
+fn parses_the_configured_names() {
+    assert_eq!(PathStrategy::parse("flat"), Some(PathStrategy::Flat));
+    assert_eq!(PathStrategy::parse("date"), Some(PathStrategy::Date));
+    assert_eq!(PathStrategy::parse("hash"), Some(PathStrategy::Hash));
+    assert_eq!(PathStrategy::parse("Hash"), None);
+    assert_eq!(PathStrategy::default(), PathStrategy::Flat);
+}
+
+#[test]
+fn builds_keys_for_each_strategy() {
+    assert_eq!(
+        PathStrategy::Flat.build_key("docs/notes.txt", b"notes"),
+        "docs/notes.txt"
+    );
+    // SHA-256 of "notes" starts ab5a.
+    assert_eq!(
+        PathStrategy::Hash.build_key("docs/notes.txt", b"notes"),
+        "ab/5a/docs/notes.txt"
+    );
+    assert_eq!(
+        PathStrategy::Date.build_key("notes.txt", b"notes"),
+        format!("{}/notes.txt", Utc::now().format("%Y/%m/%d"))
+    );
+}
+
+#[test]
+fn sharded_keys_map_back_from_the_recorded_shard() {
+    let shard = PathStrategy::Hash.shard(b"notes");
+    assert_eq!(shard.as_deref(), Some("ab/5a"));
+    assert_eq!(
+        sharded_key(shard.as_deref(), "notes.txt"),
+        PathStrategy::Hash.build_key("notes.txt", b"notes")
+    );
+    assert_eq!(sharded_key(None, "notes.txt"), "notes.txt");
+}
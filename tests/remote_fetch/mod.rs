@@ -0,0 +1,127 @@
+use server::remote_fetch::{self, FetchError, FetchPolicy};
+use std::time::Duration;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path},
+};
+
+/// Any host, including the loopback address wiremock listens on.
+fn open_policy() -> FetchPolicy {
+    FetchPolicy {
+        allowed_hosts: Vec::new(),
+        allow_any_host: true,
+        allow_private_addresses: true,
+        timeout: Duration::from_secs(5),
+        max_redirects: 2,
+        max_bytes: 1024,
+    }
+}
+
+async fn redirect(server: &MockServer, from: &str, to: &str) {
+    Mock::given(method("GET"))
+        .and(path(from))
+        .respond_with(ResponseTemplate::new(302).insert_header("Location", to))
+        .mount(server)
+        .await;
+}
+
+#[test]
+fn internal_addresses_are_not_public() {
+    for ip in [
+        "127.0.0.1",
+        "10.1.2.3",
+        "172.16.0.1",
+        "192.168.1.1",
+        "169.254.169.254",
+        "100.64.0.1",
+        "0.0.0.0",
+        "::1",
+        "fe80::1",
+        "fd00::1",
+        "::ffff:10.0.0.1",
+    ] {
+        assert!(!remote_fetch::is_public(ip.parse().unwrap()), "{ip}");
+    }
+    assert!(remote_fetch::is_public("93.184.216.34".parse().unwrap()));
+    assert!(remote_fetch::is_public("2606:4700::1111".parse().unwrap()));
+}
+
+#[test]
+fn the_allowlist_matches_hosts_and_wildcard_domains() {
+    let policy = FetchPolicy {
+        allowed_hosts: vec!["files.example.com".into(), "*.archive.org".into()],
+        allow_any_host: false,
+        ..open_policy()
+    };
+
+    assert!(policy.allows_host("files.example.com"));
+    assert!(policy.allows_host("Files.Example.com"));
+    assert!(policy.allows_host("archive.org"));
+    assert!(policy.allows_host("ia800.archive.org"));
+    assert!(!policy.allows_host("example.com"));
+    assert!(!policy.allows_host("evilarchive.org"));
+}
+
+#[tokio::test]
+async fn loopback_targets_are_refused_by_default() {
+    let server = MockServer::start().await;
+    let policy = FetchPolicy {
+        allow_private_addresses: false,
+        ..open_policy()
+    };
+
+    let error = remote_fetch::fetch(&policy, &format!("{}/report.pdf", server.uri()))
+        .await
+        .unwrap_err();
+
+    assert!(matches!(error, FetchError::NotAllowed(_)), "{error}");
+    assert!(server.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn redirects_are_followed_up_to_the_limit() {
+    let server = MockServer::start().await;
+    redirect(&server, "/a", "/b").await;
+    redirect(&server, "/b", "/report.pdf").await;
+    redirect(&server, "/loop", "/loop").await;
+    Mock::given(method("GET"))
+        .and(path("/report.pdf"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("%PDF-1.7"))
+        .mount(&server)
+        .await;
+
+    let fetched = remote_fetch::fetch(&open_policy(), &format!("{}/a", server.uri()))
+        .await
+        .unwrap();
+    assert_eq!(fetched.url.path(), "/report.pdf");
+    assert_eq!(fetched.bytes.as_ref(), b"%PDF-1.7");
+
+    let error = remote_fetch::fetch(&open_policy(), &format!("{}/loop", server.uri()))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, FetchError::Failed(_)), "{error}");
+}
+
+#[tokio::test]
+async fn bodies_over_the_limit_are_refused() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![0u8; 2048]))
+        .mount(&server)
+        .await;
+
+    let error = remote_fetch::fetch(&open_policy(), &format!("{}/big.bin", server.uri()))
+        .await
+        .unwrap_err();
+
+    assert!(matches!(error, FetchError::TooLarge(1024)), "{error}");
+}
+
+#[tokio::test]
+async fn only_http_urls_are_fetched() {
+    let error = remote_fetch::fetch(&open_policy(), "file:///etc/passwd")
+        .await
+        .unwrap_err();
+
+    assert!(matches!(error, FetchError::InvalidUrl(_)), "{error}");
+}
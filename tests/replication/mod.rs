@@ -0,0 +1,102 @@
+use object_store::{
+    Attribute, Attributes, ObjectStore, PutOptions, memory::InMemory, path::Path as ObjectPath,
+};
+use server::replication::{self, Reconciliation};
+use std::sync::Arc;
+
+async fn read(store: &dyn ObjectStore, location: &str) -> Option<String> {
+    let result = store.get(&ObjectPath::from(location)).await.ok()?;
+    Some(String::from_utf8(result.bytes().await.unwrap().to_vec()).unwrap())
+}
+
+fn buckets() -> (Arc<InMemory>, Arc<dyn ObjectStore>) {
+    (Arc::new(InMemory::new()), Arc::new(InMemory::new()))
+}
+
+#[tokio::test]
+async fn copies_keep_the_object_attributes() {
+    let (primary, replica) = buckets();
+    let location = ObjectPath::from("notes.txt");
+    let attributes = Attributes::from_iter([(Attribute::ContentType, "text/plain")]);
+    let options = PutOptions {
+        attributes,
+        ..Default::default()
+    };
+    primary
+        .put_opts(&location, "notes".into(), options)
+        .await
+        .unwrap();
+
+    replication::copy_object(primary.as_ref(), &replica, &location)
+        .await
+        .unwrap();
+    let copy = replica.get(&location).await.unwrap();
+    assert_eq!(
+        copy.attributes
+            .get(&Attribute::ContentType)
+            .unwrap()
+            .as_ref(),
+        "text/plain"
+    );
+    assert_eq!(copy.bytes().await.unwrap(), "notes");
+}
+
+#[tokio::test]
+async fn reconciliation_copies_what_is_missing_or_changed() {
+    let (primary, replica) = buckets();
+    for (key, content) in [("a.txt", "a"), ("b.txt", "b"), ("c.txt", "c")] {
+        primary
+            .put(&ObjectPath::from(key), content.into())
+            .await
+            .unwrap();
+    }
+    replica
+        .put(&ObjectPath::from("b.txt"), "stale".into())
+        .await
+        .unwrap();
+
+    let report = replication::reconcile(primary.as_ref(), &replica, false)
+        .await
+        .unwrap();
+    assert_eq!(report.copied, 3);
+    assert_eq!(read(replica.as_ref(), "b.txt").await.as_deref(), Some("b"));
+
+    let report = replication::reconcile(primary.as_ref(), &replica, false)
+        .await
+        .unwrap();
+    assert_eq!(
+        report,
+        Reconciliation {
+            in_sync: 3,
+            ..Default::default()
+        }
+    );
+}
+
+#[tokio::test]
+async fn objects_gone_from_the_primary_are_only_deleted_when_asked() {
+    let (primary, replica) = buckets();
+    primary
+        .put(&ObjectPath::from("b.txt"), "b".into())
+        .await
+        .unwrap();
+    for key in ["a.txt", "c.txt"] {
+        replica
+            .put(&ObjectPath::from(key), "old".into())
+            .await
+            .unwrap();
+    }
+
+    let report = replication::reconcile(primary.as_ref(), &replica, false)
+        .await
+        .unwrap();
+    assert_eq!((report.copied, report.deleted), (1, 0));
+    assert!(read(replica.as_ref(), "a.txt").await.is_some());
+
+    let report = replication::reconcile(primary.as_ref(), &replica, true)
+        .await
+        .unwrap();
+    assert_eq!((report.in_sync, report.deleted), (1, 2));
+    assert!(read(replica.as_ref(), "a.txt").await.is_none());
+    assert!(read(replica.as_ref(), "c.txt").await.is_none());
+}
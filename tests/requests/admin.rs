@@ -0,0 +1,107 @@
+use axum::{Extension, Router, http::StatusCode};
+use axum_test::{
+    TestServer,
+    multipart::{MultipartForm, Part},
+};
+use loco_rs::{app::AppContext, controller::AppRoutes, testing::prelude::*};
+use object_store::{ObjectStore, memory::InMemory};
+use serde_json::Value;
+use serial_test::serial;
+use server::{
+    app::App,
+    controllers::{admin, files},
+};
+use std::sync::Arc;
+
+use super::{admin_token, bearer_token};
+
+/// The `/files` and `/admin` routes over an in-memory store.
+fn test_server(ctx: &AppContext) -> TestServer {
+    let router: Router = AppRoutes::empty()
+        .add_route(files::routes(ctx))
+        .add_route(admin::routes())
+        .to_router::<App>(ctx.clone(), Router::new())
+        .unwrap()
+        .layer(Extension(Arc::new(InMemory::new()) as Arc<dyn ObjectStore>));
+    TestServer::new(router).unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn uploads_and_deletes_are_audited() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context);
+
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(
+            MultipartForm::new().add_part("file", Part::bytes(&b"bytes"[..]).file_name("a.bin")),
+        )
+        .await
+        .assert_status_ok();
+    server
+        .delete("/files/a.bin")
+        .authorization_bearer(&token)
+        .await
+        .assert_status_ok();
+
+    let response = server
+        .get("/admin/audit-log")
+        .authorization_bearer(admin_token())
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    let entries = body["entries"].as_array().unwrap();
+    let summary: Vec<(&str, &str, &str)> = entries
+        .iter()
+        .map(|e| {
+            (
+                e["operation"].as_str().unwrap(),
+                e["file_key"].as_str().unwrap(),
+                e["outcome"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("DELETE", "a.bin", "SUCCESS"),
+            ("UPLOAD", "a.bin", "SUCCESS")
+        ]
+    );
+    assert!(entries[0]["user_id"].is_i64());
+    assert!(body["next_page_token"].is_null());
+
+    let page: Value = server
+        .get("/admin/audit-log")
+        .add_query_param("limit", 1)
+        .authorization_bearer(admin_token())
+        .await
+        .json();
+    assert_eq!(page["entries"].as_array().unwrap().len(), 1);
+    assert_eq!(page["entries"][0]["operation"], "DELETE");
+    let next: Value = server
+        .get("/admin/audit-log")
+        .add_query_param("page_token", page["next_page_token"].as_str().unwrap())
+        .authorization_bearer(admin_token())
+        .await
+        .json();
+    assert_eq!(next["entries"][0]["operation"], "UPLOAD");
+}
+
+#[tokio::test]
+#[serial]
+async fn audit_log_requires_the_admin_scope() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context);
+
+    let response = server
+        .get("/admin/audit-log")
+        .authorization_bearer(&token)
+        .await;
+
+    response.assert_status(StatusCode::FORBIDDEN);
+}
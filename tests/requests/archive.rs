@@ -0,0 +1,103 @@
+use async_compression::tokio::bufread::GzipDecoder;
+use axum::http::StatusCode;
+use axum_test::multipart::{MultipartForm, Part};
+use loco_rs::testing::prelude::*;
+use object_store::{ObjectStore, memory::InMemory, path::Path as ObjectPath};
+use serde_json::Value;
+use serial_test::serial;
+use server::app::App;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+
+use super::{bearer_token, test_server};
+
+#[tokio::test]
+#[serial]
+async fn archive_stores_a_gzipped_copy_next_to_the_file() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let store = Arc::new(InMemory::new());
+    let server = test_server(&boot.app_context, store.clone());
+    let content = "all work and no play makes a dull report\n".repeat(1000);
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(MultipartForm::new().add_part(
+            "file",
+            Part::bytes(content.clone().into_bytes()).file_name("report.pdf"),
+        ))
+        .await
+        .assert_status_ok();
+
+    let response = server
+        .post("/files/report.pdf/archive")
+        .authorization_bearer(&token)
+        .await;
+
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["archived_key"], "report.pdf.gz");
+    assert_eq!(body["original_size"], content.len());
+    let archived_size = body["archived_size"].as_f64().unwrap();
+    assert!(archived_size < content.len() as f64 / 10.0);
+    assert_eq!(
+        body["compression_ratio"].as_f64().unwrap(),
+        content.len() as f64 / archived_size
+    );
+
+    let gzipped = store
+        .get(&ObjectPath::from("report.pdf.gz"))
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    let mut decoded = String::new();
+    GzipDecoder::new(&gzipped[..])
+        .read_to_string(&mut decoded)
+        .await
+        .unwrap();
+    assert_eq!(decoded, content);
+    store.head(&ObjectPath::from("report.pdf")).await.unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn archive_is_a_conflict_unless_overwrite_is_set() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(
+            MultipartForm::new()
+                .add_part("file", Part::bytes(&b"first"[..]).file_name("notes.txt")),
+        )
+        .await
+        .assert_status_ok();
+    server
+        .post("/files/notes.txt/archive")
+        .authorization_bearer(&token)
+        .await
+        .assert_status_ok();
+
+    let again = server
+        .post("/files/notes.txt/archive")
+        .authorization_bearer(&token)
+        .await;
+    again.assert_status(StatusCode::CONFLICT);
+    let body: Value = again.json();
+    assert_eq!(body["code"], "archive_exists");
+
+    server
+        .post("/files/notes.txt/archive")
+        .add_query_param("overwrite", true)
+        .authorization_bearer(&token)
+        .await
+        .assert_status_ok();
+    server
+        .post("/files/notes.txt/archive")
+        .await
+        .assert_status_unauthorized();
+}
@@ -0,0 +1,245 @@
+use axum::http::{StatusCode, header};
+use axum_test::TestServer;
+use chrono::{Duration, SecondsFormat, Utc};
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::Value;
+use serial_test::serial;
+use server::{
+    access_log::{self, AccessLogger},
+    app::App,
+    controllers::{auth, files},
+    models::file_audit_log::{self, Actor, Entry, Operation},
+};
+use std::sync::Arc;
+
+use super::{admin_token, bearer_token, test_server, upload};
+
+async fn entries(server: &TestServer, query: &str) -> Vec<Value> {
+    let response = server
+        .get(&format!("/files/audit{query}"))
+        .authorization_bearer(admin_token())
+        .await;
+    response.assert_status_ok();
+    response.json::<Value>()["entries"]
+        .as_array()
+        .unwrap()
+        .clone()
+}
+
+#[tokio::test]
+#[serial]
+async fn downloads_are_audited_refused_ones_included() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let user_id: i64 = auth::decode_token(&token).unwrap().pid.parse().unwrap();
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    upload(&server, &token, "notes.txt", b"notes").await;
+
+    server
+        .get("/files/notes.txt")
+        .authorization_bearer(&token)
+        .add_header(header::USER_AGENT, "audit-test/1.0")
+        .await
+        .assert_status_ok();
+    server
+        .get("/files/missing.txt")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    server
+        .get("/files/notes.txt?token=forged&expires=4102444800")
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let entries = entries(&server, "").await;
+    let summary: Vec<(&str, &str, &str, i64)> = entries
+        .iter()
+        .map(|e| {
+            (
+                e["operation"].as_str().unwrap(),
+                e["file_key"].as_str().unwrap(),
+                e["outcome"].as_str().unwrap(),
+                e["status_code"].as_i64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("LINK_ACCESS", "notes.txt", "FAILURE", 403),
+            ("DOWNLOAD", "missing.txt", "FAILURE", 404),
+            ("DOWNLOAD", "notes.txt", "SUCCESS", 200),
+            ("UPLOAD", "notes.txt", "SUCCESS", 200),
+        ]
+    );
+
+    let download = &entries[2];
+    assert_eq!(download["bytes"], 5);
+    assert_eq!(download["user_id"], user_id);
+    assert_eq!(download["user_agent"], "audit-test/1.0");
+    assert_eq!(download["token_id"].as_str().unwrap().len(), 16);
+    assert_eq!(download["token_id"], entries[3]["token_id"]);
+
+    let link = &entries[0];
+    assert!(link["user_id"].is_null());
+    assert_eq!(link["token_id"], auth::token_id("forged"));
+    assert!(!token.contains(link["token_id"].as_str().unwrap()));
+}
+
+#[tokio::test]
+#[serial]
+async fn a_file_audit_is_for_its_owner_and_admins() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    upload(&server, &token, "notes.txt", b"notes").await;
+
+    let own = server
+        .get("/files/notes.txt/audit")
+        .authorization_bearer(&token)
+        .await;
+    own.assert_status_ok();
+    assert_eq!(own.json::<Value>()["entries"][0]["operation"], "UPLOAD");
+
+    let stranger = auth::generate_token("999999", "stranger", vec!["tester".into()]).unwrap();
+    let refused = server
+        .get("/files/notes.txt/audit")
+        .authorization_bearer(&stranger)
+        .await;
+    refused.assert_status(StatusCode::FORBIDDEN);
+    assert_eq!(refused.json::<Value>()["code"], "not_file_owner");
+    server
+        .get("/files/notes.txt/audit")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    server
+        .get("/files/gone.txt/audit")
+        .authorization_bearer(&stranger)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    let admin = server
+        .get("/files/notes.txt/audit")
+        .authorization_bearer(admin_token())
+        .await;
+    admin.assert_status_ok();
+    assert_eq!(
+        admin.json::<Value>()["entries"].as_array().unwrap().len(),
+        1
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn the_audit_log_is_searched_by_user_and_time_in_pages() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let user_id = auth::decode_token(&token).unwrap().pid;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        upload(&server, &token, name, b"notes").await;
+    }
+    server.get("/files/a.txt").await.assert_status_ok();
+
+    let first = server
+        .get(&format!("/files/audit?user={user_id}&limit=2"))
+        .authorization_bearer(admin_token())
+        .await
+        .json::<Value>();
+    let keys = |page: &Value| -> Vec<String> {
+        page["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["file_key"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(keys(&first), ["c.txt", "b.txt"]);
+    let next = first["next_page_token"].as_str().unwrap();
+    let second = server
+        .get(&format!(
+            "/files/audit?user={user_id}&limit=2&page_token={next}"
+        ))
+        .authorization_bearer(admin_token())
+        .await
+        .json::<Value>();
+    assert_eq!(keys(&second), ["a.txt"]);
+    assert!(second["next_page_token"].is_null());
+
+    let at = |offset: Duration| (Utc::now() + offset).to_rfc3339_opts(SecondsFormat::Secs, true);
+    let hour = Duration::hours(1);
+    let recent = entries(&server, &format!("?from={}", at(-hour))).await;
+    assert_eq!(recent.len(), 4);
+    let future = entries(&server, &format!("?from={}", at(hour))).await;
+    assert!(future.is_empty());
+    let past = entries(&server, &format!("?to={}", at(-hour))).await;
+    assert!(past.is_empty());
+
+    server
+        .get("/files/audit?from=yesterday")
+        .authorization_bearer(admin_token())
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    server
+        .get("/files/audit")
+        .authorization_bearer(&token)
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+#[serial]
+async fn entries_past_the_retention_period_are_purged() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    let mut old = Entry::new(Operation::Delete, Some("old.txt"), &Actor::default());
+    old.at = (Utc::now() - Duration::days(31)).naive_utc();
+    file_audit_log::insert_all(&ctx.db, vec![old])
+        .await
+        .unwrap();
+    upload(&server, &token, "notes.txt", b"notes").await;
+
+    assert_eq!(files::purge_audit_log(ctx).await.unwrap(), 1);
+    let kept = entries(&server, "").await;
+    assert_eq!(kept.len(), 1);
+    assert_eq!(kept[0]["file_key"], "notes.txt");
+}
+
+#[tokio::test]
+#[serial]
+async fn uploads_and_downloads_are_written_to_the_access_log() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let path = std::env::temp_dir()
+        .join(format!("access-log-{}", uuid::Uuid::new_v4()))
+        .join("access.jsonl");
+    let logger = AccessLogger::open(&path, 1024 * 1024).await.unwrap();
+    access_log::install(ctx, Arc::new(logger));
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    upload(&server, &token, "notes.txt", b"notes").await;
+    server.get("/files/notes.txt").await.assert_status_ok();
+
+    // Lines are written in the background.
+    let mut lines = Vec::new();
+    for _ in 0..50 {
+        let text = std::fs::read_to_string(&path).unwrap();
+        lines = text
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .collect();
+        if lines.len() == 2 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let ops: Vec<(&str, &str)> = lines
+        .iter()
+        .map(|l| (l["op"].as_str().unwrap(), l["key"].as_str().unwrap()))
+        .collect();
+    assert_eq!(ops, [("upload", "notes.txt"), ("download", "notes.txt")]);
+    assert_eq!(lines[0]["user"], auth::decode_token(&token).unwrap().pid);
+    assert!(lines[1]["user"].is_null());
+}
@@ -0,0 +1,77 @@
+use axum::http::StatusCode;
+use base64::{Engine, prelude::BASE64_STANDARD};
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::app::App;
+use std::sync::Arc;
+
+use super::{bearer_token, test_server};
+
+#[tokio::test]
+#[serial]
+async fn base64_uploads_are_stored_decoded() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    let content = b"%PDF-1.7 quarterly figures";
+
+    let response = server
+        .post("/files/base64")
+        .authorization_bearer(&token)
+        .json(&json!({
+            "name": "q3.pdf",
+            "content_type": "application/pdf",
+            "data": BASE64_STANDARD.encode(content),
+        }))
+        .await;
+
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["name"], "q3.pdf");
+    assert_eq!(body["size"], content.len());
+    let download = server
+        .get("/files/q3.pdf")
+        .authorization_bearer(&token)
+        .await;
+    download.assert_status_ok();
+    assert_eq!(download.as_bytes().as_ref(), content);
+}
+
+#[tokio::test]
+#[serial]
+async fn invalid_base64_uploads_are_rejected() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    let upload = |name: &str, content_type: &str, data: String| {
+        server
+            .post("/files/base64")
+            .authorization_bearer(&token)
+            .json(&json!({ "name": name, "content_type": content_type, "data": data }))
+    };
+
+    let response = upload("a.txt", "text/plain", "not base64!".into()).await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(response.json::<Value>()["code"], "bad_request");
+
+    upload("../a.txt", "text/plain", BASE64_STANDARD.encode("x"))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    upload("a.txt", "plain", BASE64_STANDARD.encode("x"))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    // Over `base64_max_size_bytes` of config/test.yaml.
+    let response = upload("a.txt", "text/plain", BASE64_STANDARD.encode([b'x'; 4000])).await;
+    response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response.json::<Value>()["code"], "file_too_large");
+
+    let listed: Vec<Value> = server
+        .get("/files")
+        .authorization_bearer(&token)
+        .await
+        .json();
+    assert!(listed.is_empty());
+}
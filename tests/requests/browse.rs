@@ -0,0 +1,103 @@
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::Value;
+use serial_test::serial;
+use server::app::App;
+use std::sync::Arc;
+
+use super::{bearer_token, seed_all, test_server};
+
+async fn browse(server: &TestServer, query: &str) -> Value {
+    let response = server.get(&format!("/files/browse{query}")).await;
+    response.assert_status_ok();
+    response.json()
+}
+
+fn prefixes(listing: &Value) -> Vec<&str> {
+    listing["common_prefixes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["prefix"].as_str().unwrap())
+        .collect()
+}
+
+fn names(listing: &Value) -> Vec<&str> {
+    listing["objects"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| f["name"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+#[serial]
+async fn folders_list_their_subfolders_and_files() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    seed_all(
+        &server,
+        &token,
+        &[
+            "readme.txt",
+            "projects/plan.txt",
+            "projects/2024/q1.txt",
+            "projects/2024/q2.txt",
+            "projects/2024/drafts/q3.txt",
+            "projects_old/a.txt",
+        ],
+    )
+    .await;
+
+    let root = browse(&server, "").await;
+    assert_eq!(root["path"], "");
+    assert_eq!(prefixes(&root), ["projects/", "projects_old/"]);
+    assert_eq!(names(&root), ["readme.txt"]);
+    assert_eq!(root["prefix_count"], 2);
+    assert_eq!(root["object_count"], 1);
+    assert!(root["common_prefixes"][0].get("total_size").is_none());
+
+    for path in ["projects/2024/", "projects/2024", "/projects/2024//"] {
+        let listing = browse(&server, &format!("?path={path}")).await;
+        assert_eq!(listing["path"], "projects/2024/", "{path}");
+        assert_eq!(prefixes(&listing), ["projects/2024/drafts/"]);
+        assert_eq!(
+            names(&listing),
+            ["projects/2024/q1.txt", "projects/2024/q2.txt"]
+        );
+    }
+
+    let sized = browse(&server, "?path=projects&with_sizes=true").await;
+    assert_eq!(names(&sized), ["projects/plan.txt"]);
+    assert_eq!(sized["common_prefixes"][0]["prefix"], "projects/2024/");
+    assert_eq!(sized["common_prefixes"][0]["total_size"], 15);
+    assert_eq!(sized["common_prefixes"][0]["file_count"], 3);
+
+    let empty = browse(&server, "?path=nowhere/").await;
+    assert_eq!(empty["object_count"], 0);
+    assert_eq!(empty["prefix_count"], 0);
+}
+
+#[tokio::test]
+#[serial]
+async fn a_path_naming_a_file_lists_that_file() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    seed_all(&server, &token, &["projects/2024/q1.txt"]).await;
+
+    let listing = browse(&server, "?path=projects/2024/q1.txt").await;
+    assert_eq!(listing["path"], "projects/2024/q1.txt");
+    assert_eq!(names(&listing), ["projects/2024/q1.txt"]);
+    assert_eq!(listing["objects"][0]["size"], 5);
+    assert_eq!(listing["prefix_count"], 0);
+
+    server
+        .get("/files/browse?path=projects/../secrets")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,162 @@
+use axum::http::{HeaderName, HeaderValue, StatusCode};
+use axum_test::{
+    TestResponse, TestServer,
+    multipart::{MultipartForm, Part},
+};
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::app::App;
+use std::sync::Arc;
+
+use super::{bearer_token, test_server_with_parts};
+
+/// Digests of "notes".
+const NOTES_MD5: &str = "Q1i1AJxn0OMdf78WY/zTvw==";
+const NOTES_CRC32: &str = "ARumjA==";
+const NOTES_SHA256: &str = "q1qpcHTEVKBjIFfnBCINmmZ4+/dzoKWAb8CbgXOwcwk=";
+
+async fn upload(
+    server: &TestServer,
+    token: &str,
+    content: &str,
+    headers: &[(&'static str, &str)],
+    part_headers: &[(&'static str, &str)],
+) -> TestResponse {
+    let mut part = Part::bytes(content.as_bytes().to_vec()).file_name("notes.txt");
+    for (name, value) in part_headers {
+        part = part.add_header(*name, *value);
+    }
+    let mut request = server.post("/files").authorization_bearer(token);
+    for (name, value) in headers {
+        request = request.add_header(
+            HeaderName::from_static(name),
+            HeaderValue::from_str(value).unwrap(),
+        );
+    }
+    request
+        .multipart(MultipartForm::new().add_part("file", part))
+        .await
+}
+
+#[tokio::test]
+#[serial]
+async fn verified_digests_are_recorded_with_the_file() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server_with_parts(ctx, Arc::new(InMemory::new()));
+
+    let response = upload(
+        &server,
+        &token,
+        "notes",
+        &[("content-md5", NOTES_MD5)],
+        &[
+            ("x-amz-checksum-crc32", NOTES_CRC32),
+            ("x-amz-checksum-sha256", NOTES_SHA256),
+        ],
+    )
+    .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    let expected = json!({
+        "md5": "4358b5009c67d0e31d7fbf1663fcd3bf",
+        "crc32": "011ba68c",
+        "sha256": "ab5aa97074c454a0632057e704220d9a6678fbf773a0a5806fc09b8173b07309",
+    });
+    assert_eq!(body["results"][0]["file"]["checksums"], expected);
+
+    let listing: Vec<Value> = server
+        .get("/files")
+        .authorization_bearer(&token)
+        .await
+        .json();
+    let file = listing.iter().find(|f| f["name"] == "notes.txt").unwrap();
+    assert_eq!(file["checksums"], expected);
+}
+
+#[tokio::test]
+#[serial]
+async fn a_mismatched_digest_stores_nothing() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server_with_parts(ctx, Arc::new(InMemory::new()));
+
+    let response = upload(&server, &token, "other", &[], &[("content-md5", NOTES_MD5)]).await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    let body: Value = response.json();
+    assert_eq!(body["code"], "checksum_mismatch");
+    assert_eq!(body["details"]["algorithm"], "md5");
+    assert_eq!(
+        body["details"]["expected"],
+        "4358b5009c67d0e31d7fbf1663fcd3bf"
+    );
+    server
+        .get("/files/notes.txt")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    let response = upload(
+        &server,
+        &token,
+        "notes",
+        &[("content-md5", "bm90ZXM=")],
+        &[],
+    )
+    .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(response.json::<Value>()["code"], "invalid_checksum_header");
+    let response = upload(
+        &server,
+        &token,
+        "notes",
+        &[("x-amz-checksum-crc32c", "AAAAAA==")],
+        &[],
+    )
+    .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(response.json::<Value>()["code"], "unsupported_checksum");
+}
+
+#[tokio::test]
+#[serial]
+async fn chunked_parts_are_checked_before_they_are_stored() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server_with_parts(ctx, Arc::new(InMemory::new()));
+
+    let response = server
+        .post("/files/uploads")
+        .authorization_bearer(&token)
+        .json(&json!({ "file_name": "notes.txt" }))
+        .await;
+    response.assert_status(StatusCode::CREATED);
+    let id = response.json::<Value>()["id"].as_str().unwrap().to_string();
+
+    let response = server
+        .put(&format!("/files/uploads/{id}/parts/1"))
+        .authorization_bearer(&token)
+        .add_header(
+            HeaderName::from_static("content-md5"),
+            HeaderValue::from_static(NOTES_MD5),
+        )
+        .bytes("other".into())
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(response.json::<Value>()["code"], "checksum_mismatch");
+
+    server
+        .put(&format!("/files/uploads/{id}/parts/1"))
+        .authorization_bearer(&token)
+        .add_header(
+            HeaderName::from_static("content-md5"),
+            HeaderValue::from_static(NOTES_MD5),
+        )
+        .bytes("notes".into())
+        .await
+        .assert_status_ok();
+}
@@ -0,0 +1,188 @@
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use loco_rs::testing::prelude::*;
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::{app::App, models::chunked_upload};
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path_regex, query_param},
+};
+
+use super::{
+    bearer_token,
+    files::{mock_puts, object_response, s3_client},
+    test_server_with_parts,
+};
+
+/// Answers multipart calls, giving part `n` the ETag `part-n`.
+async fn mock_multipart(server: &MockServer) {
+    Mock::given(method("POST"))
+        .and(query_param("uploads", ""))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId></InitiateMultipartUploadResult>",
+        ))
+        .mount(server)
+        .await;
+    for n in 1..=3 {
+        Mock::given(method("PUT"))
+            .and(query_param("partNumber", n.to_string()))
+            .respond_with(ResponseTemplate::new(200).insert_header("ETag", format!("\"part-{n}\"")))
+            .with_priority(1)
+            .mount(server)
+            .await;
+    }
+    Mock::given(method("POST"))
+        .and(query_param("uploadId", "upload-1"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            "<CompleteMultipartUploadResult><ETag>\"multipart-etag\"</ETag></CompleteMultipartUploadResult>",
+        ))
+        .mount(server)
+        .await;
+    Mock::given(method("DELETE"))
+        .respond_with(ResponseTemplate::new(204))
+        .mount(server)
+        .await;
+    mock_puts(server).await;
+}
+
+async fn start_upload(server: &TestServer, token: &str, name: &str) -> String {
+    let response = server
+        .post("/files/uploads")
+        .authorization_bearer(token)
+        .json(&json!({ "file_name": name, "metadata": { "title": "Field report" } }))
+        .await;
+    response.assert_status(StatusCode::CREATED);
+    let body: Value = response.json();
+    assert_eq!(body["file_name"], name);
+    body["id"].as_str().unwrap().to_string()
+}
+
+async fn put_part(server: &TestServer, token: &str, id: &str, n: u32, body: Vec<u8>) {
+    server
+        .put(&format!("/files/uploads/{id}/parts/{n}"))
+        .authorization_bearer(token)
+        .bytes(body.into())
+        .await
+        .assert_status_ok();
+}
+
+#[tokio::test]
+#[serial]
+async fn parts_sent_out_of_order_and_retried_are_assembled_in_order() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_multipart(&s3).await;
+    Mock::given(method("GET"))
+        .and(path_regex("^/files/uploads/"))
+        .respond_with(object_response("hello world"))
+        .mount(&s3)
+        .await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server_with_parts(&boot.app_context, s3_client(&s3));
+
+    let id = start_upload(&server, &token, "report.txt").await;
+    put_part(&server, &token, &id, 2, b"broken".to_vec()).await;
+    put_part(&server, &token, &id, 1, vec![b'x'; 5 * 1024 * 1024]).await;
+    put_part(&server, &token, &id, 2, b"world".to_vec()).await;
+
+    let response = server
+        .post(&format!("/files/uploads/{id}/complete"))
+        .authorization_bearer(&token)
+        .json(&json!({ "parts": [1, 2] }))
+        .await;
+
+    response.assert_status_ok();
+    let info: Value = response.json();
+    assert_eq!(info["name"], "report.txt");
+    assert_eq!(info["size"], 11);
+    let requests = s3.received_requests().await.unwrap();
+    let retried = requests
+        .iter()
+        .filter(|r| r.url.query().is_some_and(|q| q.contains("partNumber=2")))
+        .count();
+    assert_eq!(retried, 2);
+    let complete = requests
+        .iter()
+        .find(|r| r.method.as_str() == "POST" && r.url.query() == Some("uploadId=upload-1"))
+        .unwrap();
+    let body = String::from_utf8_lossy(&complete.body);
+    let (first, second) = (body.find("part-1").unwrap(), body.find("part-2").unwrap());
+    assert!(first < second, "{body}");
+    assert!(
+        chunked_upload::find(&boot.app_context.db, &id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn completing_without_every_part_is_refused() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_multipart(&s3).await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server_with_parts(&boot.app_context, s3_client(&s3));
+
+    let id = start_upload(&server, &token, "report.txt").await;
+    put_part(&server, &token, &id, 1, b"hello ".to_vec()).await;
+    let complete = |parts: Value| {
+        server
+            .post(&format!("/files/uploads/{id}/complete"))
+            .authorization_bearer(&token)
+            .json(&json!({ "parts": parts }))
+    };
+
+    let response = complete(json!([1, 2, 3])).await;
+    response.assert_status(StatusCode::CONFLICT);
+    let body: Value = response.json();
+    assert_eq!(body["details"]["missing"], json!([2, 3]));
+
+    put_part(&server, &token, &id, 2, b"world".to_vec()).await;
+    complete(json!([2, 1])).await.assert_status_bad_request();
+    let response = complete(json!([1, 2])).await;
+    response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    let body: Value = response.json();
+    assert_eq!(body["details"]["parts"], json!([1]));
+
+    assert!(
+        chunked_upload::find(&boot.app_context.db, &id)
+            .await
+            .unwrap()
+            .is_some()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn an_aborted_upload_is_gone() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_multipart(&s3).await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server_with_parts(&boot.app_context, s3_client(&s3));
+
+    let id = start_upload(&server, &token, "report.txt").await;
+    put_part(&server, &token, &id, 1, b"hello".to_vec()).await;
+
+    server
+        .delete(&format!("/files/uploads/{id}"))
+        .authorization_bearer(&token)
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+
+    let requests = s3.received_requests().await.unwrap();
+    assert!(
+        requests
+            .iter()
+            .any(|r| r.method.as_str() == "DELETE" && r.url.query() == Some("uploadId=upload-1"))
+    );
+    server
+        .put(&format!("/files/uploads/{id}/parts/2"))
+        .authorization_bearer(&token)
+        .bytes(b"world".to_vec().into())
+        .await
+        .assert_status_not_found();
+}
@@ -0,0 +1,105 @@
+use axum::http::{HeaderName, HeaderValue, Method};
+use axum_test::multipart::{MultipartForm, Part};
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use server::app::App;
+use std::sync::Arc;
+
+use super::{bearer_token, test_server};
+
+/// Allowed, with credentials, in `config/test.yaml`.
+const APP_ORIGIN: &str = "https://app.example.com";
+
+fn header(name: &'static str) -> HeaderName {
+    HeaderName::from_static(name)
+}
+
+#[tokio::test]
+async fn preflights_are_answered_for_routes_with_parameters() {
+    let boot = boot_test::<App>().await.unwrap();
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+
+    let response = server
+        .method(Method::OPTIONS, "/files/report.txt")
+        .add_header(header("origin"), HeaderValue::from_static(APP_ORIGIN))
+        .add_header(
+            header("access-control-request-method"),
+            HeaderValue::from_static("DELETE"),
+        )
+        .add_header(
+            header("access-control-request-headers"),
+            HeaderValue::from_static("authorization"),
+        )
+        .await;
+
+    response.assert_status_ok();
+    assert_eq!(response.header("access-control-allow-origin"), APP_ORIGIN);
+    assert_eq!(response.header("access-control-allow-credentials"), "true");
+    assert_eq!(
+        response.header("access-control-allow-headers"),
+        "authorization"
+    );
+    assert!(
+        response
+            .header("access-control-allow-methods")
+            .to_str()
+            .unwrap()
+            .contains("DELETE")
+    );
+    assert_eq!(response.header("access-control-max-age"), "3600");
+}
+
+#[tokio::test]
+async fn downloads_expose_their_headers_to_the_allowed_origin() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(MultipartForm::new().add_part(
+            "file",
+            Part::bytes(b"hello".as_slice()).file_name("report.txt"),
+        ))
+        .await
+        .assert_status_ok();
+
+    let response = server
+        .get("/files/report.txt")
+        .add_header(header("origin"), HeaderValue::from_static(APP_ORIGIN))
+        .await;
+
+    response.assert_status_ok();
+    assert_eq!(response.header("access-control-allow-origin"), APP_ORIGIN);
+    let exposed = response
+        .header("access-control-expose-headers")
+        .to_str()
+        .unwrap()
+        .to_ascii_lowercase();
+    assert!(exposed.contains("etag"), "{exposed}");
+    assert!(exposed.contains("content-disposition"), "{exposed}");
+}
+
+#[tokio::test]
+async fn other_origins_get_no_cors_headers() {
+    let boot = boot_test::<App>().await.unwrap();
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+
+    let response = server
+        .method(Method::OPTIONS, "/files/report.txt")
+        .add_header(
+            header("origin"),
+            HeaderValue::from_static("https://evil.example.com"),
+        )
+        .add_header(
+            header("access-control-request-method"),
+            HeaderValue::from_static("GET"),
+        )
+        .await;
+
+    assert!(
+        response
+            .maybe_header("access-control-allow-origin")
+            .is_none()
+    );
+}
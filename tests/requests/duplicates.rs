@@ -0,0 +1,86 @@
+use axum::http::StatusCode;
+use loco_rs::testing::prelude::*;
+use object_store::{ObjectStore, memory::InMemory, path::Path as ObjectPath};
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::app::App;
+use std::sync::Arc;
+
+use super::{admin_token, bearer_token, test_server};
+
+fn names(group: &Value) -> Vec<&str> {
+    group["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| f["name"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+#[serial]
+async fn identical_files_are_grouped_by_hash() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let admin = admin_token();
+    let store = Arc::new(InMemory::new());
+    let server = test_server(&boot.app_context, store.clone());
+    // "quarterly", three times, and "notes" twice.
+    for (name, data) in [
+        ("q3.txt", "cXVhcnRlcmx5"),
+        ("copy of q3.txt", "cXVhcnRlcmx5"),
+        ("old/q3.txt", "cXVhcnRlcmx5"),
+        ("notes.txt", "bm90ZXM="),
+        ("notes (1).txt", "bm90ZXM="),
+        ("unique.txt", "b25l"),
+    ] {
+        server
+            .post("/files/base64")
+            .authorization_bearer(&token)
+            .json(&json!({ "name": name, "data": data }))
+            .await
+            .assert_status_ok();
+    }
+    // Found in storage by the sync, so neither has a hash.
+    store
+        .put(&ObjectPath::from("dropped-in.txt"), "quarterly".into())
+        .await
+        .unwrap();
+    server
+        .post("/files/sync/storage")
+        .authorization_bearer(&admin)
+        .await
+        .assert_status_ok();
+
+    let response = server
+        .get("/files/duplicates")
+        .authorization_bearer(&admin)
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["skipped_without_hash"], 1);
+    let groups = body["groups"].as_array().unwrap();
+    assert_eq!(groups.len(), 2);
+    assert_eq!(
+        groups[0]["sha256"],
+        "52cb0c6abb02dced4eb97286d6f91d4c4d09dffcd60797f58a2915a53b981412"
+    );
+    assert_eq!(
+        names(&groups[0]),
+        ["q3.txt", "copy of q3.txt", "old/q3.txt"]
+    );
+    assert_eq!(names(&groups[1]), ["notes.txt", "notes (1).txt"]);
+
+    let response = server
+        .get("/files/duplicates?min_count=3")
+        .authorization_bearer(&admin)
+        .await;
+    let groups: Value = response.json();
+    assert_eq!(groups["groups"].as_array().unwrap().len(), 1);
+
+    server
+        .get("/files/duplicates")
+        .authorization_bearer(&token)
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+}
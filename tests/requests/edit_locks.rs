@@ -0,0 +1,281 @@
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use loco_rs::{app::AppContext, testing::prelude::*};
+use object_store::memory::InMemory;
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::{
+    app::App,
+    controllers::auth,
+    models::{file_edit_lock, role, user},
+};
+use std::{sync::Arc, time::Duration};
+
+use super::{admin_token, bearer_token, test_server};
+
+/// A second user besides `bearer_token`'s, and their token.
+async fn editor(ctx: &AppContext) -> (user::Model, String) {
+    let role = role::create(&ctx.db, "editor", json!(["read"]))
+        .await
+        .unwrap();
+    let editor = user::create(&ctx.db, "Editor", "editor", "unused", role.id)
+        .await
+        .unwrap();
+    let token =
+        auth::generate_token(&editor.id.to_string(), &editor.login, vec!["editor".into()]).unwrap();
+    (editor, token)
+}
+
+async fn upload(server: &TestServer, token: &str, name: &str) -> axum_test::TestResponse {
+    server
+        .post("/files/base64")
+        .authorization_bearer(token)
+        .json(&json!({ "name": name, "data": "bm90ZXM=" }))
+        .await
+}
+
+#[tokio::test]
+#[serial]
+async fn others_cannot_overwrite_or_delete_a_file_being_edited() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let (editor, editor_token) = editor(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    upload(&server, &token, "report.txt")
+        .await
+        .assert_status_ok();
+
+    let response = server
+        .post("/files/report.txt/lock")
+        .authorization_bearer(&editor_token)
+        .await;
+    response.assert_status_ok();
+    let lock: Value = response.json();
+    assert_eq!(lock["holder"]["id"], editor.id);
+    assert_eq!(lock["holder"]["login"], "editor");
+
+    let response = upload(&server, &token, "report.txt").await;
+    response.assert_status(StatusCode::LOCKED);
+    let body: Value = response.json();
+    assert_eq!(body["code"], "edit_locked");
+    assert_eq!(body["details"]["holder"]["login"], "editor");
+    assert_eq!(body["details"]["expires_at"], lock["expires_at"]);
+
+    let response = server
+        .delete("/files/report.txt")
+        .authorization_bearer(&token)
+        .await;
+    response.assert_status(StatusCode::LOCKED);
+
+    // The holder edits as usual.
+    upload(&server, &editor_token, "report.txt")
+        .await
+        .assert_status_ok();
+
+    let status: Value = server
+        .get("/files/report.txt/lock")
+        .authorization_bearer(&token)
+        .await
+        .json();
+    assert_eq!(status["locked"], true);
+    assert_eq!(status["lock"]["holder"]["login"], "editor");
+}
+
+#[tokio::test]
+#[serial]
+async fn a_lock_is_refreshed_and_released_only_by_its_holder() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let (_, editor_token) = editor(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    upload(&server, &token, "report.txt")
+        .await
+        .assert_status_ok();
+
+    let lock: Value = server
+        .post("/files/report.txt/lock?ttl_seconds=60")
+        .authorization_bearer(&editor_token)
+        .await
+        .json();
+
+    for response in [
+        server
+            .post("/files/report.txt/lock")
+            .authorization_bearer(&token)
+            .await,
+        server
+            .patch("/files/report.txt/lock")
+            .authorization_bearer(&token)
+            .await,
+        server
+            .delete("/files/report.txt/lock")
+            .authorization_bearer(&token)
+            .await,
+    ] {
+        response.assert_status(StatusCode::LOCKED);
+        let body: Value = response.json();
+        assert_eq!(body["details"]["holder"]["login"], "editor");
+    }
+
+    let response = server
+        .patch("/files/report.txt/lock?ttl_seconds=600")
+        .authorization_bearer(&editor_token)
+        .await;
+    response.assert_status_ok();
+    let refreshed: Value = response.json();
+    assert_eq!(refreshed["acquired_at"], lock["acquired_at"]);
+    assert!(refreshed["expires_at"].as_str() > lock["expires_at"].as_str());
+
+    let response = server
+        .delete("/files/report.txt/lock")
+        .authorization_bearer(&editor_token)
+        .await;
+    response.assert_status_ok();
+    let status: Value = response.json();
+    assert_eq!(status["locked"], false);
+
+    let status: Value = server
+        .get("/files/report.txt/lock")
+        .authorization_bearer(&token)
+        .await
+        .json();
+    assert_eq!(status["locked"], false);
+    let response = server
+        .patch("/files/report.txt/lock")
+        .authorization_bearer(&editor_token)
+        .await;
+    response.assert_status(StatusCode::NOT_FOUND);
+    let body: Value = response.json();
+    assert_eq!(body["code"], "not_locked");
+
+    server
+        .post("/files/report.txt/lock")
+        .authorization_bearer(&token)
+        .await
+        .assert_status_ok();
+}
+
+#[tokio::test]
+#[serial]
+async fn stale_locks_stop_counting_and_can_be_taken_over() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let (_, editor_token) = editor(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    upload(&server, &token, "report.txt")
+        .await
+        .assert_status_ok();
+    // Expires at once, as if its holder had closed their editor without unlocking.
+    let tester = user::find_by_login(&ctx.db, "tester")
+        .await
+        .unwrap()
+        .unwrap();
+    file_edit_lock::acquire(&ctx.db, "report.txt", tester.id, 0)
+        .await
+        .unwrap()
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let status: Value = server
+        .get("/files/report.txt/lock")
+        .authorization_bearer(&editor_token)
+        .await
+        .json();
+    assert_eq!(status["locked"], false);
+    upload(&server, &editor_token, "report.txt")
+        .await
+        .assert_status_ok();
+
+    let response = server
+        .post("/files/report.txt/lock")
+        .authorization_bearer(&editor_token)
+        .await;
+    response.assert_status_ok();
+    let lock: Value = response.json();
+    assert_eq!(lock["holder"]["login"], "editor");
+}
+
+#[tokio::test]
+#[serial]
+async fn only_one_of_concurrent_acquires_wins() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+
+    let attempts =
+        (1..=8).map(|holder| file_edit_lock::acquire(&ctx.db, "report.txt", holder, 300));
+    let won: Vec<_> = futures_util::future::join_all(attempts)
+        .await
+        .into_iter()
+        .filter_map(|attempt| attempt.unwrap())
+        .collect();
+
+    assert_eq!(won.len(), 1);
+    let held = file_edit_lock::find_active(&ctx.db, "report.txt")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(held.holder_id, won[0].holder_id);
+}
+
+#[tokio::test]
+#[serial]
+async fn admins_may_release_anyones_lock() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let admin = admin_token();
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    upload(&server, &token, "report.txt")
+        .await
+        .assert_status_ok();
+    server
+        .post("/files/report.txt/lock")
+        .authorization_bearer(&token)
+        .await
+        .assert_status_ok();
+
+    server
+        .delete("/files/report.txt/lock")
+        .authorization_bearer(&admin)
+        .await
+        .assert_status_ok();
+
+    let status: Value = server
+        .get("/files/report.txt/lock")
+        .authorization_bearer(&token)
+        .await
+        .json();
+    assert_eq!(status["locked"], false);
+}
+
+#[tokio::test]
+#[serial]
+async fn locks_need_an_existing_file_and_a_sane_ttl() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+
+    let response = server
+        .post("/files/missing.txt/lock")
+        .authorization_bearer(&token)
+        .await;
+    response.assert_status(StatusCode::NOT_FOUND);
+
+    upload(&server, &token, "report.txt")
+        .await
+        .assert_status_ok();
+    for ttl in ["0", "86401"] {
+        let response = server
+            .post(&format!("/files/report.txt/lock?ttl_seconds={ttl}"))
+            .authorization_bearer(&token)
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    let response = server.post("/files/report.txt/lock").await;
+    response.assert_status(StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,915 @@
+use axum::http::StatusCode;
+use axum_test::multipart::{MultipartForm, Part};
+use loco_rs::{app::AppContext, testing::prelude::*};
+use object_store::{
+    RetryConfig,
+    aws::{AmazonS3, AmazonS3Builder},
+};
+use serde_json::Value;
+use serial_test::serial;
+use server::{app::App, models::file};
+use std::sync::Arc;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path, path_regex, query_param},
+};
+
+use super::{bearer_token, test_server};
+
+const BUCKET: &str = "files";
+
+/// An S3 client for `server`, without retries so failures surface at once.
+pub(super) fn s3_client(server: &MockServer) -> Arc<AmazonS3> {
+    let store = AmazonS3Builder::new()
+        .with_endpoint(server.uri())
+        .with_allow_http(true)
+        .with_bucket_name(BUCKET)
+        .with_region("us-east-1")
+        .with_access_key_id("test")
+        .with_secret_access_key("test")
+        .with_retry(RetryConfig {
+            max_retries: 0,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    Arc::new(store)
+}
+
+pub(super) fn object_path(key: &str) -> String {
+    format!("/{BUCKET}/{key}")
+}
+
+pub(super) async fn mock_puts(server: &MockServer) {
+    Mock::given(method("PUT"))
+        .and(path_regex(format!("^/{BUCKET}/.+")))
+        .respond_with(ResponseTemplate::new(200).insert_header("ETag", "\"test-etag\""))
+        .mount(server)
+        .await;
+}
+
+pub(super) fn object_response(body: &'static str) -> ResponseTemplate {
+    ResponseTemplate::new(200)
+        .insert_header("ETag", "\"test-etag\"")
+        .insert_header("Last-Modified", "Tue, 15 Oct 2024 12:00:00 GMT")
+        .set_body_string(body)
+}
+
+/// Indexes objects the mock bucket serves, as `sync_storage` would.
+async fn index(ctx: &AppContext, names: &[&str]) {
+    for name in names {
+        file::create_unattributed(&ctx.db, name, 0, None)
+            .await
+            .unwrap();
+    }
+}
+
+fn upload_form(parts: &[(&str, &'static str)]) -> MultipartForm {
+    parts
+        .iter()
+        .fold(MultipartForm::new(), |form, (name, body)| {
+            form.add_part("file", Part::bytes(body.as_bytes()).file_name(*name))
+        })
+}
+
+#[tokio::test]
+#[serial]
+async fn upload_stores_every_file_of_a_multipart_request() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_puts(&s3).await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let response = server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(upload_form(&[("a.bin", "first"), ("b.bin", "second")]))
+        .await;
+
+    response.assert_status_ok();
+    let body: Value = response.json();
+    let names: Vec<&str> = body["uploaded"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| f["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["a.bin", "b.bin"]);
+    assert!(
+        body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|r| r["status"] == "stored" && r["e_tag"] == "\"test-etag\"")
+    );
+
+    let puts: Vec<String> = s3
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|r| r.method.as_str() == "PUT")
+        .map(|r| r.url.path().to_string())
+        .collect();
+    assert!(puts.contains(&object_path("a.bin")));
+    assert!(puts.contains(&object_path("b.bin")));
+}
+
+#[tokio::test]
+#[serial]
+async fn a_destination_prefix_puts_the_files_under_it() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_puts(&s3).await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let form = MultipartForm::new()
+        .add_text("destination_prefix", "invoices/2024/")
+        .add_part(
+            "file",
+            Part::bytes("total".as_bytes()).file_name("report.pdf"),
+        );
+    let response = server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(form)
+        .await;
+
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["uploaded"][0]["name"], "invoices/2024/report.pdf");
+    let puts: Vec<String> = s3
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|r| r.method.as_str() == "PUT")
+        .map(|r| r.url.path().to_string())
+        .collect();
+    assert!(puts.contains(&object_path("invoices/2024/report.pdf")));
+
+    for prefix in ["__trash__/", "../up", "versions"] {
+        let form = MultipartForm::new()
+            .add_text("destination_prefix", prefix)
+            .add_part(
+                "file",
+                Part::bytes("total".as_bytes()).file_name("report.pdf"),
+            );
+        let response = server
+            .post("/files")
+            .authorization_bearer(&token)
+            .multipart(form)
+            .await;
+        response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    let form = upload_form(&[("a.bin", "first")]).add_text("destination_prefix", "late/");
+    let response = server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(form)
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+#[serial]
+async fn each_part_of_a_mixed_upload_gets_its_own_outcome() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_puts(&s3).await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+    // One byte over `max_file_size_bytes` in config/test.yaml.
+    let too_large = vec![b'x'; 16 * 1024 * 1024 + 1];
+    let form = MultipartForm::new()
+        .add_part("file", Part::bytes(&b"fine"[..]).file_name("fine.txt"))
+        .add_part(
+            "file",
+            Part::bytes(&b"sneaky"[..]).file_name("../escape.txt"),
+        )
+        .add_part("file", Part::bytes(too_large).file_name("huge.bin"));
+
+    let response = server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(form)
+        .await;
+
+    response.assert_status_ok();
+    let body: Value = response.json();
+    let outcomes: Vec<(&str, &str, i64)> = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| {
+            (
+                r["name"].as_str().unwrap(),
+                r["status"].as_str().unwrap(),
+                r["size"].as_i64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        outcomes,
+        [
+            ("fine.txt", "stored", 4),
+            ("../escape.txt", "rejected", 6),
+            ("huge.bin", "rejected", 16 * 1024 * 1024 + 1),
+        ]
+    );
+    assert_eq!(body["results"][1]["code"], "bad_request");
+    assert_eq!(body["results"][1]["reason"], "Invalid file name");
+    assert!(body["results"][0]["code"].is_null());
+    assert_eq!(body["results"][2]["code"], "bad_request");
+    assert!(
+        body["results"][2]["reason"]
+            .as_str()
+            .unwrap()
+            .contains("byte limit")
+    );
+    let uploaded = body["uploaded"].as_array().unwrap();
+    assert_eq!(uploaded.len(), 1);
+    assert_eq!(uploaded[0]["name"], "fine.txt");
+
+    let puts: Vec<String> = s3
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|r| r.method.as_str() == "PUT")
+        .map(|r| r.url.path().to_string())
+        .collect();
+    assert!(puts.contains(&object_path("fine.txt")));
+    assert!(puts.iter().all(|p| p.ends_with("fine.txt")), "{puts:?}");
+}
+
+#[tokio::test]
+#[serial]
+async fn upload_reports_a_failed_s3_write() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&s3)
+        .await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let response = server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(upload_form(&[("a.bin", "first")]))
+        .await;
+
+    response.assert_status(StatusCode::BAD_REQUEST);
+    let body: Value = response.json();
+    let result = &body["results"][0];
+    assert_eq!(result["status"], "failed");
+    assert_eq!(result["code"], "storage_auth");
+    // The backend's own error, which names its endpoint, stays in the log.
+    let reason = result["reason"].as_str().unwrap();
+    assert!(
+        reason.starts_with("The storage backend refused the server's credentials"),
+        "{reason}"
+    );
+    assert!(!reason.contains("127.0.0.1"), "{reason}");
+    assert!(body["uploaded"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+#[serial]
+async fn upload_requires_a_token() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let response = server
+        .post("/files")
+        .multipart(upload_form(&[("a.bin", "first")]))
+        .await;
+
+    response.assert_status_unauthorized();
+    let body: Value = response.json();
+    assert_eq!(body["code"], "unauthorized");
+    assert!(s3.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+#[serial]
+async fn upload_without_files_is_rejected() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let response = server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(MultipartForm::new().add_text("note", "no file here"))
+        .await;
+
+    response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    let body: Value = response.json();
+    assert_eq!(body["results"][0]["status"], "rejected");
+}
+
+#[tokio::test]
+#[serial]
+async fn get_file_streams_the_stored_object() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_puts(&s3).await;
+    Mock::given(method("GET"))
+        .and(path(object_path("report.txt")))
+        .respond_with(object_response("hello from s3"))
+        .mount(&s3)
+        .await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(upload_form(&[("report.txt", "hello from s3")]))
+        .await
+        .assert_status_ok();
+
+    let response = server.get("/files/report.txt").await;
+
+    response.assert_status_ok();
+    response.assert_text("hello from s3");
+    assert_eq!(response.header("content-type"), "text/plain");
+    assert_eq!(
+        response.header("content-disposition"),
+        "attachment; filename=\"report.txt\""
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn downloads_are_counted() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_puts(&s3).await;
+    Mock::given(method("GET"))
+        .and(path(object_path("report.txt")))
+        .respond_with(object_response("hello from s3"))
+        .mount(&s3)
+        .await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+    server
+        .get("/files/report.txt/download-count")
+        .await
+        .assert_status_not_found();
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(upload_form(&[("report.txt", "hello from s3")]))
+        .await
+        .assert_status_ok();
+
+    let counted: Value = server.get("/files/report.txt/download-count").await.json();
+    assert_eq!(counted["count"], 0);
+    assert!(counted["last_downloaded_at"].is_null());
+
+    for _ in 0..2 {
+        server.get("/files/report.txt").await.assert_status_ok();
+    }
+
+    // Rows are written in the background, after the response.
+    let mut counted = Value::Null;
+    for _ in 0..50 {
+        counted = server.get("/files/report.txt/download-count").await.json();
+        if counted["count"] == 2 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(counted["count"], 2);
+    assert!(counted["last_downloaded_at"].is_string());
+}
+
+#[tokio::test]
+#[serial]
+async fn gzipped_objects_are_decoded_unless_the_client_takes_gzip() {
+    use async_compression::tokio::write::GzipEncoder;
+    use tokio::io::AsyncWriteExt;
+
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    let mut encoder = GzipEncoder::new(Vec::new());
+    encoder.write_all(b"{\"rows\": []}").await.unwrap();
+    encoder.shutdown().await.unwrap();
+    let gzipped = encoder.into_inner();
+    Mock::given(method("GET"))
+        .and(path(object_path("export.json")))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("ETag", "\"test-etag\"")
+                .insert_header("Last-Modified", "Tue, 15 Oct 2024 12:00:00 GMT")
+                .insert_header("Content-Encoding", "gzip")
+                .set_body_bytes(gzipped.clone()),
+        )
+        .mount(&s3)
+        .await;
+    index(&boot.app_context, &["export.json"]).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let decoded = server.get("/files/export.json").await;
+    decoded.assert_status_ok();
+    decoded.assert_text("{\"rows\": []}");
+    assert!(decoded.maybe_header("content-encoding").is_none());
+    assert_eq!(decoded.header("vary"), "accept-encoding");
+
+    let passed = server
+        .get("/files/export.json")
+        .add_header("Accept-Encoding", "br;q=1, gzip;q=0.8")
+        .await;
+    passed.assert_status_ok();
+    assert_eq!(passed.header("content-encoding"), "gzip");
+    assert_eq!(passed.as_bytes().as_ref(), gzipped.as_slice());
+}
+
+#[tokio::test]
+#[serial]
+async fn downloads_are_compressed_unless_the_media_is_already() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    let rows = format!("[{}]", vec!["{\"id\": 1}"; 500].join(","));
+    for name in ["rows.json", "photo.png"] {
+        Mock::given(method("GET"))
+            .and(path(object_path(name)))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("ETag", "\"test-etag\"")
+                    .insert_header("Last-Modified", "Tue, 15 Oct 2024 12:00:00 GMT")
+                    .set_body_string(rows.clone()),
+            )
+            .mount(&s3)
+            .await;
+    }
+    index(&boot.app_context, &["rows.json", "photo.png"]).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let json = server
+        .get("/files/rows.json")
+        .add_header("Accept-Encoding", "br")
+        .await;
+    json.assert_status_ok();
+    assert_eq!(json.header("content-encoding"), "br");
+    assert!(json.as_bytes().len() < rows.len());
+
+    let plain = server.get("/files/rows.json").await;
+    assert!(plain.maybe_header("content-encoding").is_none());
+    plain.assert_text(&rows);
+
+    let image = server
+        .get("/files/photo.png")
+        .add_header("Accept-Encoding", "gzip, br")
+        .await;
+    image.assert_status_ok();
+    assert!(image.maybe_header("content-encoding").is_none());
+    assert_eq!(image.header("content-length"), rows.len().to_string());
+}
+
+#[tokio::test]
+#[serial]
+async fn get_file_is_404_when_s3_has_no_object() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&s3)
+        .await;
+    index(&boot.app_context, &["missing.bin"]).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let response = server.get("/files/missing.bin").await;
+
+    response.assert_status_not_found();
+    let body: Value = response.json();
+    assert_eq!(body["code"], "not_found");
+}
+
+#[tokio::test]
+#[serial]
+async fn exists_answers_in_the_body() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .and(path(object_path("report.txt")))
+        .respond_with(object_response("").insert_header("Content-Length", "6"))
+        .mount(&s3)
+        .await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&s3)
+        .await;
+    index(&boot.app_context, &["report.txt"]).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let found = server.get("/files/report.txt/exists").await;
+    found.assert_status_ok();
+    assert_eq!(
+        found.json::<Value>(),
+        serde_json::json!({ "exists": true, "name": "report.txt" })
+    );
+
+    let missing = server.get("/files/missing.bin/exists").await;
+    missing.assert_status_ok();
+    assert_eq!(
+        missing.json::<Value>(),
+        serde_json::json!({ "exists": false })
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn delete_file_removes_the_objects_and_the_row() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_puts(&s3).await;
+    Mock::given(method("GET"))
+        .and(path(format!("/{BUCKET}")))
+        .and(query_param("list-type", "2"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            "<ListBucketResult><IsTruncated>false</IsTruncated></ListBucketResult>",
+        ))
+        .mount(&s3)
+        .await;
+    Mock::given(method("DELETE"))
+        .respond_with(ResponseTemplate::new(204))
+        .mount(&s3)
+        .await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(upload_form(&[("old.bin", "bytes")]))
+        .await
+        .assert_status_ok();
+
+    let response = server
+        .delete("/files/old.bin")
+        .authorization_bearer(&token)
+        .await;
+
+    response.assert_status_ok();
+    response.assert_json(&serde_json::json!({ "deleted": "old.bin" }));
+
+    let deleted: Vec<String> = s3
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|r| r.method.as_str() == "DELETE")
+        .map(|r| r.url.path().to_string())
+        .collect();
+    assert!(deleted.contains(&object_path("old.bin")));
+
+    let listing: Value = server.get("/files").await.json();
+    assert!(listing.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+#[serial]
+async fn responses_point_at_the_cdn_copy() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_puts(&s3).await;
+    Mock::given(method("GET"))
+        .and(path(object_path("docs/q3%20report.txt")))
+        .respond_with(object_response("numbers"))
+        .mount(&s3)
+        .await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+    let cdn_url = "https://cdn.example.com/docs/q3%20report.txt";
+
+    let uploaded: Value = server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(upload_form(&[("docs/q3 report.txt", "numbers")]))
+        .await
+        .json();
+    assert_eq!(uploaded["uploaded"][0]["cdn_url"], cdn_url);
+
+    let listing: Value = server.get("/files").await.json();
+    assert_eq!(listing[0]["cdn_url"], cdn_url);
+
+    let response = server.get("/files/docs%2Fq3%20report.txt").await;
+    response.assert_status_ok();
+    assert_eq!(response.header("x-cdn-url"), cdn_url);
+}
+
+#[tokio::test]
+#[serial]
+async fn delete_file_requires_a_token() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    server
+        .delete("/files/old.bin")
+        .await
+        .assert_status_unauthorized();
+    assert!(s3.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+#[serial]
+async fn get_all_files_lists_uploads_with_their_author() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_puts(&s3).await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let empty: Value = server.get("/files").await.json();
+    assert!(empty.as_array().unwrap().is_empty());
+
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(upload_form(&[("one.bin", "1"), ("two.bin", "22")]))
+        .await
+        .assert_status_ok();
+
+    let response = server.get("/files").await;
+
+    response.assert_status_ok();
+    let listing: Value = response.json();
+    let files = listing.as_array().unwrap();
+    assert_eq!(files.len(), 2);
+    let one = files.iter().find(|f| f["name"] == "one.bin").unwrap();
+    assert_eq!(one["size"], 1);
+    assert_eq!(one["author"]["login"], "tester");
+}
+
+#[tokio::test]
+#[serial]
+async fn large_listings_are_gzipped_and_downloads_are_not() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_puts(&s3).await;
+    Mock::given(method("GET"))
+        .and(path(object_path("file-0.txt")))
+        .respond_with(object_response("plain text"))
+        .mount(&s3)
+        .await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let small = server
+        .get("/files")
+        .add_header("Accept-Encoding", "gzip")
+        .await;
+    assert!(small.maybe_header("content-encoding").is_none());
+
+    let names: Vec<String> = (0..10).map(|i| format!("file-{i}.txt")).collect();
+    let form = names.iter().fold(MultipartForm::new(), |form, name| {
+        form.add_part(
+            "file",
+            Part::bytes(vec![b'x'; 2048]).file_name(name.clone()),
+        )
+    });
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(form)
+        .await
+        .assert_status_ok();
+
+    let listing = server
+        .get("/files")
+        .add_header("Accept-Encoding", "gzip")
+        .await;
+    listing.assert_status_ok();
+    assert_eq!(listing.header("content-encoding"), "gzip");
+
+    let download = server
+        .get("/files/file-0.txt")
+        .add_header("Accept-Encoding", "gzip")
+        .await;
+    download.assert_status_ok();
+    assert!(download.maybe_header("content-encoding").is_none());
+}
+
+#[tokio::test]
+#[serial]
+async fn uploads_above_the_threshold_go_multipart() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_puts(&s3).await;
+    Mock::given(method("POST"))
+        .and(query_param("uploads", ""))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId></InitiateMultipartUploadResult>",
+        ))
+        .mount(&s3)
+        .await;
+    Mock::given(method("POST"))
+        .and(query_param("uploadId", "upload-1"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            "<CompleteMultipartUploadResult><ETag>\"multipart-etag\"</ETag></CompleteMultipartUploadResult>",
+        ))
+        .mount(&s3)
+        .await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let big = vec![b'x'; 9 * 1024 * 1024];
+    let form = MultipartForm::new()
+        .add_part("file", Part::bytes(big).file_name("big.bin"))
+        .add_part(
+            "file",
+            Part::bytes(b"small".as_slice()).file_name("small.bin"),
+        );
+    let response = server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(form)
+        .await;
+
+    response.assert_status_ok();
+    let requests = s3.received_requests().await.unwrap();
+    let parts: Vec<&str> = requests
+        .iter()
+        .filter(|r| r.method.as_str() == "PUT" && r.url.path() == object_path("big.bin"))
+        .filter_map(|r| r.url.query())
+        .collect();
+    assert_eq!(parts.len(), 2, "{parts:?}");
+    assert!(parts.iter().all(|q| q.contains("uploadId=upload-1")));
+    let single_puts: Vec<&wiremock::Request> = requests
+        .iter()
+        .filter(|r| r.method.as_str() == "PUT" && r.url.path() == object_path("small.bin"))
+        .collect();
+    assert_eq!(single_puts.len(), 1);
+    assert!(single_puts[0].url.query().is_none());
+}
+
+#[tokio::test]
+#[serial]
+async fn upload_from_url_stores_the_remote_file() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_puts(&s3).await;
+    let source = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/exports/report%20final.pdf"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("%PDF-1.7"))
+        .mount(&source)
+        .await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let response = server
+        .post("/files/from-url")
+        .authorization_bearer(&token)
+        .json(&serde_json::json!({ "url": format!("{}/exports/report%20final.pdf", source.uri()) }))
+        .await;
+
+    response.assert_status_ok();
+    let info: Value = response.json();
+    assert_eq!(info["name"], "report final.pdf");
+    assert_eq!(info["size"], 8);
+    let requests = s3.received_requests().await.unwrap();
+    let stored = requests
+        .iter()
+        .find(|r| r.method.as_str() == "PUT" && r.url.path() == object_path("report%20final.pdf"))
+        .unwrap();
+    assert_eq!(stored.body, b"%PDF-1.7");
+}
+
+fn s3_import(source: &MockServer, secret: &str) -> Value {
+    serde_json::json!({
+        "source_bucket": "legacy",
+        "source_key": "reports/q3.pdf",
+        "source_region": "eu-west-1",
+        "source_access_key": "AKIDSOURCE",
+        "source_secret_key": secret,
+        "source_endpoint": source.uri(),
+    })
+}
+
+#[tokio::test]
+#[serial]
+async fn import_from_s3_copies_the_object_with_the_given_credentials() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_puts(&s3).await;
+    let source = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/legacy/reports/q3.pdf"))
+        .respond_with(object_response("%PDF-1.7"))
+        .mount(&source)
+        .await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let response = server
+        .post("/files/import-from-s3")
+        .authorization_bearer(&token)
+        .json(&s3_import(&source, "source-secret"))
+        .await;
+
+    response.assert_status_ok();
+    let info: Value = response.json();
+    assert_eq!(info["name"], "q3.pdf");
+    assert_eq!(info["size"], 8);
+    let fetched = source.received_requests().await.unwrap();
+    let signed = fetched[0].headers["authorization"].to_str().unwrap();
+    assert!(signed.contains("Credential=AKIDSOURCE/"), "{signed}");
+    assert!(signed.contains("/eu-west-1/s3/"), "{signed}");
+    let requests = s3.received_requests().await.unwrap();
+    let stored = requests
+        .iter()
+        .find(|r| r.method.as_str() == "PUT" && r.url.path() == object_path("q3.pdf"))
+        .unwrap();
+    assert_eq!(stored.body, b"%PDF-1.7");
+}
+
+#[tokio::test]
+#[serial]
+async fn import_from_s3_reports_refused_credentials_without_them() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    let source = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(403).set_body_string(
+            "<Error><Code>SignatureDoesNotMatch</Code><AWSAccessKeyId>AKIDSOURCE</AWSAccessKeyId></Error>",
+        ))
+        .mount(&source)
+        .await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let response = server
+        .post("/files/import-from-s3")
+        .authorization_bearer(&token)
+        .json(&s3_import(&source, "wrong-secret"))
+        .await;
+
+    response.assert_status(StatusCode::FORBIDDEN);
+    let body = response.text();
+    assert!(body.contains("source_access_denied"), "{body}");
+    assert!(
+        !body.contains("AKIDSOURCE") && !body.contains("wrong-secret"),
+        "{body}"
+    );
+
+    let response = server
+        .post("/files/import-from-s3")
+        .authorization_bearer(&token)
+        .json(&s3_import(&source, ""))
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(source.received_requests().await.unwrap().len(), 1);
+    assert!(s3.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+#[serial]
+async fn a_repeat_upload_with_deduplicate_returns_the_existing_file() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    mock_puts(&s3).await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+    let first = server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(upload_form(&[("original.txt", "same bytes")]))
+        .await;
+    first.assert_status_ok();
+    let puts = |requests: Vec<wiremock::Request>| {
+        requests
+            .into_iter()
+            .filter(|r| r.method.as_str() == "PUT")
+            .count()
+    };
+    let puts_before = puts(s3.received_requests().await.unwrap());
+
+    let response = server
+        .post("/files?deduplicate=true")
+        .authorization_bearer(&token)
+        .multipart(upload_form(&[("copy.txt", "same bytes")]))
+        .await;
+
+    response.assert_status_ok();
+    assert_eq!(response.header("X-Deduplicated"), "true");
+    let result = &response.json::<Value>()["results"][0];
+    assert_eq!(result["status"], "deduplicated");
+    assert_eq!(result["name"], "original.txt");
+    assert_eq!(
+        result["file"]["id"],
+        first.json::<Value>()["results"][0]["file"]["id"]
+    );
+    assert_eq!(puts(s3.received_requests().await.unwrap()), puts_before);
+}
@@ -0,0 +1,396 @@
+use axum::http::StatusCode;
+use futures_util::StreamExt;
+use loco_rs::testing::prelude::*;
+use object_store::{ObjectStore, PutPayload, memory::InMemory, path::Path as ObjectPath};
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::{app::App, models::file};
+use std::sync::Arc;
+
+use super::{bearer_token, seed_all, test_server};
+
+async fn exists(store: &dyn ObjectStore, key: &str) -> bool {
+    store.head(&ObjectPath::from(key)).await.is_ok()
+}
+
+#[tokio::test]
+#[serial]
+async fn deletes_files_rows_and_stray_objects_under_the_prefix() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let server = test_server(ctx, store.clone());
+    seed_all(
+        &server,
+        &token,
+        &[
+            "projects/old/a.txt",
+            "projects/old/deep/b.txt",
+            "projects/older.txt",
+            "c.txt",
+        ],
+    )
+    .await;
+    store
+        .put(
+            &ObjectPath::from("projects/old/stray.bin"),
+            PutPayload::from_static(b"abc"),
+        )
+        .await
+        .unwrap();
+
+    let response = server
+        .delete("/files/folder?prefix=projects/old")
+        .authorization_bearer(&token)
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["prefix"], "projects/old/");
+    assert_eq!(body["dry_run"], false);
+    assert_eq!(
+        body["files"],
+        json!(["projects/old/a.txt", "projects/old/deep/b.txt"])
+    );
+    assert_eq!(body["deleted_objects"], 3);
+    assert_eq!(body["bytes_freed"], 13);
+    assert_eq!(body["failures"], json!([]));
+
+    for key in [
+        "projects/old/a.txt",
+        "projects/old/deep/b.txt",
+        "projects/old/stray.bin",
+    ] {
+        assert!(!exists(store.as_ref(), key).await, "{key}");
+    }
+    assert!(
+        file::find_by_name(&ctx.db, "projects/old/a.txt")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    for key in ["projects/older.txt", "c.txt"] {
+        assert!(exists(store.as_ref(), key).await, "{key}");
+        assert!(file::find_by_name(&ctx.db, key).await.unwrap().is_some());
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn a_dry_run_reports_without_deleting() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let server = test_server(ctx, store.clone());
+    seed_all(&server, &token, &["projects/old/a.txt", "projects/b.txt"]).await;
+
+    let response = server
+        .delete("/files/folder?prefix=projects/old/&dry_run=true")
+        .authorization_bearer(&token)
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["dry_run"], true);
+    assert_eq!(body["files"], json!(["projects/old/a.txt"]));
+    assert_eq!(body["deleted_objects"], 1);
+    assert_eq!(body["bytes_freed"], 5);
+
+    assert!(exists(store.as_ref(), "projects/old/a.txt").await);
+    assert!(
+        file::find_by_name(&ctx.db, "projects/old/a.txt")
+            .await
+            .unwrap()
+            .is_some()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn an_empty_prefix_needs_confirm_all() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let server = test_server(ctx, store.clone());
+    seed_all(&server, &token, &["a.txt", "projects/b.txt"]).await;
+
+    for query in ["", "?prefix=", "?prefix=/", "?prefix=&confirm=yes"] {
+        let response = server
+            .delete(&format!("/files/folder{query}"))
+            .authorization_bearer(&token)
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+    assert!(exists(store.as_ref(), "a.txt").await);
+
+    let response = server
+        .delete("/files/folder?prefix=&confirm=ALL")
+        .authorization_bearer(&token)
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["files"], json!(["a.txt", "projects/b.txt"]));
+    assert!(!exists(store.as_ref(), "a.txt").await);
+    assert!(!exists(store.as_ref(), "projects/b.txt").await);
+    assert!(file::find_all(&ctx.db).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+#[serial]
+async fn rejects_bad_prefixes_and_missing_tokens() {
+    let boot = boot_test::<App>().await.unwrap();
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    server
+        .delete("/files/folder?prefix=projects/")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let token = bearer_token(&boot.app_context).await;
+    server
+        .delete("/files/folder?prefix=projects/../etc/")
+        .authorization_bearer(&token)
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+async fn read(store: &dyn ObjectStore, key: &str) -> String {
+    let bytes = store
+        .get(&ObjectPath::from(key))
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn moves_files_and_stray_objects_to_the_new_folder() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let server = test_server(ctx, store.clone());
+    seed_all(
+        &server,
+        &token,
+        &[
+            "projects/alpha/a.txt",
+            "projects/alpha/deep/b.txt",
+            "projects/alphabet.txt",
+        ],
+    )
+    .await;
+    store
+        .put(
+            &ObjectPath::from("projects/alpha/stray.bin"),
+            PutPayload::from_static(b"abc"),
+        )
+        .await
+        .unwrap();
+    let id = file::find_by_name(&ctx.db, "projects/alpha/a.txt")
+        .await
+        .unwrap()
+        .unwrap()
+        .id;
+
+    let response = server
+        .post("/files/folder/move")
+        .authorization_bearer(&token)
+        .json(&json!({ "from": "projects/alpha", "to": "projects/alpha-archived/" }))
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["from"], "projects/alpha/");
+    assert_eq!(body["to"], "projects/alpha-archived/");
+    assert_eq!(body["moved"], 3);
+    assert_eq!(body["skipped"], 0);
+    assert_eq!(body["failures"], json!([]));
+
+    let moved = file::find_by_name(&ctx.db, "projects/alpha-archived/a.txt")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(moved.id, id);
+    assert!(
+        file::find_by_name(&ctx.db, "projects/alpha/a.txt")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(
+        read(store.as_ref(), "projects/alpha-archived/deep/b.txt").await,
+        "notes"
+    );
+    assert_eq!(
+        read(store.as_ref(), "projects/alpha-archived/stray.bin").await,
+        "abc"
+    );
+    let left: Vec<_> = store
+        .list(Some(&ObjectPath::from("projects/alpha")))
+        .collect()
+        .await;
+    assert!(left.is_empty(), "{left:?}");
+    assert!(exists(store.as_ref(), "projects/alphabet.txt").await);
+
+    server
+        .get("/files/projects%2Falpha-archived%2Fa.txt")
+        .await
+        .assert_status_ok();
+}
+
+#[tokio::test]
+#[serial]
+async fn a_non_empty_destination_needs_overwrite_or_merge() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let server = test_server(ctx, store.clone());
+    seed_all(&server, &token, &["old/a.txt", "old/b.txt"]).await;
+    server
+        .post("/files/base64")
+        .authorization_bearer(&token)
+        .json(&json!({ "name": "new/a.txt", "data": "b3RoZXI=" }))
+        .await
+        .assert_status_ok();
+
+    let response = server
+        .post("/files/folder/move")
+        .authorization_bearer(&token)
+        .json(&json!({ "from": "old/", "to": "new/" }))
+        .await;
+    response.assert_status(StatusCode::CONFLICT);
+    assert_eq!(response.json::<Value>()["code"], "destination_not_empty");
+
+    let response = server
+        .post("/files/folder/move")
+        .authorization_bearer(&token)
+        .json(&json!({ "from": "old/", "to": "new/", "merge": true }))
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["moved"], 1);
+    assert_eq!(body["skipped"], 1);
+    assert_eq!(read(store.as_ref(), "new/a.txt").await, "other");
+    assert_eq!(read(store.as_ref(), "new/b.txt").await, "notes");
+    assert_eq!(read(store.as_ref(), "old/a.txt").await, "notes");
+
+    let response = server
+        .post("/files/folder/move")
+        .authorization_bearer(&token)
+        .json(&json!({ "from": "old/", "to": "new/", "overwrite": true }))
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["moved"], 1);
+    assert_eq!(body["skipped"], 0);
+    assert_eq!(read(store.as_ref(), "new/a.txt").await, "notes");
+    assert!(!exists(store.as_ref(), "old/a.txt").await);
+    assert!(
+        file::find_by_name(&ctx.db, "old/a.txt")
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn a_rerun_finishes_an_interrupted_move_without_duplicates() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let server = test_server(ctx, store.clone());
+    seed_all(&server, &token, &["old/a.txt", "old/b.txt"]).await;
+    let a = file::find_by_name(&ctx.db, "old/a.txt")
+        .await
+        .unwrap()
+        .unwrap();
+    let b = file::find_by_name(&ctx.db, "old/b.txt")
+        .await
+        .unwrap()
+        .unwrap();
+    // As a run that died after copying a.txt but not its version, and after
+    // moving all of b.txt but before renaming its row.
+    store
+        .copy(
+            &ObjectPath::from("old/a.txt"),
+            &ObjectPath::from("new/a.txt"),
+        )
+        .await
+        .unwrap();
+    for (from, to) in [
+        ("old/b.txt".to_string(), "new/b.txt".to_string()),
+        (
+            format!("versions/{}/v1/old/b.txt", b.id),
+            format!("versions/{}/v1/new/b.txt", b.id),
+        ),
+    ] {
+        store
+            .rename(&ObjectPath::from(from), &ObjectPath::from(to))
+            .await
+            .unwrap();
+    }
+
+    let response = server
+        .post("/files/folder/move")
+        .authorization_bearer(&token)
+        .json(&json!({ "from": "old/", "to": "new/", "merge": true }))
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["moved"], 2);
+    assert_eq!(body["skipped"], 0);
+    assert_eq!(body["failures"], json!([]));
+
+    let keys: Vec<String> = store
+        .list(None)
+        .map(|meta| meta.unwrap().location.to_string())
+        .collect()
+        .await;
+    assert_eq!(
+        keys,
+        [
+            "new/a.txt".to_string(),
+            "new/b.txt".to_string(),
+            format!("versions/{}/v1/new/a.txt", a.id),
+            format!("versions/{}/v1/new/b.txt", b.id),
+        ]
+    );
+    for (name, id) in [("new/a.txt", a.id), ("new/b.txt", b.id)] {
+        let moved = file::find_by_name(&ctx.db, name).await.unwrap().unwrap();
+        assert_eq!(moved.id, id);
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn folders_must_be_distinct_and_not_nested() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    for (from, to) in [
+        ("a/", "a/"),
+        ("a/", "a/b/"),
+        ("a/b", "a"),
+        ("", "b/"),
+        ("a/", "../b"),
+    ] {
+        server
+            .post("/files/folder/move")
+            .authorization_bearer(&token)
+            .json(&json!({ "from": from, "to": to }))
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+    }
+    server
+        .post("/files/folder/move")
+        .authorization_bearer(&token)
+        .json(&json!({ "from": "a/", "to": "b/", "merge": true, "overwrite": true }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,226 @@
+use axum::{
+    Extension,
+    http::{StatusCode, header},
+};
+use axum_test::{
+    TestServer,
+    multipart::{MultipartForm, Part},
+};
+use loco_rs::{app::AppContext, testing::prelude::*};
+use object_store::{ObjectStore, RetryConfig, aws::AmazonS3Builder, memory::InMemory};
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::{
+    app::App,
+    circuit_store::{CircuitPolicy, CircuitState, CircuitStore},
+};
+use std::{sync::Arc, time::Duration};
+use wiremock::{Mock, MockServer, ResponseTemplate, matchers::method};
+
+use super::{bearer_token, files::s3_client, test_router, test_server, upload};
+
+/// The `/files` routes over S3 at `server`, behind a circuit that opens
+/// after two failures.
+fn over(ctx: &AppContext, s3: Arc<dyn ObjectStore>) -> TestServer {
+    let circuit = Arc::new(CircuitState::new(CircuitPolicy {
+        failure_threshold: 2,
+        window: Duration::from_secs(60),
+        cool_down: Duration::from_secs(60),
+    }));
+    let store: Arc<dyn ObjectStore> = Arc::new(CircuitStore::new(s3, circuit.clone()));
+    TestServer::new(test_router(ctx, store).layer(Extension(circuit))).unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn a_failing_backend_is_given_a_rest() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2)
+        .mount(&s3)
+        .await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = over(&boot.app_context, s3_client(&s3));
+
+    let health: Value = server.get("/files/health").await.json();
+    assert_eq!(health["ok"], true);
+    assert_eq!(health["circuit"], "closed");
+
+    for _ in 0..2 {
+        server
+            .get("/files/stats")
+            .authorization_bearer(&token)
+            .await
+            .assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    let response = server
+        .get("/files/stats")
+        .authorization_bearer(&token)
+        .await;
+
+    response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    let retry_after: u64 = response
+        .header("retry-after")
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=60).contains(&retry_after));
+    assert_eq!(response.json::<Value>()["code"], "storage_unavailable");
+
+    let health: Value = server.get("/files/health").await.json();
+    assert_eq!(health["ok"], false);
+    assert_eq!(health["circuit"], "open");
+    assert_eq!(health["consecutive_failures"], 2);
+    assert!(health["retry_after_seconds"].as_u64().unwrap() <= 60);
+    assert_eq!(health["faults"], json!({ "storage_error": 2 }));
+    assert_eq!(health["last_fault"], "storage_error");
+}
+
+async fn stats_error(server: &TestServer, token: &str) -> Value {
+    let response = server.get("/files/stats").authorization_bearer(token).await;
+    response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    let body = response.json::<Value>();
+    let message = body["message"].as_str().unwrap();
+    assert!(
+        !message.contains("127.0.0.1") && !message.contains("test"),
+        "{message}"
+    );
+    body
+}
+
+#[tokio::test]
+#[serial]
+async fn refused_credentials_are_storage_auth() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(403).set_body_string(
+            "<Error><Code>InvalidAccessKeyId</Code><Message>The AWS Access Key Id you provided does not exist in our records.</Message><AWSAccessKeyId>test</AWSAccessKeyId></Error>",
+        ))
+        .mount(&s3)
+        .await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = over(&boot.app_context, s3_client(&s3));
+
+    let body = stats_error(&server, &token).await;
+    assert_eq!(body["code"], "storage_auth");
+    assert_eq!(body["details"]["s3_code"], "InvalidAccessKeyId");
+
+    let health: Value = server.get("/files/health").await.json();
+    assert_eq!(health["ok"], false);
+    assert_eq!(health["circuit"], "closed");
+    assert_eq!(health["faults"], json!({ "storage_auth": 1 }));
+    assert_eq!(health["last_fault"], "storage_auth");
+}
+
+#[tokio::test]
+#[serial]
+async fn a_missing_bucket_or_backend_is_storage_unreachable() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let s3 = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404).set_body_string(
+            "<Error><Code>NoSuchBucket</Code><Message>The specified bucket does not exist</Message></Error>",
+        ))
+        .mount(&s3)
+        .await;
+    let body = stats_error(&over(&boot.app_context, s3_client(&s3)), &token).await;
+    assert_eq!(body["code"], "storage_unreachable");
+    assert_eq!(body["details"]["s3_code"], "NoSuchBucket");
+
+    // Nothing listens on a port that was just given up.
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let s3 = AmazonS3Builder::new()
+        .with_endpoint(format!("http://127.0.0.1:{port}"))
+        .with_allow_http(true)
+        .with_bucket_name("bucket")
+        .with_region("us-east-1")
+        .with_access_key_id("test")
+        .with_secret_access_key("test")
+        .with_retry(RetryConfig {
+            max_retries: 0,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let server = over(&boot.app_context, Arc::new(s3));
+    let body = stats_error(&server, &token).await;
+    assert_eq!(body["code"], "storage_unreachable");
+    assert!(body["details"].is_null());
+
+    let health: Value = server.get("/files/health").await.json();
+    assert_eq!(health["ok"], false);
+    assert_eq!(health["faults"], json!({ "storage_unreachable": 1 }));
+}
+
+#[tokio::test]
+#[serial]
+async fn writes_refused_by_the_backend_are_storage_auth() {
+    let boot = boot_test::<App>().await.unwrap();
+    let s3 = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .respond_with(ResponseTemplate::new(403).set_body_string(
+            "<Error><Code>SignatureDoesNotMatch</Code><Message>The request signature we calculated does not match the signature you provided.</Message></Error>",
+        ))
+        .mount(&s3)
+        .await;
+    let token = bearer_token(&boot.app_context).await;
+    let server = over(&boot.app_context, s3_client(&s3));
+
+    let response = server
+        .post("/files/base64")
+        .authorization_bearer(&token)
+        .json(&json!({ "name": "notes.txt", "data": "bm90ZXM=" }))
+        .await;
+    response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    let body = response.json::<Value>();
+    assert_eq!(body["code"], "storage_auth");
+    assert_eq!(body["details"]["s3_code"], "SignatureDoesNotMatch");
+
+    let health: Value = server.get("/files/health").await.json();
+    assert_eq!(health["faults"], json!({ "storage_auth": 1 }));
+}
+
+#[tokio::test]
+#[serial]
+async fn an_if_match_check_the_backend_refuses_is_storage_auth() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    upload(
+        &test_server(ctx, Arc::new(InMemory::new())),
+        &token,
+        "notes.txt",
+        b"notes",
+    )
+    .await;
+    let s3 = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&s3)
+        .await;
+    let server = over(ctx, s3_client(&s3));
+
+    let response = server
+        .post("/files")
+        .authorization_bearer(&token)
+        .add_header(header::IF_MATCH, "\"abc\"")
+        .multipart(
+            MultipartForm::new()
+                .add_part("file", Part::bytes(b"new".to_vec()).file_name("notes.txt")),
+        )
+        .await;
+    response.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.json::<Value>()["code"], "storage_auth");
+
+    let health: Value = server.get("/files/health").await.json();
+    assert_eq!(health["faults"], json!({ "storage_auth": 1 }));
+}
@@ -0,0 +1,95 @@
+use async_trait::async_trait;
+use axum::{body::Bytes, http::StatusCode};
+use loco_rs::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::{
+    app::App,
+    controllers::files::FileInfo,
+    storage_hooks::{self, StorageHooks},
+};
+use std::sync::{Arc, Mutex};
+
+use super::{bearer_token, test_server};
+
+/// Shouts every upload, remembers what was stored and guards `keep-*` files.
+#[derive(Default)]
+struct RecordingHooks {
+    stored: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl StorageHooks for RecordingHooks {
+    async fn pre_upload(&self, name: &str, bytes: &Bytes) -> Result<Bytes> {
+        if name == "blocked.txt" {
+            return Err(Error::BadRequest("blocked by policy".into()));
+        }
+        Ok(bytes.to_ascii_uppercase().into())
+    }
+
+    async fn post_upload(&self, name: &str, info: &FileInfo) -> Result<()> {
+        self.stored
+            .lock()
+            .unwrap()
+            .push(format!("{name}:{}", info.size));
+        Ok(())
+    }
+
+    async fn pre_delete(&self, name: &str) -> Result<()> {
+        if name.starts_with("keep-") {
+            return Err(Error::BadRequest(format!("'{name}' is retained")));
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn uploads_and_deletes_go_through_the_installed_hooks() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let hooks = Arc::new(RecordingHooks::default());
+    storage_hooks::install(ctx, hooks.clone());
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+
+    for name in ["notes.txt", "keep-notes.txt"] {
+        server
+            .post("/files/base64")
+            .authorization_bearer(&token)
+            .json(&json!({ "name": name, "data": "bm90ZXM=" }))
+            .await
+            .assert_status_ok();
+    }
+    assert_eq!(server.get("/files/notes.txt").await.text(), "NOTES");
+    assert_eq!(
+        *hooks.stored.lock().unwrap(),
+        ["notes.txt:5", "keep-notes.txt:5"]
+    );
+
+    let response = server
+        .post("/files/base64")
+        .authorization_bearer(&token)
+        .json(&json!({ "name": "blocked.txt", "data": "bm90ZXM=" }))
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    server
+        .get("/files/blocked.txt")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    let response = server
+        .delete("/files/keep-notes.txt")
+        .authorization_bearer(&token)
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(response.json::<Value>()["code"], "bad_request");
+    server.get("/files/keep-notes.txt").await.assert_status_ok();
+
+    server
+        .delete("/files/notes.txt")
+        .authorization_bearer(&token)
+        .await
+        .assert_status_ok();
+}
@@ -0,0 +1,258 @@
+use axum::http::StatusCode;
+use axum_test::{
+    TestResponse, TestServer,
+    multipart::{MultipartForm, Part},
+};
+use chrono::{Duration, Utc};
+use loco_rs::{app::AppContext, testing::prelude::*};
+use object_store::memory::InMemory;
+use sea_orm::{EntityTrait, sea_query::Expr};
+use serde_json::Value;
+use serial_test::serial;
+use server::{
+    app::App,
+    models::{file, upload_idempotency_key, user},
+};
+use std::sync::Arc;
+
+use super::{bearer_token, test_server};
+
+async fn upload(server: &TestServer, token: &str, key: &str, content: &str) -> TestResponse {
+    server
+        .post("/files")
+        .authorization_bearer(token)
+        .add_header("Idempotency-Key", key)
+        .multipart(MultipartForm::new().add_part(
+            "file",
+            Part::bytes(content.as_bytes().to_vec()).file_name("report.pdf"),
+        ))
+        .await
+}
+
+fn is_replay(response: &TestResponse) -> bool {
+    response
+        .maybe_header("Idempotent-Replayed")
+        .is_some_and(|v| v == "true")
+}
+
+async fn stored_version(ctx: &AppContext) -> i32 {
+    file::find_by_name(&ctx.db, "report.pdf")
+        .await
+        .unwrap()
+        .unwrap()
+        .version
+}
+
+#[tokio::test]
+#[serial]
+async fn a_retry_replays_the_first_response() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+
+    let first = upload(&server, &token, "retry-1", "draft").await;
+    first.assert_status_ok();
+    assert!(!is_replay(&first));
+    let retry = upload(&server, &token, "retry-1", "draft").await;
+
+    retry.assert_status_ok();
+    assert!(is_replay(&retry));
+    assert_eq!(retry.json::<Value>(), first.json::<Value>());
+    assert_eq!(stored_version(ctx).await, 1);
+}
+
+#[tokio::test]
+#[serial]
+async fn a_key_reused_for_other_bytes_is_unprocessable() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    upload(&server, &token, "retry-1", "draft")
+        .await
+        .assert_status_ok();
+
+    let response = upload(&server, &token, "retry-1", "final").await;
+
+    response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(response.json::<Value>()["code"], "idempotency_key_reused");
+    assert_eq!(stored_version(ctx).await, 1);
+}
+
+#[tokio::test]
+#[serial]
+async fn concurrent_requests_with_one_key_store_once() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+
+    let (a, b) = tokio::join!(
+        upload(&server, &token, "retry-1", "draft"),
+        upload(&server, &token, "retry-1", "draft"),
+    );
+
+    let (stored, other) = if is_replay(&a) || a.status_code() != StatusCode::OK {
+        (b, a)
+    } else {
+        (a, b)
+    };
+    stored.assert_status_ok();
+    assert!(!is_replay(&stored));
+    if other.status_code() == StatusCode::CONFLICT {
+        assert_eq!(other.json::<Value>()["code"], "idempotency_key_in_use");
+    } else {
+        other.assert_status_ok();
+        assert!(is_replay(&other));
+    }
+    assert_eq!(stored_version(ctx).await, 1);
+}
+
+#[tokio::test]
+#[serial]
+async fn a_key_held_by_a_running_upload_conflicts() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let tester = user::find_by_login(&ctx.db, "tester")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        upload_idempotency_key::claim(&ctx.db, None, tester.id, "retry-1")
+            .await
+            .unwrap()
+            .is_some()
+    );
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+
+    let response = upload(&server, &token, "retry-1", "draft").await;
+
+    response.assert_status(StatusCode::CONFLICT);
+    assert_eq!(response.json::<Value>()["code"], "idempotency_key_in_use");
+    assert!(
+        file::find_by_name(&ctx.db, "report.pdf")
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+/// Claims `retry-1` for the tester and lets its lease run out, as if the
+/// upload holding it had died. Returns the claim's token.
+async fn abandoned_claim(ctx: &AppContext) -> String {
+    let tester = user::find_by_login(&ctx.db, "tester")
+        .await
+        .unwrap()
+        .unwrap();
+    let token = upload_idempotency_key::claim(&ctx.db, None, tester.id, "retry-1")
+        .await
+        .unwrap()
+        .unwrap();
+    let expired =
+        Utc::now().naive_utc() - Duration::minutes(upload_idempotency_key::CLAIM_LEASE_MINUTES + 1);
+    upload_idempotency_key::Entity::update_many()
+        .col_expr(
+            upload_idempotency_key::Column::CreatedAt,
+            Expr::value(expired),
+        )
+        .col_expr(
+            upload_idempotency_key::Column::LeasedAt,
+            Expr::value(expired),
+        )
+        .exec(&ctx.db)
+        .await
+        .unwrap();
+    token
+}
+
+#[tokio::test]
+#[serial]
+async fn a_stale_claim_is_taken_over() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    abandoned_claim(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+
+    let response = upload(&server, &token, "retry-1", "draft").await;
+
+    response.assert_status_ok();
+    assert!(!is_replay(&response));
+    assert_eq!(stored_version(ctx).await, 1);
+}
+
+#[tokio::test]
+#[serial]
+async fn a_claim_renewed_by_a_running_upload_is_kept() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let claim = abandoned_claim(ctx).await;
+    assert!(
+        upload_idempotency_key::renew(&ctx.db, &claim)
+            .await
+            .unwrap()
+    );
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+
+    let response = upload(&server, &token, "retry-1", "draft").await;
+
+    response.assert_status(StatusCode::CONFLICT);
+    assert_eq!(response.json::<Value>()["code"], "idempotency_key_in_use");
+}
+
+#[tokio::test]
+#[serial]
+async fn a_claim_taken_over_is_left_alone_by_its_first_holder() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let first = abandoned_claim(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    let stored = upload(&server, &token, "retry-1", "draft").await;
+    stored.assert_status_ok();
+
+    // The first upload was still running after all, and now finishes.
+    assert!(
+        !upload_idempotency_key::renew(&ctx.db, &first)
+            .await
+            .unwrap()
+    );
+    upload_idempotency_key::complete(&ctx.db, &first, "other", 500, Value::Null)
+        .await
+        .unwrap();
+    upload_idempotency_key::release(&ctx.db, &first)
+        .await
+        .unwrap();
+
+    let retry = upload(&server, &token, "retry-1", "draft").await;
+    retry.assert_status_ok();
+    assert!(is_replay(&retry));
+    assert_eq!(retry.json::<Value>(), stored.json::<Value>());
+}
+
+#[tokio::test]
+#[serial]
+async fn a_key_is_claimed_per_tenant() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+
+    for tenant in ["acme", "globex"] {
+        let response = server
+            .post("/files")
+            .authorization_bearer(&token)
+            .add_header("Idempotency-Key", "retry-1")
+            .add_header("x-tenant-id", tenant)
+            .multipart(MultipartForm::new().add_part(
+                "file",
+                Part::bytes(b"draft".to_vec()).file_name("report.pdf"),
+            ))
+            .await;
+        response.assert_status_ok();
+        assert!(!is_replay(&response), "{tenant}");
+    }
+}
@@ -0,0 +1,107 @@
+use axum::http::StatusCode;
+use axum_test::multipart::{MultipartForm, Part};
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::Value;
+use serial_test::serial;
+use server::app::App;
+use std::sync::Arc;
+
+use super::{bearer_token, test_server_with_parts};
+
+#[tokio::test]
+#[serial]
+async fn bodies_over_the_upload_limit_get_the_usual_413() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server_with_parts(&boot.app_context, Arc::new(InMemory::new()));
+
+    let limits: Value = server.get("/files/limits").await.json();
+    // `config/test.yaml` allows 16 MiB files.
+    assert_eq!(limits["max_file_size_bytes"], 16 * 1024 * 1024);
+    let body_limit = limits["max_upload_body_bytes"].as_u64().unwrap();
+    assert!(body_limit > 16 * 1024 * 1024);
+
+    // Past the 2 MB framework default, well within the upload limit.
+    let fits = vec![b'x'; 4 * 1024 * 1024];
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(MultipartForm::new().add_part("file", Part::bytes(fits).file_name("fits.bin")))
+        .await
+        .assert_status_ok();
+
+    let too_large = vec![b'x'; body_limit as usize + 1];
+    let response = server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(
+            MultipartForm::new().add_part("file", Part::bytes(too_large).file_name("huge.bin")),
+        )
+        .await;
+    response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    let body: Value = response.json();
+    assert_eq!(body["code"], "file_too_large");
+    server
+        .get("/files/huge.bin")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+#[serial]
+async fn the_request_limit_covers_all_files_together() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server_with_parts(&boot.app_context, Arc::new(InMemory::new()));
+
+    let limits: Value = server.get("/files/limits").await.json();
+    // `config/test.yaml` allows 32 MiB requests of 16 MiB files.
+    assert_eq!(limits["max_upload_body_bytes"], 32 * 1024 * 1024);
+    assert!(limits["max_sync_body_bytes"].as_u64().unwrap() < 32 * 1024 * 1024);
+
+    let file = || vec![b'x'; 12 * 1024 * 1024];
+    let response = server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(
+            MultipartForm::new()
+                .add_part("file", Part::bytes(file()).file_name("first.bin"))
+                .add_part("file", Part::bytes(file()).file_name("second.bin")),
+        )
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.json::<Value>()["results"][1]["status"], "stored");
+
+    let response = server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(
+            MultipartForm::new()
+                .add_part("file", Part::bytes(file()).file_name("a.bin"))
+                .add_part("file", Part::bytes(file()).file_name("b.bin"))
+                .add_part("file", Part::bytes(file()).file_name("c.bin")),
+        )
+        .await;
+    response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    server
+        .get("/files/a.bin")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+#[serial]
+async fn parts_over_the_limit_are_refused_before_they_are_read() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server_with_parts(&boot.app_context, Arc::new(InMemory::new()));
+
+    let response = server
+        .put("/files/uploads/unknown/parts/1")
+        .authorization_bearer(&token)
+        .bytes(vec![b'x'; 16 * 1024 * 1024 + 1].into())
+        .await;
+    response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response.json::<Value>()["code"], "file_too_large");
+}
@@ -0,0 +1,90 @@
+use axum::http::{StatusCode, header};
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::app::App;
+use std::sync::Arc;
+
+use super::{bearer_token, test_server};
+
+#[tokio::test]
+#[serial]
+async fn links_redirect_to_the_cdn_copy() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    server
+        .post("/files/base64")
+        .authorization_bearer(&token)
+        .json(&json!({ "name": "q3 report.txt", "data": "bm90ZXM=" }))
+        .await
+        .assert_status_ok();
+
+    let response = server
+        .get("/files/q3%20report.txt/link")
+        .authorization_bearer(&token)
+        .await;
+    response.assert_status(StatusCode::FOUND);
+    assert_eq!(
+        response.header(header::LOCATION),
+        "https://cdn.example.com/q3%20report.txt"
+    );
+
+    let response = server
+        .get("/files/q3%20report.txt/link?redirect=false")
+        .authorization_bearer(&token)
+        .await;
+    response.assert_status_ok();
+    let link: Value = response.json();
+    assert_eq!(
+        link,
+        json!({ "url": "https://cdn.example.com/q3%20report.txt" })
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn links_need_a_token_and_an_existing_file() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+
+    let response = server
+        .get("/files/missing.txt/link")
+        .authorization_bearer(&token)
+        .await;
+    response.assert_status_not_found();
+    let body: Value = response.json();
+    assert_eq!(body["code"], "not_found");
+
+    let response = server.get("/files/missing.txt/link").await;
+    response.assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[serial]
+async fn redirected_downloads_check_the_file_first() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    server
+        .post("/files/base64")
+        .authorization_bearer(&token)
+        .json(&json!({ "name": "notes.txt", "data": "bm90ZXM=" }))
+        .await
+        .assert_status_ok();
+
+    server
+        .get("/files/missing.txt?redirect=true")
+        .await
+        .assert_status_not_found();
+
+    // Nothing pre-signs for the in-memory store, so the file is proxied.
+    let response = server.get("/files/notes.txt?redirect=true").await;
+    response.assert_status_ok();
+    assert_eq!(response.text(), "notes");
+}
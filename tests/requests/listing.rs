@@ -0,0 +1,262 @@
+use axum::http::{HeaderName, HeaderValue, StatusCode};
+use axum_test::{
+    TestServer,
+    multipart::{MultipartForm, Part},
+};
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::Value;
+use serial_test::serial;
+use server::app::App;
+use std::{collections::BTreeSet, sync::Arc};
+use wiremock::{Mock, MockServer, ResponseTemplate, matchers::method};
+
+use super::{bearer_token, files::s3_client, test_server};
+
+async fn seed_pages(server: &TestServer, token: &str, count: usize) {
+    let form = (0..count).fold(MultipartForm::new(), |form, i| {
+        form.add_part(
+            "file",
+            Part::bytes(format!("file {i}").into_bytes()).file_name(format!("page-{i:02}.txt")),
+        )
+    });
+    server
+        .post("/files")
+        .authorization_bearer(token)
+        .multipart(form)
+        .await
+        .assert_status_ok();
+}
+
+/// One page of `GET /files`, and the cursor of the next.
+async fn page(
+    server: &TestServer,
+    limit: u64,
+    cursor: Option<&str>,
+) -> (Vec<String>, Option<String>) {
+    let mut request = server.get("/files").add_query_param("limit", limit);
+    if let Some(cursor) = cursor {
+        request = request.add_query_param("cursor", cursor);
+    }
+    let response = request.await;
+    response.assert_status_ok();
+    let next = response
+        .maybe_header("x-next-cursor")
+        .map(|v| v.to_str().unwrap().to_string());
+    let names = response
+        .json::<Vec<Value>>()
+        .iter()
+        .map(|f| f["name"].as_str().unwrap().to_string())
+        .collect();
+    (names, next)
+}
+
+#[tokio::test]
+#[serial]
+async fn pages_cover_every_file_once() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    seed_pages(&server, &token, 50).await;
+
+    let (first, mut cursor) = page(&server, 20, None).await;
+    assert_eq!(first.len(), 20);
+    assert_eq!(first[0], "page-00.txt");
+
+    let mut seen = first;
+    let mut pages = 1;
+    while let Some(next) = cursor {
+        let (names, after) = page(&server, 20, Some(&next)).await;
+        seen.extend(names);
+        cursor = after;
+        pages += 1;
+    }
+
+    assert_eq!(pages, 3);
+    assert_eq!(seen.len(), 50);
+    assert_eq!(seen.iter().collect::<BTreeSet<_>>().len(), 50);
+    assert_eq!(server.get("/files").await.json::<Vec<Value>>().len(), 50);
+}
+
+#[tokio::test]
+#[serial]
+async fn a_stale_cursor_gives_an_empty_page() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    seed_pages(&server, &token, 50).await;
+
+    let (_, cursor) = page(&server, 45, None).await;
+    for i in 45..50 {
+        server
+            .delete(&format!("/files/page-{i}.txt"))
+            .authorization_bearer(&token)
+            .await
+            .assert_status_success();
+    }
+
+    let (names, next) = page(&server, 45, cursor.as_deref()).await;
+    assert!(names.is_empty());
+    assert_eq!(next, None);
+
+    server
+        .get("/files")
+        .add_query_param("cursor", "not-a-cursor")
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+#[serial]
+async fn listed_files_carry_what_storage_reports() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    seed_pages(&server, &token, 2).await;
+
+    let files: Vec<Value> = server.get("/files").await.json();
+    assert_eq!(files.len(), 2);
+    for file in &files {
+        assert_eq!(file["content_type"], "text/plain");
+        assert!(file["e_tag"].is_string(), "{file}");
+        let modified = file["last_modified"].as_str().unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(modified).is_ok());
+        // In-memory storage keeps no version IDs.
+        assert!(file.get("version_id").is_none(), "{file}");
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn sorted_pages_resume_where_the_last_ended() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    seed_pages(&server, &token, 5).await;
+
+    let names = |response: &axum_test::TestResponse| -> Vec<String> {
+        response
+            .json::<Vec<Value>>()
+            .iter()
+            .map(|f| f["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+    let first = server
+        .get("/files")
+        .add_query_param("sort_by", "name")
+        .add_query_param("order", "desc")
+        .add_query_param("limit", 3)
+        .await;
+    assert_eq!(names(&first), ["page-04.txt", "page-03.txt", "page-02.txt"]);
+    let cursor = first.header("x-next-cursor");
+
+    let second = server
+        .get("/files")
+        .add_query_param("sort_by", "name")
+        .add_query_param("order", "desc")
+        .add_query_param("cursor", cursor.to_str().unwrap())
+        .await;
+    assert_eq!(names(&second), ["page-01.txt", "page-00.txt"]);
+    assert!(second.maybe_header("x-next-cursor").is_none());
+
+    server
+        .get("/files")
+        .add_query_param("cursor", cursor.to_str().unwrap())
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+fn ndjson_lines(response: &axum_test::TestResponse) -> Vec<Value> {
+    assert_eq!(response.header("content-type"), "application/x-ndjson");
+    response
+        .text()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+#[serial]
+async fn ndjson_listings_send_a_file_per_line() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    seed_pages(&server, &token, 3).await;
+
+    let response = server
+        .get("/files")
+        .add_query_param("format", "ndjson")
+        .add_query_param("limit", 2)
+        .await;
+    response.assert_status_ok();
+    assert!(response.maybe_header("x-next-cursor").is_some());
+    let files = ndjson_lines(&response);
+    let names: Vec<&str> = files.iter().map(|f| f["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["page-00.txt", "page-01.txt"]);
+    assert!(files[0]["e_tag"].is_string(), "{}", files[0]);
+
+    let response = server
+        .get("/files")
+        .add_header(
+            HeaderName::from_static("accept"),
+            HeaderValue::from_static("application/x-ndjson; q=1.0"),
+        )
+        .await;
+    assert_eq!(ndjson_lines(&response).len(), 3);
+    let array: Vec<Value> = server.get("/files").await.json();
+    assert_eq!(array.len(), 3);
+}
+
+#[tokio::test]
+#[serial]
+async fn ndjson_listings_end_with_the_error_that_cut_them_short() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    seed_pages(
+        &test_server(&boot.app_context, Arc::new(InMemory::new())),
+        &token,
+        2,
+    )
+    .await;
+    let s3 = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&s3)
+        .await;
+    let server = test_server(&boot.app_context, s3_client(&s3));
+
+    let response = server.get("/files?format=ndjson").await;
+
+    response.assert_status_ok();
+    let lines = ndjson_lines(&response);
+    assert_eq!(lines.len(), 1, "{lines:?}");
+    assert_eq!(lines[0]["error"]["code"], "storage_error");
+}
+
+#[tokio::test]
+#[serial]
+async fn the_catalog_exports_every_file_as_an_ndjson_attachment() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    seed_pages(&server, &token, 12).await;
+    server
+        .post("/files/page-03.txt/tags")
+        .authorization_bearer(&token)
+        .json(&serde_json::json!({ "tags": ["q3"] }))
+        .await
+        .assert_status_ok();
+
+    let response = server.get("/files/export").await;
+    response.assert_status_ok();
+    assert_eq!(
+        response.header("content-disposition"),
+        "attachment; filename=\"catalog.ndjson\""
+    );
+    let files = ndjson_lines(&response);
+    let names: Vec<&str> = files.iter().map(|f| f["name"].as_str().unwrap()).collect();
+    let expected: Vec<String> = (0..12).map(|i| format!("page-{i:02}.txt")).collect();
+    assert_eq!(names, expected);
+    assert_eq!(files[3]["tags"], serde_json::json!(["q3"]));
+    assert_eq!(files[0]["author"]["login"], "tester");
+}
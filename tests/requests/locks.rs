@@ -0,0 +1,66 @@
+use axum::http::StatusCode;
+use axum_test::multipart::{MultipartForm, Part};
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::Value;
+use serial_test::serial;
+use server::{app::App, models::file_lock};
+use std::sync::Arc;
+
+use super::{bearer_token, test_server};
+
+fn form(name: &str) -> MultipartForm {
+    MultipartForm::new().add_part("file", Part::bytes("draft".as_bytes()).file_name(name))
+}
+
+#[tokio::test]
+#[serial]
+async fn a_locked_file_is_not_uploaded() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    file_lock::acquire(&ctx.db, "report.pdf", 300)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let response = server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(form("report.pdf"))
+        .await;
+
+    response.assert_status(StatusCode::CONFLICT);
+    let body: Value = response.json();
+    assert_eq!(body["code"], "locked");
+    assert_eq!(body["details"]["retry_after"], 30);
+}
+
+#[tokio::test]
+#[serial]
+async fn uploads_release_their_lock_and_take_over_stale_ones() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    // Expires at once, as if its upload had died.
+    file_lock::acquire(&ctx.db, "report.pdf", 0)
+        .await
+        .unwrap()
+        .unwrap();
+
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(form("report.pdf"))
+        .await
+        .assert_status_ok();
+
+    assert!(
+        file_lock::find(&ctx.db, "report.pdf")
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
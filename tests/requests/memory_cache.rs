@@ -0,0 +1,53 @@
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use loco_rs::{app::AppContext, testing::prelude::*};
+use object_store::memory::InMemory;
+use serial_test::serial;
+use server::{
+    app::App,
+    cache_store::{CacheStore, MemoryCache},
+};
+use std::{sync::Arc, time::Duration};
+
+use super::{bearer_token, test_server, upload};
+
+fn cached_server(ctx: &AppContext, memory: &Arc<MemoryCache>) -> TestServer {
+    let store = CacheStore::new(InMemory::new(), None, "files").with_memory(Some(memory.clone()));
+    test_server(ctx, Arc::new(store))
+}
+
+#[tokio::test]
+#[serial]
+async fn hot_files_are_served_from_memory_with_their_headers() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let memory = Arc::new(MemoryCache::new(1024 * 1024, 1024, Duration::from_secs(60)));
+    let server = cached_server(ctx, &memory);
+    upload(&server, &token, "config.json", b"notes").await;
+
+    let first = server.get("/files/config.json").await;
+    first.assert_status_ok();
+    let hits = memory.hits();
+    let second = server.get("/files/config.json").await;
+    assert_eq!(memory.hits(), hits + 1);
+    assert_eq!(second.text(), "notes");
+    assert_eq!(second.header("etag"), first.header("etag"));
+    assert_eq!(second.header("content-type"), "application/json");
+    assert_eq!(second.header("content-length"), "5");
+
+    server
+        .delete("/files/config.json")
+        .authorization_bearer(&token)
+        .await
+        .assert_status_ok();
+    server
+        .get("/files/config.json")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    upload(&server, &token, "config.json", b"summary").await;
+    let changed = server.get("/files/config.json").await;
+    assert_eq!(changed.text(), "summary");
+    assert_ne!(changed.header("etag"), first.header("etag"));
+}
@@ -0,0 +1,76 @@
+use axum::http::StatusCode;
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::app::App;
+use std::sync::Arc;
+
+use super::{bearer_token, seed, test_server};
+
+#[tokio::test]
+#[serial]
+async fn merges_sources_in_order_with_the_separator() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    seed(&server, &token, "logs/part1.txt", "bm90ZXM=").await;
+    seed(&server, &token, "logs/part2.txt", "c3VtbWFyeQ==").await;
+
+    let response = server
+        .post("/files/merge")
+        .authorization_bearer(&token)
+        .json(&json!({
+            "sources": ["logs/part2.txt", "logs/part1.txt"],
+            "destination": "merged.txt",
+            "separator": "\n",
+        }))
+        .await;
+    response.assert_status_ok();
+    let info: Value = response.json();
+    assert_eq!(info["name"], "merged.txt");
+    assert_eq!(info["size"], 13);
+
+    let merged = server.get("/files/merged.txt").await;
+    merged.assert_status_ok();
+    assert_eq!(merged.as_bytes().as_ref(), b"summary\nnotes");
+}
+
+#[tokio::test]
+#[serial]
+async fn a_missing_source_fails_before_anything_is_written() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    seed(&server, &token, "part1.csv", "bm90ZXM=").await;
+
+    server
+        .post("/files/merge")
+        .authorization_bearer(&token)
+        .json(&json!({ "sources": ["part1.csv", "part2.csv"], "destination": "merged.csv" }))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    server
+        .get("/files/merged.csv")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    server
+        .post("/files/merge")
+        .authorization_bearer(&token)
+        .json(&json!({
+            "sources": ["part1.csv", "part1.csv"],
+            "destination": "merged.bin",
+            "separator": ",",
+        }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    server
+        .post("/files/merge")
+        .authorization_bearer(&token)
+        .json(&json!({ "sources": [], "destination": "merged.csv" }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
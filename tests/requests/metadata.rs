@@ -0,0 +1,124 @@
+use axum::{Extension, Router, http::StatusCode};
+use axum_test::TestServer;
+use loco_rs::{app::AppContext, controller::AppRoutes, testing::prelude::*};
+use object_store::memory::InMemory;
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::{
+    app::App,
+    controllers::{auth, files},
+};
+use std::sync::Arc;
+
+use super::{bearer_token, seed};
+
+/// The `/auth` and `/files` routes, so tokens carry what login puts in them.
+fn app_server(ctx: &AppContext) -> TestServer {
+    let router = AppRoutes::empty()
+        .add_route(auth::routes())
+        .add_route(files::routes(ctx))
+        .to_router::<App>(ctx.clone(), Router::new())
+        .unwrap()
+        .layer(Extension(
+            Arc::new(InMemory::new()) as Arc<dyn object_store::ObjectStore>
+        ));
+    TestServer::new(router).unwrap()
+}
+
+/// Registers a user with the seeded `user` role and logs them in.
+async fn user_token(server: &TestServer) -> String {
+    let credentials = json!({ "login": "writer", "password": "secret" });
+    server
+        .post("/auth/register")
+        .json(&json!({ "username": "Writer", "role_name": "user", "login": "writer", "password": "secret" }))
+        .await
+        .assert_status_ok();
+    let login: Value = server.post("/auth/login").json(&credentials).await.json();
+    login["token"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+#[serial]
+async fn the_seeded_user_role_may_update_metadata() {
+    let boot = boot_test::<App>().await.unwrap();
+    let server = app_server(&boot.app_context);
+    let token = user_token(&server).await;
+    seed(&server, &token, "report.txt", "bm90ZXM=").await;
+
+    let response = server
+        .patch("/files/report.txt/metadata")
+        .authorization_bearer(&token)
+        .json(&json!({ "title": "Quarterly report", "description": null }))
+        .await;
+
+    response.assert_status_ok();
+    let body: Value = response.json();
+    assert_eq!(body["attributes"]["title"], "Quarterly report");
+    assert!(body["attributes"].get("description").is_none());
+    assert_eq!(server.get("/files/report.txt").await.text(), "notes");
+}
+
+#[tokio::test]
+#[serial]
+async fn metadata_updates_need_the_files_write_scope() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = app_server(&boot.app_context);
+    seed(&server, &token, "report.txt", "bm90ZXM=").await;
+
+    let response = server
+        .patch("/files/report.txt/metadata")
+        .authorization_bearer(&token)
+        .json(&json!({ "title": "Quarterly report" }))
+        .await;
+
+    response.assert_status(StatusCode::FORBIDDEN);
+    assert_eq!(response.json::<Value>()["code"], "missing_scope");
+}
+
+#[tokio::test]
+#[serial]
+async fn the_size_and_checksum_cannot_be_changed() {
+    let boot = boot_test::<App>().await.unwrap();
+    let server = app_server(&boot.app_context);
+    let token = user_token(&server).await;
+    seed(&server, &token, "report.txt", "bm90ZXM=").await;
+
+    for field in ["size", "sha256"] {
+        let response = server
+            .patch("/files/report.txt/metadata")
+            .authorization_bearer(&token)
+            .json(&json!({ "title": "Quarterly report", field: "0" }))
+            .await;
+
+        response.assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response.json::<Value>()["code"], "immutable_field");
+    }
+    let body: Value = server.get("/files/report.txt/metadata").await.json();
+    assert!(body["attributes"].get("title").is_none());
+}
+
+#[tokio::test]
+#[serial]
+async fn metadata_waits_for_anothers_edit_lock() {
+    let boot = boot_test::<App>().await.unwrap();
+    let server = app_server(&boot.app_context);
+    let token = user_token(&server).await;
+    seed(&server, &token, "report.txt", "bm90ZXM=").await;
+    server
+        .post("/files/report.txt/lock")
+        .authorization_bearer(&bearer_token(&boot.app_context).await)
+        .await
+        .assert_status_ok();
+
+    let response = server
+        .patch("/files/report.txt/metadata")
+        .authorization_bearer(&token)
+        .json(&json!({ "title": "Quarterly report" }))
+        .await;
+
+    response.assert_status(StatusCode::LOCKED);
+    assert_eq!(response.json::<Value>()["code"], "edit_locked");
+    let body: Value = server.get("/files/report.txt/metadata").await.json();
+    assert!(body["attributes"].get("title").is_none());
+}
@@ -0,0 +1,122 @@
+mod admin;
+mod archive;
+mod audit;
+mod base64;
+mod browse;
+mod checksums;
+mod chunked;
+mod cors;
+mod duplicates;
+mod edit_locks;
+mod files;
+mod folders;
+mod health;
+mod hooks;
+mod idempotency;
+mod limits;
+mod links;
+mod listing;
+mod locks;
+mod memory_cache;
+mod merge;
+mod metadata;
+mod names;
+mod object_lock;
+mod openapi;
+mod preconditions;
+mod preview;
+mod quota;
+mod replication;
+mod scan;
+mod signed_urls;
+mod snapshots;
+mod stats;
+mod tar_export;
+mod tenants;
+mod thumbnails;
+mod tus;
+mod verify;
+mod versions;
+
+use axum::{Extension, Router};
+use axum_test::{
+    TestServer,
+    multipart::{MultipartForm, Part},
+};
+use loco_rs::{app::AppContext, controller::AppRoutes};
+use object_store::{ObjectStore, multipart::MultipartStore};
+use serde_json::json;
+use server::{
+    app::App,
+    controllers::{
+        auth,
+        files::{PartStore, routes},
+    },
+    models::{role, user},
+};
+use std::sync::Arc;
+
+/// The `/files` routes over `store`, for tests that add layers of their own.
+fn test_router(ctx: &AppContext, store: Arc<dyn ObjectStore>) -> Router {
+    AppRoutes::empty()
+        .add_route(routes(ctx))
+        .to_router::<App>(ctx.clone(), Router::new())
+        .unwrap()
+        .layer(Extension(store))
+}
+
+/// The `/files` routes over `store`.
+fn test_server(ctx: &AppContext, store: Arc<dyn ObjectStore>) -> TestServer {
+    TestServer::new(test_router(ctx, store)).unwrap()
+}
+
+/// `test_server`, with resumable and chunked uploads assembled in `store`.
+fn test_server_with_parts<S: ObjectStore + MultipartStore>(
+    ctx: &AppContext,
+    store: Arc<S>,
+) -> TestServer {
+    let router = test_router(ctx, store.clone()).layer(Extension(PartStore(Some(store))));
+    TestServer::new(router).unwrap()
+}
+
+async fn bearer_token(ctx: &AppContext) -> String {
+    let role = role::create(&ctx.db, "tester", json!(["read"]))
+        .await
+        .unwrap();
+    let author = user::create(&ctx.db, "Tester", "tester", "unused", role.id)
+        .await
+        .unwrap();
+    auth::generate_token(&author.id.to_string(), &author.login, vec!["tester".into()]).unwrap()
+}
+
+fn admin_token() -> String {
+    auth::generate_token("0", "admin", vec!["admin".into()]).unwrap()
+}
+
+/// Stores `name` through `POST /files/base64`; `data` is base64.
+async fn seed(server: &TestServer, token: &str, name: &str, data: &str) {
+    server
+        .post("/files/base64")
+        .authorization_bearer(token)
+        .json(&json!({ "name": name, "data": data }))
+        .await
+        .assert_status_ok();
+}
+
+/// `seed`s each of `names` with the same five bytes.
+async fn seed_all(server: &TestServer, token: &str, names: &[&str]) {
+    for name in names {
+        seed(server, token, name, "bm90ZXM=").await;
+    }
+}
+
+/// Stores `name` through a multipart `POST /files`.
+async fn upload(server: &TestServer, token: &str, name: &str, content: &[u8]) {
+    let part = Part::bytes(content.to_vec()).file_name(name);
+    server
+        .post("/files")
+        .authorization_bearer(token)
+        .multipart(MultipartForm::new().add_part("file", part))
+        .await
+        .assert_status_ok();
+}
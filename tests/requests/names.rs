@@ -0,0 +1,118 @@
+use axum::http::{StatusCode, header};
+use axum_test::multipart::{MultipartForm, Part};
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use serde_json::Value;
+use serial_test::serial;
+use server::app::App;
+use std::sync::Arc;
+
+use super::{bearer_token, test_server};
+
+/// `name` as a `/files/...` URL, its folders' slashes encoded too.
+fn url(name: &str) -> String {
+    format!("/files/{}", utf8_percent_encode(name, NON_ALPHANUMERIC))
+}
+
+/// Pieces of names that have each been mangled somewhere on the way from
+/// an upload to its key and back.
+const PIECES: [&str; 10] = [
+    "plain",
+    "with space",
+    "a+b",
+    "c#1",
+    "why?",
+    "100%",
+    "Überblick (final)",
+    "party 🎉",
+    // `é` decomposed, as macOS writes it.
+    "cafe\u{301}",
+    "a&b=c",
+];
+
+/// Every piece alone, inside a folder, and after each other piece.
+fn names() -> Vec<String> {
+    let mut names = Vec::new();
+    for (i, piece) in PIECES.iter().enumerate() {
+        names.push(format!("{piece}.txt"));
+        names.push(format!("folder {i}/{piece}.pdf"));
+        let next = PIECES[(i + 1) % PIECES.len()];
+        names.push(format!("{piece} {next}"));
+    }
+    names
+}
+
+/// NFC, which names are stored in.
+fn composed(name: &str) -> String {
+    name.replace("e\u{301}", "é")
+}
+
+#[tokio::test]
+#[serial]
+async fn names_survive_the_round_trip() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+
+    for name in names() {
+        let stored = composed(&name);
+        let content = format!("content of {stored}");
+        let part = Part::bytes(content.clone().into_bytes()).file_name(name.clone());
+        let uploaded = server
+            .post("/files")
+            .authorization_bearer(&token)
+            .multipart(MultipartForm::new().add_part("file", part))
+            .await;
+        uploaded.assert_status_ok();
+        assert_eq!(
+            uploaded.json::<Value>()["results"][0]["file"]["name"],
+            stored,
+            "{name:?}"
+        );
+
+        let listed: Vec<Value> = server.get("/files").await.json();
+        assert!(
+            listed.iter().any(|f| f["name"] == stored),
+            "{stored:?} not listed"
+        );
+
+        // Either form of the name finds the file.
+        for asked in [&name, &stored] {
+            let download = server.get(&url(asked)).await;
+            assert_eq!(download.status_code(), StatusCode::OK, "GET {asked:?}");
+            assert_eq!(download.text(), content);
+            let disposition = download.header(header::CONTENT_DISPOSITION);
+            assert!(disposition.to_str().is_ok(), "{disposition:?}");
+        }
+
+        server
+            .delete(&url(&name))
+            .authorization_bearer(&token)
+            .await
+            .assert_status_success();
+        server
+            .get(&url(&stored))
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+    let listed: Vec<Value> = server.get("/files").await.json();
+    assert!(listed.is_empty(), "{listed:?}");
+}
+
+#[tokio::test]
+#[serial]
+async fn names_no_key_can_hold_are_refused() {
+    let boot = boot_test::<App>().await.unwrap();
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+
+    for path in [
+        "/files/bell%07.txt",
+        "/files/a%2F%2Fb.txt",
+        "/files/%FF.txt",
+    ] {
+        let response = server.get(path).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert_eq!(response.json::<Value>()["code"], "bad_request", "{path}");
+    }
+}
@@ -0,0 +1,129 @@
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use chrono::{DateTime, Duration, Utc};
+use loco_rs::{app::AppContext, testing::prelude::*};
+use object_store::{RetryConfig, aws::AmazonS3Builder};
+use serde_json::Value;
+use serial_test::serial;
+use server::{
+    app::App,
+    object_lock::{LockMode, LockedBucket, LockedS3, ObjectLock},
+};
+use std::sync::Arc;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path},
+};
+
+use super::{bearer_token, test_server, upload};
+
+const LIST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult><Name>files</Name><IsTruncated>false</IsTruncated></ListBucketResult>"#;
+
+/// Objects uploaded to S3 at `s3` are locked in compliance mode for 30 days.
+fn locked_server(ctx: &AppContext, s3: &MockServer) -> TestServer {
+    let client = AmazonS3Builder::new()
+        .with_endpoint(s3.uri())
+        .with_allow_http(true)
+        .with_bucket_name("files")
+        .with_region("us-east-1")
+        .with_access_key_id("test")
+        .with_secret_access_key("test")
+        .with_retry(RetryConfig {
+            max_retries: 0,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    let bucket = LockedBucket {
+        endpoint: s3.uri(),
+        name: "files".into(),
+        region: "us-east-1".into(),
+        access_key: "test".into(),
+        secret_key: "test".into(),
+        write_headers: Vec::new(),
+        upload_headers: Vec::new(),
+    };
+    let lock = ObjectLock {
+        mode: LockMode::Compliance,
+        retain_days: 30,
+    };
+    test_server(ctx, Arc::new(LockedS3::new(client, bucket, lock)))
+}
+
+async fn mock_bucket() -> MockServer {
+    let s3 = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .respond_with(ResponseTemplate::new(200).insert_header("ETag", "\"1\""))
+        .mount(&s3)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/files"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(LIST))
+        .mount(&s3)
+        .await;
+    s3
+}
+
+#[tokio::test]
+#[serial]
+async fn uploads_are_written_with_the_lock_headers() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let s3 = mock_bucket().await;
+    let server = locked_server(ctx, &s3);
+    upload(&server, &token, "ledger.txt", b"audit trail").await;
+
+    let requests = s3.received_requests().await.unwrap();
+    let put = requests
+        .iter()
+        .find(|r| r.method.as_str() == "PUT" && r.url.path() == "/files/ledger.txt")
+        .expect("the upload reached S3");
+    assert_eq!(put.headers["x-amz-object-lock-mode"], "COMPLIANCE");
+    assert!(put.headers.contains_key("content-md5"));
+    let until = put.headers["x-amz-object-lock-retain-until-date"]
+        .to_str()
+        .unwrap();
+    let until: DateTime<Utc> = until.parse().unwrap();
+    let expected = Utc::now() + Duration::days(30);
+    assert!((expected - until).num_minutes().abs() < 5);
+}
+
+#[tokio::test]
+#[serial]
+async fn a_delete_refused_by_s3_keeps_the_file() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let s3 = mock_bucket().await;
+    let server = locked_server(ctx, &s3);
+    upload(&server, &token, "ledger.txt", b"audit trail").await;
+
+    Mock::given(method("DELETE"))
+        .respond_with(ResponseTemplate::new(403).set_body_string(
+            "<Error><Code>AccessDenied</Code><Message>Access Denied because object protected by object lock.</Message></Error>",
+        ))
+        .up_to_n_times(1)
+        .mount(&s3)
+        .await;
+    let response = server
+        .delete("/files/ledger.txt")
+        .authorization_bearer(&token)
+        .await;
+    response.assert_status(StatusCode::FORBIDDEN);
+    let body = response.json::<Value>();
+    assert_eq!(body["code"], "storage_access_denied");
+    assert_eq!(body["details"]["s3_code"], "AccessDenied");
+    assert!(body["message"].as_str().unwrap().contains("object lock"));
+
+    Mock::given(method("DELETE"))
+        .respond_with(ResponseTemplate::new(204))
+        .mount(&s3)
+        .await;
+    server
+        .delete("/files/ledger.txt")
+        .authorization_bearer(&token)
+        .await
+        .assert_status_ok();
+}
@@ -0,0 +1,418 @@
+use axum::{Extension, Router};
+use axum_test::{
+    TestResponse, TestServer,
+    multipart::{MultipartForm, Part},
+};
+use loco_rs::{controller::AppRoutes, testing::prelude::*};
+use object_store::memory::InMemory;
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::{
+    app::App,
+    circuit_store::{CircuitPolicy, CircuitState},
+    controllers::{files, openapi},
+};
+use std::{sync::Arc, time::Duration};
+
+use super::{bearer_token, test_router};
+
+/// Every `"$ref"` in `value`.
+fn refs<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                match (key.as_str(), value) {
+                    ("$ref", Value::String(target)) => found.push(target),
+                    _ => refs(value, found),
+                }
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|item| refs(item, found)),
+        _ => {}
+    }
+}
+
+fn spec() -> Value {
+    serde_json::to_value(<openapi::ApiDoc as utoipa::OpenApi>::openapi()).unwrap()
+}
+
+/// Where `value` strays from `schema`, as JSON paths and what is wrong
+/// there. Covers the keywords utoipa emits; objects may not carry fields
+/// their schema doesn't list, unless it lists none.
+fn mismatches(spec: &Value, schema: &Value, value: &Value, at: &str, found: &mut Vec<String>) {
+    if let Some(target) = schema["$ref"].as_str() {
+        let name = target.trim_start_matches("#/components/schemas/");
+        return mismatches(spec, &spec["components"]["schemas"][name], value, at, found);
+    }
+    if let Some(options) = schema["oneOf"].as_array() {
+        let matching = options
+            .iter()
+            .filter(|option| {
+                let mut errors = Vec::new();
+                mismatches(spec, option, value, at, &mut errors);
+                errors.is_empty()
+            })
+            .count();
+        if matching != 1 {
+            found.push(format!("{at}: matches {matching} of oneOf"));
+        }
+        return;
+    }
+    if let Some(allowed) = schema["enum"].as_array()
+        && !allowed.contains(value)
+    {
+        found.push(format!("{at}: {value} is not one of {allowed:?}"));
+    }
+
+    let types: Vec<&str> = match &schema["type"] {
+        Value::String(t) => vec![t],
+        Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+        _ => return,
+    };
+    let actual = match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    };
+    if !types
+        .iter()
+        .any(|t| *t == actual || (*t == "number" && actual == "integer"))
+    {
+        found.push(format!("{at}: {actual} where the spec says {types:?}"));
+        return;
+    }
+    if let (Some(minimum), Some(n)) = (schema["minimum"].as_f64(), value.as_f64())
+        && n < minimum
+    {
+        found.push(format!("{at}: {n} is below the minimum {minimum}"));
+    }
+
+    match value {
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                mismatches(spec, &schema["items"], item, &format!("{at}[{i}]"), found);
+            }
+        }
+        Value::Object(fields) => {
+            for required in schema["required"].as_array().into_iter().flatten() {
+                let required = required.as_str().unwrap();
+                if !fields.contains_key(required) {
+                    found.push(format!("{at}.{required}: missing"));
+                }
+            }
+            for (name, field) in fields {
+                let at = format!("{at}.{name}");
+                match (&schema["properties"][name], &schema["additionalProperties"]) {
+                    (Value::Object(property), _) => {
+                        mismatches(spec, &Value::Object(property.clone()), field, &at, found)
+                    }
+                    (_, Value::Object(_)) => {
+                        mismatches(spec, &schema["additionalProperties"], field, &at, found)
+                    }
+                    (_, Value::Bool(true)) => {}
+                    _ if schema.get("properties").is_none() => {}
+                    _ => found.push(format!("{at}: not in the spec")),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Fails unless `response` has a status the spec lists for `method
+/// template`, with a body of the schema listed for it.
+fn assert_documented(spec: &Value, method: &str, template: &str, response: &TestResponse) {
+    let status = response.status_code();
+    let operation = &spec["paths"][template][method];
+    assert!(
+        operation.is_object(),
+        "{method} {template} is not documented"
+    );
+    let documented = &operation["responses"][status.as_str()];
+    assert!(
+        documented.is_object(),
+        "{method} {template} answered {status}, which is not documented: {}",
+        response.text()
+    );
+    let Some(schema) = documented["content"]
+        .get("application/json")
+        .map(|content| &content["schema"])
+    else {
+        return;
+    };
+    let body: Value = response.json();
+    let mut found = Vec::new();
+    mismatches(spec, schema, &body, "$", &mut found);
+    assert!(
+        found.is_empty(),
+        "{method} {template} {status} strays from the spec:\n{}\n{body:#}",
+        found.join("\n")
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn spec_documents_every_files_route() {
+    let boot = boot_test::<App>().await.unwrap();
+    let router: Router = AppRoutes::empty()
+        .add_route(openapi::routes(&boot.app_context))
+        .to_router::<App>(boot.app_context.clone(), Router::new())
+        .unwrap();
+    let server = TestServer::new(router).unwrap();
+
+    let response = server.get("/api-docs/openapi.json").await;
+    response.assert_status_ok();
+    let spec: Value = response.json();
+    assert_eq!(server.get("/openapi.json").await.json::<Value>(), spec);
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+
+    let routes = files::routes(&boot.app_context);
+    let prefix = routes.prefix.clone().unwrap_or_default();
+    for handler in &routes.handlers {
+        let uri = format!("{prefix}{}", handler.uri);
+        assert!(!handler.actions.is_empty(), "{uri} has no methods");
+        for action in &handler.actions {
+            let method = action.as_str().to_lowercase();
+            assert!(
+                spec["paths"][&uri][&method].is_object(),
+                "{method} {uri} is not documented"
+            );
+        }
+    }
+
+    let mut targets = Vec::new();
+    refs(&spec, &mut targets);
+    for target in targets {
+        let name = target.strip_prefix("#/components/schemas/").unwrap();
+        assert!(
+            spec["components"]["schemas"][name].is_object(),
+            "{target} does not resolve"
+        );
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn swagger_ui_loads_the_spec() {
+    let boot = boot_test::<App>().await.unwrap();
+    let router: Router = AppRoutes::empty()
+        .add_route(openapi::routes(&boot.app_context))
+        .to_router::<App>(boot.app_context.clone(), Router::new())
+        .unwrap();
+    let server = TestServer::new(router).unwrap();
+
+    let response = server.get("/swagger-ui").await;
+
+    response.assert_status_ok();
+    assert!(response.text().contains("/api-docs/openapi.json"));
+}
+
+#[tokio::test]
+#[serial]
+async fn responses_match_the_spec() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let circuit = Arc::new(CircuitState::new(CircuitPolicy {
+        failure_threshold: 5,
+        window: Duration::from_secs(60),
+        cool_down: Duration::from_secs(60),
+    }));
+    let memory = Arc::new(InMemory::new());
+    let router = test_router(&boot.app_context, memory.clone()).layer((
+        Extension(files::PartStore(Some(memory))),
+        Extension(circuit),
+    ));
+    let server = TestServer::new(router).unwrap();
+    let spec = spec();
+    let upload = |name: &'static str| {
+        server
+            .post("/files")
+            .authorization_bearer(&token)
+            .multipart(
+                MultipartForm::new()
+                    .add_part("file", Part::bytes("quarterly".as_bytes()).file_name(name)),
+            )
+    };
+
+    let response = upload("report.txt").await;
+    assert_documented(&spec, "post", "/files", &response);
+    let id = response.json::<Value>()["uploaded"][0]["id"].clone();
+    assert_documented(&spec, "post", "/files", &upload("report.txt").await);
+    assert_documented(
+        &spec,
+        "post",
+        "/files",
+        &server.post("/files").multipart(MultipartForm::new()).await,
+    );
+    let response = server
+        .post("/files/base64")
+        .authorization_bearer(&token)
+        .json(&json!({ "name": "notes.txt", "data": "bm90ZXM=" }))
+        .await;
+    assert_documented(&spec, "post", "/files/base64", &response);
+    let response = server
+        .post("/files/base64")
+        .authorization_bearer(&token)
+        .json(&json!({ "name": "notes.txt", "data": "!" }))
+        .await;
+    assert_documented(&spec, "post", "/files/base64", &response);
+
+    let authorized = [
+        ("get", "/files", "/files".to_string()),
+        ("get", "/files", "/files?limit=0".to_string()),
+        ("get", "/files/stats", "/files/stats".to_string()),
+        ("get", "/files/quota", "/files/quota".to_string()),
+        ("get", "/files/health", "/files/health".to_string()),
+        ("get", "/files/tags", "/files/tags".to_string()),
+        (
+            "get",
+            "/files/export.tar",
+            "/files/export.tar?prefix=reports".to_string(),
+        ),
+        ("get", "/files/search", "/files/search?q=report".to_string()),
+        (
+            "get",
+            "/files/browse",
+            "/files/browse?with_sizes=true".to_string(),
+        ),
+        (
+            "get",
+            "/files/{file_name}/meta",
+            "/files/report.txt/meta".to_string(),
+        ),
+        (
+            "get",
+            "/files/{file_name}/meta",
+            "/files/missing.txt/meta".to_string(),
+        ),
+        (
+            "get",
+            "/files/{file_name}/metadata",
+            "/files/report.txt/metadata".to_string(),
+        ),
+        (
+            "get",
+            "/files/{file_name}/exists",
+            "/files/report.txt/exists".to_string(),
+        ),
+        (
+            "get",
+            "/files/{file_name}/signed-url",
+            "/files/report.txt/signed-url".to_string(),
+        ),
+        (
+            "get",
+            "/files/{file_name}/link",
+            "/files/report.txt/link?redirect=false".to_string(),
+        ),
+        (
+            "get",
+            "/files/{file_name}/lock",
+            "/files/report.txt/lock".to_string(),
+        ),
+        (
+            "get",
+            "/files/{file_name}/download-count",
+            "/files/report.txt/download-count".to_string(),
+        ),
+        (
+            "get",
+            "/files/{id}/versions",
+            format!("/files/{id}/versions"),
+        ),
+        (
+            "get",
+            "/files/{file_name}/restore-status",
+            "/files/report.txt/restore-status".to_string(),
+        ),
+    ];
+    for (method, template, uri) in authorized {
+        let response = server.get(&uri).authorization_bearer(&token).await;
+        assert_documented(&spec, method, template, &response);
+    }
+
+    let response = server
+        .delete("/files/folder?prefix=reports/&dry_run=true")
+        .authorization_bearer(&token)
+        .await;
+    assert_documented(&spec, "delete", "/files/folder", &response);
+    let response = server
+        .delete("/files/folder")
+        .authorization_bearer(&token)
+        .await;
+    assert_documented(&spec, "delete", "/files/folder", &response);
+
+    let response = server
+        .post("/files/folder/move")
+        .authorization_bearer(&token)
+        .json(&json!({ "from": "reports/", "to": "archive/reports/" }))
+        .await;
+    assert_documented(&spec, "post", "/files/folder/move", &response);
+    let response = server
+        .post("/files/folder/move")
+        .authorization_bearer(&token)
+        .json(&json!({ "from": "reports/", "to": "reports/old/" }))
+        .await;
+    assert_documented(&spec, "post", "/files/folder/move", &response);
+
+    let response = server
+        .post("/files/export.tar")
+        .authorization_bearer(&token)
+        .json(&json!({ "files": ["report.txt"] }))
+        .await;
+    assert_documented(&spec, "post", "/files/export.tar", &response);
+    let response = server
+        .post("/files/export.tar")
+        .authorization_bearer(&token)
+        .json(&json!({ "files": ["missing.txt"] }))
+        .await;
+    assert_documented(&spec, "post", "/files/export.tar", &response);
+
+    let response = server
+        .post("/files/merge")
+        .authorization_bearer(&token)
+        .json(&json!({ "sources": ["report.txt", "notes.txt"], "destination": "both.txt" }))
+        .await;
+    assert_documented(&spec, "post", "/files/merge", &response);
+    let response = server
+        .post("/files/merge")
+        .authorization_bearer(&token)
+        .json(&json!({ "sources": ["missing.txt"], "destination": "both.txt" }))
+        .await;
+    assert_documented(&spec, "post", "/files/merge", &response);
+
+    let response = server
+        .post("/files/report.txt/tags")
+        .authorization_bearer(&token)
+        .json(&json!({ "tags": ["finance"] }))
+        .await;
+    assert_documented(&spec, "post", "/files/{file_name}/tags", &response);
+    let response = server
+        .patch("/files/report.txt/meta")
+        .authorization_bearer(&token)
+        .json(&json!({ "title": "Q3" }))
+        .await;
+    assert_documented(&spec, "patch", "/files/{file_name}/meta", &response);
+    let response = server
+        .delete("/files/report.txt/tags/finance")
+        .authorization_bearer(&token)
+        .await;
+    assert_documented(&spec, "delete", "/files/{file_name}/tags/{tag}", &response);
+    let response = server
+        .post("/files/uploads")
+        .authorization_bearer(&token)
+        .json(&json!({ "file_name": "big.bin" }))
+        .await;
+    assert_documented(&spec, "post", "/files/uploads", &response);
+    let response = server.delete("/files/report.txt").await;
+    assert_documented(&spec, "delete", "/files/{file_name}", &response);
+    let response = server
+        .delete("/files/report.txt")
+        .authorization_bearer(&token)
+        .await;
+    assert_documented(&spec, "delete", "/files/{file_name}", &response);
+}
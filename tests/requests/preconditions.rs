@@ -0,0 +1,186 @@
+use axum::http::{HeaderName, HeaderValue, StatusCode, header};
+use axum_test::{
+    TestResponse, TestServer,
+    multipart::{MultipartForm, Part},
+};
+use loco_rs::{app::AppContext, testing::prelude::*};
+use object_store::memory::InMemory;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::{
+    app::App,
+    controllers::auth,
+    models::{file, role, user},
+};
+use std::sync::Arc;
+
+use super::{bearer_token, test_server};
+
+/// A token for a second user, who may overwrite `bearer_token`'s files.
+async fn other_token(ctx: &AppContext) -> String {
+    let role = role::create(&ctx.db, "editor", json!(["read"]))
+        .await
+        .unwrap();
+    let editor = user::create(&ctx.db, "Editor", "editor", "unused", role.id)
+        .await
+        .unwrap();
+    auth::generate_token(&editor.id.to_string(), &editor.login, vec!["editor".into()]).unwrap()
+}
+
+async fn upload(
+    server: &TestServer,
+    token: &str,
+    content: &str,
+    condition: Option<(HeaderName, &str)>,
+) -> TestResponse {
+    let form = MultipartForm::new().add_part(
+        "file",
+        Part::bytes(content.as_bytes().to_vec()).file_name("report.txt"),
+    );
+    let mut request = server.post("/files").authorization_bearer(token);
+    if let Some((name, value)) = condition {
+        request = request.add_header(name, HeaderValue::from_str(value).unwrap());
+    }
+    request.multipart(form).await
+}
+
+async fn report_rows(ctx: &AppContext) -> Vec<file::Model> {
+    file::Entity::find()
+        .filter(file::Column::Name.eq("report.txt"))
+        .all(&ctx.db)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn a_re_upload_replaces_the_owners_file() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    upload(&server, &token, "first", None)
+        .await
+        .assert_status_ok();
+
+    let response = upload(&server, &token, "second draft", None).await;
+
+    response.assert_status_ok();
+    let e_tag = response.header(header::ETAG).to_str().unwrap().to_string();
+    let download = server.get("/files/report.txt").await;
+    assert_eq!(download.text(), "second draft");
+    assert_eq!(download.header(header::ETAG), e_tag.as_str());
+    let rows = report_rows(ctx).await;
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].size, "second draft".len() as i64);
+
+    // Another user's upload of the name replaces the same file too.
+    let editor = other_token(ctx).await;
+    upload(&server, &editor, "third", None)
+        .await
+        .assert_status_ok();
+    assert_eq!(server.get("/files/report.txt").await.text(), "third");
+    assert_eq!(report_rows(ctx).await.len(), 1);
+}
+
+#[tokio::test]
+#[serial]
+async fn if_match_only_overwrites_an_unchanged_file() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+
+    let response = upload(&server, &token, "first", None).await;
+    response.assert_status_ok();
+    let first = response.header(header::ETAG).to_str().unwrap().to_string();
+    let body: Value = response.json();
+    assert_eq!(body["results"][0]["e_tag"], first);
+
+    let download = server.get("/files/report.txt").await;
+    assert_eq!(download.header(header::ETAG), first.as_str());
+
+    let response = upload(
+        &server,
+        &token,
+        "second",
+        Some((header::IF_MATCH, "\"stale\"")),
+    )
+    .await;
+    response.assert_status(StatusCode::PRECONDITION_FAILED);
+    let body: Value = response.json();
+    assert_eq!(body["code"], "precondition_failed");
+    assert_eq!(body["details"]["current_e_tag"], first);
+    assert_eq!(server.get("/files/report.txt").await.text(), "first");
+
+    let response = upload(&server, &token, "second", Some((header::IF_MATCH, &first))).await;
+    response.assert_status_ok();
+    let second = response.header(header::ETAG).to_str().unwrap().to_string();
+    assert_ne!(second, first);
+    assert_eq!(server.get("/files/report.txt").await.text(), "second");
+    let rows = report_rows(ctx).await;
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].size, "second".len() as i64);
+
+    // The old ETag no longer matches.
+    let response = upload(&server, &token, "third", Some((header::IF_MATCH, &first))).await;
+    response.assert_status(StatusCode::PRECONDITION_FAILED);
+    let body: Value = response.json();
+    assert_eq!(body["details"]["current_e_tag"], second);
+}
+
+#[tokio::test]
+#[serial]
+async fn if_none_match_only_creates() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let editor = other_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+
+    let created = upload(&server, &token, "first", Some((header::IF_NONE_MATCH, "*"))).await;
+    created.assert_status_ok();
+
+    let response = upload(
+        &server,
+        &editor,
+        "second",
+        Some((header::IF_NONE_MATCH, "*")),
+    )
+    .await;
+    response.assert_status(StatusCode::PRECONDITION_FAILED);
+    let body: Value = response.json();
+    assert_eq!(
+        body["details"]["current_e_tag"],
+        created.header(header::ETAG).to_str().unwrap()
+    );
+    assert_eq!(server.get("/files/report.txt").await.text(), "first");
+}
+
+#[tokio::test]
+#[serial]
+async fn preconditions_need_an_existing_file_and_a_valid_header() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+
+    let response = upload(&server, &token, "first", Some((header::IF_MATCH, "*"))).await;
+    response.assert_status(StatusCode::PRECONDITION_FAILED);
+    server
+        .get("/files/report.txt")
+        .await
+        .assert_status_not_found();
+
+    let response = upload(
+        &server,
+        &token,
+        "first",
+        Some((header::IF_NONE_MATCH, "\"x\"")),
+    )
+    .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    let body: Value = response.json();
+    assert_eq!(body["code"], "invalid_precondition");
+}
@@ -0,0 +1,73 @@
+use axum::http::{StatusCode, header};
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::Value;
+use serial_test::serial;
+use server::app::App;
+use std::sync::Arc;
+
+use super::{bearer_token, test_server, upload};
+
+#[tokio::test]
+#[serial]
+async fn a_preview_is_the_first_bytes_of_the_file() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    let text: String = (0..2000)
+        .map(|i| char::from(b'a' + (i % 26) as u8))
+        .collect();
+    upload(&server, &token, "notes.txt", text.as_bytes()).await;
+    upload(&server, &token, "data.bin", &[0, 1, 2, 3, 4, 5]).await;
+
+    let preview = server.get("/files/notes.txt/preview").await;
+    preview.assert_status_ok();
+    assert_eq!(
+        preview.header(header::CONTENT_TYPE),
+        "text/plain; charset=utf-8"
+    );
+    assert_eq!(preview.text(), text[..1024]);
+
+    let short = server.get("/files/notes.txt/preview?bytes=10").await;
+    assert_eq!(short.text(), "abcdefghij");
+    let past_the_end = server.get("/files/notes.txt/preview?bytes=100000").await;
+    assert_eq!(past_the_end.text(), text);
+
+    let binary = server.get("/files/data.bin/preview?bytes=4").await;
+    binary.assert_status_ok();
+    assert_eq!(
+        binary.header(header::CONTENT_TYPE),
+        "application/octet-stream"
+    );
+    assert_eq!(binary.as_bytes().as_ref(), [0, 1, 2, 3]);
+
+    server
+        .get("/files/missing.txt/preview")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+#[serial]
+async fn require_pdf_refuses_what_does_not_start_like_one() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    upload(&server, &token, "real.pdf", b"%PDF-1.7\n%fake body").await;
+    upload(&server, &token, "fake.pdf", b"<html></html>").await;
+    upload(&server, &token, "pdf.txt", b"%P").await;
+
+    let real = server
+        .get("/files/real.pdf/preview?require_pdf=true&bytes=8")
+        .await;
+    real.assert_status_ok();
+    assert_eq!(real.as_bytes().as_ref(), b"%PDF-1.7");
+
+    for name in ["fake.pdf", "pdf.txt"] {
+        let refused = server
+            .get(&format!("/files/{name}/preview?require_pdf=true"))
+            .await;
+        refused.assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(refused.json::<Value>()["code"], "not_a_pdf");
+    }
+}
@@ -0,0 +1,91 @@
+use axum::http::StatusCode;
+use axum_test::multipart::{MultipartForm, Part};
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::Value;
+use serial_test::serial;
+use server::{app::App, models::user};
+use std::sync::Arc;
+
+use super::{bearer_token, test_server};
+
+fn form(name: &str, len: usize) -> MultipartForm {
+    MultipartForm::new().add_part("file", Part::bytes(vec![0; len]).file_name(name))
+}
+
+#[tokio::test]
+#[serial]
+async fn usage_follows_uploads_and_deletes() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(form("notes.txt", 1000))
+        .await
+        .assert_status_ok();
+
+    let quota: Value = server
+        .get("/files/quota")
+        .authorization_bearer(&token)
+        .await
+        .json();
+    assert_eq!(quota["used_bytes"], 1000);
+    assert_eq!(quota["limit_bytes"], Value::Null);
+
+    server
+        .delete("/files/notes.txt")
+        .authorization_bearer(&token)
+        .await
+        .assert_status_success();
+    let quota: Value = server
+        .get("/files/quota")
+        .authorization_bearer(&token)
+        .await
+        .json();
+    assert_eq!(quota["used_bytes"], 0);
+}
+
+#[tokio::test]
+#[serial]
+async fn uploads_past_the_quota_are_refused() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let tester = user::find_by_login(&ctx.db, "tester")
+        .await
+        .unwrap()
+        .unwrap();
+    user::set_storage_quota(&ctx.db, tester.id, Some(5000))
+        .await
+        .unwrap();
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(form("first.bin", 3000))
+        .await
+        .assert_status_ok();
+
+    let response = server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(form("second.bin", 3000))
+        .await;
+    response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    let body: Value = response.json();
+    assert_eq!(body["code"], "quota_exceeded");
+    assert_eq!(body["details"]["used"], 3000);
+    assert_eq!(body["details"]["limit"], 5000);
+
+    let quota: Value = server
+        .get("/files/quota")
+        .authorization_bearer(&token)
+        .await
+        .json();
+    assert_eq!(quota["used_bytes"], 3000);
+    assert_eq!(quota["remaining_bytes"], 2000);
+}
@@ -0,0 +1,69 @@
+use loco_rs::testing::prelude::*;
+use object_store::{ObjectStore, RetryConfig, aws::AmazonS3Builder, memory::InMemory};
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::{app::App, controllers::files};
+use std::sync::Arc;
+
+use super::{bearer_token, test_server};
+
+/// A bucket nothing listens for.
+fn unreachable_bucket() -> Arc<dyn ObjectStore> {
+    let s3 = AmazonS3Builder::new()
+        .with_endpoint("http://127.0.0.1:1")
+        .with_allow_http(true)
+        .with_bucket_name("files-dr")
+        .with_region("us-east-1")
+        .with_access_key_id("test")
+        .with_secret_access_key("test")
+        .with_retry(RetryConfig {
+            max_retries: 0,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    Arc::new(s3)
+}
+
+#[tokio::test]
+#[serial]
+async fn the_metadata_endpoint_reports_replication_status() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let primary: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let server = test_server(ctx, primary.clone());
+    for name in ["ledger.txt", "journal.txt"] {
+        server
+            .post("/files/base64")
+            .authorization_bearer(&token)
+            .json(&json!({ "name": name, "data": "bm90ZXM=" }))
+            .await
+            .assert_status_ok();
+    }
+    let metadata = server.get("/files/ledger.txt/metadata").await;
+    assert_eq!(metadata.json::<Value>()["replication"], Value::Null);
+
+    let replica: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    files::copy_to_replica(ctx, primary.as_ref(), &replica, "ledger.txt")
+        .await
+        .unwrap();
+    let metadata = server
+        .get("/files/ledger.txt/metadata")
+        .await
+        .json::<Value>();
+    assert_eq!(metadata["replication"]["status"], "synced");
+    assert_eq!(metadata["replication"]["last_error"], Value::Null);
+    let copy = replica.get(&"ledger.txt".into()).await.unwrap();
+    assert_eq!(copy.bytes().await.unwrap(), "notes");
+
+    let result =
+        files::copy_to_replica(ctx, primary.as_ref(), &unreachable_bucket(), "journal.txt").await;
+    assert!(result.is_err());
+    let metadata = server
+        .get("/files/journal.txt/metadata")
+        .await
+        .json::<Value>();
+    assert_eq!(metadata["replication"]["status"], "failed");
+    assert!(metadata["replication"]["last_error"].is_string());
+}
@@ -0,0 +1,149 @@
+use axum::http::StatusCode;
+use loco_rs::testing::prelude::*;
+use object_store::{ObjectStore, PutPayload, memory::InMemory, path::Path as ObjectPath};
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::{app::App, models::file};
+use std::sync::Arc;
+
+use super::{admin_token, bearer_token, test_server};
+
+fn names(listing: &Value) -> Vec<&str> {
+    listing
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| f["name"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+#[serial]
+async fn infected_files_are_only_listed_for_admins_who_ask() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let admin = admin_token();
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    for name in ["clean.txt", "eicar.txt"] {
+        server
+            .post("/files/base64")
+            .authorization_bearer(&token)
+            .json(&json!({ "name": name, "data": "bm90ZXM=" }))
+            .await
+            .assert_status_ok();
+    }
+    let infected = file::find_by_name(&ctx.db, "eicar.txt")
+        .await
+        .unwrap()
+        .unwrap();
+    file::set_scan_status(&ctx.db, infected.id, "infected", Some("Eicar-Signature"))
+        .await
+        .unwrap();
+
+    let listing: Value = server.get("/files").await.json();
+    assert_eq!(names(&listing), ["clean.txt"]);
+
+    server
+        .get("/files?include_quarantined=true")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+    server
+        .get("/files?include_quarantined=true")
+        .authorization_bearer(&token)
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let response = server
+        .get("/files?include_quarantined=true")
+        .authorization_bearer(&admin)
+        .await;
+    response.assert_status_ok();
+    let listing: Value = response.json();
+    assert_eq!(names(&listing), ["clean.txt", "eicar.txt"]);
+    assert_eq!(listing[1]["scan_status"], "infected");
+}
+
+#[tokio::test]
+#[serial]
+async fn rescanning_needs_an_admin_a_file_and_scanning_turned_on() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let admin = admin_token();
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    server
+        .post("/files/base64")
+        .authorization_bearer(&token)
+        .json(&json!({ "name": "notes.txt", "data": "bm90ZXM=" }))
+        .await
+        .assert_status_ok();
+
+    server
+        .post("/files/notes.txt/scan")
+        .authorization_bearer(&token)
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    server
+        .post("/files/missing.txt/scan")
+        .authorization_bearer(&admin)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    // config/test.yaml leaves scanning off.
+    let response = server
+        .post("/files/notes.txt/scan")
+        .authorization_bearer(&admin)
+        .await;
+    response.assert_status(StatusCode::NOT_IMPLEMENTED);
+    assert_eq!(response.json::<Value>()["code"], "scan_disabled");
+}
+
+#[tokio::test]
+#[serial]
+async fn quarantined_content_is_not_served_by_its_key() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let store = Arc::new(InMemory::new());
+    let server = test_server(ctx, store.clone());
+    server
+        .post("/files/base64")
+        .authorization_bearer(&token)
+        .json(&json!({ "name": "eicar.txt", "data": "bm90ZXM=" }))
+        .await
+        .assert_status_ok();
+    let infected = file::find_by_name(&ctx.db, "eicar.txt")
+        .await
+        .unwrap()
+        .unwrap();
+    file::set_scan_status(&ctx.db, infected.id, "infected", Some("Eicar-Signature"))
+        .await
+        .unwrap();
+    // Where `quarantine` moves the content of an infected file.
+    store
+        .rename(
+            &ObjectPath::from("eicar.txt"),
+            &ObjectPath::from("quarantine/eicar.txt"),
+        )
+        .await
+        .unwrap();
+    store
+        .put(
+            &ObjectPath::from("blobs/0123abcd"),
+            PutPayload::from_static(b"shared"),
+        )
+        .await
+        .unwrap();
+
+    let response = server.get("/files/eicar.txt").await;
+    response.assert_status(StatusCode::FORBIDDEN);
+    assert_eq!(response.json::<Value>()["code"], "file_infected");
+    for key in ["quarantine%2Feicar.txt", "blobs%2F0123abcd"] {
+        server
+            .get(&format!("/files/{key}"))
+            .await
+            .assert_status_not_found();
+        let exists: Value = server.get(&format!("/files/{key}/exists")).await.json();
+        assert_eq!(exists["exists"], false);
+    }
+}
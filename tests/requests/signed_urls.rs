@@ -0,0 +1,102 @@
+use axum::http::StatusCode;
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::Value;
+use serial_test::serial;
+use server::{app::App, signed_urls};
+use std::sync::Arc;
+
+use super::{bearer_token, seed, test_server};
+
+/// `signing_secret` in config/test.yaml.
+const SECRET: &str = "test-signing-secret";
+
+#[tokio::test]
+#[serial]
+async fn a_signed_link_downloads_without_a_token() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    seed(&server, &token, "docs/q3 report.txt", "bm90ZXM=").await;
+
+    let response = server
+        .get("/files/docs%2Fq3%20report.txt/signed-url")
+        .authorization_bearer(&token)
+        .await;
+    response.assert_status_ok();
+    let body: Value = response.json();
+    let url = body["url"].as_str().unwrap();
+    assert!(
+        url.starts_with("/files/docs%2Fq3%20report.txt?token="),
+        "{url}"
+    );
+    let expires_at = chrono::DateTime::parse_from_rfc3339(body["expires_at"].as_str().unwrap())
+        .unwrap()
+        .timestamp();
+    assert!(url.ends_with(&format!("&expires={expires_at}")), "{url}");
+    assert!(expires_at > chrono::Utc::now().timestamp());
+
+    let response = server.get(url).await;
+    response.assert_status_ok();
+    assert_eq!(response.text(), "notes");
+}
+
+#[tokio::test]
+#[serial]
+async fn forged_and_expired_links_are_refused() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    seed(&server, &token, "report.txt", "bm90ZXM=").await;
+
+    let expires = chrono::Utc::now().timestamp() + 60;
+    let other = signed_urls::sign(SECRET, "other.txt", expires);
+    let response = server
+        .get(&format!(
+            "/files/report.txt?token={other}&expires={expires}"
+        ))
+        .await;
+    response.assert_status(StatusCode::FORBIDDEN);
+    assert_eq!(response.json::<Value>()["code"], "invalid_signature");
+
+    let signed = signed_urls::sign(SECRET, "report.txt", expires);
+    let response = server
+        .get(&format!(
+            "/files/report.txt?token={signed}&expires={}",
+            expires + 1
+        ))
+        .await;
+    response.assert_status(StatusCode::FORBIDDEN);
+
+    let past = chrono::Utc::now().timestamp() - 1;
+    let signed = signed_urls::sign(SECRET, "report.txt", past);
+    let response = server
+        .get(&format!("/files/report.txt?token={signed}&expires={past}"))
+        .await;
+    response.assert_status(StatusCode::FORBIDDEN);
+    assert_eq!(response.json::<Value>()["code"], "signature_expired");
+
+    server
+        .get(&format!("/files/report.txt?token={signed}"))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    server.get("/files/report.txt").await.assert_status_ok();
+}
+
+#[tokio::test]
+#[serial]
+async fn links_need_a_token_and_an_existing_file() {
+    let boot = boot_test::<App>().await.unwrap();
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    server
+        .get("/files/report.txt/signed-url")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    let token = bearer_token(&boot.app_context).await;
+    server
+        .get("/files/missing.txt/signed-url")
+        .authorization_bearer(&token)
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
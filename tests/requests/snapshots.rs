@@ -0,0 +1,119 @@
+use axum::http::StatusCode;
+use axum_test::TestServer;
+use loco_rs::testing::prelude::*;
+use object_store::{ObjectStore, PutPayload, memory::InMemory, path::Path as ObjectPath};
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::{
+    app::App,
+    controllers::auth,
+    models::{role, user},
+};
+use std::sync::Arc;
+
+use super::{admin_token, bearer_token, seed, test_server};
+
+const SNAPSHOT: &str = "notes.txt__v1700000000000";
+
+/// `notes.txt` with one snapshot of an earlier overwrite next to it.
+async fn seed_with_snapshot(server: &TestServer, store: &InMemory, token: &str) {
+    seed(server, token, "notes.txt", "bm90ZXM=").await;
+    store
+        .put(&ObjectPath::from(SNAPSHOT), PutPayload::from_static(b"old"))
+        .await
+        .unwrap();
+}
+
+async fn delete_entries(server: &TestServer) -> Vec<(String, String, Option<i64>)> {
+    let admin = admin_token();
+    let entries: Value = server
+        .get("/files/audit")
+        .authorization_bearer(&admin)
+        .await
+        .json();
+    entries["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|e| e["operation"] == "DELETE")
+        .map(|e| {
+            (
+                e["file_key"].as_str().unwrap().to_string(),
+                e["outcome"].as_str().unwrap().to_string(),
+                e["status_code"].as_i64(),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+#[serial]
+async fn a_snapshot_is_deleted_by_its_timestamp_and_audited() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let store = Arc::new(InMemory::new());
+    let server = test_server(&boot.app_context, store.clone());
+    seed_with_snapshot(&server, &store, &token).await;
+
+    let response = server
+        .delete("/files/notes.txt/snapshots/1700000000000")
+        .authorization_bearer(&token)
+        .await;
+
+    response.assert_status_ok();
+    assert_eq!(response.json::<Value>()["deleted"], SNAPSHOT);
+    assert!(store.head(&ObjectPath::from(SNAPSHOT)).await.is_err());
+    assert_eq!(
+        delete_entries(&server).await,
+        [(SNAPSHOT.to_string(), "SUCCESS".to_string(), None)]
+    );
+
+    // Version numbers of the index stay on their own path.
+    server
+        .get("/files/notes.txt/versions/1")
+        .authorization_bearer(&token)
+        .await
+        .assert_status_ok();
+    server
+        .delete("/files/notes.txt/versions/1")
+        .authorization_bearer(&token)
+        .await
+        .assert_status(StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[tokio::test]
+#[serial]
+async fn a_snapshot_of_a_file_being_edited_is_kept() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let role = role::create(&ctx.db, "editor", json!(["read"]))
+        .await
+        .unwrap();
+    let editor = user::create(&ctx.db, "Editor", "editor", "unused", role.id)
+        .await
+        .unwrap();
+    let editor_token =
+        auth::generate_token(&editor.id.to_string(), &editor.login, vec!["editor".into()]).unwrap();
+    let store = Arc::new(InMemory::new());
+    let server = test_server(ctx, store.clone());
+    seed_with_snapshot(&server, &store, &token).await;
+    server
+        .post("/files/notes.txt/lock")
+        .authorization_bearer(&editor_token)
+        .await
+        .assert_status_ok();
+
+    let response = server
+        .delete("/files/notes.txt/snapshots/1700000000000")
+        .authorization_bearer(&token)
+        .await;
+
+    response.assert_status(StatusCode::LOCKED);
+    assert_eq!(response.json::<Value>()["code"], "edit_locked");
+    assert!(store.head(&ObjectPath::from(SNAPSHOT)).await.is_ok());
+    assert_eq!(
+        delete_entries(&server).await,
+        [(SNAPSHOT.to_string(), "FAILURE".to_string(), Some(423))]
+    );
+}
@@ -0,0 +1,94 @@
+use axum::http::StatusCode;
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::Value;
+use serial_test::serial;
+use server::app::App;
+use std::sync::Arc;
+
+use super::{admin_token, bearer_token, test_server, upload};
+
+#[tokio::test]
+#[serial]
+async fn stats_break_storage_down_by_prefix_and_type() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    upload(&server, &token, "docs/report.pdf", &[b'x'; 300]).await;
+    upload(&server, &token, "docs/notes.pdf", &[b'x'; 100]).await;
+    upload(&server, &token, "photo.jpg", &[b'x'; 200]).await;
+
+    let response = server
+        .get("/files/stats?top=2")
+        .authorization_bearer(&token)
+        .await;
+
+    response.assert_status_ok();
+    let body: Value = response.json();
+    let storage = &body["storage"];
+    // Every upload also keeps its first version under `versions/`.
+    assert_eq!(storage["object_count"], 6);
+    assert_eq!(storage["total_bytes"], 1200);
+    assert_eq!(storage["by_prefix"]["versions/"]["bytes"], 600);
+    assert_eq!(storage["by_prefix"]["docs/"]["count"], 2);
+    assert_eq!(storage["by_prefix"]["docs/"]["bytes"], 400);
+    assert_eq!(storage["by_prefix"]["/"]["bytes"], 200);
+    assert_eq!(storage["by_content_type"]["application/pdf"]["count"], 4);
+    assert_eq!(storage["by_content_type"]["image/jpeg"]["bytes"], 400);
+    let largest = storage["largest_objects"].as_array().unwrap();
+    assert_eq!(largest.len(), 2);
+    assert_eq!(largest[0]["key"], "docs/report.pdf");
+    assert_eq!(largest[1]["key"], "versions/1/v1/docs/report.pdf");
+    assert!(storage["generated_at"].is_string());
+    // Other tests may be transferring at the same time.
+    assert!(body["uploads"]["in_flight"].is_u64());
+    assert!(body["downloads"]["queued"].is_u64());
+    assert!(body["download_cache"].is_null());
+    assert!(body["memory_cache"]["hit_ratio"].is_f64());
+}
+
+#[tokio::test]
+#[serial]
+async fn stats_are_cached_until_an_admin_refreshes_them() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    upload(&server, &token, "a.txt", &[b'x'; 10]).await;
+
+    let first: Value = server
+        .get("/files/stats")
+        .authorization_bearer(&token)
+        .await
+        .json();
+    upload(&server, &token, "b.txt", &[b'x'; 10]).await;
+    let cached: Value = server
+        .get("/files/stats")
+        .authorization_bearer(&token)
+        .await
+        .json();
+    assert_eq!(
+        cached["storage"]["object_count"],
+        first["storage"]["object_count"]
+    );
+    assert_eq!(
+        cached["storage"]["generated_at"],
+        first["storage"]["generated_at"]
+    );
+
+    server
+        .get("/files/stats?refresh=true")
+        .authorization_bearer(&token)
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+
+    let admin = admin_token();
+    let refreshed: Value = server
+        .get("/files/stats?refresh=true")
+        .authorization_bearer(admin)
+        .await
+        .json();
+    assert_eq!(
+        refreshed["storage"]["object_count"].as_u64().unwrap(),
+        first["storage"]["object_count"].as_u64().unwrap() * 2
+    );
+}
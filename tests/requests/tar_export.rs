@@ -0,0 +1,129 @@
+use async_compression::tokio::bufread::GzipDecoder;
+use axum::http::{StatusCode, header};
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::json;
+use serial_test::serial;
+use server::app::App;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+
+use super::{bearer_token, seed, test_server};
+
+/// Names and contents of a ustar archive, checking it ends properly.
+fn entries(archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let text = |bytes: &[u8]| {
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8(bytes[..end].to_vec()).unwrap()
+    };
+    let mut found = Vec::new();
+    let mut at = 0;
+    loop {
+        let block = &archive[at..at + 512];
+        if block.iter().all(|&b| b == 0) {
+            assert_eq!(
+                archive.len(),
+                at + 1024,
+                "archive does not end after two zero blocks"
+            );
+            return found;
+        }
+        let size = usize::from_str_radix(&text(&block[124..136]), 8).unwrap();
+        let prefix = text(&block[345..500]);
+        let name = text(&block[0..100]);
+        let name = if prefix.is_empty() {
+            name
+        } else {
+            format!("{prefix}/{name}")
+        };
+        found.push((name, archive[at + 512..at + 512 + size].to_vec()));
+        at += 512 + size.div_ceil(512) * 512;
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn exports_a_folder_as_a_tar() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    seed(&server, &token, "reports/q1.txt", "bm90ZXM=").await;
+    seed(&server, &token, "reports/q2.txt", "c3VtbWFyeQ==").await;
+    seed(&server, &token, "other.txt", "bm90ZXM=").await;
+
+    let response = server
+        .get("/files/export.tar?prefix=reports")
+        .authorization_bearer(&token)
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.header(header::CONTENT_TYPE), "application/x-tar");
+    assert_eq!(
+        entries(response.as_bytes()),
+        vec![
+            ("reports/q1.txt".to_string(), b"notes".to_vec()),
+            ("reports/q2.txt".to_string(), b"summary".to_vec()),
+        ]
+    );
+
+    let response = server
+        .get("/files/export.tar")
+        .authorization_bearer(&token)
+        .await;
+    response.assert_status_ok();
+    assert_eq!(entries(response.as_bytes()).len(), 3);
+
+    server
+        .get("/files/export.tar?prefix=../etc")
+        .authorization_bearer(&token)
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+    server
+        .get("/files/export.tar")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+#[serial]
+async fn exports_named_files_as_a_gzipped_tar() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    seed(&server, &token, "reports/q1.txt", "bm90ZXM=").await;
+    seed(&server, &token, "other.txt", "c3VtbWFyeQ==").await;
+
+    let response = server
+        .post("/files/export.tar")
+        .authorization_bearer(&token)
+        .json(&json!({ "files": ["other.txt", "reports/q1.txt", "other.txt"], "gzip": true }))
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.header(header::CONTENT_TYPE), "application/gzip");
+    let mut archive = Vec::new();
+    GzipDecoder::new(response.as_bytes().as_ref())
+        .read_to_end(&mut archive)
+        .await
+        .unwrap();
+    assert_eq!(
+        entries(&archive),
+        vec![
+            ("other.txt".to_string(), b"summary".to_vec()),
+            ("reports/q1.txt".to_string(), b"notes".to_vec()),
+        ]
+    );
+
+    server
+        .post("/files/export.tar")
+        .authorization_bearer(&token)
+        .json(&json!({ "files": ["other.txt", "missing.txt"] }))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    server
+        .post("/files/export.tar")
+        .authorization_bearer(&token)
+        .json(&json!({ "files": [] }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,398 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::IntoResponse,
+};
+use axum_test::{
+    TestServer,
+    multipart::{MultipartForm, Part},
+};
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::{
+    app::App,
+    controllers::{
+        auth,
+        files::{self, Tenant, UpdateWithVersionRequest},
+    },
+    models::user,
+};
+use std::sync::Arc;
+
+use super::{admin_token, bearer_token, test_server};
+
+// `acme` and `globex` are the tenants of `config/test.yaml`.
+
+fn form(name: &str) -> MultipartForm {
+    form_with(name, "quarterly")
+}
+
+fn form_with(name: &str, content: &str) -> MultipartForm {
+    MultipartForm::new().add_part(
+        "file",
+        Part::bytes(content.as_bytes().to_vec()).file_name(name),
+    )
+}
+
+async fn listed_names(server: &TestServer, tenant: Option<&str>) -> Vec<String> {
+    let mut request = server.get("/files");
+    if let Some(tenant) = tenant {
+        request = request.add_header("x-tenant-id", tenant);
+    }
+    request
+        .await
+        .json::<Vec<Value>>()
+        .iter()
+        .map(|f| f["name"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+#[serial]
+async fn tenants_only_see_their_own_files() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .add_header("x-tenant-id", "acme")
+        .multipart(form("acme-report.txt"))
+        .await
+        .assert_status_ok();
+
+    assert!(
+        listed_names(&server, Some("acme"))
+            .await
+            .contains(&"acme-report.txt".into())
+    );
+    assert!(
+        !listed_names(&server, Some("globex"))
+            .await
+            .contains(&"acme-report.txt".into())
+    );
+    assert!(
+        !listed_names(&server, None)
+            .await
+            .contains(&"acme-report.txt".into())
+    );
+
+    let response = server
+        .get("/files/acme-report.txt")
+        .add_header("x-tenant-id", "acme")
+        .await;
+    response.assert_status_ok();
+    assert_eq!(response.text(), "quarterly");
+    server
+        .get("/files/acme-report.txt")
+        .add_header("x-tenant-id", "globex")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+#[serial]
+async fn unknown_tenants_are_forbidden() {
+    let boot = boot_test::<App>().await.unwrap();
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+
+    let response = server
+        .get("/files")
+        .add_header("x-tenant-id", "initech")
+        .await;
+
+    response.assert_status(StatusCode::FORBIDDEN);
+    assert_eq!(response.json::<Value>()["code"], "unknown_tenant");
+}
+
+#[tokio::test]
+#[serial]
+async fn the_token_claim_picks_the_tenant() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    bearer_token(ctx).await;
+    let tester = user::find_by_login(&ctx.db, "tester")
+        .await
+        .unwrap()
+        .unwrap();
+    let token = auth::generate_tenant_token(
+        &tester.id.to_string(),
+        &tester.login,
+        vec!["tester".into()],
+        Some("globex"),
+    )
+    .unwrap();
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .multipart(form("globex-plan.txt"))
+        .await
+        .assert_status_ok();
+    assert!(
+        listed_names(&server, Some("globex"))
+            .await
+            .contains(&"globex-plan.txt".into())
+    );
+    assert!(
+        !listed_names(&server, None)
+            .await
+            .contains(&"globex-plan.txt".into())
+    );
+
+    let response = server
+        .get("/files")
+        .authorization_bearer(&token)
+        .add_header("x-tenant-id", "acme")
+        .await;
+    response.assert_status(StatusCode::FORBIDDEN);
+    assert_eq!(response.json::<Value>()["code"], "tenant_mismatch");
+}
+
+#[tokio::test]
+#[serial]
+async fn a_tenant_deletes_only_its_own_file_of_a_shared_name() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let tester = user::find_by_login(&ctx.db, "tester")
+        .await
+        .unwrap()
+        .unwrap();
+    let other = user::create(&ctx.db, "Other", "other", "unused", tester.role_id)
+        .await
+        .unwrap();
+    let other_token =
+        auth::generate_token(&other.id.to_string(), &other.login, vec!["tester".into()]).unwrap();
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    for (tenant, token) in [("acme", &token), ("globex", &other_token)] {
+        server
+            .post("/files")
+            .authorization_bearer(token)
+            .add_header("x-tenant-id", tenant)
+            .multipart(form_with("same.txt", &format!("{tenant} copy")))
+            .await
+            .assert_status_ok();
+    }
+
+    server
+        .delete("/files/same.txt")
+        .authorization_bearer(&other_token)
+        .add_header("x-tenant-id", "globex")
+        .await
+        .assert_status_ok();
+
+    server
+        .get("/files/same.txt")
+        .add_header("x-tenant-id", "globex")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    let kept = server
+        .get("/files/same.txt")
+        .add_header("x-tenant-id", "acme")
+        .await;
+    kept.assert_status_ok();
+    assert_eq!(kept.text(), "acme copy");
+    assert_eq!(listed_names(&server, Some("acme")).await, ["same.txt"]);
+    assert!(listed_names(&server, Some("globex")).await.is_empty());
+}
+
+#[tokio::test]
+#[serial]
+async fn syncing_a_bucket_leaves_other_tenants_files_alone() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let admin = admin_token();
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    server
+        .post("/files")
+        .authorization_bearer(&token)
+        .add_header("x-tenant-id", "acme")
+        .multipart(form("acme-ledger.txt"))
+        .await
+        .assert_status_ok();
+
+    let synced = server
+        .post("/files/sync/storage")
+        .authorization_bearer(&admin)
+        .await;
+    synced.assert_status_ok();
+    assert_eq!(synced.json::<Value>()["removed"], 0);
+    assert!(
+        listed_names(&server, Some("acme"))
+            .await
+            .contains(&"acme-ledger.txt".into())
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn one_user_may_upload_a_name_to_two_tenants() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+
+    for tenant in ["acme", "globex"] {
+        server
+            .post("/files")
+            .authorization_bearer(&token)
+            .add_header("x-tenant-id", tenant)
+            .multipart(form_with("report.txt", &format!("{tenant} copy")))
+            .await
+            .assert_status_ok();
+    }
+
+    for tenant in ["acme", "globex"] {
+        let response = server
+            .get("/files/report.txt")
+            .add_header("x-tenant-id", tenant)
+            .await;
+        response.assert_status_ok();
+        assert_eq!(response.text(), format!("{tenant} copy"));
+    }
+}
+
+/// Uploads `report.txt` to acme, returning its id.
+async fn acme_report(server: &TestServer, token: &str) -> i64 {
+    let response = server
+        .post("/files")
+        .authorization_bearer(token)
+        .add_header("x-tenant-id", "acme")
+        .multipart(form("report.txt"))
+        .await;
+    response.assert_status_ok();
+    response.json::<Value>()["uploaded"][0]["id"]
+        .as_i64()
+        .unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn versions_of_another_tenants_file_are_not_found() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    let id = acme_report(&server, &token).await;
+
+    server
+        .get(&format!("/files/{id}/versions"))
+        .authorization_bearer(&token)
+        .add_header("x-tenant-id", "acme")
+        .await
+        .assert_status_ok();
+    server
+        .get(&format!("/files/{id}/versions"))
+        .authorization_bearer(&token)
+        .add_header("x-tenant-id", "globex")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+#[serial]
+async fn another_tenants_file_is_not_reverted() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    let id = acme_report(&server, &token).await;
+
+    server
+        .post(&format!("/files/{id}/revert"))
+        .authorization_bearer(&token)
+        .add_header("x-tenant-id", "globex")
+        .json(&json!({ "version": 1 }))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+#[serial]
+async fn another_tenants_file_is_not_synced() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    let id = acme_report(&server, &token).await;
+
+    server
+        .post("/files/sync")
+        .authorization_bearer(&token)
+        .add_header("x-tenant-id", "globex")
+        .multipart(
+            form_with("report.txt", "overwritten")
+                .add_text("file_id", id.to_string())
+                .add_text("version", "1"),
+        )
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    let kept = server
+        .get("/files/report.txt")
+        .add_header("x-tenant-id", "acme")
+        .await;
+    assert_eq!(kept.text(), "quarterly");
+}
+
+#[tokio::test]
+#[serial]
+async fn another_tenants_file_is_not_updated_by_version() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    let id = acme_report(&server, &token).await;
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_str(&format!("Bearer {token}")).unwrap(),
+    );
+
+    // Not routed, so called as a function.
+    let response = files::update_file_with_version(
+        State(ctx.clone()),
+        Extension(Tenant(Some("globex".into()))),
+        headers,
+        Path(i32::try_from(id).unwrap()),
+        Json(UpdateWithVersionRequest {
+            version: 1,
+            size: 1,
+        }),
+    )
+    .await
+    .into_response();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+#[serial]
+async fn download_counts_are_kept_per_tenant() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    acme_report(&server, &token).await;
+    server
+        .get("/files/report.txt")
+        .add_header("x-tenant-id", "acme")
+        .await
+        .assert_status_ok();
+
+    server
+        .get("/files/report.txt/download-count")
+        .add_header("x-tenant-id", "globex")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    server
+        .get("/files/report.txt/download-count")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+    server
+        .get("/files/report.txt/download-count")
+        .add_header("x-tenant-id", "acme")
+        .await
+        .assert_status_ok();
+}
@@ -0,0 +1,43 @@
+use axum::http::StatusCode;
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::Value;
+use serial_test::serial;
+use server::app::App;
+use std::sync::Arc;
+
+use super::{bearer_token, test_server, upload};
+
+#[tokio::test]
+#[serial]
+async fn a_pending_thumbnail_is_reported_as_processing() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    upload(&server, &token, "photo.jpg", &vec![0; 300 * 1024]).await;
+
+    let response = server.get("/files/photo.jpg/thumbnail").await;
+
+    response.assert_status(StatusCode::NOT_FOUND);
+    let body: Value = response.json();
+    assert_eq!(body["code"], "thumbnail_processing");
+    assert_eq!(body["details"]["processing"], true);
+}
+
+#[tokio::test]
+#[serial]
+async fn files_that_get_no_thumbnail_are_not_found() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server(&boot.app_context, Arc::new(InMemory::new()));
+    upload(&server, &token, "animation.gif", &vec![0; 300 * 1024]).await;
+    upload(&server, &token, "small.png", &vec![0; 1024]).await;
+
+    let response = server.get("/files/animation.gif/thumbnail").await;
+    response.assert_status(StatusCode::NOT_FOUND);
+    assert_eq!(response.json::<Value>()["code"], "not_found");
+
+    let response = server.get("/files/small.png/thumbnail").await;
+    response.assert_status(StatusCode::FOUND);
+    assert_eq!(response.header("location"), "/files/small.png");
+}
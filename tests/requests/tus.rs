@@ -0,0 +1,216 @@
+use axum::http::{Method, StatusCode};
+use axum_test::{TestRequest, TestServer};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use loco_rs::testing::prelude::*;
+use object_store::{ObjectStore, memory::InMemory, path::Path as ObjectPath};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, sea_query::Expr};
+use serde_json::Value;
+use serial_test::serial;
+use server::{
+    app::App,
+    controllers::files::{self},
+    models::tus_upload,
+};
+use std::sync::Arc;
+
+use super::{bearer_token, test_server_with_parts};
+
+const CHUNK_TYPE: &str = "application/offset+octet-stream";
+
+fn tus(request: TestRequest, token: &str) -> TestRequest {
+    request
+        .authorization_bearer(token)
+        .add_header("Tus-Resumable", "1.0.0")
+}
+
+async fn create_upload(server: &TestServer, token: &str, name: &str, length: usize) -> String {
+    let metadata = format!(
+        "filename {},title {}",
+        BASE64_STANDARD.encode(name),
+        BASE64_STANDARD.encode("Field report")
+    );
+    let response = tus(server.post("/files/tus"), token)
+        .add_header("Upload-Length", length.to_string())
+        .add_header("Upload-Metadata", metadata)
+        .await;
+    response.assert_status(StatusCode::CREATED);
+    assert_eq!(response.header("tus-resumable"), "1.0.0");
+    response.header("location").to_str().unwrap().to_string()
+}
+
+fn patch(
+    server: &TestServer,
+    token: &str,
+    location: &str,
+    offset: usize,
+    body: Vec<u8>,
+) -> TestRequest {
+    tus(server.patch(location), token)
+        .add_header("Upload-Offset", offset.to_string())
+        .content_type(CHUNK_TYPE)
+        .bytes(body.into())
+}
+
+async fn stored(store: &InMemory, key: &str) -> Vec<u8> {
+    store
+        .get(&ObjectPath::from(key))
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap()
+        .to_vec()
+}
+
+#[tokio::test]
+#[serial]
+async fn chunks_resume_at_the_reported_offset_and_finish_as_a_file() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let store = Arc::new(InMemory::new());
+    let server = test_server_with_parts(&boot.app_context, store.clone());
+
+    let location = create_upload(&server, &token, "report.txt", 11).await;
+
+    let response = patch(&server, &token, &location, 0, b"hello ".to_vec()).await;
+    response.assert_status(StatusCode::NO_CONTENT);
+    assert_eq!(response.header("upload-offset"), "6");
+
+    let head = tus(server.method(Method::HEAD, &location), &token).await;
+    head.assert_status_ok();
+    assert_eq!(head.header("upload-offset"), "6");
+    assert_eq!(head.header("upload-length"), "11");
+
+    let response = patch(&server, &token, &location, 6, b"world".to_vec()).await;
+    response.assert_status(StatusCode::NO_CONTENT);
+    assert_eq!(response.header("upload-offset"), "11");
+
+    assert_eq!(stored(&store, "report.txt").await, b"hello world");
+    let listing: Value = server.get("/files").await.json();
+    let file = &listing.as_array().unwrap()[0];
+    assert_eq!(file["name"], "report.txt");
+    assert_eq!(file["size"], 11);
+    assert_eq!(file["metadata"]["title"], "Field report");
+
+    tus(server.method(Method::HEAD, &location), &token)
+        .await
+        .assert_status_not_found();
+}
+
+#[tokio::test]
+#[serial]
+async fn uploads_larger_than_a_part_are_assembled_in_order() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let store = Arc::new(InMemory::new());
+    let server = test_server_with_parts(&boot.app_context, store.clone());
+
+    let content: Vec<u8> = (0..7 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let location = create_upload(&server, &token, "big.bin", content.len()).await;
+
+    let split = 3 * 1024 * 1024;
+    patch(&server, &token, &location, 0, content[..split].to_vec())
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+    patch(&server, &token, &location, split, content[split..].to_vec())
+        .await
+        .assert_status(StatusCode::NO_CONTENT);
+
+    assert_eq!(stored(&store, "big.bin").await, content);
+}
+
+#[tokio::test]
+#[serial]
+async fn a_wrong_offset_is_a_conflict() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server_with_parts(&boot.app_context, Arc::new(InMemory::new()));
+    let location = create_upload(&server, &token, "report.txt", 11).await;
+
+    let response = patch(&server, &token, &location, 5, b"world".to_vec()).await;
+
+    response.assert_status(StatusCode::CONFLICT);
+    let body: Value = response.json();
+    assert_eq!(body["code"], "offset_mismatch");
+    assert_eq!(body["details"]["expected"], 0);
+}
+
+#[tokio::test]
+#[serial]
+async fn chunks_need_the_tus_content_type() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server_with_parts(&boot.app_context, Arc::new(InMemory::new()));
+    let location = create_upload(&server, &token, "report.txt", 11).await;
+
+    tus(server.patch(&location), &token)
+        .add_header("Upload-Offset", "0")
+        .content_type("application/octet-stream")
+        .bytes(b"hello".to_vec().into())
+        .await
+        .assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+#[tokio::test]
+#[serial]
+async fn requests_without_tus_resumable_are_refused() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server_with_parts(&boot.app_context, Arc::new(InMemory::new()));
+
+    let response = server
+        .post("/files/tus")
+        .authorization_bearer(&token)
+        .add_header("Upload-Length", "11")
+        .await;
+
+    response.assert_status(StatusCode::PRECONDITION_FAILED);
+    assert_eq!(response.header("tus-resumable"), "1.0.0");
+}
+
+#[tokio::test]
+#[serial]
+async fn uploads_over_the_size_limit_are_refused_up_front() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server_with_parts(&boot.app_context, Arc::new(InMemory::new()));
+
+    let metadata = format!("filename {}", BASE64_STANDARD.encode("huge.bin"));
+    tus(server.post("/files/tus"), &token)
+        .add_header("Upload-Length", (200u64 * 1024 * 1024).to_string())
+        .add_header("Upload-Metadata", metadata)
+        .await
+        .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+#[serial]
+async fn stale_uploads_are_expired() {
+    let boot = boot_test::<App>().await.unwrap();
+    let token = bearer_token(&boot.app_context).await;
+    let server = test_server_with_parts(&boot.app_context, Arc::new(InMemory::new()));
+    let location = create_upload(&server, &token, "report.txt", 11).await;
+    let id = location.rsplit('/').next().unwrap();
+
+    let long_ago = chrono::Utc::now().naive_utc() - chrono::Duration::days(30);
+    tus_upload::Entity::update_many()
+        .col_expr(tus_upload::Column::UpdatedAt, Expr::value(long_ago))
+        .filter(tus_upload::Column::Id.eq(id))
+        .exec(&boot.app_context.db)
+        .await
+        .unwrap();
+
+    tus(server.method(Method::HEAD, &location), &token)
+        .await
+        .assert_status(StatusCode::GONE);
+
+    let expired = files::expire_tus_uploads(&boot.app_context).await.unwrap();
+
+    assert_eq!(expired, 1);
+    assert!(
+        tus_upload::find(&boot.app_context.db, id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
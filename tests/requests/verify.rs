@@ -0,0 +1,144 @@
+use axum_test::TestServer;
+use loco_rs::{app::AppContext, testing::prelude::*};
+use object_store::{ObjectStore, memory::InMemory, path::Path as ObjectPath};
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::{
+    app::App,
+    controllers::files::{self, OrphanObjects, VerifyOptions},
+};
+use std::{sync::Arc, time::Duration};
+
+use super::{bearer_token, test_server};
+
+/// Three indexed files, then drift made behind the server's back: one
+/// object gone, one replaced with more bytes and one added.
+async fn drifted(ctx: &AppContext) -> (TestServer, Arc<dyn ObjectStore>) {
+    let token = bearer_token(ctx).await;
+    let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let server = test_server(ctx, store.clone());
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        server
+            .post("/files/base64")
+            .authorization_bearer(&token)
+            .json(&json!({ "name": name, "data": "bm90ZXM=" }))
+            .await
+            .assert_status_ok();
+    }
+    store.delete(&ObjectPath::from("b.txt")).await.unwrap();
+    store
+        .put(&ObjectPath::from("c.txt"), "more notes".into())
+        .await
+        .unwrap();
+    store
+        .put(&ObjectPath::from("stray.txt"), "stray".into())
+        .await
+        .unwrap();
+    (server, store)
+}
+
+fn immediate(fix: bool) -> VerifyOptions {
+    VerifyOptions {
+        fix,
+        min_age: Duration::ZERO,
+        ..Default::default()
+    }
+}
+
+fn summary(report: &impl serde::Serialize) -> Value {
+    let report = serde_json::to_value(report).unwrap();
+    json!({
+        "in_sync": report["in_sync"],
+        "object_without_row": report["object_without_row"]["keys"],
+        "row_without_object": report["row_without_object"]["keys"],
+        "size_mismatch": report["size_mismatch"]["keys"],
+    })
+}
+
+#[tokio::test]
+#[serial]
+async fn drift_is_reported_then_fixed() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let (server, store) = drifted(ctx).await;
+
+    let report = files::verify_storage(ctx, store.as_ref(), &immediate(false))
+        .await
+        .unwrap();
+    assert_eq!(
+        summary(&report),
+        json!({
+            "in_sync": 1,
+            "object_without_row": ["stray.txt"],
+            "row_without_object": ["b.txt"],
+            "size_mismatch": ["c.txt"],
+        })
+    );
+    assert_eq!((report.objects, report.files, report.failed), (3, 3, 0));
+    assert_eq!(report.size_mismatch.fixed, 0);
+    let listed: Vec<Value> = server.get("/files").await.json();
+    assert_eq!(listed.len(), 3);
+
+    let report = files::verify_storage(ctx, store.as_ref(), &immediate(true))
+        .await
+        .unwrap();
+    assert_eq!(
+        (
+            report.object_without_row.fixed,
+            report.row_without_object.fixed,
+            report.size_mismatch.fixed,
+        ),
+        (1, 1, 1)
+    );
+    let listed: Vec<Value> = server.get("/files").await.json();
+    let sizes: Vec<(&str, i64)> = listed
+        .iter()
+        .map(|f| (f["name"].as_str().unwrap(), f["size"].as_i64().unwrap()))
+        .collect();
+    assert_eq!(sizes, [("a.txt", 5), ("c.txt", 10), ("stray.txt", 5)]);
+
+    let again = files::verify_storage(ctx, store.as_ref(), &immediate(false))
+        .await
+        .unwrap();
+    assert_eq!(
+        summary(&again),
+        json!({
+            "in_sync": 4,
+            "object_without_row": [],
+            "row_without_object": [],
+            "size_mismatch": [],
+        })
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn recent_changes_are_left_alone_and_strays_can_be_deleted() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let (_server, store) = drifted(ctx).await;
+
+    let fresh = files::verify_storage(
+        ctx,
+        store.as_ref(),
+        &VerifyOptions {
+            fix: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(fresh.skipped_recent, 4);
+    assert_eq!(fresh.in_sync, 0);
+    assert!(store.head(&ObjectPath::from("stray.txt")).await.is_ok());
+
+    let options = VerifyOptions {
+        orphan_objects: OrphanObjects::Delete,
+        ..immediate(true)
+    };
+    let report = files::verify_storage(ctx, store.as_ref(), &options)
+        .await
+        .unwrap();
+    assert_eq!(report.object_without_row.fixed, 1);
+    assert!(store.head(&ObjectPath::from("stray.txt")).await.is_err());
+}
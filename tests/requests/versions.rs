@@ -0,0 +1,165 @@
+use axum::http::StatusCode;
+use axum_test::{
+    TestResponse, TestServer,
+    multipart::{MultipartForm, Part},
+};
+use loco_rs::testing::prelude::*;
+use object_store::memory::InMemory;
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::{
+    app::App,
+    controllers::auth,
+    models::{role, user},
+};
+use std::sync::Arc;
+
+use super::{admin_token, bearer_token, test_server, upload};
+
+async fn sync(
+    server: &TestServer,
+    token: &str,
+    id: i64,
+    version: i32,
+    content: &str,
+) -> TestResponse {
+    server
+        .post("/files/sync")
+        .authorization_bearer(token)
+        .multipart(
+            MultipartForm::new()
+                .add_text("file_id", id.to_string())
+                .add_text("version", version.to_string())
+                .add_part(
+                    "file",
+                    Part::bytes(content.as_bytes().to_vec()).file_name("report.txt"),
+                ),
+        )
+        .await
+}
+
+async fn report_id(server: &TestServer) -> i64 {
+    let listing: Value = server.get("/files").await.json();
+    listing[0]["id"].as_i64().unwrap()
+}
+
+async fn upload_entries(server: &TestServer) -> Vec<(String, Option<i64>)> {
+    let entries: Value = server
+        .get("/files/audit")
+        .authorization_bearer(admin_token())
+        .await
+        .json();
+    entries["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|e| e["operation"] == "UPLOAD")
+        .map(|e| {
+            (
+                e["outcome"].as_str().unwrap().to_string(),
+                e["status_code"].as_i64(),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+#[serial]
+async fn a_sync_and_a_revert_each_store_an_audited_version() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    upload(&server, &token, "report.txt", b"first").await;
+    let id = report_id(&server).await;
+
+    let synced = sync(&server, &token, id, 1, "second").await;
+    synced.assert_status_ok();
+    assert_eq!(synced.json::<Value>()["version"], 2);
+    assert_eq!(server.get("/files/report.txt").await.text(), "second");
+
+    let stale = sync(&server, &token, id, 1, "third").await;
+    stale.assert_status(StatusCode::CONFLICT);
+    assert_eq!(stale.json::<Value>()["code"], "version_conflict");
+
+    let reverted = server
+        .post(&format!("/files/{id}/revert"))
+        .authorization_bearer(&token)
+        .json(&json!({ "version": 1 }))
+        .await;
+    reverted.assert_status_ok();
+    assert_eq!(reverted.json::<Value>()["file"]["version"], 3);
+    assert_eq!(server.get("/files/report.txt").await.text(), "first");
+
+    // The reverted version is dropped; the one it reverted to is kept.
+    let versions: Value = server
+        .get(&format!("/files/{id}/versions"))
+        .authorization_bearer(&token)
+        .await
+        .json();
+    let numbers: Vec<i64> = versions
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["version"].as_i64().unwrap())
+        .collect();
+    assert_eq!(numbers, [3, 1]);
+
+    let entries = upload_entries(&server).await;
+    assert_eq!(
+        entries.iter().filter(|e| e.0 == "SUCCESS").count(),
+        3,
+        "{entries:?}"
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn a_sync_or_revert_waits_for_anothers_edit_lock() {
+    let boot = boot_test::<App>().await.unwrap();
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let role = role::create(&ctx.db, "editor", json!(["read"]))
+        .await
+        .unwrap();
+    let editor = user::create(&ctx.db, "Editor", "editor", "unused", role.id)
+        .await
+        .unwrap();
+    let editor_token =
+        auth::generate_token(&editor.id.to_string(), &editor.login, vec!["editor".into()]).unwrap();
+    let server = test_server(ctx, Arc::new(InMemory::new()));
+    upload(&server, &token, "report.txt", b"first").await;
+    let id = report_id(&server).await;
+    sync(&server, &token, id, 1, "second")
+        .await
+        .assert_status_ok();
+    server
+        .post("/files/report.txt/lock")
+        .authorization_bearer(&editor_token)
+        .await
+        .assert_status_ok();
+
+    let synced = sync(&server, &token, id, 2, "third").await;
+    synced.assert_status(StatusCode::LOCKED);
+    assert_eq!(synced.json::<Value>()["code"], "edit_locked");
+    server
+        .post(&format!("/files/{id}/revert"))
+        .authorization_bearer(&token)
+        .json(&json!({ "version": 1 }))
+        .await
+        .assert_status(StatusCode::LOCKED);
+
+    assert_eq!(server.get("/files/report.txt").await.text(), "second");
+    let failed: Vec<_> = upload_entries(&server)
+        .await
+        .into_iter()
+        .filter(|e| e.0 == "FAILURE")
+        .collect();
+    assert_eq!(
+        failed,
+        [
+            ("FAILURE".to_string(), Some(423)),
+            ("FAILURE".to_string(), Some(423))
+        ]
+    );
+}
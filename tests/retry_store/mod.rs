@@ -0,0 +1,172 @@
+use object_store::{
+    ObjectStore, RetryConfig,
+    aws::{AmazonS3, AmazonS3Builder},
+    multipart::{MultipartStore, PartId},
+    path::Path as ObjectPath,
+};
+use server::retry_store::{self, RetryPolicy, RetryStore};
+use std::time::Duration;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{header_exists, method, path, query_param},
+};
+
+/// S3 at `server` that gives up at once, leaving retries to `RetryStore`.
+fn flaky_s3(server: &MockServer) -> RetryStore<AmazonS3> {
+    let s3 = AmazonS3Builder::new()
+        .with_endpoint(server.uri())
+        .with_allow_http(true)
+        .with_bucket_name("files")
+        .with_region("us-east-1")
+        .with_access_key_id("test")
+        .with_secret_access_key("test")
+        .with_retry(RetryConfig {
+            max_retries: 0,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+    RetryStore::new(
+        s3,
+        RetryPolicy {
+            max_retries: 3,
+            init_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(50),
+        },
+    )
+}
+
+fn object() -> ResponseTemplate {
+    ResponseTemplate::new(200)
+        .insert_header("ETag", "\"done\"")
+        .insert_header("Last-Modified", "Tue, 15 Oct 2024 12:00:00 GMT")
+        .insert_header("Content-Length", "5")
+}
+
+fn not_found(code: &str) -> ResponseTemplate {
+    ResponseTemplate::new(404).set_body_string(format!("<Error><Code>{code}</Code></Error>"))
+}
+
+async fn requests(server: &MockServer, verb: &str) -> usize {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|r| r.method.as_str() == verb)
+        .count()
+}
+
+#[tokio::test]
+async fn a_rename_is_retried_after_a_failed_delete() {
+    let server = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .and(path("/files/new.txt"))
+        .and(header_exists("x-amz-copy-source"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .respond_with(ResponseTemplate::new(204))
+        .mount(&server)
+        .await;
+    let before = retry_store::retried_operations();
+
+    flaky_s3(&server)
+        .rename(&ObjectPath::from("old.txt"), &ObjectPath::from("new.txt"))
+        .await
+        .unwrap();
+
+    assert_eq!(requests(&server, "DELETE").await, 2);
+    assert!(retry_store::retried_operations() > before);
+}
+
+#[tokio::test]
+async fn a_rename_that_went_through_is_not_repeated() {
+    let server = MockServer::start().await;
+    // The delete lands, but its answer is lost.
+    Mock::given(method("PUT"))
+        .and(header_exists("x-amz-copy-source"))
+        .respond_with(ResponseTemplate::new(200))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(header_exists("x-amz-copy-source"))
+        .respond_with(not_found("NoSuchKey"))
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    Mock::given(method("HEAD"))
+        .and(path("/files/new.txt"))
+        .respond_with(object())
+        .mount(&server)
+        .await;
+
+    flaky_s3(&server)
+        .rename(&ObjectPath::from("old.txt"), &ObjectPath::from("new.txt"))
+        .await
+        .unwrap();
+
+    assert_eq!(requests(&server, "DELETE").await, 1);
+}
+
+#[tokio::test]
+async fn a_refused_rename_is_not_retried() {
+    let server = MockServer::start().await;
+    Mock::given(method("PUT"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&server)
+        .await;
+
+    let result = flaky_s3(&server)
+        .rename(&ObjectPath::from("old.txt"), &ObjectPath::from("new.txt"))
+        .await;
+
+    assert!(result.is_err());
+    assert_eq!(requests(&server, "PUT").await, 1);
+}
+
+#[tokio::test]
+async fn a_completed_upload_is_found_by_the_retry() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(query_param("uploadId", "upload-1"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(query_param("uploadId", "upload-1"))
+        .respond_with(not_found("NoSuchUpload"))
+        .mount(&server)
+        .await;
+    Mock::given(method("HEAD"))
+        .and(path("/files/large.bin"))
+        .respond_with(object())
+        .mount(&server)
+        .await;
+
+    let store = flaky_s3(&server);
+    let result = store
+        .complete_multipart(
+            &ObjectPath::from("large.bin"),
+            &"upload-1".to_string(),
+            vec![PartId {
+                content_id: "\"part-1\"".into(),
+            }],
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(result.e_tag.as_deref(), Some("\"done\""));
+    assert_eq!(requests(&server, "POST").await, 2);
+}
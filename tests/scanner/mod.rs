@@ -0,0 +1,127 @@
+use async_trait::async_trait;
+use server::scanner::{Clamd, ScanStatus, Scanner, Verdict, scan_status};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+const EICAR: &str = r"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+/// Flags anything containing the EICAR test string, like a real engine would.
+struct EicarScanner;
+
+#[async_trait]
+impl Scanner for EicarScanner {
+    async fn scan(&self, bytes: &[u8]) -> Result<Verdict, String> {
+        if String::from_utf8_lossy(bytes).contains(EICAR) {
+            Ok(Verdict::Infected("Eicar-Test-Signature".into()))
+        } else {
+            Ok(Verdict::Clean)
+        }
+    }
+}
+
+struct UnavailableScanner;
+
+#[async_trait]
+impl Scanner for UnavailableScanner {
+    async fn scan(&self, _bytes: &[u8]) -> Result<Verdict, String> {
+        Err("connection refused".into())
+    }
+}
+
+/// Answers one INSTREAM request the way clamd does, checking the framing.
+async fn fake_clamd() -> (u16, tokio::task::JoinHandle<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut command = [0u8; 10];
+        socket.read_exact(&mut command).await.unwrap();
+        assert_eq!(&command, b"zINSTREAM\0");
+
+        let mut received = Vec::new();
+        loop {
+            let len = socket.read_u32().await.unwrap() as usize;
+            if len == 0 {
+                break;
+            }
+            let mut chunk = vec![0u8; len];
+            socket.read_exact(&mut chunk).await.unwrap();
+            received.extend(chunk);
+        }
+
+        let reply: &[u8] = if String::from_utf8_lossy(&received).contains(EICAR) {
+            b"stream: Eicar-Test-Signature FOUND\0"
+        } else {
+            b"stream: OK\0"
+        };
+        socket.write_all(reply).await.unwrap();
+        received
+    });
+    (port, handle)
+}
+
+fn clamd(port: u16) -> Clamd {
+    Clamd {
+        host: "127.0.0.1".into(),
+        port,
+        timeout: Duration::from_secs(5),
+    }
+}
+
+#[tokio::test]
+async fn eicar_is_infected_and_keeps_its_signature() {
+    let (status, signature) = scan_status(&EicarScanner, EICAR.as_bytes(), false).await;
+    assert_eq!(status, ScanStatus::Infected);
+    assert_eq!(signature.as_deref(), Some("Eicar-Test-Signature"));
+    assert!(!status.is_servable());
+}
+
+#[tokio::test]
+async fn harmless_content_is_clean() {
+    let (status, signature) = scan_status(&EicarScanner, b"quarterly report", false).await;
+    assert_eq!(status, ScanStatus::Clean);
+    assert_eq!(signature, None);
+    assert!(status.is_servable());
+}
+
+#[tokio::test]
+async fn unavailable_scanner_fails_closed_or_open() {
+    let (closed, _) = scan_status(&UnavailableScanner, b"anything", false).await;
+    assert_eq!(closed, ScanStatus::Pending);
+    assert!(!closed.is_servable());
+
+    let (open, _) = scan_status(&UnavailableScanner, b"anything", true).await;
+    assert_eq!(open, ScanStatus::Unscanned);
+    assert!(open.is_servable());
+}
+
+#[tokio::test]
+async fn clamd_streams_the_upload_and_reports_eicar() {
+    let (port, server) = fake_clamd().await;
+    let verdict = clamd(port).scan(EICAR.as_bytes()).await.unwrap();
+
+    assert_eq!(verdict, Verdict::Infected("Eicar-Test-Signature".into()));
+    assert_eq!(server.await.unwrap(), EICAR.as_bytes());
+}
+
+#[tokio::test]
+async fn clamd_sends_large_uploads_in_chunks() {
+    let (port, server) = fake_clamd().await;
+    let upload = vec![b'a'; 200 * 1024];
+    let verdict = clamd(port).scan(&upload).await.unwrap();
+
+    assert_eq!(verdict, Verdict::Clean);
+    assert_eq!(server.await.unwrap(), upload);
+}
+
+#[tokio::test]
+async fn unreachable_clamd_is_an_error() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+
+    assert!(clamd(port).scan(b"anything").await.is_err());
+}
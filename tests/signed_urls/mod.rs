@@ -0,0 +1,47 @@
+use server::signed_urls::{self, SignatureError};
+
+const SECRET: &str = "s3cret";
+const EXPIRES: i64 = 1_700_000_000;
+
+#[test]
+fn a_signed_link_verifies_until_it_expires() {
+    let token = signed_urls::sign(SECRET, "docs/report.pdf", EXPIRES);
+    assert_eq!(token.len(), 64);
+
+    assert_eq!(
+        signed_urls::verify(SECRET, "docs/report.pdf", EXPIRES, &token, EXPIRES),
+        Ok(())
+    );
+    assert_eq!(
+        signed_urls::verify(SECRET, "docs/report.pdf", EXPIRES, &token, EXPIRES + 1),
+        Err(SignatureError::Expired)
+    );
+}
+
+#[test]
+fn anything_changed_breaks_the_signature() {
+    let token = signed_urls::sign(SECRET, "report.pdf", EXPIRES);
+    let now = EXPIRES - 60;
+
+    assert_eq!(
+        signed_urls::verify("other", "report.pdf", EXPIRES, &token, now),
+        Err(SignatureError::Invalid)
+    );
+    assert_eq!(
+        signed_urls::verify(SECRET, "other.pdf", EXPIRES, &token, now),
+        Err(SignatureError::Invalid)
+    );
+    assert_eq!(
+        signed_urls::verify(SECRET, "report.pdf", EXPIRES + 3600, &token, now),
+        Err(SignatureError::Invalid)
+    );
+    assert_eq!(
+        signed_urls::verify(SECRET, "report.pdf", EXPIRES, "not hex", now),
+        Err(SignatureError::Invalid)
+    );
+    // A forged link is refused as forged, not as expired.
+    assert_eq!(
+        signed_urls::verify(SECRET, "report.pdf", EXPIRES, &token[2..], EXPIRES + 1),
+        Err(SignatureError::Invalid)
+    );
+}
@@ -0,0 +1,124 @@
+use chrono::{TimeZone, Utc};
+use object_store::{ObjectStore, PutPayload, memory::InMemory, path::Path};
+use server::tar_stream::{self, BLOCK_SIZE, ERROR_TRAILER, ExportError, TarEntry};
+
+fn field(block: &[u8], range: std::ops::Range<usize>) -> String {
+    let bytes = &block[range];
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8(bytes[..end].to_vec()).unwrap()
+}
+
+fn octal(block: &[u8], range: std::ops::Range<usize>) -> u64 {
+    u64::from_str_radix(field(block, range).trim(), 8).unwrap()
+}
+
+fn assert_checksum(block: &[u8]) {
+    let mut summed = block.to_vec();
+    summed[148..156].fill(b' ');
+    let sum: u64 = summed.iter().map(|&b| u64::from(b)).sum();
+    assert_eq!(octal(block, 148..155), sum);
+}
+
+async fn store_with(name: &str, content: &'static [u8]) -> InMemory {
+    let store = InMemory::new();
+    store
+        .put(&Path::from(name), PutPayload::from_static(content))
+        .await
+        .unwrap();
+    store
+}
+
+fn entry(name: &str, size: u64) -> TarEntry {
+    TarEntry {
+        name: name.into(),
+        path: Path::from(name),
+        size,
+        modified: Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+    }
+}
+
+#[test]
+fn header_fills_the_ustar_fields() {
+    let header = tar_stream::header("reports/q3.txt", 5, 1_700_000_000);
+    assert_eq!(header.len(), BLOCK_SIZE);
+    assert_eq!(field(&header, 0..100), "reports/q3.txt");
+    assert_eq!(octal(&header, 124..136), 5);
+    assert_eq!(octal(&header, 136..148), 1_700_000_000);
+    assert_eq!(header[156], b'0');
+    assert_eq!(&header[257..265], b"ustar\x0000");
+    assert_checksum(&header);
+}
+
+#[test]
+fn long_names_use_the_prefix_field_then_a_pax_header() {
+    let folder = "a".repeat(120);
+    let name = format!("{folder}/notes.txt");
+    let header = tar_stream::header(&name, 5, 0);
+    assert_eq!(header.len(), BLOCK_SIZE);
+    assert_eq!(field(&header, 345..500), folder);
+    assert_eq!(field(&header, 0..100), "notes.txt");
+
+    let name = "n".repeat(300);
+    let header = tar_stream::header(&name, 5, 0);
+    assert_eq!(header.len(), 3 * BLOCK_SIZE);
+    assert_eq!(header[156], b'x');
+    assert_checksum(&header[..BLOCK_SIZE]);
+    let records = octal(&header, 124..136) as usize;
+    let record = String::from_utf8(header[BLOCK_SIZE..BLOCK_SIZE + records].to_vec()).unwrap();
+    assert_eq!(record, format!("{} path={name}\n", records));
+    let file_header = &header[2 * BLOCK_SIZE..];
+    assert_eq!(file_header[156], b'0');
+    assert_eq!(octal(file_header, 124..136), 5);
+    assert_checksum(file_header);
+}
+
+#[tokio::test]
+async fn archive_holds_each_entry_then_the_end_blocks() {
+    let store = store_with("notes.txt", b"notes").await;
+    let mut out = Vec::new();
+    tar_stream::write_archive(&store, &[entry("notes.txt", 5)], &mut out)
+        .await
+        .unwrap();
+
+    assert_eq!(out.len(), 4 * BLOCK_SIZE);
+    assert_eq!(field(&out, 0..100), "notes.txt");
+    assert_eq!(&out[BLOCK_SIZE..BLOCK_SIZE + 5], b"notes");
+    assert!(out[BLOCK_SIZE + 5..].iter().all(|&b| b == 0));
+}
+
+#[tokio::test]
+async fn a_size_change_ends_the_archive_with_an_error_trailer() {
+    let store = store_with("notes.txt", b"notes").await;
+
+    let mut out = Vec::new();
+    let written = tar_stream::write_archive(&store, &[entry("notes.txt", 8)], &mut out).await;
+    assert!(matches!(
+        written,
+        Err(ExportError::Truncated {
+            expected: 8,
+            actual: 5,
+            ..
+        })
+    ));
+    // The short entry keeps its declared size; the trailer follows and the
+    // end-of-archive blocks don't.
+    assert_eq!(&out[BLOCK_SIZE..BLOCK_SIZE + 8], b"notes\0\0\0");
+    let trailer = &out[2 * BLOCK_SIZE..];
+    assert_eq!(field(trailer, 0..100), ERROR_TRAILER);
+    let size = octal(trailer, 124..136) as usize;
+    let report = String::from_utf8(trailer[BLOCK_SIZE..BLOCK_SIZE + size].to_vec()).unwrap();
+    assert!(
+        report.contains("'notes.txt' ended after 5 of the 8 bytes"),
+        "{report}"
+    );
+    assert_eq!(out.len(), 4 * BLOCK_SIZE);
+
+    let mut out = Vec::new();
+    let written = tar_stream::write_archive(&store, &[entry("notes.txt", 3)], &mut out).await;
+    assert!(matches!(
+        written,
+        Err(ExportError::Grew { expected: 3, .. })
+    ));
+    assert_eq!(&out[BLOCK_SIZE..BLOCK_SIZE + 4], b"not\0");
+    assert_eq!(field(&out[2 * BLOCK_SIZE..], 0..100), ERROR_TRAILER);
+}
@@ -0,0 +1,154 @@
+use axum::{http::StatusCode, response::IntoResponse};
+use object_store::{
+    ObjectStore, RetryConfig,
+    aws::{AmazonS3, AmazonS3Builder},
+    memory::InMemory,
+    path::Path as ObjectPath,
+};
+use server::{
+    errors::FileError,
+    timeout_store::{self, TimeoutStore},
+};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path},
+};
+
+const TIMEOUT: Duration = Duration::from_millis(200);
+
+/// S3 at `server`, giving up on any call after `TIMEOUT`.
+fn slow_s3(server: &MockServer) -> TimeoutStore<impl ObjectStore> {
+    TimeoutStore::new(s3_at(&server.uri()), TIMEOUT)
+}
+
+fn s3_at(endpoint: &str) -> AmazonS3 {
+    AmazonS3Builder::new()
+        .with_endpoint(endpoint)
+        .with_allow_http(true)
+        .with_bucket_name("files")
+        .with_region("us-east-1")
+        .with_access_key_id("test")
+        .with_secret_access_key("test")
+        .with_retry(RetryConfig {
+            max_retries: 0,
+            ..Default::default()
+        })
+        .build()
+        .unwrap()
+}
+
+/// Answers one GET with `chunks`, waiting `gap` before each; a `None`
+/// chunk stalls the body for good, one byte short of its length.
+async fn trickling_server(chunks: Vec<Option<&'static [u8]>>, gap: Duration) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        while !request.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            socket.read_exact(&mut byte).await.unwrap();
+            request.push(byte[0]);
+        }
+        let len: usize = chunks.iter().map(|c| c.map_or(1, <[u8]>::len)).sum();
+        let head = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {len}\r\nETag: \"1\"\r\n\
+             Last-Modified: Tue, 13 Oct 2026 00:00:00 GMT\r\n\r\n"
+        );
+        socket.write_all(head.as_bytes()).await.unwrap();
+        for chunk in chunks {
+            tokio::time::sleep(gap).await;
+            match chunk {
+                Some(chunk) => socket.write_all(chunk).await.unwrap(),
+                None => std::future::pending().await,
+            }
+        }
+    });
+    format!("http://{addr}")
+}
+
+#[tokio::test]
+async fn a_stalled_call_fails_with_a_timeout() {
+    let server = MockServer::start().await;
+    Mock::given(method("DELETE"))
+        .respond_with(ResponseTemplate::new(204).set_delay(Duration::from_secs(5)))
+        .mount(&server)
+        .await;
+
+    let error = slow_s3(&server)
+        .delete(&ObjectPath::from("report.txt"))
+        .await
+        .unwrap_err();
+
+    assert!(timeout_store::is_timeout(&error), "{error}");
+    let response = FileError::StorageError(error).into_response();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["code"], "storage_unreachable");
+}
+
+#[tokio::test]
+async fn a_get_without_an_answer_times_out() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/files/report.txt"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+        .mount(&server)
+        .await;
+
+    let error = slow_s3(&server)
+        .get(&ObjectPath::from("report.txt"))
+        .await
+        .unwrap_err();
+
+    assert!(timeout_store::is_timeout(&error), "{error}");
+}
+
+#[tokio::test]
+async fn calls_within_the_timeout_pass_through() {
+    let store = TimeoutStore::new(InMemory::new(), TIMEOUT);
+    let location = ObjectPath::from("report.txt");
+
+    store.put(&location, "hello".into()).await.unwrap();
+    let bytes = store.get(&location).await.unwrap().bytes().await.unwrap();
+
+    assert_eq!(bytes.as_ref(), b"hello");
+}
+
+#[tokio::test]
+async fn a_steady_download_may_outlast_the_timeout() {
+    let endpoint = trickling_server(vec![Some(b"he"), Some(b"ll"), Some(b"o")], TIMEOUT / 2).await;
+    let store = TimeoutStore::new(s3_at(&endpoint), TIMEOUT);
+
+    let bytes = store
+        .get(&ObjectPath::from("report.txt"))
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+
+    assert_eq!(bytes.as_ref(), b"hello");
+}
+
+#[tokio::test]
+async fn a_stalled_download_fails_after_the_idle_timeout() {
+    let endpoint = trickling_server(vec![Some(b"he"), None], Duration::ZERO).await;
+    let store =
+        TimeoutStore::new(s3_at(&endpoint), Duration::from_secs(5)).with_idle_timeout(TIMEOUT);
+
+    let result = store.get(&ObjectPath::from("report.txt")).await.unwrap();
+    let started = std::time::Instant::now();
+    let error = result.bytes().await.unwrap_err();
+
+    assert!(timeout_store::is_timeout(&error), "{error}");
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
@@ -0,0 +1,59 @@
+use server::transfer_limit::{TransferLimit, WhenBusy};
+use std::{sync::Arc, time::Duration};
+
+#[tokio::test]
+async fn transfers_over_the_limit_wait_for_a_slot() {
+    let limit = Arc::new(TransferLimit::new(
+        1,
+        WhenBusy::Queue,
+        Duration::from_secs(5),
+    ));
+    let first = limit.acquire().await.unwrap();
+    assert_eq!(limit.in_flight(), 1);
+
+    let waiting = tokio::spawn({
+        let limit = limit.clone();
+        async move { limit.acquire().await.map(|_| ()) }
+    });
+    while limit.queued() == 0 {
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(limit.in_flight(), 1);
+
+    drop(first);
+    waiting.await.unwrap().unwrap();
+    assert_eq!(limit.queued(), 0);
+    assert_eq!(limit.in_flight(), 0);
+}
+
+#[tokio::test]
+async fn queued_transfers_give_up_after_the_wait() {
+    let limit = TransferLimit::new(1, WhenBusy::Queue, Duration::from_millis(50));
+    let _held = limit.acquire().await.unwrap();
+
+    assert!(limit.acquire().await.is_err());
+    assert_eq!(limit.queued(), 0);
+}
+
+#[tokio::test]
+async fn rejecting_limits_refuse_at_once() {
+    let limit = TransferLimit::new(2, WhenBusy::Reject, Duration::from_secs(30));
+    let _first = limit.acquire().await.unwrap();
+    let _second = limit.acquire().await.unwrap();
+
+    assert!(limit.acquire().await.is_err());
+    assert_eq!(limit.queued(), 0);
+}
+
+#[tokio::test]
+async fn a_zero_limit_only_counts() {
+    let limit = TransferLimit::new(0, WhenBusy::Reject, Duration::ZERO);
+    let held: Vec<_> = futures_util::future::join_all((0..100).map(|_| limit.acquire()))
+        .await
+        .into_iter()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(limit.in_flight(), 100);
+    drop(held);
+    assert_eq!(limit.in_flight(), 0);
+}
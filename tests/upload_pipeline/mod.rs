@@ -0,0 +1,128 @@
+use async_trait::async_trait;
+use axum::body::Bytes;
+use loco_rs::Error;
+use server::upload_pipeline::{
+    ChecksumStep, ClientDigest, CompressStep, DigestAlgorithm, SanitizeStep, StepError,
+    UploadContext, UploadPipeline, UploadStep,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// SHA-256 of "notes".
+const NOTES_SHA256: &str = "ab5aa97074c454a0632057e704220d9a6678fbf773a0a5806fc09b8173b07309";
+
+fn upload(name: &str, content: &'static str) -> UploadContext {
+    UploadContext::new(name, Bytes::from_static(content.as_bytes()), None)
+}
+
+#[tokio::test]
+async fn sanitize_refuses_bad_names_and_large_files() {
+    let step = SanitizeStep { max_size: 5 };
+
+    assert!(
+        step.process(upload("docs/notes.txt", "notes"))
+            .await
+            .is_ok()
+    );
+    for refused in [
+        upload("../notes.txt", "notes"),
+        upload("notes.txt", "notes!"),
+    ] {
+        assert!(matches!(
+            step.process(refused).await,
+            Err(StepError::File(Error::BadRequest(_)))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn checksum_records_the_hash_and_fails_the_request_on_a_mismatch() {
+    let hashed = ChecksumStep {
+        expected: Vec::new(),
+    }
+    .process(upload("notes.txt", "notes"))
+    .await
+    .unwrap();
+    assert_eq!(hashed.content_hash.as_deref(), Some(NOTES_SHA256));
+
+    let matching = ChecksumStep {
+        expected: vec![ClientDigest {
+            algorithm: DigestAlgorithm::Sha256,
+            expected: NOTES_SHA256.into(),
+        }],
+    };
+    assert!(matching.process(upload("notes.txt", "notes")).await.is_ok());
+    assert!(matches!(
+        matching.process(upload("notes.txt", "other")).await,
+        Err(StepError::Request(_))
+    ));
+}
+
+#[tokio::test]
+async fn checksum_records_each_digest_that_matched() {
+    let step = ChecksumStep {
+        expected: vec![
+            ClientDigest {
+                algorithm: DigestAlgorithm::Md5,
+                expected: "4358b5009c67d0e31d7fbf1663fcd3bf".into(),
+            },
+            ClientDigest {
+                algorithm: DigestAlgorithm::Crc32,
+                expected: "011ba68c".into(),
+            },
+        ],
+    };
+    let checked = step.process(upload("notes.txt", "notes")).await.unwrap();
+    assert_eq!(
+        checked.checksums.get("md5").map(String::as_str),
+        Some("4358b5009c67d0e31d7fbf1663fcd3bf")
+    );
+    assert_eq!(
+        checked.checksums.get("crc32").map(String::as_str),
+        Some("011ba68c")
+    );
+    assert!(!checked.checksums.contains_key("sha256"));
+}
+
+#[tokio::test]
+async fn compress_only_gzips_text_when_enabled() {
+    let enabled = CompressStep { enabled: true };
+    let text = enabled.process(upload("notes.txt", "notes")).await.unwrap();
+    let gzipped = text.gzipped.unwrap();
+    assert_eq!(&gzipped[..2], [0x1f, 0x8b]);
+    assert_eq!(text.bytes, "notes");
+
+    let image = enabled.process(upload("photo.jpg", "notes")).await.unwrap();
+    assert!(image.gzipped.is_none());
+    let disabled = CompressStep { enabled: false }
+        .process(upload("notes.txt", "notes"))
+        .await
+        .unwrap();
+    assert!(disabled.gzipped.is_none());
+}
+
+/// Counts the uploads that reach it.
+struct Counter<'a>(&'a AtomicUsize);
+
+#[async_trait]
+impl UploadStep for Counter<'_> {
+    async fn process(&self, upload: UploadContext) -> Result<UploadContext, StepError> {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        Ok(upload)
+    }
+}
+
+#[tokio::test]
+async fn a_failed_step_stops_the_pipeline() {
+    let reached = AtomicUsize::new(0);
+    let pipeline = UploadPipeline::new()
+        .step(Counter(&reached))
+        .step(SanitizeStep { max_size: 5 })
+        .step(Counter(&reached));
+
+    let passed = pipeline.run(upload("notes.txt", "notes")).await.unwrap();
+    assert_eq!(passed.file_name, "notes.txt");
+    assert_eq!(reached.load(Ordering::Relaxed), 2);
+
+    assert!(pipeline.run(upload("notes.txt", "too long")).await.is_err());
+    assert_eq!(reached.load(Ordering::Relaxed), 3);
+}
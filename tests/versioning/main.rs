@@ -0,0 +1,167 @@
+//! Overwrites with `versioning` on and v1 objects sharded and gzipped.
+//! Storage settings are read once per process, so these run as a test
+//! binary of their own.
+
+use std::sync::Arc;
+
+use axum::{Extension, Router};
+use axum_test::{
+    TestResponse, TestServer,
+    multipart::{MultipartForm, Part},
+};
+use loco_rs::{
+    app::{AppContext, Hooks},
+    boot::{BootResult, StartMode},
+    controller::AppRoutes,
+    environment::Environment,
+};
+use object_store::{ObjectStore, memory::InMemory};
+use serde_json::{Value, json};
+use serial_test::serial;
+use server::{
+    app::App,
+    controllers::{auth, files},
+    models::{role, user},
+};
+
+async fn boot_versioning() -> BootResult {
+    let mut config = App::load_config(&Environment::Test).await.unwrap();
+    if let Some(settings) = config.settings.as_mut() {
+        settings["versioning"] = true.into();
+        settings["path_strategy"] = "hash".into();
+        settings["compress_text_uploads"] = true.into();
+    }
+    App::boot(StartMode::ServerOnly, &Environment::Test, config)
+        .await
+        .unwrap()
+}
+
+fn test_server(ctx: &AppContext, store: Arc<dyn ObjectStore>) -> TestServer {
+    let router: Router = AppRoutes::empty()
+        .add_route(files::routes(ctx))
+        .to_router::<App>(ctx.clone(), Router::new())
+        .unwrap()
+        .layer(Extension(store));
+    TestServer::new(router).unwrap()
+}
+
+async fn bearer_token(ctx: &AppContext) -> String {
+    let role = role::create(&ctx.db, "tester", json!(["read"]))
+        .await
+        .unwrap();
+    let author = user::create(&ctx.db, "Tester", "tester", "unused", role.id)
+        .await
+        .unwrap();
+    auth::generate_token(&author.id.to_string(), &author.login, vec!["tester".into()]).unwrap()
+}
+
+/// Enough repeated text for the upload to be stored gzipped.
+fn text(word: &str) -> Vec<u8> {
+    word.repeat(200).into_bytes()
+}
+
+async fn upload(server: &TestServer, token: &str, content: Vec<u8>) {
+    server
+        .post("/files")
+        .authorization_bearer(token)
+        .multipart(
+            MultipartForm::new().add_part("file", Part::bytes(content).file_name("report.txt")),
+        )
+        .await
+        .assert_status_ok();
+}
+
+async fn sync(
+    server: &TestServer,
+    token: &str,
+    id: i64,
+    version: i32,
+    content: Vec<u8>,
+) -> TestResponse {
+    server
+        .post("/files/sync")
+        .authorization_bearer(token)
+        .multipart(
+            MultipartForm::new()
+                .add_text("file_id", id.to_string())
+                .add_text("version", version.to_string())
+                .add_part("file", Part::bytes(content).file_name("report.txt")),
+        )
+        .await
+}
+
+async fn object_keys(store: &InMemory) -> Vec<String> {
+    use futures_util::TryStreamExt;
+
+    store
+        .list(None)
+        .map_ok(|meta| meta.location.to_string())
+        .try_collect()
+        .await
+        .unwrap()
+}
+
+async fn delete_report(server: &TestServer, token: &str) {
+    server
+        .delete("/files/report.txt")
+        .authorization_bearer(token)
+        .await
+        .assert_status_ok();
+    let listing: Value = server.get("/files").await.json();
+    assert_eq!(listing, json!([]));
+}
+
+#[tokio::test]
+#[serial]
+async fn an_overwritten_sharded_file_leaves_nothing_behind_once_deleted() {
+    let boot = boot_versioning().await;
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let store = Arc::new(InMemory::new());
+    let server = test_server(ctx, store.clone());
+
+    upload(&server, &token, text("first ")).await;
+    let keys = object_keys(&store).await;
+    assert!(
+        keys.iter().any(|k| k.ends_with("/report.txt.gz")),
+        "{keys:?}"
+    );
+    upload(&server, &token, text("second ")).await;
+    assert_eq!(
+        server.get("/files/report.txt").await.as_bytes(),
+        &text("second ")[..]
+    );
+
+    delete_report(&server, &token).await;
+    assert_eq!(object_keys(&store).await, Vec::<String>::new());
+}
+
+#[tokio::test]
+#[serial]
+async fn a_synced_and_reverted_sharded_file_leaves_nothing_behind_once_deleted() {
+    let boot = boot_versioning().await;
+    let ctx = &boot.app_context;
+    let token = bearer_token(ctx).await;
+    let store = Arc::new(InMemory::new());
+    let server = test_server(ctx, store.clone());
+
+    upload(&server, &token, text("first ")).await;
+    let listing: Value = server.get("/files").await.json();
+    let id = listing[0]["id"].as_i64().unwrap();
+    sync(&server, &token, id, 1, text("second "))
+        .await
+        .assert_status_ok();
+    server
+        .post(&format!("/files/{id}/revert"))
+        .authorization_bearer(&token)
+        .json(&json!({ "version": 1 }))
+        .await
+        .assert_status_ok();
+    assert_eq!(
+        server.get("/files/report.txt").await.as_bytes(),
+        &text("first ")[..]
+    );
+
+    delete_report(&server, &token).await;
+    assert_eq!(object_keys(&store).await, Vec::<String>::new());
+}
@@ -0,0 +1,118 @@
+use reqwest::Client;
+use server::webhooks::{
+    self, EVENT_HEADER, FileEvent, SIGNATURE_HEADER, WebhookPayload, WebhookTarget,
+};
+use std::time::Duration;
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{header, method, path},
+};
+
+const SECRET: &str = "s3cret";
+
+fn target(server: &MockServer) -> WebhookTarget {
+    WebhookTarget {
+        url: format!("{}/hook", server.uri()),
+        secret: SECRET.into(),
+        events: Vec::new(),
+    }
+}
+
+fn payload() -> WebhookPayload {
+    WebhookPayload {
+        event: FileEvent::Uploaded,
+        file_name: "report.pdf".into(),
+        size: 42,
+        checksum: Some("abc123".into()),
+        timestamp: "2024-10-15T12:00:00+00:00".into(),
+    }
+}
+
+#[tokio::test]
+async fn delivery_is_signed_with_the_target_secret() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .and(header(EVENT_HEADER, "file.uploaded"))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    webhooks::deliver(&Client::new(), &target(&server), &payload())
+        .await
+        .unwrap();
+
+    let request = &server.received_requests().await.unwrap()[0];
+    let signature = request.headers.get(SIGNATURE_HEADER).unwrap();
+    assert_eq!(
+        signature.to_str().unwrap(),
+        webhooks::sign(SECRET, &request.body)
+    );
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body["event"], "file.uploaded");
+    assert_eq!(body["file_name"], "report.pdf");
+    assert_eq!(body["size"], 42);
+    assert_eq!(body["checksum"], "abc123");
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let target = target(&server);
+    webhooks::deliver_with_retries(&Client::new(), &target, &payload(), 3, Duration::ZERO)
+        .await
+        .unwrap();
+
+    assert_eq!(server.received_requests().await.unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn delivery_gives_up_after_the_last_attempt() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+
+    let target = target(&server);
+    let error =
+        webhooks::deliver_with_retries(&Client::new(), &target, &payload(), 2, Duration::ZERO)
+            .await
+            .unwrap_err();
+
+    assert!(error.contains("500"), "{error}");
+    assert_eq!(server.received_requests().await.unwrap().len(), 2);
+}
+
+#[test]
+fn event_filters_select_targets() {
+    let target = WebhookTarget {
+        url: "http://localhost/hook".into(),
+        secret: SECRET.into(),
+        events: vec!["file.deleted".into()],
+    };
+
+    assert!(target.wants(FileEvent::Deleted));
+    assert!(!target.wants(FileEvent::Uploaded));
+    assert!(target.wants(FileEvent::Test));
+}
+
+#[test]
+fn backoff_doubles_per_attempt() {
+    let base = Duration::from_millis(100);
+
+    assert_eq!(webhooks::backoff(base, 1), base);
+    assert_eq!(webhooks::backoff(base, 3), Duration::from_millis(400));
+    assert_eq!(webhooks::backoff(base, 40), Duration::from_secs(300));
+}